@@ -0,0 +1,372 @@
+//! Playing sounds from components
+//!
+//! Like drawing, playing a sound only queues a command, so it can be done from
+//! `process`. The commands of each frame are flushed together, and applied by
+//! a separate audio thread that owns the output device
+use atomic_float::AtomicF32;
+use bina_ecs::{
+    crossbeam::queue::SegQueue, singleton::Singleton, tokio::sync::watch, universe::Universe,
+};
+use playback::{PlayingSound, SoundEnded, SoundId, SoundStatus};
+use rodio::{OutputStream, OutputStreamHandle, Sink};
+use sound::{SoundAsset, SoundHandle, SoundSource};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+pub mod playback;
+pub mod sound;
+
+/// How often the audio thread checks for sounds that have finished
+#[cfg(not(target_arch = "wasm32"))]
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(10);
+
+/// A group of sounds whose volume can be changed together, such as from a settings menu
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ChannelGroup {
+    Music,
+    Sfx,
+}
+
+impl ChannelGroup {
+    const COUNT: usize = 2;
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// How a sound is played
+#[derive(Clone, Copy, Debug)]
+pub struct PlaySettings {
+    /// Multiplies the volume of the sound, on top of the volume of its group
+    pub volume: f32,
+    /// Multiplies the speed of the sound, which also raises or lowers its pitch
+    pub pitch: f32,
+    /// Where the sound is between the left (-1) and right (1) speakers
+    pub pan: f32,
+    /// Plays the sound again whenever it ends, until it is stopped
+    pub looping: bool,
+    pub group: ChannelGroup,
+}
+
+impl PlaySettings {
+    pub const SFX: Self = Self {
+        volume: 1.0,
+        pitch: 1.0,
+        pan: 0.0,
+        looping: false,
+        group: ChannelGroup::Sfx,
+    };
+    pub const MUSIC: Self = Self {
+        looping: true,
+        group: ChannelGroup::Music,
+        ..Self::SFX
+    };
+}
+
+impl Default for PlaySettings {
+    fn default() -> Self {
+        Self::SFX
+    }
+}
+
+pub(crate) enum AudioCommand {
+    Play {
+        id: SoundId,
+        sound: Arc<SoundAsset>,
+        settings: PlaySettings,
+        status: watch::Sender<SoundStatus>,
+    },
+    Stop(SoundId),
+    Pause(SoundId),
+    Resume(SoundId),
+    /// Changes the volume of a group, or every sound if there is no group
+    SetVolume {
+        group: Option<ChannelGroup>,
+        volume: f32,
+    },
+}
+
+struct Playing {
+    id: SoundId,
+    sink: Sink,
+    status: watch::Sender<SoundStatus>,
+    volume: f32,
+    group: ChannelGroup,
+}
+
+/// Owns the output device, which must stay on the thread that opened it
+struct Mixer {
+    _stream: OutputStream,
+    stream_handle: OutputStreamHandle,
+    playing: Vec<Playing>,
+    group_volumes: [f32; ChannelGroup::COUNT],
+    master_volume: f32,
+    ended: Arc<SegQueue<SoundEnded>>,
+}
+
+impl Mixer {
+    fn new(ended: Arc<SegQueue<SoundEnded>>) -> Option<Self> {
+        let (stream, stream_handle) = match OutputStream::try_default() {
+            Ok(x) => x,
+            Err(e) => {
+                log::error!("Failed to open audio output, sounds will not be played: {e}");
+                return None;
+            }
+        };
+        Some(Self {
+            _stream: stream,
+            stream_handle,
+            playing: Vec::new(),
+            group_volumes: [1.0; ChannelGroup::COUNT],
+            master_volume: 1.0,
+            ended,
+        })
+    }
+
+    fn group_volume(&self, group: ChannelGroup) -> f32 {
+        self.group_volumes[group.index()] * self.master_volume
+    }
+
+    fn apply(&mut self, command: AudioCommand) {
+        match command {
+            AudioCommand::Play {
+                id,
+                sound,
+                settings,
+                status,
+            } => {
+                let sink = match Sink::try_new(&self.stream_handle) {
+                    Ok(x) => x,
+                    Err(e) => {
+                        log::error!("Failed to play sound: {e}");
+                        status.send_replace(SoundStatus::Stopped);
+                        self.ended.push(SoundEnded {
+                            id,
+                            status: SoundStatus::Stopped,
+                        });
+                        return;
+                    }
+                };
+                sink.set_volume(settings.volume * self.group_volume(settings.group));
+                sink.set_speed(settings.pitch);
+                sink.append(SoundSource::new(&sound, settings.pan, settings.looping));
+                self.playing.push(Playing {
+                    id,
+                    sink,
+                    status,
+                    volume: settings.volume,
+                    group: settings.group,
+                });
+            }
+            AudioCommand::Stop(id) => {
+                if let Some(i) = self.playing.iter().position(|x| x.id == id) {
+                    let playing = self.playing.swap_remove(i);
+                    playing.sink.stop();
+                    self.end(playing, SoundStatus::Stopped);
+                }
+            }
+            AudioCommand::Pause(id) => {
+                if let Some(playing) = self.playing.iter().find(|x| x.id == id) {
+                    playing.sink.pause();
+                    playing.status.send_replace(SoundStatus::Paused);
+                }
+            }
+            AudioCommand::Resume(id) => {
+                if let Some(playing) = self.playing.iter().find(|x| x.id == id) {
+                    playing.sink.play();
+                    playing.status.send_replace(SoundStatus::Playing);
+                }
+            }
+            AudioCommand::SetVolume { group, volume } => {
+                match group {
+                    Some(group) => self.group_volumes[group.index()] = volume,
+                    None => self.master_volume = volume,
+                }
+                for playing in &self.playing {
+                    playing
+                        .sink
+                        .set_volume(playing.volume * self.group_volume(playing.group));
+                }
+            }
+        }
+    }
+
+    fn end(&self, playing: Playing, status: SoundStatus) {
+        playing.status.send_replace(status);
+        self.ended.push(SoundEnded {
+            id: playing.id,
+            status,
+        });
+    }
+
+    /// Ends every sound that has played until its end
+    fn update(&mut self) {
+        let mut i = 0;
+        while i < self.playing.len() {
+            if self.playing[i].sink.empty() {
+                let playing = self.playing.swap_remove(i);
+                self.end(playing, SoundStatus::Finished);
+            } else {
+                i += 1;
+            }
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+thread_local! {
+    /// Pages only have one thread, so the mixer lives on it
+    static MIXER: std::cell::RefCell<Option<Mixer>> = const { std::cell::RefCell::new(None) };
+}
+
+/// Plays sounds. Add it to the `Universe` as a singleton
+///
+/// ```ignore
+/// let audio = universe.get_singleton::<Audio>();
+/// audio.play(&explosion, PlaySettings { pan: -0.5, ..PlaySettings::SFX });
+/// ```
+pub struct Audio {
+    commands: Arc<SegQueue<AudioCommand>>,
+    ended: Arc<SegQueue<SoundEnded>>,
+    next_id: AtomicU64,
+    #[cfg(not(target_arch = "wasm32"))]
+    sender: std::sync::mpsc::Sender<Vec<AudioCommand>>,
+    group_volumes: [AtomicF32; ChannelGroup::COUNT],
+    master_volume: AtomicF32,
+}
+
+impl Audio {
+    /// Opens the default output device. If there is none, sounds are silently dropped
+    pub fn new() -> Self {
+        let ended = Arc::new(SegQueue::new());
+        #[cfg(not(target_arch = "wasm32"))]
+        let sender = {
+            use std::sync::mpsc::RecvTimeoutError;
+
+            let (sender, receiver) = std::sync::mpsc::channel::<Vec<AudioCommand>>();
+            let ended = ended.clone();
+            std::thread::Builder::new()
+                .name("audio".into())
+                .spawn(move || {
+                    let Some(mut mixer) = Mixer::new(ended) else {
+                        return;
+                    };
+                    loop {
+                        match receiver.recv_timeout(POLL_INTERVAL) {
+                            Ok(commands) => {
+                                for command in commands {
+                                    mixer.apply(command);
+                                }
+                            }
+                            Err(RecvTimeoutError::Timeout) => {}
+                            Err(RecvTimeoutError::Disconnected) => break,
+                        }
+                        mixer.update();
+                    }
+                })
+                .expect("Failed to spawn audio thread");
+            sender
+        };
+        #[cfg(target_arch = "wasm32")]
+        MIXER.with_borrow_mut(|mixer| *mixer = Mixer::new(ended.clone()));
+
+        Self {
+            commands: Arc::new(SegQueue::new()),
+            ended,
+            next_id: AtomicU64::new(0),
+            #[cfg(not(target_arch = "wasm32"))]
+            sender,
+            group_volumes: std::array::from_fn(|_| AtomicF32::new(1.0)),
+            master_volume: AtomicF32::new(1.0),
+        }
+    }
+
+    /// Plays a sound at the end of this frame
+    ///
+    /// If the sound has not loaded yet, nothing is played and the returned sound is already stopped
+    pub fn play(&self, sound: &SoundHandle, settings: PlaySettings) -> PlayingSound {
+        let id = SoundId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let (status, receiver) = watch::channel(SoundStatus::Playing);
+        match sound.get() {
+            Some(sound) => self.commands.push(AudioCommand::Play {
+                id,
+                sound,
+                settings,
+                status,
+            }),
+            None => {
+                log::warn!("Tried to play a sound that has not loaded");
+                status.send_replace(SoundStatus::Stopped);
+            }
+        }
+        PlayingSound {
+            id,
+            status: receiver,
+            commands: self.commands.clone(),
+        }
+    }
+
+    pub fn volume(&self, group: ChannelGroup) -> f32 {
+        self.group_volumes[group.index()].load(Ordering::Relaxed)
+    }
+
+    /// Changes the volume of every sound in the group, including those already playing
+    pub fn set_volume(&self, group: ChannelGroup, volume: f32) {
+        self.group_volumes[group.index()].store(volume, Ordering::Relaxed);
+        self.commands.push(AudioCommand::SetVolume {
+            group: Some(group),
+            volume,
+        });
+    }
+
+    pub fn master_volume(&self) -> f32 {
+        self.master_volume.load(Ordering::Relaxed)
+    }
+
+    /// Changes the volume of every sound, on top of the volume of its group
+    pub fn set_master_volume(&self, volume: f32) {
+        self.master_volume.store(volume, Ordering::Relaxed);
+        self.commands
+            .push(AudioCommand::SetVolume { group: None, volume });
+    }
+}
+
+impl Default for Audio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Singleton for Audio {
+    fn process(&self, universe: &Universe) {
+        while let Some(event) = self.ended.pop() {
+            universe.send_event(event);
+        }
+    }
+
+    fn flush(&mut self, _universe: &Universe) {
+        let mut commands = Vec::with_capacity(self.commands.len());
+        while let Some(command) = self.commands.pop() {
+            commands.push(command);
+        }
+
+        // Fails if there is no output device, in which case the commands are dropped
+        #[cfg(not(target_arch = "wasm32"))]
+        if !commands.is_empty() {
+            let _ = self.sender.send(commands);
+        }
+        // There is no audio thread to check for finished sounds, so it is done every frame
+        #[cfg(target_arch = "wasm32")]
+        MIXER.with_borrow_mut(|mixer| {
+            if let Some(mixer) = mixer {
+                for command in commands {
+                    mixer.apply(command);
+                }
+                mixer.update();
+            }
+        });
+    }
+}
@@ -0,0 +1,92 @@
+//! Controlling sounds after they start playing, and waiting for them to end
+use std::{future::Future, sync::Arc};
+
+use bina_ecs::{crossbeam::queue::SegQueue, tokio::sync::watch};
+
+use crate::AudioCommand;
+
+/// Identifies one playback of a sound
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct SoundId(pub(crate) u64);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SoundStatus {
+    Playing,
+    Paused,
+    /// The sound played until its end
+    Finished,
+    /// The sound was stopped early, or could not be played at all
+    Stopped,
+}
+
+impl SoundStatus {
+    pub fn has_ended(self) -> bool {
+        matches!(self, Self::Finished | Self::Stopped)
+    }
+}
+
+/// Sent when a sound finishes or is stopped
+///
+/// ```ignore
+/// for event in universe.read_events::<SoundEnded>() {
+///     if event.id == voice_line.id() { ... }
+/// }
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct SoundEnded {
+    pub id: SoundId,
+    pub status: SoundStatus,
+}
+
+/// A sound that was started by `Audio::play`
+///
+/// Like playing, controlling a sound only queues a command that is
+/// applied at the end of the frame. Dropping this does not stop the sound
+#[derive(Clone)]
+pub struct PlayingSound {
+    pub(crate) id: SoundId,
+    pub(crate) status: watch::Receiver<SoundStatus>,
+    pub(crate) commands: Arc<SegQueue<AudioCommand>>,
+}
+
+impl PlayingSound {
+    pub fn id(&self) -> SoundId {
+        self.id
+    }
+
+    pub fn status(&self) -> SoundStatus {
+        let status = *self.status.borrow();
+        // The mixer is gone, such as when there is no output device
+        if !status.has_ended() && self.status.has_changed().is_err() {
+            return SoundStatus::Stopped;
+        }
+        status
+    }
+
+    pub fn stop(&self) {
+        self.commands.push(AudioCommand::Stop(self.id));
+    }
+
+    pub fn pause(&self) {
+        self.commands.push(AudioCommand::Pause(self.id));
+    }
+
+    pub fn resume(&self) {
+        self.commands.push(AudioCommand::Resume(self.id));
+    }
+
+    /// Resolves to `Finished` or `Stopped` once the sound ends. Looping sounds only end when stopped
+    ///
+    /// ```ignore
+    /// universe.queue_add_entity((WatchedFuture::new(voice_line.ended(), universe),));
+    /// ```
+    pub fn ended(&self) -> impl Future<Output = SoundStatus> + Send + 'static {
+        let mut status = self.status.clone();
+        async move {
+            match status.wait_for(|x| x.has_ended()).await {
+                Ok(x) => *x,
+                Err(_) => SoundStatus::Stopped,
+            }
+        }
+    }
+}
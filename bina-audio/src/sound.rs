@@ -0,0 +1,194 @@
+//! Decoded sounds and the sources that play them
+use std::{
+    io::Cursor,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+
+use bina_ecs::assets::{AssetLoader, Handle};
+use rodio::{Decoder, Source};
+
+/// A sound that has been fully decoded into samples, so that it can be played many times at once
+pub struct SoundAsset {
+    /// Interleaved samples of every channel
+    samples: Arc<[f32]>,
+    channels: u16,
+    sample_rate: u32,
+}
+
+impl SoundAsset {
+    /// Decodes a WAV or OGG Vorbis file
+    pub fn decode(bytes: Vec<u8>) -> Result<Self, String> {
+        let decoder = Decoder::new(Cursor::new(bytes)).map_err(|e| e.to_string())?;
+        let channels = decoder.channels();
+        let sample_rate = decoder.sample_rate();
+        Ok(Self {
+            samples: decoder.convert_samples::<f32>().collect(),
+            channels,
+            sample_rate,
+        })
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// How long the sound lasts when played at its original pitch
+    pub fn duration(&self) -> Duration {
+        let frames = self.samples.len() / self.channels.max(1) as usize;
+        Duration::from_secs_f64(frames as f64 / self.sample_rate as f64)
+    }
+}
+
+/// Loads WAV and OGG Vorbis files through `Assets`
+pub struct SoundLoader;
+
+impl AssetLoader for SoundLoader {
+    type Asset = SoundAsset;
+
+    fn load(&self, bytes: Vec<u8>) -> Result<Self::Asset, String> {
+        SoundAsset::decode(bytes)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["wav", "ogg"]
+    }
+}
+
+pub type SoundHandle = Handle<SoundAsset>;
+
+/// A WAV or OGG Vorbis file compiled into the binary by `load_audio!`, which is decoded the first time it is used
+///
+/// The header is checked at compile time, so only the samples themselves can fail to decode
+pub struct SoundResource {
+    path: &'static str,
+    bytes: &'static [u8],
+    channels: u16,
+    sample_rate: u32,
+    decoded: OnceLock<Result<SoundHandle, String>>,
+}
+
+impl SoundResource {
+    pub const fn new(path: &'static str, bytes: &'static [u8], channels: u16, sample_rate: u32) -> Self {
+        Self {
+            path,
+            bytes,
+            channels,
+            sample_rate,
+            decoded: OnceLock::new(),
+        }
+    }
+
+    /// The number of channels, as read from the header at compile time
+    pub const fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    /// The sample rate, as read from the header at compile time
+    pub const fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Gets a handle to the decoded sound, or the reason it could not be decoded
+    ///
+    /// Decoding blocks, so long sounds should be decoded while loading, such as by calling this once
+    pub fn handle(&self) -> Result<SoundHandle, &str> {
+        self.decoded
+            .get_or_init(|| {
+                SoundAsset::decode(self.bytes.to_vec())
+                    .map(Handle::from_asset)
+                    .map_err(|e| {
+                        log::error!("Failed to load {}: {e}", self.path);
+                        e
+                    })
+            })
+            .as_ref()
+            .map(Clone::clone)
+            .map_err(String::as_str)
+    }
+}
+
+/// Plays a sound in stereo, panning it between the left and right speakers
+pub(crate) struct SoundSource {
+    samples: Arc<[f32]>,
+    channels: usize,
+    sample_rate: u32,
+    /// The index of the first sample of the current frame
+    frame: usize,
+    /// The sample of the right speaker, which is emitted after the left one
+    right: Option<f32>,
+    /// The gain of the left and right speakers
+    gains: (f32, f32),
+    looping: bool,
+}
+
+impl SoundSource {
+    pub(crate) fn new(sound: &SoundAsset, pan: f32, looping: bool) -> Self {
+        let pan = pan.clamp(-1.0, 1.0);
+        let gains = if sound.channels == 1 {
+            // Equal power panning, so that the sound is as loud in the center as on either side
+            let angle = (pan + 1.0) * std::f32::consts::FRAC_PI_4;
+            (angle.cos(), angle.sin())
+        } else {
+            // Stereo sounds are balanced by quieting the opposite speaker
+            ((1.0 - pan).min(1.0), (1.0 + pan).min(1.0))
+        };
+        Self {
+            samples: sound.samples.clone(),
+            channels: sound.channels.max(1) as usize,
+            sample_rate: sound.sample_rate,
+            frame: 0,
+            right: None,
+            gains,
+            looping,
+        }
+    }
+}
+
+impl Iterator for SoundSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if let Some(right) = self.right.take() {
+            return Some(right);
+        }
+        if self.frame + self.channels > self.samples.len() {
+            if !self.looping || self.samples.len() < self.channels {
+                return None;
+            }
+            self.frame = 0;
+        }
+        let frame = &self.samples[self.frame..self.frame + self.channels];
+        self.frame += self.channels;
+        // Any channels past the first two are dropped
+        let (left, right) = match frame {
+            [mono] => (*mono, *mono),
+            [left, right, ..] => (*left, *right),
+            [] => unreachable!(),
+        };
+        self.right = Some(right * self.gains.1);
+        Some(left * self.gains.0)
+    }
+}
+
+impl Source for SoundSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        2
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
@@ -0,0 +1,256 @@
+//! Benchmarks for the paths that every frame goes through, so that changes to how
+//! entities are stored can be compared against the current design
+//!
+//! `cargo bench -p bina-ecs`
+use std::hint::black_box;
+
+use bina_ecs::{
+    component::{Component, ComponentField, NumberField, NumberFieldRef, Processable},
+    entity::{Entity, EntityReference, Inaccessible},
+    rayon::prelude::{IntoParallelRefIterator, ParallelIterator},
+    universe::Universe,
+};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+
+/// A component that does nothing, to measure the cost of storing entities alone
+struct Idle(#[allow(dead_code)] u64);
+
+impl Component for Idle {
+    type Reference<'a> = &'a Self;
+
+    fn get_ref<'a>(&'a self) -> Self::Reference<'a> {
+        self
+    }
+}
+
+impl Processable for Idle {
+    fn process<E: Entity>(
+        _component: Self::Reference<'_>,
+        _my_entity: EntityReference<E>,
+        _universe: &Universe,
+    ) {
+    }
+}
+
+/// A component that counts up every frame through a `NumberField`
+struct Counter(NumberField<u64>);
+
+impl Component for Counter {
+    type Reference<'a> = NumberFieldRef<'a, u64>;
+
+    fn get_ref<'a>(&'a self) -> Self::Reference<'a> {
+        self.0.get_ref()
+    }
+
+    fn flush<E: Entity>(
+        &mut self,
+        _my_entity: EntityReference<Inaccessible<E>>,
+        _universe: &Universe,
+    ) {
+        self.0.process_modifiers();
+    }
+}
+
+impl Processable for Counter {
+    fn process<E: Entity>(
+        mut component: Self::Reference<'_>,
+        _my_entity: EntityReference<E>,
+        _universe: &Universe,
+    ) {
+        component += 1;
+    }
+}
+
+/// A component that removes its entity while processing if its id is a multiple of `REMOVE_EVERY`
+struct Doomed(usize);
+
+const REMOVE_EVERY: usize = 4;
+
+impl Component for Doomed {
+    type Reference<'a> = &'a Self;
+
+    fn get_ref<'a>(&'a self) -> Self::Reference<'a> {
+        self
+    }
+}
+
+impl Processable for Doomed {
+    fn process<E: Entity>(
+        component: Self::Reference<'_>,
+        my_entity: EntityReference<E>,
+        universe: &Universe,
+    ) {
+        if component.0 % REMOVE_EVERY == 0 {
+            universe.queue_remove_entity(my_entity);
+        }
+    }
+}
+
+/// Makes a Universe with `count` entities that have already been added
+fn universe_with<E: Entity>(count: usize, mut make: impl FnMut(usize) -> E) -> Universe {
+    let mut universe = Universe::new();
+    for i in 0..count {
+        universe.queue_add_entity(make(i));
+    }
+    // The first frame creates the buffer, and the second adds the entities to it
+    universe.loop_once();
+    universe.loop_once();
+    universe
+}
+
+fn queue_add_entity(c: &mut Criterion) {
+    let mut group = c.benchmark_group("queue_add_entity");
+    for count in [1_000, 100_000] {
+        group.throughput(Throughput::Elements(count as u64));
+        // Only queueing, with a buffer that already exists
+        group.bench_with_input(BenchmarkId::new("queue", count), &count, |b, &count| {
+            b.iter_batched(
+                || universe_with(1, |_| (Idle(0),)),
+                |universe| {
+                    for i in 0..count {
+                        universe.queue_add_entity((Idle(i as u64),));
+                    }
+                    universe
+                },
+                BatchSize::LargeInput,
+            )
+        });
+        // Queueing from many threads at once, which is how entities are usually added
+        group.bench_with_input(
+            BenchmarkId::new("queue_parallel", count),
+            &count,
+            |b, &count| {
+                let ids: Vec<u64> = (0..count as u64).collect();
+                b.iter_batched(
+                    || universe_with(1, |_| (Idle(0),)),
+                    |universe| {
+                        ids.par_iter()
+                            .for_each(|&i| universe.queue_add_entity((Idle(i),)));
+                        universe
+                    },
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+        // Queueing then flushing the entities into the buffer
+        group.bench_with_input(
+            BenchmarkId::new("queue_and_flush", count),
+            &count,
+            |b, &count| {
+                b.iter_batched(
+                    || universe_with(1, |_| (Idle(0),)),
+                    |mut universe| {
+                        for i in 0..count {
+                            universe.queue_add_entity((Idle(i as u64),));
+                        }
+                        universe.loop_once();
+                        universe
+                    },
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+fn flush_with_removals(c: &mut Criterion) {
+    let mut group = c.benchmark_group("flush_with_removals");
+    for count in [10_000, 1_000_000] {
+        group.throughput(Throughput::Elements(count as u64));
+        // Removes every fourth entity, scattered through the buffer so that most removals swap
+        group.bench_with_input(BenchmarkId::new("scattered", count), &count, |b, &count| {
+            b.iter_batched(
+                || universe_with(count, |i| (Doomed(i),)),
+                |mut universe| {
+                    universe.loop_once();
+                    universe
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn process(c: &mut Criterion) {
+    let mut group = c.benchmark_group("process");
+    group.sample_size(20);
+    let count = 1_000_000;
+    group.throughput(Throughput::Elements(count as u64));
+    group.bench_function(BenchmarkId::new("idle", count), |b| {
+        let mut universe = universe_with(count, |i| (Idle(i as u64),));
+        b.iter(|| universe.loop_once())
+    });
+    group.bench_function(BenchmarkId::new("counter", count), |b| {
+        let mut universe = universe_with(count, |_| (Counter(NumberField::new(0)),));
+        b.iter(|| universe.loop_once())
+    });
+    group.bench_function(BenchmarkId::new("idle_and_counter", count), |b| {
+        let mut universe = universe_with(count, |i| (Idle(i as u64), Counter(NumberField::new(0))));
+        b.iter(|| universe.loop_once())
+    });
+    group.finish();
+}
+
+fn number_field(c: &mut Criterion) {
+    let mut group = c.benchmark_group("number_field");
+    let modifiers = 100_000;
+    group.throughput(Throughput::Elements(modifiers));
+    group.bench_function("add_assign", |b| {
+        let mut field = NumberField::new(0u64);
+        b.iter(|| {
+            let mut reference = field.get_ref();
+            for _ in 0..modifiers {
+                reference += black_box(1);
+            }
+            field.process_modifiers();
+        });
+    });
+    // Every thread adds to the same field, so this is as contended as a field can get
+    group.bench_function("add_assign_parallel", |b| {
+        let mut field = NumberField::new(0u64);
+        let ids: Vec<u64> = (0..modifiers).collect();
+        b.iter(|| {
+            ids.par_iter().for_each(|_| {
+                let mut reference = field.get_ref();
+                reference += black_box(1);
+            });
+            field.process_modifiers();
+        });
+    });
+    group.bench_function("add_assign_f32_parallel", |b| {
+        let mut field = NumberField::new(0f32);
+        let ids: Vec<u64> = (0..modifiers).collect();
+        b.iter(|| {
+            ids.par_iter().for_each(|_| {
+                let mut reference = field.get_ref();
+                reference += black_box(0.5);
+            });
+            field.process_modifiers();
+        });
+    });
+    // Applying what was queued into many separate fields, as flushing a buffer does
+    group.bench_function("process_modifiers", |b| {
+        let mut fields: Vec<NumberField<u64>> = (0..modifiers).map(NumberField::new).collect();
+        b.iter(|| {
+            fields.par_iter().for_each(|field| {
+                let mut reference = field.get_ref();
+                reference += 1;
+            });
+            for field in &mut fields {
+                field.process_modifiers();
+            }
+        });
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    queue_add_entity,
+    flush_with_removals,
+    process,
+    number_field
+);
+criterion_main!(benches);
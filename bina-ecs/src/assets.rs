@@ -0,0 +1,693 @@
+//! Loading files into assets in the background
+use std::{
+    any::{type_name, Any, TypeId},
+    sync::{Arc, Weak},
+};
+
+use crossbeam::queue::SegQueue;
+use fxhash::FxHashMap;
+use parking_lot::Mutex;
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::io::{modified_time, POLL_INTERVAL};
+use crate::{
+    pack::{AssetPack, EmbeddedAssets, VirtualFs},
+    singleton::Singleton,
+    universe::Universe,
+};
+
+/// Where an asset is loaded from
+pub enum AssetSource {
+    /// The path of a file. In browsers, this is fetched relative to the page
+    Path(String),
+    /// The contents of a file, such as an image embedded with `include_bytes!`
+    Bytes(Vec<u8>),
+    /// The path of a file inside of a pack or embedded directory
+    Mounted(Arc<dyn VirtualFs>, String),
+}
+
+impl From<&str> for AssetSource {
+    fn from(value: &str) -> Self {
+        Self::Path(value.into())
+    }
+}
+
+impl From<String> for AssetSource {
+    fn from(value: String) -> Self {
+        Self::Path(value)
+    }
+}
+
+impl From<Vec<u8>> for AssetSource {
+    fn from(value: Vec<u8>) -> Self {
+        Self::Bytes(value)
+    }
+}
+
+impl From<&[u8]> for AssetSource {
+    fn from(value: &[u8]) -> Self {
+        Self::Bytes(value.into())
+    }
+}
+
+/// Turns the contents of a file into an asset
+pub trait AssetLoader: Send + Sync + 'static {
+    type Asset: Send + Sync + 'static;
+
+    /// Decodes the contents of a file. This runs in the background,
+    /// so it may take as long as it needs
+    fn load(&self, bytes: Vec<u8>) -> Result<Self::Asset, String>;
+
+    /// The extensions of the files this loader is used for by `Assets::preload`, without the dot
+    fn extensions(&self) -> &[&str] {
+        &[]
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LoadState {
+    Loading,
+    Loaded,
+    Failed,
+}
+
+enum Slot<T> {
+    Loading,
+    Loaded(Arc<T>),
+    Failed(Arc<str>),
+}
+
+struct HandleInner<T> {
+    slot: Mutex<Slot<T>>,
+}
+
+/// A reference counted handle to an asset that may still be loading
+///
+/// The asset is unloaded once every handle to it is dropped
+pub struct Handle<T> {
+    inner: Arc<HandleInner<T>>,
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T: Send + Sync + 'static> Handle<T> {
+    /// Starts loading an asset from the given source without going through `Assets`,
+    /// calling `on_done` once it has finished loading or failed
+    pub fn load<L: AssetLoader<Asset = T> + ?Sized>(
+        universe: &Universe,
+        loader: Arc<L>,
+        source: AssetSource,
+        on_done: impl FnOnce(&Self) + Send + 'static,
+    ) -> Self {
+        let handle = Self {
+            inner: Arc::new(HandleInner {
+                slot: Mutex::new(Slot::Loading),
+            }),
+        };
+        let cloned = handle.clone();
+        universe.spawn(async move {
+            let bytes = match source {
+                AssetSource::Bytes(bytes) => Ok(bytes),
+                AssetSource::Path(path) => crate::io::read(&path)
+                    .await
+                    .map_err(|e| format!("Failed to read {path}: {e}")),
+                AssetSource::Mounted(fs, path) => fs
+                    .read(&path)
+                    .map_err(|e| format!("Failed to read {path}: {e}")),
+            };
+            let slot = match bytes.and_then(|bytes| loader.load(bytes)) {
+                Ok(asset) => Slot::Loaded(Arc::new(asset)),
+                Err(e) => {
+                    log::error!("Failed to load {}: {e}", type_name::<T>());
+                    Slot::Failed(e.into())
+                }
+            };
+            *cloned.inner.slot.lock() = slot;
+            on_done(&cloned);
+        });
+        handle
+    }
+
+    /// Creates a handle to an asset that is already loaded, such as one generated at runtime
+    pub fn from_asset(asset: T) -> Self {
+        Self {
+            inner: Arc::new(HandleInner {
+                slot: Mutex::new(Slot::Loaded(Arc::new(asset))),
+            }),
+        }
+    }
+
+    pub fn load_state(&self) -> LoadState {
+        match &*self.inner.slot.lock() {
+            Slot::Loading => LoadState::Loading,
+            Slot::Loaded(_) => LoadState::Loaded,
+            Slot::Failed(_) => LoadState::Failed,
+        }
+    }
+
+    /// Gets the asset if it has finished loading
+    pub fn get(&self) -> Option<Arc<T>> {
+        match &*self.inner.slot.lock() {
+            Slot::Loaded(asset) => Some(asset.clone()),
+            _ => None,
+        }
+    }
+
+    /// Gets the reason the asset could not be loaded, if it failed to load
+    pub fn error(&self) -> Option<Arc<str>> {
+        match &*self.inner.slot.lock() {
+            Slot::Failed(e) => Some(e.clone()),
+            _ => None,
+        }
+    }
+
+    /// Checks if both handles refer to the same asset
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+
+/// Something that a loading screen waits for, such as an asset or `Graphics::warm_up`
+pub trait Preload: Send + Sync + 'static {
+    fn load_state(&self) -> LoadState;
+}
+
+impl<T: Send + Sync + 'static> Preload for Handle<T> {
+    fn load_state(&self) -> LoadState {
+        Handle::load_state(self)
+    }
+}
+
+/// A type erased `Preload`
+trait ErasedPreload: Send + Sync {
+    fn load_state(&self) -> LoadState;
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<P: Preload> ErasedPreload for P {
+    fn load_state(&self) -> LoadState {
+        Preload::load_state(self)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// How far along a group of assets and other tasks are, such as those started by `Assets::preload`
+///
+/// Assets are kept loaded for as long as this is
+///
+/// ```ignore
+/// let mut progress = assets.preload(universe, ["sprites/hero.png", "sounds/jump.ogg"]);
+/// progress.add_task(graphics.warm_up(&progress));
+/// // Every frame of the loading screen
+/// bar.set_fraction(progress.progress());
+/// if progress.is_done() { ... }
+/// ```
+#[derive(Default)]
+pub struct ProgressHandle {
+    tasks: Vec<Box<dyn ErasedPreload>>,
+}
+
+impl ProgressHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_task(mut self, task: impl Preload) -> Self {
+        self.add_task(task);
+        self
+    }
+
+    pub fn add_task(&mut self, task: impl Preload) {
+        self.tasks.push(Box::new(task));
+    }
+
+    /// The number of assets and tasks, whether or not they have finished
+    pub fn total(&self) -> usize {
+        self.tasks.len()
+    }
+
+    /// The number of assets and tasks that have loaded or failed
+    pub fn finished(&self) -> usize {
+        self.count(|state| state != LoadState::Loading)
+    }
+
+    pub fn failed(&self) -> usize {
+        self.count(|state| state == LoadState::Failed)
+    }
+
+    /// Gets the fraction of assets and tasks that have finished, from 0 to 1
+    pub fn progress(&self) -> f32 {
+        if self.tasks.is_empty() {
+            return 1.0;
+        }
+        self.finished() as f32 / self.total() as f32
+    }
+
+    /// Checks if every asset and task has loaded or failed
+    pub fn is_done(&self) -> bool {
+        self.tasks
+            .iter()
+            .all(|x| x.load_state() != LoadState::Loading)
+    }
+
+    /// Gets the handles of every asset of type `T`
+    pub fn handles<T: Send + Sync + 'static>(&self) -> impl Iterator<Item = &Handle<T>> {
+        self.tasks
+            .iter()
+            .filter_map(|x| x.as_any().downcast_ref::<Handle<T>>())
+    }
+
+    fn count(&self, f: impl Fn(LoadState) -> bool) -> usize {
+        self.tasks.iter().filter(|x| f(x.load_state())).count()
+    }
+}
+
+/// Sent into the Universe when an asset loaded through `Assets` finishes loading,
+/// or is replaced after its file was modified
+///
+/// ```ignore
+/// for event in universe.read_events::<AssetEvent<TextureAsset>>() {
+///     if let AssetEvent::Failed(handle) = event { ... }
+/// }
+/// ```
+pub enum AssetEvent<T> {
+    Loaded(Handle<T>),
+    Failed(Handle<T>),
+    /// Sent after a hot reload. The old asset is kept if the new one fails to load
+    Modified(Handle<T>),
+}
+
+/// A type erased `Weak<HandleInner<T>>`
+trait CachedHandle: Send + Sync {
+    fn is_alive(&self) -> bool;
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<T: Send + Sync + 'static> CachedHandle for Weak<HandleInner<T>> {
+    fn is_alive(&self) -> bool {
+        self.strong_count() > 0
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+type AssetCommand = Box<dyn FnOnce(&Universe) + Send>;
+
+/// Loads the asset at a path with the loader of one type of asset
+type PreloadFn = fn(&Assets, &Universe, &str) -> Box<dyn ErasedPreload>;
+
+fn preload<T: Send + Sync + 'static>(
+    assets: &Assets,
+    universe: &Universe,
+    path: &str,
+) -> Box<dyn ErasedPreload> {
+    Box::new(assets.load::<T>(universe, path))
+}
+
+/// How long to wait for more changes after the platform reports one, as saving
+/// a file in an editor often changes it several times
+#[cfg(all(feature = "notify", not(target_arch = "wasm32")))]
+const NOTIFY_DEBOUNCE: std::time::Duration = std::time::Duration::from_millis(50);
+
+/// A file that is reloaded when it is modified
+#[cfg(not(target_arch = "wasm32"))]
+struct WatchedAsset {
+    path: std::path::PathBuf,
+    /// Only compared when polling for changes
+    modified: Option<std::time::SystemTime>,
+    handle: Box<dyn CachedHandle>,
+    /// Replaces the asset with the new contents of the file
+    reload: Arc<dyn Fn(Vec<u8>) + Send + Sync>,
+}
+
+/// The assets that are hot reloaded
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Default)]
+struct HotReload {
+    assets: Mutex<Vec<WatchedAsset>>,
+    /// `None` if the platform could not watch files, in which case they are polled
+    #[cfg(feature = "notify")]
+    watcher: Mutex<Option<notify::RecommendedWatcher>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl HotReload {
+    /// Reloads every asset that `changed` returns true for
+    ///
+    /// The lock is only held while finding the assets, so that loading
+    /// new assets is not blocked while the changed files are read and decoded
+    fn reload_changed(&self, mut changed: impl FnMut(&mut WatchedAsset) -> bool) {
+        let reloads: Vec<_> = {
+            let mut assets = self.assets.lock();
+            assets.retain(|asset| asset.handle.is_alive());
+            assets
+                .iter_mut()
+                .filter_map(|asset| changed(asset).then(|| (asset.path.clone(), asset.reload.clone())))
+                .collect()
+        };
+        for (path, reload) in reloads {
+            match std::fs::read(&path) {
+                Ok(bytes) => reload(bytes),
+                Err(e) => log::error!("Failed to reload {}: {e}", path.display()),
+            }
+        }
+    }
+
+    /// Checks every file for changes twice a second, until the `Assets` singleton is dropped
+    fn poll(weak: Weak<Self>) {
+        loop {
+            std::thread::sleep(POLL_INTERVAL);
+            let Some(hot_reload) = weak.upgrade() else {
+                break;
+            };
+            hot_reload.reload_changed(|asset| {
+                let modified = modified_time(&asset.path);
+                std::mem::replace(&mut asset.modified, modified) != modified
+            });
+        }
+    }
+
+    /// Reloads the files that the platform reports as changed, until the watcher is dropped
+    #[cfg(feature = "notify")]
+    fn receive(
+        weak: Weak<Self>,
+        events: std::sync::mpsc::Receiver<notify::Result<notify::Event>>,
+    ) {
+        fn collect(changed: &mut Vec<std::path::PathBuf>, event: notify::Result<notify::Event>) {
+            match event {
+                Ok(event) if event.kind.is_create() || event.kind.is_modify() => {
+                    changed.extend(event.paths);
+                }
+                Ok(_) => {}
+                Err(e) => log::error!("Failed to watch assets: {e}"),
+            }
+        }
+
+        let mut changed = Vec::new();
+        while let Ok(event) = events.recv() {
+            collect(&mut changed, event);
+            while let Ok(event) = events.recv_timeout(NOTIFY_DEBOUNCE) {
+                collect(&mut changed, event);
+            }
+            let Some(hot_reload) = weak.upgrade() else {
+                break;
+            };
+            hot_reload.reload_changed(|asset| changed.contains(&asset.path));
+            changed.clear();
+        }
+    }
+
+    /// Starts watching a file, returning the path that events for it are reported with
+    #[cfg(feature = "notify")]
+    fn watch(&self, path: std::path::PathBuf) -> std::path::PathBuf {
+        use notify::Watcher;
+
+        let Some(watcher) = &mut *self.watcher.lock() else {
+            return path;
+        };
+        // Events are reported with absolute paths. Editors often replace files
+        // instead of writing to them, so the directory is watched instead of the file
+        let path = std::fs::canonicalize(&path).unwrap_or(path);
+        if let Some(parent) = path.parent() {
+            if let Err(e) = watcher.watch(parent, notify::RecursiveMode::NonRecursive) {
+                log::error!("Failed to watch {}: {e}", parent.display());
+            }
+        }
+        path
+    }
+}
+
+/// Loads assets in the background using the loaders added to it
+///
+/// Loading the same path twice while the first handle is still
+/// alive returns the same handle instead of loading it again.
+/// Paths are looked up in every mounted pack or embedded directory before the root directory
+///
+/// ```ignore
+/// universe.queue_set_singleton(Assets::new().with_root("assets").with_loader(TextureLoader::default()));
+/// // Later
+/// let hero = assets.load::<TextureAsset>(universe, "sprites/hero.png");
+/// ```
+#[derive(Default)]
+pub struct Assets {
+    root: String,
+    /// `Arc<dyn AssetLoader<Asset = T>>` keyed by the TypeId of `T`
+    loaders: FxHashMap<TypeId, Box<dyn Any + Send + Sync>>,
+    /// Which type of asset `preload` loads files with each extension as
+    extensions: FxHashMap<String, PreloadFn>,
+    /// Checked in the order they were mounted
+    mounts: Vec<Arc<dyn VirtualFs>>,
+    handles: Mutex<FxHashMap<(TypeId, String), Box<dyn CachedHandle>>>,
+    /// Events for assets that finished loading, sent during the next process frame
+    finished: Arc<SegQueue<AssetCommand>>,
+    /// Only set if hot reloading is enabled
+    #[cfg(not(target_arch = "wasm32"))]
+    hot_reload: Option<Arc<HotReload>>,
+}
+
+impl Assets {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the directory that paths are relative to
+    pub fn with_root(mut self, root: impl Into<String>) -> Self {
+        self.root = root.into();
+        self
+    }
+
+    /// Mounts a pack, so that paths inside of it are read from the pack instead of the root
+    ///
+    /// ```ignore
+    /// let pack = AssetPack::load("assets.pack").await?;
+    /// let assets = Assets::new().with_root("assets").with_pack(pack);
+    /// ```
+    pub fn with_pack(mut self, pack: AssetPack) -> Self {
+        self.mounts.push(Arc::new(pack));
+        self
+    }
+
+    /// Mounts a directory that was compiled into the binary with `embed_assets!`
+    pub fn with_embedded(mut self, embedded: EmbeddedAssets) -> Self {
+        self.mounts.push(Arc::new(embedded));
+        self
+    }
+
+    /// Adds a loader, replacing any other loader for the same type of asset
+    pub fn with_loader<L: AssetLoader>(mut self, loader: L) -> Self {
+        self.add_loader(loader);
+        self
+    }
+
+    /// Adds a loader, replacing any other loader for the same type of asset
+    pub fn add_loader<L: AssetLoader>(&mut self, loader: L) {
+        for extension in loader.extensions() {
+            self.extensions
+                .insert(extension.to_ascii_lowercase(), preload::<L::Asset>);
+        }
+        let loader: Arc<dyn AssetLoader<Asset = L::Asset>> = Arc::new(loader);
+        self.loaders
+            .insert(TypeId::of::<L::Asset>(), Box::new(loader));
+    }
+
+    /// Reloads assets whenever the files they were loaded from are modified,
+    /// sending `AssetEvent::Modified` once they have been replaced
+    ///
+    /// With the `notify` feature, files are reloaded as soon as the platform reports that they
+    /// changed. Otherwise, or if the platform cannot watch files, they are checked for changes
+    /// twice a second on a separate thread. Existing handles will return the new asset, but
+    /// anything taken out of a handle beforehand, such as a `Texture`, must be fetched again
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn with_hot_reload(mut self) -> Self {
+        let hot_reload = Arc::new(HotReload::default());
+        let weak = Arc::downgrade(&hot_reload);
+        #[cfg(feature = "notify")]
+        {
+            let (sender, events) = std::sync::mpsc::channel();
+            match notify::recommended_watcher(sender) {
+                Ok(watcher) => {
+                    *hot_reload.watcher.lock() = Some(watcher);
+                    std::thread::spawn(move || HotReload::receive(weak, events));
+                    self.hot_reload = Some(hot_reload);
+                    return self;
+                }
+                Err(e) => log::error!("Failed to watch assets, falling back to polling: {e}"),
+            }
+        }
+        std::thread::spawn(move || HotReload::poll(weak));
+        self.hot_reload = Some(hot_reload);
+        self
+    }
+
+    /// Starts loading the asset at the given path, relative to the root
+    ///
+    /// # Panics
+    /// Panics if no loader was added for `T`
+    pub fn load<T: Send + Sync + 'static>(&self, universe: &Universe, path: &str) -> Handle<T> {
+        let key = (TypeId::of::<T>(), path.to_owned());
+        let mut handles = self.handles.lock();
+        if let Some(handle) = handles.get(&key) {
+            let weak = handle
+                .as_any()
+                .downcast_ref::<Weak<HandleInner<T>>>()
+                .expect("Handles should be keyed by the TypeId of their asset");
+            if let Some(inner) = weak.upgrade() {
+                return Handle { inner };
+            }
+        }
+
+        let loader = self
+            .loaders
+            .get(&TypeId::of::<T>())
+            .and_then(|x| x.downcast_ref::<Arc<dyn AssetLoader<Asset = T>>>())
+            .unwrap_or_else(|| panic!("No loader was added for {}", type_name::<T>()))
+            .clone();
+        let mount = self.mounts.iter().find(|fs| fs.contains(path));
+        let full_path = if self.root.is_empty() {
+            path.to_owned()
+        } else {
+            format!("{}/{path}", self.root)
+        };
+        let source = match mount {
+            Some(fs) => AssetSource::Mounted(fs.clone(), path.to_owned()),
+            None => full_path.clone().into(),
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        let reload_loader = loader.clone();
+        let finished = self.finished.clone();
+        let handle = Handle::load(universe, loader, source, move |handle| {
+            let event = match handle.load_state() {
+                LoadState::Failed => AssetEvent::Failed(handle.clone()),
+                _ => AssetEvent::Loaded(handle.clone()),
+            };
+            finished.push(Box::new(move |universe| universe.send_event(event)));
+        });
+        handles.insert(key, Box::new(Arc::downgrade(&handle.inner)));
+
+        // Packs are only rebuilt between runs, so only loose files are watched
+        #[cfg(not(target_arch = "wasm32"))]
+        if let (Some(hot_reload), None) = (&self.hot_reload, mount) {
+            let weak = Arc::downgrade(&handle.inner);
+            let finished = self.finished.clone();
+            let path = full_path.clone();
+            let full_path = std::path::PathBuf::from(full_path);
+            #[cfg(feature = "notify")]
+            let full_path = hot_reload.watch(full_path);
+            hot_reload.assets.lock().push(WatchedAsset {
+                modified: modified_time(&full_path),
+                path: full_path,
+                handle: Box::new(weak.clone()),
+                reload: Arc::new(move |bytes| {
+                    let Some(inner) = weak.upgrade() else {
+                        return;
+                    };
+                    match reload_loader.load(bytes) {
+                        Ok(asset) => {
+                            *inner.slot.lock() = Slot::Loaded(Arc::new(asset));
+                            let handle = Handle { inner };
+                            finished.push(Box::new(move |universe| {
+                                universe.send_event(AssetEvent::Modified(handle))
+                            }));
+                        }
+                        Err(e) => log::error!("Failed to reload {path}: {e}"),
+                    }
+                }),
+            });
+        }
+        handle
+    }
+
+    /// Starts loading every path, using the loader whose `AssetLoader::extensions`
+    /// contains the extension of the path
+    ///
+    /// Paths are loaded the same way as `load`, so loading them again returns the same handles
+    /// for as long as the returned `ProgressHandle` is kept
+    ///
+    /// # Panics
+    /// Panics if no loader was added for the extension of a path
+    pub fn preload<'a>(
+        &self,
+        universe: &Universe,
+        paths: impl IntoIterator<Item = &'a str>,
+    ) -> ProgressHandle {
+        let tasks = paths
+            .into_iter()
+            .map(|path| {
+                let extension = path
+                    .rsplit_once('.')
+                    .map(|(_, x)| x.to_ascii_lowercase())
+                    .unwrap_or_default();
+                let preload = self
+                    .extensions
+                    .get(&extension)
+                    .unwrap_or_else(|| panic!("No loader was added for .{extension} files"));
+                preload(self, universe, path)
+            })
+            .collect();
+        ProgressHandle { tasks }
+    }
+}
+
+impl Singleton for Assets {
+    fn process(&self, universe: &Universe) {
+        while let Some(command) = self.finished.pop() {
+            command(universe);
+        }
+    }
+
+    fn flush(&mut self, _universe: &Universe) {
+        // Forget assets that were unloaded after their last handle was dropped
+        self.handles.get_mut().retain(|_, handle| handle.is_alive());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU8, Ordering};
+
+    use super::*;
+
+    struct Task(Arc<AtomicU8>);
+
+    impl Preload for Task {
+        fn load_state(&self) -> LoadState {
+            match self.0.load(Ordering::Relaxed) {
+                0 => LoadState::Loading,
+                1 => LoadState::Loaded,
+                _ => LoadState::Failed,
+            }
+        }
+    }
+
+    #[test]
+    fn progress_counts_finished_tasks() {
+        let first = Arc::new(AtomicU8::new(0));
+        let second = Arc::new(AtomicU8::new(0));
+        let progress = ProgressHandle::new()
+            .with_task(Task(first.clone()))
+            .with_task(Task(second.clone()))
+            .with_task(Handle::from_asset(5u32));
+        assert_eq!(progress.total(), 3);
+        assert_eq!(progress.finished(), 1);
+        assert!(!progress.is_done());
+
+        first.store(1, Ordering::Relaxed);
+        second.store(2, Ordering::Relaxed);
+        assert!(progress.is_done());
+        assert_eq!(progress.failed(), 1);
+        assert_eq!(progress.progress(), 1.0);
+        assert_eq!(progress.handles::<u32>().count(), 1);
+        assert_eq!(ProgressHandle::new().progress(), 1.0);
+    }
+}
@@ -0,0 +1,243 @@
+//! Behavior trees, for AI that picks between prioritized actions
+//!
+//! ```ignore
+//! let tree = BehaviorTree::new(
+//!     Behavior::selector([
+//!         Behavior::sequence([
+//!             Behavior::condition(|cx: &mut Context<Brain>| cx.blackboard.target.is_some()),
+//!             Behavior::action(|cx| cx.blackboard.chase(cx.universe.get_delta())),
+//!         ]),
+//!         Behavior::action(|cx| {
+//!             let polygon = cx.entity.get_component::<Polygon>().unwrap();
+//!             cx.blackboard.wander(polygon)
+//!         }),
+//!     ]),
+//!     Brain::default(),
+//! );
+//! universe.queue_add_entity((tree, polygon));
+//! ```
+use crossbeam::atomic::AtomicCell;
+use parking_lot::{MappedMutexGuard, Mutex, MutexGuard};
+
+use crate::{
+    component::{Component, Processable},
+    entity::{Entity, EntityReference, ErasedEntityReference},
+    universe::Universe,
+};
+
+/// The result of ticking a behavior
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Status {
+    Success,
+    Failure,
+    /// The behavior has not finished, and will be ticked again next frame
+    Running,
+}
+
+/// What a leaf of a behavior tree can access while it is ticked
+pub struct Context<'a, B> {
+    /// The state shared by every behavior in the tree
+    pub blackboard: &'a mut B,
+    /// The entity that the tree is a component of.
+    /// The tree itself cannot be accessed through this, as it is being ticked
+    pub entity: &'a dyn ErasedEntityReference,
+    pub universe: &'a Universe,
+}
+
+type Leaf<B> = Box<dyn FnMut(&mut Context<B>) -> Status + Send>;
+type Decorator<B> = Box<dyn FnMut(Status, &mut Context<B>) -> Status + Send>;
+
+/// A node in a behavior tree
+///
+/// Sequences and selectors remember which child is running,
+/// so that they continue from it on the next tick instead of starting over
+pub enum Behavior<B> {
+    /// Runs each child in order until one fails
+    Sequence {
+        children: Vec<Behavior<B>>,
+        current: usize,
+    },
+    /// Runs each child in order until one succeeds
+    Selector {
+        children: Vec<Behavior<B>>,
+        current: usize,
+    },
+    /// Swaps success and failure
+    Invert(Box<Behavior<B>>),
+    /// Succeeds whenever the child finishes, even if it failed
+    AlwaysSucceed(Box<Behavior<B>>),
+    /// Runs the child once per tick until it has finished `times` times, or forever if `None`
+    Repeat {
+        child: Box<Behavior<B>>,
+        times: Option<u32>,
+        finished: u32,
+    },
+    /// Changes the status of the child with a closure
+    Decorate(Box<Behavior<B>>, Decorator<B>),
+    Action(Leaf<B>),
+}
+
+impl<B> Behavior<B> {
+    pub fn sequence(children: impl IntoIterator<Item = Self>) -> Self {
+        Self::Sequence {
+            children: children.into_iter().collect(),
+            current: 0,
+        }
+    }
+
+    pub fn selector(children: impl IntoIterator<Item = Self>) -> Self {
+        Self::Selector {
+            children: children.into_iter().collect(),
+            current: 0,
+        }
+    }
+
+    pub fn action(action: impl FnMut(&mut Context<B>) -> Status + Send + 'static) -> Self {
+        Self::Action(Box::new(action))
+    }
+
+    /// An action that succeeds if the closure returns true, and fails otherwise
+    pub fn condition(mut condition: impl FnMut(&mut Context<B>) -> bool + Send + 'static) -> Self {
+        Self::action(move |cx| {
+            if condition(cx) {
+                Status::Success
+            } else {
+                Status::Failure
+            }
+        })
+    }
+
+    pub fn invert(self) -> Self {
+        Self::Invert(Box::new(self))
+    }
+
+    pub fn always_succeed(self) -> Self {
+        Self::AlwaysSucceed(Box::new(self))
+    }
+
+    pub fn repeat(self, times: Option<u32>) -> Self {
+        Self::Repeat {
+            child: Box::new(self),
+            times,
+            finished: 0,
+        }
+    }
+
+    pub fn decorate(
+        self,
+        decorator: impl FnMut(Status, &mut Context<B>) -> Status + Send + 'static,
+    ) -> Self {
+        Self::Decorate(Box::new(self), Box::new(decorator))
+    }
+
+    pub fn tick(&mut self, cx: &mut Context<B>) -> Status {
+        match self {
+            Self::Sequence { children, current } => {
+                tick_children(children, current, Status::Success, cx)
+            }
+            Self::Selector { children, current } => {
+                tick_children(children, current, Status::Failure, cx)
+            }
+            Self::Invert(child) => match child.tick(cx) {
+                Status::Success => Status::Failure,
+                Status::Failure => Status::Success,
+                Status::Running => Status::Running,
+            },
+            Self::AlwaysSucceed(child) => match child.tick(cx) {
+                Status::Running => Status::Running,
+                _ => Status::Success,
+            },
+            Self::Repeat {
+                child,
+                times,
+                finished,
+            } => {
+                if child.tick(cx) == Status::Running {
+                    return Status::Running;
+                }
+                *finished += 1;
+                if times.is_some_and(|times| *finished >= times) {
+                    *finished = 0;
+                    Status::Success
+                } else {
+                    Status::Running
+                }
+            }
+            Self::Decorate(child, decorator) => {
+                let status = child.tick(cx);
+                decorator(status, cx)
+            }
+            Self::Action(action) => action(cx),
+        }
+    }
+}
+
+/// Ticks children starting from `current` for as long as they return `keep_going`
+fn tick_children<B>(
+    children: &mut [Behavior<B>],
+    current: &mut usize,
+    keep_going: Status,
+    cx: &mut Context<B>,
+) -> Status {
+    while let Some(child) = children.get_mut(*current) {
+        match child.tick(cx) {
+            Status::Running => return Status::Running,
+            status if status == keep_going => *current += 1,
+            status => {
+                *current = 0;
+                return status;
+            }
+        }
+    }
+    *current = 0;
+    keep_going
+}
+
+/// A component that ticks a behavior tree once every process frame
+///
+/// Once the root finishes, it starts over on the next frame
+pub struct BehaviorTree<B> {
+    inner: Mutex<(Behavior<B>, B)>,
+    status: AtomicCell<Status>,
+}
+
+impl<B: Send + 'static> BehaviorTree<B> {
+    pub fn new(root: Behavior<B>, blackboard: B) -> Self {
+        Self {
+            inner: Mutex::new((root, blackboard)),
+            status: AtomicCell::new(Status::Running),
+        }
+    }
+
+    /// The status of the root from the last tick
+    pub fn status(&self) -> Status {
+        self.status.load()
+    }
+
+    pub fn blackboard(&self) -> MappedMutexGuard<'_, B> {
+        MutexGuard::map(self.inner.lock(), |(_, blackboard)| blackboard)
+    }
+}
+
+impl<B: Send + 'static> Component for BehaviorTree<B> {
+    fn get_ref<'a>(&'a self) -> Self::Reference<'a> {
+        self
+    }
+}
+
+impl<B: Send + 'static> Processable for BehaviorTree<B> {
+    fn process<E: Entity>(
+        component: Self::Reference<'_>,
+        my_entity: EntityReference<E>,
+        universe: &Universe,
+    ) {
+        let mut inner = component.inner.lock();
+        let (root, blackboard) = &mut *inner;
+        let status = root.tick(&mut Context {
+            blackboard,
+            entity: &my_entity,
+            universe,
+        });
+        component.status.store(status);
+    }
+}
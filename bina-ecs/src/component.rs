@@ -42,6 +42,20 @@ pub trait Processable: Component {
     );
 }
 
+/// A component with no data, which only marks the entities that have it
+///
+/// Tags take no memory in their entities, and checking for them with
+/// `EntityReference::has_tag` only compares types. Usually derived with
+/// `bina::macros::Tag`, which only accepts unit structs
+///
+/// ```ignore
+/// #[derive(Tag)]
+/// struct Enemy;
+///
+/// if other.has_tag::<Enemy>() { ... }
+/// ```
+pub trait Tag: Processable {}
+
 pub trait ComponentField {
     fn process_modifiers(&mut self);
 }
@@ -257,6 +271,13 @@ impl<T: AtomicNumber> NumberField<T> {
     pub fn get_inner(&self) -> T {
         self.number
     }
+
+    /// Sets the value immediately, discarding any modifications
+    /// that have not been processed yet
+    pub fn set_inner(&mut self, number: T) {
+        self.number = number;
+        T::store(&self.new_number, number);
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -351,7 +372,34 @@ impl<'a, T: AtomicNumber> NumberFieldRef<'a, T> {
 
 pub struct StagedMutField<T> {
     value: T,
-    modifiers: SegQueue<Box<dyn FnOnce(&mut T)>>,
+    modifiers: SegQueue<Box<dyn FnOnce(&mut T) + Send>>,
+}
+
+impl<T> StagedMutField<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            value,
+            modifiers: SegQueue::new(),
+        }
+    }
+
+    pub fn get_ref(&self) -> StagedMutFieldRef<'_, T> {
+        StagedMutFieldRef { reference: self }
+    }
+
+    pub fn get_inner(&self) -> &T {
+        &self.value
+    }
+
+    pub fn get_inner_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T> From<T> for StagedMutField<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
 }
 
 impl<T> ComponentField for StagedMutField<T> {
@@ -362,13 +410,20 @@ impl<T> ComponentField for StagedMutField<T> {
     }
 }
 
-#[derive(Clone, Copy)]
 pub struct StagedMutFieldRef<'a, T> {
     reference: &'a StagedMutField<T>,
 }
 
+impl<'a, T> Clone for StagedMutFieldRef<'a, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, T> Copy for StagedMutFieldRef<'a, T> {}
+
 impl<'a, T> StagedMutFieldRef<'a, T> {
-    pub fn queue_modifier(&self, modifier: impl FnOnce(&mut T) + 'static) {
+    pub fn queue_modifier(&self, modifier: impl FnOnce(&mut T) + Send + 'static) {
         self.reference.modifiers.push(Box::new(modifier));
     }
 }
@@ -381,6 +436,69 @@ impl<'a, T> Deref for StagedMutFieldRef<'a, T> {
     }
 }
 
+/// Messages sent to a component by the other components in its entity
+///
+/// Messages sent during a process frame are only received once this field has
+/// processed its modifiers, which should be done while flushing the component.
+/// They can then be read until the next time it is flushed
+///
+/// ```ignore
+/// struct Health { hp: NumberField<f32>, damage: Mailbox<f32> }
+///
+/// impl Receiver for Health {
+///     type Message = f32;
+///     fn mailbox(&self) -> &Mailbox<f32> { &self.damage }
+/// }
+///
+/// // While processing a sibling component
+/// my_entity.send_to::<Health>(10.0);
+/// ```
+pub struct Mailbox<M> {
+    received: Vec<M>,
+    pending: SegQueue<M>,
+}
+
+impl<M> Mailbox<M> {
+    pub fn new() -> Self {
+        Self {
+            received: Vec::new(),
+            pending: SegQueue::new(),
+        }
+    }
+
+    /// Queues a message to be received when this field is flushed
+    pub fn send(&self, message: M) {
+        self.pending.push(message);
+    }
+
+    /// Gets the messages that were received when this field was last flushed
+    pub fn read(&self) -> &[M] {
+        &self.received
+    }
+}
+
+impl<M> Default for Mailbox<M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M> ComponentField for Mailbox<M> {
+    fn process_modifiers(&mut self) {
+        self.received.clear();
+        while let Some(message) = self.pending.pop() {
+            self.received.push(message);
+        }
+    }
+}
+
+/// A component that other components in its entity can send messages to with `EntityReference::send_to`
+pub trait Receiver: Component {
+    type Message: Send;
+
+    fn mailbox(&self) -> &Mailbox<Self::Message>;
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
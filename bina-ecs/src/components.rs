@@ -72,9 +72,7 @@ pub enum WatchedFutureError {
 impl<T: Send + Sync + 'static> WatchedFuture<T> {
     pub fn new(fut: impl Future<Output = T> + Send + 'static, universe: &Universe) -> Self {
         let (sender, receiver) = channel();
-        let _ = universe.enter_tokio();
-
-        tokio::spawn(async {
+        universe.spawn(async {
             let _ = sender.send(fut.await);
         });
 
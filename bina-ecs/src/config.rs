@@ -0,0 +1,172 @@
+//! Tuning data that is loaded from RON files into singletons
+use std::{fmt::Display, io, marker::PhantomData, sync::Arc};
+#[cfg(not(target_arch = "wasm32"))]
+use std::{path::PathBuf, thread::JoinHandle, time::SystemTime};
+
+#[cfg(not(target_arch = "wasm32"))]
+use crossbeam::channel::{unbounded, Receiver, RecvTimeoutError, Sender};
+use parking_lot::Mutex;
+use serde::de::DeserializeOwned;
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::io::{modified_time, POLL_INTERVAL};
+use crate::{singleton::Singleton, universe::Universe};
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(String),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{e}"),
+            Self::Parse(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<io::Error> for ConfigError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+/// Parses the contents of a RON file
+pub fn parse<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, ConfigError> {
+    ron::de::from_bytes(bytes).map_err(|e| ConfigError::Parse(e.to_string()))
+}
+
+/// Reads and parses a RON file
+#[cfg(not(target_arch = "wasm32"))]
+pub fn read<T: DeserializeOwned>(path: &std::path::Path) -> Result<T, ConfigError> {
+    parse(&std::fs::read(path)?)
+}
+
+/// Checks a RON file for changes twice a second on a separate thread, and writes whatever is sent to it
+///
+/// `reloaded` is called with the new contents whenever the file is modified after `modified`,
+/// which should be when it was last read. Writing happens on the same thread, so a write is never
+/// read back as a change. The thread stops once the sender of `writes` is dropped, after writing
+/// everything sent before then
+#[cfg(not(target_arch = "wasm32"))]
+pub fn watch<T: DeserializeOwned>(
+    path: PathBuf,
+    mut modified: Option<SystemTime>,
+    writes: Receiver<String>,
+    mut reloaded: impl FnMut(T) + Send + 'static,
+) -> JoinHandle<()> {
+    std::thread::spawn(move || loop {
+        match writes.recv_timeout(POLL_INTERVAL) {
+            Ok(contents) => {
+                let result = match path.parent() {
+                    Some(parent) if !parent.as_os_str().is_empty() => {
+                        std::fs::create_dir_all(parent)
+                    }
+                    _ => Ok(()),
+                }
+                .and_then(|_| std::fs::write(&path, contents));
+                if let Err(e) = result {
+                    log::error!("Failed to save {}: {e}", path.display());
+                }
+                modified = modified_time(&path);
+                continue;
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+        let current = modified_time(&path);
+        if current == modified {
+            continue;
+        }
+        modified = current;
+        match read(&path) {
+            Ok(values) => reloaded(values),
+            Err(e) => log::error!("Failed to reload {}: {e}", path.display()),
+        }
+    })
+}
+
+/// Replaces the singleton `T` whenever a new version of its file has been read
+struct ConfigFile<T> {
+    pending: Arc<Mutex<Option<T>>>,
+    /// Stops watching the file once dropped
+    #[cfg(not(target_arch = "wasm32"))]
+    _writes: Sender<String>,
+    _phantom: PhantomData<fn() -> T>,
+}
+
+impl<T: Singleton> Singleton for ConfigFile<T> {
+    fn process(&self, universe: &Universe) {
+        if let Some(config) = self.pending.lock().take() {
+            universe.queue_set_singleton(config);
+        }
+    }
+}
+
+impl Universe {
+    /// Reads a RON file into the singleton `T`, which is added when the process frame ends
+    ///
+    /// The file is checked for changes twice a second, and the singleton is replaced
+    /// whenever it is modified. If the new contents cannot be parsed, the error is logged
+    /// and the old values are kept. Calling this again for the same `T` stops watching the old file
+    ///
+    /// ```ignore
+    /// #[derive(Deserialize)]
+    /// struct Balance { enemy_health: f32, spawn_rate: f32 }
+    /// impl Singleton for Balance {}
+    ///
+    /// universe.load_config::<Balance>("config/balance.ron")?;
+    /// ```
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_config<T: Singleton + DeserializeOwned>(
+        &self,
+        path: &str,
+    ) -> Result<(), ConfigError> {
+        let path = PathBuf::from(path);
+        let modified = modified_time(&path);
+        self.queue_set_singleton(read::<T>(&path)?);
+
+        let pending = Arc::new(Mutex::new(None));
+        let (writes, receiver) = unbounded();
+        let sender = pending.clone();
+        watch(path, modified, receiver, move |config: T| *sender.lock() = Some(config));
+        self.queue_set_singleton(ConfigFile {
+            pending,
+            _writes: writes,
+            _phantom: PhantomData,
+        });
+        Ok(())
+    }
+
+    /// Fetches a RON file relative to the page, and reads it into the singleton `T`
+    ///
+    /// Browsers cannot watch files, so the file is only read once. Errors are logged
+    #[cfg(target_arch = "wasm32")]
+    pub fn load_config<T: Singleton + DeserializeOwned>(
+        &self,
+        path: &str,
+    ) -> Result<(), ConfigError> {
+        let pending = Arc::new(Mutex::new(None));
+        let sender = pending.clone();
+        let path = path.to_owned();
+        self.spawn(async move {
+            match crate::io::read(&path)
+                .await
+                .map_err(ConfigError::from)
+                .and_then(|bytes| parse::<T>(&bytes))
+            {
+                Ok(config) => *sender.lock() = Some(config),
+                Err(e) => log::error!("Failed to load {path}: {e}"),
+            }
+        });
+        self.queue_set_singleton(ConfigFile {
+            pending,
+            _phantom: PhantomData,
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,288 @@
+//! Writing a report to disk when the game panics, so that players can send actionable bug reports
+//!
+//! A report has the panic message, a backtrace, the OS, the most recent log messages,
+//! and every section set with `set_section`. `Diagnostics` keeps a snapshot of itself in the
+//! `diagnostics` section, and `Graphics` describes the GPU in the `gpu` section
+//!
+//! ```ignore
+//! CrashReporter::new("crashes")
+//!     .with_game("My Game", env!("CARGO_PKG_VERSION"))
+//!     .with_logger(Box::new(my_logger))
+//!     .with_dialog(true)
+//!     .install();
+//! ```
+use std::{
+    backtrace::Backtrace,
+    cell::Cell,
+    collections::{BTreeMap, VecDeque},
+    fmt::Write as _,
+    panic::PanicHookInfo,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use parking_lot::Mutex;
+
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+static SECTIONS: Mutex<BTreeMap<&'static str, String>> = Mutex::new(BTreeMap::new());
+static RECENT_LOGS: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+/// Counts the reports written by this process, so that panics in the same second get their own files
+static REPORTS: AtomicU32 = AtomicU32::new(0);
+
+thread_local! {
+    /// Whether this thread is running code whose panics are caught, such as the processing of
+    /// entities under `PanicPolicy::Despawn`
+    static CATCHING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Runs `f` without writing a report if it panics, for code that catches its own panics
+/// and keeps the game running
+pub(crate) fn catching<R>(f: impl FnOnce() -> R) -> R {
+    let previous = CATCHING.with(|x| x.replace(true));
+    let result = f();
+    CATCHING.with(|x| x.set(previous));
+    result
+}
+
+/// Whether a `CrashReporter` was installed, so that sections are only kept up to date when they would be written
+pub fn is_installed() -> bool {
+    INSTALLED.load(Ordering::Relaxed)
+}
+
+/// Replaces a section of every report written after this, such as a description of a level
+pub fn set_section(name: &'static str, contents: impl Into<String>) {
+    SECTIONS.lock().insert(name, contents.into());
+}
+
+/// Keeps the most recent log messages for crash reports, passing every record on to another logger
+struct RecentLogger {
+    inner: Option<Box<dyn log::Log>>,
+    capacity: usize,
+    max_level: log::LevelFilter,
+}
+
+impl log::Log for RecentLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= self.max_level
+            && (self.capacity > 0 || self.inner.as_ref().is_some_and(|x| x.enabled(metadata)))
+    }
+
+    fn log(&self, record: &log::Record) {
+        if record.level() > self.max_level {
+            return;
+        }
+        if self.capacity > 0 {
+            let mut logs = RECENT_LOGS.lock();
+            if logs.len() == self.capacity {
+                logs.pop_front();
+            }
+            logs.push_back(format!(
+                "[{} {}] {}",
+                record.level(),
+                record.target(),
+                record.args()
+            ));
+        }
+        if let Some(inner) = &self.inner {
+            if inner.enabled(record.metadata()) {
+                inner.log(record);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(inner) = &self.inner {
+            inner.flush();
+        }
+    }
+}
+
+/// Installs a panic hook that writes a crash report into a directory
+///
+/// Only installed in release builds, unless `with_debug_builds` is set,
+/// as a debugger or the terminal is more useful while developing
+pub struct CrashReporter {
+    dir: PathBuf,
+    game: String,
+    version: String,
+    logger: Option<Box<dyn log::Log>>,
+    log_capacity: usize,
+    max_level: log::LevelFilter,
+    dialog: bool,
+    debug_builds: bool,
+}
+
+impl CrashReporter {
+    /// Writes reports into the given directory, which is created if it does not exist
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            game: "The game".into(),
+            version: String::new(),
+            logger: None,
+            log_capacity: 200,
+            max_level: log::LevelFilter::Info,
+            dialog: false,
+            debug_builds: false,
+        }
+    }
+
+    /// Names the game and its version at the top of every report, and in the dialog
+    pub fn with_game(mut self, name: impl Into<String>, version: impl Into<String>) -> Self {
+        self.game = name.into();
+        self.version = version.into();
+        self
+    }
+
+    /// Passes every log record on to the given logger. The crash reporter becomes the global
+    /// logger to keep the most recent messages, so another logger cannot be installed alongside it
+    pub fn with_logger(mut self, logger: Box<dyn log::Log>) -> Self {
+        self.logger = Some(logger);
+        self
+    }
+
+    /// Sets how many of the most recent log messages are written into reports, which is 200 by default
+    pub fn with_log_capacity(mut self, capacity: usize) -> Self {
+        self.log_capacity = capacity;
+        self
+    }
+
+    /// Sets the most verbose level that is logged, which is `Info` by default, as the crash
+    /// reporter becomes the global logger and decides which records are formatted at all
+    pub fn with_max_level(mut self, max_level: log::LevelFilter) -> Self {
+        self.max_level = max_level;
+        self
+    }
+
+    /// Shows a native dialog telling the player where the report was written.
+    /// Does nothing without the `crash_dialog` feature
+    pub fn with_dialog(mut self, dialog: bool) -> Self {
+        self.dialog = dialog;
+        self
+    }
+
+    /// Also installs the reporter in debug builds, such as to test it
+    pub fn with_debug_builds(mut self, debug_builds: bool) -> Self {
+        self.debug_builds = debug_builds;
+        self
+    }
+
+    /// Installs the panic hook and the logger. The previous panic hook still runs after the report is written
+    pub fn install(self) {
+        if cfg!(debug_assertions) && !self.debug_builds {
+            return;
+        }
+        let logger = RecentLogger {
+            inner: self.logger,
+            capacity: self.log_capacity,
+            max_level: self.max_level,
+        };
+        if log::set_boxed_logger(Box::new(logger)).is_ok() {
+            log::set_max_level(self.max_level);
+        } else {
+            log::warn!(
+                "A logger was already installed, so crash reports will not have recent log messages"
+            );
+        }
+        INSTALLED.store(true, Ordering::Relaxed);
+
+        let previous = std::panic::take_hook();
+        let dir = self.dir;
+        let game = self.game;
+        let version = self.version;
+        let dialog = self.dialog;
+        std::panic::set_hook(Box::new(move |info| {
+            if CATCHING.with(Cell::get) {
+                previous(info);
+                return;
+            }
+            let report = write_report(&game, &version, info, &Backtrace::force_capture());
+            match save_report(&dir, &report) {
+                Ok(path) => {
+                    eprintln!("A crash report was written to {}", path.display());
+                    if dialog {
+                        show_dialog(&game, &path);
+                    }
+                }
+                Err(e) => eprintln!("Failed to write a crash report into {}: {e}", dir.display()),
+            }
+            previous(info);
+        }));
+    }
+}
+
+fn write_report(game: &str, version: &str, info: &PanicHookInfo, backtrace: &Backtrace) -> String {
+    let mut report = String::new();
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |x| x.as_secs());
+    let _ = writeln!(
+        report,
+        "{game} {version} crashed at {seconds} (seconds since the Unix epoch)"
+    );
+    let _ = writeln!(
+        report,
+        "OS: {} {} ({})",
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        std::env::consts::FAMILY
+    );
+    let thread = std::thread::current();
+    let _ = writeln!(
+        report,
+        "Thread '{}' {info}",
+        thread.name().unwrap_or("<unnamed>")
+    );
+    let _ = writeln!(report, "\nBacktrace:\n{backtrace}");
+    // The panic may have happened while a lock was held, which must not deadlock the report
+    if let Some(sections) = SECTIONS.try_lock() {
+        for (name, contents) in sections.iter() {
+            let _ = writeln!(report, "[{name}]\n{contents}\n");
+        }
+    }
+    if let Some(logs) = RECENT_LOGS.try_lock() {
+        let _ = writeln!(report, "[recent log]");
+        for line in logs.iter() {
+            let _ = writeln!(report, "{line}");
+        }
+    }
+    report
+}
+
+fn save_report(dir: &Path, report: &str) -> std::io::Result<PathBuf> {
+    std::fs::create_dir_all(dir)?;
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |x| x.as_secs());
+    let path = dir.join(format!(
+        "crash-{seconds}-{}-{}.txt",
+        std::process::id(),
+        REPORTS.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::write(&path, report)?;
+    Ok(path)
+}
+
+#[cfg(all(
+    feature = "crash_dialog",
+    not(any(target_arch = "wasm32", target_os = "android"))
+))]
+fn show_dialog(game: &str, path: &Path) {
+    let text = format!(
+        "{game} has crashed. A report was written to {}, which can be sent to the developers",
+        path.display()
+    );
+    rfd::MessageDialog::new()
+        .set_level(rfd::MessageLevel::Error)
+        .set_title("Crash")
+        .set_description(text)
+        .set_buttons(rfd::MessageButtons::Ok)
+        .show();
+}
+
+#[cfg(not(all(
+    feature = "crash_dialog",
+    not(any(target_arch = "wasm32", target_os = "android"))
+)))]
+fn show_dialog(_game: &str, _path: &Path) {}
@@ -0,0 +1,256 @@
+use std::{collections::VecDeque, io::Write};
+
+use fxhash::FxHashMap;
+use parking_lot::Mutex;
+
+use crate::{
+    entity::EntityBufferMemory, pacing::FramePacingStats, singleton::Singleton, universe::Universe,
+};
+
+/// How many frame times are kept for computing statistics
+const FRAME_HISTORY: usize = 240;
+
+/// How many messages are kept before the oldest are dropped
+const MESSAGE_HISTORY: usize = 64;
+
+/// Statistics about the duration of recent frames, in seconds
+#[derive(Clone, Copy, Default, Debug)]
+pub struct FrameTimes {
+    /// Frames per second, based on the average frame time
+    pub fps: f32,
+    pub average: f32,
+    /// The median frame time
+    pub p50: f32,
+    pub p95: f32,
+    pub p99: f32,
+    pub worst: f32,
+}
+
+/// A message reported to `Diagnostics`, such as an error that did not stop the Universe
+#[derive(Clone, Debug)]
+pub struct DiagnosticMessage {
+    /// What reported the message
+    pub source: &'static str,
+    pub message: String,
+}
+
+/// Collects statistics about the Universe every frame
+///
+/// This singleton is not added automatically. Once added, it records frame times
+/// and entity counts, and any component or singleton can add its own counters.
+/// All statistics are from the previous frame, so they do not change while processing
+///
+/// Every frame can also be written out as CSV with `with_csv`, such as to compare benchmarks between releases
+#[derive(Default)]
+pub struct Diagnostics {
+    history: VecDeque<f32>,
+    frame_times: FrameTimes,
+    frame_pacing: Option<FramePacingStats>,
+    entity_counts: Vec<(&'static str, usize)>,
+    entity_memory: Vec<EntityBufferMemory>,
+    pending_entity_memory: Mutex<Vec<EntityBufferMemory>>,
+    counters: Vec<(&'static str, f64)>,
+    pending_counters: Mutex<FxHashMap<&'static str, f64>>,
+    messages: VecDeque<DiagnosticMessage>,
+    pending_messages: Mutex<Vec<DiagnosticMessage>>,
+    csv: Option<Mutex<Box<dyn Write + Send>>>,
+    /// How many frames have been written to the CSV
+    csv_frame: u64,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes the statistics of every frame to `writer` as CSV, with the columns `frame,stat,value`
+    ///
+    /// Each frame has a row for its frame time in milliseconds (`frame_time_ms`), two for each type
+    /// of entity (`entities/<name>` and `entity_bytes/<name>`), and one for each counter
+    /// (`counter/<name>`). If writing fails, the error is logged and nothing more is written
+    ///
+    /// ```ignore
+    /// let file = BufWriter::new(File::create("bench.csv")?);
+    /// universe.queue_set_singleton(Diagnostics::new().with_csv(file));
+    /// ```
+    pub fn with_csv(mut self, writer: impl Write + Send + 'static) -> Self {
+        self.csv = Some(Mutex::new(Box::new(writer)));
+        self
+    }
+
+    /// Adds to a counter, which is reset to 0 every frame
+    ///
+    /// Useful for counting how often something happens in a frame. Counters should
+    /// be changed while processing, as changes made while flushing may be counted
+    /// towards either frame
+    pub fn add_counter(&self, name: &'static str, amount: f64) {
+        *self.pending_counters.lock().entry(name).or_default() += amount;
+    }
+
+    /// Sets the value of a counter for this frame, overwriting anything added to it
+    pub fn set_counter(&self, name: &'static str, value: f64) {
+        self.pending_counters.lock().insert(name, value);
+    }
+
+    /// Gets all counters from the last frame, sorted by name
+    pub fn counters(&self) -> &[(&'static str, f64)] {
+        &self.counters
+    }
+
+    /// Gets the value of a counter from the last frame
+    pub fn counter(&self, name: &str) -> Option<f64> {
+        self.counters
+            .binary_search_by_key(&name, |(x, _)| x)
+            .ok()
+            .map(|i| self.counters[i].1)
+    }
+
+    /// Records a message, which is kept until enough newer messages are reported
+    pub fn report(&self, source: &'static str, message: impl Into<String>) {
+        self.pending_messages.lock().push(DiagnosticMessage {
+            source,
+            message: message.into(),
+        });
+    }
+
+    /// Gets the most recent messages as of the last frame, from oldest to newest
+    pub fn messages(&self) -> &VecDeque<DiagnosticMessage> {
+        &self.messages
+    }
+
+    pub fn frame_times(&self) -> FrameTimes {
+        self.frame_times
+    }
+
+    /// Gets how evenly frames were presented, if the Universe is running with `DeltaStrategy::Adaptive`
+    pub fn frame_pacing(&self) -> Option<FramePacingStats> {
+        self.frame_pacing
+    }
+
+    /// Gets the name of every type of entity and how many of them existed
+    /// during the last frame, sorted by name
+    pub fn entity_counts(&self) -> &[(&'static str, usize)] {
+        &self.entity_counts
+    }
+
+    /// Gets how much memory the buffer of every type of entity was using
+    /// during the last frame, sorted by name
+    pub fn entity_memory(&self) -> &[EntityBufferMemory] {
+        &self.entity_memory
+    }
+
+    /// Describes the statistics of the last frame for crash reports
+    fn snapshot(&self) -> String {
+        let times = self.frame_times;
+        let mut out = format!(
+            "fps: {:.1}, average: {:.2}ms, p99: {:.2}ms, worst: {:.2}ms\n",
+            times.fps,
+            times.average * 1000.0,
+            times.p99 * 1000.0,
+            times.worst * 1000.0
+        );
+        for memory in &self.entity_memory {
+            out += &format!("entities/{}: {} ({} bytes)\n", memory.name, memory.len, memory.bytes);
+        }
+        for (name, value) in &self.counters {
+            out += &format!("counter/{name}: {value}\n");
+        }
+        for message in &self.messages {
+            out += &format!("message/{}: {}\n", message.source, message.message);
+        }
+        out
+    }
+
+    fn write_csv(&mut self, delta: f32) {
+        let Some(csv) = &mut self.csv else {
+            return;
+        };
+        let writer = csv.get_mut();
+        let frame = self.csv_frame;
+        let mut result = Ok(());
+        if frame == 0 {
+            result = writeln!(writer, "frame,stat,value");
+        }
+        result = result.and_then(|_| writeln!(writer, "{frame},frame_time_ms,{}", delta * 1000.0));
+        // Names of entities are type names, which can have commas in them
+        for memory in &self.entity_memory {
+            let stat = format!("entities/{}", memory.name);
+            let stat = csv_quote(&stat);
+            result = result.and_then(|_| writeln!(writer, "{frame},{stat},{}", memory.len));
+            let stat = format!("entity_bytes/{}", memory.name);
+            let stat = csv_quote(&stat);
+            result = result.and_then(|_| writeln!(writer, "{frame},{stat},{}", memory.bytes));
+        }
+        for (name, value) in &self.counters {
+            let stat = format!("counter/{name}");
+            let stat = csv_quote(&stat);
+            result = result.and_then(|_| writeln!(writer, "{frame},{stat},{value}"));
+        }
+        if let Err(e) = result {
+            log::error!("Failed to write diagnostics CSV: {e}");
+            self.csv = None;
+        }
+        self.csv_frame += 1;
+    }
+
+    fn update_frame_times(&mut self, delta: f32) {
+        if self.history.len() == FRAME_HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(delta);
+
+        let mut sorted: Vec<f32> = self.history.iter().copied().collect();
+        sorted.sort_unstable_by(f32::total_cmp);
+        let percentile = |p: f32| sorted[((sorted.len() - 1) as f32 * p).round() as usize];
+        let average = sorted.iter().sum::<f32>() / sorted.len() as f32;
+
+        self.frame_times = FrameTimes {
+            fps: if average > 0.0 { 1.0 / average } else { 0.0 },
+            average,
+            p50: percentile(0.5),
+            p95: percentile(0.95),
+            p99: percentile(0.99),
+            worst: sorted[sorted.len() - 1],
+        };
+    }
+}
+
+/// Quotes a CSV field if it has anything in it that would be mistaken for the end of the field
+fn csv_quote(field: &str) -> std::borrow::Cow<'_, str> {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\"")).into()
+    } else {
+        field.into()
+    }
+}
+
+impl Singleton for Diagnostics {
+    fn process(&self, universe: &Universe) {
+        // Entity buffers cannot be read while they are flushing
+        let mut memory = universe.entity_memory();
+        memory.sort_unstable_by_key(|x| x.name);
+        *self.pending_entity_memory.lock() = memory;
+    }
+
+    fn flush(&mut self, universe: &Universe) {
+        self.update_frame_times(universe.get_delta());
+        self.frame_pacing = universe.frame_pacer().stats();
+        self.entity_memory = std::mem::take(self.pending_entity_memory.get_mut());
+        self.entity_counts.clear();
+        self.entity_counts
+            .extend(self.entity_memory.iter().map(|x| (x.name, x.len)));
+
+        self.counters.clear();
+        self.counters.extend(self.pending_counters.get_mut().drain());
+        self.counters.sort_unstable_by_key(|(name, _)| *name);
+
+        self.messages.extend(self.pending_messages.get_mut().drain(..));
+        let excess = self.messages.len().saturating_sub(MESSAGE_HISTORY);
+        self.messages.drain(..excess);
+
+        self.write_csv(universe.get_delta());
+        if crate::crash::is_installed() {
+            crate::crash::set_section("diagnostics", self.snapshot());
+        }
+    }
+}
@@ -0,0 +1,404 @@
+//! Easing functions and keyframed curves, so that anything animated over time
+//! shares the same math
+//!
+//! ```ignore
+//! let scale = Curve::new(0.0)
+//!     .with_key(0.2, 1.2, Easing::BackOut)
+//!     .with_key(1.0, 1.0, Easing::QuadInOut);
+//! let now = scale.sample(elapsed);
+//! ```
+use std::{
+    f32::consts::{FRAC_PI_2, PI},
+    fmt::Display,
+};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// How a value moves from one keyframe to the next
+///
+/// `In` functions start slowly, `Out` functions end slowly, and `InOut` functions do both.
+/// See <https://easings.net> for what each looks like
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Easing {
+    #[default]
+    Linear,
+    /// Stays at the start until the end is reached
+    Step,
+    QuadIn,
+    QuadOut,
+    QuadInOut,
+    CubicIn,
+    CubicOut,
+    CubicInOut,
+    QuartIn,
+    QuartOut,
+    QuartInOut,
+    QuintIn,
+    QuintOut,
+    QuintInOut,
+    SineIn,
+    SineOut,
+    SineInOut,
+    ExpoIn,
+    ExpoOut,
+    ExpoInOut,
+    CircIn,
+    CircOut,
+    CircInOut,
+    /// Pulls back past the start before moving
+    BackIn,
+    /// Overshoots the end before settling
+    BackOut,
+    BackInOut,
+    ElasticIn,
+    ElasticOut,
+    ElasticInOut,
+    BounceIn,
+    BounceOut,
+    BounceInOut,
+}
+
+impl Easing {
+    /// Eases `t`, which is clamped between 0 and 1
+    ///
+    /// Always returns 0 at the start and 1 at the end, but `Back` and `Elastic`
+    /// functions go outside of that range in between
+    pub fn apply(self, t: f32) -> f32 {
+        const BACK: f32 = 1.70158;
+        const BACK_IN_OUT: f32 = BACK * 1.525;
+        const ELASTIC: f32 = 2.0 * PI / 3.0;
+        const ELASTIC_IN_OUT: f32 = 2.0 * PI / 4.5;
+
+        let t = t.clamp(0.0, 1.0);
+        // Most InOut functions are the In function on the first half, mirrored on the second half
+        let in_out = |f: fn(f32) -> f32| {
+            if t < 0.5 {
+                f(t * 2.0) / 2.0
+            } else {
+                1.0 - f(2.0 - t * 2.0) / 2.0
+            }
+        };
+        match self {
+            Self::Linear => t,
+            Self::Step => {
+                if t < 1.0 {
+                    0.0
+                } else {
+                    1.0
+                }
+            }
+            Self::QuadIn => t.powi(2),
+            Self::QuadOut => 1.0 - (1.0 - t).powi(2),
+            Self::QuadInOut => in_out(|t| t.powi(2)),
+            Self::CubicIn => t.powi(3),
+            Self::CubicOut => 1.0 - (1.0 - t).powi(3),
+            Self::CubicInOut => in_out(|t| t.powi(3)),
+            Self::QuartIn => t.powi(4),
+            Self::QuartOut => 1.0 - (1.0 - t).powi(4),
+            Self::QuartInOut => in_out(|t| t.powi(4)),
+            Self::QuintIn => t.powi(5),
+            Self::QuintOut => 1.0 - (1.0 - t).powi(5),
+            Self::QuintInOut => in_out(|t| t.powi(5)),
+            Self::SineIn => 1.0 - (t * FRAC_PI_2).cos(),
+            Self::SineOut => (t * FRAC_PI_2).sin(),
+            Self::SineInOut => (1.0 - (t * PI).cos()) / 2.0,
+            Self::ExpoIn => expo_in(t),
+            Self::ExpoOut => 1.0 - expo_in(1.0 - t),
+            Self::ExpoInOut => in_out(expo_in),
+            Self::CircIn => 1.0 - (1.0 - t.powi(2)).sqrt(),
+            Self::CircOut => (1.0 - (t - 1.0).powi(2)).sqrt(),
+            Self::CircInOut => in_out(|t| 1.0 - (1.0 - t.powi(2)).sqrt()),
+            Self::BackIn => (BACK + 1.0) * t.powi(3) - BACK * t.powi(2),
+            Self::BackOut => 1.0 + (BACK + 1.0) * (t - 1.0).powi(3) + BACK * (t - 1.0).powi(2),
+            Self::BackInOut => {
+                in_out(|t| (BACK_IN_OUT + 1.0) * t.powi(3) - BACK_IN_OUT * t.powi(2))
+            }
+            Self::ElasticIn => elastic_in(t, ELASTIC, 10.0, 10.75),
+            Self::ElasticOut => 1.0 - elastic_in(1.0 - t, ELASTIC, 10.0, 10.75),
+            Self::ElasticInOut => in_out(|t| elastic_in(t, ELASTIC_IN_OUT, 10.0, 11.125)),
+            Self::BounceIn => 1.0 - bounce_out(1.0 - t),
+            Self::BounceOut => bounce_out(t),
+            Self::BounceInOut => in_out(|t| 1.0 - bounce_out(1.0 - t)),
+        }
+    }
+}
+
+fn expo_in(t: f32) -> f32 {
+    if t <= 0.0 {
+        0.0
+    } else {
+        2f32.powf(10.0 * t - 10.0)
+    }
+}
+
+fn elastic_in(t: f32, period: f32, decay: f32, phase: f32) -> f32 {
+    if t <= 0.0 || t >= 1.0 {
+        t
+    } else {
+        -2f32.powf(decay * t - decay) * ((t * 10.0 - phase) * period).sin()
+    }
+}
+
+fn bounce_out(t: f32) -> f32 {
+    const N: f32 = 7.5625;
+    const D: f32 = 2.75;
+    if t < 1.0 / D {
+        N * t * t
+    } else if t < 2.0 / D {
+        let t = t - 1.5 / D;
+        N * t * t + 0.75
+    } else if t < 2.5 / D {
+        let t = t - 2.25 / D;
+        N * t * t + 0.9375
+    } else {
+        let t = t - 2.625 / D;
+        N * t * t + 0.984375
+    }
+}
+
+/// Values that can be blended between
+pub trait Lerp: Clone {
+    /// Blends from `self` at 0 to `other` at 1. `t` may go outside of that range
+    fn lerp(&self, other: &Self, t: f32) -> Self;
+}
+
+impl Lerp for f32 {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        self + (other - self) * t
+    }
+}
+
+impl Lerp for f64 {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        self + (other - self) * t as f64
+    }
+}
+
+impl<T: Lerp, const N: usize> Lerp for [T; N] {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        std::array::from_fn(|i| self[i].lerp(&other[i], t))
+    }
+}
+
+/// A value at a point in time in a `Curve`
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Keyframe<T> {
+    pub time: f32,
+    pub value: T,
+    /// How the value moves from the previous keyframe to this one
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub easing: Easing,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CurveError {
+    /// A curve needs at least one keyframe
+    Empty,
+    /// A keyframe's time was infinite or NaN
+    InvalidTime(f32),
+}
+
+impl Display for CurveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Empty => write!(f, "curve has no keyframes"),
+            Self::InvalidTime(time) => write!(f, "{time} is not a valid keyframe time"),
+        }
+    }
+}
+
+impl std::error::Error for CurveError {}
+
+/// A value that changes over time, by easing between keyframes
+///
+/// Before the first keyframe, the curve stays at its first value, and after the last
+/// keyframe it stays at its last value. With the `serde` feature, curves can be loaded
+/// from a list of keyframes, such as this in RON:
+///
+/// ```ron
+/// [(time: 0.0, value: 0.0), (time: 1.0, value: 2.0, easing: QuadOut)]
+/// ```
+#[derive(Clone, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize),
+    serde(
+        try_from = "Vec<Keyframe<T>>",
+        bound(deserialize = "T: Deserialize<'de>")
+    )
+)]
+pub struct Curve<T> {
+    /// Sorted by time, and never empty
+    keys: Vec<Keyframe<T>>,
+}
+
+impl<T: Lerp> Curve<T> {
+    /// Creates a curve that starts at `value` at time 0
+    pub fn new(value: T) -> Self {
+        Self {
+            keys: vec![Keyframe {
+                time: 0.0,
+                value,
+                easing: Easing::Linear,
+            }],
+        }
+    }
+
+    /// Adds a keyframe, which is eased into from the keyframe before it
+    pub fn with_key(mut self, time: f32, value: T, easing: Easing) -> Self {
+        self.add_key(Keyframe {
+            time,
+            value,
+            easing,
+        });
+        self
+    }
+
+    /// Adds a keyframe, which is eased into from the keyframe before it
+    ///
+    /// A keyframe at the same time as another is placed after it, making the value jump
+    pub fn add_key(&mut self, key: Keyframe<T>) {
+        let index = self.keys.partition_point(|x| x.time <= key.time);
+        self.keys.insert(index, key);
+    }
+
+    /// Gets the value at the given time
+    pub fn sample(&self, time: f32) -> T {
+        let next = self.keys.partition_point(|x| x.time <= time);
+        if next == 0 {
+            return self.keys[0].value.clone();
+        }
+        let previous = &self.keys[next - 1];
+        let Some(next) = self.keys.get(next) else {
+            return previous.value.clone();
+        };
+        let t = (time - previous.time) / (next.time - previous.time);
+        previous.value.lerp(&next.value, next.easing.apply(t))
+    }
+
+    /// The time of the first keyframe
+    pub fn start(&self) -> f32 {
+        self.keys[0].time
+    }
+
+    /// The time of the last keyframe, after which the value stops changing
+    pub fn end(&self) -> f32 {
+        self.keys[self.keys.len() - 1].time
+    }
+
+    pub fn keys(&self) -> &[Keyframe<T>] {
+        &self.keys
+    }
+}
+
+impl<T> TryFrom<Vec<Keyframe<T>>> for Curve<T> {
+    type Error = CurveError;
+
+    fn try_from(mut keys: Vec<Keyframe<T>>) -> Result<Self, Self::Error> {
+        if let Some(key) = keys.iter().find(|x| !x.time.is_finite()) {
+            return Err(CurveError::InvalidTime(key.time));
+        }
+        if keys.is_empty() {
+            return Err(CurveError::Empty);
+        }
+        // Stable, so keyframes at the same time keep their order
+        keys.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Ok(Self { keys })
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T: Serialize> Serialize for Curve<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.keys.serialize(serializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Easing; 32] = [
+        Easing::Linear,
+        Easing::Step,
+        Easing::QuadIn,
+        Easing::QuadOut,
+        Easing::QuadInOut,
+        Easing::CubicIn,
+        Easing::CubicOut,
+        Easing::CubicInOut,
+        Easing::QuartIn,
+        Easing::QuartOut,
+        Easing::QuartInOut,
+        Easing::QuintIn,
+        Easing::QuintOut,
+        Easing::QuintInOut,
+        Easing::SineIn,
+        Easing::SineOut,
+        Easing::SineInOut,
+        Easing::ExpoIn,
+        Easing::ExpoOut,
+        Easing::ExpoInOut,
+        Easing::CircIn,
+        Easing::CircOut,
+        Easing::CircInOut,
+        Easing::BackIn,
+        Easing::BackOut,
+        Easing::BackInOut,
+        Easing::ElasticIn,
+        Easing::ElasticOut,
+        Easing::ElasticInOut,
+        Easing::BounceIn,
+        Easing::BounceOut,
+        Easing::BounceInOut,
+    ];
+
+    #[test]
+    fn easings_start_and_end_in_place() {
+        for easing in ALL {
+            assert!(
+                easing.apply(0.0).abs() < 1e-3,
+                "{easing:?} starts at {}",
+                easing.apply(0.0)
+            );
+            assert!(
+                (easing.apply(1.0) - 1.0).abs() < 1e-3,
+                "{easing:?} ends at {}",
+                easing.apply(1.0)
+            );
+            // Every InOut function is symmetric around its middle
+            let mirrored = easing.apply(0.3) + easing.apply(0.7);
+            if format!("{easing:?}").ends_with("InOut") {
+                assert!((mirrored - 1.0).abs() < 1e-3, "{easing:?} is not symmetric");
+            }
+        }
+    }
+
+    #[test]
+    fn curves_ease_between_keys() {
+        let curve = Curve::new([0.0, 10.0])
+            .with_key(2.0, [4.0, 10.0], Easing::Linear)
+            .with_key(1.0, [1.0, 0.0], Easing::QuadIn);
+        assert_eq!(curve.sample(-1.0), [0.0, 10.0]);
+        assert_eq!(curve.sample(0.5), [0.25, 7.5]);
+        assert_eq!(curve.sample(1.5), [2.5, 5.0]);
+        assert_eq!(curve.sample(3.0), [4.0, 10.0]);
+        assert_eq!(curve.end(), 2.0);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn curves_load_from_ron() {
+        let curve: Curve<f32> =
+            ron::from_str("[(time: 1.0, value: 2.0, easing: QuadOut), (time: 0.0, value: 0.0)]")
+                .unwrap();
+        assert_eq!(curve.sample(1.0), 2.0);
+        assert_eq!(curve.sample(0.5), 1.5);
+        let again: Curve<f32> = ron::from_str(&ron::to_string(&curve).unwrap()).unwrap();
+        assert_eq!(again.sample(0.5), 1.5);
+        assert!(ron::from_str::<Curve<f32>>("[]").is_err());
+    }
+}
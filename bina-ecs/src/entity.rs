@@ -1,8 +1,10 @@
 use std::{
-    any::TypeId,
+    any::{Any, TypeId},
     marker::{PhantomData, Tuple},
-    mem::transmute,
+    mem::{size_of, transmute},
     ops::Deref,
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
 use crossbeam::{atomic::AtomicCell, queue::SegQueue};
@@ -16,7 +18,8 @@ use rayon::{
 use triomphe::{Arc, UniqueArc};
 
 use crate::{
-    component::{Component, Processable},
+    component::{Component, Processable, Receiver, Tag},
+    crash,
     universe::Universe,
 };
 
@@ -31,9 +34,21 @@ fn arr_to_arc<T: Copy, const N: usize>(arr: [T; N]) -> Arc<[T]> {
 pub trait Entity: Tuple + Send + Sync + Sized + 'static {
     fn process(&self, my_index: usize, universe: &Universe);
     fn flush(&mut self, my_index: usize, universe: &Universe);
+    /// Gets the first component that matches the predicate
+    fn find_component(&self, predicate: &mut dyn FnMut(&dyn Any) -> bool) -> Option<&dyn Any>;
+    /// Whether this type of entity has a component of the given type
+    fn has_component(type_id: TypeId) -> bool;
 }
 
 impl<A: Component + Processable> Entity for (A,) {
+    fn has_component(type_id: TypeId) -> bool {
+        type_id == TypeId::of::<A>()
+    }
+
+    fn find_component(&self, predicate: &mut dyn FnMut(&dyn Any) -> bool) -> Option<&dyn Any> {
+        [&self.0 as &dyn Any].into_iter().find(|x| predicate(*x))
+    }
+
     fn flush(&mut self, my_index: usize, universe: &Universe) {
         self.0.flush(
             EntityReference {
@@ -58,6 +73,16 @@ impl<A: Component + Processable> Entity for (A,) {
     }
 }
 impl<A: Component + Processable, B: Component + Processable> Entity for (A, B) {
+    fn has_component(type_id: TypeId) -> bool {
+        type_id == TypeId::of::<A>() || type_id == TypeId::of::<B>()
+    }
+
+    fn find_component(&self, predicate: &mut dyn FnMut(&dyn Any) -> bool) -> Option<&dyn Any> {
+        [&self.0 as &dyn Any, &self.1]
+            .into_iter()
+            .find(|x| predicate(*x))
+    }
+
     fn flush(&mut self, my_index: usize, universe: &Universe) {
         let entity_ref = EntityReference {
             index: my_index,
@@ -101,6 +126,20 @@ pub(crate) trait EntityBuffer: Send + Sync {
     fn process(&self, universe: &Universe);
 
     fn queue_remove_entity(&self, index: usize);
+
+    /// The number of entities in this buffer
+    fn len(&self) -> usize;
+
+    /// Frees the memory that this buffer is not using
+    fn shrink(&mut self);
+
+    fn memory(&self) -> EntityBufferMemory;
+
+    /// The name of the type of entity stored in this buffer
+    fn entity_type_name(&self) -> &'static str;
+
+    /// Whether the type of entity stored in this buffer has a component of the given type
+    fn has_component(&self, type_id: TypeId) -> bool;
 }
 
 pub(crate) unsafe fn cast_entity_buffer<E: Entity>(
@@ -120,6 +159,8 @@ enum EntityIndex {
 struct EntityWrapper<E: Entity> {
     entity: E,
     index: Arc<AtomicCell<EntityIndex>>,
+    /// Set when a component panicked, so that the entity is never processed or pooled again
+    quarantined: AtomicCell<bool>,
 }
 
 impl<E: Entity> EntityWrapper<E> {
@@ -127,12 +168,57 @@ impl<E: Entity> EntityWrapper<E> {
         Self {
             entity,
             index: Arc::new(AtomicCell::new(EntityIndex::Alive(index))),
+            quarantined: AtomicCell::new(false),
         }
     }
 }
 
+/// How much memory the buffer of one type of entity is using
+#[derive(Clone, Copy, Debug)]
+pub struct EntityBufferMemory {
+    /// The type name of the entity
+    pub name: &'static str,
+    pub len: usize,
+    /// How many entities fit before the buffer has to grow
+    pub capacity: usize,
+    /// How many removed entities are waiting to be reused by `Universe::spawn_from_pool`
+    pub pooled: usize,
+    /// Roughly how many bytes the buffer has allocated, not counting what the components allocate themselves
+    pub bytes: usize,
+}
+
+/// What happens when a component panics while its entity is processing
+///
+/// Catching panics needs panics to unwind, so it does nothing in builds with `panic = "abort"`,
+/// which includes most builds for browsers
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum PanicPolicy {
+    /// The panic is not caught, and ends the Universe
+    #[default]
+    Propagate,
+    /// The entity is removed at the end of the frame
+    Despawn,
+    /// The entity is kept and still flushed, but never processed again
+    Quarantine,
+}
+
+/// Sent when a component panics while processing, if the `PanicPolicy` of its entity catches panics
+///
+/// The other components in the same entity may or may not have processed during that frame
+#[derive(Clone, Debug)]
+pub struct ComponentPanicked {
+    /// The type name of the entity, which names every component in it
+    pub entity_type: &'static str,
+    /// What the panic said, if it was given a message
+    pub message: Option<String>,
+    /// What was done with the entity
+    pub policy: PanicPolicy,
+}
+
 pub trait MaybeEntity {
     fn get_buffer_type() -> TypeId;
+    /// Whether this type of entity has a component of the given type, even if it cannot be accessed
+    fn has_component(type_id: TypeId) -> bool;
 }
 
 /// Represents an entity whose components cannot be accessed,
@@ -157,20 +243,87 @@ impl<E: Entity> MaybeEntity for Inaccessible<E> {
     fn get_buffer_type() -> TypeId {
         TypeId::of::<EntityBufferStruct<E>>()
     }
+    fn has_component(type_id: TypeId) -> bool {
+        E::has_component(type_id)
+    }
 }
 impl<E: Entity> MaybeEntity for E {
     fn get_buffer_type() -> TypeId {
         TypeId::of::<EntityBufferStruct<E>>()
     }
+    fn has_component(type_id: TypeId) -> bool {
+        <E as Entity>::has_component(type_id)
+    }
+}
+
+/// Decides which types of entities are iterated by `Universe::iter_entities_filtered`
+///
+/// Filters only look at the components an entity has, so they are checked once for every
+/// type of entity instead of once for every entity. Tuples of filters match if all of them do
+pub trait EntityFilter {
+    fn matches<E: MaybeEntity>() -> bool;
+}
+
+/// Matches entities that have a component of type `T`
+pub struct With<T>(PhantomData<T>);
+
+/// Matches entities that do not have a component of type `T`
+pub struct Without<T>(PhantomData<T>);
+
+impl<T: Component> EntityFilter for With<T> {
+    fn matches<E: MaybeEntity>() -> bool {
+        E::has_component(TypeId::of::<T>())
+    }
+}
+
+impl<T: Component> EntityFilter for Without<T> {
+    fn matches<E: MaybeEntity>() -> bool {
+        !E::has_component(TypeId::of::<T>())
+    }
+}
+
+impl EntityFilter for () {
+    fn matches<E: MaybeEntity>() -> bool {
+        true
+    }
+}
+
+impl<A: EntityFilter> EntityFilter for (A,) {
+    fn matches<E: MaybeEntity>() -> bool {
+        A::matches::<E>()
+    }
+}
+
+impl<A: EntityFilter, B: EntityFilter> EntityFilter for (A, B) {
+    fn matches<E: MaybeEntity>() -> bool {
+        A::matches::<E>() && B::matches::<E>()
+    }
 }
 
-#[derive(Clone)]
 pub struct EntityReference<'a, E: MaybeEntity> {
     pub(crate) index: usize,
     entity: &'a E,
     ignore_ptrs: Arc<[usize]>,
 }
 
+// Derived Clone would require E: Clone, even though only a reference to E is stored
+impl<'a, E: MaybeEntity> Clone for EntityReference<'a, E> {
+    fn clone(&self) -> Self {
+        Self {
+            index: self.index,
+            entity: self.entity,
+            ignore_ptrs: self.ignore_ptrs.clone(),
+        }
+    }
+}
+
+impl<'a, E: MaybeEntity> EntityReference<'a, E> {
+    /// Whether this entity has the tag `T`, which works even while the entity is flushing
+    pub fn has_tag<T: Tag>(&self) -> bool {
+        E::has_component(TypeId::of::<T>())
+    }
+}
+
 impl<'a, E: MaybeEntity> Deref for EntityReference<'a, E> {
     type Target = E;
 
@@ -189,6 +342,40 @@ impl<'a, E: Entity> EntityReference<'a, E> {
             .binary_search(&ref_to_usize(reference))
             .is_ok()
     }
+
+    /// Sends a message to the component of type `T` in this entity, which receives it when it flushes
+    ///
+    /// Returns false if there is no such component, other than the one sending the message
+    pub fn send_to<T: Receiver>(&self, message: T::Message) -> bool {
+        let Some(receiver) = (self as &dyn ErasedEntityReference).get_component::<T>() else {
+            return false;
+        };
+        receiver.mailbox().send(message);
+        true
+    }
+}
+
+/// An `EntityReference` to an entity of any type
+///
+/// This lets code that cannot name the type of the entity, such as a closure
+/// stored inside of one of its components, get the other components
+pub trait ErasedEntityReference {
+    fn get_any(&self, type_id: TypeId) -> Option<&dyn Any>;
+}
+
+impl dyn ErasedEntityReference + '_ {
+    pub fn get_component<T: 'static>(&self) -> Option<&T> {
+        self.get_any(TypeId::of::<T>())?.downcast_ref()
+    }
+}
+
+impl<'a, E: Entity> ErasedEntityReference for EntityReference<'a, E> {
+    fn get_any(&self, type_id: TypeId) -> Option<&dyn Any> {
+        self.entity.find_component(&mut |x| {
+            let ptr = (x as *const dyn Any).cast::<()>() as usize;
+            (*x).type_id() == type_id && self.ignore_ptrs.binary_search(&ptr).is_err()
+        })
+    }
 }
 
 impl<'a, A> EntityReference<'a, (A,)>
@@ -284,6 +471,11 @@ pub(crate) struct EntityBufferStruct<E: Entity> {
     pending_adds: SegQueue<E>,
     pending_removes: SegQueue<usize>,
     remove_buffer: Vec<usize>,
+    /// The most entities that should fit after the next flush, on top of those already stored
+    pending_reserve: AtomicUsize,
+    /// Removed entities that can be added again by `Universe::spawn_from_pool`
+    pool: SegQueue<E>,
+    pool_capacity: AtomicUsize,
 }
 
 impl<E: Entity> EntityBufferStruct<E> {
@@ -293,15 +485,70 @@ impl<E: Entity> EntityBufferStruct<E> {
             pending_adds: SegQueue::new(),
             pending_removes: SegQueue::new(),
             remove_buffer: Default::default(),
+            pending_reserve: AtomicUsize::new(0),
+            pool: SegQueue::new(),
+            pool_capacity: AtomicUsize::new(0),
         }
     }
 
+    pub(crate) fn set_pool_capacity(&self, capacity: usize) {
+        self.pool_capacity.store(capacity, Ordering::Relaxed);
+    }
+
+    pub(crate) fn take_pooled(&self) -> Option<E> {
+        self.pool.pop()
+    }
+
     pub(crate) fn queue_add_entity(&self, entity: E) {
         self.pending_adds.push(entity);
     }
 
-    pub(crate) fn par_iter(&self) -> impl IndexedParallelIterator + '_ {
-        self.buffer.par_iter()
+    pub(crate) fn queue_reserve(&self, additional: usize) {
+        self.pending_reserve
+            .fetch_max(additional, Ordering::Relaxed);
+    }
+
+    /// Gets a reference to every entity
+    pub(crate) fn par_references(
+        &self,
+    ) -> impl IndexedParallelIterator<Item = EntityReference<'_, E>> {
+        let ignore_ptrs = arr_to_arc([]);
+        self.buffer
+            .par_iter()
+            .enumerate()
+            .map(move |(index, x)| EntityReference {
+                index,
+                entity: &x.entity,
+                ignore_ptrs: ignore_ptrs.clone(),
+            })
+    }
+
+    /// Gets a reference to every entity that matches `F` and `predicate`
+    ///
+    /// `F` is checked once for the whole buffer, and `predicate` is checked before each reference is made
+    pub(crate) fn par_references_filtered<'a, F: EntityFilter>(
+        &'a self,
+        predicate: impl Fn(&E) -> bool + Send + Sync + 'a,
+    ) -> impl ParallelIterator<Item = EntityReference<'a, E>> {
+        let entities = if F::matches::<E>() {
+            self.buffer.as_slice()
+        } else {
+            &[]
+        };
+        let ignore_ptrs = arr_to_arc([]);
+        entities
+            .par_iter()
+            .enumerate()
+            .filter(move |(_, x)| predicate(&x.entity))
+            .map(move |(index, x)| EntityReference {
+                index,
+                entity: &x.entity,
+                ignore_ptrs: ignore_ptrs.clone(),
+            })
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &E> + '_ {
+        self.buffer.iter().map(|x| &x.entity)
     }
 }
 
@@ -335,7 +582,7 @@ impl<E: Entity> EntityBuffer for EntityBufferStruct<E> {
             }
             last = Some(index);
 
-            unsafe {
+            let removed = unsafe {
                 // We assume the entity exists here
                 let removed = self.buffer.get_unchecked_mut(index);
                 // Register the entity as removed by overwriting its index with Freed
@@ -344,7 +591,7 @@ impl<E: Entity> EntityBuffer for EntityBufferStruct<E> {
                 if index == self.buffer.len() - 1 {
                     // The entity we are removing just so happens to be at the end
                     // The pop is guaranteed to work
-                    self.buffer.pop().unwrap_unchecked();
+                    self.buffer.pop().unwrap_unchecked()
                 } else {
                     // The entity is not at the end, so to perform a safe swap remove,
                     // we must set the index of the last element to Moving, so that threads
@@ -352,11 +599,20 @@ impl<E: Entity> EntityBuffer for EntityBufferStruct<E> {
                     let last = self.buffer.last_mut().unwrap_unchecked();
                     last.index.store(EntityIndex::Moving);
                     // Now we can safely swap remove
-                    self.buffer.swap_remove(index);
+                    let removed = self.buffer.swap_remove(index);
                     // We give the index of the removed entity to the entity that replaced it
                     self.buffer.get_unchecked(index).index.store(old_index);
+                    removed
                 }
             };
+            // Entities that panicked may have been left in a broken state, so they are not reused
+            if !removed.quarantined.load() && self.pool.len() < *self.pool_capacity.get_mut() {
+                self.pool.push(removed.entity);
+            }
+        }
+        // The capacity may have been lowered since the pool was filled
+        while self.pool.len() > *self.pool_capacity.get_mut() {
+            self.pool.pop();
         }
 
         self.buffer.reserve(self.pending_adds.len());
@@ -366,13 +622,49 @@ impl<E: Entity> EntityBuffer for EntityBufferStruct<E> {
             let entity = EntityWrapper::new(entity, self.buffer.len());
             self.buffer.push(entity);
         }
+        self.buffer
+            .reserve(std::mem::take(self.pending_reserve.get_mut()));
     }
 
     fn process(&self, universe: &Universe) {
-        self.buffer
-            .par_iter()
-            .enumerate()
-            .for_each(|(index, x)| x.entity.process(index, universe));
+        let policy = universe.panic_policy(TypeId::of::<Self>());
+        self.buffer.par_iter().enumerate().for_each(|(index, x)| {
+            if x.quarantined.load() {
+                return;
+            }
+            if policy == PanicPolicy::Propagate {
+                x.entity.process(index, universe);
+                return;
+            }
+            // Nothing else can see the entity in a broken state, as it is either
+            // removed or never processed again. The panic is reported through
+            // `ComponentPanicked` instead of a crash report
+            let Err(payload) = crash::catching(|| {
+                catch_unwind(AssertUnwindSafe(|| x.entity.process(index, universe)))
+            }) else {
+                return;
+            };
+            match policy {
+                PanicPolicy::Despawn => self.queue_remove_entity(index),
+                PanicPolicy::Quarantine => {}
+                PanicPolicy::Propagate => unreachable!(),
+            }
+            x.quarantined.store(true);
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|x| x.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned());
+            log::error!(
+                "A component of {} panicked: {}",
+                self.entity_type_name(),
+                message.as_deref().unwrap_or("no message")
+            );
+            universe.send_event(ComponentPanicked {
+                entity_type: self.entity_type_name(),
+                message,
+                policy,
+            });
+        });
     }
 
     fn queue_remove_entity(&self, index: usize) {
@@ -381,4 +673,644 @@ impl<E: Entity> EntityBuffer for EntityBufferStruct<E> {
         }
         self.pending_removes.push(index);
     }
+
+    fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    fn shrink(&mut self) {
+        self.buffer.shrink_to_fit();
+        self.remove_buffer.shrink_to_fit();
+    }
+
+    fn memory(&self) -> EntityBufferMemory {
+        // Every entity also has its index behind an Arc, next to its reference count
+        let index_size = size_of::<usize>() + size_of::<AtomicCell<EntityIndex>>();
+        EntityBufferMemory {
+            name: self.entity_type_name(),
+            len: self.buffer.len(),
+            capacity: self.buffer.capacity(),
+            pooled: self.pool.len(),
+            bytes: self.buffer.capacity() * size_of::<EntityWrapper<E>>()
+                + self.pool.len() * size_of::<E>()
+                + self.buffer.len() * index_size
+                + self.remove_buffer.capacity() * size_of::<usize>(),
+        }
+    }
+
+    fn entity_type_name(&self) -> &'static str {
+        std::any::type_name::<E>()
+    }
+
+    fn has_component(&self, type_id: TypeId) -> bool {
+        E::has_component(type_id)
+    }
+}
+
+/// Adds, removes and processes entities from many threads at once, then checks that every
+/// entity that should be left is stored exactly once at the index it was given
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use parking_lot::Mutex;
+    use rayon::ThreadPoolBuilder;
+
+    use super::*;
+    use crate::{
+        component::{ComponentField, Mailbox},
+        singleton::Singleton,
+        universe::FlushStage,
+    };
+
+    /// Runs `test` on a single thread, where rayon runs everything in the same order every time,
+    /// then on many threads, where the order changes from run to run
+    fn on_every_executor(test: impl Fn() + Send + Sync) {
+        for threads in [1, 8] {
+            ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap()
+                .install(&test);
+        }
+    }
+
+    /// What the entities did during the last frame
+    #[derive(Default)]
+    struct Log {
+        /// The id and index of every processed entity
+        processed: Mutex<Vec<(usize, usize)>>,
+        /// The stage of everything that was flushed, in the order they were flushed
+        flushed: Mutex<Vec<FlushStage>>,
+    }
+
+    impl Singleton for Log {}
+
+    struct Tagged {
+        id: usize,
+        /// How many times to queue this entity for removal each frame
+        removals: usize,
+        /// Ids of entities to add each frame
+        spawns: Vec<usize>,
+        /// Panics after logging that it was processed
+        panics: bool,
+    }
+
+    impl Tagged {
+        fn new(id: usize) -> Self {
+            Self {
+                id,
+                removals: 0,
+                spawns: Vec::new(),
+                panics: false,
+            }
+        }
+    }
+
+    impl Component for Tagged {
+        type Reference<'a> = &'a Self;
+
+        fn get_ref<'a>(&'a self) -> Self::Reference<'a> {
+            self
+        }
+    }
+
+    impl Processable for Tagged {
+        fn process<E: Entity>(
+            component: &Self,
+            my_entity: EntityReference<E>,
+            universe: &Universe,
+        ) {
+            universe
+                .get_singleton::<Log>()
+                .processed
+                .lock()
+                .push((component.id, my_entity.index));
+            for _ in 0..component.removals {
+                universe.queue_remove_entity(my_entity.clone());
+            }
+            for &id in &component.spawns {
+                universe.queue_add_entity((Tagged::new(id),));
+            }
+            if component.panics {
+                panic!("Tagged {} panicked", component.id);
+            }
+        }
+    }
+
+    /// Makes a Universe holding the given entities, which have been flushed into their buffer
+    fn universe_with(entities: impl IntoIterator<Item = Tagged>) -> Universe {
+        let mut universe = Universe::new();
+        universe.queue_set_singleton(Log::default());
+        for entity in entities {
+            universe.queue_add_entity((entity,));
+        }
+        // The first frame adds the singleton and the buffer, and the second adds the entities to it
+        universe.loop_once();
+        universe.loop_once();
+        universe
+    }
+
+    /// Runs a frame, returning the ids and indices of the entities that were processed in it
+    fn run_frame(universe: &mut Universe) -> Vec<(usize, usize)> {
+        universe.loop_once();
+        let mut processed = std::mem::take(&mut *universe.get_singleton::<Log>().processed.lock());
+        processed.sort_unstable();
+        processed
+    }
+
+    /// Checks that the buffer holds exactly the entities with `ids`,
+    /// and that each knows where it is stored
+    fn assert_stored(universe: &Universe, ids: &BTreeSet<usize>) {
+        let buffer = universe.get_entity_buffer::<(Tagged,)>().unwrap();
+        let stored: BTreeSet<usize> = buffer.iter().map(|x| x.0.id).collect();
+        assert_eq!(stored.len(), buffer.len(), "An entity is stored twice");
+        assert_eq!(&stored, ids);
+        for (index, wrapper) in buffer.buffer.iter().enumerate() {
+            match wrapper.index.load() {
+                EntityIndex::Alive(x) if x == index => {}
+                EntityIndex::Alive(x) => panic!("Entity at {index} thinks it is at {x}"),
+                EntityIndex::Moving => panic!("Entity at {index} was left moving"),
+                EntityIndex::Freed => panic!("Entity at {index} was freed but is still stored"),
+            }
+        }
+    }
+
+    #[test]
+    fn process_sees_every_entity_once() {
+        on_every_executor(|| {
+            let mut universe = universe_with((0..1000).map(Tagged::new));
+            let processed = run_frame(&mut universe);
+            let buffer = universe.get_entity_buffer::<(Tagged,)>().unwrap();
+            // Nothing moved, so every entity was processed at the index it is still stored at
+            let mut expected: Vec<_> = buffer
+                .iter()
+                .enumerate()
+                .map(|(i, x)| (x.0.id, i))
+                .collect();
+            expected.sort_unstable();
+            assert_eq!(processed, expected);
+        });
+    }
+
+    #[test]
+    fn swap_remove_keeps_indices() {
+        on_every_executor(|| {
+            // Removes entities at the start, the end, and scattered in between,
+            // some of which are queued more than once
+            let doomed = |id: usize| !(3..997).contains(&id) || id.is_multiple_of(7);
+            let mut universe = universe_with((0..1000).map(|id| Tagged {
+                removals: if doomed(id) { 1 + id % 3 } else { 0 },
+                ..Tagged::new(id)
+            }));
+
+            let freed: Vec<_> = universe
+                .get_entity_buffer::<(Tagged,)>()
+                .unwrap()
+                .buffer
+                .iter()
+                .filter(|x| doomed(x.entity.0.id))
+                .map(|x| x.index.clone())
+                .collect();
+
+            run_frame(&mut universe);
+            let alive = (0..1000).filter(|&id| !doomed(id)).collect();
+            assert_stored(&universe, &alive);
+            for index in freed {
+                assert!(matches!(index.load(), EntityIndex::Freed));
+            }
+        });
+    }
+
+    #[test]
+    fn remove_everything() {
+        on_every_executor(|| {
+            let mut universe = universe_with((0..1000).map(|id| Tagged {
+                removals: 2,
+                ..Tagged::new(id)
+            }));
+            run_frame(&mut universe);
+            assert_stored(&universe, &BTreeSet::new());
+            assert!(run_frame(&mut universe).is_empty());
+        });
+    }
+
+    /// Entities added in a frame are stored when it is flushed, after the removals
+    #[test]
+    fn add_and_remove_in_the_same_frame() {
+        on_every_executor(|| {
+            let mut universe = universe_with((0..1000).map(|id| Tagged {
+                removals: (id % 2 == 0) as usize,
+                spawns: if id % 10 == 0 {
+                    vec![id + 1000]
+                } else {
+                    vec![]
+                },
+                ..Tagged::new(id)
+            }));
+            run_frame(&mut universe);
+
+            let mut alive: BTreeSet<_> = (0..1000).filter(|id| id % 2 == 1).collect();
+            alive.extend((0..1000).step_by(10).map(|id| id + 1000));
+            assert_stored(&universe, &alive);
+
+            // The new entities are processed at the indices they were given
+            let processed = run_frame(&mut universe);
+            assert_eq!(processed.len(), alive.len());
+            let buffer = universe.get_entity_buffer::<(Tagged,)>().unwrap();
+            for (id, index) in processed {
+                assert_eq!(buffer.buffer[index].entity.0.id, id);
+            }
+        });
+    }
+
+    /// Removals of indices that do not exist, such as from a reference kept past its frame, are ignored
+    #[test]
+    fn remove_out_of_range() {
+        on_every_executor(|| {
+            let mut universe = universe_with((0..10).map(Tagged::new));
+            let buffer = universe.get_entity_buffer::<(Tagged,)>().unwrap();
+            buffer.queue_remove_entity(10);
+            buffer.queue_remove_entity(usize::MAX);
+            run_frame(&mut universe);
+            assert_stored(&universe, &(0..10).collect());
+        });
+    }
+
+    #[test]
+    fn panics_are_isolated() {
+        on_every_executor(|| {
+            for policy in [PanicPolicy::Despawn, PanicPolicy::Quarantine] {
+                let panics = |id: usize| id.is_multiple_of(100);
+                let mut universe = universe_with((0..1000).map(|id| Tagged {
+                    panics: panics(id),
+                    ..Tagged::new(id)
+                }));
+                universe.set_entity_panic_policy::<(Tagged,)>(policy);
+
+                assert_eq!(run_frame(&mut universe).len(), 1000);
+                let healthy: BTreeSet<_> = (0..1000).filter(|&id| !panics(id)).collect();
+                if policy == PanicPolicy::Despawn {
+                    assert_stored(&universe, &healthy);
+                } else {
+                    assert_stored(&universe, &(0..1000).collect());
+                }
+
+                // Events sent during a frame can be read until the next one ends
+                let mut events: Vec<_> = universe.read_events::<ComponentPanicked>().to_vec();
+                events.sort_unstable_by_key(|x| x.message.clone());
+                assert_eq!(events.len(), 10);
+                assert!(events.iter().all(|x| x.policy == policy));
+                assert_eq!(events[0].message.as_deref(), Some("Tagged 0 panicked"));
+
+                // Neither despawned nor quarantined entities are processed again
+                let processed = run_frame(&mut universe);
+                let processed: BTreeSet<_> = processed.into_iter().map(|(id, _)| id).collect();
+                assert_eq!(processed, healthy);
+            }
+        });
+    }
+
+    #[test]
+    fn reserve_and_shrink() {
+        let mut universe = universe_with((0..10).map(Tagged::new));
+        universe.reserve_entities::<(Tagged,)>(1000);
+        universe.reserve_entities::<(Tagged,)>(100);
+        run_frame(&mut universe);
+        let memory = universe.entity_memory()[0];
+        assert_eq!(memory.len, 10);
+        assert!(memory.capacity >= 1010);
+
+        universe.shrink_entity_buffers();
+        run_frame(&mut universe);
+        let shrunk = universe.entity_memory()[0];
+        assert!(shrunk.capacity < memory.capacity);
+        assert!(shrunk.bytes < memory.bytes);
+        assert_stored(&universe, &(0..10).collect());
+    }
+
+    #[test]
+    fn removed_entities_are_pooled() {
+        on_every_executor(|| {
+            let mut universe = universe_with((0..10).map(|id| Tagged {
+                removals: (id < 8) as usize,
+                ..Tagged::new(id)
+            }));
+            universe.set_entity_pool_capacity::<(Tagged,)>(5);
+            run_frame(&mut universe);
+            assert_stored(&universe, &[8, 9].into());
+            assert_eq!(universe.entity_memory()[0].pooled, 5);
+
+            // Pooled entities still remember what they were, until they are reset
+            let mut reused = Vec::new();
+            for id in 10..17 {
+                let pooled = universe.spawn_from_pool(
+                    || (Tagged::new(id),),
+                    |(entity,)| {
+                        reused.push(entity.id);
+                        *entity = Tagged::new(id);
+                    },
+                );
+                assert_eq!(pooled, id < 15);
+            }
+            run_frame(&mut universe);
+            assert_eq!(reused.len(), 5);
+            assert!(reused.iter().all(|&id| id < 8));
+            assert_eq!(universe.entity_memory()[0].pooled, 0);
+            assert_stored(&universe, &[8, 9, 10, 11, 12, 13, 14, 15, 16].into());
+        });
+    }
+
+    /// Logs its stage whenever it is flushed
+    struct Staged<const STAGE: usize>;
+
+    impl<const STAGE: usize> Singleton for Staged<STAGE> {
+        fn flush_stage(&self) -> FlushStage {
+            FlushStage::ALL[STAGE]
+        }
+
+        fn flush(&mut self, universe: &Universe) {
+            let log = universe.get_singleton::<Log>();
+            log.flushed.lock().push(FlushStage::ALL[STAGE]);
+        }
+    }
+
+    impl<const STAGE: usize> Component for Staged<STAGE> {
+        type Reference<'a> = &'a Self;
+
+        fn get_ref<'a>(&'a self) -> Self::Reference<'a> {
+            self
+        }
+
+        fn flush<E: Entity>(
+            &mut self,
+            _my_entity: EntityReference<Inaccessible<E>>,
+            universe: &Universe,
+        ) {
+            let log = universe.get_singleton::<Log>();
+            log.flushed.lock().push(FlushStage::ALL[STAGE]);
+        }
+    }
+
+    impl<const STAGE: usize> Processable for Staged<STAGE> {
+        fn process<E: Entity>(
+            _component: &Self,
+            _my_entity: EntityReference<E>,
+            _universe: &Universe,
+        ) {
+        }
+    }
+
+    #[test]
+    fn flush_stages_run_in_order() {
+        on_every_executor(|| {
+            let mut universe = universe_with([]);
+            universe.queue_set_singleton(Staged::<3>);
+            universe.queue_set_singleton(Staged::<0>);
+            universe.queue_set_singleton(Staged::<1>);
+            for _ in 0..4 {
+                universe.queue_add_entity((Staged::<2>,));
+            }
+            universe.set_entity_flush_stage::<(Staged<2>,)>(FlushStage::PostUpdate);
+            universe.loop_once();
+            universe.loop_once();
+            universe.get_singleton::<Log>().flushed.lock().clear();
+
+            universe.loop_once();
+            let flushed = std::mem::take(&mut *universe.get_singleton::<Log>().flushed.lock());
+            assert_eq!(
+                flushed,
+                [
+                    FlushStage::PreUpdate,
+                    FlushStage::Update,
+                    FlushStage::PostUpdate,
+                    FlushStage::PostUpdate,
+                    FlushStage::PostUpdate,
+                    FlushStage::PostUpdate,
+                    FlushStage::PreRender,
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn entities_flush_before_singletons_of_their_stage() {
+        on_every_executor(|| {
+            let mut universe = universe_with([]);
+            universe.queue_set_singleton(Staged::<1>);
+            for _ in 0..4 {
+                universe.queue_add_entity((Staged::<2>,));
+            }
+            // The entities log `PostUpdate`, but flush with the singleton in `Update`
+            universe.set_entity_flush_stage::<(Staged<2>,)>(FlushStage::Update);
+            universe.loop_once();
+            universe.loop_once();
+            universe.get_singleton::<Log>().flushed.lock().clear();
+
+            universe.loop_once();
+            let flushed = std::mem::take(&mut *universe.get_singleton::<Log>().flushed.lock());
+            assert_eq!(
+                flushed,
+                [
+                    FlushStage::PostUpdate,
+                    FlushStage::PostUpdate,
+                    FlushStage::PostUpdate,
+                    FlushStage::PostUpdate,
+                    FlushStage::Update,
+                ]
+            );
+        });
+    }
+
+    /// Sends `damage` to the `Health` in the same entity every frame
+    struct Attacker {
+        damage: u32,
+        /// Whether the last message sent to itself was delivered
+        sent_to_self: AtomicCell<bool>,
+    }
+
+    impl Component for Attacker {
+        type Reference<'a> = &'a Self;
+
+        fn get_ref<'a>(&'a self) -> Self::Reference<'a> {
+            self
+        }
+    }
+
+    impl Receiver for Attacker {
+        type Message = u32;
+
+        fn mailbox(&self) -> &Mailbox<u32> {
+            unreachable!("Components cannot send messages to themselves")
+        }
+    }
+
+    impl Processable for Attacker {
+        fn process<E: Entity>(
+            component: &Self,
+            my_entity: EntityReference<E>,
+            _universe: &Universe,
+        ) {
+            assert!(my_entity.send_to::<Health>(component.damage));
+            component
+                .sent_to_self
+                .store(my_entity.send_to::<Attacker>(0));
+        }
+    }
+
+    #[derive(Default)]
+    struct Health {
+        lost: u32,
+        damage: Mailbox<u32>,
+    }
+
+    impl Component for Health {
+        type Reference<'a> = &'a Self;
+
+        fn get_ref<'a>(&'a self) -> Self::Reference<'a> {
+            self
+        }
+
+        fn flush<E: Entity>(
+            &mut self,
+            _my_entity: EntityReference<Inaccessible<E>>,
+            _universe: &Universe,
+        ) {
+            self.damage.process_modifiers();
+            self.lost += self.damage.read().iter().sum::<u32>();
+        }
+    }
+
+    impl Receiver for Health {
+        type Message = u32;
+
+        fn mailbox(&self) -> &Mailbox<u32> {
+            &self.damage
+        }
+    }
+
+    impl Processable for Health {
+        fn process<E: Entity>(
+            _component: &Self,
+            _my_entity: EntityReference<E>,
+            _universe: &Universe,
+        ) {
+        }
+    }
+
+    #[test]
+    fn components_receive_messages_when_flushed() {
+        on_every_executor(|| {
+            let mut universe = universe_with([]);
+            universe.queue_add_entity((
+                Attacker {
+                    damage: 3,
+                    sent_to_self: AtomicCell::new(true),
+                },
+                Health::default(),
+            ));
+            // The first frame adds the buffer, and the second adds the entity to it
+            for _ in 0..4 {
+                universe.loop_once();
+            }
+            let buffer = universe.get_entity_buffer::<(Attacker, Health)>().unwrap();
+            let (attacker, health) = buffer.iter().next().unwrap();
+            assert_eq!(health.lost, 6);
+            // Only the messages from the last frame are kept
+            assert_eq!(health.damage.read(), [3]);
+            assert!(!attacker.sent_to_self.load());
+        });
+    }
+
+    struct Marked;
+
+    impl Component for Marked {
+        type Reference<'a> = &'a Self;
+
+        fn get_ref<'a>(&'a self) -> Self::Reference<'a> {
+            self
+        }
+    }
+
+    impl Processable for Marked {
+        fn process<E: Entity>(
+            _component: &Self,
+            my_entity: EntityReference<E>,
+            _universe: &Universe,
+        ) {
+            assert!(my_entity.has_tag::<Marked>());
+        }
+    }
+
+    impl Tag for Marked {}
+
+    #[test]
+    fn tags_classify_entities() {
+        let mut universe = universe_with((0..3).map(Tagged::new));
+        for id in 3..5 {
+            universe.queue_add_entity((Tagged::new(id), Marked));
+        }
+        universe.queue_add_entity((Marked,));
+        universe.loop_once();
+        universe.loop_once();
+        assert_eq!(universe.count_tagged::<Marked>(), 3);
+        assert_eq!(size_of::<(Tagged, Marked)>(), size_of::<(Tagged,)>());
+    }
+
+    #[test]
+    fn filtered_iteration() {
+        let mut universe = universe_with((0..10).map(Tagged::new));
+        for id in 10..14 {
+            universe.queue_add_entity((Tagged::new(id), Marked));
+        }
+        universe.loop_once();
+        universe.loop_once();
+
+        let ids = |entities: Option<Vec<EntityReference<(Tagged,)>>>| -> BTreeSet<usize> {
+            entities.unwrap().iter().map(|x| x.0.id).collect()
+        };
+        let all = universe.iter_entities::<(Tagged,)>().map(|x| x.collect());
+        assert_eq!(ids(all), (0..10).collect());
+        let even = universe
+            .iter_entities_filtered::<(Tagged,), Without<Marked>>(|(x,)| x.id % 2 == 0)
+            .map(|x| x.collect());
+        assert_eq!(ids(even), [0, 2, 4, 6, 8].into());
+        let marked = universe
+            .iter_entities_filtered::<(Tagged,), With<Marked>>(|_| panic!("Should not be called"))
+            .map(|x| x.collect());
+        assert_eq!(ids(marked), [].into());
+
+        let both = universe
+            .iter_entities_filtered::<(Tagged, Marked), (With<Marked>, With<Tagged>)>(|(x, _)| {
+                x.id > 11
+            })
+            .unwrap();
+        let mut both: Vec<_> = both.map(|x| (x.0.id, x.index)).collect();
+        both.sort_unstable();
+        assert_eq!(both, [(12, 2), (13, 3)]);
+    }
+
+    #[test]
+    fn exclusive_systems_run_after_flush() {
+        let mut universe = universe_with((0..3).map(Tagged::new));
+        universe.queue_add_entity((Tagged::new(3),));
+        universe.queue_exclusive(|universe| {
+            // The entity added this frame has already been flushed into the buffer
+            let len = universe.get_entity_buffer::<(Tagged,)>().unwrap().len();
+            let log = universe.singleton_entry(Log::default);
+            log.processed.get_mut().push((len, usize::MAX));
+            universe.queue_exclusive(|universe| {
+                let log = universe.singleton_entry(Log::default);
+                log.processed.get_mut().push((99, usize::MAX));
+            });
+        });
+        assert_eq!(
+            run_frame(&mut universe),
+            [(0, 0), (1, 1), (2, 2), (4, usize::MAX)]
+        );
+        assert_eq!(
+            run_frame(&mut universe),
+            [(0, 0), (1, 1), (2, 2), (3, 3), (99, usize::MAX)]
+        );
+    }
 }
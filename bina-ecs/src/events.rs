@@ -0,0 +1,63 @@
+use crossbeam::queue::SegQueue;
+
+use crate::{
+    singleton::Singleton,
+    universe::{FlushStage, Universe},
+};
+
+/// A double buffered queue of events of a single type
+///
+/// Events sent during a process frame can be read during the next process
+/// frame, after which they are discarded. This singleton is created automatically
+/// by `Universe::send_event`, so there is usually no need to interact with it directly
+pub struct Events<T: Send + Sync + 'static> {
+    current: Vec<T>,
+    pending: SegQueue<T>,
+}
+
+impl<T: Send + Sync + 'static> Events<T> {
+    pub fn new() -> Self {
+        Self {
+            current: Vec::new(),
+            pending: SegQueue::new(),
+        }
+    }
+
+    /// Queues an event to be readable in the next process frame
+    pub fn send(&self, event: T) {
+        self.pending.push(event);
+    }
+
+    /// Gets all events that were sent in the last process frame
+    pub fn read(&self) -> &[T] {
+        &self.current
+    }
+
+    /// Makes an event readable immediately
+    ///
+    /// Used when the singleton has not been added to the Universe yet,
+    /// so that the event is not delayed by an extra frame
+    pub(crate) fn send_now(&mut self, event: T) {
+        self.current.push(event);
+    }
+}
+
+impl<T: Send + Sync + 'static> Default for Events<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Send + Sync + 'static> Singleton for Events<T> {
+    /// Flushed last, so that events sent while flushing the other stages can be read in the next frame
+    fn flush_stage(&self) -> FlushStage {
+        FlushStage::PreRender
+    }
+
+    fn flush(&mut self, _universe: &Universe) {
+        self.current.clear();
+        while let Some(event) = self.pending.pop() {
+            self.current.push(event);
+        }
+    }
+}
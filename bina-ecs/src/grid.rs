@@ -0,0 +1,238 @@
+//! Grids of cells that change according to rules, such as falling sand, fluids, and fire
+//!
+//! ```ignore
+//! #[derive(Clone, Copy, PartialEq)]
+//! enum Cell { Air, Sand }
+//!
+//! // Sand falls into air below it
+//! universe.queue_set_singleton(Grid2D::new(320, 180, Cell::Air).with_rule(|cells| {
+//!     match (cells.center(), cells.get(0, -1), cells.get(0, 1)) {
+//!         (Cell::Sand, Some(Cell::Air), _) => Cell::Air,
+//!         (Cell::Air, _, Some(Cell::Sand)) => Cell::Sand,
+//!         (cell, ..) => *cell,
+//!     }
+//! }));
+//! ```
+use crossbeam::queue::SegQueue;
+use rayon::prelude::*;
+
+use crate::{singleton::Singleton, universe::Universe};
+
+type Rule<T> = Box<dyn Fn(Cells<'_, T>) -> T + Send + Sync>;
+
+/// A double buffered grid of cells, where `y` points up and row 0 is at the bottom
+///
+/// The cells can be read while processing, and stay the same for the whole process frame.
+/// Every flush, the changes queued with `queue_set` are applied first, then every cell
+/// is replaced with the result of the rule, if there is one. The rule is applied to rows
+/// in parallel, reading the cells from before the step
+pub struct Grid2D<T: Clone + Send + Sync + 'static> {
+    width: usize,
+    height: usize,
+    current: Vec<T>,
+    /// Written to by the rule and then swapped with `current`, so that the cells are never reallocated
+    next: Vec<T>,
+    pending: SegQueue<(usize, usize, T)>,
+    rule: Option<Rule<T>>,
+    generation: u64,
+}
+
+impl<T: Clone + Send + Sync + 'static> Grid2D<T> {
+    /// Creates a `width` by `height` grid where every cell is `fill`
+    pub fn new(width: usize, height: usize, fill: T) -> Self {
+        Self {
+            width,
+            height,
+            current: vec![fill.clone(); width * height],
+            next: vec![fill; width * height],
+            pending: SegQueue::new(),
+            rule: None,
+            generation: 0,
+        }
+    }
+
+    /// Sets the rule that decides what each cell becomes in the next step, given its neighbors
+    pub fn with_rule(mut self, rule: impl Fn(Cells<'_, T>) -> T + Send + Sync + 'static) -> Self {
+        self.rule = Some(Box::new(rule));
+        self
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Gets a cell, or None if it is outside of the grid
+    pub fn get(&self, x: usize, y: usize) -> Option<&T> {
+        if x < self.width && y < self.height {
+            Some(&self.current[y * self.width + x])
+        } else {
+            None
+        }
+    }
+
+    /// Gets every cell, row by row from the bottom
+    pub fn cells(&self) -> &[T] {
+        &self.current
+    }
+
+    /// Incremented whenever any cell may have changed, so that renderers
+    /// can skip grids that are the same as when they last looked
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Sets a cell immediately, such as before the grid is added to the Universe
+    ///
+    /// # Panics
+    /// Panics if the cell is outside of the grid
+    pub fn set(&mut self, x: usize, y: usize, value: T) {
+        assert!(
+            x < self.width && y < self.height,
+            "Cell is outside of the grid"
+        );
+        self.current[y * self.width + x] = value;
+        self.generation += 1;
+    }
+
+    /// Sets a cell during the next flush, before the rule is applied.
+    /// Cells outside of the grid are ignored
+    pub fn queue_set(&self, x: usize, y: usize, value: T) {
+        self.pending.push((x, y, value));
+    }
+
+    /// Replaces every cell with the result of the rule given to `with_rule`, if there is one
+    pub fn step(&mut self) {
+        let Some(rule) = self.rule.take() else {
+            return;
+        };
+        self.step_with(&rule);
+        self.rule = Some(rule);
+    }
+
+    /// Replaces every cell with the result of the given rule, instead of the rule given to `with_rule`
+    pub fn step_with(&mut self, rule: impl Fn(Cells<'_, T>) -> T + Sync) {
+        if self.width == 0 {
+            return;
+        }
+        let current = &self.current;
+        let (width, height) = (self.width, self.height);
+        self.next
+            .par_chunks_mut(width)
+            .enumerate()
+            .for_each(|(y, row)| {
+                for (x, cell) in row.iter_mut().enumerate() {
+                    *cell = rule(Cells {
+                        cells: current,
+                        width,
+                        height,
+                        x,
+                        y,
+                    });
+                }
+            });
+        std::mem::swap(&mut self.current, &mut self.next);
+        self.generation += 1;
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> Singleton for Grid2D<T> {
+    fn flush(&mut self, _universe: &Universe) {
+        let mut changed = false;
+        while let Some((x, y, value)) = self.pending.pop() {
+            if x < self.width && y < self.height {
+                self.current[y * self.width + x] = value;
+                changed = true;
+            }
+        }
+        if changed {
+            self.generation += 1;
+        }
+        self.step();
+    }
+}
+
+/// A cell of a `Grid2D` and its neighbors, as they were before the current step
+#[derive(Clone, Copy)]
+pub struct Cells<'a, T> {
+    cells: &'a [T],
+    width: usize,
+    height: usize,
+    x: usize,
+    y: usize,
+}
+
+impl<'a, T> Cells<'a, T> {
+    /// The position of the cell being stepped
+    pub fn position(&self) -> (usize, usize) {
+        (self.x, self.y)
+    }
+
+    /// The cell being stepped
+    pub fn center(&self) -> &'a T {
+        &self.cells[self.y * self.width + self.x]
+    }
+
+    /// Gets the cell that is `dx` to the right and `dy` above the cell being stepped,
+    /// or None if it is outside of the grid
+    pub fn get(&self, dx: isize, dy: isize) -> Option<&'a T> {
+        let x = self.x.checked_add_signed(dx).filter(|x| *x < self.width)?;
+        let y = self.y.checked_add_signed(dy).filter(|y| *y < self.height)?;
+        Some(&self.cells[y * self.width + x])
+    }
+
+    /// Gets the 8 cells touching the cell being stepped, skipping those outside of the grid
+    pub fn neighbors(&self) -> impl Iterator<Item = &'a T> + '_ {
+        (-1..=1)
+            .flat_map(|dy| (-1..=1).map(move |dx| (dx, dy)))
+            .filter(|&offset| offset != (0, 0))
+            .filter_map(|(dx, dy)| self.get(dx, dy))
+    }
+
+    /// Counts the neighbors that match the predicate
+    pub fn count(&self, mut predicate: impl FnMut(&T) -> bool) -> usize {
+        self.neighbors().filter(|cell| predicate(cell)).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn life() -> Grid2D<bool> {
+        Grid2D::new(5, 5, false).with_rule(|cells| {
+            matches!(
+                (*cells.center(), cells.count(|alive| *alive)),
+                (true, 2) | (_, 3)
+            )
+        })
+    }
+
+    #[test]
+    fn grids_step_after_queued_writes() {
+        let mut universe = Universe::new();
+        let grid = life();
+        // A horizontal blinker, which turns vertical every step
+        for x in 1..3 {
+            grid.queue_set(x, 2, true);
+        }
+        universe.queue_set_singleton(grid);
+        universe.loop_once();
+
+        let grid = universe.get_singleton::<Grid2D<bool>>();
+        grid.queue_set(3, 2, true);
+        grid.queue_set(9, 9, true);
+        universe.loop_once();
+
+        let grid = universe.get_singleton::<Grid2D<bool>>();
+        let alive: Vec<_> = (0..25)
+            .filter(|i| grid.cells()[*i])
+            .map(|i| (i % 5, i / 5))
+            .collect();
+        assert_eq!(alive, [(2, 1), (2, 2), (2, 3)]);
+        assert_eq!(grid.get(5, 0), None);
+    }
+}
@@ -0,0 +1,51 @@
+//! Reading files in a way that also works in browsers
+use std::io;
+#[cfg(not(target_arch = "wasm32"))]
+use std::{
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+/// How often watched files are checked for changes, when the platform does not report them
+#[cfg(not(target_arch = "wasm32"))]
+pub const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Gets when a file was last modified, or `None` if it cannot be read
+#[cfg(not(target_arch = "wasm32"))]
+pub fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|x| x.modified()).ok()
+}
+
+/// Reads the entire file at the given path
+///
+/// Browsers cannot access files, so the path is fetched relative to the page instead
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn read(path: &str) -> io::Result<Vec<u8>> {
+    tokio::fs::read(path).await
+}
+
+/// Reads the entire file at the given path
+///
+/// Browsers cannot access files, so the path is fetched relative to the page instead
+#[cfg(target_arch = "wasm32")]
+pub async fn read(path: &str) -> io::Result<Vec<u8>> {
+    fetch(path)
+        .await
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{e:?}")))
+}
+
+#[cfg(target_arch = "wasm32")]
+async fn fetch(path: &str) -> Result<Vec<u8>, wasm_bindgen::JsValue> {
+    use wasm_bindgen::JsCast;
+    use wasm_bindgen_futures::JsFuture;
+
+    let window = web_sys::window().ok_or("There is no window to fetch from")?;
+    let response: web_sys::Response = JsFuture::from(window.fetch_with_str(path))
+        .await?
+        .dyn_into()?;
+    if !response.ok() {
+        return Err(format!("Fetching {path} failed with status {}", response.status()).into());
+    }
+    let buffer = JsFuture::from(response.array_buffer()?).await?;
+    Ok(js_sys::Uint8Array::new(&buffer).to_vec())
+}
@@ -16,15 +16,49 @@
 // #![feature(arbitrary_self_types)]
 // #![feature(vec_push_within_capacity)]
 // #![feature(associated_type_defaults)]
+pub mod assets;
+pub mod behavior;
 pub mod component;
+#[cfg(feature = "serde")]
+pub mod config;
+pub mod crash;
+pub mod diagnostics;
+pub mod easing;
 pub mod entity;
+pub mod events;
+pub mod grid;
+pub mod io;
+pub mod loading;
+pub mod pack;
+pub mod pacing;
+pub mod plugin;
+pub mod profiler;
+pub mod reflect;
+#[cfg(feature = "serde")]
+pub mod replay;
 pub mod rng;
+pub mod rollback;
+#[cfg(all(feature = "serde", not(target_arch = "wasm32")))]
+pub mod save;
+#[cfg(all(feature = "lua", not(target_arch = "wasm32")))]
+pub mod script;
+#[cfg(all(feature = "serde", not(target_arch = "wasm32")))]
+pub mod stats;
+#[cfg(all(feature = "steam", not(target_arch = "wasm32")))]
+pub mod steam;
+pub mod testing;
+pub mod time;
+pub mod undo;
 pub mod universe;
 pub mod worker;
 pub use crossbeam;
 pub use parking_lot;
+pub use rand;
 pub use rayon;
+#[cfg(all(feature = "steam", not(target_arch = "wasm32")))]
+pub use steamworks;
 pub use tokio;
 pub use triomphe;
 pub mod components;
 pub mod singleton;
+pub mod state_machine;
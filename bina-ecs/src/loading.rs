@@ -0,0 +1,192 @@
+//! A loading screen that runs while assets load, before the rest of the game
+//!
+//! While a `LoadingState` is loading, only entities tagged with `LoadingScreen`, such as a spinner
+//! or a progress bar, are processed. Every other entity is still flushed, so the game can be
+//! spawned while loading, but it does not run until everything has loaded or failed
+//!
+//! ```ignore
+//! let progress = assets.preload(universe, ["sprites/hero.png", "sounds/jump.ogg"]);
+//! universe.queue_set_singleton(LoadingState::new(progress).on_done(|universe, progress| {
+//!     universe.queue_add_entity(Hero::new(progress.handles::<TextureAsset>().next().unwrap().clone()));
+//! }));
+//! universe.queue_add_entity((ProgressBar::default(), LoadingScreen));
+//! ```
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use parking_lot::Mutex;
+
+use crate::{
+    assets::ProgressHandle,
+    component::{Component, Processable, Tag},
+    entity::{Entity, EntityReference},
+    singleton::Singleton,
+    universe::Universe,
+};
+
+/// Marks entities that are still processed while a `LoadingState` is loading
+pub struct LoadingScreen;
+
+impl Component for LoadingScreen {
+    type Reference<'a> = &'a Self;
+
+    fn get_ref<'a>(&'a self) -> Self::Reference<'a> {
+        self
+    }
+}
+
+impl Processable for LoadingScreen {
+    fn process<E: Entity>(
+        _component: Self::Reference<'_>,
+        _my_entity: EntityReference<E>,
+        _universe: &Universe,
+    ) {
+    }
+}
+
+impl Tag for LoadingScreen {}
+
+/// Sent into the Universe once everything a `LoadingState` was waiting for has loaded or failed
+pub struct LoadingFinished {
+    /// How many assets and tasks failed to load
+    pub failed: usize,
+}
+
+type OnDone = Box<dyn FnOnce(&Universe, &ProgressHandle) + Send>;
+
+/// A singleton that only lets `LoadingScreen` entities process until `progress` is done
+///
+/// The assets in `progress` stay loaded for as long as this singleton exists
+pub struct LoadingState {
+    progress: ProgressHandle,
+    on_done: Mutex<Option<OnDone>>,
+    /// Set during the process frame that `on_done` was called in
+    finished: AtomicBool,
+    loading: AtomicBool,
+}
+
+impl LoadingState {
+    pub fn new(progress: ProgressHandle) -> Self {
+        Self {
+            progress,
+            on_done: Mutex::new(None),
+            finished: AtomicBool::new(false),
+            loading: AtomicBool::new(true),
+        }
+    }
+
+    /// Called once everything has loaded or failed, during the process frame before the
+    /// rest of the entities start processing. Usually spawns the game and removes the loading screen
+    pub fn on_done(
+        mut self,
+        on_done: impl FnOnce(&Universe, &ProgressHandle) + Send + 'static,
+    ) -> Self {
+        *self.on_done.get_mut() = Some(Box::new(on_done));
+        self
+    }
+
+    pub fn progress(&self) -> &ProgressHandle {
+        &self.progress
+    }
+
+    /// Whether only `LoadingScreen` entities are processed
+    pub fn is_loading(&self) -> bool {
+        self.loading.load(Ordering::Relaxed)
+    }
+}
+
+impl Singleton for LoadingState {
+    fn process(&self, universe: &Universe) {
+        if !self.is_loading() || !self.progress.is_done() {
+            return;
+        }
+        self.finished.store(true, Ordering::Relaxed);
+        if let Some(on_done) = self.on_done.lock().take() {
+            on_done(universe, &self.progress);
+        }
+        universe.send_event(LoadingFinished {
+            failed: self.progress.failed(),
+        });
+    }
+
+    fn flush(&mut self, _universe: &Universe) {
+        // Only stops during the flush, so every entity starts processing on the same frame
+        if *self.finished.get_mut() {
+            *self.loading.get_mut() = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::*;
+    use crate::assets::{LoadState, Preload};
+
+    struct Gate(Arc<AtomicBool>);
+
+    impl Preload for Gate {
+        fn load_state(&self) -> LoadState {
+            if self.0.load(Ordering::Relaxed) {
+                LoadState::Loaded
+            } else {
+                LoadState::Loading
+            }
+        }
+    }
+
+    static GAME: AtomicUsize = AtomicUsize::new(0);
+    static SPINNER: AtomicUsize = AtomicUsize::new(0);
+
+    struct Counter(&'static AtomicUsize);
+
+    impl Component for Counter {
+        type Reference<'a> = &'a Self;
+
+        fn get_ref<'a>(&'a self) -> Self::Reference<'a> {
+            self
+        }
+    }
+
+    impl Processable for Counter {
+        fn process<E: Entity>(
+            component: &Self,
+            _my_entity: EntityReference<E>,
+            _universe: &Universe,
+        ) {
+            component.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn only_loading_screen_processes_until_done() {
+        let gate = Arc::new(AtomicBool::new(false));
+        let done = Arc::new(AtomicBool::new(false));
+        let done_clone = done.clone();
+        let progress = ProgressHandle::new().with_task(Gate(gate.clone()));
+
+        let mut universe = Universe::new();
+        universe.queue_set_singleton(
+            LoadingState::new(progress)
+                .on_done(move |_, _| done_clone.store(true, Ordering::Relaxed)),
+        );
+        universe.queue_add_entity((Counter(&GAME),));
+        universe.queue_add_entity((Counter(&SPINNER), LoadingScreen));
+        for _ in 0..4 {
+            universe.loop_once();
+        }
+        assert_eq!(GAME.load(Ordering::Relaxed), 0);
+        assert!(SPINNER.load(Ordering::Relaxed) > 0);
+        assert!(!done.load(Ordering::Relaxed));
+
+        gate.store(true, Ordering::Relaxed);
+        universe.loop_once();
+        assert!(done.load(Ordering::Relaxed));
+        assert!(!universe.get_singleton::<LoadingState>().is_loading());
+        universe.loop_once();
+        assert_eq!(GAME.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,150 @@
+//! Adaptive frame pacing for `DeltaStrategy::Adaptive`
+//!
+//! The Universe sleeps after every frame so that frames are presented at a target rate.
+//! Whatever presents frames, such as the render thread, reports each present to the
+//! `FramePacer` of the Universe. The time between presents is compared against the target,
+//! and the sleep is shortened when frames arrive late or lengthened when they arrive early.
+//! Without any presents, the time between frames of the Universe is used instead
+use std::collections::VecDeque;
+
+use parking_lot::Mutex;
+
+use crate::time::{Duration, Instant};
+
+/// How many intervals are kept for computing statistics
+const INTERVAL_HISTORY: usize = 240;
+
+/// How much of the error in each interval is corrected in the next sleep
+const GAIN: f64 = 0.1;
+
+/// Intervals longer than this many target frame times count as missed frames
+const MISSED_THRESHOLD: f64 = 1.5;
+
+/// Statistics about how evenly frames were presented, in seconds
+#[derive(Clone, Copy, Default, Debug)]
+pub struct FramePacingStats {
+    /// The time between frames that is being aimed for
+    pub target: f32,
+    /// How long each frame is currently allowed to take, including sleeping
+    pub sleep_target: f32,
+    /// The average time between presents, or between frames of the Universe if nothing presents
+    pub average_interval: f32,
+    /// The standard deviation of the time between presents
+    pub jitter: f32,
+    /// The furthest any interval was from the target
+    pub worst_deviation: f32,
+    /// How many intervals were much longer than the target
+    pub missed_frames: usize,
+    /// Whether the intervals were measured between presents
+    pub measuring_presents: bool,
+}
+
+#[derive(Default)]
+struct PacerState {
+    /// The target frame time, or `None` if pacing has not started
+    target: Option<f64>,
+    sleep_target: f64,
+    last_present: Option<Instant>,
+    /// Intervals between presents since the last frame of the Universe
+    pending_presents: Vec<f64>,
+    /// When presents were last reported, so that frame intervals are used once they stop
+    presents_seen: Option<Instant>,
+    intervals: VecDeque<f64>,
+    stats: FramePacingStats,
+}
+
+/// Measures when frames are presented, and decides how long the Universe sleeps after each frame
+///
+/// Gotten from `Universe::frame_pacer`
+#[derive(Default)]
+pub struct FramePacer {
+    state: Mutex<PacerState>,
+}
+
+impl FramePacer {
+    /// Records that a frame was just presented
+    ///
+    /// Called by whatever presents frames, such as the render thread
+    pub fn frame_presented(&self) {
+        let now = Instant::now();
+        let mut state = self.state.lock();
+        if let Some(last) = state.last_present.replace(now) {
+            let interval = (now - last).as_secs_f64();
+            state.pending_presents.push(interval);
+        }
+        state.presents_seen = Some(now);
+    }
+
+    /// Gets statistics about the intervals between recent frames,
+    /// or `None` if `DeltaStrategy::Adaptive` is not being used
+    pub fn stats(&self) -> Option<FramePacingStats> {
+        let state = self.state.lock();
+        state.target.map(|_| state.stats)
+    }
+
+    /// Adjusts the sleep target after a frame of the Universe ends, and returns how long
+    /// each frame should now take including sleeping
+    ///
+    /// `frame_interval` is the time between the previous two frames, which is zero before the first
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    pub(crate) fn frame_finished(&self, target: Duration, frame_interval: Duration) -> Duration {
+        let target = target.as_secs_f64();
+        let mut state = self.state.lock();
+        if state.target != Some(target) {
+            state.target = Some(target);
+            state.sleep_target = target;
+        }
+
+        // Presents that stopped more than a few frames ago, such as while minimized, are ignored
+        let measuring_presents = state
+            .presents_seen
+            .is_some_and(|x| x.elapsed().as_secs_f64() < target * 4.0 + 0.1);
+        let measured: Vec<f64> = if measuring_presents {
+            std::mem::take(&mut state.pending_presents)
+        } else {
+            state.pending_presents.clear();
+            state.last_present = None;
+            if frame_interval.is_zero() {
+                Vec::new()
+            } else {
+                vec![frame_interval.as_secs_f64()]
+            }
+        };
+
+        for interval in measured {
+            let error = interval - target;
+            state.sleep_target = (state.sleep_target - error * GAIN).clamp(0.0, target);
+            if state.intervals.len() == INTERVAL_HISTORY {
+                state.intervals.pop_front();
+            }
+            state.intervals.push_back(interval);
+        }
+
+        let count = state.intervals.len().max(1) as f64;
+        let average = state.intervals.iter().sum::<f64>() / count;
+        let variance = state
+            .intervals
+            .iter()
+            .map(|x| (x - average) * (x - average))
+            .sum::<f64>()
+            / count;
+        state.stats = FramePacingStats {
+            target: target as f32,
+            sleep_target: state.sleep_target as f32,
+            average_interval: average as f32,
+            jitter: variance.sqrt() as f32,
+            worst_deviation: state
+                .intervals
+                .iter()
+                .map(|x| (x - target).abs())
+                .fold(0.0, f64::max) as f32,
+            missed_frames: state
+                .intervals
+                .iter()
+                .filter(|x| **x > target * MISSED_THRESHOLD)
+                .count(),
+            measuring_presents,
+        };
+        Duration::from_secs_f64(state.sleep_target)
+    }
+}
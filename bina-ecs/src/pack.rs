@@ -0,0 +1,486 @@
+//! Bundling many assets into a single file for shipping builds
+//!
+//! A pack starts with an index of every file it contains, followed by their contents.
+//! Each file may be compressed with deflate, or zstd with the `zstd` feature, and is checked
+//! against a BLAKE3 hash of its original contents when it is read, so that a damaged or
+//! tampered pack is reported instead of producing garbage assets
+use std::{
+    fmt::Display,
+    io::{self, Read, Write},
+};
+
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use fxhash::FxHashMap;
+
+const MAGIC: &[u8; 8] = b"BINAPACK";
+const VERSION: u32 = 2;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PackCompression {
+    None,
+    Deflate,
+    /// Decompresses faster than deflate, and usually to smaller files
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+struct PackEntry {
+    offset: u64,
+    stored_len: u64,
+    len: u64,
+    compression: PackCompression,
+    hash: blake3::Hash,
+}
+
+#[derive(Debug)]
+pub enum PackError {
+    /// The file does not start with the header of a pack
+    NotAPack,
+    /// The pack was written by another version of bina
+    UnsupportedVersion(u32),
+    /// The index or contents of the pack end early
+    Truncated,
+    NotFound(String),
+    /// The contents of a file do not match the hash in the index
+    Corrupt(String),
+    /// A file is compressed with zstd, but the `zstd` feature is not enabled
+    UnsupportedCompression(String),
+    Io(io::Error),
+}
+
+impl Display for PackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotAPack => write!(f, "not an asset pack"),
+            Self::UnsupportedVersion(version) => write!(f, "unsupported pack version {version}"),
+            Self::Truncated => write!(f, "pack is truncated"),
+            Self::NotFound(path) => write!(f, "{path} was not found"),
+            Self::Corrupt(path) => write!(f, "{path} is corrupt"),
+            Self::UnsupportedCompression(path) => {
+                write!(
+                    f,
+                    "{path} is compressed with zstd, which needs the zstd feature"
+                )
+            }
+            Self::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for PackError {}
+
+impl From<io::Error> for PackError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+/// A pack of assets that has been loaded into memory
+///
+/// Files are only decompressed when they are read
+pub struct AssetPack {
+    data: Vec<u8>,
+    entries: FxHashMap<String, PackEntry>,
+}
+
+/// Reads the index of a pack
+struct Cursor<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], PackError> {
+        if self.data.len() < len {
+            return Err(PackError::Truncated);
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, PackError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, PackError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, PackError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, PackError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn hash(&mut self) -> Result<blake3::Hash, PackError> {
+        let bytes: [u8; blake3::OUT_LEN] = self.take(blake3::OUT_LEN)?.try_into().unwrap();
+        Ok(bytes.into())
+    }
+}
+
+impl AssetPack {
+    /// Reads a pack from the given path. In browsers, this is fetched relative to the page
+    pub async fn load(path: &str) -> Result<Self, PackError> {
+        Self::from_bytes(crate::io::read(path).await?)
+    }
+
+    /// Reads the index of a pack that has already been read into memory
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self, PackError> {
+        let mut cursor = Cursor { data: &data };
+        if cursor.take(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+            return Err(PackError::NotAPack);
+        }
+        let version = cursor.u32()?;
+        if version != VERSION {
+            return Err(PackError::UnsupportedVersion(version));
+        }
+
+        let count = cursor.u32()?;
+        let mut entries = FxHashMap::default();
+        for _ in 0..count {
+            let path_len = cursor.u16()? as usize;
+            let path = String::from_utf8_lossy(cursor.take(path_len)?).into_owned();
+            let entry = PackEntry {
+                offset: cursor.u64()?,
+                stored_len: cursor.u64()?,
+                len: cursor.u64()?,
+                compression: match cursor.u8()? {
+                    0 => PackCompression::None,
+                    1 => PackCompression::Deflate,
+                    #[cfg(feature = "zstd")]
+                    2 => PackCompression::Zstd,
+                    #[cfg(not(feature = "zstd"))]
+                    2 => return Err(PackError::UnsupportedCompression(path)),
+                    _ => return Err(PackError::Corrupt(path)),
+                },
+                hash: cursor.hash()?,
+            };
+            entries.insert(path, entry);
+        }
+
+        // Offsets are relative to the end of the index
+        let header_len = (data.len() - cursor.data.len()) as u64;
+        for entry in entries.values_mut() {
+            entry.offset = entry
+                .offset
+                .checked_add(header_len)
+                .ok_or(PackError::Truncated)?;
+            match entry.offset.checked_add(entry.stored_len) {
+                Some(end) if end <= data.len() as u64 => {}
+                _ => return Err(PackError::Truncated),
+            }
+        }
+
+        Ok(Self { data, entries })
+    }
+
+    pub fn contains(&self, path: &str) -> bool {
+        self.entries.contains_key(path)
+    }
+
+    /// Iterates over the path of every file in the pack
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(String::as_str)
+    }
+
+    /// Reads and decompresses the file at the given path
+    pub fn read(&self, path: &str) -> Result<Vec<u8>, PackError> {
+        let entry = self
+            .entries
+            .get(path)
+            .ok_or_else(|| PackError::NotFound(path.into()))?;
+        let stored = entry
+            .offset
+            .checked_add(entry.stored_len)
+            .and_then(|end| self.data.get(entry.offset as usize..end as usize))
+            .ok_or(PackError::Truncated)?;
+        let bytes = decompress(stored, entry.compression, entry.len, path)?;
+        // `Hash` is compared in constant time
+        if bytes.len() as u64 != entry.len || blake3::hash(&bytes) != entry.hash {
+            return Err(PackError::Corrupt(path.into()));
+        }
+        Ok(bytes)
+    }
+}
+
+impl VirtualFs for AssetPack {
+    fn contains(&self, path: &str) -> bool {
+        AssetPack::contains(self, path)
+    }
+
+    fn read(&self, path: &str) -> Result<Vec<u8>, PackError> {
+        AssetPack::read(self, path)
+    }
+}
+
+/// Decompresses at most one byte more than `len`, so that a corrupt file
+/// claiming to be huge is caught by the length check instead of exhausting memory
+fn decompress(
+    stored: &[u8],
+    compression: PackCompression,
+    len: u64,
+    path: &str,
+) -> Result<Vec<u8>, PackError> {
+    match compression {
+        PackCompression::None => Ok(stored.to_vec()),
+        PackCompression::Deflate => {
+            let mut bytes = Vec::new();
+            DeflateDecoder::new(stored)
+                .take(len.saturating_add(1))
+                .read_to_end(&mut bytes)
+                .map_err(|_| PackError::Corrupt(path.into()))?;
+            Ok(bytes)
+        }
+        #[cfg(feature = "zstd")]
+        PackCompression::Zstd => {
+            let mut bytes = Vec::new();
+            zstd::Decoder::new(stored)
+                .and_then(|decoder| decoder.take(len.saturating_add(1)).read_to_end(&mut bytes))
+                .map_err(|_| PackError::Corrupt(path.into()))?;
+            Ok(bytes)
+        }
+    }
+}
+
+/// A read only set of files that can be mounted in `Assets`
+pub trait VirtualFs: Send + Sync + 'static {
+    fn contains(&self, path: &str) -> bool;
+    fn read(&self, path: &str) -> Result<Vec<u8>, PackError>;
+}
+
+/// A file compiled into the binary by `embed_assets!`
+pub struct EmbeddedFile {
+    pub path: &'static str,
+    pub bytes: &'static [u8],
+    pub compression: PackCompression,
+}
+
+/// A directory compiled into the binary by `embed_assets!`
+///
+/// ```ignore
+/// static ASSETS: EmbeddedAssets = embed_assets!("assets/", compress);
+/// // Later
+/// let assets = Assets::new().with_embedded(ASSETS);
+/// ```
+#[derive(Clone, Copy)]
+pub struct EmbeddedAssets {
+    files: &'static [EmbeddedFile],
+}
+
+impl EmbeddedAssets {
+    /// The files must be sorted by path, which `embed_assets!` ensures
+    pub const fn new(files: &'static [EmbeddedFile]) -> Self {
+        Self { files }
+    }
+
+    fn get(&self, path: &str) -> Option<&'static EmbeddedFile> {
+        self.files
+            .binary_search_by(|file| file.path.cmp(path))
+            .ok()
+            .map(|i| &self.files[i])
+    }
+
+    /// Iterates over the path of every embedded file
+    pub fn paths(&self) -> impl Iterator<Item = &'static str> {
+        self.files.iter().map(|file| file.path)
+    }
+}
+
+impl VirtualFs for EmbeddedAssets {
+    fn contains(&self, path: &str) -> bool {
+        self.get(path).is_some()
+    }
+
+    fn read(&self, path: &str) -> Result<Vec<u8>, PackError> {
+        let file = self
+            .get(path)
+            .ok_or_else(|| PackError::NotFound(path.into()))?;
+        // Embedded files were compressed at compile time, so their size is trusted
+        decompress(file.bytes, file.compression, u64::MAX, path)
+    }
+}
+
+/// Builds an `AssetPack`, usually from a build script or a separate packing tool
+///
+/// ```ignore
+/// let mut writer = PackWriter::new();
+/// writer.add_dir("assets", PackCompression::Deflate)?;
+/// writer.write_to(File::create("assets.pack")?)?;
+/// ```
+#[derive(Default)]
+pub struct PackWriter {
+    files: Vec<(String, Vec<u8>, PackCompression)>,
+}
+
+impl PackWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a file to the pack. Paths should use `/` as a separator,
+    /// as they are matched against the paths given to `Assets::load`
+    pub fn add(&mut self, path: impl Into<String>, bytes: Vec<u8>, compression: PackCompression) {
+        self.files.push((path.into(), bytes, compression));
+    }
+
+    /// Adds every file in the given directory and its subdirectories,
+    /// with paths relative to the directory
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn add_dir(
+        &mut self,
+        dir: impl AsRef<std::path::Path>,
+        compression: PackCompression,
+    ) -> io::Result<()> {
+        let dir = dir.as_ref();
+        let mut pending = vec![dir.to_path_buf()];
+        while let Some(current) = pending.pop() {
+            for entry in std::fs::read_dir(current)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    pending.push(path);
+                    continue;
+                }
+                let relative = path
+                    .strip_prefix(dir)
+                    .expect("Walked paths should be inside the directory")
+                    .components()
+                    .map(|x| x.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                self.add(relative, std::fs::read(&path)?, compression);
+            }
+        }
+        Ok(())
+    }
+
+    /// Compresses every file and writes the pack
+    pub fn write_to(self, mut writer: impl Write) -> io::Result<()> {
+        let mut index = Vec::new();
+        let mut contents = Vec::new();
+        index.extend_from_slice(MAGIC);
+        index.extend_from_slice(&VERSION.to_le_bytes());
+        index.extend_from_slice(&(self.files.len() as u32).to_le_bytes());
+
+        for (path, bytes, compression) in self.files {
+            let path_len: u16 = path
+                .len()
+                .try_into()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Path is too long"))?;
+            let offset = contents.len() as u64;
+            match compression {
+                PackCompression::None => contents.extend_from_slice(&bytes),
+                PackCompression::Deflate => {
+                    let mut encoder = DeflateEncoder::new(&mut contents, Compression::best());
+                    encoder.write_all(&bytes)?;
+                    encoder.finish()?;
+                }
+                #[cfg(feature = "zstd")]
+                PackCompression::Zstd => {
+                    zstd::stream::copy_encode(bytes.as_slice(), &mut contents, 19)?;
+                }
+            }
+
+            index.extend_from_slice(&path_len.to_le_bytes());
+            index.extend_from_slice(path.as_bytes());
+            index.extend_from_slice(&offset.to_le_bytes());
+            index.extend_from_slice(&(contents.len() as u64 - offset).to_le_bytes());
+            index.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+            index.push(match compression {
+                PackCompression::None => 0,
+                PackCompression::Deflate => 1,
+                #[cfg(feature = "zstd")]
+                PackCompression::Zstd => 2,
+            });
+            index.extend_from_slice(blake3::hash(&bytes).as_bytes());
+        }
+
+        writer.write_all(&index)?;
+        writer.write_all(&contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pack() -> Vec<u8> {
+        let mut writer = PackWriter::new();
+        writer.add("a.txt", b"hello".to_vec(), PackCompression::None);
+        writer.add("b/c.txt", vec![7; 1000], PackCompression::Deflate);
+        let mut data = Vec::new();
+        writer.write_to(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn round_trip() {
+        let pack = AssetPack::from_bytes(pack()).unwrap();
+        assert_eq!(pack.read("a.txt").unwrap(), b"hello");
+        assert_eq!(pack.read("b/c.txt").unwrap(), vec![7; 1000]);
+        assert!(matches!(pack.read("d.txt"), Err(PackError::NotFound(_))));
+        let mut paths: Vec<_> = pack.paths().collect();
+        paths.sort();
+        assert_eq!(paths, ["a.txt", "b/c.txt"]);
+    }
+
+    #[test]
+    fn truncated() {
+        let data = pack();
+        for len in [4, 20, data.len() - 1] {
+            assert!(matches!(
+                AssetPack::from_bytes(data[..len].to_vec()),
+                Err(PackError::NotAPack | PackError::Truncated)
+            ));
+        }
+    }
+
+    #[test]
+    fn offset_overflow() {
+        let mut data = pack();
+        // The offset of the first entry, after the header, count, path length and path
+        let offset = MAGIC.len() + 4 + 4 + 2 + "a.txt".len();
+        data[offset..offset + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(
+            AssetPack::from_bytes(data),
+            Err(PackError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn corrupt() {
+        let mut data = pack();
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        let pack = AssetPack::from_bytes(data).unwrap();
+        assert!(matches!(pack.read("b/c.txt"), Err(PackError::Corrupt(_))));
+        assert_eq!(pack.read("a.txt").unwrap(), b"hello");
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd() {
+        let mut writer = PackWriter::new();
+        writer.add("a.txt", vec![7; 1000], PackCompression::Zstd);
+        let mut data = Vec::new();
+        writer.write_to(&mut data).unwrap();
+        assert!(data.len() < 1000);
+        let pack = AssetPack::from_bytes(data).unwrap();
+        assert_eq!(pack.read("a.txt").unwrap(), vec![7; 1000]);
+
+        let contents = zstd::encode_all([0; 4096].as_slice(), 0).unwrap();
+        let bytes = decompress(&contents, PackCompression::Zstd, 16, "a").unwrap();
+        assert_eq!(bytes.len(), 17);
+    }
+
+    #[test]
+    fn decompression_is_bounded() {
+        let mut contents = Vec::new();
+        let mut encoder = DeflateEncoder::new(&mut contents, Compression::best());
+        encoder.write_all(&[0; 4096]).unwrap();
+        encoder.finish().unwrap();
+        let bytes = decompress(&contents, PackCompression::Deflate, 16, "a").unwrap();
+        assert_eq!(bytes.len(), 17);
+    }
+}
@@ -0,0 +1,216 @@
+//! Extending a game with plugins, which add singletons, types of entities, and asset loaders
+//! to a Universe before it starts
+//!
+//! ```ignore
+//! struct Physics;
+//!
+//! impl Plugin for Physics {
+//!     fn build(&self, universe: &mut Universe) {
+//!         universe.queue_set_singleton(PhysicsWorld::default());
+//!         universe.singleton_entry(ReflectRegistry::new).register::<(RigidBody,)>();
+//!         universe.singleton_entry(Assets::new).add_loader(ColliderLoader);
+//!     }
+//! }
+//!
+//! universe.add_plugin(Physics);
+//! ```
+//!
+//! With the `dynamic_plugins` feature, plugins can also be built as dynamic libraries
+//! by third parties and loaded with `Universe::load_plugin`
+#[cfg(all(feature = "dynamic_plugins", not(target_arch = "wasm32")))]
+use std::{ffi::OsStr, fmt::Display};
+
+use crate::universe::Universe;
+
+/// Something that sets up a Universe, usually by adding singletons and registering types
+pub trait Plugin: 'static {
+    fn build(&self, universe: &mut Universe);
+    /// Plugins with the same name are only built once
+    fn name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+}
+
+/// The plugins that have been added to a Universe
+#[derive(Default)]
+pub(crate) struct Plugins {
+    names: Vec<&'static str>,
+    /// Kept until the Universe is dropped, as its singletons and entities may have come from them
+    #[cfg(all(feature = "dynamic_plugins", not(target_arch = "wasm32")))]
+    libraries: Vec<libloading::Library>,
+}
+
+impl Universe {
+    /// Builds a plugin, unless a plugin with the same name was already added
+    ///
+    /// Plugins can add the plugins they depend on without worrying that they will be built twice
+    pub fn add_plugin(&mut self, plugin: impl Plugin) {
+        let name = plugin.name();
+        if self.plugins.names.contains(&name) {
+            return;
+        }
+        self.plugins.names.push(name);
+        log::info!("Adding plugin {name}");
+        plugin.build(self);
+    }
+
+    /// Gets the names of every plugin that has been added, in the order they were added
+    pub fn plugin_names(&self) -> &[&'static str] {
+        &self.plugins.names
+    }
+
+    /// Loads a dynamic library made with `export_plugin!`, and adds its plugin
+    ///
+    /// The library is kept loaded until this Universe is dropped
+    ///
+    /// # Safety
+    /// Rust has no stable ABI, so the library must be built with the same compiler,
+    /// and the same version of bina and its features, as this game. Only the version
+    /// of bina and the size of `Universe` are checked. Loading a library also runs its
+    /// initialization code
+    #[cfg(all(feature = "dynamic_plugins", not(target_arch = "wasm32")))]
+    pub unsafe fn load_plugin(&mut self, path: impl AsRef<OsStr>) -> Result<(), PluginError> {
+        let library = libloading::Library::new(path)?;
+        let declaration: libloading::Symbol<*const PluginDeclaration> =
+            library.get(b"BINA_PLUGIN\0")?;
+        let declaration = &**declaration;
+        if declaration.bina_version != VERSION {
+            return Err(PluginError::VersionMismatch(
+                declaration.bina_version.to_owned(),
+            ));
+        }
+        if declaration.universe_size != std::mem::size_of::<Universe>() {
+            return Err(PluginError::FeatureMismatch);
+        }
+        let plugin = (declaration.create)();
+        self.plugins.libraries.push(library);
+        self.add_plugin(BoxedPlugin(plugin));
+        Ok(())
+    }
+}
+
+#[cfg(all(feature = "dynamic_plugins", not(target_arch = "wasm32")))]
+struct BoxedPlugin(Box<dyn Plugin>);
+
+#[cfg(all(feature = "dynamic_plugins", not(target_arch = "wasm32")))]
+impl Plugin for BoxedPlugin {
+    fn build(&self, universe: &mut Universe) {
+        self.0.build(universe);
+    }
+    fn name(&self) -> &'static str {
+        self.0.name()
+    }
+}
+
+#[doc(hidden)]
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// What a dynamic library made with `export_plugin!` exports
+#[doc(hidden)]
+pub struct PluginDeclaration {
+    pub bina_version: &'static str,
+    /// Differs if the library was built with different features, which is likely to change it
+    pub universe_size: usize,
+    pub create: fn() -> Box<dyn Plugin>,
+}
+
+/// Exports a plugin from a dynamic library, so that it can be loaded with `Universe::load_plugin`
+///
+/// The library should be a `cdylib` or `dylib`
+///
+/// ```ignore
+/// bina_ecs::export_plugin!(Physics);
+/// ```
+#[macro_export]
+macro_rules! export_plugin {
+    ($plugin: expr) => {
+        #[no_mangle]
+        pub static BINA_PLUGIN: $crate::plugin::PluginDeclaration =
+            $crate::plugin::PluginDeclaration {
+                bina_version: $crate::plugin::VERSION,
+                universe_size: ::std::mem::size_of::<$crate::universe::Universe>(),
+                create: || -> Box<dyn $crate::plugin::Plugin> { Box::new($plugin) },
+            };
+    };
+}
+
+#[cfg(all(feature = "dynamic_plugins", not(target_arch = "wasm32")))]
+#[derive(Debug)]
+pub enum PluginError {
+    /// The library could not be loaded, or does not export a plugin
+    Library(libloading::Error),
+    /// The library was built against a different version of bina
+    VersionMismatch(String),
+    /// The library was built with different features of bina
+    FeatureMismatch,
+}
+
+#[cfg(all(feature = "dynamic_plugins", not(target_arch = "wasm32")))]
+impl Display for PluginError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Library(e) => write!(f, "{e}"),
+            Self::VersionMismatch(version) => write!(
+                f,
+                "plugin was built for bina {version}, but this is bina {VERSION}"
+            ),
+            Self::FeatureMismatch => write!(f, "plugin was built with different features of bina"),
+        }
+    }
+}
+
+#[cfg(all(feature = "dynamic_plugins", not(target_arch = "wasm32")))]
+impl std::error::Error for PluginError {}
+
+#[cfg(all(feature = "dynamic_plugins", not(target_arch = "wasm32")))]
+impl From<libloading::Error> for PluginError {
+    fn from(value: libloading::Error) -> Self {
+        Self::Library(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::singleton::Singleton;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct Counts(Vec<&'static str>);
+
+    impl Singleton for Counts {}
+
+    struct Base;
+
+    impl Plugin for Base {
+        fn build(&self, universe: &mut Universe) {
+            universe.singleton_entry(Counts::default).0.push("base");
+        }
+    }
+
+    struct Game;
+
+    impl Plugin for Game {
+        fn build(&self, universe: &mut Universe) {
+            universe.add_plugin(Base);
+            universe.singleton_entry(Counts::default).0.push("game");
+        }
+    }
+
+    #[test]
+    fn plugins_are_built_once() {
+        let mut universe = Universe::new();
+        universe.add_plugin(Game);
+        universe.add_plugin(Base);
+        universe.loop_once();
+        assert_eq!(universe.get_singleton::<Counts>().0, ["base", "game"]);
+        assert_eq!(universe.plugin_names().len(), 2);
+
+        // Singletons that already exist are added to in place
+        universe.singleton_entry(Counts::default).0.push("later");
+        assert_eq!(
+            universe.get_singleton::<Counts>().0,
+            ["base", "game", "later"]
+        );
+    }
+}
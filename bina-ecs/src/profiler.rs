@@ -0,0 +1,107 @@
+use crate::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+/// The phase of a frame that a `Span` was measured in
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Phase {
+    Process,
+    Flush,
+}
+
+/// How long a single entity buffer or singleton took during a phase of a frame
+#[derive(Clone, Copy, Debug)]
+pub struct Span {
+    /// The type name of the entity or singleton
+    pub name: &'static str,
+    pub phase: Phase,
+    pub duration: Duration,
+}
+
+/// CPU timings of a single frame
+///
+/// Entity buffers and singletons run in parallel, so the durations of their
+/// spans may add up to more than the duration of their phase
+#[derive(Clone, Default, Debug)]
+pub struct FrameProfile {
+    /// The time taken to process everything
+    pub process: Duration,
+    /// The time taken to flush everything
+    pub flush: Duration,
+    pub spans: Vec<Span>,
+}
+
+/// Measures CPU spans of the Universe
+///
+/// With the `tracing` feature, spans are also emitted through `tracing`
+/// regardless of whether the profiler is enabled, so they can be exported
+/// to tools like Tracy with a suitable subscriber
+#[derive(Default)]
+pub(crate) struct Profiler {
+    enabled: bool,
+    spans: Mutex<Vec<Span>>,
+    phase_start: Option<Instant>,
+    current: FrameProfile,
+    last: FrameProfile,
+}
+
+impl Profiler {
+    pub(crate) fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.last = FrameProfile::default();
+        }
+    }
+
+    /// Runs the given function, measuring it if the profiler is enabled
+    pub(crate) fn time<T>(&self, phase: Phase, name: &'static str, f: impl FnOnce() -> T) -> T {
+        #[cfg(feature = "tracing")]
+        let _span = match phase {
+            Phase::Process => tracing::info_span!("process", name),
+            Phase::Flush => tracing::info_span!("flush", name),
+        }
+        .entered();
+
+        if !self.enabled {
+            return f();
+        }
+        let start = Instant::now();
+        let result = f();
+        self.spans.lock().push(Span {
+            name,
+            phase,
+            duration: start.elapsed(),
+        });
+        result
+    }
+
+    pub(crate) fn begin_phase(&mut self) {
+        if self.enabled {
+            self.phase_start = Some(Instant::now());
+        }
+    }
+
+    pub(crate) fn end_phase(&mut self, phase: Phase) {
+        let Some(start) = self.phase_start.take() else {
+            return;
+        };
+        match phase {
+            Phase::Process => self.current.process = start.elapsed(),
+            Phase::Flush => self.current.flush = start.elapsed(),
+        }
+    }
+
+    /// Makes the spans measured so far readable through `last_frame`
+    pub(crate) fn end_frame(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        self.current.spans.clear();
+        self.current.spans.append(self.spans.get_mut());
+        std::mem::swap(&mut self.current, &mut self.last);
+    }
+
+    pub(crate) fn last_frame(&self) -> &FrameProfile {
+        &self.last
+    }
+}
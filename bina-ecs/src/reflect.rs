@@ -0,0 +1,172 @@
+//! Runtime access to the fields of components, for tools such as inspectors
+use std::{any::type_name, fmt::Debug};
+
+use crate::{
+    component::{AtomicNumber, NumberField},
+    entity::Entity,
+    singleton::Singleton,
+    universe::Universe,
+};
+
+/// A number that can be viewed and edited as a list of `f64` components
+pub trait ReflectNumber: AtomicNumber {
+    /// The number of components, such as 2 for a 2D vector
+    const COMPONENTS: usize = 1;
+
+    fn component(self, index: usize) -> f64;
+    fn with_component(self, index: usize, value: f64) -> Self;
+}
+
+macro_rules! impl_reflect_num {
+    ($($ty: ty) *) => {
+        $(
+            impl ReflectNumber for $ty {
+                fn component(self, _index: usize) -> f64 {
+                    self as f64
+                }
+
+                fn with_component(self, _index: usize, value: f64) -> Self {
+                    value as Self
+                }
+            }
+        )*
+    };
+}
+
+impl_reflect_num! { u8 u16 u32 u64 usize i8 i16 i32 i64 isize f32 f64 }
+
+impl<T: ReflectNumber, const N: usize> ReflectNumber for [T; N] {
+    const COMPONENTS: usize = N * T::COMPONENTS;
+
+    fn component(self, index: usize) -> f64 {
+        self[index / T::COMPONENTS].component(index % T::COMPONENTS)
+    }
+
+    fn with_component(mut self, index: usize, value: f64) -> Self {
+        let item = &mut self[index / T::COMPONENTS];
+        *item = item.with_component(index % T::COMPONENTS, value);
+        self
+    }
+}
+
+/// A `NumberField` with its number type erased
+pub trait ReflectNumberField {
+    fn components(&self) -> usize;
+    fn get(&self, index: usize) -> f64;
+    /// Sets a component of the number when the process frame ends,
+    /// overwriting any other changes made to it this frame
+    fn queue_set(&self, index: usize, value: f64);
+}
+
+impl<T: ReflectNumber> ReflectNumberField for NumberField<T> {
+    fn components(&self) -> usize {
+        T::COMPONENTS
+    }
+
+    fn get(&self, index: usize) -> f64 {
+        self.get_inner().component(index)
+    }
+
+    fn queue_set(&self, index: usize, value: f64) {
+        self.get_ref()
+            .set(self.get_inner().with_component(index, value));
+    }
+}
+
+pub enum Field<'a> {
+    /// A field that can be edited
+    Number(&'a dyn ReflectNumberField),
+    /// A field that can only be viewed
+    Debug(&'a dyn Debug),
+}
+
+/// A component whose fields can be accessed at runtime
+pub trait Reflect {
+    /// Calls `visitor` with the name and value of every field that should be visible
+    fn reflect_fields(&self, visitor: &mut dyn FnMut(&'static str, Field<'_>));
+}
+
+/// An entity whose components can be accessed at runtime
+///
+/// Implemented for every entity whose components all implement `Reflect`
+pub trait ReflectComponents {
+    /// Calls `visitor` with the type name of every component
+    fn reflect_components(&self, visitor: &mut dyn FnMut(&'static str, &dyn Reflect));
+}
+
+impl<A: Reflect> ReflectComponents for (A,) {
+    fn reflect_components(&self, visitor: &mut dyn FnMut(&'static str, &dyn Reflect)) {
+        visitor(type_name::<A>(), &self.0);
+    }
+}
+
+impl<A: Reflect, B: Reflect> ReflectComponents for (A, B) {
+    fn reflect_components(&self, visitor: &mut dyn FnMut(&'static str, &dyn Reflect)) {
+        visitor(type_name::<A>(), &self.0);
+        visitor(type_name::<B>(), &self.1);
+    }
+}
+
+type ForEachEntity = fn(&Universe, &mut dyn FnMut(usize, &dyn ReflectComponents));
+
+fn for_each_entity<E: Entity + ReflectComponents>(
+    universe: &Universe,
+    f: &mut dyn FnMut(usize, &dyn ReflectComponents),
+) {
+    if let Some(buffer) = universe.get_entity_buffer::<E>() {
+        for (index, entity) in buffer.iter().enumerate() {
+            f(index, entity);
+        }
+    }
+}
+
+/// The types of entities that can be accessed at runtime
+///
+/// ```ignore
+/// universe.queue_set_singleton(ReflectRegistry::new().with::<(Polygon,)>());
+/// ```
+#[derive(Default)]
+pub struct ReflectRegistry {
+    entities: Vec<(&'static str, ForEachEntity)>,
+}
+
+impl ReflectRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a type of entity
+    pub fn with<E: Entity + ReflectComponents>(mut self) -> Self {
+        self.register::<E>();
+        self
+    }
+
+    /// Registers a type of entity, unless it was already registered
+    pub fn register<E: Entity + ReflectComponents>(&mut self) {
+        let name = type_name::<E>();
+        if !self.entities.iter().any(|(x, _)| *x == name) {
+            self.entities.push((name, for_each_entity::<E>));
+        }
+    }
+
+    /// Gets the type name of every registered type of entity
+    pub fn entity_names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.entities.iter().map(|(name, _)| *name)
+    }
+
+    /// Calls `f` with the index of every entity of the registered type with the given name
+    ///
+    /// Entities should only be accessed while processing, as they are modified while flushing
+    pub fn for_each_entity(
+        &self,
+        universe: &Universe,
+        name: &str,
+        mut f: impl FnMut(usize, &dyn ReflectComponents),
+    ) {
+        if let Some((_, for_each)) = self.entities.iter().find(|(x, _)| *x == name) {
+            for_each(universe, &mut f);
+        }
+    }
+}
+
+impl Singleton for ReflectRegistry {}
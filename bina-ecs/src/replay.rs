@@ -0,0 +1,241 @@
+//! Recording the input of a game every frame and playing it back, for sharing replays and testing gameplay
+//!
+//! The game reads its input from a singleton that implements `ReplayInput`. A `ReplayRecorder`
+//! captures that singleton every frame, and a `ReplayPlayer` overwrites it with what was captured.
+//! Replays only play back the same way if the game is deterministic, so both set the seed of
+//! `BufferedRng` when installed, and the Universe should run with `DeltaStrategy::FakeDelta`
+//! set to the delta of the replay
+//!
+//! ```ignore
+//! ReplayRecorder::<PlayerInput>::install(&universe, 1234, Duration::from_secs_f32(1.0 / 60.0));
+//! // Later, such as when the level ends
+//! universe.get_singleton::<ReplayRecorder<PlayerInput>>().finish().save("level1.replay")?;
+//!
+//! // In another run of the game, or in a test
+//! let replay = Replay::<InputSnapshot>::load("level1.replay")?;
+//! let delta = replay.delta;
+//! ReplayPlayer::<PlayerInput>::install(&universe, replay);
+//! universe.loop_many(LoopCount::Forever, DeltaStrategy::FakeDelta(delta));
+//! ```
+use std::fmt::Display;
+
+use parking_lot::Mutex;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    rng::BufferedRng,
+    singleton::Singleton,
+    time::Duration,
+    universe::{FlushStage, Universe},
+};
+
+const MAGIC: &[u8; 8] = b"BINARPLY";
+
+#[derive(Debug)]
+pub enum ReplayError {
+    /// The file does not start with the header of a replay
+    NotAReplay,
+    Serialization(String),
+    Io(std::io::Error),
+}
+
+impl Display for ReplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotAReplay => write!(f, "not a replay file"),
+            Self::Serialization(e) => write!(f, "{e}"),
+            Self::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<std::io::Error> for ReplayError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<bincode::Error> for ReplayError {
+    fn from(value: bincode::Error) -> Self {
+        Self::Serialization(value.to_string())
+    }
+}
+
+/// A singleton that the game reads its input from, such as which buttons are held
+///
+/// It must be flushed before `FlushStage::PreRender`, as that is when it is captured
+pub trait ReplayInput: Singleton {
+    /// Everything the game reads from this singleton in a frame
+    type Snapshot: Clone + Serialize + DeserializeOwned + Send + Sync + 'static;
+
+    fn capture(&self) -> Self::Snapshot;
+    /// Replaces the input with a captured snapshot, ignoring what the devices reported
+    fn restore(&mut self, snapshot: Self::Snapshot);
+}
+
+/// The input of every frame, along with what is needed to simulate them the same way again
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Replay<T> {
+    /// The seed of `BufferedRng`
+    pub seed: u64,
+    /// The fixed delta of every frame
+    pub delta: Duration,
+    pub frames: Vec<T>,
+}
+
+impl<T: Serialize + DeserializeOwned> Replay<T> {
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ReplayError> {
+        let mut bytes = MAGIC.to_vec();
+        bincode::serialize_into(&mut bytes, self)?;
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ReplayError> {
+        let Some(payload) = bytes.strip_prefix(MAGIC) else {
+            return Err(ReplayError::NotAReplay);
+        };
+        Ok(bincode::deserialize(payload)?)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> Result<(), ReplayError> {
+        std::fs::write(path, self.to_bytes()?)?;
+        Ok(())
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Self, ReplayError> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+}
+
+/// Captures a `ReplayInput` singleton every frame
+///
+/// The first frame captured is the one after the recorder is added to the Universe
+pub struct ReplayRecorder<S: ReplayInput> {
+    seed: u64,
+    delta: Duration,
+    frames: Mutex<Vec<S::Snapshot>>,
+}
+
+impl<S: ReplayInput> ReplayRecorder<S> {
+    /// Seeds `BufferedRng` and starts recording, replacing any recording in progress
+    pub fn install(universe: &Universe, seed: u64, delta: Duration) {
+        BufferedRng::set_seed(seed);
+        universe.queue_set_singleton(Self {
+            seed,
+            delta,
+            frames: Mutex::default(),
+        });
+    }
+
+    /// How many frames have been captured
+    pub fn len(&self) -> usize {
+        self.frames.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Copies everything captured so far into a replay. Recording continues until the recorder is removed
+    pub fn finish(&self) -> Replay<S::Snapshot> {
+        Replay {
+            seed: self.seed,
+            delta: self.delta,
+            frames: self.frames.lock().clone(),
+        }
+    }
+}
+
+impl<S: ReplayInput> Singleton for ReplayRecorder<S> {
+    /// Flushed last, so that the input has already been updated for the next frame
+    fn flush_stage(&self) -> FlushStage {
+        FlushStage::PreRender
+    }
+
+    fn flush(&mut self, universe: &Universe) {
+        if let Some(input) = universe.try_get_singleton::<S>() {
+            self.frames.get_mut().push(input.capture());
+        }
+    }
+}
+
+/// Sent into the Universe once a `ReplayPlayer` has restored every frame of its replay
+#[derive(Clone, Copy, Debug)]
+pub struct ReplayFinished;
+
+/// Overwrites a `ReplayInput` singleton with the frames of a replay, one per frame
+pub struct ReplayPlayer<S: ReplayInput> {
+    replay: Replay<S::Snapshot>,
+    next: usize,
+}
+
+impl<S: ReplayInput> ReplayPlayer<S> {
+    /// Seeds `BufferedRng` and starts playing, replacing any replay in progress
+    pub fn install(universe: &Universe, replay: Replay<S::Snapshot>) {
+        BufferedRng::set_seed(replay.seed);
+        universe.queue_set_singleton(Self { replay, next: 0 });
+    }
+
+    /// How many frames have been restored
+    pub fn position(&self) -> usize {
+        self.next
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.replay.frames.len()
+    }
+
+    pub fn replay(&self) -> &Replay<S::Snapshot> {
+        &self.replay
+    }
+}
+
+impl<S: ReplayInput> Singleton for ReplayPlayer<S> {
+    /// Flushed in the same stage as a `ReplayRecorder`, so frames line up with how they were captured
+    fn flush_stage(&self) -> FlushStage {
+        FlushStage::PreRender
+    }
+
+    fn flush(&mut self, universe: &Universe) {
+        let Some(snapshot) = self.replay.frames.get(self.next).cloned() else {
+            return;
+        };
+        self.next += 1;
+        let finished = self.is_finished();
+        // Restored after every singleton has flushed, so that the devices cannot overwrite it
+        universe.queue_exclusive(move |universe| {
+            if let Some(input) = universe.try_get_singleton_mut::<S>() {
+                input.restore(snapshot);
+            }
+            if finished {
+                universe.send_event(ReplayFinished);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bytes_round_trip() {
+        let replay = Replay {
+            seed: 7,
+            delta: Duration::from_millis(16),
+            frames: vec![[true, false], [false, true]],
+        };
+        let decoded = Replay::<[bool; 2]>::from_bytes(&replay.to_bytes().unwrap()).unwrap();
+        assert_eq!(decoded.seed, 7);
+        assert_eq!(decoded.delta, replay.delta);
+        assert_eq!(decoded.frames, replay.frames);
+        assert!(matches!(
+            Replay::<[bool; 2]>::from_bytes(b"BINASAVE"),
+            Err(ReplayError::NotAReplay)
+        ));
+    }
+}
@@ -1,63 +1,353 @@
-use std::sync::{atomic::AtomicUsize, OnceLock};
+use std::{
+    cell::{Cell, RefCell},
+    f32::consts::TAU,
+    fmt::Display,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
-use crossbeam::queue::ArrayQueue;
-use parking_lot::Mutex;
-use rand::{rngs::SmallRng, RngCore, SeedableRng};
-use rand_core::impls::fill_bytes_via_next;
+use crossbeam::atomic::AtomicCell;
+use rand::{
+    distributions::uniform::{SampleRange, SampleUniform},
+    rngs::SmallRng,
+    seq::SliceRandom,
+    Rng, RngCore, SeedableRng,
+};
 
-static RANDOM_BYTES_LEN: AtomicUsize = AtomicUsize::new(256);
+/// The seed set by `BufferedRng::set_seed`, or None to seed from entropy
+static SEED: AtomicCell<Option<u64>> = AtomicCell::new(None);
+/// Increased whenever the seed changes, so that every thread replaces its stream
+static GENERATION: AtomicUsize = AtomicUsize::new(0);
 
-static RANDOM: OnceLock<Mutex<SmallRng>> = OnceLock::new();
-static RANDOM_BYTES: OnceLock<ArrayQueue<u64>> = OnceLock::new();
+thread_local! {
+    static STREAM: RefCell<Option<(usize, SmallRng)>> = const { RefCell::new(None) };
+    /// The seed of the `seeded_pool` this thread belongs to, which takes the place of `SEED`
+    static POOL_SEED: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+/// Makes a thread pool whose threads generate numbers from `seed`, no matter what
+/// seed is set for every other thread, so that tests running at once cannot reseed each other
+pub(crate) fn seeded_pool(seed: u64) -> rayon::ThreadPool {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(rayon::current_num_threads())
+        .start_handler(move |_| POOL_SEED.with(|x| x.set(Some(seed))))
+        .build()
+        .expect("Failed to start a seeded thread pool")
+}
+
+/// Makes a new stream for the current thread
+fn new_stream() -> SmallRng {
+    match POOL_SEED.with(Cell::get).or(SEED.load()) {
+        // Each thread in the rayon pool always gets the same stream for a given seed.
+        // Every other thread, such as the main thread, shares the first stream
+        Some(seed) => {
+            let stream = rayon::current_thread_index().map_or(0, |x| x as u64 + 1);
+            SmallRng::seed_from_u64(seed ^ stream.wrapping_mul(0x9E37_79B9_7F4A_7C15))
+        }
+        None => SmallRng::from_entropy(),
+    }
+}
+
+fn with_stream<T>(f: impl FnOnce(&mut SmallRng) -> T) -> T {
+    STREAM.with(|stream| {
+        let mut stream = stream.borrow_mut();
+        // The streams of a seeded pool are never replaced
+        let generation = match POOL_SEED.with(Cell::get) {
+            Some(_) => 0,
+            None => GENERATION.load(Ordering::Acquire),
+        };
+        if !matches!(&*stream, Some((x, _)) if *x == generation) {
+            *stream = Some((generation, new_stream()));
+        }
+        f(&mut stream.as_mut().unwrap().1)
+    })
+}
 
 /// An extension to `rand`'s `SmallRng` that has better throughput for multithreaded code
 ///
-/// This type costs nothing to instantiate, and generating a `u64` only requires popping
-/// from a concurrent queue. If the queue is empty when popping, it will be filled with
-/// `u64`s again. The underlying `SmallRng` is only instantiated once using the system
-/// entropy during the first usage of `BufferedRng`.
+/// This type costs nothing to instantiate. Every thread has its own `SmallRng`,
+/// so generating numbers never waits on other threads. Each is seeded using the
+/// system entropy the first time it is used on that thread, unless `set_seed` was called
 #[derive(Clone, Copy)]
 pub struct BufferedRng;
 
 impl BufferedRng {
-    /// Sets the size of the buffer of random numbers
+    /// Formerly set the size of the buffer shared by every thread
+    #[deprecated(note = "Every thread now has its own generator, so there is no buffer to size")]
+    pub fn set_buffer_size(_size: usize) {}
+
+    /// Makes every thread generate numbers from `seed` from now on
     ///
-    /// Should be called as early as possible
+    /// A thread always generates the same numbers after the same seed is set, which makes
+    /// single threaded runs reproducible. rayon may hand work to different threads in each run,
+    /// so which entity gets which numbers while processing can still change between runs
+    pub fn set_seed(seed: u64) {
+        SEED.store(Some(seed));
+        GENERATION.fetch_add(1, Ordering::Release);
+    }
+
+    /// Undoes `set_seed`, seeding every thread using the system entropy again
+    pub fn seed_from_entropy() {
+        SEED.store(None);
+        GENERATION.fetch_add(1, Ordering::Release);
+    }
+
+    /// Generates a number in the given range, such as `0..10` or `-1.0..=1.0`
     ///
     /// # Panics
-    /// If `BufferedRNG` has already been used, this function will panic.
-    /// As such, there is no way to change the buffer size after the first usage
-    pub fn set_buffer_size(size: usize) {
-        assert!(!RANDOM_BYTES.get().is_none());
-        RANDOM_BYTES_LEN.store(size, std::sync::atomic::Ordering::Relaxed);
+    /// Panics if the range is empty
+    pub fn range<T: SampleUniform, R: SampleRange<T>>(self, range: R) -> T {
+        with_stream(|rng| rng.gen_range(range))
+    }
+
+    /// Returns true with the given probability, which is clamped between 0 and 1
+    pub fn chance(self, probability: f32) -> bool {
+        with_stream(|rng| rng.gen::<f32>() < probability)
+    }
+
+    /// Returns true with the probability of something that happens with
+    /// `probability` every second happening within `delta` seconds
+    ///
+    /// Unlike calling `chance` every frame, this happens just as often
+    /// no matter the frame rate
+    pub fn chance_per_second(self, probability: f32, delta: f32) -> bool {
+        let probability = probability.clamp(0.0, 1.0);
+        self.chance(1.0 - (1.0 - probability).powf(delta))
+    }
+
+    /// Picks a random item from the slice, or None if it is empty
+    pub fn pick_from_slice<T>(self, slice: &[T]) -> Option<&T> {
+        with_stream(|rng| slice.choose(rng))
+    }
+
+    /// Generates a vector with a length of 1 pointing in a random direction, as `[x, y]`
+    pub fn unit_vector(self) -> [f32; 2] {
+        let (sin, cos) = self.range(0.0..TAU).sin_cos();
+        [cos, sin]
     }
 }
 
 impl RngCore for BufferedRng {
     fn next_u32(&mut self) -> u32 {
-        (self.next_u64() >> 32) as u32
+        with_stream(|rng| rng.next_u32())
     }
 
     fn next_u64(&mut self) -> u64 {
-        let len = RANDOM_BYTES_LEN.load(std::sync::atomic::Ordering::Relaxed);
-        let queue = RANDOM_BYTES.get_or_init(|| ArrayQueue::new(len));
-        queue.pop().unwrap_or_else(|| {
-            let mut lock = RANDOM
-                .get_or_init(|| Mutex::new(SmallRng::from_entropy()))
-                .lock();
-            for _i in 0..len {
-                unsafe { queue.push(lock.next_u64()).unwrap_unchecked() };
-            }
-            lock.next_u64()
-        })
+        with_stream(|rng| rng.next_u64())
     }
 
     fn fill_bytes(&mut self, dest: &mut [u8]) {
-        fill_bytes_via_next(self, dest);
+        with_stream(|rng| rng.fill_bytes(dest));
     }
 
     fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
-        fill_bytes_via_next(self, dest);
-        Ok(())
+        with_stream(|rng| rng.try_fill_bytes(dest))
+    }
+}
+
+/// A collection of items that are picked at random in proportion to their weights,
+/// such as a drop table or the enemies that can spawn in an area
+///
+/// Picking an item takes the same time no matter how many items there are, as
+/// it uses the alias method. With the `serde` feature, tables can be loaded from
+/// a list of items and their weights, such as `[("sword", 1.0), ("gold", 10.0)]` in RON
+#[derive(Clone, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(
+        try_from = "Vec<(T, f32)>",
+        bound(deserialize = "T: serde::Deserialize<'de>")
+    )
+)]
+pub struct WeightedTable<T> {
+    items: Vec<T>,
+    weights: Vec<f32>,
+    /// The chance of keeping each item when it is rolled, instead of taking its alias
+    probabilities: Vec<f32>,
+    aliases: Vec<usize>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum WeightedTableError {
+    /// There are no items, or all of them have a weight of 0
+    Empty,
+    /// A weight was negative, infinite or NaN
+    InvalidWeight(f32),
+}
+
+impl Display for WeightedTableError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Empty => write!(f, "weighted table has nothing to pick"),
+            Self::InvalidWeight(weight) => write!(f, "{weight} is not a valid weight"),
+        }
+    }
+}
+
+impl std::error::Error for WeightedTableError {}
+
+impl<T> WeightedTable<T> {
+    pub fn new(entries: impl IntoIterator<Item = (T, f32)>) -> Result<Self, WeightedTableError> {
+        let (items, weights): (Vec<T>, Vec<f32>) = entries.into_iter().unzip();
+        if let Some(&weight) = weights.iter().find(|x| !x.is_finite() || **x < 0.0) {
+            return Err(WeightedTableError::InvalidWeight(weight));
+        }
+        let total: f32 = weights.iter().sum();
+        if total <= 0.0 {
+            return Err(WeightedTableError::Empty);
+        }
+
+        // Vose's alias method. Each slot is split between its own item and one alias,
+        // so that every slot holds the same total weight
+        let len = items.len();
+        let mut probabilities: Vec<f32> = weights.iter().map(|x| x * len as f32 / total).collect();
+        let mut aliases: Vec<usize> = (0..len).collect();
+        let (mut small, mut large): (Vec<usize>, Vec<usize>) =
+            (0..len).partition(|&i| probabilities[i] < 1.0);
+        // The smallest are aliased first, so that items with no weight are never
+        // left over because of rounding errors
+        small.sort_unstable_by(|a, b| probabilities[*b].total_cmp(&probabilities[*a]));
+        while let (Some(&less), Some(&more)) = (small.last(), large.last()) {
+            small.pop();
+            aliases[less] = more;
+            probabilities[more] -= 1.0 - probabilities[less];
+            if probabilities[more] < 1.0 {
+                large.pop();
+                small.push(more);
+            }
+        }
+        // Whatever is left is only short of 1 because of rounding errors
+        for i in small.into_iter().chain(large) {
+            probabilities[i] = 1.0;
+        }
+
+        Ok(Self {
+            items,
+            weights,
+            probabilities,
+            aliases,
+        })
+    }
+
+    /// Picks an item using `BufferedRng`
+    pub fn sample(&self) -> &T {
+        self.sample_with(&mut BufferedRng)
+    }
+
+    /// Picks an item using the given random number generator
+    pub fn sample_with(&self, rng: &mut impl Rng) -> &T {
+        let slot = rng.gen_range(0..self.items.len());
+        if rng.gen::<f32>() < self.probabilities[slot] {
+            &self.items[slot]
+        } else {
+            &self.items[self.aliases[slot]]
+        }
+    }
+
+    /// Gets every item and its weight, in the order they were given
+    pub fn iter(&self) -> impl Iterator<Item = (&T, f32)> + '_ {
+        self.items.iter().zip(self.weights.iter().copied())
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Always false, as a table cannot be made without items
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+impl<T> TryFrom<Vec<(T, f32)>> for WeightedTable<T> {
+    type Error = WeightedTableError;
+
+    fn try_from(value: Vec<(T, f32)>) -> Result<Self, Self::Error> {
+        Self::new(value)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T: serde::Serialize> serde::Serialize for WeightedTable<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Held by tests that set the seed of every thread, so that they do not reseed each other
+    static GLOBAL_SEED: parking_lot::Mutex<()> = parking_lot::Mutex::new(());
+
+    #[test]
+    fn seeded_streams_repeat() {
+        let _seed = GLOBAL_SEED.lock();
+        let generate = || {
+            std::thread::spawn(|| (0..8).map(|_| BufferedRng.next_u64()).collect::<Vec<_>>())
+                .join()
+                .unwrap()
+        };
+        BufferedRng::set_seed(42);
+        let first = generate();
+        assert_eq!(first, generate());
+        BufferedRng::set_seed(43);
+        assert_ne!(first, generate());
+        BufferedRng::seed_from_entropy();
+
+        let [x, y] = BufferedRng.unit_vector();
+        assert!((x.hypot(y) - 1.0).abs() < 1e-5);
+        assert!(!BufferedRng.chance_per_second(0.0, 1.0));
+        assert!(BufferedRng.chance_per_second(1.0, 0.5));
+        assert_eq!(BufferedRng.pick_from_slice::<u8>(&[]), None);
+    }
+
+    #[test]
+    fn seeded_pools_ignore_other_seeds() {
+        let _seed = GLOBAL_SEED.lock();
+        let generate = |pool: &rayon::ThreadPool| pool.broadcast(|_| BufferedRng.next_u64());
+        let first = seeded_pool(42);
+        let second = seeded_pool(42);
+        let frames = [generate(&first), generate(&first)];
+        // Reseeding every other thread does not restart the streams of either pool
+        BufferedRng::set_seed(43);
+        assert_eq!(frames, [generate(&second), generate(&second)]);
+        assert_ne!(frames[0], generate(&seeded_pool(43)));
+        BufferedRng::seed_from_entropy();
+    }
+
+    #[test]
+    fn weighted_tables_follow_their_weights() {
+        let table = WeightedTable::new([("common", 3.0), ("never", 0.0), ("rare", 1.0)]).unwrap();
+        let mut rng = SmallRng::seed_from_u64(7);
+        let mut common = 0;
+        for _ in 0..10_000 {
+            match *table.sample_with(&mut rng) {
+                "common" => common += 1,
+                "never" => panic!("An item with no weight was picked"),
+                _ => {}
+            }
+        }
+        assert!((7_000..8_000).contains(&common), "{common}");
+
+        assert_eq!(
+            WeightedTable::new([("a", 0.0)]).unwrap_err(),
+            WeightedTableError::Empty
+        );
+        assert!(WeightedTable::<()>::new([]).is_err());
+        assert!(WeightedTable::new([("a", -1.0)]).is_err());
+        assert!(WeightedTable::new([("a", f32::NAN)]).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn weighted_tables_load_from_ron() {
+        let table: WeightedTable<String> =
+            ron::from_str(r#"[("sword", 1.0), ("gold", 10.0)]"#).unwrap();
+        assert_eq!(table.len(), 2);
+        let again: WeightedTable<String> = ron::from_str(&ron::to_string(&table).unwrap()).unwrap();
+        assert!(again.iter().eq(table.iter()));
+        assert!(ron::from_str::<WeightedTable<String>>("[]").is_err());
     }
 }
@@ -0,0 +1,425 @@
+//! Rollback netcode, in the style of GGPO
+//!
+//! Every peer simulates the game with the inputs it has, predicting that remote players
+//! keep pressing whatever they last pressed. When a remote input arrives that does not
+//! match the prediction, the game is restored to the frame it was for and simulated again
+//! up to the current frame, all before the next frame is drawn.
+//!
+//! The session does no networking itself. Send the inputs returned by
+//! `RollbackSession::add_local_input` to every peer, and give the inputs they
+//! send back to `RollbackSession::add_remote_input`. The game must be deterministic,
+//! and advance by the same fixed step every frame
+use std::{collections::VecDeque, fmt::Display};
+
+/// A game that can be saved, restored and advanced by one frame at a time
+pub trait RollbackGame {
+    type Input: Copy + PartialEq + Default;
+    type State;
+
+    fn save(&self) -> Self::State;
+    fn load(&mut self, state: &Self::State);
+    /// Advances the game by one frame, given the input of every player in order
+    fn advance(&mut self, inputs: &[Self::Input]);
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RollbackError {
+    /// The game has predicted as many frames as it is allowed to, so it must
+    /// wait for remote inputs before advancing
+    PredictionThreshold,
+    /// Inputs must be added in order. The missing inputs should be sent again
+    MissingInputs {
+        player: usize,
+        expected: u32,
+        received: u32,
+    },
+    InvalidPlayer(usize),
+}
+
+impl Display for RollbackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::PredictionThreshold => write!(f, "too many frames have been predicted"),
+            Self::MissingInputs {
+                player,
+                expected,
+                received,
+            } => write!(
+                f,
+                "expected the input of player {player} for frame {expected}, but received frame {received}"
+            ),
+            Self::InvalidPlayer(player) => write!(f, "there is no player {player}"),
+        }
+    }
+}
+
+impl std::error::Error for RollbackError {}
+
+struct Frame<G: RollbackGame> {
+    /// The state of the game before this frame was simulated
+    state: Option<G::State>,
+    /// The inputs received for this frame
+    confirmed: Vec<Option<G::Input>>,
+    /// The inputs this frame was last simulated with
+    simulated: Vec<G::Input>,
+}
+
+/// Keeps a game in sync with remote peers by predicting their inputs and rolling back when wrong
+///
+/// ```ignore
+/// let mut session = RollbackSession::new(2, 0).with_input_delay(2);
+/// // Every fixed step
+/// let (frame, input) = session.add_local_input(read_input())?;
+/// socket.send(&encode(frame, input));
+/// for (frame, input) in received_packets() {
+///     session.add_remote_input(1, frame, input)?;
+/// }
+/// match session.advance_frame(&mut game) {
+///     Err(RollbackError::PredictionThreshold) => {} // Skip this step to let the peer catch up
+///     x => x?,
+/// }
+/// ```
+pub struct RollbackSession<G: RollbackGame> {
+    local_player: usize,
+    input_delay: u32,
+    max_prediction: u32,
+    current_frame: u32,
+    /// The first frame in `frames`
+    base_frame: u32,
+    frames: VecDeque<Frame<G>>,
+    /// The next frame each player has not sent an input for
+    next_input_frame: Vec<u32>,
+    /// The latest input of each player, which is repeated as the prediction of their later inputs
+    latest_input: Vec<G::Input>,
+    /// The earliest frame that was simulated with a wrong prediction
+    first_incorrect_frame: Option<u32>,
+    rollbacks: u32,
+}
+
+impl<G: RollbackGame> RollbackSession<G> {
+    pub fn new(players: usize, local_player: usize) -> Self {
+        assert!(
+            local_player < players,
+            "The local player must be one of the players"
+        );
+        Self {
+            local_player,
+            input_delay: 0,
+            max_prediction: 8,
+            current_frame: 0,
+            base_frame: 0,
+            frames: VecDeque::new(),
+            next_input_frame: vec![0; players],
+            latest_input: vec![G::Input::default(); players],
+            first_incorrect_frame: None,
+            rollbacks: 0,
+        }
+    }
+
+    /// Delays local inputs by the given number of frames, which hides that much latency
+    /// without rolling back. Every peer must use the same delay
+    ///
+    /// The inputs of every player during the first `frames` frames are the default input
+    pub fn with_input_delay(mut self, frames: u32) -> Self {
+        debug_assert_eq!(
+            self.current_frame, 0,
+            "The delay must be set before the session starts"
+        );
+        for frame in self.input_delay..frames {
+            self.frame_mut(frame)
+                .confirmed
+                .fill(Some(G::Input::default()));
+        }
+        self.input_delay = frames;
+        self.next_input_frame.fill(frames);
+        self
+    }
+
+    /// Sets how many frames can be simulated past the last frame with every input
+    ///
+    /// Defaults to 8
+    pub fn with_max_prediction(mut self, frames: u32) -> Self {
+        self.max_prediction = frames;
+        self
+    }
+
+    fn frame_mut(&mut self, frame: u32) -> &mut Frame<G> {
+        let index = (frame - self.base_frame) as usize;
+        while self.frames.len() <= index {
+            let players = self.next_input_frame.len();
+            self.frames.push_back(Frame {
+                state: None,
+                confirmed: vec![None; players],
+                simulated: Vec::with_capacity(players),
+            });
+        }
+        &mut self.frames[index]
+    }
+
+    /// The next frame that will be simulated
+    pub fn current_frame(&self) -> u32 {
+        self.current_frame
+    }
+
+    /// The first frame that is missing the input of any player.
+    /// Every frame before this one is final and will never be rolled back
+    pub fn confirmed_frame(&self) -> u32 {
+        self.next_input_frame.iter().copied().min().unwrap_or(0)
+    }
+
+    /// How many times the game has been rolled back, useful for tuning the input delay
+    pub fn rollbacks(&self) -> u32 {
+        self.rollbacks
+    }
+
+    /// Adds the input of the local player, which is applied after the input delay.
+    /// This must be called once before every call to `advance_frame`
+    ///
+    /// Returns the frame the input is for, which should be sent to every peer along with it
+    pub fn add_local_input(&mut self, input: G::Input) -> Result<(u32, G::Input), RollbackError> {
+        let frame = self.current_frame + self.input_delay;
+        let local_player = self.local_player;
+        self.add_input(local_player, frame, input)?;
+        // Only the first input added for a frame is kept
+        let input = self.frame_mut(frame).confirmed[local_player].unwrap_or(input);
+        Ok((frame, input))
+    }
+
+    /// Adds the input a remote player sent for the given frame. Inputs that were already added are ignored
+    pub fn add_remote_input(
+        &mut self,
+        player: usize,
+        frame: u32,
+        input: G::Input,
+    ) -> Result<(), RollbackError> {
+        if player == self.local_player {
+            return Err(RollbackError::InvalidPlayer(player));
+        }
+        self.add_input(player, frame, input)
+    }
+
+    fn add_input(
+        &mut self,
+        player: usize,
+        frame: u32,
+        input: G::Input,
+    ) -> Result<(), RollbackError> {
+        let Some(&expected) = self.next_input_frame.get(player) else {
+            return Err(RollbackError::InvalidPlayer(player));
+        };
+        if frame < expected {
+            return Ok(());
+        }
+        if frame > expected {
+            return Err(RollbackError::MissingInputs {
+                player,
+                expected,
+                received: frame,
+            });
+        }
+
+        let current_frame = self.current_frame;
+        let stored = self.frame_mut(frame);
+        stored.confirmed[player] = Some(input);
+        if frame < current_frame && stored.simulated[player] != input {
+            let first = self.first_incorrect_frame.get_or_insert(frame);
+            *first = (*first).min(frame);
+        }
+        self.next_input_frame[player] = frame + 1;
+        self.latest_input[player] = input;
+        Ok(())
+    }
+
+    fn simulate(&mut self, game: &mut G, frame: u32) {
+        let state = game.save();
+        let latest_input = std::mem::take(&mut self.latest_input);
+        let stored = self.frame_mut(frame);
+        stored.state = Some(state);
+        stored.simulated.clear();
+        stored.simulated.extend(
+            stored
+                .confirmed
+                .iter()
+                .zip(&latest_input)
+                .map(|(confirmed, latest)| confirmed.unwrap_or(*latest)),
+        );
+        game.advance(&stored.simulated);
+        self.latest_input = latest_input;
+    }
+
+    /// Rolls back if a prediction was wrong, then simulates the current frame
+    ///
+    /// If too many frames have been predicted, nothing is simulated and
+    /// `RollbackError::PredictionThreshold` is returned
+    pub fn advance_frame(&mut self, game: &mut G) -> Result<(), RollbackError> {
+        if let Some(first_incorrect) = self.first_incorrect_frame.take() {
+            let state = self.frames[(first_incorrect - self.base_frame) as usize]
+                .state
+                .as_ref()
+                .expect("Simulated frames should have a saved state");
+            game.load(state);
+            for frame in first_incorrect..self.current_frame {
+                self.simulate(game, frame);
+            }
+            self.rollbacks += 1;
+        }
+
+        if self.current_frame >= self.confirmed_frame() + self.max_prediction {
+            return Err(RollbackError::PredictionThreshold);
+        }
+        self.simulate(game, self.current_frame);
+        self.current_frame += 1;
+
+        // Confirmed frames can never be rolled back to, so they are forgotten
+        let keep_from = self.confirmed_frame().min(self.current_frame);
+        while self.base_frame < keep_from {
+            self.frames.pop_front();
+            self.base_frame += 1;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Mixes every input into a hash, so that any wrong input changes the state
+    #[derive(Clone, Default, PartialEq, Debug)]
+    struct Game {
+        frame: u32,
+        hash: u64,
+    }
+
+    impl RollbackGame for Game {
+        type Input = u8;
+        type State = Self;
+
+        fn save(&self) -> Self {
+            self.clone()
+        }
+
+        fn load(&mut self, state: &Self) {
+            *self = state.clone();
+        }
+
+        fn advance(&mut self, inputs: &[u8]) {
+            for &input in inputs {
+                self.hash = self.hash.wrapping_mul(31).wrapping_add(input as u64);
+            }
+            self.frame += 1;
+        }
+    }
+
+    fn simulate(inputs: &[[u8; 2]]) -> Game {
+        let mut game = Game::default();
+        for frame in inputs {
+            game.advance(frame);
+        }
+        game
+    }
+
+    #[test]
+    fn corrects_misprediction() {
+        let remote = [0, 0, 3, 3, 7, 7, 7];
+        let mut session = RollbackSession::<Game>::new(2, 0);
+        let mut game = Game::default();
+        for frame in 0..6 {
+            session.add_local_input(1).unwrap();
+            // The inputs for frame 2 onwards arrive late
+            if frame < 2 {
+                session.add_remote_input(1, frame, remote[frame as usize]).unwrap();
+            }
+            session.advance_frame(&mut game).unwrap();
+        }
+        assert_eq!(session.rollbacks(), 0);
+        assert_eq!(session.confirmed_frame(), 2);
+        // Predicted by repeating the last input of the remote player
+        assert_eq!(game, simulate(&[[1, 0]; 6]));
+
+        for frame in 2..7 {
+            session.add_remote_input(1, frame, remote[frame as usize]).unwrap();
+        }
+        session.add_local_input(1).unwrap();
+        session.advance_frame(&mut game).unwrap();
+        assert_eq!(session.rollbacks(), 1);
+        assert_eq!(session.current_frame(), 7);
+        assert_eq!(session.confirmed_frame(), 7);
+        assert_eq!(game, simulate(&remote.map(|x| [1, x])));
+    }
+
+    #[test]
+    fn correct_prediction_does_not_roll_back() {
+        let mut session = RollbackSession::<Game>::new(2, 1);
+        let mut game = Game::default();
+        session.add_remote_input(0, 0, 4).unwrap();
+        for _ in 0..4 {
+            session.add_local_input(2).unwrap();
+            session.advance_frame(&mut game).unwrap();
+        }
+        for frame in 1..4 {
+            session.add_remote_input(0, frame, 4).unwrap();
+        }
+        session.add_local_input(2).unwrap();
+        session.advance_frame(&mut game).unwrap();
+        assert_eq!(session.rollbacks(), 0);
+        assert_eq!(game, simulate(&[[4, 2]; 5]));
+    }
+
+    #[test]
+    fn waits_at_prediction_threshold() {
+        let mut session = RollbackSession::<Game>::new(2, 0).with_max_prediction(2);
+        let mut game = Game::default();
+        for _ in 0..2 {
+            session.add_local_input(1).unwrap();
+            session.advance_frame(&mut game).unwrap();
+        }
+        session.add_local_input(1).unwrap();
+        assert_eq!(
+            session.advance_frame(&mut game),
+            Err(RollbackError::PredictionThreshold)
+        );
+        assert_eq!(game.frame, 2);
+
+        session.add_remote_input(1, 0, 5).unwrap();
+        session.advance_frame(&mut game).unwrap();
+        assert_eq!(session.rollbacks(), 1);
+        assert_eq!(game, simulate(&[[1, 5]; 3]));
+    }
+
+    #[test]
+    fn input_delay() {
+        let mut session = RollbackSession::<Game>::new(2, 0).with_input_delay(2);
+        let mut game = Game::default();
+        assert_eq!(session.add_local_input(1), Ok((2, 1)));
+        session.add_remote_input(1, 2, 3).unwrap();
+        session.advance_frame(&mut game).unwrap();
+        assert_eq!(session.add_local_input(1), Ok((3, 1)));
+        session.advance_frame(&mut game).unwrap();
+        session.add_local_input(1).unwrap();
+        session.advance_frame(&mut game).unwrap();
+        assert_eq!(session.rollbacks(), 0);
+        assert_eq!(game, simulate(&[[0, 0], [0, 0], [1, 3]]));
+    }
+
+    #[test]
+    fn rejects_out_of_order_inputs() {
+        let mut session = RollbackSession::<Game>::new(2, 0);
+        assert_eq!(
+            session.add_remote_input(1, 1, 0),
+            Err(RollbackError::MissingInputs {
+                player: 1,
+                expected: 0,
+                received: 1
+            })
+        );
+        assert_eq!(
+            session.add_remote_input(0, 0, 0),
+            Err(RollbackError::InvalidPlayer(0))
+        );
+        assert_eq!(
+            session.add_remote_input(2, 0, 0),
+            Err(RollbackError::InvalidPlayer(2))
+        );
+    }
+}
@@ -0,0 +1,492 @@
+//! Saving and loading games from the platform's save directory
+//!
+//! A save is a set of named sections, each holding any serializable value, such as the
+//! fields of a component or a whole singleton. Sections are serialized separately so
+//! that a migration can rewrite one without understanding the others
+use std::{
+    any::type_name,
+    collections::BTreeMap,
+    fmt::Display,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    pack::PackCompression,
+    singleton::Singleton,
+    universe::Universe,
+    worker::{ExecResult, MutabilityAccess, Worker, WorkerMessage},
+};
+
+const MAGIC: &[u8; 8] = b"BINASAVE";
+const EXTENSION: &str = "save";
+
+#[derive(Debug)]
+pub enum SaveError {
+    /// The file does not start with the header of a save
+    NotASave,
+    /// The contents of the file do not match the checksum in its header
+    Corrupt,
+    /// The save was written by a newer version of the game
+    UnsupportedVersion(u32),
+    /// There is no migration from the given version to the next one
+    MissingMigration(u32),
+    Migration(String),
+    /// Slot names must be non empty and cannot contain `/`, `\` or `..`
+    InvalidSlot(String),
+    Serialization(String),
+    Io(io::Error),
+}
+
+impl Display for SaveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotASave => write!(f, "not a save file"),
+            Self::Corrupt => write!(f, "save is corrupt"),
+            Self::UnsupportedVersion(version) => write!(f, "unsupported save version {version}"),
+            Self::MissingMigration(version) => {
+                write!(f, "no migration from save version {version}")
+            }
+            Self::Migration(e) => write!(f, "failed to migrate save: {e}"),
+            Self::InvalidSlot(slot) => write!(f, "{slot:?} is not a valid save slot"),
+            Self::Serialization(e) => write!(f, "{e}"),
+            Self::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {}
+
+impl From<io::Error> for SaveError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<bincode::Error> for SaveError {
+    fn from(value: bincode::Error) -> Self {
+        Self::Serialization(value.to_string())
+    }
+}
+
+/// The contents of a save, as named sections of serialized values
+///
+/// ```ignore
+/// let mut save = SaveGame::new();
+/// save.insert("player", &PlayerSave { health: player.health.get(), position })?;
+/// save.insert_singleton::<Inventory>(universe)?;
+/// universe.get_singleton::<SaveStore>().save("slot1", save)?;
+/// ```
+#[derive(Default, Serialize, Deserialize)]
+pub struct SaveGame {
+    version: u32,
+    sections: BTreeMap<String, Vec<u8>>,
+}
+
+impl SaveGame {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The version of the game that wrote this save. After loading, this is always the current version
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn insert<T: Serialize>(
+        &mut self,
+        key: impl Into<String>,
+        value: &T,
+    ) -> Result<(), SaveError> {
+        self.sections.insert(key.into(), bincode::serialize(value)?);
+        Ok(())
+    }
+
+    /// Gets the value of a section, or `None` if the save does not have it
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, SaveError> {
+        self.sections
+            .get(key)
+            .map(|bytes| bincode::deserialize(bytes))
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.sections.contains_key(key)
+    }
+
+    pub fn remove(&mut self, key: &str) -> bool {
+        self.sections.remove(key).is_some()
+    }
+
+    /// Iterates over the name of every section
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.sections.keys().map(String::as_str)
+    }
+
+    /// Saves a singleton in a section named after its type
+    pub fn insert_singleton<T: Singleton + Serialize>(
+        &mut self,
+        universe: &Universe,
+    ) -> Result<(), SaveError> {
+        self.insert(type_name::<T>(), universe.get_singleton::<T>())
+    }
+
+    /// Replaces a singleton with the one in this save when the process frame ends
+    ///
+    /// Returns false if the save does not have the singleton
+    pub fn restore_singleton<T: Singleton + DeserializeOwned>(
+        &self,
+        universe: &Universe,
+    ) -> Result<bool, SaveError> {
+        let Some(singleton) = self.get::<T>(type_name::<T>())? else {
+            return Ok(false);
+        };
+        universe.queue_set_singleton(singleton);
+        Ok(true)
+    }
+
+    fn encode(&self, compression: PackCompression) -> Result<Vec<u8>, SaveError> {
+        let payload = bincode::serialize(self)?;
+        let mut bytes = Vec::with_capacity(payload.len() + 13);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+        match compression {
+            PackCompression::None => {
+                bytes.push(0);
+                bytes.extend_from_slice(&payload);
+            }
+            PackCompression::Deflate => {
+                bytes.push(1);
+                let mut encoder = DeflateEncoder::new(bytes, Compression::default());
+                encoder.write_all(&payload)?;
+                bytes = encoder.finish()?;
+            }
+            #[cfg(feature = "zstd")]
+            PackCompression::Zstd => {
+                bytes.push(2);
+                zstd::stream::copy_encode(payload.as_slice(), &mut bytes, 0)?;
+            }
+        }
+        Ok(bytes)
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, SaveError> {
+        if bytes.len() < 13 || &bytes[..8] != MAGIC {
+            return Err(SaveError::NotASave);
+        }
+        let crc = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+        let stored = &bytes[13..];
+        let payload = match bytes[12] {
+            0 => stored.to_vec(),
+            1 => {
+                let mut payload = Vec::new();
+                DeflateDecoder::new(stored)
+                    .read_to_end(&mut payload)
+                    .map_err(|_| SaveError::Corrupt)?;
+                payload
+            }
+            #[cfg(feature = "zstd")]
+            2 => zstd::decode_all(stored).map_err(|_| SaveError::Corrupt)?,
+            _ => return Err(SaveError::Corrupt),
+        };
+        if crc32fast::hash(&payload) != crc {
+            return Err(SaveError::Corrupt);
+        }
+        Ok(bincode::deserialize(&payload)?)
+    }
+}
+
+/// Sent once a save has been written to disk, or failed to be
+pub enum SaveEvent {
+    Saved(String),
+    Failed(String, SaveError),
+}
+
+enum SaveMessage {
+    Write {
+        slot: String,
+        path: PathBuf,
+        bytes: Vec<u8>,
+    },
+    Written {
+        slot: String,
+        result: Result<(), SaveError>,
+    },
+}
+
+impl WorkerMessage for SaveMessage {
+    type WorkerValue = ();
+    /// Only one thread writes saves, so that they are written in order
+    const MUTABILITY_ACCESS: MutabilityAccess = MutabilityAccess::Mutable;
+
+    fn exec_mut(self, _value: &mut Self::WorkerValue) -> ExecResult<Self> {
+        match self {
+            Self::Write { slot, path, bytes } => ExecResult::Some(Self::Written {
+                slot,
+                result: write_atomically(&path, &bytes).map_err(Into::into),
+            }),
+            Self::Written { .. } => ExecResult::None,
+        }
+    }
+}
+
+/// Writes to a temporary file first, so that a crash cannot leave a save half written
+fn write_atomically(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let temp = path.with_extension("tmp");
+    std::fs::write(&temp, bytes)?;
+    std::fs::rename(temp, path)
+}
+
+type Migration = Box<dyn Fn(&mut SaveGame) -> Result<(), String> + Send + Sync>;
+
+/// Writes saves in the background and loads them, migrating saves from older versions of the game
+///
+/// ```ignore
+/// universe.queue_set_singleton(
+///     SaveStore::new("my_game", 2)
+///         .with_compression(PackCompression::Deflate)
+///         .with_migration(1, |save| {
+///             let old: PlayerV1 = save.get("player").map_err(|e| e.to_string())?.unwrap_or_default();
+///             save.insert("player", &PlayerV2::from(old)).map_err(|e| e.to_string())
+///         }),
+/// );
+/// ```
+pub struct SaveStore {
+    dir: PathBuf,
+    version: u32,
+    compression: PackCompression,
+    /// Upgrades a save from the version it is keyed by to the next version
+    migrations: BTreeMap<u32, Migration>,
+    worker: Worker<SaveMessage>,
+}
+
+impl SaveStore {
+    /// Stores saves in a folder named after the game inside of the platform's data directory,
+    /// such as `%APPDATA%` on Windows or `~/.local/share` on Linux
+    ///
+    /// The version should be increased whenever the contents of saves change
+    pub fn new(game_name: &str, version: u32) -> Self {
+        let dir = dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join(game_name)
+            .join("saves");
+        Self::with_dir(dir, version)
+    }
+
+    /// Stores saves in `Steam::save_dir`, which is separate for every Steam user
+    /// and can be synced by Steam Auto-Cloud
+    #[cfg(feature = "steam")]
+    pub fn for_steam_user(game_name: &str, version: u32, steam: &crate::steam::Steam) -> Self {
+        Self::with_dir(steam.save_dir(game_name), version)
+    }
+
+    /// Stores saves in the given directory instead of the platform's data directory
+    pub fn with_dir(dir: impl Into<PathBuf>, version: u32) -> Self {
+        Self {
+            dir: dir.into(),
+            version,
+            compression: PackCompression::None,
+            migrations: BTreeMap::new(),
+            worker: Worker::spawn(()),
+        }
+    }
+
+    pub fn with_compression(mut self, compression: PackCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Adds a migration that upgrades saves from the given version to the next one.
+    /// Saves that are several versions old are upgraded by each migration in turn
+    pub fn with_migration(
+        mut self,
+        from_version: u32,
+        migration: impl Fn(&mut SaveGame) -> Result<(), String> + Send + Sync + 'static,
+    ) -> Self {
+        self.migrations.insert(from_version, Box::new(migration));
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Slots are file names, so they cannot lead outside of the save directory
+    fn path(&self, slot: &str) -> Result<PathBuf, SaveError> {
+        if slot.is_empty() || slot.contains(['/', '\\']) || slot.contains("..") {
+            return Err(SaveError::InvalidSlot(slot.into()));
+        }
+        Ok(self.dir.join(format!("{slot}.{EXTENSION}")))
+    }
+
+    /// Serializes the save now, and writes it in the background. A `SaveEvent` is sent once it is written
+    pub fn save(&self, slot: &str, mut game: SaveGame) -> Result<(), SaveError> {
+        game.version = self.version;
+        let path = self.path(slot)?;
+        let bytes = game.encode(self.compression)?;
+        self.worker.send(SaveMessage::Write {
+            slot: slot.into(),
+            path,
+            bytes,
+        });
+        Ok(())
+    }
+
+    /// Reads a save, migrating it to the current version if it is older
+    pub fn load(&self, slot: &str) -> Result<SaveGame, SaveError> {
+        let mut game = SaveGame::decode(&std::fs::read(self.path(slot)?)?)?;
+        if game.version > self.version {
+            return Err(SaveError::UnsupportedVersion(game.version));
+        }
+        while game.version < self.version {
+            let migration = self
+                .migrations
+                .get(&game.version)
+                .ok_or(SaveError::MissingMigration(game.version))?;
+            migration(&mut game).map_err(SaveError::Migration)?;
+            game.version += 1;
+        }
+        Ok(game)
+    }
+
+    pub fn exists(&self, slot: &str) -> bool {
+        self.path(slot).is_ok_and(|x| x.is_file())
+    }
+
+    pub fn delete(&self, slot: &str) -> Result<(), SaveError> {
+        std::fs::remove_file(self.path(slot)?).map_err(Into::into)
+    }
+
+    /// Gets the name of every slot that has been saved
+    pub fn slots(&self) -> io::Result<Vec<String>> {
+        let mut slots = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|x| x == EXTENSION) {
+                if let Some(stem) = path.file_stem() {
+                    slots.push(stem.to_string_lossy().into_owned());
+                }
+            }
+        }
+        slots.sort();
+        Ok(slots)
+    }
+}
+
+impl Singleton for SaveStore {
+    fn process(&self, universe: &Universe) {
+        while let Some(message) = self.worker.try_recv() {
+            let SaveMessage::Written { slot, result } = message else {
+                continue;
+            };
+            universe.send_event(match result {
+                Ok(()) => SaveEvent::Saved(slot),
+                Err(e) => {
+                    log::error!("Failed to write save {slot}: {e}");
+                    SaveEvent::Failed(slot, e)
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bina_save_{name}_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    /// Saves through the worker and waits for the save to be written
+    fn save_and_wait(store: &SaveStore, slot: &str, game: SaveGame) {
+        store.save(slot, game).unwrap();
+        loop {
+            if let Some(SaveMessage::Written { result, .. }) = store.worker.try_recv() {
+                return result.unwrap();
+            }
+            std::thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn encode_decode() {
+        let mut game = SaveGame::new();
+        game.insert("health", &7u32).unwrap();
+        game.insert("name", &"hero".to_string()).unwrap();
+        for compression in [PackCompression::None, PackCompression::Deflate] {
+            let decoded = SaveGame::decode(&game.encode(compression).unwrap()).unwrap();
+            assert_eq!(decoded.get::<u32>("health").unwrap(), Some(7));
+            assert_eq!(decoded.get::<String>("name").unwrap().as_deref(), Some("hero"));
+            assert_eq!(decoded.get::<u32>("missing").unwrap(), None);
+            assert_eq!(decoded.keys().collect::<Vec<_>>(), ["health", "name"]);
+        }
+    }
+
+    #[test]
+    fn decode_rejects_damaged_saves() {
+        let mut game = SaveGame::new();
+        game.insert("health", &7u32).unwrap();
+        let mut bytes = game.encode(PackCompression::None).unwrap();
+        assert!(matches!(SaveGame::decode(&bytes[..12]), Err(SaveError::NotASave)));
+        assert!(matches!(SaveGame::decode(b"NOTASAVE00000"), Err(SaveError::NotASave)));
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        assert!(matches!(SaveGame::decode(&bytes), Err(SaveError::Corrupt)));
+    }
+
+    #[test]
+    fn rejects_invalid_slots() {
+        let store = SaveStore::with_dir(temp_dir("invalid"), 1);
+        for slot in ["", "../escape", "a/b", "a\\b", ".."] {
+            assert!(matches!(
+                store.save(slot, SaveGame::new()),
+                Err(SaveError::InvalidSlot(_))
+            ));
+            assert!(matches!(store.load(slot), Err(SaveError::InvalidSlot(_))));
+            assert!(!store.exists(slot));
+        }
+    }
+
+    #[test]
+    fn migrates_old_saves() {
+        let dir = temp_dir("migrate");
+        let old = SaveStore::with_dir(&dir, 1).with_compression(PackCompression::Deflate);
+        let mut game = SaveGame::new();
+        game.insert("health", &7u32).unwrap();
+        save_and_wait(&old, "slot.1", game);
+        assert!(old.exists("slot.1"));
+        assert_eq!(old.slots().unwrap(), ["slot.1"]);
+
+        let new = SaveStore::with_dir(&dir, 3)
+            .with_migration(1, |save| {
+                let health: u32 = save.get("health").map_err(|e| e.to_string())?.unwrap();
+                save.insert("health", &(health as f32)).map_err(|e| e.to_string())
+            })
+            .with_migration(2, |save| save.insert("mana", &1.5f32).map_err(|e| e.to_string()));
+        let game = new.load("slot.1").unwrap();
+        assert_eq!(game.version(), 3);
+        assert_eq!(game.get::<f32>("health").unwrap(), Some(7.0));
+        assert_eq!(game.get::<f32>("mana").unwrap(), Some(1.5));
+
+        let missing = SaveStore::with_dir(&dir, 2);
+        assert!(matches!(missing.load("slot.1"), Err(SaveError::MissingMigration(1))));
+        // A save from a newer version of the game
+        let bytes = game.encode(PackCompression::None).unwrap();
+        write_atomically(&new.path("slot.1").unwrap(), &bytes).unwrap();
+        assert!(matches!(old.load("slot.1"), Err(SaveError::UnsupportedVersion(3))));
+
+        new.delete("slot.1").unwrap();
+        assert!(!new.exists("slot.1"));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
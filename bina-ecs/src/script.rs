@@ -0,0 +1,537 @@
+//! Behaviors written in Lua, which can be changed without recompiling
+//!
+//! Scripts can read and change the fields of any component in an entity registered with
+//! `ReflectRegistry`, and handle events that were bridged with `ScriptHost::with_event`.
+//! Loaded through `Assets` with hot reloading, a script is run again whenever its file is saved
+//!
+//! ```lua
+//! -- Runs every frame
+//! function process(delta)
+//!     bina.for_each("Polygon", function(entity)
+//!         entity.Polygon.rotation = entity.Polygon.rotation + delta
+//!         -- Numbers with more than one component, such as vectors, are lists
+//!         entity.Polygon.origin[2] = math.sin(entity.Polygon.rotation) * 0.25
+//!     end)
+//! end
+//!
+//! bina.on("component_panicked", function(entity_type)
+//!     bina.log(entity_type .. " panicked")
+//! end)
+//! ```
+//!
+//! Every script has its own global variables, so two scripts can both define `process`.
+//! A script that errors is stopped until it is reloaded, and the error is logged and
+//! reported to `Diagnostics`
+use std::sync::Arc;
+
+pub use mlua;
+use mlua::{Function, IntoLua, Lua, RegistryKey, Table, Value};
+use parking_lot::Mutex;
+
+use crate::{
+    assets::{AssetLoader, Assets, Handle},
+    diagnostics::Diagnostics,
+    reflect::{Field, ReflectComponents, ReflectRegistry},
+    singleton::Singleton,
+    universe::Universe,
+};
+
+/// The source code of a Lua script
+pub struct Script {
+    source: String,
+}
+
+impl Script {
+    pub fn new(source: impl Into<String>) -> Self {
+        Self {
+            source: source.into(),
+        }
+    }
+}
+
+/// Loads `.lua` files as `Script`s
+#[derive(Default)]
+pub struct ScriptLoader;
+
+impl AssetLoader for ScriptLoader {
+    type Asset = Script;
+
+    fn load(&self, bytes: Vec<u8>) -> Result<Self::Asset, String> {
+        String::from_utf8(bytes)
+            .map(Script::new)
+            .map_err(|e| format!("Scripts must be UTF-8: {e}"))
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["lua"]
+    }
+}
+
+/// Makes the `bina.on` function of a script, which adds handlers to the given table
+const ON: &str = r#"
+local handlers = ...
+return function(event, handler)
+    local list = handlers[event]
+    if list == nil then
+        list = {}
+        handlers[event] = list
+    end
+    list[#list + 1] = handler
+end
+"#;
+
+struct LoadedScript {
+    /// The path of the script, or the name given to `with_source`
+    name: String,
+    /// `None` until `Assets` exists to load it
+    handle: Option<Handle<Script>>,
+    /// The script that was last run, to notice when it is hot reloaded
+    running: Option<Arc<Script>>,
+    /// The global variables of the script
+    env: Option<RegistryKey>,
+    /// Lists of functions given to `bina.on`, keyed by the name of their event
+    handlers: Option<RegistryKey>,
+    failed: bool,
+}
+
+struct ScriptState {
+    lua: Lua,
+    scripts: Vec<LoadedScript>,
+    /// The `bina` table that every script can read
+    api: RegistryKey,
+}
+
+type EventBridge =
+    Box<dyn for<'lua> Fn(&Universe, &'lua Lua) -> mlua::Result<Vec<Value<'lua>>> + Send + Sync>;
+
+/// Runs Lua scripts every frame
+///
+/// ```ignore
+/// universe.queue_set_singleton(
+///     Assets::new().with_root("assets").with_hot_reload().with_loader(ScriptLoader),
+/// );
+/// universe.queue_set_singleton(ReflectRegistry::new().with::<(Polygon,)>());
+/// universe.queue_set_singleton(
+///     ScriptHost::new()
+///         .with_script("scripts/spin.lua")
+///         .with_event("component_panicked", |event: &ComponentPanicked| event.entity_type),
+/// );
+/// ```
+pub struct ScriptHost {
+    state: Mutex<ScriptState>,
+    events: Vec<(&'static str, EventBridge)>,
+}
+
+impl ScriptHost {
+    pub fn new() -> Self {
+        let lua = Lua::new();
+        let api = lua
+            .create_table()
+            .and_then(|api| {
+                api.set(
+                    "log",
+                    lua.create_function(|_, message: String| {
+                        log::info!("{message}");
+                        Ok(())
+                    })?,
+                )?;
+                lua.create_registry_value(api)
+            })
+            .expect("Creating the script API should not fail");
+        Self {
+            state: Mutex::new(ScriptState {
+                lua,
+                scripts: Vec::new(),
+                api,
+            }),
+            events: Vec::new(),
+        }
+    }
+
+    /// Runs the script at the given path, which is loaded through the `Assets` singleton
+    /// once it exists. `Assets` must have a `ScriptLoader`
+    pub fn with_script(self, path: impl Into<String>) -> Self {
+        self.add(path.into(), None)
+    }
+
+    /// Runs a script that is already in memory, such as one embedded with `include_str!`
+    pub fn with_source(self, name: impl Into<String>, source: impl Into<String>) -> Self {
+        self.add(name.into(), Some(Handle::from_asset(Script::new(source))))
+    }
+
+    fn add(self, name: String, handle: Option<Handle<Script>>) -> Self {
+        self.state.lock().scripts.push(LoadedScript {
+            name,
+            handle,
+            running: None,
+            env: None,
+            handlers: None,
+            failed: false,
+        });
+        self
+    }
+
+    /// Calls the handlers that scripts gave to `bina.on(name, handler)` with every event
+    /// of type `T`, after it has been converted into a Lua value by `convert`
+    pub fn with_event<T, V>(
+        mut self,
+        name: &'static str,
+        convert: impl Fn(&T) -> V + Send + Sync + 'static,
+    ) -> Self
+    where
+        T: Send + Sync + 'static,
+        V: for<'lua> IntoLua<'lua>,
+    {
+        self.events.push((name, bridge(convert)));
+        self
+    }
+}
+
+impl Default for ScriptHost {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Boxes a function that converts every event of type `T` into Lua values
+fn bridge<T, V>(convert: impl Fn(&T) -> V + Send + Sync + 'static) -> EventBridge
+where
+    T: Send + Sync + 'static,
+    V: for<'lua> IntoLua<'lua>,
+{
+    Box::new(move |universe, lua| {
+        universe
+            .read_events::<T>()
+            .iter()
+            .map(|event| convert(event).into_lua(lua))
+            .collect()
+    })
+}
+
+/// Gets the name of a type without its path or generics, which is how scripts refer to components
+fn short_name(type_name: &str) -> &str {
+    let name = type_name.split('<').next().unwrap_or(type_name);
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+/// Calls `f` with a table of every entity that has the given component
+///
+/// Each table has the index of the entity, and a table of fields for each of its components.
+/// Numbers that `f` changes are written back to the entity
+fn for_each<'lua>(
+    lua: &'lua Lua,
+    universe: &Universe,
+    component: &str,
+    f: Function<'lua>,
+) -> mlua::Result<()> {
+    let Some(registry) = universe.try_get_singleton::<ReflectRegistry>() else {
+        return Ok(());
+    };
+    let mut result = Ok(());
+    for entity_type in registry.entity_names() {
+        registry.for_each_entity(universe, entity_type, |index, components| {
+            if result.is_ok() {
+                result = visit_entity(lua, component, index, components, &f);
+            }
+        });
+        if result.is_err() {
+            break;
+        }
+    }
+    result
+}
+
+fn visit_entity<'lua>(
+    lua: &'lua Lua,
+    component: &str,
+    index: usize,
+    components: &dyn ReflectComponents,
+    f: &Function<'lua>,
+) -> mlua::Result<()> {
+    let mut found = false;
+    components.reflect_components(&mut |name, _| found |= short_name(name) == component);
+    if !found {
+        return Ok(());
+    }
+
+    let entity = lua.create_table()?;
+    entity.set("index", index)?;
+    let mut result = Ok(());
+    components.reflect_components(&mut |name, reflect| {
+        let table = || {
+            let fields = lua.create_table()?;
+            let mut result = Ok(());
+            reflect.reflect_fields(&mut |field, value| {
+                if result.is_ok() {
+                    result = match value {
+                        Field::Number(number) if number.components() == 1 => {
+                            fields.set(field, number.get(0))
+                        }
+                        Field::Number(number) => lua
+                            .create_sequence_from((0..number.components()).map(|i| number.get(i)))
+                            .and_then(|list| fields.set(field, list)),
+                        Field::Debug(debug) => fields.set(field, format!("{debug:?}")),
+                    };
+                }
+            });
+            result?;
+            entity.set(short_name(name), fields)
+        };
+        if result.is_ok() {
+            result = table();
+        }
+    });
+    result?;
+
+    f.call::<_, ()>(entity.clone())?;
+
+    // Only numbers that the script changed are set, so that changes made by anything else are kept
+    components.reflect_components(&mut |name, reflect| {
+        let Ok(fields) = entity.get::<_, Table>(short_name(name)) else {
+            return;
+        };
+        reflect.reflect_fields(&mut |field, value| {
+            let Field::Number(number) = value else {
+                return;
+            };
+            let values: Vec<Option<f64>> = if number.components() == 1 {
+                vec![fields.get(field).ok()]
+            } else {
+                let list: Option<Table> = fields.get(field).ok();
+                (0..number.components())
+                    .map(|i| list.as_ref().and_then(|x| x.get(i + 1).ok()))
+                    .collect()
+            };
+            for (i, value) in values.into_iter().enumerate() {
+                if let Some(value) = value.filter(|x| *x != number.get(i)) {
+                    number.queue_set(i, value);
+                }
+            }
+        });
+    });
+    Ok(())
+}
+
+impl LoadedScript {
+    /// Runs the script again if it was loaded or reloaded, then calls its event handlers
+    /// and `process` function
+    fn update<'lua>(
+        &mut self,
+        lua: &'lua Lua,
+        api: &Table<'lua>,
+        delta: f32,
+        events: &[(&'static str, Vec<Value<'lua>>)],
+    ) -> mlua::Result<()> {
+        let Some(script) = self.handle.as_ref().and_then(Handle::get) else {
+            return Ok(());
+        };
+        if !self
+            .running
+            .as_ref()
+            .is_some_and(|x| Arc::ptr_eq(x, &script))
+        {
+            self.running = Some(script.clone());
+            self.failed = false;
+            self.start(lua, api, &script)?;
+        }
+        if self.failed {
+            return Ok(());
+        }
+
+        let (Some(env), Some(handlers)) = (&self.env, &self.handlers) else {
+            return Ok(());
+        };
+        // Events are handled first, so that `process` can react to them in the same frame
+        let handlers: Table = lua.registry_value(handlers)?;
+        for (name, values) in events {
+            let Some(list) = handlers.get::<_, Option<Table>>(*name)? else {
+                continue;
+            };
+            for handler in list.sequence_values::<Function>() {
+                let handler = handler?;
+                for value in values {
+                    handler.call::<_, ()>(value.clone())?;
+                }
+            }
+        }
+        let env: Table = lua.registry_value(env)?;
+        if let Some(process) = env.get::<_, Option<Function>>("process")? {
+            process.call::<_, ()>(delta)?;
+        }
+        Ok(())
+    }
+
+    /// Runs the script with new global variables, forgetting anything the last version of it did
+    fn start<'lua>(
+        &mut self,
+        lua: &'lua Lua,
+        api: &Table<'lua>,
+        script: &Script,
+    ) -> mlua::Result<()> {
+        self.env = None;
+        self.handlers = None;
+        lua.expire_registry_values();
+
+        // Globals that the script does not define are looked up in the real globals, such as `math`
+        let env = lua.create_table()?;
+        let meta = lua.create_table()?;
+        meta.set("__index", lua.globals())?;
+        env.set_metatable(Some(meta));
+
+        let handlers = lua.create_table()?;
+        let bina = lua.create_table()?;
+        let meta = lua.create_table()?;
+        meta.set("__index", api.clone())?;
+        bina.set_metatable(Some(meta));
+        bina.set("on", lua.load(ON).call::<_, Function>(handlers.clone())?)?;
+        env.set("bina", bina)?;
+
+        self.env = Some(lua.create_registry_value(env.clone())?);
+        self.handlers = Some(lua.create_registry_value(handlers)?);
+        lua.load(&script.source)
+            .set_name(self.name.as_str())
+            .set_environment(env)
+            .exec()
+    }
+}
+
+impl Singleton for ScriptHost {
+    fn process(&self, universe: &Universe) {
+        let mut state = self.state.lock();
+        let ScriptState { lua, scripts, api } = &mut *state;
+
+        if let Some(assets) = universe.try_get_singleton::<Assets>() {
+            for script in scripts.iter_mut().filter(|x| x.handle.is_none()) {
+                script.handle = Some(assets.load(universe, &script.name));
+            }
+        }
+
+        let result = lua.scope(|scope| {
+            // Functions that need the Universe only exist while the scripts are running
+            let api: Table = lua.registry_value(api)?;
+            api.set(
+                "delta",
+                scope.create_function(|_, ()| Ok(universe.get_delta()))?,
+            )?;
+            api.set(
+                "for_each",
+                scope.create_function(|lua, (component, f): (String, Function)| {
+                    for_each(lua, universe, &component, f)
+                })?,
+            )?;
+
+            let mut events = Vec::new();
+            for (name, bridge) in &self.events {
+                let values = bridge(universe, lua)?;
+                if !values.is_empty() {
+                    events.push((*name, values));
+                }
+            }
+
+            for script in scripts.iter_mut() {
+                if let Err(e) = script.update(lua, &api, universe.get_delta(), &events) {
+                    script.failed = true;
+                    log::error!("Script {} failed: {e}", script.name);
+                    if let Some(diagnostics) = universe.try_get_singleton::<Diagnostics>() {
+                        diagnostics.report("scripts", format!("{} failed: {e}", script.name));
+                    }
+                }
+            }
+            Ok(())
+        });
+        if let Err(e) = result {
+            log::error!("Failed to run scripts: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        component::{Component, ComponentField, NumberField, Processable},
+        entity::{Entity, EntityReference, Inaccessible},
+        reflect::Reflect,
+    };
+
+    struct Body {
+        position: NumberField<[f32; 2]>,
+        mass: NumberField<f32>,
+    }
+
+    impl Component for Body {
+        type Reference<'a> = &'a Self;
+
+        fn get_ref<'a>(&'a self) -> Self::Reference<'a> {
+            self
+        }
+
+        fn flush<E: Entity>(
+            &mut self,
+            _my_entity: EntityReference<Inaccessible<E>>,
+            _universe: &Universe,
+        ) {
+            self.position.process_modifiers();
+            self.mass.process_modifiers();
+        }
+    }
+
+    impl Processable for Body {
+        fn process<E: Entity>(
+            _component: &Self,
+            _my_entity: EntityReference<E>,
+            _universe: &Universe,
+        ) {
+        }
+    }
+
+    impl Reflect for Body {
+        fn reflect_fields(&self, visitor: &mut dyn FnMut(&'static str, Field<'_>)) {
+            visitor("position", Field::Number(&self.position));
+            visitor("mass", Field::Number(&self.mass));
+        }
+    }
+
+    struct Hit(u32);
+
+    const SCRIPT: &str = r#"
+        local hits = 0
+        function process(delta)
+            bina.for_each("Body", function(entity)
+                entity.Body.position[2] = entity.Body.position[2] + hits
+            end)
+        end
+        bina.on("hit", function(damage)
+            hits = hits + damage
+        end)
+    "#;
+
+    #[test]
+    fn scripts_change_fields_and_handle_events() {
+        let mut universe = Universe::new();
+        universe.queue_set_singleton(ReflectRegistry::new().with::<(Body,)>());
+        universe.queue_set_singleton(
+            ScriptHost::new()
+                .with_source("test", SCRIPT)
+                .with_event("hit", |hit: &Hit| hit.0),
+        );
+        universe.queue_add_entity((Body {
+            position: [1.0, 2.0].into(),
+            mass: 3.0.into(),
+        },));
+        universe.loop_once();
+        universe.loop_once();
+
+        universe.send_event(Hit(5));
+        // The event can only be read once the frame it was sent in has ended,
+        // and then moves the body in every frame after that
+        universe.loop_once();
+        universe.loop_once();
+        universe.loop_once();
+
+        let buffer = universe.get_entity_buffer::<(Body,)>().unwrap();
+        let body = &buffer.iter().next().unwrap().0;
+        assert_eq!(body.position.get_inner(), [1.0, 2.0 + 5.0 * 2.0]);
+        assert_eq!(body.mass.get_inner(), 3.0);
+    }
+}
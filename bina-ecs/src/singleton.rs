@@ -1,4 +1,6 @@
-use crate::universe::Universe;
+use crossbeam::queue::SegQueue;
+
+use crate::universe::{FlushStage, Universe};
 
 pub trait Singleton: Send + Sync + 'static {
     fn get_void_ptr(&self) -> *const () {
@@ -7,6 +9,71 @@ pub trait Singleton: Send + Sync + 'static {
     // fn get_void_mut_ptr(&mut self) -> *mut () {
     //     std::ptr::from_mut(self).cast()
     // }
+    /// The name of this singleton's type, used for profiling
+    fn type_name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+    /// The stage that this singleton is flushed in
+    fn flush_stage(&self) -> FlushStage {
+        FlushStage::Update
+    }
     fn process(&self, _universe: &Universe) {}
     fn flush(&mut self, _universe: &Universe) {}
 }
+
+/// Changes to a singleton that are queued while processing and applied when it is flushed
+///
+/// Queueing never blocks, so any entity or singleton can queue commands in parallel
+pub struct CommandQueue<C> {
+    commands: SegQueue<C>,
+}
+
+impl<C> CommandQueue<C> {
+    pub fn new() -> Self {
+        Self {
+            commands: SegQueue::new(),
+        }
+    }
+
+    pub fn push(&self, command: C) {
+        self.commands.push(command);
+    }
+
+    /// Takes every command queued so far, in the order they were queued
+    ///
+    /// Commands queued while these are applied are left for the next flush
+    pub fn drain(&mut self) -> impl Iterator<Item = C> + '_ {
+        let len = self.commands.len();
+        (0..len).map_while(|_| self.commands.pop())
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+}
+
+impl<C> Default for CommandQueue<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A singleton that only changes by applying the commands in its `CommandQueue`
+///
+/// `derive_singleton!` implements `Singleton` for types that implement this, applying
+/// every queued command in order when the singleton is flushed
+pub trait CommandSingleton: Send + Sync + 'static {
+    type Command: Send + 'static;
+
+    fn apply(&mut self, command: Self::Command, universe: &Universe);
+    fn process(&self, _universe: &Universe) {}
+    /// Called once every queued command has been applied
+    fn after_commands(&mut self, _universe: &Universe) {}
+    fn flush_stage(&self) -> FlushStage {
+        FlushStage::Update
+    }
+}
@@ -0,0 +1,220 @@
+//! Finite state machines, for AI that switches between a few distinct modes
+//!
+//! ```ignore
+//! let machine = StateMachine::new(Guard::Patrol, Brain::default())
+//!     .on_update(Guard::Patrol, |cx| {
+//!         cx.blackboard.patrol(cx.universe.get_delta());
+//!         cx.blackboard.sees_player().then_some(Guard::Chase)
+//!     })
+//!     .on_enter(Guard::Chase, |cx| cx.blackboard.alert());
+//! ```
+use std::hash::Hash;
+
+use crossbeam::atomic::AtomicCell;
+use fxhash::FxHashMap;
+use parking_lot::{MappedMutexGuard, Mutex, MutexGuard};
+
+use crate::{
+    behavior::Context,
+    component::{Component, Processable},
+    entity::{Entity, EntityReference, ErasedEntityReference},
+    universe::Universe,
+};
+
+type Callback<B> = Box<dyn FnMut(&mut Context<B>) + Send>;
+type Update<S, B> = Box<dyn FnMut(&mut Context<B>) -> Option<S> + Send>;
+
+/// What happens while a state machine is in a state
+struct State<S, B> {
+    enter: Option<Callback<B>>,
+    update: Option<Update<S, B>>,
+    exit: Option<Callback<B>>,
+}
+
+impl<S, B> Default for State<S, B> {
+    fn default() -> Self {
+        Self {
+            enter: None,
+            update: None,
+            exit: None,
+        }
+    }
+}
+
+struct Machine<S, B> {
+    states: FxHashMap<S, State<S, B>>,
+    blackboard: B,
+    /// The initial state is only entered on the first update
+    entered: bool,
+}
+
+/// A component that is always in exactly one state, and updates it once every process frame
+///
+/// States without any callbacks do nothing, but can still be changed to and from
+pub struct StateMachine<S, B> {
+    inner: Mutex<Machine<S, B>>,
+    current: AtomicCell<S>,
+    queued: AtomicCell<Option<S>>,
+}
+
+impl<S: Copy + Eq + Hash + Send + 'static, B: Send + 'static> StateMachine<S, B> {
+    pub fn new(initial: S, blackboard: B) -> Self {
+        Self {
+            inner: Mutex::new(Machine {
+                states: FxHashMap::default(),
+                blackboard,
+                entered: false,
+            }),
+            current: AtomicCell::new(initial),
+            queued: AtomicCell::new(None),
+        }
+    }
+
+    fn state_mut(&mut self, state: S) -> &mut State<S, B> {
+        self.inner.get_mut().states.entry(state).or_default()
+    }
+
+    /// Called when the machine changes to `state`, before its first update
+    pub fn on_enter(
+        mut self,
+        state: S,
+        enter: impl FnMut(&mut Context<B>) + Send + 'static,
+    ) -> Self {
+        self.state_mut(state).enter = Some(Box::new(enter));
+        self
+    }
+
+    /// Called every process frame while the machine is in `state`.
+    /// Returning a state changes to it after this update
+    pub fn on_update(
+        mut self,
+        state: S,
+        update: impl FnMut(&mut Context<B>) -> Option<S> + Send + 'static,
+    ) -> Self {
+        self.state_mut(state).update = Some(Box::new(update));
+        self
+    }
+
+    /// Called when the machine changes away from `state`
+    pub fn on_exit(mut self, state: S, exit: impl FnMut(&mut Context<B>) + Send + 'static) -> Self {
+        self.state_mut(state).exit = Some(Box::new(exit));
+        self
+    }
+
+    pub fn state(&self) -> S {
+        self.current.load()
+    }
+
+    /// Changes to the given state on the next process frame, before it is updated
+    pub fn queue_transition(&self, state: S) {
+        self.queued.store(Some(state));
+    }
+
+    pub fn blackboard(&self) -> MappedMutexGuard<'_, B> {
+        MutexGuard::map(self.inner.lock(), |x| &mut x.blackboard)
+    }
+}
+
+impl<S: Copy + Eq + Hash, B> Machine<S, B> {
+    fn context<'a>(
+        &'a mut self,
+        state: S,
+        entity: &'a dyn ErasedEntityReference,
+        universe: &'a Universe,
+    ) -> Option<(&'a mut State<S, B>, Context<'a, B>)> {
+        let state = self.states.get_mut(&state)?;
+        Some((
+            state,
+            Context {
+                blackboard: &mut self.blackboard,
+                entity,
+                universe,
+            },
+        ))
+    }
+
+    fn enter(&mut self, state: S, entity: &dyn ErasedEntityReference, universe: &Universe) {
+        if let Some((
+            State {
+                enter: Some(enter), ..
+            },
+            mut cx,
+        )) = self.context(state, entity, universe)
+        {
+            enter(&mut cx);
+        }
+    }
+
+    fn exit(&mut self, state: S, entity: &dyn ErasedEntityReference, universe: &Universe) {
+        if let Some((
+            State {
+                exit: Some(exit), ..
+            },
+            mut cx,
+        )) = self.context(state, entity, universe)
+        {
+            exit(&mut cx);
+        }
+    }
+
+    fn update(
+        &mut self,
+        state: S,
+        entity: &dyn ErasedEntityReference,
+        universe: &Universe,
+    ) -> Option<S> {
+        match self.context(state, entity, universe) {
+            Some((
+                State {
+                    update: Some(update),
+                    ..
+                },
+                mut cx,
+            )) => update(&mut cx),
+            _ => None,
+        }
+    }
+
+    /// Changes from `current` to `next`, returning the new current state
+    fn transition(
+        &mut self,
+        current: S,
+        next: S,
+        entity: &dyn ErasedEntityReference,
+        universe: &Universe,
+    ) -> S {
+        if next != current {
+            self.exit(current, entity, universe);
+            self.enter(next, entity, universe);
+        }
+        next
+    }
+}
+
+impl<S: Copy + Eq + Hash + Send + 'static, B: Send + 'static> Component for StateMachine<S, B> {
+    fn get_ref<'a>(&'a self) -> Self::Reference<'a> {
+        self
+    }
+}
+
+impl<S: Copy + Eq + Hash + Send + 'static, B: Send + 'static> Processable for StateMachine<S, B> {
+    fn process<E: Entity>(
+        component: Self::Reference<'_>,
+        my_entity: EntityReference<E>,
+        universe: &Universe,
+    ) {
+        let mut machine = component.inner.lock();
+        let mut current = component.current.load();
+        if !machine.entered {
+            machine.entered = true;
+            machine.enter(current, &my_entity, universe);
+        }
+        if let Some(next) = component.queued.take() {
+            current = machine.transition(current, next, &my_entity, universe);
+        }
+        if let Some(next) = machine.update(current, &my_entity, universe) {
+            current = machine.transition(current, next, &my_entity, universe);
+        }
+        component.current.store(current);
+    }
+}
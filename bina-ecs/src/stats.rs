@@ -0,0 +1,398 @@
+//! Named statistics that persist between runs, and achievements that unlock when they pass a threshold
+//!
+//! Counters count how many times something happened, and gauges hold the latest or best value of
+//! something. Both are saved into a `SaveGame` along with which achievements were unlocked.
+//! A `StatsBackend`, such as `SteamStats`, also receives every change so that a platform can show them
+//!
+//! ```ignore
+//! universe.queue_set_singleton(
+//!     Stats::new()
+//!         .with_achievement("first_blood", "kills", 1.0)
+//!         .with_achievement("marathon", "distance_walked", 42_195.0),
+//! );
+//! // While processing
+//! stats.increment("kills", 1);
+//! stats.set_gauge_max("best_score", score);
+//! for event in universe.read_events::<AchievementUnlocked>() { ... }
+//! ```
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    save::{SaveError, SaveGame},
+    singleton::Singleton,
+    universe::Universe,
+};
+
+/// The name of the section of a `SaveGame` that stats are saved in
+const SECTION: &str = "bina_ecs::stats";
+
+/// Sent into the Universe the frame after an achievement is unlocked
+pub struct AchievementUnlocked {
+    pub id: String,
+}
+
+/// Receives every change to `Stats`, such as to report them to a platform like Steam
+pub trait StatsBackend: Send + Sync {
+    fn set_counter(&self, name: &str, value: i64);
+    fn set_gauge(&self, name: &str, value: f64);
+    fn unlock(&self, achievement: &str);
+    /// Uploads the changes since the last store. Called after an achievement
+    /// is unlocked and after the stats are saved, instead of every frame
+    fn store(&self);
+}
+
+/// Unlocks an achievement once a counter or gauge reaches a value
+struct Achievement {
+    id: String,
+    stat: String,
+    threshold: f64,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct StatsData {
+    counters: BTreeMap<String, i64>,
+    gauges: BTreeMap<String, f64>,
+    unlocked: BTreeSet<String>,
+}
+
+impl StatsData {
+    /// Gets the value of a counter, or of a gauge if there is no counter with the name
+    fn value(&self, stat: &str) -> Option<f64> {
+        match self.counters.get(stat) {
+            Some(count) => Some(*count as f64),
+            None => self.gauges.get(stat).copied(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct StatsInner {
+    data: StatsData,
+    /// The names of the stats that changed since the last flush
+    changed: BTreeSet<String>,
+    /// Achievements that were unlocked since the last flush, which are sent as events
+    unlocked: Vec<String>,
+    /// Achievements that the backend has not been told about, which includes those from a loaded save
+    unreported: Vec<String>,
+}
+
+impl StatsInner {
+    fn unlock(&mut self, id: &str) {
+        if self.data.unlocked.insert(id.to_owned()) {
+            self.unlocked.push(id.to_owned());
+            self.unreported.push(id.to_owned());
+        }
+    }
+}
+
+/// Counters, gauges, and achievements, which can be changed from any component while processing
+///
+/// Achievements are checked against their thresholds during the flush,
+/// and each one is only unlocked once
+#[derive(Default)]
+pub struct Stats {
+    inner: Mutex<StatsInner>,
+    achievements: Vec<Achievement>,
+    backend: Option<Box<dyn StatsBackend>>,
+    /// Set when the stats are saved, so the backend stores them too
+    store_requested: AtomicBool,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an achievement that is unlocked once the counter or gauge named `stat` is at least `threshold`
+    pub fn with_achievement(
+        mut self,
+        id: impl Into<String>,
+        stat: impl Into<String>,
+        threshold: f64,
+    ) -> Self {
+        self.achievements.push(Achievement {
+            id: id.into(),
+            stat: stat.into(),
+            threshold,
+        });
+        self
+    }
+
+    /// Sends every change to the given backend, starting with every stat and achievement there already is
+    pub fn with_backend(mut self, backend: impl StatsBackend + 'static) -> Self {
+        self.set_backend(backend);
+        self
+    }
+
+    /// Replaces the backend, which receives every stat and achievement there already is
+    pub fn set_backend(&mut self, backend: impl StatsBackend + 'static) {
+        self.backend = Some(Box::new(backend));
+        self.report_everything();
+    }
+
+    /// Adds `amount` to a counter, which starts at 0
+    pub fn increment(&self, name: &str, amount: i64) {
+        let mut inner = self.inner.lock();
+        *inner.data.counters.entry(name.to_owned()).or_default() += amount;
+        inner.changed.insert(name.to_owned());
+    }
+
+    pub fn counter(&self, name: &str) -> i64 {
+        self.inner
+            .lock()
+            .data
+            .counters
+            .get(name)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    pub fn set_gauge(&self, name: &str, value: f64) {
+        let mut inner = self.inner.lock();
+        inner.data.gauges.insert(name.to_owned(), value);
+        inner.changed.insert(name.to_owned());
+    }
+
+    /// Sets a gauge only if `value` is higher than it, such as for a best score
+    pub fn set_gauge_max(&self, name: &str, value: f64) {
+        let mut inner = self.inner.lock();
+        let gauge = inner.data.gauges.entry(name.to_owned()).or_insert(value);
+        if *gauge < value {
+            *gauge = value;
+        }
+        inner.changed.insert(name.to_owned());
+    }
+
+    /// Gets a gauge, or `None` if it was never set
+    pub fn gauge(&self, name: &str) -> Option<f64> {
+        self.inner.lock().data.gauges.get(name).copied()
+    }
+
+    /// Unlocks an achievement now, whether or not it has a threshold
+    pub fn unlock(&self, id: &str) {
+        self.inner.lock().unlock(id);
+    }
+
+    pub fn is_unlocked(&self, id: &str) -> bool {
+        self.inner.lock().data.unlocked.contains(id)
+    }
+
+    /// Writes every stat and unlocked achievement into a section of the save
+    pub fn save_into(&self, save: &mut SaveGame) -> Result<(), SaveError> {
+        save.insert(SECTION, &self.inner.lock().data)?;
+        self.store_requested.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Replaces every stat and unlocked achievement with those in the save,
+    /// returning false if the save does not have any
+    ///
+    /// Achievements unlocked in the save are not sent as events again
+    pub fn load_from(&self, save: &SaveGame) -> Result<bool, SaveError> {
+        let Some(data) = save.get::<StatsData>(SECTION)? else {
+            return Ok(false);
+        };
+        self.inner.lock().data = data;
+        self.report_everything();
+        Ok(true)
+    }
+
+    /// Marks every stat and achievement as changed, so the backend receives all of them
+    fn report_everything(&self) {
+        let mut inner = self.inner.lock();
+        let inner = &mut *inner;
+        inner.changed = inner
+            .data
+            .counters
+            .keys()
+            .chain(inner.data.gauges.keys())
+            .cloned()
+            .collect();
+        inner.unreported = inner.data.unlocked.iter().cloned().collect();
+    }
+}
+
+impl Singleton for Stats {
+    fn flush(&mut self, universe: &Universe) {
+        let inner = self.inner.get_mut();
+        for achievement in &self.achievements {
+            if inner.data.unlocked.contains(&achievement.id) {
+                continue;
+            }
+            if inner
+                .data
+                .value(&achievement.stat)
+                .is_some_and(|x| x >= achievement.threshold)
+            {
+                inner.unlock(&achievement.id);
+            }
+        }
+        for id in inner.unlocked.drain(..) {
+            universe.send_event(AchievementUnlocked { id });
+        }
+
+        let Some(backend) = &self.backend else {
+            inner.changed.clear();
+            inner.unreported.clear();
+            return;
+        };
+        for name in std::mem::take(&mut inner.changed) {
+            if let Some(count) = inner.data.counters.get(&name) {
+                backend.set_counter(&name, *count);
+            } else if let Some(value) = inner.data.gauges.get(&name) {
+                backend.set_gauge(&name, *value);
+            }
+        }
+        let mut store = std::mem::take(self.store_requested.get_mut());
+        for id in inner.unreported.drain(..) {
+            backend.unlock(&id);
+            store = true;
+        }
+        if store {
+            backend.store();
+        }
+    }
+}
+
+/// Reports stats and achievements to Steam, whose API names must match the names used in `Stats`
+///
+/// Steam only stores 32 bit stats, so counters and gauges are truncated to fit
+///
+/// `SteamPlugin` gives this to the `Stats` singleton once it has connected to Steam
+#[cfg(feature = "steam")]
+pub struct SteamStats {
+    client: steamworks::Client,
+}
+
+#[cfg(feature = "steam")]
+impl SteamStats {
+    /// The client must already have received the stats of the current user,
+    /// which Steam requests on its own when the game starts
+    pub fn new(client: steamworks::Client) -> Self {
+        Self { client }
+    }
+}
+
+#[cfg(feature = "steam")]
+impl StatsBackend for SteamStats {
+    fn set_counter(&self, name: &str, value: i64) {
+        let value = value.clamp(i32::MIN as i64, i32::MAX as i64) as i32;
+        if self.client.user_stats().set_stat_i32(name, value).is_err() {
+            log::warn!("Steam does not have a stat named {name}");
+        }
+    }
+
+    fn set_gauge(&self, name: &str, value: f64) {
+        if self
+            .client
+            .user_stats()
+            .set_stat_f32(name, value as f32)
+            .is_err()
+        {
+            log::warn!("Steam does not have a stat named {name}");
+        }
+    }
+
+    fn unlock(&self, achievement: &str) {
+        let stats = self.client.user_stats();
+        if stats.achievement(achievement).set().is_err() {
+            log::warn!("Steam does not have an achievement named {achievement}");
+        }
+    }
+
+    fn store(&self) {
+        if self.client.user_stats().store_stats().is_err() {
+            log::error!("Failed to store stats on Steam");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl StatsBackend for Recorder {
+        fn set_counter(&self, name: &str, value: i64) {
+            self.0.lock().push(format!("{name}={value}"));
+        }
+
+        fn set_gauge(&self, name: &str, value: f64) {
+            self.0.lock().push(format!("{name}={value}"));
+        }
+
+        fn unlock(&self, achievement: &str) {
+            self.0.lock().push(format!("unlock {achievement}"));
+        }
+
+        fn store(&self) {
+            self.0.lock().push("store".into());
+        }
+    }
+
+    #[test]
+    fn thresholds_unlock_achievements_once() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mut universe = Universe::new();
+        universe.queue_set_singleton(
+            Stats::new()
+                .with_achievement("ten_kills", "kills", 10.0)
+                .with_backend(Recorder(calls.clone())),
+        );
+        universe.loop_once();
+
+        let stats = universe.get_singleton::<Stats>();
+        stats.increment("kills", 9);
+        universe.loop_once();
+        assert!(universe.read_events::<AchievementUnlocked>().is_empty());
+
+        let stats = universe.get_singleton::<Stats>();
+        stats.increment("kills", 1);
+        universe.loop_once();
+        let events = universe.read_events::<AchievementUnlocked>();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].id, "ten_kills");
+
+        universe.get_singleton::<Stats>().increment("kills", 1);
+        universe.loop_once();
+        assert!(universe.read_events::<AchievementUnlocked>().is_empty());
+        assert_eq!(
+            *calls.lock(),
+            [
+                "kills=9",
+                "kills=10",
+                "unlock ten_kills",
+                "store",
+                "kills=11"
+            ]
+        );
+    }
+
+    #[test]
+    fn stats_round_trip_through_saves() {
+        let stats = Stats::new();
+        stats.increment("jumps", 3);
+        stats.set_gauge_max("best_score", 50.0);
+        stats.set_gauge_max("best_score", 20.0);
+        stats.unlock("secret");
+        let mut save = SaveGame::new();
+        stats.save_into(&mut save).unwrap();
+
+        let loaded = Stats::new();
+        assert!(loaded.load_from(&save).unwrap());
+        assert_eq!(loaded.counter("jumps"), 3);
+        assert_eq!(loaded.gauge("best_score"), Some(50.0));
+        assert!(loaded.is_unlocked("secret"));
+        assert!(!Stats::new().load_from(&SaveGame::new()).unwrap());
+    }
+}
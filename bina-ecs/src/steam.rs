@@ -0,0 +1,192 @@
+//! Connecting to Steam on startup, for achievements, rich presence, the overlay, and cloud saves
+//!
+//! `SteamPlugin` connects to the Steam client, adds the `Steam` singleton, and reports the `Stats`
+//! singleton to Steam. If Steam is not running, the error is logged and the game runs without it
+//!
+//! ```ignore
+//! universe.add_plugin(SteamPlugin::new());
+//! // Later
+//! if let Some(steam) = universe.try_get_singleton::<Steam>() {
+//!     steam.set_rich_presence("status", Some("In the dungeon"));
+//!     universe.queue_set_singleton(SaveStore::for_steam_user("my_game", 1, steam));
+//! }
+//! for event in universe.read_events::<SteamOverlayToggled>() {
+//!     paused.set(event.active);
+//! }
+//! ```
+use std::{
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crossbeam::{channel, queue::SegQueue};
+use parking_lot::Mutex;
+use steamworks::{CallbackHandle, Client, GameOverlayActivated, SingleClient};
+use triomphe::Arc;
+
+use crate::{
+    plugin::Plugin,
+    singleton::Singleton,
+    stats::{Stats, SteamStats},
+    universe::Universe,
+};
+
+/// Sent into the Universe when the Steam overlay is opened or closed
+pub struct SteamOverlayToggled {
+    pub active: bool,
+}
+
+/// A connection to the Steam client, whose callbacks are run on their own thread every frame
+pub struct Steam {
+    client: Client,
+    /// Asks the callback thread to run the callbacks, which stops once this is dropped
+    run_callbacks: channel::Sender<()>,
+    /// Whether the callback thread is still running the callbacks of an earlier frame
+    running_callbacks: Arc<AtomicBool>,
+    overlay_active: Arc<AtomicBool>,
+    overlay_toggles: Arc<SegQueue<bool>>,
+    /// Stops reporting the overlay once dropped
+    _overlay_callback: Mutex<CallbackHandle>,
+}
+
+impl Steam {
+    /// Connects to the Steam client, which must be running, using the app id in `steam_appid.txt`
+    /// or the one Steam launched the game with
+    pub fn init() -> Result<Self, steamworks::SteamAPIInitError> {
+        Client::init().map(Self::from_clients)
+    }
+
+    /// Connects to the Steam client as the given app, such as while developing without `steam_appid.txt`
+    pub fn init_app(app_id: u32) -> Result<Self, steamworks::SteamAPIInitError> {
+        Client::init_app(app_id).map(Self::from_clients)
+    }
+
+    fn from_clients((client, single): (Client, SingleClient)) -> Self {
+        let (run_callbacks, requests) = channel::unbounded::<()>();
+        let running_callbacks = Arc::new(AtomicBool::new(false));
+        {
+            let running_callbacks = running_callbacks.clone();
+            // Callbacks can only be run by one thread at a time, and block while they run,
+            // so they get a thread of their own instead of taking one from the rayon pool
+            std::thread::spawn(move || {
+                for () in requests {
+                    single.run_callbacks();
+                    running_callbacks.store(false, Ordering::Relaxed);
+                }
+            });
+        }
+        let overlay_active = Arc::new(AtomicBool::new(false));
+        let overlay_toggles = Arc::new(SegQueue::new());
+        let overlay_callback = {
+            let overlay_active = overlay_active.clone();
+            let overlay_toggles = overlay_toggles.clone();
+            client.register_callback(move |event: GameOverlayActivated| {
+                overlay_active.store(event.active, Ordering::Relaxed);
+                overlay_toggles.push(event.active);
+            })
+        };
+        Self {
+            client,
+            run_callbacks,
+            running_callbacks,
+            overlay_active,
+            overlay_toggles,
+            _overlay_callback: Mutex::new(overlay_callback),
+        }
+    }
+
+    /// Gets the client, for the parts of Steamworks that this does not wrap
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Whether the Steam overlay is open, during which games usually pause
+    pub fn is_overlay_active(&self) -> bool {
+        self.overlay_active.load(Ordering::Relaxed)
+    }
+
+    /// Sets or removes a key of the rich presence shown to friends, returning false if Steam rejected it
+    pub fn set_rich_presence(&self, key: &str, value: Option<&str>) -> bool {
+        self.client.friends().set_rich_presence(key, value)
+    }
+
+    pub fn clear_rich_presence(&self) {
+        self.client.friends().clear_rich_presence();
+    }
+
+    /// Reports stats and achievements to Steam when given to `Stats::with_backend`
+    pub fn stats(&self) -> SteamStats {
+        SteamStats::new(self.client.clone())
+    }
+
+    /// Whether Steam Cloud is enabled by both the user and the game
+    pub fn is_cloud_enabled(&self) -> bool {
+        let storage = self.client.remote_storage();
+        storage.is_cloud_enabled_for_account() && storage.is_cloud_enabled_for_app()
+    }
+
+    /// The directory to keep the saves of the current Steam user in, so that several users on
+    /// one computer do not share saves. Steam Auto-Cloud can be set up to sync this directory,
+    /// which is where `SaveStore::for_steam_user` stores saves
+    pub fn save_dir(&self, game_name: &str) -> PathBuf {
+        dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join(game_name)
+            .join("steam")
+            .join(self.client.user().steam_id().raw().to_string())
+            .join("saves")
+    }
+}
+
+impl Singleton for Steam {
+    fn process(&self, universe: &Universe) {
+        // Callbacks are skipped for a frame if the thread has not caught up, instead of piling up
+        if !self.running_callbacks.swap(true, Ordering::Relaxed) {
+            let _ = self.run_callbacks.send(());
+        }
+        while let Some(active) = self.overlay_toggles.pop() {
+            universe.send_event(SteamOverlayToggled { active });
+        }
+    }
+}
+
+/// Connects to Steam when added, adding the `Steam` singleton and reporting `Stats` to Steam
+///
+/// Queueing a `Stats` singleton after adding this plugin replaces the one that reports to Steam,
+/// so achievements should be added through `Universe::singleton_entry` or before the plugin
+#[derive(Default)]
+pub struct SteamPlugin {
+    app_id: Option<u32>,
+}
+
+impl SteamPlugin {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Connects as the given app instead of the one in `steam_appid.txt`
+    pub fn with_app_id(mut self, app_id: u32) -> Self {
+        self.app_id = Some(app_id);
+        self
+    }
+}
+
+impl Plugin for SteamPlugin {
+    fn build(&self, universe: &mut Universe) {
+        let steam = match self.app_id {
+            Some(app_id) => Steam::init_app(app_id),
+            None => Steam::init(),
+        };
+        let steam = match steam {
+            Ok(steam) => steam,
+            Err(e) => {
+                log::error!("Failed to connect to Steam: {e}");
+                return;
+            }
+        };
+        universe
+            .singleton_entry(Stats::new)
+            .set_backend(steam.stats());
+        universe.queue_set_singleton(steam);
+    }
+}
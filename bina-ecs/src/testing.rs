@@ -0,0 +1,234 @@
+//! Running a Universe one frame at a time from tests
+//!
+//! ```ignore
+//! let mut test = TestUniverse::new().with_seed(1);
+//! test.universe().queue_add_entity((Player::default(),));
+//! test.run(2);
+//! test.send_event(KeyInput { key: Some(VirtualKeyCode::Space), pressed: true, .. });
+//! test.run(1);
+//! assert!(test.entities::<(Player,)>()[0].is_jumping());
+//! ```
+use std::error::Error;
+
+use rayon::prelude::*;
+
+use crate::{
+    entity::{Entity, EntityReference},
+    rng,
+    singleton::Singleton,
+    time::Duration,
+    universe::Universe,
+};
+
+type FrameHook = Box<dyn FnMut(&mut Universe)>;
+
+/// A Universe that only runs when told to, with the same delta every frame
+///
+/// Nothing waits between frames, so frames run as fast as they can be processed.
+/// Whatever else must happen between frames, such as drawing them, can be added with `after_frame`
+pub struct TestUniverse {
+    universe: Universe,
+    delta: Duration,
+    frame: u64,
+    exit_result: Option<Result<(), Box<dyn Error + Send + Sync>>>,
+    after_frame: Vec<FrameHook>,
+    /// Runs the frames of a seeded test, see `with_seed`
+    pool: Option<rayon::ThreadPool>,
+}
+
+impl TestUniverse {
+    /// Creates a Universe that advances by a 60th of a second every frame
+    pub fn new() -> Self {
+        Self::from_universe(Universe::new())
+    }
+
+    /// Runs an existing Universe, such as one that plugins were already added to
+    pub fn from_universe(universe: Universe) -> Self {
+        Self {
+            universe,
+            delta: Duration::from_secs_f64(1.0 / 60.0),
+            frame: 0,
+            exit_result: None,
+            after_frame: Vec::new(),
+            pool: None,
+        }
+    }
+
+    pub fn with_delta(mut self, delta: Duration) -> Self {
+        self.delta = delta;
+        self
+    }
+
+    /// Seeds `BufferedRng` while frames run, so that tests which generate random numbers are reproducible
+    ///
+    /// Frames run on a thread pool of their own, so other tests running at the same time
+    /// do not change the numbers, and this does not change theirs. Numbers generated
+    /// outside of frames, such as by the test itself or `after_frame` hooks, are not seeded
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.pool = Some(rng::seeded_pool(seed));
+        self
+    }
+
+    /// Calls `hook` after every frame, once everything queued in the frame has been applied
+    pub fn after_frame(&mut self, hook: impl FnMut(&mut Universe) + 'static) {
+        self.after_frame.push(Box::new(hook));
+    }
+
+    pub fn universe(&self) -> &Universe {
+        &self.universe
+    }
+
+    pub fn universe_mut(&mut self) -> &mut Universe {
+        &mut self.universe
+    }
+
+    /// How many frames have run
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Gets what the Universe exited with, if it has exited
+    pub fn exit_result(&self) -> Option<&Result<(), Box<dyn Error + Send + Sync>>> {
+        self.exit_result.as_ref()
+    }
+
+    pub fn has_exited(&self) -> bool {
+        self.exit_result.is_some()
+    }
+
+    /// Runs one frame, returning false without running it if the Universe has exited
+    pub fn step(&mut self) -> bool {
+        if self.has_exited() {
+            return false;
+        }
+        let delta = self.delta;
+        let universe = &mut self.universe;
+        self.exit_result = match &self.pool {
+            Some(pool) => pool.install(|| universe.loop_once_with_delta(delta)),
+            None => universe.loop_once_with_delta(delta),
+        };
+        self.frame += 1;
+        for hook in &mut self.after_frame {
+            hook(&mut self.universe);
+        }
+        true
+    }
+
+    /// Runs the given number of frames, stopping early if the Universe exits
+    ///
+    /// # Panics
+    /// Panics if the Universe exits with an error, so that the test fails with it
+    pub fn run(&mut self, frames: usize) {
+        for _ in 0..frames {
+            if !self.step() {
+                break;
+            }
+        }
+        if let Some(Err(e)) = &self.exit_result {
+            panic!("Universe exited with an error on frame {}: {e}", self.frame);
+        }
+    }
+
+    /// Runs frames until `condition` is true, up to `max_frames`, returning whether it became true
+    ///
+    /// The condition is checked before every frame, including the first
+    pub fn run_until(&mut self, max_frames: usize, mut condition: impl FnMut(&Universe) -> bool) -> bool {
+        for _ in 0..max_frames {
+            if condition(&self.universe) {
+                return true;
+            }
+            self.run(1);
+            if self.has_exited() {
+                break;
+            }
+        }
+        condition(&self.universe)
+    }
+
+    /// Sends an event that can be read during the next frame, such as synthetic input
+    pub fn send_event<T: Send + Sync + 'static>(&self, event: T) {
+        self.universe.send_event(event);
+    }
+
+    /// # Panics
+    /// Panics if the singleton does not exist
+    pub fn singleton<T: Singleton>(&self) -> &T {
+        self.universe.get_singleton()
+    }
+
+    /// Gets a singleton to change it between frames, such as to overwrite the input
+    ///
+    /// # Panics
+    /// Panics if the singleton does not exist
+    pub fn singleton_mut<T: Singleton>(&mut self) -> &mut T {
+        self.universe
+            .try_get_singleton_mut()
+            .expect("Singleton should be initialized")
+    }
+
+    /// Gets every entity of type `E`, in the order they are stored
+    pub fn entities<E: Entity>(&self) -> Vec<EntityReference<'_, E>> {
+        self.universe
+            .iter_entities::<E>()
+            .map(|x| x.collect())
+            .unwrap_or_default()
+    }
+}
+
+impl Default for TestUniverse {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct FrameCounter {
+        frames: usize,
+        elapsed: f32,
+    }
+
+    impl Singleton for FrameCounter {
+        fn flush(&mut self, universe: &Universe) {
+            self.frames += 1;
+            self.elapsed += universe.get_delta();
+        }
+    }
+
+    struct Quit;
+
+    impl Singleton for Quit {
+        fn process(&self, universe: &Universe) {
+            universe.exit_ok();
+        }
+    }
+
+    #[test]
+    fn runs_frames_with_fixed_delta() {
+        let mut test = TestUniverse::new().with_delta(Duration::from_millis(100));
+        test.universe().queue_set_singleton(FrameCounter::default());
+        // The singleton is added once the first frame ends
+        test.run(4);
+        let counter = test.singleton::<FrameCounter>();
+        assert_eq!(counter.frames, 3);
+        assert!((counter.elapsed - 0.3).abs() < 1e-5);
+        assert_eq!(test.frame(), 4);
+
+        test.singleton_mut::<FrameCounter>().frames = 0;
+        assert!(test.run_until(10, |x| x.get_singleton::<FrameCounter>().frames == 5));
+        assert_eq!(test.frame(), 9);
+    }
+
+    #[test]
+    fn stops_after_exit() {
+        let mut test = TestUniverse::new();
+        test.universe().queue_set_singleton(Quit);
+        test.run(5);
+        assert!(test.has_exited());
+        assert_eq!(test.frame(), 2);
+        assert!(!test.step());
+    }
+}
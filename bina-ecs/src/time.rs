@@ -0,0 +1,6 @@
+//! Time measurement that also works in browsers, where `std::time::Instant` panics
+pub use std::time::Duration;
+#[cfg(not(target_arch = "wasm32"))]
+pub use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+pub use web_time::Instant;
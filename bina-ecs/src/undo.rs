@@ -0,0 +1,352 @@
+//! Undoing and redoing changes, for editors and for mechanics such as taking back a move
+//!
+//! A `CommandStack` owns the value being changed, so that every change goes through a command
+//! that knows how to reverse it. Commands can be closures made with `FnCommand`, or any type
+//! that implements `UndoCommand`, such as a serializable enum of every edit a level editor makes
+//!
+//! ```ignore
+//! let mut stack = CommandStack::new(Level::default()).with_capacity(100);
+//! stack.begin_group();
+//! stack.execute(Box::new(FnCommand::new(
+//!     move |level: &mut Level| level.add_wall(wall),
+//!     move |level: &mut Level| level.remove_wall(wall),
+//! )));
+//! stack.execute(Box::new(FnCommand::new(
+//!     move |level: &mut Level| level.add_door(door),
+//!     move |level: &mut Level| level.remove_door(door),
+//! )));
+//! stack.end_group();
+//! // Removes both the door and the wall
+//! stack.undo();
+//! ```
+use std::collections::VecDeque;
+
+use parking_lot::Mutex;
+
+use crate::{singleton::Singleton, universe::Universe};
+
+/// A change to a value that can be reversed
+pub trait UndoCommand<T>: Send + Sync + 'static {
+    /// Makes the change. Called again whenever the command is redone
+    fn execute(&mut self, target: &mut T);
+    /// Reverses the change, leaving `target` as it was before `execute`
+    fn undo(&mut self, target: &mut T);
+}
+
+impl<T: 'static> UndoCommand<T> for Box<dyn UndoCommand<T>> {
+    fn execute(&mut self, target: &mut T) {
+        self.as_mut().execute(target);
+    }
+
+    fn undo(&mut self, target: &mut T) {
+        self.as_mut().undo(target);
+    }
+}
+
+type CommandFn<T> = Box<dyn FnMut(&mut T) + Send + Sync>;
+
+/// A command made from a closure that makes a change and a closure that reverses it
+pub struct FnCommand<T> {
+    execute: CommandFn<T>,
+    undo: CommandFn<T>,
+}
+
+impl<T> FnCommand<T> {
+    pub fn new(
+        execute: impl FnMut(&mut T) + Send + Sync + 'static,
+        undo: impl FnMut(&mut T) + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            execute: Box::new(execute),
+            undo: Box::new(undo),
+        }
+    }
+}
+
+impl<T: 'static> UndoCommand<T> for FnCommand<T> {
+    fn execute(&mut self, target: &mut T) {
+        (self.execute)(target);
+    }
+
+    fn undo(&mut self, target: &mut T) {
+        (self.undo)(target);
+    }
+}
+
+/// Changes queued while processing, applied in order when the stack is flushed
+enum Pending<C> {
+    Execute(C),
+    Undo,
+    Redo,
+    BeginGroup,
+    EndGroup,
+    Clear,
+}
+
+/// A value and the history of the commands that changed it
+///
+/// Commands executed between `begin_group` and `end_group` are undone and redone together.
+/// Once there are more groups than the capacity, the oldest are forgotten and can no longer be undone
+///
+/// As a singleton, changes can be queued while processing with the `queue_` methods,
+/// which are applied when the singleton is flushed
+pub struct CommandStack<T, C = Box<dyn UndoCommand<T>>> {
+    target: T,
+    /// Every group that can be undone, from oldest to newest
+    undo: VecDeque<Vec<C>>,
+    /// Every group that was undone, with the most recently undone last
+    redo: Vec<Vec<C>>,
+    /// The group that commands are being added to, if `begin_group` was called
+    group: Option<Vec<C>>,
+    /// How many `begin_group` calls have not been ended, so that groups can be nested
+    group_depth: usize,
+    capacity: Option<usize>,
+    pending: Mutex<Vec<Pending<C>>>,
+}
+
+impl<T, C: UndoCommand<T>> CommandStack<T, C> {
+    pub fn new(target: T) -> Self {
+        Self {
+            target,
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            group: None,
+            group_depth: 0,
+            capacity: None,
+            pending: Mutex::default(),
+        }
+    }
+
+    /// Limits how many groups can be undone. There is no limit by default
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self.trim();
+        self
+    }
+
+    pub fn target(&self) -> &T {
+        &self.target
+    }
+
+    /// Gets the value without recording anything, so that changes made through this cannot be undone
+    pub fn target_mut(&mut self) -> &mut T {
+        &mut self.target
+    }
+
+    /// Executes a command and records it, forgetting everything that could be redone
+    pub fn execute(&mut self, mut command: C) {
+        command.execute(&mut self.target);
+        self.redo.clear();
+        match &mut self.group {
+            Some(group) => group.push(command),
+            None => {
+                self.undo.push_back(vec![command]);
+                self.trim();
+            }
+        }
+    }
+
+    /// Starts a group, which lasts until the matching `end_group`
+    ///
+    /// Groups started inside another group are part of the outer group
+    pub fn begin_group(&mut self) {
+        if self.group_depth == 0 {
+            self.group = Some(Vec::new());
+        }
+        self.group_depth += 1;
+    }
+
+    /// Ends the group started by the last `begin_group`. Does nothing if no group was started
+    pub fn end_group(&mut self) {
+        if self.group_depth == 0 {
+            return;
+        }
+        self.group_depth -= 1;
+        if self.group_depth > 0 {
+            return;
+        }
+        if let Some(group) = self.group.take() {
+            // An empty group would be an undo that does nothing
+            if !group.is_empty() {
+                self.undo.push_back(group);
+                self.trim();
+            }
+        }
+    }
+
+    /// Undoes the most recent group, returning false if there was nothing to undo
+    ///
+    /// Ends any group that was not ended first
+    pub fn undo(&mut self) -> bool {
+        self.end_all_groups();
+        let Some(mut group) = self.undo.pop_back() else {
+            return false;
+        };
+        for command in group.iter_mut().rev() {
+            command.undo(&mut self.target);
+        }
+        self.redo.push(group);
+        true
+    }
+
+    /// Executes the most recently undone group again, returning false if there was nothing to redo
+    pub fn redo(&mut self) -> bool {
+        self.end_all_groups();
+        let Some(mut group) = self.redo.pop() else {
+            return false;
+        };
+        for command in &mut group {
+            command.execute(&mut self.target);
+        }
+        self.undo.push_back(group);
+        self.trim();
+        true
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty() || self.group.as_ref().is_some_and(|x| !x.is_empty())
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// How many groups can be undone, not counting a group that has not been ended
+    pub fn undo_len(&self) -> usize {
+        self.undo.len()
+    }
+
+    pub fn redo_len(&self) -> usize {
+        self.redo.len()
+    }
+
+    /// Forgets every command without undoing them, such as after a level is saved
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.group = None;
+        self.group_depth = 0;
+    }
+
+    /// Gets every command that can be undone, from oldest to newest, such as to save the history
+    pub fn history(&self) -> impl Iterator<Item = &C> {
+        self.undo.iter().flatten()
+    }
+
+    /// Queues a command to be executed when the stack is flushed
+    pub fn queue_execute(&self, command: C) {
+        self.pending.lock().push(Pending::Execute(command));
+    }
+
+    pub fn queue_undo(&self) {
+        self.pending.lock().push(Pending::Undo);
+    }
+
+    pub fn queue_redo(&self) {
+        self.pending.lock().push(Pending::Redo);
+    }
+
+    pub fn queue_begin_group(&self) {
+        self.pending.lock().push(Pending::BeginGroup);
+    }
+
+    pub fn queue_end_group(&self) {
+        self.pending.lock().push(Pending::EndGroup);
+    }
+
+    pub fn queue_clear(&self) {
+        self.pending.lock().push(Pending::Clear);
+    }
+
+    fn end_all_groups(&mut self) {
+        while self.group_depth > 0 {
+            self.end_group();
+        }
+    }
+
+    fn trim(&mut self) {
+        if let Some(capacity) = self.capacity {
+            let excess = self.undo.len().saturating_sub(capacity);
+            self.undo.drain(..excess);
+        }
+    }
+}
+
+impl<T, C> Singleton for CommandStack<T, C>
+where
+    T: Send + Sync + 'static,
+    C: UndoCommand<T>,
+{
+    fn flush(&mut self, _universe: &Universe) {
+        for pending in std::mem::take(self.pending.get_mut()) {
+            match pending {
+                Pending::Execute(command) => self.execute(command),
+                Pending::Undo => {
+                    self.undo();
+                }
+                Pending::Redo => {
+                    self.redo();
+                }
+                Pending::BeginGroup => self.begin_group(),
+                Pending::EndGroup => self.end_group(),
+                Pending::Clear => self.clear(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add(n: i32) -> FnCommand<i32> {
+        FnCommand::new(move |x| *x += n, move |x| *x -= n)
+    }
+
+    #[test]
+    fn undo_and_redo() {
+        let mut stack = CommandStack::new(0);
+        stack.execute(add(1));
+        stack.execute(add(2));
+        assert!(stack.undo());
+        assert_eq!(*stack.target(), 1);
+        assert!(stack.redo());
+        assert_eq!(*stack.target(), 3);
+        assert!(!stack.redo());
+
+        stack.undo();
+        stack.execute(add(10));
+        // Executing forgets what could be redone
+        assert!(!stack.can_redo());
+        assert_eq!(*stack.target(), 11);
+    }
+
+    #[test]
+    fn groups_are_undone_together() {
+        let mut stack = CommandStack::new(0);
+        stack.execute(add(1));
+        stack.begin_group();
+        stack.execute(add(2));
+        stack.begin_group();
+        stack.execute(add(3));
+        stack.end_group();
+        stack.end_group();
+        assert_eq!(stack.undo_len(), 2);
+        stack.undo();
+        assert_eq!(*stack.target(), 1);
+        stack.redo();
+        assert_eq!(*stack.target(), 6);
+    }
+
+    #[test]
+    fn capacity_forgets_oldest() {
+        let mut stack = CommandStack::new(0).with_capacity(2);
+        for _ in 0..3 {
+            stack.execute(add(1));
+        }
+        assert!(stack.undo());
+        assert!(stack.undo());
+        assert!(!stack.undo());
+        assert_eq!(*stack.target(), 1);
+    }
+}
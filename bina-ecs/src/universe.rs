@@ -3,10 +3,10 @@ use std::{
     cell::SyncUnsafeCell,
     collections::hash_map::Entry,
     error::Error,
-    time::{Duration, Instant},
+    future::Future,
 };
 
-use crossbeam::atomic::AtomicCell;
+use crossbeam::{atomic::AtomicCell, queue::SegQueue};
 use fxhash::FxHashMap;
 use parking_lot::Mutex;
 use rayon::{
@@ -16,15 +16,48 @@ use rayon::{
         ParallelIterator,
     },
 };
+#[cfg(not(target_arch = "wasm32"))]
 use spin_sleep::{SpinSleeper, LoopHelper};
 use tokio::runtime::Handle;
+use triomphe::Arc;
 
 use crate::{
+    component::Tag,
     entity::{
-        cast_entity_buffer, Entity, EntityBuffer, EntityBufferStruct, EntityReference, MaybeEntity,
+        cast_entity_buffer, Entity, EntityBuffer, EntityBufferMemory, EntityBufferStruct,
+        EntityFilter, EntityReference, MaybeEntity, PanicPolicy,
     },
+    events::Events,
+    loading::{LoadingScreen, LoadingState},
+    pacing::FramePacer,
+    plugin::Plugins,
+    profiler::{FrameProfile, Phase, Profiler},
     singleton::Singleton,
+    time::Duration,
 };
+#[cfg(not(target_arch = "wasm32"))]
+use crate::time::Instant;
+
+/// When a singleton or type of entity is flushed, relative to the others
+///
+/// The entities of a stage are flushed in parallel, then its singletons, and each stage
+/// only starts once the previous one has finished
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default)]
+pub enum FlushStage {
+    PreUpdate,
+    /// Where everything is flushed unless told otherwise
+    #[default]
+    Update,
+    /// For things that depend on the rest of the frame, such as propagating transforms
+    PostUpdate,
+    /// For things that send the frame to be drawn, such as `Graphics`
+    PreRender,
+}
+
+impl FlushStage {
+    /// Every stage, in the order they are flushed
+    pub const ALL: [Self; 4] = [Self::PreUpdate, Self::Update, Self::PostUpdate, Self::PreRender];
+}
 
 #[derive(Default)]
 struct BetterUnsafeCell<T>(SyncUnsafeCell<T>);
@@ -41,6 +74,8 @@ impl<T> BetterUnsafeCell<T> {
     }
 }
 
+type ExclusiveSystem = Box<dyn FnOnce(&mut Universe) + Send>;
+
 pub struct Universe {
     entity_buffers: BetterUnsafeCell<FxHashMap<TypeId, Box<dyn EntityBuffer>>>,
     pending_new_entity_buffers: Mutex<FxHashMap<TypeId, Box<dyn EntityBuffer>>>,
@@ -52,6 +87,17 @@ pub struct Universe {
     async_handle: Option<Handle>,
     delta_accurate: f64,
     delta: f32,
+    profiler: Profiler,
+    pacer: Arc<FramePacer>,
+    panic_policy: PanicPolicy,
+    /// Overrides `panic_policy` for some types of entities, keyed by the type of their buffer
+    entity_panic_policies: FxHashMap<TypeId, PanicPolicy>,
+    shrink_entity_buffers: AtomicCell<bool>,
+    /// Keyed by the type of their buffer, as with `entity_panic_policies`
+    entity_flush_stages: FxHashMap<TypeId, FlushStage>,
+    exclusive_systems: SegQueue<ExclusiveSystem>,
+    /// Declared last so that the libraries of dynamic plugins are unloaded after everything else
+    pub(crate) plugins: Plugins,
 }
 
 impl Universe {
@@ -69,10 +115,84 @@ impl Universe {
             async_handle: Handle::try_current().ok(),
             delta_accurate: Default::default(),
             delta: Default::default(),
+            profiler: Default::default(),
+            pacer: Default::default(),
+            panic_policy: Default::default(),
+            entity_panic_policies: Default::default(),
+            shrink_entity_buffers: AtomicCell::new(false),
+            entity_flush_stages: Default::default(),
+            exclusive_systems: SegQueue::new(),
+            plugins: Default::default(),
         }
     }
 
     pub fn queue_add_entity<E: Entity>(&self, entity: E) {
+        self.with_entity_buffer::<E>(|buffer| buffer.queue_add_entity(entity));
+    }
+
+    /// Makes room for at least `additional` more entities of type `E` when this frame is flushed,
+    /// so that adding them later does not need to grow the buffer
+    ///
+    /// Useful when many entities are about to be added at once, such as bullets in a bullet hell
+    pub fn reserve_entities<E: Entity>(&self, additional: usize) {
+        self.with_entity_buffer::<E>(|buffer| buffer.queue_reserve(additional));
+    }
+
+    /// Keeps up to `capacity` removed entities of type `E` so that `spawn_from_pool` can add them
+    /// again, instead of dropping them
+    ///
+    /// Reusing entities keeps whatever their components allocated, such as the meshes of polygons,
+    /// so it helps with entities that are added and removed often, like bullets. Pools are empty by
+    /// default, and lowering the capacity drops the excess when this frame is flushed
+    pub fn set_entity_pool_capacity<E: Entity>(&self, capacity: usize) {
+        self.with_entity_buffer::<E>(|buffer| buffer.set_pool_capacity(capacity));
+    }
+
+    /// Adds an entity that was removed earlier if its pool has one, after passing it to `reset`,
+    /// and otherwise adds the entity made by `new`
+    ///
+    /// Returns true if an entity was reused
+    ///
+    /// ```ignore
+    /// universe.spawn_from_pool(
+    ///     || (Polygon::new(graphics, &BULLET, material.clone()), Bullet::default()),
+    ///     |(polygon, bullet)| {
+    ///         polygon.set_transform(position, 0.0, Vector::new(1.0, 1.0));
+    ///         *bullet = Bullet::default();
+    ///     },
+    /// );
+    /// ```
+    pub fn spawn_from_pool<E: Entity>(
+        &self,
+        new: impl FnOnce() -> E,
+        reset: impl FnOnce(&mut E),
+    ) -> bool {
+        let pooled = self
+            .get_entity_buffer::<E>()
+            .and_then(EntityBufferStruct::take_pooled);
+        match pooled {
+            Some(mut entity) => {
+                reset(&mut entity);
+                self.queue_add_entity(entity);
+                true
+            }
+            None => {
+                self.queue_add_entity(new());
+                false
+            }
+        }
+    }
+
+    /// Frees the memory that entity buffers are not using when this frame is flushed
+    ///
+    /// Buffers never shrink on their own, so this is useful after many entities were removed,
+    /// such as when changing levels in a long session
+    pub fn shrink_entity_buffers(&self) {
+        self.shrink_entity_buffers.store(true);
+    }
+
+    /// Calls `f` with the buffer of entities of type `E`, creating the buffer if there is none yet
+    fn with_entity_buffer<E: Entity>(&self, f: impl FnOnce(&EntityBufferStruct<E>)) {
         let type_id = TypeId::of::<EntityBufferStruct<E>>();
         let mut lock;
         let entry;
@@ -91,18 +211,45 @@ impl Universe {
         };
 
         let buffer: &EntityBufferStruct<E> = unsafe { cast_entity_buffer(&buffer) };
-        buffer.queue_add_entity(entity);
+        f(buffer);
+    }
+
+    /// Iterates over every entity of type `E` in parallel, or returns None if none were ever added
+    ///
+    /// Entities should only be accessed while processing, as they are modified while flushing
+    pub fn iter_entities<E: Entity>(
+        &self,
+    ) -> Option<impl IndexedParallelIterator<Item = EntityReference<'_, E>>> {
+        self.get_entity_buffer::<E>()
+            .map(|buffer| buffer.par_references())
+    }
+
+    /// Iterates over the entities of type `E` that match the filter `F` and `predicate`
+    ///
+    /// Returns an empty iterator if `E` does not match `F`, without looking at any entities.
+    /// Otherwise `predicate` is called on each entity before a reference to it is made.
+    /// Filters are most useful in code that is generic over the type of entity
+    ///
+    /// ```ignore
+    /// let alive = universe.iter_entities_filtered::<E, Without<Frozen>>(|_| ...);
+    /// if let Some(alive) = alive {
+    ///     alive.for_each(|enemy| ...);
+    /// }
+    /// ```
+    pub fn iter_entities_filtered<'a, E: Entity, F: EntityFilter>(
+        &'a self,
+        predicate: impl Fn(&E) -> bool + Send + Sync + 'a,
+    ) -> Option<impl ParallelIterator<Item = EntityReference<'a, E>>> {
+        self.get_entity_buffer::<E>()
+            .map(|buffer| buffer.par_references_filtered::<F>(predicate))
     }
 
-    pub fn iter_entities<E: Entity>(&self) -> Option<impl IndexedParallelIterator + '_> {
+    pub(crate) fn get_entity_buffer<E: Entity>(&self) -> Option<&EntityBufferStruct<E>> {
         unsafe {
             self.entity_buffers
                 .get()
                 .get(&TypeId::of::<EntityBufferStruct<E>>())
-                .map(|buffer| {
-                    let buffer: &EntityBufferStruct<E> = cast_entity_buffer(buffer);
-                    buffer.par_iter()
-                })
+                .map(|buffer| cast_entity_buffer(buffer))
         }
     }
 
@@ -115,6 +262,35 @@ impl Universe {
         };
     }
 
+    /// Gets the name of every type of entity and how many of them exist
+    ///
+    /// Entities queued to be added or removed are not counted
+    pub fn entity_counts(&self) -> Vec<(&'static str, usize)> {
+        unsafe { self.entity_buffers.get() }
+            .values()
+            .map(|buffer| (buffer.entity_type_name(), buffer.len()))
+            .collect()
+    }
+
+    /// Counts the entities that have the tag `T`, across every type of entity
+    ///
+    /// Entities queued to be added or removed are not counted
+    pub fn count_tagged<T: Tag>(&self) -> usize {
+        unsafe { self.entity_buffers.get() }
+            .values()
+            .filter(|buffer| buffer.has_component(TypeId::of::<T>()))
+            .map(|buffer| buffer.len())
+            .sum()
+    }
+
+    /// Gets how much memory the buffer of every type of entity is using
+    pub fn entity_memory(&self) -> Vec<EntityBufferMemory> {
+        unsafe { self.entity_buffers.get() }
+            .values()
+            .map(|buffer| buffer.memory())
+            .collect()
+    }
+
     /// Gets a singleton
     ///
     /// # Panics
@@ -135,6 +311,14 @@ impl Universe {
         }
     }
 
+    /// Gets a singleton mutably if it exists, such as from a system queued with `queue_exclusive`
+    pub fn try_get_singleton_mut<T: Singleton>(&mut self) -> Option<&mut T> {
+        self.singletons
+            .safe_get_mut()
+            .get_mut(&TypeId::of::<T>())
+            .map(|x| unsafe { &mut *std::ptr::from_mut(x.as_mut()).cast::<T>() })
+    }
+
     /// Adds a new singleton, or overwrites and existing singleton
     pub fn queue_set_singleton<T: Singleton>(&self, singleton: T) {
         self.pending_new_singletons
@@ -142,6 +326,57 @@ impl Universe {
             .insert(TypeId::of::<T>(), Box::new(singleton));
     }
 
+    /// Gets the singleton of type `T` that will be used next frame, queueing the one
+    /// returned by `default` if there is none
+    ///
+    /// Lets plugins add to singletons that other plugins may have set, such as `ReflectRegistry`
+    pub fn singleton_entry<T: Singleton>(&mut self, default: impl FnOnce() -> T) -> &mut T {
+        let id = TypeId::of::<T>();
+        let pending = self.pending_new_singletons.get_mut();
+        // Queued singletons replace the current ones when this frame ends
+        let singleton = if pending.contains_key(&id)
+            || !self.singletons.safe_get_mut().contains_key(&id)
+        {
+            pending.entry(id).or_insert_with(|| Box::new(default()))
+        } else {
+            self.singletons.safe_get_mut().get_mut(&id).unwrap()
+        };
+        unsafe { &mut *std::ptr::from_mut(singleton.as_mut()).cast::<T>() }
+    }
+
+    /// Queues `system` to run with exclusive access to this Universe once the current frame
+    /// has been flushed, before the next process frame starts
+    ///
+    /// Meant for restructuring that would be awkward through queues, such as loading a level.
+    /// Nothing else runs at the same time, so this should be used sparingly. Singletons and
+    /// entity buffers queued in the same frame have already been added when `system` runs,
+    /// and systems queued by `system` run after the next frame
+    pub fn queue_exclusive(&self, system: impl FnOnce(&mut Universe) + Send + 'static) {
+        self.exclusive_systems.push(Box::new(system));
+    }
+
+    /// Sends an event that can be read with `read_events` during the next process frame
+    pub fn send_event<T: Send + Sync + 'static>(&self, event: T) {
+        if let Some(events) = self.try_get_singleton::<Events<T>>() {
+            events.send(event);
+            return;
+        }
+        let mut lock = self.pending_new_singletons.lock();
+        let events = lock
+            .entry(TypeId::of::<Events<T>>())
+            .or_insert_with(|| Box::new(Events::<T>::new()));
+        // The singleton is guaranteed to be an Events<T> as it is keyed by its TypeId
+        let events = unsafe { &mut *(events.as_mut() as *mut dyn Singleton).cast::<Events<T>>() };
+        events.send_now(event);
+    }
+
+    /// Gets all events of the given type that were sent during the last process frame
+    pub fn read_events<T: Send + Sync + 'static>(&self) -> &[T] {
+        self.try_get_singleton::<Events<T>>()
+            .map(Events::read)
+            .unwrap_or_default()
+    }
+
     /// If this universe was initialized without a tokio runtime,
     /// one can be added with this method
     ///
@@ -155,6 +390,25 @@ impl Universe {
         self.async_handle.as_ref().unwrap().enter()
     }
 
+    /// Runs the given future in the background
+    ///
+    /// # Panics
+    /// This will panic if this universe has no tokio runtime
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn spawn(&self, fut: impl Future<Output = ()> + Send + 'static) {
+        let _guard = self.enter_tokio();
+        tokio::spawn(fut);
+    }
+
+    /// Runs the given future in the background
+    ///
+    /// Browsers only have one thread, so the future runs on the
+    /// browser's event loop between frames
+    #[cfg(target_arch = "wasm32")]
+    pub fn spawn(&self, fut: impl Future<Output = ()> + 'static) {
+        wasm_bindgen_futures::spawn_local(fut);
+    }
+
     pub fn exit_ok(&self) {
         self.exit_result.store(Some(Ok(())));
     }
@@ -163,40 +417,117 @@ impl Universe {
         self.exit_result.store(Some(Err(Box::new(e))));
     }
 
+    /// Starts or stops measuring how long each entity buffer and
+    /// singleton takes to process and flush every frame
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.profiler.set_enabled(enabled);
+    }
+
+    /// Sets what happens when a component panics while processing, for every type of entity
+    /// that has not been given its own policy with `set_entity_panic_policy`
+    ///
+    /// By default, panics are not caught
+    pub fn set_panic_policy(&mut self, policy: PanicPolicy) {
+        self.panic_policy = policy;
+    }
+
+    /// Sets what happens when a component of an entity of type `E` panics while processing
+    pub fn set_entity_panic_policy<E: Entity>(&mut self, policy: PanicPolicy) {
+        self.entity_panic_policies
+            .insert(TypeId::of::<EntityBufferStruct<E>>(), policy);
+    }
+
+    pub(crate) fn panic_policy(&self, buffer_type: TypeId) -> PanicPolicy {
+        self.entity_panic_policies
+            .get(&buffer_type)
+            .copied()
+            .unwrap_or(self.panic_policy)
+    }
+
+    /// Sets the stage that entities of type `E` are flushed in, which is `FlushStage::Update` by default
+    ///
+    /// Singletons choose their stage with `Singleton::flush_stage`
+    pub fn set_entity_flush_stage<E: Entity>(&mut self, stage: FlushStage) {
+        self.entity_flush_stages
+            .insert(TypeId::of::<EntityBufferStruct<E>>(), stage);
+    }
+
+    fn entity_flush_stage(&self, buffer_type: TypeId) -> FlushStage {
+        self.entity_flush_stages
+            .get(&buffer_type)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Gets the CPU timings of the last frame
+    ///
+    /// Empty unless profiling was enabled with `set_profiling`
+    pub fn last_frame_profile(&self) -> &FrameProfile {
+        self.profiler.last_frame()
+    }
+
     pub fn loop_once(&mut self) -> Option<Result<(), Box<dyn Error + Send + Sync>>> {
+        self.profiler.begin_phase();
+        let loading = self
+            .try_get_singleton::<LoadingState>()
+            .is_some_and(LoadingState::is_loading);
         join(
-            // Process all entities
+            // Process all entities, or only the loading screen while loading
             || unsafe {
                 self.entity_buffers
                     .get()
                     .par_iter()
-                    .for_each(|(_, x)| x.process(self))
+                    .filter(|(_, x)| !loading || x.has_component(TypeId::of::<LoadingScreen>()))
+                    .for_each(|(_, x)| {
+                        self.profiler
+                            .time(Phase::Process, x.entity_type_name(), || x.process(self))
+                    })
             },
             // Process all singletons
             || unsafe {
                 self.singletons
                     .get()
                     .par_iter()
-                    .for_each(|(_, x)| x.process(self))
+                    .for_each(|(_, x)| {
+                        self.profiler
+                            .time(Phase::Process, x.type_name(), || x.process(self))
+                    })
             },
         );
-
-        join(
-            // Flush entity buffers
-            || unsafe {
+        self.profiler.end_phase(Phase::Process);
+
+        self.profiler.begin_phase();
+        let shrink = self.shrink_entity_buffers.swap(false);
+        for stage in FlushStage::ALL {
+            // Entities read singletons while flushing, such as polygons reading the device
+            // of `Graphics`, so the singletons of a stage are only flushed once every entity
+            // of that stage has been flushed
+            unsafe {
                 self.entity_buffers
                     .get_mut()
                     .par_iter_mut()
-                    .for_each(|(_, x)| x.flush(self))
-            },
-            // Flush singletons
-            || unsafe {
+                    .filter(|(id, _)| self.entity_flush_stage(**id) == stage)
+                    .for_each(|(_, x)| {
+                        let name = x.entity_type_name();
+                        self.profiler.time(Phase::Flush, name, || x.flush(self));
+                        if shrink {
+                            x.shrink();
+                        }
+                    })
+            }
+            unsafe {
                 self.singletons
                     .get_mut()
                     .par_iter_mut()
-                    .for_each(|(_, x)| x.flush(self))
-            },
-        );
+                    .filter(|(_, x)| x.flush_stage() == stage)
+                    .for_each(|(_, x)| {
+                        let name = x.type_name();
+                        self.profiler.time(Phase::Flush, name, || x.flush(self))
+                    })
+            }
+        }
+        self.profiler.end_phase(Phase::Flush);
+        self.profiler.end_frame();
 
         if let Some(result) = self.exit_result.take() {
             return Some(result);
@@ -217,9 +548,31 @@ impl Universe {
             },
         );
 
+        for _ in 0..self.exclusive_systems.len() {
+            let Some(system) = self.exclusive_systems.pop() else {
+                break;
+            };
+            system(self);
+        }
+
         None
     }
 
+    /// Runs a single frame with the given delta
+    ///
+    /// Used when something other than `loop_many` decides when frames run,
+    /// such as the browser's event loop
+    pub fn loop_once_with_delta(&mut self, delta: Duration) -> Option<Result<(), Box<dyn Error + Send + Sync>>> {
+        self.delta_accurate = delta.as_secs_f64();
+        self.delta = delta.as_secs_f32();
+        self.loop_once()
+    }
+
+    /// Gets the pacer used by `DeltaStrategy::Adaptive`, which should be told whenever a frame is presented
+    pub fn frame_pacer(&self) -> &Arc<FramePacer> {
+        &self.pacer
+    }
+
     #[inline(always)]
     pub fn get_delta(&self) -> f32 {
         self.delta
@@ -230,6 +583,11 @@ impl Universe {
         self.delta_accurate
     }
 
+    /// Runs frames until the given count is reached or an exit is requested
+    ///
+    /// Not available in browsers, as this blocks. Use `loop_once_with_delta` from
+    /// the browser's event loop instead
+    #[cfg(not(target_arch = "wasm32"))]
     pub fn loop_many(
         &mut self,
         count: LoopCount,
@@ -264,6 +622,14 @@ impl Universe {
                         loop_helper.loop_sleep();
                     }
                 }
+                DeltaStrategy::Adaptive(target) => {
+                    let sleeper = SpinSleeper::default();
+                    let mut last_frame = Instant::now();
+                    loop {
+                        loop_once!();
+                        self.pace(target, &sleeper, &mut last_frame);
+                    }
+                }
             }
         };
 
@@ -298,10 +664,30 @@ impl Universe {
                     }
                 }
             }
+            DeltaStrategy::Adaptive(target) => {
+                let sleeper = SpinSleeper::default();
+                let mut last_frame = Instant::now();
+                for _i in 0..n {
+                    loop_once!();
+                    self.pace(target, &sleeper, &mut last_frame);
+                }
+            }
         }
 
         None
     }
+
+    /// Sleeps for the rest of the time the pacer gives the frame that just ended, then sets the delta
+    #[cfg(not(target_arch = "wasm32"))]
+    fn pace(&mut self, target: Duration, sleeper: &SpinSleeper, last_frame: &mut Instant) {
+        let last_interval = Duration::from_secs_f64(self.delta_accurate);
+        let frame_time = self.pacer.frame_finished(target, last_interval);
+        sleeper.sleep(frame_time.saturating_sub(last_frame.elapsed()));
+        let now = Instant::now();
+        self.delta_accurate = (now - *last_frame).as_secs_f64();
+        self.delta = self.delta_accurate as f32;
+        *last_frame = now;
+    }
 }
 
 pub enum LoopCount {
@@ -312,4 +698,10 @@ pub enum LoopCount {
 pub enum DeltaStrategy {
     FakeDelta(Duration),
     RealDelta(Duration),
+    /// Aims for frames to be presented with the given time between them, sleeping less
+    /// when frames are late and more when they are early
+    ///
+    /// Frames are measured by when they are presented if something reports presents to
+    /// `Universe::frame_pacer`, and by when the Universe finishes them otherwise
+    Adaptive(Duration),
 }
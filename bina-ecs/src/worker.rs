@@ -132,6 +132,11 @@ impl<T: WorkerMessage> Worker<T> {
         self.receiver.recv().ok().or_else(|| self.requeued.pop())
     }
 
+    /// Receives a message from the task if one is ready, without blocking
+    pub fn try_recv(&self) -> Option<T> {
+        self.receiver.try_recv().ok().or_else(|| self.requeued.pop())
+    }
+
     /// Requeue the given result so that it can be picked up by another thread
     ///
     /// There is no guarantee that another thread will receive the message before
@@ -0,0 +1,175 @@
+//! Light that reaches every polygon in the world, which can follow a day and night cycle
+//!
+//! ```ignore
+//! universe.queue_set_singleton(AmbientLight::day_night(240.0).with_time(0.3));
+//!
+//! // In any process
+//! for event in universe.read_events::<DayEvent>() {
+//!     match event {
+//!         DayEvent::Dawn => spawn_birds(),
+//!         DayEvent::Dusk => spawn_bats(),
+//!     }
+//! }
+//! ```
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use bina_ecs::{
+    crossbeam::atomic::AtomicCell,
+    easing::{Curve, Easing},
+    singleton::Singleton,
+    universe::Universe,
+};
+
+/// Sent when the time of day passes the dawn or dusk of an `AmbientLight`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DayEvent {
+    Dawn,
+    Dusk,
+}
+
+/// The color and brightness of the light that every polygon outside of the UI is multiplied by
+///
+/// The time of day goes from 0 at midnight, through 0.5 at noon, to 1 at the next midnight,
+/// where it wraps around. The color and intensity curves are sampled at the time of day,
+/// and emission is added on top, so emissive polygons still glow at night.
+/// Without this singleton, polygons are drawn at their own color
+pub struct AmbientLight {
+    color: Curve<[f32; 3]>,
+    intensity: Curve<f32>,
+    /// In seconds, or infinite for light that never changes
+    day_length: f32,
+    time: f32,
+    dawn: f32,
+    dusk: f32,
+    paused: AtomicBool,
+    pending_time: AtomicCell<Option<f32>>,
+}
+
+impl AmbientLight {
+    /// Light of a single color and intensity that does not change over time
+    pub fn constant(color: [f32; 3], intensity: f32) -> Self {
+        Self {
+            color: Curve::new(color),
+            intensity: Curve::new(intensity),
+            day_length: f32::INFINITY,
+            time: 0.5,
+            dawn: 0.25,
+            dusk: 0.75,
+            paused: AtomicBool::new(false),
+            pending_time: AtomicCell::new(None),
+        }
+    }
+
+    /// A day that lasts `day_length` seconds, with a dim blue night, a warm dawn and dusk,
+    /// and white light during the day. It starts at noon
+    pub fn day_night(day_length: f32) -> Self {
+        let night = [0.35, 0.4, 0.7];
+        let sunrise = [1.0, 0.7, 0.5];
+        let day = [1.0, 1.0, 1.0];
+        Self {
+            day_length,
+            color: Curve::new(night)
+                .with_key(0.2, night, Easing::Linear)
+                .with_key(0.25, sunrise, Easing::SineInOut)
+                .with_key(0.35, day, Easing::SineInOut)
+                .with_key(0.65, day, Easing::Linear)
+                .with_key(0.75, sunrise, Easing::SineInOut)
+                .with_key(0.8, night, Easing::SineInOut),
+            intensity: Curve::new(0.25)
+                .with_key(0.2, 0.25, Easing::Linear)
+                .with_key(0.35, 1.0, Easing::SineInOut)
+                .with_key(0.65, 1.0, Easing::Linear)
+                .with_key(0.8, 0.25, Easing::SineInOut),
+            ..Self::constant(day, 1.0)
+        }
+    }
+
+    /// Sets the color over the course of a day, where the times of the keyframes are times of day
+    pub fn with_color(mut self, color: Curve<[f32; 3]>) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Sets the intensity over the course of a day, where the times of the keyframes are times of day
+    pub fn with_intensity(mut self, intensity: Curve<f32>) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    /// Sets the time of day to start at
+    pub fn with_time(mut self, time: f32) -> Self {
+        self.time = time.rem_euclid(1.0);
+        self
+    }
+
+    /// Sets the times of day at which `DayEvent::Dawn` and `DayEvent::Dusk` are sent,
+    /// which are 0.25 and 0.75 by default
+    pub fn with_dawn_and_dusk(mut self, dawn: f32, dusk: f32) -> Self {
+        self.dawn = dawn;
+        self.dusk = dusk;
+        self
+    }
+
+    /// Gets the time of day, from 0 to 1
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    /// Whether the time of day is between dawn and dusk
+    pub fn is_day(&self) -> bool {
+        is_day(self.time, self.dawn, self.dusk)
+    }
+
+    /// Gets the color of the light at the current time of day, before it is multiplied by the intensity
+    pub fn color(&self) -> [f32; 3] {
+        self.color.sample(self.time)
+    }
+
+    pub fn intensity(&self) -> f32 {
+        self.intensity.sample(self.time)
+    }
+
+    /// Stops or resumes the passing of time
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    /// Sets the time of day during the next flush, without sending any events
+    pub fn queue_set_time(&self, time: f32) {
+        self.pending_time.store(Some(time.rem_euclid(1.0)));
+    }
+
+    /// The color that polygons are multiplied by
+    pub(crate) fn light(&self) -> [f32; 3] {
+        let intensity = self.intensity();
+        self.color().map(|x| x * intensity)
+    }
+}
+
+/// Dusk may come before dawn, such as for a day that wraps around midnight
+fn is_day(time: f32, dawn: f32, dusk: f32) -> bool {
+    if dawn <= dusk {
+        (dawn..dusk).contains(&time)
+    } else {
+        time >= dawn || time < dusk
+    }
+}
+
+impl Singleton for AmbientLight {
+    fn flush(&mut self, universe: &Universe) {
+        if let Some(time) = self.pending_time.take() {
+            self.time = time;
+            return;
+        }
+        if *self.paused.get_mut() {
+            return;
+        }
+        let was_day = self.is_day();
+        self.time = (self.time + universe.get_delta() / self.day_length).rem_euclid(1.0);
+        match (was_day, self.is_day()) {
+            (false, true) => universe.send_event(DayEvent::Dawn),
+            (true, false) => universe.send_event(DayEvent::Dusk),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,26 @@
+//! Helpers for running as an Android app
+pub use winit::platform::android::activity::AndroidApp;
+
+use bina_ecs::universe::{DeltaStrategy, LoopCount, Universe};
+use winit::{
+    event_loop::EventLoopBuilder,
+    platform::android::EventLoopBuilderExtAndroid,
+    window::WindowBuilder,
+};
+
+use crate::{Graphics, ScalingMode};
+
+/// Runs the given Universe inside the app's window
+///
+/// Should be called from the `android_main` function of the app, which is given the
+/// `AndroidApp`. Apps are suspended whenever they are backgrounded, which pauses the Universe
+pub async fn run_app(
+    universe: Universe,
+    count: LoopCount,
+    delta: DeltaStrategy,
+    app: AndroidApp,
+    scaling_mode: ScalingMode,
+) -> ! {
+    let event_loop = EventLoopBuilder::new().with_android_app(app).build();
+    Graphics::run_with_window(universe, count, delta, event_loop, WindowBuilder::new(), scaling_mode).await
+}
@@ -0,0 +1,80 @@
+//! Many small images packed into one texture, usually built by `load_atlas!`
+use bina_ecs::universe::Universe;
+use image::Rgba;
+
+use crate::{
+    polygon::Vector2,
+    texture::{SamplerOptions, Texture, TextureResource},
+    Graphics,
+};
+
+/// Where an image was packed inside of an atlas, in pixels
+#[derive(Clone, Copy, Debug)]
+pub struct AtlasRegion {
+    /// The file stem of the image
+    pub name: &'static str,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// A `W` by `H` texture containing many images, which can be drawn
+/// by mapping the texture coordinates of a polygon onto one of its regions
+///
+/// ```ignore
+/// load_atlas!(pub SPRITES = "sprites/*.png");
+/// // Later
+/// let (min, max) = SPRITES.uv_rect("hero").unwrap();
+/// ```
+pub struct TextureAtlas<const W: u32, const H: u32> {
+    texture: TextureResource<Rgba<u8>, W, H>,
+    regions: &'static [AtlasRegion],
+}
+
+impl<const W: u32, const H: u32> TextureAtlas<W, H> {
+    /// The regions must be sorted by name, which `load_atlas!` ensures
+    pub const fn new(
+        texture: TextureResource<Rgba<u8>, W, H>,
+        regions: &'static [AtlasRegion],
+    ) -> Self {
+        Self { texture, regions }
+    }
+
+    /// Sets how the atlas is sampled. Use `SamplerOptions::PIXEL_ART` for pixel art
+    pub const fn with_sampler(self, sampler: SamplerOptions) -> Self {
+        let Self { texture, regions } = self;
+        Self {
+            texture: texture.with_sampler(sampler),
+            regions,
+        }
+    }
+
+    pub fn try_get(&'static self, universe: &Universe, graphics: &Graphics) -> Option<Texture> {
+        self.texture.try_get(universe, graphics)
+    }
+
+    pub fn region(&self, name: &str) -> Option<&AtlasRegion> {
+        self.regions
+            .binary_search_by(|region| region.name.cmp(name))
+            .ok()
+            .map(|i| &self.regions[i])
+    }
+
+    pub fn regions(&self) -> &'static [AtlasRegion] {
+        self.regions
+    }
+
+    /// Gets the texture coordinates of the top left and bottom right corners of a region
+    pub fn uv_rect(&self, name: &str) -> Option<(Vector2, Vector2)> {
+        let region = self.region(name)?;
+        let size = Vector2::new(W as f32, H as f32);
+        let min = Vector2::new(region.x as f32, region.y as f32).component_div(&size);
+        let max = Vector2::new(
+            (region.x + region.width) as f32,
+            (region.y + region.height) as f32,
+        )
+        .component_div(&size);
+        Some((min, max))
+    }
+}
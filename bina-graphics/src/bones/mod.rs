@@ -0,0 +1,626 @@
+//! 2D characters made of sprites attached to a hierarchy of bones
+//!
+//! A `Rig` describes the bones, the parts drawn on them, inverse kinematics constraints and
+//! keyframed animations, and can be read from Spine JSON files with a `SpineLoader`.
+//! A `Skeleton2D` places a rig in the world, plays its animations, and draws every part
+//! as a polygon
+use bina_ecs::{
+    component::{
+        Component, ComponentField, NumberField, NumberFieldRef, Processable, StagedMutField,
+        StagedMutFieldRef,
+    },
+    reflect::{Field, Reflect},
+    triomphe::Arc,
+};
+use nalgebra::{Matrix3, Vector2};
+
+use crate::{
+    layers::Visible,
+    polygon::{Material, Polygon, Vector},
+    Graphics,
+};
+
+mod spine;
+
+pub use spine::SpineLoader;
+
+/// The position, rotation and scale of a bone relative to its parent
+#[derive(Clone, Copy)]
+pub struct BoneTransform {
+    pub position: Vector,
+    /// Counterclockwise, in radians
+    pub rotation: f32,
+    pub scale: Vector,
+}
+
+impl Default for BoneTransform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl BoneTransform {
+    pub const IDENTITY: Self = Self {
+        position: Vector::new(0.0, 0.0),
+        rotation: 0.0,
+        scale: Vector::new(1.0, 1.0),
+    };
+
+    /// Gets the matrix that scales, then rotates, then translates, matching how polygons are transformed
+    pub fn to_matrix(&self) -> Matrix3<f32> {
+        let (sin, cos) = self.rotation.sin_cos();
+        Matrix3::new(
+            cos * self.scale.x,
+            -sin * self.scale.y,
+            self.position.x,
+            sin * self.scale.x,
+            cos * self.scale.y,
+            self.position.y,
+            0.0,
+            0.0,
+            1.0,
+        )
+    }
+
+    /// Splits a matrix into a position, rotation and scale, ignoring any shear
+    fn from_matrix(matrix: &Matrix3<f32>) -> Self {
+        let scale_x = Vector2::new(matrix.m11, matrix.m21).norm();
+        let determinant = matrix.m11 * matrix.m22 - matrix.m12 * matrix.m21;
+        Self {
+            position: translation(matrix),
+            rotation: angle(matrix),
+            scale: Vector::new(
+                scale_x,
+                if scale_x > 0.0 {
+                    determinant / scale_x
+                } else {
+                    0.0
+                },
+            ),
+        }
+    }
+}
+
+/// Gets where a matrix moves the origin to
+fn translation(matrix: &Matrix3<f32>) -> Vector {
+    Vector::new(matrix.m13, matrix.m23)
+}
+
+/// Gets the angle of the x axis after it is transformed by a matrix
+fn angle(matrix: &Matrix3<f32>) -> f32 {
+    matrix.m21.atan2(matrix.m11)
+}
+
+/// Wraps an angle into -pi..pi, so that rotating by it takes the shortest way around
+fn wrap_angle(angle: f32) -> f32 {
+    (angle + std::f32::consts::PI).rem_euclid(std::f32::consts::TAU) - std::f32::consts::PI
+}
+
+pub struct Bone {
+    pub name: String,
+    /// Must come before this bone in `Rig::bones`
+    pub parent: Option<usize>,
+    /// The transform of the bone when it is not animated
+    pub rest: BoneTransform,
+    /// How far the bone reaches along its x axis, which is used by inverse kinematics
+    pub length: f32,
+}
+
+/// A rectangular sprite attached to a bone
+pub struct RigPart {
+    pub name: String,
+    pub bone: usize,
+    /// Where the center of the sprite is relative to its bone
+    pub transform: BoneTransform,
+    pub size: Vector,
+    /// The name of the image drawn on this part, which is used to pick its material
+    pub attachment: String,
+}
+
+/// Bends one or two bones so that the end of the chain reaches a target, such as for a foot or a hand
+pub struct IkConstraint {
+    pub name: String,
+    /// A single bone, or a parent bone followed by its child
+    pub bones: Vec<usize>,
+    /// The bone whose position is reached for, unless the `Skeleton2D` overrides it
+    pub target: usize,
+    /// Whether two bones bend counterclockwise at the joint between them
+    pub bend_positive: bool,
+    /// How much of the constraint is applied, from 0 to 1
+    pub mix: f32,
+}
+
+#[derive(Clone, Copy)]
+pub struct Keyframe<T> {
+    /// In seconds from the start of the animation
+    pub time: f32,
+    pub value: T,
+    /// Holds the value until the next keyframe, instead of blending towards it
+    pub stepped: bool,
+}
+
+/// Keyframes that are added onto the rest transform of a bone
+pub enum Timeline {
+    /// Added to the rest rotation, in radians
+    Rotate(Vec<Keyframe<f32>>),
+    /// Added to the rest position
+    Translate(Vec<Keyframe<Vector>>),
+    /// Multiplied with the rest scale
+    Scale(Vec<Keyframe<Vector>>),
+}
+
+pub struct BoneTimeline {
+    pub bone: usize,
+    pub timeline: Timeline,
+}
+
+pub struct BoneAnimation {
+    pub name: String,
+    /// The time of the last keyframe, in seconds
+    pub duration: f32,
+    pub timelines: Vec<BoneTimeline>,
+}
+
+/// Finds the keyframes on either side of `time`, and how far between them `time` is
+fn locate<T: Copy>(keyframes: &[Keyframe<T>], time: f32) -> Option<(T, T, f32)> {
+    let next = keyframes.partition_point(|x| x.time <= time);
+    if next == 0 {
+        let first = keyframes.first()?;
+        return Some((first.value, first.value, 0.0));
+    }
+    let previous = &keyframes[next - 1];
+    match keyframes.get(next) {
+        Some(next) if !previous.stepped => {
+            let span = next.time - previous.time;
+            let t = if span > 0.0 {
+                (time - previous.time) / span
+            } else {
+                0.0
+            };
+            Some((previous.value, next.value, t))
+        }
+        _ => Some((previous.value, previous.value, 0.0)),
+    }
+}
+
+impl BoneAnimation {
+    /// Writes the pose at `time` seconds into the transforms of the bones, where every bone
+    /// starts from its rest transform
+    pub fn sample(&self, rig: &Rig, time: f32, pose: &mut [BoneTransform]) {
+        for BoneTimeline { bone, timeline } in &self.timelines {
+            let (Some(transform), Some(rest)) = (pose.get_mut(*bone), rig.bones.get(*bone)) else {
+                continue;
+            };
+            let rest = rest.rest;
+            match timeline {
+                Timeline::Rotate(keyframes) => {
+                    if let Some((a, b, t)) = locate(keyframes, time) {
+                        transform.rotation = rest.rotation + a + wrap_angle(b - a) * t;
+                    }
+                }
+                Timeline::Translate(keyframes) => {
+                    if let Some((a, b, t)) = locate(keyframes, time) {
+                        transform.position = rest.position + a + (b - a) * t;
+                    }
+                }
+                Timeline::Scale(keyframes) => {
+                    if let Some((a, b, t)) = locate(keyframes, time) {
+                        let scale = a + (b - a) * t;
+                        transform.scale =
+                            Vector::new(rest.scale.x * scale.x, rest.scale.y * scale.y);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Everything needed to pose and draw a 2D character
+pub struct Rig {
+    /// Every bone comes after its parent
+    pub bones: Vec<Bone>,
+    /// Drawn in order, so later parts are drawn over earlier ones
+    pub parts: Vec<RigPart>,
+    /// Applied in order after the animation
+    pub ik: Vec<IkConstraint>,
+    pub animations: Vec<BoneAnimation>,
+}
+
+impl Rig {
+    pub fn find_bone(&self, name: &str) -> Option<usize> {
+        self.bones.iter().position(|bone| bone.name == name)
+    }
+
+    pub fn find_animation(&self, name: &str) -> Option<usize> {
+        self.animations
+            .iter()
+            .position(|animation| animation.name == name)
+    }
+
+    pub fn rest_pose(&self) -> Vec<BoneTransform> {
+        self.bones.iter().map(|bone| bone.rest).collect()
+    }
+
+    /// Gets the matrix of every bone in the world, with the whole rig placed at `root`
+    pub fn world_matrices(&self, root: &Matrix3<f32>, pose: &[BoneTransform]) -> Vec<Matrix3<f32>> {
+        let mut world: Vec<Matrix3<f32>> = Vec::with_capacity(self.bones.len());
+        for (i, bone) in self.bones.iter().enumerate() {
+            let parent = bone
+                .parent
+                .and_then(|parent| world.get(parent))
+                .unwrap_or(root);
+            let local = pose.get(i).unwrap_or(&bone.rest);
+            world.push(parent * local.to_matrix());
+        }
+        world
+    }
+
+    /// Rotates the bones of every IK constraint towards their targets,
+    /// returning the matrices of the bones in the world afterwards
+    ///
+    /// `targets` overrides the target of each constraint with a position in the world
+    pub fn apply_ik(
+        &self,
+        root: &Matrix3<f32>,
+        pose: &mut [BoneTransform],
+        targets: &[Option<Vector>],
+    ) -> Vec<Matrix3<f32>> {
+        let mut world = self.world_matrices(root, pose);
+        // Rotating a bone in the world rotates it the other way relative to a mirrored parent
+        let turn = |world: &[Matrix3<f32>], pose: &mut [BoneTransform], bone: usize, delta: f32| {
+            let parent = self.bones[bone]
+                .parent
+                .and_then(|parent| world.get(parent))
+                .unwrap_or(root);
+            let mirrored = parent.m11 * parent.m22 - parent.m12 * parent.m21 < 0.0;
+            pose[bone].rotation += if mirrored { -delta } else { delta };
+        };
+
+        for (i, constraint) in self.ik.iter().enumerate() {
+            let Some(target_matrix) = world.get(constraint.target) else {
+                continue;
+            };
+            let target = targets
+                .get(i)
+                .copied()
+                .flatten()
+                .unwrap_or_else(|| translation(target_matrix));
+            let mix = constraint.mix.clamp(0.0, 1.0);
+            match constraint.bones[..] {
+                [bone] if bone < pose.len() => {
+                    let to_target = target - translation(&world[bone]);
+                    let delta = wrap_angle(to_target.y.atan2(to_target.x) - angle(&world[bone]));
+                    turn(&world, pose, bone, delta * mix);
+                }
+                [parent, child] if parent < pose.len() && child < pose.len() => {
+                    let start = translation(&world[parent]);
+                    let joint = translation(&world[child]);
+                    let upper = (joint - start).length();
+                    let lower = self.bones[child].length
+                        * Vector2::new(world[child].m11, world[child].m21).norm();
+                    let to_target = target - start;
+                    let distance = to_target.length();
+                    if upper > 0.0 && lower > 0.0 && distance > 0.0 {
+                        // The law of cosines gives the angle between the upper bone and the target
+                        let cos = (upper * upper + distance * distance - lower * lower)
+                            / (2.0 * upper * distance);
+                        let bend = if constraint.bend_positive { -1.0 } else { 1.0 };
+                        let upper_angle =
+                            to_target.y.atan2(to_target.x) + bend * cos.clamp(-1.0, 1.0).acos();
+                        let to_joint = joint - start;
+                        let delta = wrap_angle(upper_angle - to_joint.y.atan2(to_joint.x));
+                        turn(&world, pose, parent, delta * mix);
+
+                        world = self.world_matrices(root, pose);
+                        let to_target = target - translation(&world[child]);
+                        let delta =
+                            wrap_angle(to_target.y.atan2(to_target.x) - angle(&world[child]));
+                        turn(&world, pose, child, delta * mix);
+                    }
+                }
+                _ => continue,
+            }
+            world = self.world_matrices(root, pose);
+        }
+        world
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Playback {
+    animation: Option<usize>,
+    /// In seconds from the start of the animation
+    time: f32,
+    looping: bool,
+}
+
+/// A `Rig` placed in the world, which plays its animations and draws its parts
+///
+/// ```ignore
+/// let mut skeleton = Skeleton2D::new(graphics, rig.clone(), |part| {
+///     Material::Texture(atlas.get(&part.attachment))
+/// });
+/// universe.queue_add_entity((skeleton.with_animation(rig.find_animation("walk"), true),));
+/// ```
+pub struct Skeleton2D {
+    rig: Arc<Rig>,
+    /// One for every part of the rig, in the same order
+    parts: Vec<Polygon>,
+    /// The matrix of every bone in the world, as of the last flush
+    world: Vec<Matrix3<f32>>,
+    origin: NumberField<Vector>,
+    rotation: NumberField<f32>,
+    scale: NumberField<Vector>,
+    /// The z of the first part, with every part after it one higher
+    z: NumberField<u32>,
+    /// How many seconds of animation are played each second
+    speed: NumberField<f32>,
+    visible: NumberField<Visible>,
+    playback: StagedMutField<Playback>,
+    /// Replaces the target of each IK constraint with a position in the world
+    ik_targets: StagedMutField<Vec<Option<Vector>>>,
+}
+
+impl Skeleton2D {
+    /// Creates a skeleton in its rest pose, where `material` picks what each part is drawn with
+    pub fn new(
+        graphics: &Graphics,
+        rig: Arc<Rig>,
+        mut material: impl FnMut(&RigPart) -> Material,
+    ) -> Self {
+        let parts = rig
+            .parts
+            .iter()
+            .enumerate()
+            .map(|(i, part)| {
+                let half = part.size * 0.5;
+                let mut polygon = Polygon::new(
+                    graphics,
+                    &[
+                        (Vector::new(-half.x, -half.y), Vector::new(0.0, 1.0)),
+                        (Vector::new(half.x, -half.y), Vector::new(1.0, 1.0)),
+                        (Vector::new(half.x, half.y), Vector::new(1.0, 0.0)),
+                        (Vector::new(-half.x, half.y), Vector::new(0.0, 0.0)),
+                    ],
+                    material(part),
+                )
+                .with_name(&part.name);
+                polygon.set_z(i as u32);
+                polygon
+            })
+            .collect();
+        let mut skeleton = Self {
+            world: Vec::new(),
+            parts,
+            origin: NumberField::new(Vector::new(0.0, 0.0)),
+            rotation: NumberField::new(0.0),
+            scale: NumberField::new(Vector::new(1.0, 1.0)),
+            z: NumberField::new(0),
+            speed: NumberField::new(1.0),
+            visible: NumberField::new(Visible::default()),
+            playback: StagedMutField::new(Playback::default()),
+            ik_targets: StagedMutField::new(vec![None; rig.ik.len()]),
+            rig,
+        };
+        skeleton.pose();
+        skeleton
+    }
+
+    /// Starts playing an animation of the rig from the beginning, or nothing if `animation` is `None`
+    pub fn with_animation(mut self, animation: Option<usize>, looping: bool) -> Self {
+        *self.playback.get_inner_mut() = Playback {
+            animation,
+            time: 0.0,
+            looping,
+        };
+        self.pose();
+        self
+    }
+
+    pub fn with_origin(mut self, origin: Vector) -> Self {
+        self.origin.set_inner(origin);
+        self.pose();
+        self
+    }
+
+    pub fn with_z(mut self, z: u32) -> Self {
+        self.z.set_inner(z);
+        self
+    }
+
+    pub fn rig(&self) -> &Arc<Rig> {
+        &self.rig
+    }
+
+    /// Samples the current animation, applies inverse kinematics, then moves every part onto its bone
+    fn pose(&mut self) {
+        let root = BoneTransform {
+            position: self.origin.get_inner(),
+            rotation: self.rotation.get_inner(),
+            scale: self.scale.get_inner(),
+        }
+        .to_matrix();
+        let mut pose = self.rig.rest_pose();
+        let playback = self.playback.get_inner();
+        if let Some(animation) = playback.animation.and_then(|i| self.rig.animations.get(i)) {
+            animation.sample(&self.rig, playback.time, &mut pose);
+        }
+        self.world = self
+            .rig
+            .apply_ik(&root, &mut pose, self.ik_targets.get_inner());
+
+        let z = self.z.get_inner();
+        let visible = self.visible.get_inner();
+        for (i, (polygon, part)) in self.parts.iter_mut().zip(&self.rig.parts).enumerate() {
+            let bone = self.world.get(part.bone).unwrap_or(&root);
+            let transform = BoneTransform::from_matrix(&(bone * part.transform.to_matrix()));
+            polygon.stage_transform(transform.position, transform.rotation, transform.scale);
+            polygon.set_z(z + i as u32);
+            polygon.set_visible(visible);
+        }
+    }
+}
+
+impl Component for Skeleton2D {
+    type Reference<'a> = Skeleton2DRef<'a>;
+
+    fn get_ref<'a>(&'a self) -> Self::Reference<'a> {
+        Skeleton2DRef {
+            rig: &self.rig,
+            parts: &self.parts,
+            world: &self.world,
+            origin: self.origin.get_ref(),
+            rotation: self.rotation.get_ref(),
+            scale: self.scale.get_ref(),
+            z: self.z.get_ref(),
+            speed: self.speed.get_ref(),
+            visible: self.visible.get_ref(),
+            playback: self.playback.get_ref(),
+            ik_targets: self.ik_targets.get_ref(),
+        }
+    }
+
+    fn flush<E: bina_ecs::entity::Entity>(
+        &mut self,
+        my_entity: bina_ecs::entity::EntityReference<bina_ecs::entity::Inaccessible<E>>,
+        universe: &bina_ecs::universe::Universe,
+    ) {
+        self.origin.process_modifiers();
+        self.rotation.process_modifiers();
+        self.scale.process_modifiers();
+        self.z.process_modifiers();
+        self.speed.process_modifiers();
+        self.visible.process_modifiers();
+        self.playback.process_modifiers();
+        self.ik_targets.process_modifiers();
+
+        let delta = universe.get_delta() * self.speed.get_inner();
+        let playback = self.playback.get_inner_mut();
+        if let Some(animation) = playback.animation.and_then(|i| self.rig.animations.get(i)) {
+            playback.time += delta;
+            if playback.looping && animation.duration > 0.0 {
+                playback.time = playback.time.rem_euclid(animation.duration);
+            } else {
+                playback.time = playback.time.clamp(0.0, animation.duration);
+            }
+        }
+
+        self.pose();
+        for polygon in &mut self.parts {
+            polygon.flush(my_entity.clone(), universe);
+        }
+    }
+}
+
+impl Reflect for Skeleton2D {
+    fn reflect_fields(&self, visitor: &mut dyn FnMut(&'static str, Field<'_>)) {
+        let playback = self.playback.get_inner();
+        visitor("origin", Field::Number(&self.origin));
+        visitor("rotation", Field::Number(&self.rotation));
+        visitor("scale", Field::Number(&self.scale));
+        visitor("z", Field::Number(&self.z));
+        visitor("speed", Field::Number(&self.speed));
+        visitor("visible", Field::Debug(&self.visible));
+        visitor(
+            "animation",
+            Field::Debug(
+                &playback
+                    .animation
+                    .and_then(|i| self.rig.animations.get(i))
+                    .map(|x| x.name.as_str()),
+            ),
+        );
+        visitor("time", Field::Debug(&playback.time));
+    }
+}
+
+impl Processable for Skeleton2D {
+    fn process<E: bina_ecs::entity::Entity>(
+        component: Self::Reference<'_>,
+        my_entity: bina_ecs::entity::EntityReference<E>,
+        universe: &bina_ecs::universe::Universe,
+    ) {
+        for polygon in component.parts {
+            Polygon::process(polygon.get_ref(), my_entity.clone(), universe);
+        }
+    }
+}
+
+pub struct Skeleton2DRef<'a> {
+    rig: &'a Arc<Rig>,
+    parts: &'a [Polygon],
+    world: &'a [Matrix3<f32>],
+    pub origin: NumberFieldRef<'a, Vector>,
+    pub rotation: NumberFieldRef<'a, f32>,
+    pub scale: NumberFieldRef<'a, Vector>,
+    pub z: NumberFieldRef<'a, u32>,
+    pub speed: NumberFieldRef<'a, f32>,
+    pub visible: NumberFieldRef<'a, Visible>,
+    playback: StagedMutFieldRef<'a, Playback>,
+    ik_targets: StagedMutFieldRef<'a, Vec<Option<Vector>>>,
+}
+
+impl<'a> Skeleton2DRef<'a> {
+    pub fn rig(&self) -> &Arc<Rig> {
+        self.rig
+    }
+
+    /// Gets the position of a bone in the world, as of the last flush
+    pub fn bone_position(&self, bone: usize) -> Option<Vector> {
+        self.world.get(bone).map(translation)
+    }
+
+    /// Gets the counterclockwise rotation of a bone in the world, as of the last flush
+    pub fn bone_rotation(&self, bone: usize) -> Option<f32> {
+        self.world.get(bone).map(angle)
+    }
+
+    /// Gets the index of the animation that is playing
+    pub fn animation(&self) -> Option<usize> {
+        self.playback.animation
+    }
+
+    /// Gets how far into the current animation the skeleton is, in seconds
+    pub fn time(&self) -> f32 {
+        self.playback.time
+    }
+
+    /// Checks if the current animation does not loop and has reached its end, or if nothing is playing
+    pub fn is_finished(&self) -> bool {
+        match self
+            .playback
+            .animation
+            .and_then(|i| self.rig.animations.get(i))
+        {
+            Some(animation) => !self.playback.looping && self.playback.time >= animation.duration,
+            None => true,
+        }
+    }
+
+    /// Starts playing an animation of the rig from the beginning after the current process frame ends
+    pub fn queue_play(&self, animation: usize, looping: bool) {
+        self.playback.queue_modifier(move |playback| {
+            *playback = Playback {
+                animation: Some(animation),
+                time: 0.0,
+                looping,
+            }
+        });
+    }
+
+    /// Stops playing after the current process frame ends, returning the skeleton to its rest pose
+    pub fn queue_stop(&self) {
+        self.playback
+            .queue_modifier(|playback| playback.animation = None);
+    }
+
+    /// Makes an IK constraint reach for a position in the world after the current process frame ends,
+    /// or for its target bone again if `target` is `None`
+    pub fn queue_set_ik_target(&self, constraint: usize, target: Option<Vector>) {
+        self.ik_targets.queue_modifier(move |targets| {
+            if let Some(x) = targets.get_mut(constraint) {
+                *x = target;
+            }
+        });
+    }
+}
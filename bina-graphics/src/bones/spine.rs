@@ -0,0 +1,210 @@
+//! Reads rigs from the JSON files exported by Spine, in either the 3.x or the 4.x layout
+//!
+//! Only bones, region attachments in the default skin, IK constraints and the rotate, translate
+//! and scale timelines of bones are read. Curves other than stepped are played linearly
+use bina_ecs::assets::AssetLoader;
+
+use super::{
+    Bone, BoneAnimation, BoneTimeline, BoneTransform, IkConstraint, Keyframe, Rig, RigPart,
+    Timeline,
+};
+use crate::{json::Json, polygon::Vector};
+
+/// Reads a position, rotation in degrees, and scale with the names Spine uses
+fn read_transform(json: &Json) -> BoneTransform {
+    BoneTransform {
+        position: Vector::new(
+            json.get("x").as_f32().unwrap_or(0.0),
+            json.get("y").as_f32().unwrap_or(0.0),
+        ),
+        rotation: json.get("rotation").as_f32().unwrap_or(0.0).to_radians(),
+        scale: Vector::new(
+            json.get("scaleX").as_f32().unwrap_or(1.0),
+            json.get("scaleY").as_f32().unwrap_or(1.0),
+        ),
+    }
+}
+
+/// Reads the keyframes of a timeline, where `value` reads the value of one keyframe
+fn read_keyframes<T>(json: &Json, mut value: impl FnMut(&Json) -> T) -> Vec<Keyframe<T>> {
+    let mut keyframes: Vec<_> = json
+        .as_array()
+        .iter()
+        .map(|keyframe| Keyframe {
+            time: keyframe.get("time").as_f32().unwrap_or(0.0),
+            value: value(keyframe),
+            stepped: keyframe.get("curve").as_str() == Some("stepped"),
+        })
+        .collect();
+    keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+    keyframes
+}
+
+impl Rig {
+    /// Reads a rig from a Spine JSON file
+    pub fn from_spine_json(text: &str) -> Result<Self, String> {
+        let json = Json::parse(text)?;
+
+        let mut bones: Vec<Bone> = Vec::new();
+        for bone in json.get("bones").as_array() {
+            let name = bone
+                .get("name")
+                .as_str()
+                .ok_or_else(|| "Bone has no name".to_string())?;
+            let parent = match bone.get("parent").as_str() {
+                Some(parent) => Some(
+                    bones
+                        .iter()
+                        .position(|x| x.name == parent)
+                        .ok_or_else(|| format!("Bone {name} comes before its parent {parent}"))?,
+                ),
+                None => None,
+            };
+            bones.push(Bone {
+                name: name.to_string(),
+                parent,
+                rest: read_transform(bone),
+                length: bone.get("length").as_f32().unwrap_or(0.0),
+            });
+        }
+        let find_bone = |name: Option<&str>| -> Result<usize, String> {
+            let name = name.ok_or_else(|| "Missing bone name".to_string())?;
+            bones
+                .iter()
+                .position(|x| x.name == name)
+                .ok_or_else(|| format!("Unknown bone {name}"))
+        };
+
+        // 3.x keeps skins in an object by name, while 4.x keeps them in an array
+        let skins = json.get("skins");
+        let default_skin = match skins {
+            Json::Array(skins) => skins
+                .iter()
+                .find(|skin| skin.get("name").as_str() == Some("default"))
+                .or(skins.first())
+                .map_or(&Json::Null, |skin| skin.get("attachments")),
+            _ => skins.get("default"),
+        };
+
+        let mut parts = Vec::new();
+        for slot in json.get("slots").as_array() {
+            let name = slot.get("name").as_str().unwrap_or_default();
+            let bone = find_bone(slot.get("bone").as_str())?;
+            let Some(attachment_name) = slot.get("attachment").as_str() else {
+                continue;
+            };
+            let attachment = default_skin.get(name).get(attachment_name);
+            if !matches!(attachment.get("type").as_str(), None | Some("region")) {
+                continue;
+            }
+            let (Some(width), Some(height)) = (
+                attachment.get("width").as_f32(),
+                attachment.get("height").as_f32(),
+            ) else {
+                continue;
+            };
+            parts.push(RigPart {
+                name: name.to_string(),
+                bone,
+                transform: read_transform(attachment),
+                size: Vector::new(width, height),
+                attachment: attachment
+                    .get("path")
+                    .as_str()
+                    .unwrap_or(attachment_name)
+                    .to_string(),
+            });
+        }
+
+        let mut ik = Vec::new();
+        for constraint in json.get("ik").as_array() {
+            let constraint_bones = constraint
+                .get("bones")
+                .as_array()
+                .iter()
+                .map(|bone| find_bone(bone.as_str()))
+                .collect::<Result<Vec<_>, _>>()?;
+            if !matches!(constraint_bones.len(), 1 | 2) {
+                return Err("IK constraints must have one or two bones".to_string());
+            }
+            ik.push(IkConstraint {
+                name: constraint
+                    .get("name")
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+                bones: constraint_bones,
+                target: find_bone(constraint.get("target").as_str())?,
+                bend_positive: constraint.get("bendPositive").as_bool().unwrap_or(true),
+                mix: constraint.get("mix").as_f32().unwrap_or(1.0),
+            });
+        }
+
+        let mut animations = Vec::new();
+        for (name, animation) in json.get("animations").as_object() {
+            let mut timelines = Vec::new();
+            for (bone_name, bone_timelines) in animation.get("bones").as_object() {
+                let bone = find_bone(Some(bone_name))?;
+                for (kind, keyframes) in bone_timelines.as_object() {
+                    let timeline = match kind.as_str() {
+                        // 3.x names the angle, while 4.x calls it the value
+                        "rotate" => Timeline::Rotate(read_keyframes(keyframes, |x| {
+                            x.get("angle")
+                                .as_f32()
+                                .or(x.get("value").as_f32())
+                                .unwrap_or(0.0)
+                                .to_radians()
+                        })),
+                        "translate" => Timeline::Translate(read_keyframes(keyframes, |x| {
+                            Vector::new(
+                                x.get("x").as_f32().unwrap_or(0.0),
+                                x.get("y").as_f32().unwrap_or(0.0),
+                            )
+                        })),
+                        "scale" => Timeline::Scale(read_keyframes(keyframes, |x| {
+                            Vector::new(
+                                x.get("x").as_f32().unwrap_or(1.0),
+                                x.get("y").as_f32().unwrap_or(1.0),
+                            )
+                        })),
+                        _ => continue,
+                    };
+                    timelines.push(BoneTimeline { bone, timeline });
+                }
+            }
+            let duration = timelines
+                .iter()
+                .filter_map(|x| match &x.timeline {
+                    Timeline::Rotate(keyframes) => keyframes.last().map(|x| x.time),
+                    Timeline::Translate(keyframes) | Timeline::Scale(keyframes) => {
+                        keyframes.last().map(|x| x.time)
+                    }
+                })
+                .fold(0.0, f32::max);
+            animations.push(BoneAnimation {
+                name: name.clone(),
+                duration,
+                timelines,
+            });
+        }
+
+        Ok(Self {
+            bones,
+            parts,
+            ik,
+            animations,
+        })
+    }
+}
+
+/// Loads Spine `.json` files through `Assets`
+pub struct SpineLoader;
+
+impl AssetLoader for SpineLoader {
+    type Asset = Rig;
+
+    fn load(&self, bytes: Vec<u8>) -> Result<Self::Asset, String> {
+        let text = String::from_utf8(bytes).map_err(|e| e.to_string())?;
+        Rig::from_spine_json(&text)
+    }
+}
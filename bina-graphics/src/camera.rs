@@ -1,13 +1,270 @@
-use bina_ecs::component::{NumberField, Component, NumberFieldRef, Processable};
+use bina_ecs::{
+    component::{
+        Component, ComponentField, NumberField, NumberFieldRef, Processable, StagedMutField,
+        StagedMutFieldRef,
+    },
+    rand::Rng,
+    reflect::{Field, Reflect},
+    rng::BufferedRng,
+    universe::Universe,
+};
+use nalgebra::{Matrix2, Vector2};
+use winit::dpi::PhysicalSize;
 
-use crate::polygon::Vector;
+use crate::{
+    layers::RenderLayers, polygon::Vector, transform::TransformHandle, Graphics, ScalingMode,
+};
 
 pub struct Camera {
     pub(crate) origin: NumberField<Vector>,
     pub(crate) scale: NumberField<Vector>,
     pub(crate) rotation: NumberField<f32>,
+    /// The layers this camera renders
+    pub(crate) layers: NumberField<RenderLayers>,
+    follow: StagedMutField<Option<CameraFollow>>,
+    /// Replaces the origin and rotation of the camera with those of a `Transform`
+    attached: StagedMutField<Option<TransformHandle>>,
+    bounds: StagedMutField<Option<CameraBounds>>,
+    shake: StagedMutField<ScreenShake>,
+    /// How much the camera is shaking, from 0 to 1
+    trauma: NumberField<f32>,
+    pub(crate) shake_offset: Vector,
+    pub(crate) shake_rotation: f32,
 }
 
+#[derive(Clone)]
+pub struct CameraFollow {
+    /// The transform of the entity being followed
+    pub target: TransformHandle,
+    /// How quickly the camera catches up to its target
+    ///
+    /// Higher values are snappier. Roughly 63% of the remaining
+    /// distance is covered every `1 / damping` seconds
+    pub damping: f32,
+}
+
+/// The area the center of a camera is confined to, in world coordinates
+#[derive(Clone, Copy)]
+pub struct CameraBounds {
+    pub min: Vector,
+    pub max: Vector,
+}
+
+/// Configuration for trauma based screen shake
+///
+/// The intensity of the shake is the square of the current trauma,
+/// so small amounts of trauma are barely noticeable
+#[derive(Clone, Copy)]
+pub struct ScreenShake {
+    /// The offset applied to the camera at full trauma, in world units
+    pub max_offset: Vector,
+    /// The rotation applied to the camera at full trauma, in radians
+    pub max_rotation: f32,
+    /// How much trauma is removed every second
+    pub decay: f32,
+}
+
+impl Default for ScreenShake {
+    fn default() -> Self {
+        Self {
+            max_offset: Vector::new(0.05, 0.05),
+            max_rotation: 0.05,
+            decay: 1.0,
+        }
+    }
+}
+
+impl Camera {
+    /// Creates a camera centered on `origin` that shows everything within
+    /// `half_extents` of its center
+    pub fn new(origin: Vector, half_extents: Vector) -> Self {
+        Self {
+            origin: NumberField::new(origin),
+            scale: NumberField::new(half_extents),
+            rotation: NumberField::new(0.0),
+            layers: NumberField::new(RenderLayers::ALL),
+            follow: StagedMutField::new(None),
+            attached: StagedMutField::new(None),
+            bounds: StagedMutField::new(None),
+            shake: StagedMutField::new(ScreenShake::default()),
+            trauma: NumberField::new(0.0),
+            shake_offset: Vector::new(0.0, 0.0),
+            shake_rotation: 0.0,
+        }
+    }
+
+    /// Gets the transform from world coordinates to normalized device coordinates
+    pub(crate) fn view_transform(&self, surface_size: PhysicalSize<u32>, scaling_mode: ScalingMode) -> ViewTransform {
+        ViewTransform::new(
+            self.origin.get_inner() + self.shake_offset,
+            self.scale.get_inner(),
+            self.rotation.get_inner() + self.shake_rotation,
+            surface_size,
+            scaling_mode,
+        )
+    }
+
+    /// Converts a position on the window in physical pixels, such as the position
+    /// of the cursor, into world coordinates
+    pub fn screen_to_world(&self, graphics: &Graphics, position: Vector) -> Vector {
+        self.view_transform(graphics.surface_size(), graphics.scaling_mode)
+            .screen_to_world(position)
+    }
+
+    /// Converts world coordinates into a position on the window in physical pixels
+    pub fn world_to_screen(&self, graphics: &Graphics, position: Vector) -> Vector {
+        self.view_transform(graphics.surface_size(), graphics.scaling_mode)
+            .world_to_screen(position)
+    }
+
+    /// Applies all staged changes, then moves the camera towards its
+    /// target, clamps it to its bounds, and updates the screen shake
+    pub(crate) fn update(&mut self, universe: &Universe) {
+        self.origin.process_modifiers();
+        self.scale.process_modifiers();
+        self.rotation.process_modifiers();
+        self.layers.process_modifiers();
+        self.follow.process_modifiers();
+        self.attached.process_modifiers();
+        self.bounds.process_modifiers();
+        self.shake.process_modifiers();
+        self.trauma.process_modifiers();
+
+        let delta = universe.get_delta();
+        let mut origin = self.origin.get_inner();
+
+        if let Some(transform) = self.attached.get_inner() {
+            origin = transform.position();
+            self.rotation.set_inner(transform.rotation());
+        } else if let Some(follow) = self.follow.get_inner() {
+            let target = follow.target.position();
+            let t = 1.0 - (-follow.damping * delta).exp();
+            origin = Vector::new(
+                origin.x + (target.x - origin.x) * t,
+                origin.y + (target.y - origin.y) * t,
+            );
+        }
+
+        if let Some(bounds) = self.bounds.get_inner() {
+            origin = Vector::new(
+                origin.x.clamp(bounds.min.x, bounds.max.x),
+                origin.y.clamp(bounds.min.y, bounds.max.y),
+            );
+        }
+        self.origin.set_inner(origin);
+
+        let shake = *self.shake.get_inner();
+        let trauma = self.trauma.get_inner().clamp(0.0, 1.0);
+        let intensity = trauma * trauma;
+        let mut rng = BufferedRng;
+        self.shake_offset = Vector::new(
+            shake.max_offset.x * intensity * rng.gen_range(-1.0..=1.0),
+            shake.max_offset.y * intensity * rng.gen_range(-1.0..=1.0),
+        );
+        self.shake_rotation = shake.max_rotation * intensity * rng.gen_range(-1.0..=1.0);
+        self.trauma
+            .set_inner((trauma - shake.decay * delta).max(0.0));
+    }
+}
+
+/// The number of floats in the uniform buffer of a camera
+pub(crate) const CAMERA_FLOATS: usize = 12;
+
+/// Maps world coordinates to normalized device coordinates
+#[derive(Clone, Copy)]
+pub(crate) struct ViewTransform {
+    matrix: Matrix2<f32>,
+    origin: Vector,
+    surface_size: PhysicalSize<u32>,
+}
+
+impl ViewTransform {
+    pub(crate) fn new(
+        origin: Vector,
+        half_extents: Vector,
+        rotation: f32,
+        surface_size: PhysicalSize<u32>,
+        scaling_mode: ScalingMode,
+    ) -> Self {
+        let aspect = surface_size.width.max(1) as f32 / surface_size.height.max(1) as f32;
+        // Stretch whichever axis is needed so that world units stay square
+        let (ax, ay) = match scaling_mode {
+            ScalingMode::Expand if aspect >= 1.0 => (1.0 / aspect, 1.0),
+            ScalingMode::Expand => (1.0, aspect),
+            ScalingMode::Shrink if aspect >= 1.0 => (1.0, aspect),
+            ScalingMode::Shrink => (1.0 / aspect, 1.0),
+        };
+        let (sin, cos) = rotation.sin_cos();
+        let camera = Matrix2::new(cos, -sin, sin, cos) * Matrix2::new(half_extents.x, 0.0, 0.0, half_extents.y);
+        let inverse = camera.try_inverse().unwrap_or_else(Matrix2::identity);
+
+        Self {
+            matrix: Matrix2::new(ax, 0.0, 0.0, ay) * inverse,
+            origin,
+            surface_size,
+        }
+    }
+
+    /// Maps UI coordinates, where the origin is the top left corner of the window,
+    /// y points down, and each unit is `pixels_per_unit` physical pixels
+    pub(crate) fn ui(surface_size: PhysicalSize<u32>, pixels_per_unit: f32) -> Self {
+        let width = surface_size.width.max(1) as f32 / pixels_per_unit;
+        let height = surface_size.height.max(1) as f32 / pixels_per_unit;
+        Self {
+            matrix: Matrix2::new(2.0 / width, 0.0, 0.0, -2.0 / height),
+            origin: Vector::new(width * 0.5, height * 0.5),
+            surface_size,
+        }
+    }
+
+    /// The floats of the camera uniform buffer, with the matrix in column major order,
+    /// followed by the light that polygons seen through this camera are multiplied by
+    pub(crate) fn to_uniform(self, light: [f32; 3]) -> [f32; CAMERA_FLOATS] {
+        [
+            self.matrix.m11,
+            self.matrix.m21,
+            self.matrix.m12,
+            self.matrix.m22,
+            self.origin.x,
+            self.origin.y,
+            // Padding, as the light is aligned to 16 bytes
+            0.0,
+            0.0,
+            light[0],
+            light[1],
+            light[2],
+            1.0,
+        ]
+    }
+
+    pub(crate) fn world_to_ndc(&self, position: Vector) -> Vector {
+        let v = self.matrix * Vector2::new(position.x - self.origin.x, position.y - self.origin.y);
+        Vector::new(v.x, v.y)
+    }
+
+    pub(crate) fn ndc_to_world(&self, position: Vector) -> Vector {
+        let inverse = self.matrix.try_inverse().unwrap_or_else(Matrix2::identity);
+        let v = inverse * Vector2::new(position.x, position.y);
+        Vector::new(v.x + self.origin.x, v.y + self.origin.y)
+    }
+
+    pub(crate) fn screen_to_world(&self, position: Vector) -> Vector {
+        let width = self.surface_size.width.max(1) as f32;
+        let height = self.surface_size.height.max(1) as f32;
+        self.ndc_to_world(Vector::new(
+            position.x / width * 2.0 - 1.0,
+            1.0 - position.y / height * 2.0,
+        ))
+    }
+
+    pub(crate) fn world_to_screen(&self, position: Vector) -> Vector {
+        let ndc = self.world_to_ndc(position);
+        Vector::new(
+            (ndc.x + 1.0) * 0.5 * self.surface_size.width as f32,
+            (1.0 - ndc.y) * 0.5 * self.surface_size.height as f32,
+        )
+    }
+}
 
 impl Component for Camera {
     type Reference<'a> = CameraRef<'a>;
@@ -17,10 +274,33 @@ impl Component for Camera {
             origin: self.origin.get_ref(),
             scale: self.scale.get_ref(),
             rotation: self.rotation.get_ref(),
+            layers: self.layers.get_ref(),
+            follow: self.follow.get_ref(),
+            attached: self.attached.get_ref(),
+            bounds: self.bounds.get_ref(),
+            shake: self.shake.get_ref(),
+            trauma: self.trauma.get_ref(),
         }
     }
+
+    fn flush<E: bina_ecs::entity::Entity>(
+        &mut self,
+        _my_entity: bina_ecs::entity::EntityReference<bina_ecs::entity::Inaccessible<E>>,
+        universe: &bina_ecs::universe::Universe,
+    ) {
+        self.update(universe);
+    }
 }
 
+impl Reflect for Camera {
+    fn reflect_fields(&self, visitor: &mut dyn FnMut(&'static str, Field<'_>)) {
+        visitor("origin", Field::Number(&self.origin));
+        visitor("scale", Field::Number(&self.scale));
+        visitor("rotation", Field::Number(&self.rotation));
+        visitor("trauma", Field::Number(&self.trauma));
+        visitor("layers", Field::Debug(&self.layers));
+    }
+}
 
 impl Processable for Camera {
     fn process<E: bina_ecs::entity::Entity>(
@@ -36,4 +316,45 @@ pub struct CameraRef<'a> {
     pub origin: NumberFieldRef<'a, Vector>,
     pub scale: NumberFieldRef<'a, Vector>,
     pub rotation: NumberFieldRef<'a, f32>,
+    pub layers: NumberFieldRef<'a, RenderLayers>,
+    pub follow: StagedMutFieldRef<'a, Option<CameraFollow>>,
+    pub attached: StagedMutFieldRef<'a, Option<TransformHandle>>,
+    pub bounds: StagedMutFieldRef<'a, Option<CameraBounds>>,
+    pub shake: StagedMutFieldRef<'a, ScreenShake>,
+    /// Add to this to shake the camera. It is clamped between 0 and 1
+    pub trauma: NumberFieldRef<'a, f32>,
+}
+
+impl<'a> CameraRef<'a> {
+    /// Starts smoothly following the position of a `Transform`
+    pub fn follow(&self, target: TransformHandle, damping: f32) {
+        self.follow
+            .queue_modifier(move |x| *x = Some(CameraFollow { target, damping }));
+    }
+
+    pub fn stop_following(&self) {
+        self.follow.queue_modifier(|x| *x = None);
+    }
+
+    /// Moves and rotates the camera with a `Transform`, ignoring any target it follows
+    ///
+    /// The camera is still confined to its bounds
+    pub fn attach(&self, transform: TransformHandle) {
+        self.attached
+            .queue_modifier(move |x| *x = Some(transform));
+    }
+
+    pub fn detach(&self) {
+        self.attached.queue_modifier(|x| *x = None);
+    }
+
+    /// Confines the center of the camera to the given area
+    pub fn set_bounds(&self, bounds: Option<CameraBounds>) {
+        self.bounds.queue_modifier(move |x| *x = bounds);
+    }
+
+    /// Adds trauma, making the camera shake more violently
+    pub fn add_trauma(&mut self, amount: f32) {
+        self.trauma += amount;
+    }
 }
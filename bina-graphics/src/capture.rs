@@ -0,0 +1,72 @@
+//! Reading rendered frames back from the GPU
+use image::RgbaImage;
+#[cfg(not(target_arch = "wasm32"))]
+use winit::dpi::PhysicalSize;
+
+/// Called with a copy of the next frame that is rendered
+pub(crate) type CaptureCallback = Box<dyn FnOnce(RgbaImage) + Send>;
+
+/// Copies the contents of the given `Rgba8` texture into an image
+///
+/// Blocks until the GPU has finished with every submitted frame.
+/// Returns `None` if the copy could not be mapped
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn read_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    size: PhysicalSize<u32>,
+) -> Option<RgbaImage> {
+    let unpadded_bytes_per_row = size.width * 4;
+    // Rows copied out of a texture must be aligned
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    let padded_bytes_per_row = (unpadded_bytes_per_row + align - 1) / align * align;
+
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("capture_buffer"),
+        size: padded_bytes_per_row as u64 * size.height as u64,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Capture Encoder"),
+    });
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::ImageCopyBuffer {
+            buffer: &buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_bytes_per_row),
+                rows_per_image: None,
+            },
+        },
+        wgpu::Extent3d {
+            width: size.width,
+            height: size.height,
+            depth_or_array_layers: 1,
+        },
+    );
+    queue.submit(std::iter::once(encoder.finish()));
+
+    let slice = buffer.slice(..);
+    let (sender, receiver) = std::sync::mpsc::channel();
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+        let _ = sender.send(result);
+    });
+    device.poll(wgpu::Maintain::Wait);
+    if let Err(e) = receiver.recv().ok()? {
+        log::error!("Failed to map captured frame: {e}");
+        return None;
+    }
+
+    let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * size.height) as usize);
+    {
+        let view = slice.get_mapped_range();
+        for row in view.chunks(padded_bytes_per_row as usize) {
+            pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+        }
+    }
+    buffer.unmap();
+    RgbaImage::from_raw(size.width, size.height, pixels)
+}
@@ -0,0 +1,297 @@
+//! Textures that stay compressed on the GPU, loaded from KTX2 files
+//!
+//! Only KTX2 files without supercompression are supported, so Basis Universal
+//! and zstd files must be transcoded ahead of time, for example with
+//! `ktx transcode --target bc7 hero.ktx2 hero.bc.ktx2`
+use bina_ecs::{
+    assets::AssetLoader, crossbeam::atomic::AtomicCell, parking_lot::Mutex, triomphe::Arc,
+};
+use wgpu::{AstcBlock, AstcChannel, TextureFormat};
+
+use crate::{
+    texture::{bind_texture, SamplerOptions, Texture, TextureInner, TextureRef},
+    Graphics,
+};
+
+const KTX2_IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+/// The size of the header and index that come before the level index
+const KTX2_HEADER_LEN: usize = 80;
+
+/// The families of compressed formats that GPUs support
+///
+/// Desktop GPUs support BC, while mobile GPUs support ETC2 and usually ASTC.
+/// Games should ship a copy of each texture for every family they target
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CompressionFamily {
+    Bc,
+    Astc,
+    Etc2,
+}
+
+impl CompressionFamily {
+    /// Ordered from the best quality for its size to the worst
+    const PREFERENCE: [Self; 3] = [Self::Bc, Self::Astc, Self::Etc2];
+
+    pub(crate) fn feature(self) -> wgpu::Features {
+        match self {
+            Self::Bc => wgpu::Features::TEXTURE_COMPRESSION_BC,
+            Self::Astc => wgpu::Features::TEXTURE_COMPRESSION_ASTC,
+            Self::Etc2 => wgpu::Features::TEXTURE_COMPRESSION_ETC2,
+        }
+    }
+
+    /// Every feature needed to use any family, which are requested if the adapter has them
+    pub(crate) fn all_features() -> wgpu::Features {
+        Self::PREFERENCE
+            .into_iter()
+            .fold(wgpu::Features::empty(), |features, family| features | family.feature())
+    }
+
+    /// Picks the best family supported by the given device
+    pub(crate) fn select(device: &wgpu::Device) -> Option<Self> {
+        Self::PREFERENCE
+            .into_iter()
+            .find(|family| device.features().contains(family.feature()))
+    }
+
+    /// A short name for the family, useful for naming the copies of each texture
+    ///
+    /// ```ignore
+    /// let family = graphics.compression_family().unwrap();
+    /// let hero = assets.load::<CompressedTextureAsset>(universe, &format!("hero.{}.ktx2", family.extension()));
+    /// ```
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Bc => "bc",
+            Self::Astc => "astc",
+            Self::Etc2 => "etc2",
+        }
+    }
+}
+
+/// Maps the Vulkan formats that wgpu can sample to their wgpu equivalents
+fn texture_format(vk_format: u32) -> Option<TextureFormat> {
+    let astc = |channel| TextureFormat::Astc {
+        block: AstcBlock::B4x4,
+        channel,
+    };
+    Some(match vk_format {
+        133 => TextureFormat::Bc1RgbaUnorm,
+        134 => TextureFormat::Bc1RgbaUnormSrgb,
+        135 => TextureFormat::Bc2RgbaUnorm,
+        136 => TextureFormat::Bc2RgbaUnormSrgb,
+        137 => TextureFormat::Bc3RgbaUnorm,
+        138 => TextureFormat::Bc3RgbaUnormSrgb,
+        145 => TextureFormat::Bc7RgbaUnorm,
+        146 => TextureFormat::Bc7RgbaUnormSrgb,
+        147 => TextureFormat::Etc2Rgb8Unorm,
+        148 => TextureFormat::Etc2Rgb8UnormSrgb,
+        149 => TextureFormat::Etc2Rgb8A1Unorm,
+        150 => TextureFormat::Etc2Rgb8A1UnormSrgb,
+        151 => TextureFormat::Etc2Rgba8Unorm,
+        152 => TextureFormat::Etc2Rgba8UnormSrgb,
+        157 => astc(AstcChannel::Unorm),
+        158 => astc(AstcChannel::UnormSrgb),
+        _ => return None,
+    })
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// A compressed texture and all of its mip levels, which is uploaded to the GPU the first time it is used
+pub struct CompressedTextureAsset {
+    format: TextureFormat,
+    width: u32,
+    height: u32,
+    /// Ordered from the largest level to the smallest
+    levels: Vec<Vec<u8>>,
+    sampler: AtomicCell<SamplerOptions>,
+    /// Recreated along with the device
+    texture: Mutex<Option<Arc<TextureInner>>>,
+}
+
+impl CompressedTextureAsset {
+    fn size(&self) -> wgpu::Extent3d {
+        wgpu::Extent3d {
+            width: self.width,
+            height: self.height,
+            depth_or_array_layers: 1,
+        }
+    }
+
+    /// Gets the texture, or `None` if the GPU does not support its format
+    pub fn get(&self, graphics: &Graphics) -> Option<Texture> {
+        if !graphics
+            .inner
+            .device
+            .features()
+            .contains(self.format.required_features())
+        {
+            return None;
+        }
+        let mut texture = self.texture.lock();
+        let texture = match &*texture {
+            Some(texture) if texture.generation == graphics.inner.generation => texture.clone(),
+            _ => texture.insert(Arc::new(self.upload(graphics))).clone(),
+        };
+        Some(Texture {
+            texture: TextureRef::Shared(texture),
+        })
+    }
+
+    fn upload(&self, graphics: &Graphics) -> TextureInner {
+        let texture = graphics
+            .inner
+            .device
+            .create_texture(&wgpu::TextureDescriptor {
+                size: self.size(),
+                mip_level_count: self.levels.len() as u32,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: self.format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                label: Some("compressed_texture"),
+                view_formats: &[],
+            });
+
+        let (block_width, block_height) = self.format.block_dimensions();
+        for (level, data) in self.levels.iter().enumerate() {
+            let level_size = self
+                .size()
+                .mip_level_size(level as u32, wgpu::TextureDimension::D2);
+            graphics.inner.queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &texture,
+                    mip_level: level as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                data,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(level_size.width.div_ceil(block_width) * self.block_len()),
+                    rows_per_image: Some(level_size.height.div_ceil(block_height)),
+                },
+                // Copies of compressed textures must cover whole blocks
+                level_size.physical_size(self.format),
+            );
+        }
+
+        bind_texture(graphics, texture, self.sampler.load(), None)
+    }
+
+    fn block_len(&self) -> u32 {
+        self.format
+            .block_size(None)
+            .expect("Compressed formats should have a block size")
+    }
+
+    /// Changes how this texture is sampled. `Texture`s that were already
+    /// taken out of this asset keep using the old sampler
+    pub fn set_sampler(&self, sampler: SamplerOptions) {
+        self.sampler.store(sampler);
+        *self.texture.lock() = None;
+    }
+
+    /// Gets the width and height of the largest mip level
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn format(&self) -> TextureFormat {
+        self.format
+    }
+}
+
+/// Loads KTX2 files containing BC, ETC2 or ASTC 4x4 textures
+#[derive(Default)]
+pub struct Ktx2Loader {
+    /// How every texture loaded by this loader is sampled, until it is changed with `CompressedTextureAsset::set_sampler`
+    pub sampler: SamplerOptions,
+}
+
+impl AssetLoader for Ktx2Loader {
+    type Asset = CompressedTextureAsset;
+
+    fn load(&self, bytes: Vec<u8>) -> Result<Self::Asset, String> {
+        if bytes.len() < KTX2_HEADER_LEN || bytes[..12] != KTX2_IDENTIFIER {
+            return Err("Not a KTX2 file".into());
+        }
+        let vk_format = read_u32(&bytes, 12);
+        let width = read_u32(&bytes, 20);
+        let height = read_u32(&bytes, 24);
+        let depth = read_u32(&bytes, 28);
+        let layers = read_u32(&bytes, 32);
+        let faces = read_u32(&bytes, 36);
+        let level_count = read_u32(&bytes, 40).max(1) as usize;
+        let supercompression = read_u32(&bytes, 44);
+
+        if supercompression != 0 {
+            return Err(format!(
+                "Supercompression scheme {supercompression} is not supported, transcode the file first"
+            ));
+        }
+        if depth > 1 || layers > 1 || faces != 1 {
+            return Err("Only 2D textures are supported".into());
+        }
+        if width == 0 || height == 0 {
+            return Err(format!("{width}x{height} is not a valid size"));
+        }
+        // Each level after the first halves the size, down to 1 pixel
+        let max_levels = (width.max(height).ilog2() + 1) as usize;
+        if level_count > max_levels {
+            return Err(format!(
+                "{level_count} levels is more than the {max_levels} a {width}x{height} texture can have"
+            ));
+        }
+        if level_count > (bytes.len() - KTX2_HEADER_LEN) / 24 {
+            return Err("Level index is truncated".into());
+        }
+        let format = texture_format(vk_format)
+            .ok_or_else(|| format!("Vulkan format {vk_format} is not supported"))?;
+
+        let mut asset = CompressedTextureAsset {
+            format,
+            width,
+            height,
+            levels: Vec::with_capacity(level_count),
+            sampler: AtomicCell::new(self.sampler),
+            texture: Mutex::new(None),
+        };
+        let (block_width, block_height) = format.block_dimensions();
+        let block_len = asset.block_len() as u64;
+        for level in 0..level_count {
+            let index = KTX2_HEADER_LEN + level * 24;
+            let offset = read_u64(&bytes, index);
+            let len = read_u64(&bytes, index + 8);
+            let level_size = asset
+                .size()
+                .mip_level_size(level as u32, wgpu::TextureDimension::D2);
+            let expected = (level_size.width.div_ceil(block_width) as u64)
+                .saturating_mul(level_size.height.div_ceil(block_height) as u64)
+                .saturating_mul(block_len);
+            if len != expected {
+                return Err(format!("Level {level} is {len} bytes instead of {expected}"));
+            }
+            let data = offset
+                .checked_add(len)
+                .and_then(|end| usize::try_from(end).ok())
+                .and_then(|end| bytes.get(offset as usize..end))
+                .ok_or_else(|| format!("Level {level} is truncated"))?;
+            asset.levels.push(data.to_vec());
+        }
+        Ok(asset)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["ktx2"]
+    }
+}
@@ -0,0 +1,44 @@
+//! The cursor over the window
+//!
+//! The icon of the system cursor, whether it is visible, and whether it is grabbed are changed
+//! through `Graphics` on the thread that runs the window. winit cannot turn an image into a
+//! system cursor, so a `CustomCursor` is drawn on top of the UI instead, at the latest cursor position
+use crate::{
+    polygon::{Material, Polygon, Vector},
+    texture::Texture,
+    Graphics,
+};
+
+pub use winit::window::{CursorGrabMode, CursorIcon};
+
+/// An image drawn in place of the system cursor
+///
+/// ```ignore
+/// let texture = CURSOR.try_get(universe, graphics).unwrap();
+/// graphics.set_custom_cursor(Some(CustomCursor::new(graphics, texture, Vector::new(32.0, 32.0), Vector::new(4.0, 2.0))));
+/// ```
+pub struct CustomCursor {
+    pub(crate) polygon: Polygon,
+}
+
+impl CustomCursor {
+    /// Creates a cursor that is `size` UI units large, where `hotspot` is the point
+    /// that clicks happen at, in UI units from the top left corner of the image
+    pub fn new(graphics: &Graphics, texture: Texture, size: Vector, hotspot: Vector) -> Self {
+        let (left, top) = (-hotspot.x, -hotspot.y);
+        let (right, bottom) = (size.x - hotspot.x, size.y - hotspot.y);
+        Self {
+            polygon: Polygon::new(
+                graphics,
+                &[
+                    (Vector::new(left, top), Vector::new(0.0, 0.0)),
+                    (Vector::new(right, top), Vector::new(1.0, 0.0)),
+                    (Vector::new(right, bottom), Vector::new(1.0, 1.0)),
+                    (Vector::new(left, bottom), Vector::new(0.0, 1.0)),
+                ],
+                Material::Texture(texture),
+            )
+            .with_name("custom cursor"),
+        }
+    }
+}
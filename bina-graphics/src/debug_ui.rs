@@ -0,0 +1,399 @@
+//! An immediate mode debug overlay powered by egui
+//!
+//! The overlay is built by the Universe through `Graphics::debug_ui`, and drawn by the
+//! render thread on top of everything else. Only available with the `egui` feature
+use bina_ecs::{
+    diagnostics::Diagnostics,
+    parking_lot::Mutex,
+    profiler::{FrameProfile, Phase},
+    reflect::{Field, ReflectRegistry},
+    triomphe::Arc,
+    universe::Universe,
+};
+use egui::{ClippedPrimitive, Context, FullOutput, PlatformOutput, RawInput, TexturesDelta};
+use egui_wgpu::{renderer::ScreenDescriptor, Renderer};
+use winit::{dpi::PhysicalSize, event::WindowEvent, window::Window};
+
+use crate::{
+    drawing::DrawInstruction,
+    font::{font_definitions, FontResource},
+    stats::GpuSpan,
+    GraphicsInner,
+};
+
+/// Everything the render thread needs to draw a single frame of the overlay
+pub(crate) struct DebugUiFrame {
+    ctx: Context,
+    paint_jobs: Vec<ClippedPrimitive>,
+    textures_delta: TexturesDelta,
+    platform_output: PlatformOutput,
+    pixels_per_point: f32,
+}
+
+/// The side of the overlay owned by the Universe
+pub(crate) struct DebugUi {
+    ctx: Context,
+    /// Serializes calls to `Graphics::debug_ui` from parallel components,
+    /// so that their windows are not laid out in an interleaved order
+    lock: Mutex<()>,
+    /// Input collected by the render thread since the last frame began
+    input: Arc<Mutex<RawInput>>,
+    /// Added with `Graphics::add_font`, kept to be added again when the context is replaced
+    fonts: Mutex<Vec<&'static FontResource>>,
+}
+
+impl DebugUi {
+    pub(crate) fn new(input: Arc<Mutex<RawInput>>) -> Self {
+        let ctx = Context::default();
+        ctx.begin_frame(RawInput::default());
+        Self {
+            ctx,
+            lock: Mutex::new(()),
+            input,
+            fonts: Mutex::default(),
+        }
+    }
+
+    /// Makes text use the given font where it has glyphs, starting from the next frame
+    pub(crate) fn add_font(&self, font: &'static FontResource) {
+        let mut fonts = self.fonts.lock();
+        if fonts.iter().any(|x| std::ptr::eq(*x, font)) {
+            return;
+        }
+        fonts.push(font);
+        self.ctx.set_fonts(font_definitions(&fonts));
+    }
+
+    pub(crate) fn run(&self, add_contents: impl FnOnce(&Context)) {
+        let _guard = self.lock.lock();
+        add_contents(&self.ctx);
+    }
+
+    /// Ends the current frame and begins the next one with the latest input, drawn with
+    /// `pixels_per_point` physical pixels per egui point on a surface of the given size
+    ///
+    /// Returns `None` if there is nothing to draw
+    pub(crate) fn end_frame(
+        &mut self,
+        pixels_per_point: f32,
+        surface_size: PhysicalSize<u32>,
+    ) -> Option<DebugUiFrame> {
+        let FullOutput {
+            platform_output,
+            textures_delta,
+            shapes,
+            ..
+        } = self.ctx.end_frame();
+        let paint_jobs = self.ctx.tessellate(shapes);
+        // The frame was laid out with the scale given to the previous call
+        let frame_pixels_per_point = self.ctx.pixels_per_point();
+
+        let mut input = std::mem::take(&mut *self.input.lock());
+        // egui-winit only knows the scale factor of the monitor, not the UI scale
+        let pixels_per_point = pixels_per_point.max(f32::EPSILON);
+        input.pixels_per_point = Some(pixels_per_point);
+        input.screen_rect = Some(egui::Rect::from_min_size(
+            egui::Pos2::ZERO,
+            egui::vec2(surface_size.width as f32, surface_size.height as f32) / pixels_per_point,
+        ));
+        self.ctx.begin_frame(input);
+
+        if paint_jobs.is_empty() && textures_delta.is_empty() {
+            return None;
+        }
+        Some(DebugUiFrame {
+            ctx: self.ctx.clone(),
+            paint_jobs,
+            textures_delta,
+            platform_output,
+            pixels_per_point: frame_pixels_per_point,
+        })
+    }
+
+    /// Replaces the context with a new one
+    ///
+    /// Called after the device is recreated, as egui only uploads
+    /// its font texture once per context
+    pub(crate) fn reset(&mut self) {
+        self.ctx = Context::default();
+        let fonts = self.fonts.get_mut();
+        if !fonts.is_empty() {
+            self.ctx.set_fonts(font_definitions(fonts));
+        }
+        self.ctx.begin_frame(RawInput::default());
+    }
+}
+
+/// The side of the overlay owned by the render thread
+pub(crate) struct DebugUiState {
+    state: egui_winit::State,
+    /// The context of the last received frame, used to decide if egui wants input
+    ctx: Option<Context>,
+    /// The frame to draw next. Frames that could not be drawn are merged into
+    /// the next one so that no texture updates are lost
+    frame: Option<DebugUiFrame>,
+    input: Arc<Mutex<RawInput>>,
+}
+
+impl DebugUiState {
+    pub(crate) fn new(window: &Window, input: Arc<Mutex<RawInput>>) -> Self {
+        let mut state = egui_winit::State::new(window);
+        state.set_pixels_per_point(egui_winit::native_pixels_per_point(window));
+        Self {
+            state,
+            ctx: None,
+            frame: None,
+            input,
+        }
+    }
+
+    /// Forwards a window event to egui
+    pub(crate) fn on_event(&mut self, window: &Window, event: &WindowEvent) {
+        let Some(ctx) = &self.ctx else {
+            return;
+        };
+        let _ = self.state.on_event(ctx, event);
+        let input = self.state.take_egui_input(window);
+        self.input.lock().append(input);
+    }
+
+    /// Takes the overlay frame out of the given draw instructions, if there is one
+    pub(crate) fn receive(&mut self, instructions: &mut Vec<DrawInstruction>) {
+        // The overlay is always queued last
+        if !matches!(instructions.last(), Some(DrawInstruction::DebugUi(_))) {
+            return;
+        }
+        let Some(DrawInstruction::DebugUi(mut frame)) = instructions.pop() else {
+            unreachable!()
+        };
+        if let Some(old) = self.frame.take() {
+            let mut textures_delta = old.textures_delta;
+            textures_delta.append(frame.textures_delta);
+            frame.textures_delta = textures_delta;
+        }
+        self.ctx = Some(frame.ctx.clone());
+        self.frame = Some(frame);
+    }
+
+    /// Draws the last received frame on top of the given view
+    ///
+    /// Returns command buffers that must be submitted before the given encoder
+    pub(crate) fn render(
+        &mut self,
+        renderer: &mut Renderer,
+        graphics: &GraphicsInner,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        surface_size: PhysicalSize<u32>,
+    ) -> Vec<wgpu::CommandBuffer> {
+        let Some(DebugUiFrame {
+            ctx,
+            paint_jobs,
+            textures_delta,
+            platform_output,
+            pixels_per_point,
+        }) = self.frame.take()
+        else {
+            return Vec::new();
+        };
+        self.state
+            .handle_platform_output(graphics.window(), &ctx, platform_output);
+
+        let screen_descriptor = ScreenDescriptor {
+            size_in_pixels: [surface_size.width, surface_size.height],
+            pixels_per_point,
+        };
+        for (id, image_delta) in &textures_delta.set {
+            renderer.update_texture(&graphics.device, &graphics.queue, *id, image_delta);
+        }
+        let commands = renderer.update_buffers(
+            &graphics.device,
+            &graphics.queue,
+            encoder,
+            &paint_jobs,
+            &screen_descriptor,
+        );
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Debug UI Render Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        // Draw on top of the scene
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            renderer.render(&mut render_pass, &paint_jobs, &screen_descriptor);
+        }
+        for id in &textures_delta.free {
+            renderer.free_texture(id);
+        }
+        commands
+    }
+}
+
+/// Shows the statistics collected by the `Diagnostics` singleton,
+/// along with the CPU and GPU profiles of the last measured frame
+pub(crate) fn diagnostics_window(
+    ctx: &Context,
+    diagnostics: &Diagnostics,
+    cpu_profile: &FrameProfile,
+    gpu_profile: &[GpuSpan],
+) {
+    let frame_times = diagnostics.frame_times();
+
+    egui::Window::new("Diagnostics")
+        .default_pos([8.0, 8.0])
+        .resizable(false)
+        .show(ctx, |ui| {
+            ui.label(format!("FPS: {:.0}", frame_times.fps));
+            egui::Grid::new("frame_times").show(ui, |ui| {
+                for (name, time) in [
+                    ("average", frame_times.average),
+                    ("p50", frame_times.p50),
+                    ("p95", frame_times.p95),
+                    ("p99", frame_times.p99),
+                    ("worst", frame_times.worst),
+                ] {
+                    ui.label(name);
+                    ui.label(format!("{:.2} ms", time * 1000.0));
+                    ui.end_row();
+                }
+            });
+
+            if let Some(pacing) = diagnostics.frame_pacing() {
+                ui.separator();
+                egui::Grid::new("frame_pacing").show(ui, |ui| {
+                    for (name, time) in [
+                        ("target", pacing.target),
+                        ("sleep target", pacing.sleep_target),
+                        ("interval", pacing.average_interval),
+                        ("jitter", pacing.jitter),
+                        ("worst deviation", pacing.worst_deviation),
+                    ] {
+                        ui.label(name);
+                        ui.label(format!("{:.2} ms", time * 1000.0));
+                        ui.end_row();
+                    }
+                    ui.label("missed frames");
+                    ui.label(pacing.missed_frames.to_string());
+                    ui.end_row();
+                });
+            }
+
+            ui.separator();
+            ui.collapsing("Entities", |ui| {
+                egui::Grid::new("entity_counts").show(ui, |ui| {
+                    for memory in diagnostics.entity_memory() {
+                        ui.label(memory.name);
+                        ui.label(memory.len.to_string());
+                        ui.label(format!("{:.1} KiB", memory.bytes as f64 / 1024.0));
+                        ui.end_row();
+                    }
+                });
+            });
+
+            ui.separator();
+            egui::Grid::new("counters").show(ui, |ui| {
+                for (name, value) in diagnostics.counters() {
+                    ui.label(*name);
+                    ui.label(format!("{value:.2}"));
+                    ui.end_row();
+                }
+            });
+
+            if !cpu_profile.spans.is_empty() {
+                ui.separator();
+                ui.collapsing("CPU", |ui| {
+                    egui::Grid::new("cpu_profile").show(ui, |ui| {
+                        for (phase, total) in [
+                            (Phase::Process, cpu_profile.process),
+                            (Phase::Flush, cpu_profile.flush),
+                        ] {
+                            ui.strong(format!("{phase:?}"));
+                            ui.strong(format_ms(total));
+                            ui.end_row();
+                            for span in cpu_profile.spans.iter().filter(|x| x.phase == phase) {
+                                ui.label(span.name);
+                                ui.label(format_ms(span.duration));
+                                ui.end_row();
+                            }
+                        }
+                    });
+                });
+            }
+            if !gpu_profile.is_empty() {
+                ui.separator();
+                ui.collapsing("GPU", |ui| {
+                    egui::Grid::new("gpu_profile").show(ui, |ui| {
+                        for span in gpu_profile {
+                            ui.label(span.name);
+                            ui.label(format_ms(span.duration));
+                            ui.end_row();
+                        }
+                    });
+                });
+            }
+            if !diagnostics.messages().is_empty() {
+                ui.separator();
+                ui.collapsing(format!("Messages ({})", diagnostics.messages().len()), |ui| {
+                    for message in diagnostics.messages().iter().rev() {
+                        ui.horizontal_wrapped(|ui| {
+                            ui.strong(message.source);
+                            ui.label(&message.message);
+                        });
+                    }
+                });
+            }
+        });
+}
+
+/// Lists every registered entity along with the fields of its components,
+/// allowing number fields to be edited
+pub(crate) fn inspector_window(ctx: &Context, universe: &Universe, registry: &ReflectRegistry) {
+    egui::Window::new("Inspector")
+        .default_pos([8.0, 256.0])
+        .vscroll(true)
+        .show(ctx, |ui| {
+            for entity_name in registry.entity_names() {
+                ui.collapsing(entity_name, |ui| {
+                    registry.for_each_entity(universe, entity_name, |index, entity| {
+                        ui.collapsing(format!("#{index}"), |ui| {
+                            entity.reflect_components(&mut |component_name, component| {
+                                ui.strong(component_name);
+                                egui::Grid::new((entity_name, index, component_name)).show(ui, |ui| {
+                                    component.reflect_fields(&mut |field_name, field| {
+                                        ui.label(field_name);
+                                        match field {
+                                            Field::Number(number) => {
+                                                ui.horizontal(|ui| {
+                                                    for i in 0..number.components() {
+                                                        let mut value = number.get(i);
+                                                        if ui.add(egui::DragValue::new(&mut value).speed(0.01)).changed() {
+                                                            number.queue_set(i, value);
+                                                        }
+                                                    }
+                                                });
+                                            }
+                                            Field::Debug(value) => {
+                                                ui.label(format!("{value:?}"));
+                                            }
+                                        }
+                                        ui.end_row();
+                                    });
+                                });
+                            });
+                        });
+                    });
+                });
+            }
+        });
+}
+
+fn format_ms(duration: std::time::Duration) -> String {
+    format!("{:.3} ms", duration.as_secs_f64() * 1000.0)
+}
@@ -1,5 +1,23 @@
-use crate::renderers::DrawPolygon;
+use std::sync::atomic::AtomicBool;
+
+use bina_ecs::triomphe::Arc;
+
+use crate::{gizmos::GizmoBatch, minimap::OffscreenPass, renderers::DrawPolygon, texture_array::SpriteBatch};
+#[cfg(feature = "egui")]
+use crate::debug_ui::DebugUiFrame;
+#[cfg(feature = "3d")]
+use crate::three_d::renderer::DrawMesh;
 
 pub(crate) enum DrawInstruction {
     DrawPolygon(DrawPolygon),
+    Gizmos(GizmoBatch),
+    SpriteBatch(SpriteBatch),
+    /// Creates every render pipeline ahead of time, then sets the flag
+    Warmup(Arc<AtomicBool>),
+    /// Drawn before everything else, as other polygons may sample its target
+    Offscreen(OffscreenPass),
+    #[cfg(feature = "3d")]
+    DrawMesh(DrawMesh),
+    #[cfg(feature = "egui")]
+    DebugUi(DebugUiFrame),
 }
@@ -0,0 +1,235 @@
+use bina_ecs::time::Duration;
+
+#[cfg(not(bina_loom))]
+use bina_ecs::parking_lot::{Condvar, Mutex};
+#[cfg(bina_loom)]
+use loom_sync::{Condvar, Mutex};
+
+use crate::drawing::DrawInstruction;
+
+struct Slots<T> {
+    /// Instructions that the Universe has finished, waiting to be drawn
+    filled: Option<Vec<T>>,
+    /// A drawn buffer that the Universe can fill again
+    empty: Option<Vec<T>>,
+    /// Set once either side has gone away, so the other side stops waiting
+    closed: bool,
+}
+
+/// Passes buffers of draw instructions back and forth between the Universe and the render thread
+///
+/// There are exactly two buffers, so the Universe is never more than one frame ahead of the
+/// render thread. Both sides sleep while waiting for a buffer instead of spinning
+///
+/// Anything can be exchanged, so that the exchange can be tested without a GPU
+pub(crate) struct InstructionExchange<T = DrawInstruction> {
+    slots: Mutex<Slots<T>>,
+    changed: Condvar,
+}
+
+impl<T> InstructionExchange<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            slots: Mutex::new(Slots {
+                filled: None,
+                empty: Some(Vec::new()),
+                closed: false,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Blocks until a buffer can be filled, or gives `None` once the render thread has closed the exchange
+    pub(crate) fn take_empty(&self) -> Option<Vec<T>> {
+        let mut slots = self.slots.lock();
+        loop {
+            if slots.closed {
+                return None;
+            }
+            if let Some(buffer) = slots.empty.take() {
+                return Some(buffer);
+            }
+            self.changed.wait(&mut slots);
+        }
+    }
+
+    /// Gives a filled buffer to the render thread
+    pub(crate) fn send_filled(&self, buffer: Vec<T>) {
+        self.slots.lock().filled = Some(buffer);
+        self.changed.notify_all();
+    }
+
+    /// Takes the filled buffer if there is one, without waiting
+    #[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+    pub(crate) fn try_take_filled(&self) -> Option<Vec<T>> {
+        self.slots.lock().filled.take()
+    }
+
+    /// Waits up to `timeout` for a filled buffer, giving `None` if there is none by then
+    /// or if the Universe has closed the exchange
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    pub(crate) fn take_filled_timeout(&self, timeout: Duration) -> Option<Vec<T>> {
+        let mut slots = self.slots.lock();
+        if slots.filled.is_none() && !slots.closed {
+            self.changed.wait_for(&mut slots, timeout);
+        }
+        slots.filled.take()
+    }
+
+    /// Gives a drawn buffer back to the Universe
+    pub(crate) fn return_empty(&self, mut buffer: Vec<T>) {
+        buffer.clear();
+        self.slots.lock().empty = Some(buffer);
+        self.changed.notify_all();
+    }
+
+    /// Wakes the other side and stops it from waiting again
+    pub(crate) fn close(&self) {
+        self.slots.lock().closed = true;
+        self.changed.notify_all();
+    }
+}
+
+/// Wraps the locks of loom, which checks every interleaving of the threads using them,
+/// to look like those of parking_lot
+///
+/// Loom never lets a wait time out, but it does try waking up waiting threads spuriously,
+/// which is what a timeout looks like to the code waiting
+#[cfg(bina_loom)]
+mod loom_sync {
+    use std::ops::{Deref, DerefMut};
+
+    use bina_ecs::time::Duration;
+
+    pub(super) struct Mutex<T>(loom::sync::Mutex<T>);
+
+    impl<T> Mutex<T> {
+        pub(super) fn new(value: T) -> Self {
+            Self(loom::sync::Mutex::new(value))
+        }
+
+        pub(super) fn lock(&self) -> MutexGuard<'_, T> {
+            MutexGuard(Some(self.0.lock().unwrap()))
+        }
+    }
+
+    /// Only empty while a `Condvar` is waiting with it
+    pub(super) struct MutexGuard<'a, T>(Option<loom::sync::MutexGuard<'a, T>>);
+
+    impl<'a, T> Deref for MutexGuard<'a, T> {
+        type Target = T;
+
+        fn deref(&self) -> &T {
+            self.0.as_ref().unwrap()
+        }
+    }
+
+    impl<'a, T> DerefMut for MutexGuard<'a, T> {
+        fn deref_mut(&mut self) -> &mut T {
+            self.0.as_mut().unwrap()
+        }
+    }
+
+    pub(super) struct Condvar(loom::sync::Condvar);
+
+    impl Condvar {
+        pub(super) fn new() -> Self {
+            Self(loom::sync::Condvar::new())
+        }
+
+        pub(super) fn wait<T>(&self, guard: &mut MutexGuard<'_, T>) {
+            let inner = guard.0.take().unwrap();
+            guard.0 = Some(self.0.wait(inner).unwrap());
+        }
+
+        pub(super) fn wait_for<T>(&self, guard: &mut MutexGuard<'_, T>, _timeout: Duration) {
+            self.wait(guard);
+        }
+
+        pub(super) fn notify_all(&self) {
+            self.0.notify_all();
+        }
+    }
+}
+
+/// Run with `RUSTFLAGS="--cfg bina_loom" cargo test -p bina-graphics --release --lib exchange`
+#[cfg(all(test, bina_loom))]
+mod tests {
+    use loom::{sync::Arc, thread};
+
+    use super::InstructionExchange;
+
+    /// How many frames the Universe sends in each model. Every extra frame
+    /// makes the number of interleavings to check much larger
+    const FRAMES: u32 = 2;
+
+    /// Pretends to be the Universe, sending `FRAMES` frames and then closing the exchange
+    fn send_frames(exchange: &InstructionExchange<u32>) {
+        for frame in 0..FRAMES {
+            let mut buffer = exchange.take_empty().expect("Closed by the render thread");
+            assert!(
+                buffer.is_empty(),
+                "A buffer was returned without being cleared"
+            );
+            buffer.push(frame);
+            exchange.send_filled(buffer);
+        }
+        exchange.close();
+    }
+
+    #[test]
+    fn frames_arrive_in_order() {
+        loom::model(|| {
+            let exchange = Arc::new(InstructionExchange::new());
+            let universe = {
+                let exchange = exchange.clone();
+                thread::spawn(move || send_frames(&exchange))
+            };
+
+            // The render thread
+            let mut drawn = Vec::new();
+            while drawn.len() < FRAMES as usize {
+                // Waking up without a frame is the same as timing out, which the render thread retries
+                let Some(buffer) = exchange.take_filled_timeout(Default::default()) else {
+                    thread::yield_now();
+                    continue;
+                };
+                drawn.extend_from_slice(&buffer);
+                exchange.return_empty(buffer);
+            }
+            universe.join().unwrap();
+
+            assert_eq!(drawn, (0..FRAMES).collect::<Vec<_>>());
+        });
+    }
+
+    /// Closing from the render thread must wake the Universe if it is waiting for a buffer
+    #[test]
+    fn close_wakes_the_universe() {
+        loom::model(|| {
+            let exchange = Arc::new(InstructionExchange::<u32>::new());
+            let universe = {
+                let exchange = exchange.clone();
+                thread::spawn(move || {
+                    let mut sent = 0;
+                    while let Some(buffer) = exchange.take_empty() {
+                        exchange.send_filled(buffer);
+                        sent += 1;
+                    }
+                    sent
+                })
+            };
+
+            // Draws one frame, then goes away without giving the buffer back
+            let drawn = exchange.take_filled_timeout(Default::default());
+            exchange.close();
+
+            assert!(drawn.is_some());
+            assert_eq!(
+                universe.join().unwrap(),
+                1,
+                "The Universe filled a buffer it never got back"
+            );
+        });
+    }
+}
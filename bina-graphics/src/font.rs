@@ -0,0 +1,63 @@
+//! Fonts compiled into the binary, which text drawn through the debug overlay can use
+
+/// A TrueType or OpenType font compiled into the binary by `load_font!`
+///
+/// The tables of the font are checked at compile time, so it is never rejected when it is used
+pub struct FontResource {
+    path: &'static str,
+    family: &'static str,
+    bytes: &'static [u8],
+    glyph_count: u16,
+}
+
+impl FontResource {
+    pub const fn new(path: &'static str, family: &'static str, bytes: &'static [u8], glyph_count: u16) -> Self {
+        Self {
+            path,
+            family,
+            bytes,
+            glyph_count,
+        }
+    }
+
+    pub const fn path(&self) -> &'static str {
+        self.path
+    }
+
+    /// The name of the font's family, as read from its name table
+    pub const fn family(&self) -> &'static str {
+        self.family
+    }
+
+    pub const fn bytes(&self) -> &'static [u8] {
+        self.bytes
+    }
+
+    pub const fn glyph_count(&self) -> u16 {
+        self.glyph_count
+    }
+}
+
+/// Builds egui's fonts with every given font in front of the defaults, so they are used
+/// wherever they have a glyph. Each font can also be chosen by its family name
+#[cfg(feature = "egui")]
+pub(crate) fn font_definitions(fonts: &[&'static FontResource]) -> egui::FontDefinitions {
+    let mut definitions = egui::FontDefinitions::default();
+    for (i, font) in fonts.iter().enumerate() {
+        let name = font.family.to_owned();
+        definitions
+            .font_data
+            .insert(name.clone(), egui::FontData::from_static(font.bytes));
+        for family in [egui::FontFamily::Proportional, egui::FontFamily::Monospace] {
+            definitions
+                .families
+                .entry(family)
+                .or_default()
+                .insert(i, name.clone());
+        }
+        definitions
+            .families
+            .insert(egui::FontFamily::Name(name.as_str().into()), vec![name]);
+    }
+    definitions
+}
@@ -0,0 +1,366 @@
+//! Shapes that are drawn for a single frame, for seeing things like velocities, colliders and paths
+//!
+//! Gizmos can be added from any `process`, and are drawn during the next flush on the debug layer,
+//! on top of everything else. Lines keep the same thickness in pixels however far the camera is zoomed
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use atomic_float::AtomicF32;
+use bina_ecs::{crossbeam::queue::SegQueue, universe::Universe};
+use image::Rgba;
+use wgpu::util::DeviceExt;
+
+use crate::{camera::ViewTransform, polygon::Vector, Graphics, GraphicsInner};
+
+/// How many lines a circle is drawn with
+const CIRCLE_SEGMENTS: usize = 32;
+/// The longest that the head of an arrow can be, in multiples of the thickness
+const MAX_ARROW_HEAD: f32 = 5.0;
+
+/// A position followed by a linear color
+pub(crate) const GIZMO_VERTEX_BUFFER_DESCRIPTOR: wgpu::VertexBufferLayout<'static> =
+    wgpu::VertexBufferLayout {
+        array_stride: std::mem::size_of::<f32>() as wgpu::BufferAddress * 6,
+        step_mode: wgpu::VertexStepMode::Vertex,
+        attributes: &[
+            wgpu::VertexAttribute {
+                offset: 0,
+                shader_location: 0,
+                format: wgpu::VertexFormat::Float32x2,
+            },
+            wgpu::VertexAttribute {
+                offset: std::mem::size_of::<f32>() as wgpu::BufferAddress * 2,
+                shader_location: 1,
+                format: wgpu::VertexFormat::Float32x4,
+            },
+        ],
+    };
+
+/// Used by `universe.gizmos()` when there is no `Graphics` singleton
+static DISABLED: Gizmos = Gizmos::new(false);
+
+enum Gizmo {
+    Line {
+        from: Vector,
+        to: Vector,
+        color: Rgba<u8>,
+    },
+    Circle {
+        center: Vector,
+        radius: f32,
+        color: Rgba<u8>,
+    },
+    Rect {
+        center: Vector,
+        size: Vector,
+        color: Rgba<u8>,
+    },
+    Arrow {
+        from: Vector,
+        to: Vector,
+        color: Rgba<u8>,
+    },
+    Text {
+        position: Vector,
+        text: String,
+        color: Rgba<u8>,
+    },
+}
+
+/// Immediate mode drawing of lines, shapes and text in world coordinates
+///
+/// Like the debug UI, gizmos must be added every frame that they should be visible.
+/// Nothing is drawn if the active camera cannot see `RenderLayers::DEBUG`
+///
+/// ```ignore
+/// universe.gizmos().arrow(position, position + velocity, Rgba([0, 255, 0, 255]));
+/// ```
+pub struct Gizmos {
+    shapes: SegQueue<Gizmo>,
+    enabled: AtomicBool,
+    /// The thickness of every line in physical pixels
+    thickness: AtomicF32,
+}
+
+impl Gizmos {
+    pub(crate) const fn new(enabled: bool) -> Self {
+        Self {
+            shapes: SegQueue::new(),
+            enabled: AtomicBool::new(enabled),
+            thickness: AtomicF32::new(2.0),
+        }
+    }
+
+    fn push(&self, gizmo: Gizmo) {
+        if self.is_enabled() {
+            self.shapes.push(gizmo);
+        }
+    }
+
+    pub fn line(&self, from: Vector, to: Vector, color: Rgba<u8>) {
+        self.push(Gizmo::Line { from, to, color });
+    }
+
+    /// Draws the outline of a circle
+    pub fn circle(&self, center: Vector, radius: f32, color: Rgba<u8>) {
+        self.push(Gizmo::Circle {
+            center,
+            radius,
+            color,
+        });
+    }
+
+    /// Draws the outline of an axis aligned rectangle
+    pub fn rect(&self, center: Vector, size: Vector, color: Rgba<u8>) {
+        self.push(Gizmo::Rect {
+            center,
+            size,
+            color,
+        });
+    }
+
+    /// Draws a line with an arrow head at `to`
+    pub fn arrow(&self, from: Vector, to: Vector, color: Rgba<u8>) {
+        self.push(Gizmo::Arrow { from, to, color });
+    }
+
+    /// Draws text centered on the given position
+    ///
+    /// Text is drawn with the debug UI, so nothing is drawn unless the `egui` feature is enabled
+    pub fn text(&self, position: Vector, text: impl Into<String>, color: Rgba<u8>) {
+        self.push(Gizmo::Text {
+            position,
+            text: text.into(),
+            color,
+        });
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Ignores every gizmo added while disabled, such as to hide them in release builds
+    ///
+    /// Enabled by default
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Gets the thickness of every line in physical pixels
+    pub fn thickness(&self) -> f32 {
+        self.thickness.load(Ordering::Relaxed)
+    }
+
+    /// Defaults to 2 physical pixels
+    pub fn set_thickness(&self, thickness: f32) {
+        self.thickness.store(thickness, Ordering::Relaxed);
+    }
+
+    /// Discards every gizmo added since the last frame
+    pub(crate) fn clear(&self) {
+        while self.shapes.pop().is_some() {}
+    }
+
+    /// Tessellates every gizmo added since the last call
+    pub(crate) fn take_frame(&self, graphics: &GraphicsInner, view: &ViewTransform) -> GizmoFrame {
+        // The length of one pixel in world units
+        let pixel = (view.screen_to_world(Vector::new(1.0, 0.0))
+            - view.screen_to_world(Vector::new(0.0, 0.0)))
+        .length();
+        let mut builder = GizmoBuilder {
+            vertices: Vec::new(),
+            indices: Vec::new(),
+            thickness: self.thickness() * pixel,
+        };
+        let mut text = Vec::new();
+
+        while let Some(gizmo) = self.shapes.pop() {
+            match gizmo {
+                Gizmo::Line { from, to, color } => builder.line(from, to, linear(color)),
+                Gizmo::Circle {
+                    center,
+                    radius,
+                    color,
+                } => {
+                    let color = linear(color);
+                    let point = |i: usize| {
+                        let angle = i as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::TAU;
+                        center + Vector::new(angle.cos(), angle.sin()) * radius
+                    };
+                    for i in 0..CIRCLE_SEGMENTS {
+                        builder.line(point(i), point(i + 1), color);
+                    }
+                }
+                Gizmo::Rect {
+                    center,
+                    size,
+                    color,
+                } => {
+                    let color = linear(color);
+                    let half = size * 0.5;
+                    let corners = [
+                        center + Vector::new(-half.x, -half.y),
+                        center + Vector::new(half.x, -half.y),
+                        center + Vector::new(half.x, half.y),
+                        center + Vector::new(-half.x, half.y),
+                    ];
+                    for i in 0..4 {
+                        builder.line(corners[i], corners[(i + 1) % 4], color);
+                    }
+                }
+                Gizmo::Arrow { from, to, color } => {
+                    let color = linear(color);
+                    builder.line(from, to, color);
+                    let delta = to - from;
+                    let length = delta.length();
+                    if length > 0.0 {
+                        let head = (length * 0.25).min(builder.thickness * MAX_ARROW_HEAD);
+                        let back = delta * (-head / length);
+                        // Each side of the head is the shaft turned back by 30 degrees
+                        let (sin, cos) = std::f32::consts::FRAC_PI_6.sin_cos();
+                        for sin in [sin, -sin] {
+                            let side = Vector::new(
+                                back.x * cos - back.y * sin,
+                                back.x * sin + back.y * cos,
+                            );
+                            builder.line(to, to + side, color);
+                        }
+                    }
+                }
+                Gizmo::Text {
+                    position,
+                    text: string,
+                    color,
+                } => text.push(GizmoText {
+                    position: view.world_to_screen(position),
+                    text: string,
+                    color,
+                }),
+            }
+        }
+
+        let batch = (!builder.indices.is_empty()).then(|| GizmoBatch {
+            vertices: graphics
+                .device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("gizmo_vertex_buffer"),
+                    contents: bytemuck::cast_slice(&builder.vertices),
+                    usage: wgpu::BufferUsages::VERTEX,
+                }),
+            indices: graphics
+                .device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("gizmo_index_buffer"),
+                    contents: bytemuck::cast_slice(&builder.indices),
+                    usage: wgpu::BufferUsages::INDEX,
+                }),
+            index_count: builder.indices.len() as u32,
+            generation: graphics.generation,
+        });
+        GizmoFrame { batch, text }
+    }
+}
+
+/// Gets the gizmos of the `Graphics` singleton
+pub trait UniverseGizmos {
+    /// Gets the gizmos that are drawn during the next flush
+    ///
+    /// If there is no `Graphics` singleton, every gizmo is ignored
+    fn gizmos(&self) -> &Gizmos;
+}
+
+impl UniverseGizmos for Universe {
+    fn gizmos(&self) -> &Gizmos {
+        self.try_get_singleton::<Graphics>()
+            .map(Graphics::gizmos)
+            .unwrap_or(&DISABLED)
+    }
+}
+
+struct GizmoBuilder {
+    vertices: Vec<[f32; 6]>,
+    indices: Vec<u32>,
+    /// The thickness of every line in world units
+    thickness: f32,
+}
+
+impl GizmoBuilder {
+    /// Adds a line as a quad that is `thickness` wide
+    fn line(&mut self, from: Vector, to: Vector, color: [f32; 4]) {
+        let delta = to - from;
+        let length = delta.length();
+        if length == 0.0 {
+            return;
+        }
+        let half = self.thickness * 0.5 / length;
+        let normal = Vector::new(-delta.y * half, delta.x * half);
+        let start = self.vertices.len() as u32;
+        for point in [from + normal, from - normal, to - normal, to + normal] {
+            let [r, g, b, a] = color;
+            self.vertices.push([point.x, point.y, r, g, b, a]);
+        }
+        self.indices
+            .extend([0, 1, 2, 0, 2, 3].into_iter().map(|i| start + i));
+    }
+}
+
+/// Converts an sRGB color into the linear color that the scene is drawn in
+pub(crate) fn linear(color: Rgba<u8>) -> [f32; 4] {
+    let channel = |x: u8| {
+        let x = x as f32 / 255.0;
+        if x <= 0.04045 {
+            x / 12.92
+        } else {
+            ((x + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    let [r, g, b, a] = color.0;
+    [channel(r), channel(g), channel(b), a as f32 / 255.0]
+}
+
+/// Everything added to the gizmos in a frame
+pub(crate) struct GizmoFrame {
+    pub(crate) batch: Option<GizmoBatch>,
+    #[cfg_attr(not(feature = "egui"), allow(dead_code))]
+    pub(crate) text: Vec<GizmoText>,
+}
+
+/// The lines and shapes of a frame, drawn with a single draw call
+pub(crate) struct GizmoBatch {
+    pub(crate) vertices: wgpu::Buffer,
+    pub(crate) indices: wgpu::Buffer,
+    pub(crate) index_count: u32,
+    pub(crate) generation: u64,
+}
+
+#[cfg_attr(not(feature = "egui"), allow(dead_code))]
+pub(crate) struct GizmoText {
+    /// Where the text is centered, in physical pixels
+    position: Vector,
+    text: String,
+    color: Rgba<u8>,
+}
+
+/// Draws the text of a frame behind every debug window
+#[cfg(feature = "egui")]
+pub(crate) fn paint_text(ctx: &egui::Context, text: Vec<GizmoText>) {
+    let painter = ctx.layer_painter(egui::LayerId::new(
+        egui::Order::Background,
+        egui::Id::new("gizmos"),
+    ));
+    let pixels_per_point = ctx.pixels_per_point();
+    for GizmoText {
+        position,
+        text,
+        color,
+    } in text
+    {
+        let [r, g, b, a] = color.0;
+        painter.text(
+            egui::pos2(position.x / pixels_per_point, position.y / pixels_per_point),
+            egui::Align2::CENTER_CENTER,
+            text,
+            egui::FontId::monospace(14.0),
+            egui::Color32::from_rgba_unmultiplied(r, g, b, a),
+        );
+    }
+}
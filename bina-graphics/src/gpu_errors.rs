@@ -0,0 +1,110 @@
+//! Errors reported by wgpu, such as invalid shaders, pipelines or draw calls
+//!
+//! By default, wgpu panics on any error that is not caught. Instead, every error is logged,
+//! sent into the Universe as a `GpuError` event, and reported to `Diagnostics` so that it
+//! shows up in the debug overlay. Creating the renderers and rendering each frame happen
+//! inside error scopes, so their errors say which of the two they came from
+use std::fmt::Display;
+
+use bina_ecs::{crossbeam::queue::SegQueue, triomphe::Arc};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GpuErrorKind {
+    /// Something was used incorrectly, which is a bug in the code or the data
+    Validation,
+    OutOfMemory,
+}
+
+/// Sent into the Universe whenever wgpu reports an error
+#[derive(Clone, Debug)]
+pub struct GpuError {
+    pub kind: GpuErrorKind,
+    pub message: String,
+    /// What was happening when the error occurred, if it was caught in an error scope
+    pub scope: Option<&'static str>,
+}
+
+impl GpuError {
+    fn new(error: wgpu::Error, scope: Option<&'static str>) -> Self {
+        let (kind, message) = match error {
+            wgpu::Error::Validation { description, .. } => (GpuErrorKind::Validation, description),
+            wgpu::Error::OutOfMemory { source } => (GpuErrorKind::OutOfMemory, source.to_string()),
+        };
+        Self {
+            kind,
+            message,
+            scope,
+        }
+    }
+}
+
+impl Display for GpuError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self.kind {
+            GpuErrorKind::Validation => "Validation error",
+            GpuErrorKind::OutOfMemory => "Out of memory",
+        };
+        match self.scope {
+            Some(scope) => write!(f, "{kind} while {scope}: {}", self.message),
+            None => write!(f, "{kind}: {}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for GpuError {}
+
+/// The errors of a device that have not been sent into the Universe yet
+#[derive(Clone, Default)]
+pub(crate) struct GpuErrors {
+    pending: Arc<SegQueue<GpuError>>,
+}
+
+impl GpuErrors {
+    /// Catches every error on the device that is not caught by an error scope
+    pub(crate) fn install(&self, device: &wgpu::Device) {
+        let errors = self.clone();
+        device.on_uncaptured_error(Box::new(move |error| {
+            errors.push(GpuError::new(error, None))
+        }));
+    }
+
+    fn push(&self, error: GpuError) {
+        log::error!("{error}");
+        self.pending.push(error);
+    }
+
+    pub(crate) fn pop(&self) -> Option<GpuError> {
+        self.pending.pop()
+    }
+
+    /// Starts catching validation errors, which are attributed to `scope` once `pop_scope` is called
+    pub(crate) fn push_scope(device: &wgpu::Device) {
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+    }
+
+    /// Stops catching errors, recording the first error caught since the matching `push_scope`
+    pub(crate) fn pop_scope(&self, device: &wgpu::Device, scope: &'static str) {
+        let future = device.pop_error_scope();
+        let errors = self.clone();
+        let report = move |error: Option<wgpu::Error>| {
+            if let Some(error) = error {
+                errors.push(GpuError::new(error, Some(scope)));
+            }
+        };
+        // Browsers report errors asynchronously
+        #[cfg(target_arch = "wasm32")]
+        wasm_bindgen_futures::spawn_local(async move { report(future.await) });
+        // Native devices already know the result of the scope, so the future is ready immediately
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            use std::{
+                future::Future,
+                pin::pin,
+                task::{Context, Poll, Waker},
+            };
+            if let Poll::Ready(error) = pin!(future).poll(&mut Context::from_waker(Waker::noop())) {
+                report(error);
+            }
+        }
+    }
+}
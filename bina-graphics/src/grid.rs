@@ -0,0 +1,219 @@
+//! Drawing the cells of a `Grid2D`, either as a color per cell or as a tile from an atlas per cell
+//!
+//! ```ignore
+//! let grid = Grid2D::new(320, 180, Cell::Air).with_rule(falling_sand);
+//! let renderer = GridRenderer::colors(graphics, &grid, 0.1, |cell| match cell {
+//!     Cell::Air => Rgba([0, 0, 0, 0]),
+//!     Cell::Sand => Rgba([220, 190, 120, 255]),
+//! });
+//! universe.queue_set_singleton(grid);
+//! universe.queue_add_entity((renderer,));
+//! ```
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use bina_ecs::{
+    component::{Component, Processable},
+    grid::Grid2D,
+    rayon::prelude::*,
+    reflect::{Field, Reflect},
+};
+use image::{Rgba, RgbaImage};
+use lyon::lyon_tessellation::VertexBuffers;
+
+use crate::{
+    polygon::{Material, Polygon, PolygonRef, Vector, Vector2},
+    texture::{SamplerOptions, TextureAsset, TextureRect},
+    Graphics,
+};
+
+type ColorOf<T> = Box<dyn Fn(&T) -> Rgba<u8> + Send + Sync>;
+type TileOf<T> = Box<dyn Fn(&T) -> Option<(Vector2, Vector2)> + Send + Sync>;
+
+enum CellStyle<T> {
+    /// One pixel of the texture for every cell, which is stretched over the grid
+    Colors {
+        texture: TextureAsset,
+        color: ColorOf<T>,
+    },
+    /// One quad for every cell that has a tile
+    Tiles { cell_size: f32, tile: TileOf<T> },
+}
+
+/// Draws the `Grid2D<T>` singleton whenever it changes
+///
+/// The bottom left corner of the grid is at the origin of the polygon
+pub struct GridRenderer<T: Clone + Send + Sync + 'static> {
+    polygon: Polygon,
+    style: CellStyle<T>,
+    /// The generation of the grid that was last drawn
+    drawn: AtomicU64,
+}
+
+impl<T: Clone + Send + Sync + 'static> GridRenderer<T> {
+    /// Draws every cell as a square of the color returned by `color`, which is best for grids
+    /// with many cells such as falling sand
+    pub fn colors(
+        graphics: &Graphics,
+        grid: &Grid2D<T>,
+        cell_size: f32,
+        color: impl Fn(&T) -> Rgba<u8> + Send + Sync + 'static,
+    ) -> Self {
+        let (width, height) = (grid.width() as u32, grid.height() as u32);
+        let mut image = RgbaImage::new(width, height);
+        image.copy_from_slice(&pixels_of(grid, &color));
+        let texture = TextureAsset::new(image, SamplerOptions::PIXEL_ART);
+        let (x, y) = (width as f32 * cell_size, height as f32 * cell_size);
+        let polygon = Polygon::new(
+            graphics,
+            &[
+                (Vector::new(0.0, 0.0), Vector::new(0.0, 1.0)),
+                (Vector::new(x, 0.0), Vector::new(1.0, 1.0)),
+                (Vector::new(x, y), Vector::new(1.0, 0.0)),
+                (Vector::new(0.0, y), Vector::new(0.0, 0.0)),
+            ],
+            Material::Texture(texture.get(graphics)),
+        );
+        Self {
+            polygon,
+            style: CellStyle::Colors {
+                texture,
+                color: Box::new(color),
+            },
+            drawn: AtomicU64::new(grid.generation()),
+        }
+    }
+
+    /// Draws every cell as a tile of `material`, where `tile` returns the texture coordinates
+    /// of the top left and bottom right corners of the tile, such as from `TextureAtlas::uv_rect`.
+    /// Cells without a tile are not drawn
+    pub fn tiles(
+        graphics: &Graphics,
+        material: Material,
+        cell_size: f32,
+        tile: impl Fn(&T) -> Option<(Vector2, Vector2)> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            polygon: Polygon::from_geometry(graphics, VertexBuffers::new(), material),
+            style: CellStyle::Tiles {
+                cell_size,
+                tile: Box::new(tile),
+            },
+            // The tiles are made when the grid is first drawn
+            drawn: AtomicU64::new(u64::MAX),
+        }
+    }
+}
+
+/// The color of every cell, flipped so that the top row of the grid is the first row of the image
+fn pixels_of<T: Clone + Send + Sync + 'static>(
+    grid: &Grid2D<T>,
+    color: &(impl Fn(&T) -> Rgba<u8> + Sync + ?Sized),
+) -> Vec<u8> {
+    let mut pixels = vec![0; grid.cells().len() * 4];
+    if grid.width() == 0 {
+        return pixels;
+    }
+    pixels
+        .par_chunks_mut(grid.width() * 4)
+        .zip(grid.cells().par_chunks(grid.width()).rev())
+        .for_each(|(pixels, cells)| {
+            for (pixel, cell) in pixels.chunks_exact_mut(4).zip(cells) {
+                pixel.copy_from_slice(&color(cell).0);
+            }
+        });
+    pixels
+}
+
+fn tiles_of<T: Clone + Send + Sync + 'static>(
+    grid: &Grid2D<T>,
+    cell_size: f32,
+    tile: &(impl Fn(&T) -> Option<(Vector2, Vector2)> + ?Sized),
+) -> VertexBuffers<[f32; 4], u32> {
+    let mut geometry = VertexBuffers::new();
+    for (i, cell) in grid.cells().iter().enumerate() {
+        let Some((min, max)) = tile(cell) else {
+            continue;
+        };
+        let x = (i % grid.width()) as f32 * cell_size;
+        let y = (i / grid.width()) as f32 * cell_size;
+        let first = geometry.vertices.len() as u32;
+        geometry.vertices.extend([
+            [x, y, min.x, max.y],
+            [x + cell_size, y, max.x, max.y],
+            [x + cell_size, y + cell_size, max.x, min.y],
+            [x, y + cell_size, min.x, min.y],
+        ]);
+        geometry
+            .indices
+            .extend([first, first + 1, first + 2, first, first + 2, first + 3]);
+    }
+    geometry
+}
+
+impl<T: Clone + Send + Sync + 'static> Component for GridRenderer<T> {
+    type Reference<'a> = GridRendererRef<'a, T>;
+
+    fn get_ref<'a>(&'a self) -> Self::Reference<'a> {
+        GridRendererRef {
+            polygon: self.polygon.get_ref(),
+            style: &self.style,
+            drawn: &self.drawn,
+        }
+    }
+
+    fn flush<E: bina_ecs::entity::Entity>(
+        &mut self,
+        my_entity: bina_ecs::entity::EntityReference<bina_ecs::entity::Inaccessible<E>>,
+        universe: &bina_ecs::universe::Universe,
+    ) {
+        self.polygon.flush(my_entity, universe);
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> Reflect for GridRenderer<T> {
+    fn reflect_fields(&self, visitor: &mut dyn FnMut(&'static str, Field<'_>)) {
+        self.polygon.reflect_fields(visitor);
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> Processable for GridRenderer<T> {
+    fn process<E: bina_ecs::entity::Entity>(
+        component: Self::Reference<'_>,
+        my_entity: bina_ecs::entity::EntityReference<E>,
+        universe: &bina_ecs::universe::Universe,
+    ) {
+        if let Some(grid) = universe.try_get_singleton::<Grid2D<T>>() {
+            let generation = grid.generation();
+            if component.drawn.swap(generation, Ordering::Relaxed) != generation {
+                let graphics =
+                    unsafe { universe.try_get_singleton::<Graphics>().unwrap_unchecked() };
+                match component.style {
+                    CellStyle::Colors { texture, color } => {
+                        let (width, height) = texture.size();
+                        // Grids with a different size than the texture are not drawn
+                        if grid.width() as u32 == width && grid.height() as u32 == height {
+                            texture.write_region(
+                                graphics,
+                                TextureRect::new(0, 0, width, height),
+                                &pixels_of(grid, color),
+                            );
+                        }
+                    }
+                    CellStyle::Tiles { cell_size, tile } => {
+                        component
+                            .polygon
+                            .queue_set_geometry(tiles_of(grid, *cell_size, tile));
+                    }
+                }
+            }
+        }
+        Polygon::process(component.polygon, my_entity, universe);
+    }
+}
+
+pub struct GridRendererRef<'a, T> {
+    /// The polygon that the grid is drawn on, for moving it or changing its layers
+    pub polygon: PolygonRef<'a>,
+    style: &'a CellStyle<T>,
+    drawn: &'a AtomicU64,
+}
@@ -0,0 +1,283 @@
+//! High dynamic range rendering
+//!
+//! The scene is drawn into a floating point texture so that colors brighter than white,
+//! such as those of emissive polygons, are kept. Anything brighter than the bloom threshold
+//! bleeds into its surroundings, then every color is tonemapped back into the range of the window
+use bina_ecs::triomphe::Arc;
+use wgpu::{BindGroup, BindGroupLayout, Device, RenderPipeline, TextureFormat, TextureView};
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    memory::{GpuMemory, MemoryAllocation},
+    renderers::pipelines::BindGroupLayouts,
+};
+
+/// The format that the scene is drawn in before it is tonemapped
+pub(crate) const HDR_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+/// How colors brighter than white are brought back into the range of the window
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Tonemapping {
+    /// Clamps every color, so anything brighter than white is drawn as white
+    #[default]
+    None,
+    /// A filmic curve with more contrast, which desaturates very bright colors
+    Aces,
+    /// Compresses bright colors smoothly, but washes out the whole image
+    Reinhard,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct HdrSettings {
+    pub tonemapping: Tonemapping,
+    /// Multiplies every color before it is tonemapped
+    pub exposure: f32,
+    /// How bright a color must be after exposure before it blooms.
+    /// A threshold above 1 only lets colors brighter than white bloom
+    pub bloom_threshold: f32,
+    /// How much bloom is added, where 0 disables it
+    pub bloom_intensity: f32,
+}
+
+impl Default for HdrSettings {
+    /// Draws the scene the same way it would be drawn without HDR
+    fn default() -> Self {
+        Self {
+            tonemapping: Tonemapping::None,
+            exposure: 1.0,
+            bloom_threshold: 1.0,
+            bloom_intensity: 0.0,
+        }
+    }
+}
+
+impl HdrSettings {
+    pub(crate) fn to_uniform(self) -> [f32; 4] {
+        let tonemapping = match self.tonemapping {
+            Tonemapping::None => 0.0,
+            Tonemapping::Aces => 1.0,
+            Tonemapping::Reinhard => 2.0,
+        };
+        [
+            self.exposure,
+            self.bloom_threshold,
+            self.bloom_intensity,
+            tonemapping,
+        ]
+    }
+}
+
+pub(crate) fn create_settings_buffer(device: &Device) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("hdr_settings_buffer"),
+        size: std::mem::size_of::<[f32; 4]>() as u64,
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+/// A texture that can be drawn into and then sampled
+struct Target {
+    view: TextureView,
+    bind_group: BindGroup,
+    _memory: MemoryAllocation,
+}
+
+impl Target {
+    fn new(
+        device: &Device,
+        layouts: &BindGroupLayouts,
+        memory: &Arc<GpuMemory>,
+        sampler: &wgpu::Sampler,
+        label: &'static str,
+        size: PhysicalSize<u32>,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: size.width.max(1),
+                height: size.height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: HDR_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layouts.texture,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+            label: Some(label),
+        });
+        Self {
+            view,
+            bind_group,
+            _memory: GpuMemory::allocate_texture(memory, &texture),
+        }
+    }
+}
+
+/// The textures that the scene and its bloom are drawn into, and the passes that tonemap them
+pub(crate) struct HdrRenderer {
+    bright_pipeline: RenderPipeline,
+    composite_pipeline: RenderPipeline,
+    settings_bind_group: BindGroup,
+    sampler: wgpu::Sampler,
+    /// Resized alongside the surface
+    targets: Option<(Target, Target, PhysicalSize<u32>)>,
+}
+
+impl HdrRenderer {
+    pub(crate) fn new(
+        device: &Device,
+        layouts: &BindGroupLayouts,
+        settings_buffer: &wgpu::Buffer,
+        surface_format: TextureFormat,
+    ) -> Self {
+        let settings_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("hdr_settings_bind_group_layout"),
+        });
+        let settings_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &settings_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: settings_buffer.as_entire_binding(),
+            }],
+            label: Some("hdr_settings_bind_group"),
+        });
+        let shader = device.create_shader_module(wgpu::include_wgsl!("shaders/tonemap.wgsl"));
+        let create_pipeline = |label, entry_point, format, bind_group_layouts: &[&BindGroupLayout]| {
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(label),
+                bind_group_layouts,
+                push_constant_ranges: &[],
+            });
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_fullscreen",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+        Self {
+            bright_pipeline: create_pipeline(
+                "hdr_bright_pipeline",
+                "fs_bright",
+                HDR_FORMAT,
+                &[&layouts.texture, &settings_layout],
+            ),
+            composite_pipeline: create_pipeline(
+                "hdr_composite_pipeline",
+                "fs_composite",
+                surface_format,
+                &[&layouts.texture, &settings_layout, &layouts.texture],
+            ),
+            settings_bind_group,
+            sampler: device.create_sampler(&wgpu::SamplerDescriptor {
+                label: Some("hdr_sampler"),
+                mag_filter: wgpu::FilterMode::Linear,
+                min_filter: wgpu::FilterMode::Linear,
+                ..Default::default()
+            }),
+            targets: None,
+        }
+    }
+
+    /// Gets the view that the scene should be drawn into, recreating it if the surface size changed
+    pub(crate) fn scene_view(
+        &mut self,
+        device: &Device,
+        layouts: &BindGroupLayouts,
+        memory: &Arc<GpuMemory>,
+        size: PhysicalSize<u32>,
+    ) -> &TextureView {
+        if self.targets.as_ref().map(|(_, _, x)| *x) != Some(size) {
+            let bloom_size = PhysicalSize::new(size.width / 2, size.height / 2);
+            self.targets = Some((
+                Target::new(device, layouts, memory, &self.sampler, "hdr_scene_texture", size),
+                Target::new(device, layouts, memory, &self.sampler, "hdr_bloom_texture", bloom_size),
+                size,
+            ));
+        }
+        &self.targets.as_ref().unwrap().0.view
+    }
+
+    /// Extracts the bloom from the scene, then tonemaps both onto the given view
+    ///
+    /// Must be called after `scene_view`
+    pub(crate) fn resolve(&self, encoder: &mut wgpu::CommandEncoder, view: &TextureView) {
+        let Some((scene, bloom, _)) = &self.targets else {
+            return;
+        };
+        let mut pass = begin_pass(encoder, "hdr_bright_pass", &bloom.view);
+        pass.set_pipeline(&self.bright_pipeline);
+        pass.set_bind_group(0, &scene.bind_group, &[]);
+        pass.set_bind_group(1, &self.settings_bind_group, &[]);
+        pass.draw(0..3, 0..1);
+        drop(pass);
+
+        let mut pass = begin_pass(encoder, "hdr_composite_pass", view);
+        pass.set_pipeline(&self.composite_pipeline);
+        pass.set_bind_group(0, &scene.bind_group, &[]);
+        pass.set_bind_group(1, &self.settings_bind_group, &[]);
+        pass.set_bind_group(2, &bloom.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+fn begin_pass<'a>(
+    encoder: &'a mut wgpu::CommandEncoder,
+    label: &'static str,
+    view: &'a TextureView,
+) -> wgpu::RenderPass<'a> {
+    encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                store: true,
+            },
+        })],
+        depth_stencil_attachment: None,
+    })
+}
@@ -0,0 +1,30 @@
+//! Labels for GPU resources, shown by graphics debuggers such as RenderDoc and Xcode
+//!
+//! Every resource is labelled with what kind of resource it is. With the `debug_labels`
+//! feature, the label also names what the resource belongs to, such as the path of a texture
+//! or the name of a mesh, and every polygon and mesh that is drawn is marked with its name.
+//! Without the feature, names are still kept but never formatted into labels
+use std::borrow::Cow;
+
+use wgpu::RenderPass;
+
+/// Labels a resource of the given kind, naming what it belongs to if labels are enabled
+pub(crate) fn named(kind: &'static str, owner: Option<&str>) -> Cow<'static, str> {
+    #[cfg(feature = "debug_labels")]
+    if let Some(owner) = owner {
+        return Cow::Owned(format!("{kind}: {owner}"));
+    }
+    #[cfg(not(feature = "debug_labels"))]
+    let _ = owner;
+    Cow::Borrowed(kind)
+}
+
+/// Marks the next draw in the render pass with the name of what is being drawn, if labels are enabled
+pub(crate) fn mark_draw(render_pass: &mut RenderPass, name: Option<&str>) {
+    #[cfg(feature = "debug_labels")]
+    if let Some(name) = name {
+        render_pass.insert_debug_marker(name);
+    }
+    #[cfg(not(feature = "debug_labels"))]
+    let _ = (render_pass, name);
+}
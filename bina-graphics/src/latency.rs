@@ -0,0 +1,95 @@
+use bina_ecs::{
+    parking_lot::Mutex,
+    time::{Duration, Instant},
+};
+
+/// How much each new sample contributes to the running averages
+const SMOOTHING: f64 = 0.1;
+
+/// An estimate of how long it takes for input to become visible
+///
+/// Input is timestamped when the window receives it, then marked as consumed by
+/// the first simulation frame that ends after it arrived, and finally marked as displayed
+/// when the frame drawn by that simulation frame is presented. The time between presenting
+/// and the image actually reaching the screen depends on the display and is not included
+#[derive(Clone, Copy, Default, Debug)]
+pub struct InputLatency {
+    /// The time from receiving input to the end of the simulation frame that consumed it
+    pub input_to_simulation: Duration,
+    /// The time from the end of the simulation frame to its frame being presented
+    pub simulation_to_present: Duration,
+    /// The sum of the above for the most recent sample
+    pub last: Duration,
+    /// A running average of `last`
+    pub average: Duration,
+    /// The worst `last` since the last call to `Graphics::reset_input_latency`
+    pub worst: Duration,
+    /// The number of samples taken so far
+    pub samples: u64,
+}
+
+struct InFlight {
+    input_received: Instant,
+    consumed: Instant,
+}
+
+#[derive(Default)]
+pub(crate) struct LatencyTracker {
+    /// The earliest input that has not been consumed by a simulation frame
+    pending: Mutex<Option<Instant>>,
+    /// The input consumed by the frame that is waiting to be presented
+    in_flight: Mutex<Option<InFlight>>,
+    stats: Mutex<InputLatency>,
+}
+
+impl LatencyTracker {
+    /// Called by the render thread when input is received
+    pub(crate) fn input_received(&self) {
+        self.pending.lock().get_or_insert_with(Instant::now);
+    }
+
+    /// Called by the Universe at the end of every frame, before its draw instructions are sent
+    pub(crate) fn frame_submitted(&self) {
+        if let Some(input_received) = self.pending.lock().take() {
+            *self.in_flight.lock() = Some(InFlight {
+                input_received,
+                consumed: Instant::now(),
+            });
+        }
+    }
+
+    /// Called by the render thread right after it presents a frame
+    pub(crate) fn frame_presented(&self) {
+        let Some(InFlight {
+            input_received,
+            consumed,
+        }) = self.in_flight.lock().take()
+        else {
+            return;
+        };
+        let now = Instant::now();
+        let mut stats = self.stats.lock();
+
+        stats.input_to_simulation = consumed - input_received;
+        stats.simulation_to_present = now - consumed;
+        stats.last = now - input_received;
+        stats.worst = stats.worst.max(stats.last);
+        stats.average = if stats.samples == 0 {
+            stats.last
+        } else {
+            Duration::from_secs_f64(
+                stats.average.as_secs_f64() * (1.0 - SMOOTHING)
+                    + stats.last.as_secs_f64() * SMOOTHING,
+            )
+        };
+        stats.samples += 1;
+    }
+
+    pub(crate) fn get(&self) -> InputLatency {
+        *self.stats.lock()
+    }
+
+    pub(crate) fn reset(&self) {
+        *self.stats.lock() = InputLatency::default();
+    }
+}
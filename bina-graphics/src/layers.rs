@@ -0,0 +1,153 @@
+use std::{
+    ops::{BitAnd, BitOr, Not},
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+};
+
+use bina_ecs::component::AtomicNumber;
+
+/// A bitmask of the layers a drawable belongs to, or the layers a camera can see
+///
+/// A drawable is only rendered by a camera if they share at least one layer
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub struct RenderLayers(pub u32);
+
+impl RenderLayers {
+    pub const NONE: Self = Self(0);
+    pub const WORLD: Self = Self(1);
+    /// Drawn in screen space through the UI camera, on top of everything else
+    pub const UI: Self = Self(1 << 1);
+    pub const DEBUG: Self = Self(1 << 2);
+    pub const ALL: Self = Self(u32::MAX);
+
+    /// Creates a mask containing only the given layer
+    ///
+    /// # Panics
+    /// Panics if `layer` is 32 or greater
+    pub const fn layer(layer: u32) -> Self {
+        assert!(layer < 32, "There are only 32 render layers");
+        Self(1 << layer)
+    }
+
+    pub const fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl Default for RenderLayers {
+    fn default() -> Self {
+        Self::WORLD
+    }
+}
+
+impl BitOr for RenderLayers {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitAnd for RenderLayers {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self::Output {
+        Self(self.0 & rhs.0)
+    }
+}
+
+impl Not for RenderLayers {
+    type Output = Self;
+
+    fn not(self) -> Self::Output {
+        Self(!self.0)
+    }
+}
+
+impl AtomicNumber for RenderLayers {
+    type Atomic = AtomicU32;
+
+    fn new_atomic(value: Self) -> Self::Atomic {
+        AtomicU32::new(value.0)
+    }
+
+    fn load(atomic: &mut Self::Atomic) -> Self {
+        Self(*atomic.get_mut())
+    }
+
+    fn store(atomic: &Self::Atomic, other: Self) {
+        atomic.store(other.0, Ordering::Relaxed);
+    }
+
+    /// Adds the given layers
+    fn atomic_add_assign(atomic: &Self::Atomic, other: Self) {
+        atomic.fetch_or(other.0, Ordering::Relaxed);
+    }
+
+    /// Removes the given layers
+    fn atomic_sub_assign(atomic: &Self::Atomic, other: Self) {
+        atomic.fetch_and(!other.0, Ordering::Relaxed);
+    }
+
+    /// Keeps only the given layers
+    fn atomic_mul_assign(atomic: &Self::Atomic, other: Self) {
+        atomic.fetch_and(other.0, Ordering::Relaxed);
+    }
+
+    /// Toggles the given layers
+    fn atomic_div_assign(atomic: &Self::Atomic, other: Self) {
+        atomic.fetch_xor(other.0, Ordering::Relaxed);
+    }
+}
+
+/// Whether or not a drawable should be rendered
+///
+/// Hiding a drawable is much cheaper than removing and
+/// re-adding its entity
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Visible(pub bool);
+
+impl Default for Visible {
+    fn default() -> Self {
+        Self(true)
+    }
+}
+
+impl AtomicNumber for Visible {
+    type Atomic = AtomicBool;
+
+    fn new_atomic(value: Self) -> Self::Atomic {
+        AtomicBool::new(value.0)
+    }
+
+    fn load(atomic: &mut Self::Atomic) -> Self {
+        Self(*atomic.get_mut())
+    }
+
+    fn store(atomic: &Self::Atomic, other: Self) {
+        atomic.store(other.0, Ordering::Relaxed);
+    }
+
+    /// Shows the drawable if `other` is visible, so it is shown if any modifier shows it
+    fn atomic_add_assign(atomic: &Self::Atomic, other: Self) {
+        atomic.fetch_or(other.0, Ordering::Relaxed);
+    }
+
+    /// Hides the drawable if `other` is visible
+    fn atomic_sub_assign(atomic: &Self::Atomic, other: Self) {
+        atomic.fetch_and(!other.0, Ordering::Relaxed);
+    }
+
+    /// Hides the drawable unless `other` is visible, so it is only shown if every modifier shows it
+    fn atomic_mul_assign(atomic: &Self::Atomic, other: Self) {
+        atomic.fetch_and(other.0, Ordering::Relaxed);
+    }
+
+    /// Toggles the drawable if `other` is visible
+    fn atomic_div_assign(atomic: &Self::Atomic, other: Self) {
+        atomic.fetch_xor(other.0, Ordering::Relaxed);
+    }
+}
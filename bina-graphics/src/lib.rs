@@ -1,111 +1,620 @@
-#![feature(associated_type_bounds, exclusive_wrapper, let_chains)]
-use std::{sync::{mpsc::{Receiver, TryRecvError}, Exclusive}, mem::size_of};
+#![feature(associated_type_bounds, let_chains, const_precise_live_drops)]
+use std::mem::size_of;
 
+use atomic_float::AtomicF32;
 use bina_ecs::{
-    crossbeam::{queue::{ArrayQueue, SegQueue}, utils::Backoff},
-    parking_lot::Mutex,
-    rayon,
+    crossbeam::{atomic::AtomicCell, queue::SegQueue},
+    parking_lot::{Mutex, MutexGuard},
     singleton::Singleton,
     triomphe::{self, Arc},
-    universe::{DeltaStrategy, LoopCount, Universe},
+    universe::{DeltaStrategy, FlushStage, LoopCount, Universe},
 };
-use camera::Camera;
+use bina_ecs::component::Component;
+use bina_ecs::diagnostics::Diagnostics;
+use bina_ecs::tokio::sync::Notify;
+#[cfg(not(target_arch = "wasm32"))]
+use bina_ecs::rayon;
+use std::collections::HashMap;
+use capture::CaptureCallback;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use camera::{Camera, CameraRef, ViewTransform, CAMERA_FLOATS};
+use cursor::{CursorGrabMode, CursorIcon, CustomCursor};
+use polygon::{Material, Vector};
 use drawing::DrawInstruction;
-use nalgebra::Matrix2;
-use renderers::{PolygonRenderer, PolygonRendererCreation};
-use wgpu::{BindGroupLayout, BufferUsages};
+use exchange::InstructionExchange;
+use gizmos::Gizmos;
+use gpu_errors::GpuErrors;
+#[cfg(feature = "egui")]
+use debug_ui::{DebugUi, DebugUiState};
+use hdr::{HdrRenderer, HdrSettings};
+#[cfg(feature = "3d")]
+use three_d::{renderer::MeshRenderer, Camera3D};
+use latency::{InputLatency, LatencyTracker};
+use lifecycle::Lifecycle;
+use mask::StencilBuffer;
+use memory::{GpuMemory, MemoryCategory, MemoryUsage};
+use meshes::MeshArena;
+use minimap::Minimap;
+use ambient::AmbientLight;
+use stats::{GpuProfiler, GpuSpan, RenderStats};
+use compressed::CompressionFamily;
+use transforms::{TransformBuffer, TransformSlots};
+use texture::{
+    CachedTexture, SamplerOptions, Texture, TextureAsset, TextureCache, TextureHandle, TextureInner,
+    TextureLoader, TextureRef, TextureWrite,
+};
+use texture_array::QueuedSprite;
+use warmup::{PendingWarmup, Warmup};
+use bina_ecs::assets::ProgressHandle;
+use bina_ecs::assets::AssetSource;
+use layers::RenderLayers;
+use renderers::{pipelines::BindGroupLayouts, DrawPolygon, DrawResources, PolygonRenderer};
+use wgpu::BufferUsages;
 use winit::{
-    dpi::PhysicalSize,
+    dpi::{LogicalSize, PhysicalSize},
     event::*,
     event_loop::{ControlFlow, EventLoop},
     window::{Window, WindowBuilder},
 };
 
 pub use image;
+pub mod ambient;
+#[cfg(target_os = "android")]
+pub mod android;
+pub mod atlas;
+pub mod bones;
 pub mod drawing;
+pub mod gizmos;
+pub mod gpu_errors;
+pub mod grid;
+pub mod hdr;
 pub mod polygon;
 mod renderers;
+pub mod sprite;
 pub mod texture;
+pub mod texture_array;
 pub use nalgebra;
+pub use winit;
+#[cfg(feature = "egui")]
+pub use egui;
 pub mod camera;
+mod capture;
+pub mod cursor;
+pub mod font;
+pub mod compressed;
+// Only glTF uses every accessor
+#[cfg_attr(not(feature = "3d"), allow(dead_code))]
+mod json;
+#[cfg(feature = "egui")]
+mod debug_ui;
+mod exchange;
+mod labels;
+pub mod layers;
+pub mod latency;
+mod lifecycle;
+pub mod mask;
+pub mod memory;
+mod meshes;
+pub mod minimap;
+pub mod stats;
+pub mod transform;
+mod transforms;
+pub mod svg;
+pub mod terrain;
+pub mod warmup;
+#[cfg(feature = "3d")]
+pub mod three_d;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod testing;
+#[cfg(target_arch = "wasm32")]
+pub mod web;
 
 
+/// How the visible area of the camera is adapted to the aspect ratio of the window
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum ScalingMode {
+    /// The entire area of the camera is always visible,
+    /// and more is shown along the longer side of the window
     Expand,
+    /// The camera area always fills the entire window,
+    /// and the longer side of the camera area is cut off
     Shrink
 }
 
+/// What the Universe does while the window is minimized or completely covered by other windows
+///
+/// Nothing is drawn while the window cannot be seen, whichever is chosen
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum BackgroundMode {
+    /// Keeps running frames as usual
+    #[default]
+    Run,
+    /// Runs at most one frame every given duration
+    Throttle(bina_ecs::time::Duration),
+    /// Stops running frames until the window can be seen again, except to
+    /// deliver window events such as `WindowCloseRequested`
+    Pause,
+}
+
 struct Config {
     config: wgpu::SurfaceConfiguration,
     size: winit::dpi::PhysicalSize<u32>,
+    scale_factor: f64,
+}
+
+/// Sent into the Universe when the window is asked to close, such as by its close button
+///
+/// Unless the close is vetoed while this event is being read, the Universe exits at the end of
+/// that frame. Everything in the Universe is dropped before the window closes
+///
+/// ```ignore
+/// for request in universe.read_events::<WindowCloseRequested>() {
+///     if has_unsaved_changes {
+///         request.veto();
+///         // Later, once the player has saved
+///         universe.exit_ok();
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct WindowCloseRequested {
+    vetoed: Arc<AtomicBool>,
+}
+
+impl WindowCloseRequested {
+    /// Keeps the window open
+    pub fn veto(&self) {
+        self.vetoed.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_vetoed(&self) -> bool {
+        self.vetoed.load(Ordering::Relaxed)
+    }
+}
+
+/// Sent into the Universe whenever the window is resized or moved to
+/// a monitor with a different scale factor
+#[derive(Clone, Copy, Debug)]
+pub struct WindowResized {
+    pub size: PhysicalSize<u32>,
+    pub scale_factor: f64,
+}
+
+/// Sent into the Universe when the number of physical pixels covered by each UI unit changes,
+/// which happens when the window moves to a monitor with a different scale factor or
+/// `Graphics::set_ui_scale` is called. Also sent on the first frame
+#[derive(Clone, Copy, Debug)]
+pub struct UiScaleChanged {
+    pub scale_factor: f64,
+    pub ui_scale: f32,
+}
+
+impl UiScaleChanged {
+    /// Gets how many physical pixels each UI unit covers
+    pub fn pixels_per_unit(&self) -> f32 {
+        self.scale_factor as f32 * self.ui_scale
+    }
+}
+
+/// Sent into the Universe whenever the cursor or a touch moves
+///
+/// The position is in physical pixels from the top left corner of the window
+#[derive(Clone, Copy)]
+pub struct PointerMoved {
+    pub position: Vector,
+}
+
+/// Sent into the Universe whenever the mouse moves, even if the cursor is locked or at the edge of the screen
+///
+/// Unlike `PointerMoved`, this is the raw movement of the mouse, in units that depend on the
+/// platform and the mouse. This suits camera controls, along with `Graphics::set_cursor_grab`
+#[derive(Clone, Copy)]
+pub struct MouseMotion {
+    pub delta: Vector,
+}
+
+/// Sent into the Universe whenever a mouse button is pressed or released
+///
+/// Touches are sent as the left mouse button. The position is where the
+/// pointer was at the time, in physical pixels from the top left corner of the window
+#[derive(Clone, Copy)]
+pub struct PointerButton {
+    pub button: MouseButton,
+    pub pressed: bool,
+    pub position: Vector,
+}
+
+/// Sent into the Universe whenever a key is pressed or released
+#[derive(Clone, Copy, Debug)]
+pub struct KeyInput {
+    /// The meaning of the key in the current keyboard layout, if it has one
+    pub key: Option<VirtualKeyCode>,
+    /// The physical position of the key, which does not depend on the keyboard layout
+    pub scancode: u32,
+    pub pressed: bool,
+    /// The modifier keys that were held at the time
+    pub modifiers: ModifiersState,
+}
+
+/// Sent into the Universe for every character that is typed, after the keyboard layout has been applied
+///
+/// Keys that do not type anything, such as the arrow keys, are only sent as `KeyInput`
+#[derive(Clone, Copy, Debug)]
+pub struct CharacterTyped(pub char);
+
+/// How many times in a row the surface may fail to produce a texture
+/// after being reconfigured before the adapter is assumed to have changed
+const MAX_SURFACE_FAILURES: usize = 3;
+
+/// The longest the render thread sleeps waiting for a frame before checking for window events
+#[cfg(not(target_arch = "wasm32"))]
+const FRAME_WAIT: bina_ecs::time::Duration = bina_ecs::time::Duration::from_millis(50);
+
+/// The format of the off-screen texture used when running headless
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+const HEADLESS_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// What frames are drawn onto
+enum RenderTarget {
+    Window {
+        /// `None` while the app is suspended, as mobile platforms
+        /// take away the native window when an app is backgrounded
+        surface: Mutex<Option<wgpu::Surface>>,
+        // The window must be declared after the surface so
+        // it gets dropped after it as the surface contains
+        // unsafe references to the window's resources.
+        window: Arc<Window>,
+    },
+    /// An off-screen texture, used when running headless
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    Texture(wgpu::Texture),
 }
 
 struct GraphicsInner {
-    surface: wgpu::Surface,
+    target: RenderTarget,
+    adapter: wgpu::Adapter,
     device: wgpu::Device,
     queue: wgpu::Queue,
     config: Mutex<Config>,
-    // The window must be declared after the surface so
-    // it gets dropped after it as the surface contains
-    // unsafe references to the window's resources.
-    window: Window,
-    texture_bind_grp_layout: BindGroupLayout,
-    transform_bind_group_layout: BindGroupLayout,
-    camera_matrix_buffer: wgpu::Buffer
+    /// Shared with the pipeline cache of the render thread
+    layouts: Arc<BindGroupLayouts>,
+    /// Replaced by a larger buffer when there are more polygons than it can hold
+    transform_buffer: Mutex<Arc<TransformBuffer>>,
+    /// The vertices and indices of every polygon created on this device
+    meshes: Arc<MeshArena>,
+    /// The bytes used by every texture and buffer created on this device
+    memory: Arc<GpuMemory>,
+    camera_matrix_buffer: wgpu::Buffer,
+    /// The camera that polygons on the UI layer are drawn through
+    ui_matrix_buffer: wgpu::Buffer,
+    hdr_settings_buffer: wgpu::Buffer,
+    /// The view projection matrix of the 3D camera
+    #[cfg(feature = "3d")]
+    camera_3d_buffer: wgpu::Buffer,
+    /// Shared by every texture with the same options
+    samplers: Mutex<HashMap<SamplerOptions, wgpu::Sampler>>,
+    /// The textures of every `TextureResource`, shared between resources with the same pixels
+    texture_cache: TextureCache,
+    /// Errors reported by the device, sent into the Universe while processing
+    errors: GpuErrors,
+    /// Incremented every time the device is recreated
+    ///
+    /// Any GPU resource created with a different generation belongs
+    /// to a device that no longer exists and must be rebuilt
+    generation: u64,
+}
+
+// # Safety
+//
+// Windows in browsers cannot be shared between threads,
+// but pages only ever run on a single thread
+#[cfg(target_arch = "wasm32")]
+unsafe impl Send for GraphicsInner {}
+#[cfg(target_arch = "wasm32")]
+unsafe impl Sync for GraphicsInner {}
+
+/// State that only the render thread needs
+struct RenderState {
+    poly_render: PolygonRenderer,
+    /// Draws polygons into textures, such as for the minimap, before the scene is drawn
+    offscreen_render: PolygonRenderer,
+    offscreen_stencil: StencilBuffer,
+    camera_matrix_buffer_bind_group: wgpu::BindGroup,
+    ui_matrix_buffer_bind_group: wgpu::BindGroup,
+    stencil: StencilBuffer,
+    hdr: HdrRenderer,
+    #[cfg(feature = "3d")]
+    mesh_render: MeshRenderer,
+    gpu_profiler: Option<GpuProfiler>,
+    #[cfg(feature = "egui")]
+    debug_ui_renderer: egui_wgpu::Renderer,
 }
 
 pub struct Graphics {
     inner: triomphe::Arc<GraphicsInner>,
+    /// A replacement for `inner` created by the render thread after the adapter changed
+    new_inner: Arc<Mutex<Option<Arc<GraphicsInner>>>>,
     current_instructions_queue: SegQueue<DrawInstruction>,
-    filled_instructions_sender: Arc<ArrayQueue<Vec<DrawInstruction>>>,
-    empty_instructions_recv: Exclusive<Receiver<Vec<DrawInstruction>>>,
+    instructions: Arc<InstructionExchange>,
     active_camera: Option<Camera>,
+    pending_camera: Mutex<Option<Camera>>,
+    latency: Arc<LatencyTracker>,
+    scaling_mode: ScalingMode,
+    /// Work queued by the render thread that needs access to the Universe
+    universe_commands: Arc<SegQueue<UniverseCommand>>,
+    window_commands: Arc<SegQueue<WindowCommand>>,
+    /// The latest position of the cursor in physical pixels, or `None` if it is not over the window
+    cursor_position: Arc<AtomicCell<Option<Vector>>>,
+    /// Drawn at the cursor position in place of the system cursor
+    custom_cursor: Mutex<Option<CustomCursor>>,
+    render_stats: Arc<RenderStats>,
+    /// Callbacks waiting for a copy of the next rendered frame
+    captures: Arc<SegQueue<CaptureCallback>>,
+    lifecycle: Arc<Lifecycle>,
+    background_mode: Arc<AtomicCell<BackgroundMode>>,
+    /// Whether presenting waits for the vertical blank, which the render thread applies to the surface
+    vsync: Arc<AtomicBool>,
+    /// Copied into their textures during the next flush
+    texture_writes: SegQueue<TextureWrite>,
+    /// Batched by texture array during the next flush
+    array_sprites: SegQueue<QueuedSprite>,
+    missing_texture_fallback: AtomicBool,
+    /// The transform of every polygon
+    transform_slots: Arc<TransformSlots>,
+    /// Whether every transform must be uploaded, such as after the device changed
+    upload_all_transforms: bool,
+    /// How many logical pixels each UI unit covers
+    ui_scale: AtomicF32,
+    /// The scale factor and UI scale that were last sent as a `UiScaleChanged` event
+    sent_ui_scale: Option<(f64, f32)>,
+    hdr_settings: Mutex<HdrSettings>,
+    #[cfg(feature = "3d")]
+    camera_3d: Mutex<Option<Camera3D>>,
+    gizmos: Gizmos,
+    minimap: Mutex<Option<Minimap>>,
+    minimap_markers: Gizmos,
+    /// Created the first time it is needed on each device
+    missing_texture: Mutex<Option<Arc<TextureInner>>>,
+    /// In bytes, past which `UncacheAfter` textures are unloaded without waiting
+    memory_soft_limit: AtomicCell<Option<u64>>,
+    /// Woken when the memory in use goes past the soft limit
+    memory_pressure: Arc<Notify>,
+    /// The memory in use when `memory_pressure` was last woken, so it is only woken again if more is used
+    pressure_total: AtomicU64,
+    /// Every `TextureResource` loaded with `CacheOption::Lru`
+    lru_textures: Mutex<Vec<&'static dyn CachedTexture>>,
+    /// In bytes, past which the least recently drawn `CacheOption::Lru` textures are unloaded
+    texture_budget: AtomicCell<Option<u64>>,
+    /// How many frames have been flushed, which textures are stamped with when they are drawn
+    frame: u64,
+    /// Waiting for their textures to load before the render thread creates every pipeline
+    warmups: Mutex<Vec<PendingWarmup>>,
+    /// A close request sent into the Universe during this frame
+    pending_close: Mutex<Option<WindowCloseRequested>>,
+    /// A close request that could be vetoed during this frame
+    awaiting_close: Option<WindowCloseRequested>,
+    #[cfg(feature = "egui")]
+    debug_ui: DebugUi,
+    #[cfg(feature = "egui")]
+    show_diagnostics: AtomicBool,
+    #[cfg(feature = "egui")]
+    show_inspector: AtomicBool,
 }
 
-impl Graphics {
-    /// Creates a new GUI immediately
-    /// 
-    /// Generally, the only `DeltaStrategy` you should use is `RealDelta` with a delta
-    /// of 0. The window will stop the given `Universe` from processing more frames than needed.
-    ///
-    /// To avoid issues with cross compatability, the window's event loop must
-    /// use the main thread. This method ensures that is true while running the Universe
-    /// loop in a separate thread.
+type UniverseCommand = Box<dyn FnOnce(&Universe) + Send>;
+
+/// Changes to the window, which can only be made on the thread that runs the event loop
+type WindowCommand = Box<dyn FnOnce(&Window) + Send>;
+
+/// The render thread's ends of everything shared with `Graphics`
+struct RenderChannels {
+    instructions: Arc<InstructionExchange>,
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    new_inner: Arc<Mutex<Option<Arc<GraphicsInner>>>>,
+    latency: Arc<LatencyTracker>,
+    universe_commands: Arc<SegQueue<UniverseCommand>>,
+    window_commands: Arc<SegQueue<WindowCommand>>,
+    cursor_position: Arc<AtomicCell<Option<Vector>>>,
+    render_stats: Arc<RenderStats>,
+    captures: Arc<SegQueue<CaptureCallback>>,
+    lifecycle: Arc<Lifecycle>,
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    background_mode: Arc<AtomicCell<BackgroundMode>>,
+    vsync: Arc<AtomicBool>,
+    #[cfg(feature = "egui")]
+    debug_ui_input: Arc<Mutex<egui::RawInput>>,
+}
+
+impl RenderChannels {
+    /// Gives a drawn buffer of instructions back to the Universe
     ///
-    /// Even though this function never returns, the universe will be safely dropped if a
-    /// component has requested an exit, even if an exit with an error was requested. Any data
-    /// not stored in the Universe will not be dropped however
-    pub async fn run(mut universe: Universe, count: LoopCount, delta: DeltaStrategy, title: impl Into<String>, scaling_mode: ScalingMode) -> ! {
-        let event_loop = EventLoop::new();
-        let window = WindowBuilder::new().with_title(title).build(&event_loop).unwrap();
+    /// The buffer must always be given back, otherwise the Universe
+    /// will wait for it until the render thread ends
+    fn return_instructions(&self, instructions: Vec<DrawInstruction>) {
+        self.instructions.return_empty(instructions);
+    }
+}
 
-        let size = window.inner_size();
+impl Drop for RenderChannels {
+    /// Lets the Universe know that nothing will draw its frames anymore
+    fn drop(&mut self) {
+        self.instructions.close();
+    }
+}
 
-        // The instance is a handle to our GPU
-        // Backends::all => Vulkan + Metal + DX12 + Browser WebGPU
-        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::all(),
-            dx12_shader_compiler: Default::default(),
-        });
+fn create_surface(instance: &wgpu::Instance, window: &Window) -> wgpu::Surface {
+    // # Safety
+    //
+    // The surface needs to live as long as the window that created it.
+    // GraphicsInner owns a handle to the window so this should be safe.
+    unsafe { instance.create_surface(window) }.unwrap()
+}
 
-        // # Safety
-        //
-        // The surface needs to live as long as the window that created it.
-        // State owns the window so this should be safe.
-        let surface = unsafe { instance.create_surface(&window) }.unwrap();
+/// Recreates the device on an adapter that is compatible with the window,
+/// returning false if that is not possible
+#[cfg(not(target_arch = "wasm32"))]
+fn recreate_device(
+    instance: &wgpu::Instance,
+    window: &Arc<Window>,
+    graphics: &mut Arc<GraphicsInner>,
+    render_state: &mut RenderState,
+    channels: &RenderChannels,
+) -> bool {
+    log::warn!("Surface is no longer compatible with the current adapter, recreating device");
+    let (new_graphics, new_render_state) = bina_ecs::tokio::task::block_in_place(|| {
+        bina_ecs::tokio::runtime::Handle::current().block_on(GraphicsInner::new(
+            instance,
+            window.clone(),
+            graphics.generation + 1,
+            true,
+        ))
+    });
+    *graphics = Arc::new(new_graphics);
+    *render_state = new_render_state;
+    *channels.new_inner.lock() = Some(graphics.clone());
+    true
+}
+
+/// The device cannot be recreated without blocking the browser
+#[cfg(target_arch = "wasm32")]
+fn recreate_device(
+    _instance: &wgpu::Instance,
+    _window: &Arc<Window>,
+    _graphics: &mut Arc<GraphicsInner>,
+    _render_state: &mut RenderState,
+    _channels: &RenderChannels,
+) -> bool {
+    log::error!("Surface is no longer compatible with the current adapter");
+    false
+}
+
+fn new_instance() -> wgpu::Instance {
+    // The instance is a handle to our GPU
+    // Backends::all => Vulkan + Metal + DX12 + Browser WebGPU
+    wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends: wgpu::Backends::all(),
+        dx12_shader_compiler: Default::default(),
+    })
+}
+
+impl GraphicsInner {
+    /// Selects an adapter compatible with the given window and creates
+    /// every device dependent resource on it
+    ///
+    /// Android only provides a native window while the app is resumed, so if `resumed`
+    /// is false, the surface is left to be created by `resume_surface` instead
+    async fn new(instance: &wgpu::Instance, window: Arc<Window>, generation: u64, resumed: bool) -> (Self, RenderState) {
+        let size = window.inner_size();
+        let surface = resumed.then(|| create_surface(instance, &window));
 
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
                 power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: Some(&surface),
+                compatible_surface: surface.as_ref(),
                 force_fallback_adapter: false,
             })
             .await
             .unwrap();
-        let (device, queue) = adapter
+        log::info!("Using adapter {:?}", adapter.get_info());
+        let (device, queue) = Self::request_device(&adapter).await;
+
+        let config = match &surface {
+            Some(surface) => {
+                let surface_caps = surface.get_capabilities(&adapter);
+                // Shader code in this tutorial assumes an sRGB surface texture. Using a different
+                // one will result all the colors coming out darker. If you want to support non
+                // sRGB surfaces, you'll need to account for that when drawing to the frame.
+                let surface_format = surface_caps
+                    .formats
+                    .iter()
+                    .copied()
+                    .find(|f| f.is_srgb())
+                    .unwrap_or(surface_caps.formats[0]);
+                let config = wgpu::SurfaceConfiguration {
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                    format: surface_format,
+                    width: size.width,
+                    height: size.height,
+                    present_mode: wgpu::PresentMode::AutoVsync,
+                    alpha_mode: surface_caps.alpha_modes[0],
+                    view_formats: vec![],
+                };
+                surface.configure(&device, &config);
+                config
+            }
+            // Supported by almost every surface. If the surface turns out to not support it,
+            // the device is recreated once it exists
+            None => wgpu::SurfaceConfiguration {
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                width: size.width,
+                height: size.height,
+                present_mode: wgpu::PresentMode::AutoVsync,
+                alpha_mode: wgpu::CompositeAlphaMode::Auto,
+                view_formats: vec![],
+            },
+        };
+
+        let config = Config { config, size, scale_factor: window.scale_factor() };
+        let target = RenderTarget::Window {
+            surface: Mutex::new(surface),
+            window,
+        };
+        Self::from_parts(adapter, device, queue, config, target, generation)
+    }
+
+    /// Selects any adapter and creates every device dependent resource on it,
+    /// drawing onto an off-screen texture of the given size
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    async fn new_headless(instance: &wgpu::Instance, size: PhysicalSize<u32>) -> (Self, RenderState) {
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::default(),
+                compatible_surface: None,
+                force_fallback_adapter: false,
+            })
+            .await
+            .expect("There should be an adapter, even if it is a software renderer");
+        log::info!("Using adapter {:?}", adapter.get_info());
+        let (device, queue) = Self::request_device(&adapter).await;
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("headless_target"),
+            size: wgpu::Extent3d {
+                width: size.width,
+                height: size.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: HEADLESS_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        // Never used to configure a surface, but the renderers are created from it
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: HEADLESS_FORMAT,
+            width: size.width,
+            height: size.height,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+            view_formats: vec![],
+        };
+
+        let config = Config { config, size, scale_factor: 1.0 };
+        Self::from_parts(adapter, device, queue, config, RenderTarget::Texture(texture), 0)
+    }
+
+    async fn request_device(adapter: &wgpu::Adapter) -> (wgpu::Device, wgpu::Queue) {
+        adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
-                    features: wgpu::Features::empty(),
+                    // Timestamp queries are only used for diagnostics, and compressed
+                    // textures are picked based on what is supported, so both are optional
+                    features: adapter.features()
+                        & (wgpu::Features::TIMESTAMP_QUERY | CompressionFamily::all_features()),
                     // WebGL doesn't support all of wgpu's features, so if
                     // we're building for the web we'll have to disable some.
                     limits: if cfg!(target_arch = "wasm32") {
@@ -118,70 +627,40 @@ impl Graphics {
                 None, // Trace path
             )
             .await
-            .unwrap();
+            .unwrap()
+    }
 
-        let surface_caps = surface.get_capabilities(&adapter);
-        // Shader code in this tutorial assumes an sRGB surface texture. Using a different
-        // one will result all the colors coming out darker. If you want to support non
-        // sRGB surfaces, you'll need to account for that when drawing to the frame.
-        let surface_format = surface_caps
-            .formats
-            .iter()
-            .copied()
-            .find(|f| f.is_srgb())
-            .unwrap_or(surface_caps.formats[0]);
-        let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface_format,
-            width: size.width,
-            height: size.height,
-            present_mode: wgpu::PresentMode::AutoVsync,
-            alpha_mode: surface_caps.alpha_modes[0],
-            view_formats: vec![],
-        };
-        surface.configure(&device, &config);
+    /// Creates every resource that does not depend on the render target
+    fn from_parts(
+        adapter: wgpu::Adapter,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        config: Config,
+        target: RenderTarget,
+        generation: u64,
+    ) -> (Self, RenderState) {
+        if bina_ecs::crash::is_installed() {
+            bina_ecs::crash::set_section("gpu", format!("{:?}", adapter.get_info()));
+        }
+        let errors = GpuErrors::default();
+        errors.install(&device);
+        GpuErrors::push_scope(&device);
+        let layouts = Arc::new(BindGroupLayouts::new(&device));
+        let memory = Arc::new(GpuMemory::default());
+        let transform_buffer = TransformBuffer::with_initial_capacity(&device, &layouts.transform, &memory);
+        let meshes = Arc::new(MeshArena::new(&device, &memory, generation));
 
-        let transform_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
-                label: Some("transform_bind_group_layout"),
-            });
-        
-        let camera_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
-                label: Some("camera_matrix_bind_group_layout"),
-            });
-        
         let camera_matrix_buffer = 
             device.create_buffer(&wgpu::BufferDescriptor {
                 label: Some("camera_matrix_buffer_descriptor"),
-                size: size_of::<f32>() as u64 * 6,
+                size: size_of::<[f32; CAMERA_FLOATS]>() as u64,
                 usage: BufferUsages::UNIFORM.union(BufferUsages::COPY_DST),
                 mapped_at_creation: false,
             });
         
         let camera_matrix_buffer_bind_group =
             device.create_bind_group(&wgpu::BindGroupDescriptor {
-                layout: &camera_bind_group_layout,
+                layout: &layouts.camera,
                 entries: &[wgpu::BindGroupEntry {
                     binding: 0,
                     resource: wgpu::BindingResource::Buffer(camera_matrix_buffer.as_entire_buffer_binding()),
@@ -189,48 +668,473 @@ impl Graphics {
                 label: Some("transform_bind_group"),
             });
 
-        let PolygonRendererCreation {
-            mut poly_render,
-            tex_grp_layout,
-        } = PolygonRenderer::new(&device, &config, &transform_bind_group_layout, &camera_bind_group_layout);
+        let ui_matrix_buffer =
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("ui_matrix_buffer_descriptor"),
+                size: size_of::<[f32; CAMERA_FLOATS]>() as u64,
+                usage: BufferUsages::UNIFORM.union(BufferUsages::COPY_DST),
+                mapped_at_creation: false,
+            });
+
+        let ui_matrix_buffer_bind_group =
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &layouts.camera,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(ui_matrix_buffer.as_entire_buffer_binding()),
+                }],
+                label: Some("ui_matrix_bind_group"),
+            });
 
-        let graphics = Arc::new(GraphicsInner {
-            surface,
-            device,
-            queue,
-            config: Mutex::new(Config { config, size }),
-            window,
-            texture_bind_grp_layout: tex_grp_layout,
-            transform_bind_group_layout,
-            camera_matrix_buffer
+        let poly_render = PolygonRenderer::new(layouts.clone(), generation);
+        let offscreen_render = PolygonRenderer::new(layouts.clone(), generation);
+        let hdr_settings_buffer = hdr::create_settings_buffer(&device);
+        let hdr = HdrRenderer::new(&device, &layouts, &hdr_settings_buffer, config.config.format);
+        #[cfg(feature = "3d")]
+        let camera_3d_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("camera_3d_buffer"),
+            size: size_of::<[f32; 16]>() as u64,
+            usage: BufferUsages::UNIFORM.union(BufferUsages::COPY_DST),
+            mapped_at_creation: false,
         });
+        #[cfg(feature = "3d")]
+        let mesh_render = MeshRenderer::new(&device, &queue, &layouts, &camera_3d_buffer, generation);
+        let gpu_profiler = GpuProfiler::new(&device, &queue);
+        #[cfg(feature = "egui")]
+        let debug_ui_renderer = egui_wgpu::Renderer::new(&device, config.config.format, None, 1);
+        errors.pop_scope(&device, "creating renderers");
 
-        let cloned = graphics.clone();
-        let (exit_sender, mut exit_receiver) = bina_ecs::tokio::sync::oneshot::channel();
-        let filled_instructions_sender = Arc::new(ArrayQueue::new(1));
-        let filled_instructions_receiver = filled_instructions_sender.clone();
+        (
+            Self {
+                target,
+                adapter,
+                device,
+                queue,
+                config: Mutex::new(config),
+                layouts,
+                transform_buffer: Mutex::new(Arc::new(transform_buffer)),
+                meshes,
+                memory,
+                camera_matrix_buffer,
+                ui_matrix_buffer,
+                hdr_settings_buffer,
+                #[cfg(feature = "3d")]
+                camera_3d_buffer,
+                samplers: Mutex::new(HashMap::new()),
+                texture_cache: TextureCache::default(),
+                errors,
+                generation,
+            },
+            RenderState {
+                offscreen_render,
+                offscreen_stencil: StencilBuffer::new(),
+                poly_render,
+                camera_matrix_buffer_bind_group,
+                ui_matrix_buffer_bind_group,
+                stencil: StencilBuffer::new(),
+                hdr,
+                #[cfg(feature = "3d")]
+                mesh_render,
+                gpu_profiler,
+                #[cfg(feature = "egui")]
+                debug_ui_renderer,
+            },
+        )
+    }
+}
 
-        let (empty_instructions_sender, empty_instructions_recv) = std::sync::mpsc::sync_channel(1);
-        unsafe {
-            empty_instructions_sender
-                .send(Vec::new())
-                .unwrap_unchecked();
+impl RenderState {
+    /// Draws the given instructions onto the given view and submits the frame
+    ///
+    /// `overlay` is called after the scene has been encoded to draw anything on top of it,
+    /// and returns command buffers that must be submitted before the frame
+    fn render(
+        &mut self,
+        graphics: &GraphicsInner,
+        view: &wgpu::TextureView,
+        instructions: &mut Vec<DrawInstruction>,
+        render_stats: &RenderStats,
+        overlay: impl FnOnce(&mut Self, &mut wgpu::CommandEncoder, PhysicalSize<u32>) -> Vec<wgpu::CommandBuffer>,
+    ) {
+        GpuErrors::push_scope(&graphics.device);
+        if let Some(gpu_profiler) = &mut self.gpu_profiler {
+            if let Some(spans) = gpu_profiler.poll(&graphics.device) {
+                *render_stats.gpu_spans.lock() = spans;
+            }
+        }
+        let mut encoder =
+            graphics
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Render Encoder"),
+                });
+        if let Some(gpu_profiler) = &mut self.gpu_profiler {
+            gpu_profiler.begin(&mut encoder);
         }
 
-        rayon::spawn(move || {
-            universe.queue_set_singleton(Graphics {
-                inner: cloned,
-                filled_instructions_sender,
-                empty_instructions_recv: Exclusive::new(empty_instructions_recv),
+        let mut offscreen_passes = Vec::new();
+        for instruction in instructions.drain(..) {
+            match instruction {
+                DrawInstruction::DrawPolygon(x) => self.poly_render.push(x),
+                DrawInstruction::Offscreen(x) => offscreen_passes.push(x),
+                DrawInstruction::Gizmos(x) => self.poly_render.push_gizmos(x),
+                DrawInstruction::SpriteBatch(x) => self.poly_render.push_sprite_batch(x),
+                DrawInstruction::Warmup(done) => {
+                    self.poly_render.warm_up(&graphics.device);
+                    self.offscreen_render.warm_up(&graphics.device);
+                    done.store(true, Ordering::Release);
+                }
+                #[cfg(feature = "3d")]
+                DrawInstruction::DrawMesh(x) => self.mesh_render.push(x),
+                // Taken out by `DebugUiState::receive` when there is a window,
+                // and discarded when running headless
+                #[cfg(feature = "egui")]
+                DrawInstruction::DebugUi(_) => {}
+            }
+        }
+        let surface_size = graphics.config.lock().size;
+        let transforms = graphics.transform_buffer.lock().clone();
+        let meshes = graphics.meshes.buffers();
+        let offscreen_draw_calls: usize = offscreen_passes
+            .into_iter()
+            .map(|pass| pass.draw(graphics, &mut encoder, &mut self.offscreen_render, &mut self.offscreen_stencil, &meshes, &transforms))
+            .sum();
+        let stencil_view = self.stencil.view(&graphics.device, &graphics.memory, surface_size);
+        let scene_view = self.hdr.scene_view(&graphics.device, &graphics.layouts, &graphics.memory, surface_size);
+        // Meshes are drawn first, and clear the scene themselves so the polygons can be drawn over them
+        #[cfg(feature = "3d")]
+        let mesh_draw_calls = if self.mesh_render.is_empty() {
+            0
+        } else {
+            let draw_calls = self.mesh_render.draw_all(&graphics.device, &mut encoder, scene_view, surface_size);
+            if let Some(gpu_profiler) = &mut self.gpu_profiler {
+                gpu_profiler.end_span(&mut encoder, "meshes");
+            }
+            draw_calls
+        };
+        #[cfg(not(feature = "3d"))]
+        let mesh_draw_calls = 0;
+        {
+            let mut render_pass =
+                encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Render Pass"),
+                    color_attachments: &[
+                        // This is what @location(0) in the fragment shader targets
+                        Some(wgpu::RenderPassColorAttachment {
+                            view: scene_view,
+                            resolve_target: None,
+                            ops: wgpu::Operations {
+                                load: if mesh_draw_calls > 0 {
+                                    wgpu::LoadOp::Load
+                                } else {
+                                    wgpu::LoadOp::Clear(wgpu::Color {
+                                        r: 0.0,
+                                        g: 0.0,
+                                        b: 0.0,
+                                        a: 1.0,
+                                    })
+                                },
+                                store: true,
+                            },
+                        }),
+                    ],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: stencil_view,
+                        depth_ops: None,
+                        stencil_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(0),
+                            store: false,
+                        }),
+                    }),
+                });
+
+            let draw_calls = self.poly_render.draw_all(
+                &graphics.device,
+                &mut render_pass,
+                DrawResources {
+                    meshes: &meshes,
+                    transforms: &transforms,
+                    camera_bind_group: &self.camera_matrix_buffer_bind_group,
+                    ui_camera_bind_group: &self.ui_matrix_buffer_bind_group,
+                },
+                surface_size,
+            );
+            let draw_calls = draw_calls + mesh_draw_calls + offscreen_draw_calls;
+            render_stats.draw_calls.store(draw_calls, std::sync::atomic::Ordering::Relaxed);
+        }
+        if let Some(gpu_profiler) = &mut self.gpu_profiler {
+            gpu_profiler.end_span(&mut encoder, "polygons");
+        }
+        self.hdr.resolve(&mut encoder, view);
+        if let Some(gpu_profiler) = &mut self.gpu_profiler {
+            gpu_profiler.end_span(&mut encoder, "tonemapping");
+        }
+        let overlay_commands = overlay(self, &mut encoder, surface_size);
+        if let Some(gpu_profiler) = &mut self.gpu_profiler {
+            #[cfg(feature = "egui")]
+            gpu_profiler.end_span(&mut encoder, "debug ui");
+            gpu_profiler.end(&mut encoder);
+        }
+        // submit will accept anything that implements IntoIter
+        graphics.queue.submit(overlay_commands.into_iter().chain(std::iter::once(encoder.finish())));
+        if let Some(gpu_profiler) = &mut self.gpu_profiler {
+            gpu_profiler.submitted();
+        }
+        self.poly_render.clear();
+        #[cfg(feature = "3d")]
+        self.mesh_render.clear();
+        graphics.errors.pop_scope(&graphics.device, "rendering");
+    }
+}
+
+impl GraphicsInner {
+    fn window_resized(&self) -> WindowResized {
+        let lock = self.config.lock();
+        WindowResized {
+            size: lock.size,
+            scale_factor: lock.scale_factor,
+        }
+    }
+
+    /// Reconfigures the surface if vsync was turned on or off since it was last configured
+    fn apply_vsync(&self, vsync: bool) {
+        let present_mode = if vsync {
+            wgpu::PresentMode::AutoVsync
+        } else {
+            wgpu::PresentMode::AutoNoVsync
+        };
+        let mut lock = self.config.lock();
+        if lock.config.present_mode != present_mode {
+            lock.config.present_mode = present_mode;
+            if let Some(surface) = &*self.surface() {
+                surface.configure(&self.device, &lock.config);
+            }
+        }
+    }
+
+    fn resize(&self, size: PhysicalSize<u32>) {
+        if size.width > 0 && size.height > 0 {
+            let mut lock = self.config.lock();
+            lock.size = size;
+            lock.config.width = size.width;
+            lock.config.height = size.height;
+            if let Some(surface) = &*self.surface() {
+                surface.configure(&self.device, &lock.config);
+            }
+        }
+    }
+
+    /// Gets the surface, which is `None` while the app is suspended
+    ///
+    /// # Panics
+    /// Panics if running headless
+    fn surface(&self) -> MutexGuard<'_, Option<wgpu::Surface>> {
+        match &self.target {
+            RenderTarget::Window { surface, .. } => surface.lock(),
+            RenderTarget::Texture(_) => unreachable!("Headless graphics has no surface"),
+        }
+    }
+
+    /// Creates a new surface after the app is resumed
+    ///
+    /// Returns false if the surface is not compatible with
+    /// the current device, in which case it must be recreated
+    fn resume_surface(&self, instance: &wgpu::Instance) -> bool {
+        let lock = self.config.lock();
+        let mut surface = self.surface();
+        if surface.is_some() {
+            return true;
+        }
+        let new_surface = create_surface(instance, self.window());
+        if !self.adapter.is_surface_supported(&new_surface)
+            || !new_surface
+                .get_capabilities(&self.adapter)
+                .formats
+                .contains(&lock.config.format)
+        {
+            return false;
+        }
+        new_surface.configure(&self.device, &lock.config);
+        *surface = Some(new_surface);
+        true
+    }
+
+    /// # Panics
+    /// Panics if running headless
+    fn window(&self) -> &Window {
+        match &self.target {
+            RenderTarget::Window { window, .. } => window,
+            RenderTarget::Texture(_) => unreachable!("Headless graphics has no window"),
+        }
+    }
+}
+
+impl Graphics {
+    /// Creates the Universe's side of the renderer, along with the render thread's side
+    fn new(inner: Arc<GraphicsInner>, scaling_mode: ScalingMode) -> (Self, RenderChannels) {
+        let latency = Arc::new(LatencyTracker::default());
+        let render_stats = Arc::new(RenderStats::default());
+        let universe_commands: Arc<SegQueue<UniverseCommand>> = Arc::new(SegQueue::new());
+        let window_commands: Arc<SegQueue<WindowCommand>> = Arc::new(SegQueue::new());
+        let cursor_position = Arc::new(AtomicCell::new(None));
+        let new_inner = Arc::new(Mutex::new(None));
+        let captures = Arc::new(SegQueue::new());
+        let lifecycle = Arc::new(Lifecycle::default());
+        let background_mode = Arc::new(AtomicCell::new(BackgroundMode::default()));
+        let vsync = Arc::new(AtomicBool::new(true));
+        let instructions = Arc::new(InstructionExchange::new());
+        #[cfg(feature = "egui")]
+        let debug_ui_input = Arc::new(Mutex::new(egui::RawInput::default()));
+
+        (
+            Self {
+                inner,
+                new_inner: new_inner.clone(),
+                instructions: instructions.clone(),
                 current_instructions_queue: SegQueue::new(),
-                active_camera: None
-            });
-            if let Some(result) = universe.loop_many(count, delta) {
-                drop(universe);
+                active_camera: None,
+                pending_camera: Mutex::new(None),
+                latency: latency.clone(),
+                scaling_mode,
+                universe_commands: universe_commands.clone(),
+                window_commands: window_commands.clone(),
+                cursor_position: cursor_position.clone(),
+                custom_cursor: Mutex::new(None),
+                render_stats: render_stats.clone(),
+                captures: captures.clone(),
+                lifecycle: lifecycle.clone(),
+                background_mode: background_mode.clone(),
+                vsync: vsync.clone(),
+                texture_writes: SegQueue::new(),
+                array_sprites: SegQueue::new(),
+                missing_texture_fallback: AtomicBool::new(false),
+                transform_slots: Arc::new(TransformSlots::new()),
+                upload_all_transforms: true,
+                ui_scale: AtomicF32::new(1.0),
+                sent_ui_scale: None,
+                hdr_settings: Mutex::new(HdrSettings::default()),
+                #[cfg(feature = "3d")]
+                camera_3d: Mutex::new(None),
+                gizmos: Gizmos::new(true),
+                minimap: Mutex::new(None),
+                minimap_markers: Gizmos::new(true),
+                missing_texture: Mutex::new(None),
+                memory_soft_limit: AtomicCell::new(None),
+                memory_pressure: Arc::new(Notify::new()),
+                pressure_total: AtomicU64::new(0),
+                lru_textures: Mutex::new(Vec::new()),
+                texture_budget: AtomicCell::new(None),
+                frame: 0,
+                warmups: Mutex::new(Vec::new()),
+                pending_close: Mutex::new(None),
+                awaiting_close: None,
+                #[cfg(feature = "egui")]
+                debug_ui: DebugUi::new(debug_ui_input.clone()),
+                #[cfg(feature = "egui")]
+                show_diagnostics: AtomicBool::new(false),
+                #[cfg(feature = "egui")]
+                show_inspector: AtomicBool::new(false),
+            },
+            RenderChannels {
+                instructions,
+                new_inner,
+                latency,
+                universe_commands,
+                window_commands,
+                cursor_position,
+                render_stats,
+                captures,
+                lifecycle,
+                background_mode,
+                vsync,
+                #[cfg(feature = "egui")]
+                debug_ui_input,
+            },
+        )
+    }
+
+    /// Creates a new GUI immediately
+    /// 
+    /// Generally, the only `DeltaStrategy` you should use is `RealDelta` with a delta
+    /// of 0. The window will stop the given `Universe` from processing more frames than needed.
+    ///
+    /// To avoid issues with cross compatability, the window's event loop must
+    /// use the main thread. This method ensures that is true while running the Universe
+    /// loop in a separate thread.
+    ///
+    /// Even though this function never returns, the universe will be safely dropped if a
+    /// component has requested an exit, even if an exit with an error was requested. Any data
+    /// not stored in the Universe will not be dropped however
+    ///
+    /// Closing the window sends a `WindowCloseRequested` event into the Universe, which exits
+    /// unless the close is vetoed. The window only closes once the Universe has been dropped
+    ///
+    /// If the surface stops being compatible with the current adapter (such as when a laptop
+    /// with switchable graphics docks or undocks), a new adapter is selected and the device
+    /// is recreated. Polygons rebuild their GPU buffers on their next flush, but textures that
+    /// were processed on the old device must be fetched again from their `TextureResource`
+    /// or `TextureHandle`
+    ///
+    /// On mobile platforms, the Universe is paused at the end of its current frame while the
+    /// app is in the background, and the surface is recreated when the app is resumed. Rotating
+    /// the device resizes the window, which is sent into the Universe as a `WindowResized` event
+    pub async fn run(universe: Universe, count: LoopCount, delta: DeltaStrategy, title: impl Into<String>, scaling_mode: ScalingMode) -> ! {
+        Self::run_with_builder(universe, count, delta, WindowBuilder::new().with_title(title), scaling_mode).await
+    }
+
+    /// The same as `run`, except that the window is built from the given `WindowBuilder`,
+    /// such as to open it at a size loaded from a settings file
+    pub async fn run_with_builder(universe: Universe, count: LoopCount, delta: DeltaStrategy, window_builder: WindowBuilder, scaling_mode: ScalingMode) -> ! {
+        Self::run_with_window(universe, count, delta, EventLoop::new(), window_builder, scaling_mode).await
+    }
+
+    /// The same as `run`, except that the window is built from the given `WindowBuilder`
+    /// on the given `EventLoop`
+    pub(crate) async fn run_with_window(#[cfg_attr(target_arch = "wasm32", allow(unused_mut))] mut universe: Universe, count: LoopCount, delta: DeltaStrategy, event_loop: EventLoop<()>, window_builder: WindowBuilder, scaling_mode: ScalingMode) -> ! {
+        let window = Arc::new(window_builder.build(&event_loop).unwrap());
+
+        let instance = new_instance();
+
+        // Android only provides a native window once the app is resumed
+        let resumed = !cfg!(target_os = "android");
+        let (graphics, mut render_state) = GraphicsInner::new(&instance, window.clone(), 0, resumed).await;
+        let mut graphics = Arc::new(graphics);
+
+        let (universe_graphics, channels) = Self::new(graphics.clone(), scaling_mode);
+        if !resumed {
+            channels.lifecycle.suspend();
+        }
+        let (exit_sender, mut exit_receiver) = bina_ecs::tokio::sync::oneshot::channel();
+        #[cfg(feature = "egui")]
+        let mut debug_ui_state = DebugUiState::new(&window, channels.debug_ui_input.clone());
+
+        universe.queue_set_singleton(universe_graphics);
+        let pacer = universe.frame_pacer().clone();
+        #[cfg(not(target_arch = "wasm32"))]
+        rayon::spawn(move || {
+            let result = universe.loop_many(count, delta);
+            // Everything in the Universe is dropped before the event loop ends the process
+            drop(universe);
+            if let Some(result) = result {
                 result.expect("Error while running Universe");
             }
             let _ = exit_sender.send(0);
         });
+        // Browsers only have one thread, so the Universe runs
+        // a frame right before every frame is drawn instead
+        #[cfg(target_arch = "wasm32")]
+        let mut universe_loop = web::UniverseLoop::new(universe, count, delta, exit_sender);
+
+        let mut surface_failures = 0usize;
+        // Mouse button events do not say where the cursor is
+        let mut cursor_position = Vector::new(0.0, 0.0);
+        // Keyboard events do not say which modifiers are held
+        let mut modifiers = ModifiersState::empty();
+        // Nothing drawn while the window is minimized or covered would be seen
+        #[cfg(not(target_arch = "wasm32"))]
+        let (mut minimized, mut occluded) = (false, false);
+        #[cfg(not(target_arch = "wasm32"))]
+        let mut last_background_frame = bina_ecs::time::Instant::now();
 
         event_loop.run(move |event, _, control_flow| {
             match event {
@@ -239,177 +1143,949 @@ impl Graphics {
                         *control_flow = ControlFlow::ExitWithCode(n);
                         return;
                     }
+                    while let Some(command) = channels.window_commands.pop() {
+                        command(graphics.window());
+                    }
+                    // Also restores vsync after the device is recreated
+                    graphics.apply_vsync(channels.vsync.load(Ordering::Relaxed));
+                    if channels.lifecycle.is_suspended() {
+                        return;
+                    }
 
-                    let mut instructions = {
-                        let backoff = Backoff::new();
-                        loop {
-                            let Some(tmp) = filled_instructions_receiver.pop() else {
-                                backoff.snooze();
-                                continue;
-                            };
-                            break tmp
+                    #[cfg(not(target_arch = "wasm32"))]
+                    if minimized || occluded {
+                        let next_frame = match channels.background_mode.load() {
+                            BackgroundMode::Run => None,
+                            BackgroundMode::Throttle(interval) => Some(last_background_frame + interval),
+                            BackgroundMode::Pause if channels.universe_commands.is_empty() => {
+                                // Woken up by the next window event
+                                *control_flow = ControlFlow::Wait;
+                                return;
+                            }
+                            BackgroundMode::Pause => None,
+                        };
+                        if let Some(next_frame) = next_frame.filter(|x| *x > bina_ecs::time::Instant::now()) {
+                            *control_flow = ControlFlow::WaitUntil(next_frame);
+                            return;
+                        }
+                        // The frame is accepted without acquiring the surface, which fails
+                        // on some platforms while the window is minimized
+                        if let Some(instructions) = channels.instructions.take_filled_timeout(FRAME_WAIT) {
+                            last_background_frame = bina_ecs::time::Instant::now();
+                            while channels.captures.pop().is_some() {}
+                            channels.return_instructions(instructions);
+                        }
+                        return;
+                    }
+                    *control_flow = ControlFlow::Poll;
+
+                    #[cfg(target_arch = "wasm32")]
+                    {
+                        if web::is_paused() {
+                            return;
                         }
+                        if let Some(size) = web::poll_canvas_size(graphics.window()) {
+                            graphics.resize(size);
+                        }
+                        universe_loop.run_frame();
+                    }
+
+                    // Waiting for the Universe would never end in browsers, as it runs on this thread
+                    #[cfg(target_arch = "wasm32")]
+                    let Some(mut instructions) = channels.instructions.try_take_filled() else {
+                        return;
+                    };
+                    // Sleeps until the Universe finishes a frame, waking up now and then so
+                    // that window events are still handled if the Universe stops sending frames
+                    #[cfg(not(target_arch = "wasm32"))]
+                    let Some(mut instructions) = channels.instructions.take_filled_timeout(FRAME_WAIT) else {
+                        return;
                     };
 
-                    let output = match graphics.surface.get_current_texture() {
-                        Ok(x) => x,
-                        Err(e) => match e {
-                            wgpu::SurfaceError::Lost => {
-                                let lock = graphics.config.lock();
-                                graphics.surface.configure(&graphics.device, &lock.config);
-                                return;
-                            }
-                            wgpu::SurfaceError::OutOfMemory => {
-                                *control_flow = ControlFlow::ExitWithCode(1);
-                                return;
+                    #[cfg(feature = "egui")]
+                    debug_ui_state.receive(&mut instructions);
+
+                    let output = graphics.surface().as_ref().map(wgpu::Surface::get_current_texture);
+                    let output = match output {
+                        Some(Ok(x)) => {
+                            surface_failures = 0;
+                            x
+                        }
+                        None => {
+                            channels.return_instructions(instructions);
+                            return;
+                        }
+                        Some(Err(e)) => {
+                            channels.return_instructions(instructions);
+                            match e {
+                                wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated => {
+                                    surface_failures += 1;
+                                    let supported = graphics
+                                        .surface()
+                                        .as_ref()
+                                        .is_some_and(|surface| graphics.adapter.is_surface_supported(surface));
+                                    if surface_failures > MAX_SURFACE_FAILURES || !supported {
+                                        if recreate_device(&instance, &window, &mut graphics, &mut render_state, &channels) {
+                                            surface_failures = 0;
+                                        } else {
+                                            *control_flow = ControlFlow::ExitWithCode(1);
+                                        }
+                                    } else {
+                                        let lock = graphics.config.lock();
+                                        if let Some(surface) = &*graphics.surface() {
+                                            surface.configure(&graphics.device, &lock.config);
+                                        }
+                                    }
+                                }
+                                wgpu::SurfaceError::OutOfMemory => {
+                                    *control_flow = ControlFlow::ExitWithCode(1);
+                                }
+                                wgpu::SurfaceError::Timeout => {}
                             }
-                            _ => return,
-                        },
+                            return;
+                        }
                     };
                     let view = output
                         .texture
                         .create_view(&wgpu::TextureViewDescriptor::default());
-                    let mut encoder =
-                        graphics
-                            .device
-                            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                                label: Some("Render Encoder"),
-                            });
-
-                    for instruction in instructions.drain(..) {
-                        match instruction {
-                            DrawInstruction::DrawPolygon(x) => poly_render.push(x),
-                        }
-                    }
-                    {
-                        let mut render_pass =
-                            encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                                label: Some("Render Pass"),
-                                color_attachments: &[
-                                    // This is what @location(0) in the fragment shader targets
-                                    Some(wgpu::RenderPassColorAttachment {
-                                        view: &view,
-                                        resolve_target: None,
-                                        ops: wgpu::Operations {
-                                            load: wgpu::LoadOp::Clear(wgpu::Color {
-                                                r: 0.0,
-                                                g: 0.0,
-                                                b: 0.0,
-                                                a: 1.0,
-                                            }),
-                                            store: true,
-                                        },
-                                    }),
-                                ],
-                                depth_stencil_attachment: None,
-                            });
-
-                        poly_render.draw_all(&mut render_pass, &camera_matrix_buffer_bind_group);
-                    }
-                    // submit will accept anything that implements IntoIter
-                    graphics.queue.submit(std::iter::once(encoder.finish()));
+                    #[cfg(feature = "egui")]
+                    let overlay = |render_state: &mut RenderState, encoder: &mut wgpu::CommandEncoder, surface_size| {
+                        debug_ui_state.render(
+                            &mut render_state.debug_ui_renderer,
+                            &graphics,
+                            encoder,
+                            &view,
+                            surface_size,
+                        )
+                    };
+                    #[cfg(not(feature = "egui"))]
+                    let overlay = |_: &mut RenderState, _: &mut wgpu::CommandEncoder, _| Vec::new();
+                    render_state.render(&graphics, &view, &mut instructions, &channels.render_stats, overlay);
                     output.present();
-                    poly_render.clear();
-
-                    unsafe {
-                        empty_instructions_sender
-                            .send(instructions)
-                            .unwrap_unchecked()
-                    }
+                    channels.latency.frame_presented();
+                    pacer.frame_presented();
+                    // Frames are only captured when running headless
+                    while channels.captures.pop().is_some() {}
+                    channels.return_instructions(instructions);
                 }
                 Event::WindowEvent {
                     ref event,
                     window_id,
-                } if window_id == graphics.window.id() => {
-                    let resize = |size: PhysicalSize<u32>| {
-                        if size.width > 0 && size.height > 0 {
-                            let mut lock = graphics.config.lock();
-                            lock.size = size;
-                            lock.config.width = size.width;
-                            lock.config.height = size.height;
-                            graphics.surface.configure(&graphics.device, &lock.config);
-                        }
-                    };
+                } if window_id == graphics.window().id() => {
+                    #[cfg(feature = "egui")]
+                    debug_ui_state.on_event(graphics.window(), event);
+
                     match event {
-                        WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+                        // The window closes once the Universe has exited, unless the close is vetoed
+                        WindowEvent::CloseRequested => {
+                            channels.universe_commands.push(Box::new(|universe| {
+                                let event = WindowCloseRequested::default();
+                                if let Some(graphics) = universe.try_get_singleton::<Graphics>() {
+                                    *graphics.pending_close.lock() = Some(event.clone());
+                                }
+                                universe.send_event(event);
+                            }));
+                        }
                         WindowEvent::Resized(physical_size) => {
-                            resize(*physical_size);
+                            #[cfg(not(target_arch = "wasm32"))]
+                            {
+                                minimized = physical_size.width == 0 || physical_size.height == 0;
+                            }
+                            graphics.resize(*physical_size);
+                            let event = graphics.window_resized();
+                            channels.universe_commands.push(Box::new(move |universe| universe.send_event(event)));
                         }
-                        WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                            resize(**new_inner_size);
+                        WindowEvent::ScaleFactorChanged { new_inner_size, scale_factor } => {
+                            graphics.config.lock().scale_factor = *scale_factor;
+                            graphics.resize(**new_inner_size);
+                            let event = graphics.window_resized();
+                            channels.universe_commands.push(Box::new(move |universe| universe.send_event(event)));
+                        }
+                        WindowEvent::CursorMoved { position, .. } => {
+                            channels.latency.input_received();
+                            cursor_position = Vector::new(position.x as f32, position.y as f32);
+                            channels.cursor_position.store(Some(cursor_position));
+                            let event = PointerMoved { position: cursor_position };
+                            channels.universe_commands.push(Box::new(move |universe| universe.send_event(event)));
+                        }
+                        WindowEvent::MouseInput { state, button, .. } => {
+                            channels.latency.input_received();
+                            let event = PointerButton {
+                                button: *button,
+                                pressed: *state == ElementState::Pressed,
+                                position: cursor_position,
+                            };
+                            channels.universe_commands.push(Box::new(move |universe| universe.send_event(event)));
+                        }
+                        WindowEvent::Touch(touch) => {
+                            channels.latency.input_received();
+                            let position = Vector::new(touch.location.x as f32, touch.location.y as f32);
+                            let pressed = match touch.phase {
+                                TouchPhase::Started => Some(true),
+                                TouchPhase::Moved => None,
+                                TouchPhase::Ended | TouchPhase::Cancelled => Some(false),
+                            };
+                            channels.universe_commands.push(Box::new(move |universe| {
+                                universe.send_event(PointerMoved { position });
+                                if let Some(pressed) = pressed {
+                                    universe.send_event(PointerButton {
+                                        button: MouseButton::Left,
+                                        pressed,
+                                        position,
+                                    });
+                                }
+                            }));
+                        }
+                        WindowEvent::KeyboardInput { input, .. } => {
+                            channels.latency.input_received();
+                            let event = KeyInput {
+                                key: input.virtual_keycode,
+                                scancode: input.scancode,
+                                pressed: input.state == ElementState::Pressed,
+                                modifiers,
+                            };
+                            channels.universe_commands.push(Box::new(move |universe| universe.send_event(event)));
                         }
-                        WindowEvent::KeyboardInput { .. } => {}
+                        WindowEvent::ReceivedCharacter(character) => {
+                            let event = CharacterTyped(*character);
+                            channels.universe_commands.push(Box::new(move |universe| universe.send_event(event)));
+                        }
+                        WindowEvent::CursorLeft { .. } => channels.cursor_position.store(None),
+                        WindowEvent::ModifiersChanged(state) => modifiers = *state,
+                        #[cfg(not(target_arch = "wasm32"))]
+                        WindowEvent::Occluded(is_occluded) => occluded = *is_occluded,
+                        WindowEvent::MouseWheel { .. } => channels.latency.input_received(),
                         _ => {}
                     }
                 }
+                Event::DeviceEvent {
+                    event: DeviceEvent::MouseMotion { delta },
+                    ..
+                } => {
+                    channels.latency.input_received();
+                    let event = MouseMotion {
+                        delta: Vector::new(delta.0 as f32, delta.1 as f32),
+                    };
+                    channels.universe_commands.push(Box::new(move |universe| universe.send_event(event)));
+                }
+                Event::Suspended => {
+                    channels.lifecycle.suspend();
+                    *graphics.surface() = None;
+                    // Nothing is drawn until the app is resumed
+                    *control_flow = ControlFlow::Wait;
+                }
+                Event::Resumed => {
+                    if !graphics.resume_surface(&instance)
+                        && !recreate_device(&instance, &window, &mut graphics, &mut render_state, &channels)
+                    {
+                        *control_flow = ControlFlow::ExitWithCode(1);
+                        return;
+                    }
+                    // The device may have been rotated while the app was suspended
+                    let size = graphics.window().inner_size();
+                    if size != graphics.config.lock().size {
+                        graphics.resize(size);
+                        let event = graphics.window_resized();
+                        channels.universe_commands.push(Box::new(move |universe| universe.send_event(event)));
+                    }
+                    channels.lifecycle.resume();
+                    *control_flow = ControlFlow::Poll;
+                }
                 _ => {}
             }
         });
     }
 
+    /// Runs the given Universe while rendering into an off-screen texture of the given size,
+    /// without a window or an event loop
+    ///
+    /// This is meant for integration tests and dedicated servers that need to run where there
+    /// is no display, such as in CI. Frames can be read back with `Graphics::queue_capture`.
+    /// Unlike `run`, this does not need the main thread and returns once the Universe exits,
+    /// with the error the Universe exited with, if any. The debug overlay is never drawn
+    ///
+    /// Not available in browsers
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn run_headless(mut universe: Universe, count: LoopCount, delta: DeltaStrategy, size: PhysicalSize<u32>, scaling_mode: ScalingMode) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut renderer = testing::HeadlessRenderer::new(&universe, size, scaling_mode).await;
+        let (exit_sender, mut exit_receiver) = bina_ecs::tokio::sync::oneshot::channel();
+
+        rayon::spawn(move || {
+            let result = universe.loop_many(count, delta);
+            drop(universe);
+            let _ = exit_sender.send(result.unwrap_or(Ok(())));
+        });
+
+        loop {
+            if let Ok(result) = exit_receiver.try_recv() {
+                return result;
+            }
+            renderer.render_frame(FRAME_WAIT);
+        }
+    }
+
     pub(crate) fn queue_draw_instruction(&self, instruction: DrawInstruction) {
         self.current_instructions_queue.push(instruction);
     }
+
+    pub(crate) fn queue_array_sprite(&self, sprite: QueuedSprite) {
+        self.array_sprites.push(sprite);
+    }
+
+    /// Sets the camera that the scene is rendered through
+    ///
+    /// The camera becomes active after the current process frame ends
+    pub fn queue_set_camera(&self, camera: Camera) {
+        *self.pending_camera.lock() = Some(camera);
+    }
+
+    /// Gets a reference to the active camera, if there is one
+    ///
+    /// Changes made through the reference are applied when the process frame ends
+    pub fn camera(&self) -> Option<CameraRef<'_>> {
+        self.active_camera.as_ref().map(Component::get_ref)
+    }
+
+    /// Gets the transform from world coordinates to normalized device coordinates
+    /// for the active camera, or the default view if there is no camera
+    fn view_transform(&self) -> ViewTransform {
+        let size = self.surface_size();
+        match &self.active_camera {
+            Some(camera) => camera.view_transform(size, self.scaling_mode),
+            None => ViewTransform::new(
+                Vector::new(0.0, 0.0),
+                Vector::new(0.5, 0.5),
+                0.0,
+                size,
+                self.scaling_mode,
+            ),
+        }
+    }
+
+    /// Converts a position on the window in physical pixels, such as the position
+    /// of the cursor, into world coordinates using the active camera
+    pub fn screen_to_world(&self, position: Vector) -> Vector {
+        self.view_transform().screen_to_world(position)
+    }
+
+    /// Converts world coordinates into a position on the window in physical pixels
+    /// using the active camera
+    pub fn world_to_screen(&self, position: Vector) -> Vector {
+        self.view_transform().world_to_screen(position)
+    }
+
+    /// Sets the camera that meshes are drawn through, or stops drawing meshes if it is `None`
+    #[cfg(feature = "3d")]
+    pub fn set_camera_3d(&self, camera: Option<Camera3D>) {
+        *self.camera_3d.lock() = camera;
+    }
+
+    #[cfg(feature = "3d")]
+    pub fn camera_3d(&self) -> Option<Camera3D> {
+        *self.camera_3d.lock()
+    }
+
+    /// Shows a minimap on top of the UI, or removes it if `None`, starting from the next flush
+    pub fn set_minimap(&self, minimap: Option<Minimap>) {
+        *self.minimap.lock() = minimap;
+    }
+
+    /// Gets the markers to draw on the minimap this frame, in world coordinates
+    ///
+    /// Markers are drawn like gizmos, but only on the minimap, and are discarded if there is no minimap
+    pub fn minimap_markers(&self) -> &Gizmos {
+        &self.minimap_markers
+    }
+
+    /// Gets the lines, shapes and text to draw on the debug layer this frame
+    ///
+    /// This can also be reached with `universe.gizmos()` through `gizmos::UniverseGizmos`
+    pub fn gizmos(&self) -> &Gizmos {
+        &self.gizmos
+    }
+
+    /// Gets how many logical pixels each UI unit covers
+    pub fn ui_scale(&self) -> f32 {
+        self.ui_scale.load(Ordering::Relaxed)
+    }
+
+    /// Scales everything drawn through the UI camera and the debug overlay, such as for a
+    /// setting that makes the UI larger
+    ///
+    /// Defaults to 1, where each UI unit is one logical pixel. UI units already follow the scale
+    /// factor of the monitor, so this only needs to change for the preference of the player
+    pub fn set_ui_scale(&self, scale: f32) {
+        self.ui_scale.store(scale, Ordering::Relaxed);
+    }
+
+    pub fn hdr_settings(&self) -> HdrSettings {
+        *self.hdr_settings.lock()
+    }
+
+    /// Changes how the scene is tonemapped, starting from the frame sent at the end of this process frame
+    pub fn set_hdr_settings(&self, settings: HdrSettings) {
+        *self.hdr_settings.lock() = settings;
+    }
+
+    fn ui_transform(&self) -> ViewTransform {
+        let pixels_per_unit = self.scale_factor() as f32 * self.ui_scale();
+        ViewTransform::ui(self.surface_size(), pixels_per_unit.max(f32::EPSILON))
+    }
+
+    /// Gets the size of the window in UI units
+    ///
+    /// This stays the same when the window moves between monitors with different scale factors,
+    /// as long as its logical size does not change
+    pub fn ui_size(&self) -> Vector {
+        let transform = self.ui_transform();
+        let size = self.surface_size();
+        transform.screen_to_world(Vector::new(size.width as f32, size.height as f32))
+    }
+
+    /// Converts a position on the window in physical pixels, such as the position
+    /// of the cursor, into UI coordinates
+    pub fn screen_to_ui(&self, position: Vector) -> Vector {
+        self.ui_transform().screen_to_world(position)
+    }
+
+    /// Gets the size of the area being drawn to in physical pixels
+    pub fn surface_size(&self) -> PhysicalSize<u32> {
+        self.inner.config.lock().size
+    }
+
+    /// Gets the ratio of physical pixels to logical pixels of the monitor
+    /// the window is on
+    pub fn scale_factor(&self) -> f64 {
+        self.inner.config.lock().scale_factor
+    }
+
+    /// Gets the size of the area being drawn to in logical pixels, which ignores the UI scale
+    pub fn logical_size(&self) -> LogicalSize<f64> {
+        let config = self.inner.config.lock();
+        config.size.to_logical(config.scale_factor)
+    }
+
+    /// Gets an estimate of the time between input being received
+    /// and its effects being presented
+    pub fn input_latency(&self) -> InputLatency {
+        self.latency.get()
+    }
+
+    /// Resets all input latency statistics
+    pub fn reset_input_latency(&self) {
+        self.latency.reset();
+    }
+
+    /// Calls the given function with a copy of the next frame that is rendered
+    ///
+    /// Frames are only captured when running through `Graphics::run_headless` or a `HeadlessRenderer`.
+    /// Otherwise, the function is dropped without being called
+    pub fn queue_capture(&self, callback: impl FnOnce(image::RgbaImage) + Send + 'static) {
+        self.captures.push(Box::new(callback));
+    }
+
+    /// Starts loading a texture whose size is read from the image itself
+    ///
+    /// Unlike loading through `Assets`, the texture is never shared with other handles
+    ///
+    /// ```ignore
+    /// let handle = graphics.load_texture(universe, "avatar.png");
+    /// // Later, once it has loaded
+    /// if let Some(asset) = handle.get() {
+    ///     polygon.material = Material::Texture(asset.get(graphics));
+    /// }
+    /// ```
+    pub fn load_texture(&self, universe: &Universe, source: impl Into<AssetSource>) -> TextureHandle {
+        let source = source.into();
+        let loader = TextureLoader {
+            name: match &source {
+                AssetSource::Path(path) | AssetSource::Mounted(_, path) => Some(path.clone()),
+                AssetSource::Bytes(_) => None,
+            },
+            ..Default::default()
+        };
+        TextureHandle::load(universe, std::sync::Arc::new(loader), source, |_| {})
+    }
+
+    /// Creates a transparent texture that can be drawn to with `TextureAsset::write_region`
+    pub fn create_texture(&self, width: u32, height: u32) -> TextureHandle {
+        TextureHandle::from_asset(TextureAsset::new(
+            image::RgbaImage::new(width, height),
+            SamplerOptions::default(),
+        ))
+    }
+
+    pub(crate) fn queue_texture_write(&self, write: TextureWrite) {
+        self.texture_writes.push(write);
+    }
+
+    /// Gets how much GPU memory the textures and buffers on the current device are using
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.inner.memory.usage()
+    }
+
+    /// Sets how many bytes of GPU memory can be used before `TextureResource`s loaded with
+    /// `CacheOption::UncacheAfter` are unloaded early, instead of after their duration.
+    /// Textures that are being drawn are never unloaded. There is no limit by default
+    pub fn set_memory_soft_limit(&self, bytes: Option<u64>) {
+        self.memory_soft_limit.store(bytes);
+    }
+
+    pub(crate) fn memory_pressure(&self) -> Arc<Notify> {
+        self.memory_pressure.clone()
+    }
+
+    /// Sets how many bytes the `TextureResource`s loaded with `CacheOption::Lru` can use together
+    /// before the least recently drawn are unloaded. Textures that are being drawn are never unloaded,
+    /// so the budget can be exceeded for as long as they are. There is no budget by default
+    pub fn set_texture_budget(&self, bytes: Option<u64>) {
+        self.texture_budget.store(bytes);
+    }
+
+    pub(crate) fn cache_texture(&self, texture: &'static dyn CachedTexture) {
+        let mut textures = self.lru_textures.lock();
+        // It is registered again every time it is reloaded
+        if !textures.iter().any(|x| std::ptr::addr_eq(*x, texture)) {
+            textures.push(texture);
+        }
+    }
+
+    /// Uploads every `TextureAsset` in `preload` to the GPU as soon as it has loaded,
+    /// then creates every render pipeline on the render thread,
+    /// so that the first frames that draw with them do not hitch
+    ///
+    /// The returned `Warmup` finishes once both are done, and can be added to `preload`
+    /// so that a loading screen waits for it
+    pub fn warm_up(&self, preload: &ProgressHandle) -> Warmup {
+        let (pending, warmup) = PendingWarmup::new(preload.handles().cloned().collect());
+        self.warmups.lock().push(pending);
+        warmup
+    }
+
+    /// Unloads the least recently drawn `CacheOption::Lru` textures until they fit in the budget
+    fn evict_textures(&self) {
+        let Some(budget) = self.texture_budget.load() else {
+            return;
+        };
+        let mut textures = self.lru_textures.lock();
+        let mut loaded: Vec<_> = textures
+            .iter()
+            .filter_map(|x| Some((x.last_drawn()?, *x)))
+            .collect();
+        let mut used: u64 = loaded.iter().map(|(_, x)| x.bytes()).sum();
+        if used <= budget {
+            return;
+        }
+        loaded.sort_unstable_by_key(|(last_drawn, _)| *last_drawn);
+        for (_, texture) in loaded {
+            if used <= budget {
+                break;
+            }
+            if texture.try_unload() {
+                used -= texture.bytes();
+            }
+        }
+        // Unloaded textures register themselves again when they are reloaded
+        textures.retain(|x| x.last_drawn().is_some());
+    }
+
+    /// Draws `TextureResource`s that failed to load with the missing texture,
+    /// so that a missing file is obvious instead of the polygon disappearing
+    pub fn set_missing_texture_fallback(&self, enabled: bool) {
+        self.missing_texture_fallback.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Runs `command` with the window on the thread that runs the event loop, before the next frame is drawn
+    ///
+    /// Some platforms only allow windows and native dialogs to be used from that thread.
+    /// Commands are dropped without running when running headless
+    pub fn run_on_window(&self, command: impl FnOnce(&Window) + Send + 'static) {
+        self.window_commands.push(Box::new(command));
+    }
+
+    /// Changes the icon of the system cursor while it is over the window
+    pub fn set_cursor_icon(&self, icon: CursorIcon) {
+        self.window_commands.push(Box::new(move |window| window.set_cursor_icon(icon)));
+    }
+
+    /// Shows or hides the system cursor while it is over the window
+    pub fn set_cursor_visible(&self, visible: bool) {
+        self.window_commands.push(Box::new(move |window| window.set_cursor_visible(visible)));
+    }
+
+    /// Confines the cursor to the window, locks it in place, or releases it
+    ///
+    /// Locking the cursor and hiding it with `set_cursor_visible` suits camera controls, which
+    /// can read how far the mouse moved from `MouseMotion` events. Platforms that cannot lock
+    /// the cursor confine it instead, and platforms that cannot confine it lock it instead
+    pub fn set_cursor_grab(&self, mode: CursorGrabMode) {
+        self.window_commands.push(Box::new(move |window| {
+            let fallback = match mode {
+                CursorGrabMode::None => None,
+                CursorGrabMode::Confined => Some(CursorGrabMode::Locked),
+                CursorGrabMode::Locked => Some(CursorGrabMode::Confined),
+            };
+            let result = window
+                .set_cursor_grab(mode)
+                .or_else(|e| fallback.map_or(Err(e), |x| window.set_cursor_grab(x)));
+            if let Err(e) = result {
+                log::warn!("Failed to grab the cursor: {e}");
+            }
+        }));
+    }
+
+    /// Draws an image in place of the system cursor, or shows the system cursor again if `None`
+    ///
+    /// The image is drawn during each flush at the latest position of the cursor,
+    /// and is hidden while the cursor is outside of the window
+    pub fn set_custom_cursor(&self, cursor: Option<CustomCursor>) {
+        self.set_cursor_visible(cursor.is_none());
+        *self.custom_cursor.lock() = cursor;
+    }
+
+    /// Sets what the Universe does while the window is minimized or completely covered,
+    /// which is `BackgroundMode::Run` by default
+    ///
+    /// Ignored in browsers, which already slow down pages that cannot be seen
+    pub fn set_background_mode(&self, mode: BackgroundMode) {
+        self.background_mode.store(mode);
+    }
+
+    pub fn vsync(&self) -> bool {
+        self.vsync.load(Ordering::Relaxed)
+    }
+
+    /// Sets whether frames are presented in step with the refresh rate of the monitor, which
+    /// is true by default. Turning it off lowers latency at the cost of tearing
+    ///
+    /// Ignored when running headless, and by browsers, which always present in step
+    pub fn set_vsync(&self, vsync: bool) {
+        self.vsync.store(vsync, Ordering::Relaxed);
+    }
+
+    /// Asks for the inside of the window to be resized, which is sent into the Universe
+    /// as a `WindowResized` event once it happens
+    ///
+    /// Some platforms, such as mobile platforms and tiling window managers, ignore this
+    pub fn set_window_size(&self, size: PhysicalSize<u32>) {
+        self.window_commands.push(Box::new(move |window| {
+            window.set_inner_size(size);
+        }));
+    }
+
+    pub(crate) fn missing_texture_fallback(&self) -> bool {
+        self.missing_texture_fallback.load(Ordering::Relaxed)
+    }
+
+    /// Gets a magenta texture that stands in for textures that could not be loaded
+    pub fn missing_texture(&self) -> Texture {
+        let mut texture = self.missing_texture.lock();
+        let texture = match &*texture {
+            Some(texture) if texture.generation == self.inner.generation => texture.clone(),
+            _ => texture
+                .insert(Arc::new(texture::load_img(self, &[255, 0, 255, 255], 1, 1, SamplerOptions::PIXEL_ART, Some("missing texture"))))
+                .clone(),
+        };
+        Texture {
+            texture: TextureRef::Shared(texture),
+        }
+    }
+
+    /// Gets the best family of compressed textures that the GPU supports,
+    /// which decides which copy of a `CompressedTextureAsset` should be loaded
+    pub fn compression_family(&self) -> Option<CompressionFamily> {
+        CompressionFamily::select(&self.inner.device)
+    }
+
+    /// Gets how long the GPU took to execute each render pass of a recent frame
+    ///
+    /// Empty if the adapter does not support timestamp queries
+    pub fn gpu_profile(&self) -> Vec<GpuSpan> {
+        self.render_stats.gpu_spans()
+    }
+
+    /// Adds to the debug overlay, which is drawn on top of everything else
+    ///
+    /// Like any immediate mode UI, this must be called every frame that
+    /// the contents should be visible. Calls from parallel components are
+    /// serialized, so this should be kept short
+    ///
+    /// ```ignore
+    /// graphics.debug_ui(|ctx| {
+    ///     egui::Window::new("Player").show(ctx, |ui| {
+    ///         ui.add(egui::Slider::new(&mut speed, 0.0..=10.0).text("speed"));
+    ///     });
+    /// });
+    /// ```
+    #[cfg(feature = "egui")]
+    pub fn debug_ui(&self, add_contents: impl FnOnce(&egui::Context)) {
+        self.debug_ui.run(add_contents);
+    }
+
+    /// Makes text in the debug overlay, including gizmo text, use a font embedded with `load_font!`
+    ///
+    /// Fonts added earlier are preferred over those added later, and the default fonts are only
+    /// used for glyphs that no added font has. A font can also be chosen with
+    /// `egui::FontFamily::Name` and its family name
+    #[cfg(feature = "egui")]
+    pub fn add_font(&self, font: &'static font::FontResource) {
+        self.debug_ui.add_font(font);
+    }
+
+    /// Shows or hides the diagnostics overlay
+    ///
+    /// The overlay shows the statistics collected by the `Diagnostics` singleton,
+    /// so it is empty unless that singleton has been added to the Universe
+    #[cfg(feature = "egui")]
+    pub fn set_diagnostics_visible(&self, visible: bool) {
+        self.show_diagnostics.store(visible, Ordering::Relaxed);
+    }
+
+    #[cfg(feature = "egui")]
+    pub fn toggle_diagnostics(&self) {
+        self.show_diagnostics.fetch_xor(true, Ordering::Relaxed);
+    }
+
+    /// Shows or hides the entity inspector
+    ///
+    /// The inspector lists the entities registered in the `ReflectRegistry` singleton,
+    /// so it is empty unless that singleton has been added to the Universe
+    #[cfg(feature = "egui")]
+    pub fn set_inspector_visible(&self, visible: bool) {
+        self.show_inspector.store(visible, Ordering::Relaxed);
+    }
+
+    #[cfg(feature = "egui")]
+    pub fn toggle_inspector(&self) {
+        self.show_inspector.fetch_xor(true, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Graphics {
+    /// Lets the render thread know that no more frames are coming
+    fn drop(&mut self) {
+        self.instructions.close();
+    }
 }
 
 impl Singleton for Graphics {
-    fn process(&self, _universe: &Universe) {}
+    /// Flushed after entities, so that every polygon has queued its instructions before they are sent
+    fn flush_stage(&self) -> FlushStage {
+        FlushStage::PreRender
+    }
 
-    fn flush(&mut self, _universe: &Universe) {
-        if self.current_instructions_queue.is_empty() {
-            return;
+    fn process(&self, universe: &Universe) {
+        while let Some(command) = self.universe_commands.pop() {
+            command(universe);
         }
-        let empty_instructions_recv = self.empty_instructions_recv.get_mut();
-
-        let mut vec = empty_instructions_recv.try_recv().unwrap_or_else(|e| match e {
-            TryRecvError::Empty => {
-                // println!("No buffer");
-                match empty_instructions_recv.recv() {
-                    Ok(x) => x,
-                    Err(_) => loop {
-                        // If the event loop has closed, it is only a matter
-                        // of time before this thread will end as well,
-                        // as the event loop is always running on the main thread
-                        std::hint::spin_loop()
-                    }
-                }}
-            
-            TryRecvError::Disconnected => loop {
-                // If the event loop has closed, it is only a matter
-                // of time before this thread will end as well,
-                // as the event loop is always running on the main thread
-                std::hint::spin_loop()
+        let diagnostics = universe.try_get_singleton::<Diagnostics>();
+        while let Some(error) = self.inner.errors.pop() {
+            if let Some(diagnostics) = diagnostics {
+                diagnostics.report("wgpu", error.to_string());
+                diagnostics.add_counter("gpu errors", 1.0);
             }
-        });
+            universe.send_event(error);
+        }
+        let memory = self.memory_usage();
+        match self.memory_soft_limit.load() {
+            Some(limit) if memory.total() > limit => {
+                if self.pressure_total.swap(memory.total(), Ordering::Relaxed) < memory.total() {
+                    self.memory_pressure.notify_waiters();
+                }
+            }
+            _ => self.pressure_total.store(0, Ordering::Relaxed),
+        }
+        if let Some(diagnostics) = diagnostics {
+            diagnostics.set_counter("draw calls", self.render_stats.draw_calls() as f64);
+            const MEGABYTE: f64 = 1024.0 * 1024.0;
+            for category in MemoryCategory::ALL {
+                diagnostics.set_counter(category.counter_name(), memory.bytes(category) as f64 / MEGABYTE);
+            }
+            diagnostics.set_counter(memory::TOTAL_COUNTER, memory.total() as f64 / MEGABYTE);
+            let gpu_spans = self.render_stats.gpu_spans();
+            if !gpu_spans.is_empty() {
+                let gpu_time: std::time::Duration = gpu_spans.iter().map(|x| x.duration).sum();
+                diagnostics.set_counter("gpu time (ms)", gpu_time.as_secs_f64() * 1000.0);
+            }
+            let latency = self.latency.get();
+            if latency.samples > 0 {
+                diagnostics.set_counter("input latency (ms)", latency.average.as_secs_f64() * 1000.0);
+            }
+            #[cfg(feature = "egui")]
+            if self.show_diagnostics.load(Ordering::Relaxed) {
+                self.debug_ui(|ctx| {
+                    debug_ui::diagnostics_window(ctx, diagnostics, universe.last_frame_profile(), &gpu_spans)
+                });
+            }
+        }
+        #[cfg(feature = "egui")]
+        if self.show_inspector.load(Ordering::Relaxed) {
+            if let Some(registry) = universe.try_get_singleton::<bina_ecs::reflect::ReflectRegistry>() {
+                self.debug_ui(|ctx| debug_ui::inspector_window(ctx, universe, registry));
+            }
+        }
+    }
 
-        let camera_floats = self.active_camera.as_ref().map(|x| {
-            let scale = x.scale.get_inner();
-            let rot = x.rotation.get_inner();
-            let origin = x.origin.get_inner();
-            let basis = Matrix2::<f32>::new(rot.cos() * scale.x, rot.sin() * scale.x, -rot.sin() * scale.y, rot.cos() * scale.y);
-            let basis = basis.try_inverse().unwrap_or_else(|| Matrix2::identity());
-            [
-                basis.m11,
-                basis.m12,
-                basis.m21,
-                basis.m22,
-                origin.x,
-                origin.y
-            ]
-        }).unwrap_or_else(|| {
-            [
-                2.0, 0.0,
-                0.0, 2.0,
-                0.0, 0.0
-            ]
-        });
+    fn flush(&mut self, universe: &Universe) {
+        if let Some(request) = self.awaiting_close.take() {
+            if !request.is_vetoed() {
+                universe.exit_ok();
+            }
+        }
+        self.awaiting_close = self.pending_close.get_mut().take();
+        // Stops the Universe while the app is in the background
+        self.lifecycle.wait_until_resumed();
+        if let Some(inner) = self.new_inner.lock().take() {
+            self.inner = inner;
+            self.upload_all_transforms = true;
+            #[cfg(feature = "egui")]
+            self.debug_ui.reset();
+        }
+        if let Some(camera) = self.pending_camera.get_mut().take() {
+            self.active_camera = Some(camera);
+        }
+        let ui_scale = (self.scale_factor(), self.ui_scale());
+        if self.sent_ui_scale != Some(ui_scale) {
+            self.sent_ui_scale = Some(ui_scale);
+            universe.send_event(UiScaleChanged {
+                scale_factor: ui_scale.0,
+                ui_scale: ui_scale.1,
+            });
+        }
+        if let Some(position) = self.cursor_position.load() {
+            let position = self.screen_to_ui(position);
+            if let Some(cursor) = &mut *self.custom_cursor.lock() {
+                cursor.polygon.draw_overlay(self, position, u32::MAX);
+            }
+        }
+        // The UI is never lit
+        let light = universe
+            .try_get_singleton::<AmbientLight>()
+            .map_or([1.0; 3], AmbientLight::light);
+        let mut minimap_pass = match &mut *self.minimap.lock() {
+            Some(minimap) => Some(minimap.prepare(self, light)),
+            None => {
+                // Otherwise markers would pile up until a minimap is added
+                self.minimap_markers.clear();
+                None
+            }
+        };
+        self.frame += 1;
+        self.evict_textures();
+        // Written before this frame's instructions are sent, so they are visible in it
+        while let Some(write) = self.texture_writes.pop() {
+            write.apply(self);
+        }
+        let mut ready_warmups = Vec::new();
+        for mut warmup in std::mem::take(self.warmups.get_mut()) {
+            if warmup.upload(self) {
+                ready_warmups.push(warmup.into_done());
+            } else {
+                self.warmups.get_mut().push(warmup);
+            }
+        }
+        transforms::upload(
+            &self.transform_slots,
+            &self.inner.transform_buffer,
+            &self.inner.device,
+            &self.inner.queue,
+            &self.inner.layouts.transform,
+            &self.inner.memory,
+            std::mem::take(&mut self.upload_all_transforms),
+        );
+        if let Some(camera) = &mut self.active_camera {
+            camera.update(universe);
+        }
+        let camera_layers = self
+            .active_camera
+            .as_ref()
+            .map(|x| x.layers.get_inner())
+            .unwrap_or(RenderLayers::ALL);
+        // Queued before the debug UI, which must be the last instruction
+        let gizmo_frame = self.gizmos.take_frame(&self.inner, &self.view_transform());
+        if camera_layers.intersects(RenderLayers::DEBUG) {
+            if let Some(batch) = gizmo_frame.batch {
+                self.queue_draw_instruction(DrawInstruction::Gizmos(batch));
+            }
+            #[cfg(feature = "egui")]
+            if !gizmo_frame.text.is_empty() {
+                self.debug_ui(|ctx| gizmos::paint_text(ctx, gizmo_frame.text));
+            }
+        }
+        #[cfg(feature = "egui")]
+        if let Some(frame) = self
+            .debug_ui
+            .end_frame(self.scale_factor() as f32 * self.ui_scale(), self.surface_size())
+        {
+            self.queue_draw_instruction(DrawInstruction::DebugUi(frame));
+        }
+        // Input is consumed by this simulation frame even if nothing new is drawn
+        self.latency.frame_submitted();
+        if self.current_instructions_queue.is_empty() && self.array_sprites.is_empty() && ready_warmups.is_empty() {
+            return;
+        }
+        let Some(mut vec) = self.instructions.take_empty() else {
+            // Nothing will draw this frame, as the window has closed
+            universe.exit_ok();
+            return;
+        };
+
+        let camera_floats = self.view_transform().to_uniform(light);
 
         self.inner.queue.write_buffer(&self.inner.camera_matrix_buffer, 0, bytemuck::cast_slice(&camera_floats));
+        let ui_floats = self.ui_transform().to_uniform([1.0; 3]);
+        self.inner.queue.write_buffer(&self.inner.ui_matrix_buffer, 0, bytemuck::cast_slice(&ui_floats));
+        let hdr_floats = self.hdr_settings.get_mut().to_uniform();
+        self.inner.queue.write_buffer(&self.inner.hdr_settings_buffer, 0, bytemuck::cast_slice(&hdr_floats));
+        #[cfg(feature = "3d")]
+        let camera_3d = *self.camera_3d.get_mut();
+        #[cfg(feature = "3d")]
+        if let Some(camera) = camera_3d {
+            let size = self.surface_size();
+            let aspect = size.width.max(1) as f32 / size.height.max(1) as f32;
+            let matrix = camera.view_projection(aspect);
+            self.inner.queue.write_buffer(&self.inner.camera_3d_buffer, 0, bytemuck::cast_slice(matrix.as_slice()));
+        }
 
         vec.reserve(self.current_instructions_queue.len());
         while let Some(instruction) = self.current_instructions_queue.pop() {
-            vec.push(instruction);
+            if let (Some(pass), DrawInstruction::DrawPolygon(x)) = (&mut minimap_pass, &instruction) {
+                // Clip rectangles are in pixels of the window, which do not match the minimap
+                if !x.is_ui() && x.layers.intersects(pass.layers) {
+                    pass.polygons.push(DrawPolygon { clip: None, ..x.clone() });
+                }
+            }
+            if let DrawInstruction::DrawPolygon(x) = &instruction {
+                if let Material::Texture(texture) = &x.polygon.material {
+                    texture.texture.last_drawn.store(self.frame, Ordering::Relaxed);
+                }
+            }
+            match &instruction {
+                // The UI camera draws the UI layer no matter which layers the active camera sees
+                DrawInstruction::DrawPolygon(x) if !x.is_ui() && !x.layers.intersects(camera_layers) => continue,
+                #[cfg(feature = "3d")]
+                DrawInstruction::DrawMesh(_) if camera_3d.is_none() => continue,
+                _ => vec.push(instruction),
+            }
+        }
+        let sprites: Vec<_> = std::iter::from_fn(|| self.array_sprites.pop()).collect();
+        let sprite_batches = texture_array::batch(
+            &self.inner,
+            sprites.iter().filter(|x| x.is_ui() || x.layers.intersects(camera_layers)),
+        );
+        // Before the debug UI, which must be the last instruction
+        vec.splice(0..0, sprite_batches.into_iter().map(DrawInstruction::SpriteBatch));
+        if let Some(pass) = &mut minimap_pass {
+            pass.sprite_batches = texture_array::batch(
+                &self.inner,
+                sprites.iter().filter(|x| !x.is_ui() && x.layers.intersects(pass.layers)),
+            );
+        }
+        if let Some(pass) = minimap_pass {
+            // First, as the debug UI must be the last instruction
+            vec.insert(0, DrawInstruction::Offscreen(pass));
         }
-        unsafe { self.filled_instructions_sender.push(vec).unwrap_unchecked() }
+        vec.splice(0..0, ready_warmups.into_iter().map(DrawInstruction::Warmup));
+        self.instructions.send_filled(vec);
     }
 }
\ No newline at end of file
@@ -0,0 +1,33 @@
+use bina_ecs::parking_lot::{Condvar, Mutex};
+
+/// Whether the app is in the background, shared between the render thread and the Universe
+///
+/// Mobile platforms suspend apps when they are backgrounded, taking away their window
+#[derive(Default)]
+pub(crate) struct Lifecycle {
+    suspended: Mutex<bool>,
+    resumed: Condvar,
+}
+
+impl Lifecycle {
+    pub(crate) fn suspend(&self) {
+        *self.suspended.lock() = true;
+    }
+
+    pub(crate) fn resume(&self) {
+        *self.suspended.lock() = false;
+        self.resumed.notify_all();
+    }
+
+    pub(crate) fn is_suspended(&self) -> bool {
+        *self.suspended.lock()
+    }
+
+    /// Blocks until the app is resumed, if it is suspended
+    pub(crate) fn wait_until_resumed(&self) {
+        let mut suspended = self.suspended.lock();
+        while *suspended {
+            self.resumed.wait(&mut suspended);
+        }
+    }
+}
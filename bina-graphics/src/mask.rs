@@ -0,0 +1,101 @@
+use bina_ecs::triomphe::Arc;
+use wgpu::{Device, TextureView};
+use winit::dpi::PhysicalSize;
+
+use crate::memory::{GpuMemory, MemoryAllocation};
+
+/// The format of the stencil buffer used for masking
+pub(crate) const STENCIL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Stencil8;
+
+/// A rectangle on the window in physical pixels, with the origin at the top left
+///
+/// Anything drawn outside of this rectangle is discarded
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ClipRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl ClipRect {
+    pub const fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Shrinks this rectangle so that it fits within a surface of the given size
+    pub(crate) fn clamp_to(self, size: PhysicalSize<u32>) -> Self {
+        let x = self.x.min(size.width);
+        let y = self.y.min(size.height);
+        Self {
+            x,
+            y,
+            width: self.width.min(size.width - x),
+            height: self.height.min(size.height - y),
+        }
+    }
+}
+
+/// How a drawable interacts with the stencil buffer
+///
+/// Every frame, all mask writers are drawn first regardless of their z. They
+/// are not visible, but mark the area they cover with their mask id. Drawables
+/// that read a mask are then only visible where that mask was written.
+/// Masks with the same id can be used to build more complex shapes
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Mask {
+    /// Not masked, and does not write a mask
+    #[default]
+    None,
+    /// Writes the given mask id wherever it is drawn, but is not visible
+    Write(u8),
+    /// Only visible where the given mask id was written
+    Read(u8),
+}
+
+/// The stencil texture used for masking, resized alongside the surface
+pub(crate) struct StencilBuffer {
+    view: Option<(TextureView, PhysicalSize<u32>, MemoryAllocation)>,
+}
+
+impl StencilBuffer {
+    pub(crate) fn new() -> Self {
+        Self { view: None }
+    }
+
+    /// Gets a view of the stencil buffer, recreating it if the surface size changed
+    pub(crate) fn view(
+        &mut self,
+        device: &Device,
+        memory: &Arc<GpuMemory>,
+        size: PhysicalSize<u32>,
+    ) -> &TextureView {
+        if self.view.as_ref().map(|(_, x, _)| *x) != Some(size) {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("stencil_buffer"),
+                size: wgpu::Extent3d {
+                    width: size.width.max(1),
+                    height: size.height.max(1),
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: STENCIL_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                view_formats: &[],
+            });
+            self.view = Some((
+                texture.create_view(&wgpu::TextureViewDescriptor::default()),
+                size,
+                GpuMemory::allocate_texture(memory, &texture),
+            ));
+        }
+        &self.view.as_ref().unwrap().0
+    }
+}
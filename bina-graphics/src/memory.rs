@@ -0,0 +1,151 @@
+//! How much GPU memory the textures and buffers created by `Graphics` are using
+//!
+//! Sizes are what was asked of the device, which may pad or compress them, so the totals are an
+//! estimate of how much VRAM is in use. Buffers that only live for a frame, such as gizmos, are not counted
+//!
+//! ```ignore
+//! // Textures loaded with `CacheOption::UncacheAfter` are unloaded early past 512 MB
+//! graphics.set_memory_soft_limit(Some(512 * 1024 * 1024));
+//!
+//! let usage = graphics.memory_usage();
+//! println!("{} MB of textures", usage.bytes(MemoryCategory::Textures) / 1024 / 1024);
+//! ```
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use bina_ecs::triomphe::Arc;
+
+/// What GPU memory is used for
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum MemoryCategory {
+    /// Images that are sampled, including texture arrays and compressed textures
+    Textures,
+    /// Textures that are drawn into, such as the scene, its bloom, stencil buffers, and minimaps
+    RenderTargets,
+    /// The vertices and indices of polygons and 3D meshes
+    Meshes,
+    /// Every other buffer, such as the transform of every polygon
+    Buffers,
+}
+
+impl MemoryCategory {
+    pub const ALL: [Self; 4] = [
+        Self::Textures,
+        Self::RenderTargets,
+        Self::Meshes,
+        Self::Buffers,
+    ];
+
+    /// The name of the counter that this category is reported to `Diagnostics` as, in megabytes
+    pub fn counter_name(self) -> &'static str {
+        match self {
+            Self::Textures => "gpu memory: textures (MB)",
+            Self::RenderTargets => "gpu memory: render targets (MB)",
+            Self::Meshes => "gpu memory: meshes (MB)",
+            Self::Buffers => "gpu memory: buffers (MB)",
+        }
+    }
+}
+
+/// The name of the counter that the total of every category is reported to `Diagnostics` as
+pub const TOTAL_COUNTER: &str = "gpu memory (MB)";
+
+/// How many bytes each category was using when this was taken
+#[derive(Clone, Copy, Default, Debug)]
+pub struct MemoryUsage {
+    bytes: [u64; MemoryCategory::ALL.len()],
+}
+
+impl MemoryUsage {
+    pub fn bytes(&self, category: MemoryCategory) -> u64 {
+        self.bytes[category as usize]
+    }
+
+    pub fn total(&self) -> u64 {
+        self.bytes.iter().sum()
+    }
+}
+
+/// The bytes in use on one device
+#[derive(Default)]
+pub(crate) struct GpuMemory {
+    bytes: [AtomicU64; MemoryCategory::ALL.len()],
+}
+
+impl GpuMemory {
+    /// Counts `bytes` towards `category` until the returned allocation is dropped
+    pub(crate) fn allocate(
+        memory: &Arc<Self>,
+        category: MemoryCategory,
+        bytes: u64,
+    ) -> MemoryAllocation {
+        memory.bytes[category as usize].fetch_add(bytes, Ordering::Relaxed);
+        MemoryAllocation {
+            memory: memory.clone(),
+            category,
+            bytes,
+        }
+    }
+
+    /// Counts a texture towards `RenderTargets` if it can be drawn into, and `Textures` otherwise
+    pub(crate) fn allocate_texture(
+        memory: &Arc<Self>,
+        texture: &wgpu::Texture,
+    ) -> MemoryAllocation {
+        let category = if texture
+            .usage()
+            .contains(wgpu::TextureUsages::RENDER_ATTACHMENT)
+        {
+            MemoryCategory::RenderTargets
+        } else {
+            MemoryCategory::Textures
+        };
+        Self::allocate(memory, category, texture_bytes(texture))
+    }
+
+    pub(crate) fn usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            bytes: std::array::from_fn(|i| self.bytes[i].load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// Bytes counted towards a category of a `GpuMemory`, which are no longer counted once this is dropped
+pub(crate) struct MemoryAllocation {
+    memory: Arc<GpuMemory>,
+    category: MemoryCategory,
+    bytes: u64,
+}
+
+impl MemoryAllocation {
+    /// Changes how many bytes are counted, such as after a buffer grows
+    pub(crate) fn set(&mut self, bytes: u64) {
+        let counter = &self.memory.bytes[self.category as usize];
+        counter.fetch_add(bytes, Ordering::Relaxed);
+        counter.fetch_sub(self.bytes, Ordering::Relaxed);
+        self.bytes = bytes;
+    }
+}
+
+impl Drop for MemoryAllocation {
+    fn drop(&mut self) {
+        self.memory.bytes[self.category as usize].fetch_sub(self.bytes, Ordering::Relaxed);
+    }
+}
+
+/// The size of every mip level and sample of a texture
+fn texture_bytes(texture: &wgpu::Texture) -> u64 {
+    let format = texture.format();
+    let (block_width, block_height) = format.block_dimensions();
+    // Combined depth and stencil formats do not have a size, so they are assumed to be 4 bytes
+    let block_size = format.block_size(None).unwrap_or(4) as u64;
+    let bytes: u64 = (0..texture.mip_level_count())
+        .map(|level| {
+            let size = texture.size().mip_level_size(level, texture.dimension());
+            size.width.div_ceil(block_width) as u64
+                * size.height.div_ceil(block_height) as u64
+                * size.depth_or_array_layers as u64
+                * block_size
+        })
+        .sum();
+    bytes * texture.sample_count() as u64
+}
@@ -0,0 +1,225 @@
+//! The vertices and indices of every polygon, suballocated from one vertex buffer and one index buffer
+//!
+//! Every polygon can then be drawn without rebinding either buffer. Indices are offset by the
+//! position of their vertices when they are written, as WebGL cannot draw with a base vertex
+use std::{mem::size_of, ops::Range};
+
+use bina_ecs::{parking_lot::Mutex, triomphe::Arc};
+use lyon::lyon_tessellation::VertexBuffers;
+use wgpu::{BufferUsages, Device, Queue};
+
+use crate::memory::{GpuMemory, MemoryAllocation, MemoryCategory};
+
+/// How many vertices the vertex buffer starts with
+const INITIAL_VERTICES: u32 = 1 << 14;
+/// How many indices the index buffer starts with
+const INITIAL_INDICES: u32 = 1 << 15;
+
+/// A buffer that hands out ranges of itself, growing whenever it runs out of room
+struct Suballocator {
+    buffer: Arc<wgpu::Buffer>,
+    /// The ranges that are not allocated, sorted and never adjacent to each other
+    free: Vec<Range<u32>>,
+    capacity: u32,
+    label: &'static str,
+    usage: BufferUsages,
+    /// The size of each element in bytes
+    element_size: u64,
+    memory: MemoryAllocation,
+}
+
+impl Suballocator {
+    fn new(
+        device: &Device,
+        memory: &Arc<GpuMemory>,
+        label: &'static str,
+        usage: BufferUsages,
+        element_size: u64,
+        capacity: u32,
+    ) -> Self {
+        let usage = usage | BufferUsages::COPY_DST | BufferUsages::COPY_SRC;
+        Self {
+            buffer: Arc::new(create_buffer(device, label, usage, element_size, capacity)),
+            free: std::iter::once(0..capacity).collect(),
+            capacity,
+            label,
+            usage,
+            element_size,
+            memory: GpuMemory::allocate(memory, MemoryCategory::Meshes, capacity as u64 * element_size),
+        }
+    }
+
+    /// Writes the given elements into a free range of the buffer, returning where they were written
+    fn allocate(&mut self, device: &Device, queue: &Queue, data: &[u8]) -> Range<u32> {
+        let len = (data.len() as u64 / self.element_size) as u32;
+        if len == 0 {
+            return 0..0;
+        }
+        let index = match self.free.iter().position(|x| x.len() as u32 >= len) {
+            Some(index) => index,
+            None => {
+                self.grow(device, queue, len);
+                self.free.len() - 1
+            }
+        };
+        let free = &mut self.free[index];
+        let range = free.start..free.start + len;
+        free.start += len;
+        if free.start == free.end {
+            self.free.remove(index);
+        }
+        queue.write_buffer(&self.buffer, range.start as u64 * self.element_size, data);
+        range
+    }
+
+    fn free(&mut self, range: Range<u32>) {
+        if range.is_empty() {
+            return;
+        }
+        let index = self.free.partition_point(|x| x.start < range.start);
+        self.free.insert(index, range);
+        // Merge with the next range first so that `index` stays valid
+        if index + 1 < self.free.len() && self.free[index].end == self.free[index + 1].start {
+            self.free[index].end = self.free.remove(index + 1).end;
+        }
+        if index > 0 && self.free[index - 1].end == self.free[index].start {
+            self.free[index - 1].end = self.free.remove(index).end;
+        }
+    }
+
+    /// Replaces the buffer with one large enough that the last free range can hold `len` elements
+    ///
+    /// The contents of the old buffer are copied over, so every allocation stays where it is
+    fn grow(&mut self, device: &Device, queue: &Queue, len: u32) {
+        let trailing = match self.free.last() {
+            Some(last) if last.end == self.capacity => last.len() as u32,
+            _ => 0,
+        };
+        let capacity = (self.capacity - trailing + len).next_power_of_two();
+        let buffer = create_buffer(device, self.label, self.usage, self.element_size, capacity);
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("mesh_arena_grow"),
+        });
+        encoder.copy_buffer_to_buffer(
+            &self.buffer,
+            0,
+            &buffer,
+            0,
+            self.capacity as u64 * self.element_size,
+        );
+        // Writes to the old buffer are staged before this submission, so they are copied too
+        queue.submit(std::iter::once(encoder.finish()));
+        self.buffer = Arc::new(buffer);
+        if trailing > 0 {
+            self.free.last_mut().unwrap().end = capacity;
+        } else {
+            self.free.push(self.capacity..capacity);
+        }
+        self.capacity = capacity;
+        self.memory.set(capacity as u64 * self.element_size);
+    }
+}
+
+fn create_buffer(
+    device: &Device,
+    label: &'static str,
+    usage: BufferUsages,
+    element_size: u64,
+    capacity: u32,
+) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        size: capacity as u64 * element_size,
+        usage,
+        mapped_at_creation: false,
+    })
+}
+
+/// The vertex and index buffers of the arena, as they were when this was taken
+pub(crate) struct MeshBuffers {
+    pub(crate) vertices: Arc<wgpu::Buffer>,
+    pub(crate) indices: Arc<wgpu::Buffer>,
+}
+
+pub(crate) struct MeshArena {
+    vertices: Mutex<Suballocator>,
+    indices: Mutex<Suballocator>,
+    /// The generation of the device the buffers were created on
+    generation: u64,
+}
+
+impl MeshArena {
+    pub(crate) fn new(device: &Device, memory: &Arc<GpuMemory>, generation: u64) -> Self {
+        Self {
+            generation,
+            vertices: Mutex::new(Suballocator::new(
+                device,
+                memory,
+                "mesh_vertex_buffer",
+                BufferUsages::VERTEX,
+                size_of::<[f32; 4]>() as u64,
+                INITIAL_VERTICES,
+            )),
+            indices: Mutex::new(Suballocator::new(
+                device,
+                memory,
+                "mesh_index_buffer",
+                BufferUsages::INDEX,
+                size_of::<u32>() as u64,
+                INITIAL_INDICES,
+            )),
+        }
+    }
+
+    /// Writes the given geometry into the arena, where it stays until the returned mesh is dropped
+    pub(crate) fn allocate(
+        arena: &Arc<Self>,
+        device: &Device,
+        queue: &Queue,
+        geometry: &VertexBuffers<[f32; 4], u32>,
+    ) -> Mesh {
+        let vertices =
+            arena
+                .vertices
+                .lock()
+                .allocate(device, queue, bytemuck::cast_slice(&geometry.vertices));
+        let indices: Vec<u32> = geometry
+            .indices
+            .iter()
+            .map(|i| i + vertices.start)
+            .collect();
+        let indices = arena
+            .indices
+            .lock()
+            .allocate(device, queue, bytemuck::cast_slice(&indices));
+        Mesh {
+            vertices,
+            indices,
+            generation: arena.generation,
+            arena: arena.clone(),
+        }
+    }
+
+    pub(crate) fn buffers(&self) -> MeshBuffers {
+        MeshBuffers {
+            vertices: self.vertices.lock().buffer.clone(),
+            indices: self.indices.lock().buffer.clone(),
+        }
+    }
+}
+
+/// The ranges of the arena that a polygon's geometry was written to, which are freed when this is dropped
+pub(crate) struct Mesh {
+    vertices: Range<u32>,
+    /// Which indices in the index buffer to draw
+    pub(crate) indices: Range<u32>,
+    pub(crate) generation: u64,
+    arena: Arc<MeshArena>,
+}
+
+impl Drop for Mesh {
+    fn drop(&mut self) {
+        self.arena.vertices.lock().free(self.vertices.clone());
+        self.arena.indices.lock().free(self.indices.clone());
+    }
+}
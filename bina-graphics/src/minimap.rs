@@ -0,0 +1,335 @@
+//! A second view of the world drawn into a texture, shown in a corner of the window
+//!
+//! The minimap sees every polygon on its layers through its own camera, ignoring the layers
+//! of the active camera. Markers are drawn on top like gizmos, in world coordinates, and must be
+//! added every frame through `Graphics::minimap_markers`. To draw something only on the minimap,
+//! put it on a layer that the minimap sees but the active camera does not
+//!
+//! ```ignore
+//! graphics.set_minimap(Some(
+//!     Minimap::new(player_transform.handle(), Vector::new(40.0, 40.0), PhysicalSize::new(256, 256))
+//!         .with_placement(Anchor::TopRight, Vector::new(-16.0, 16.0), Vector::new(160.0, 160.0))
+//!         .with_border(2.0, Rgba([255, 255, 255, 255])),
+//! ));
+//! // Every frame
+//! graphics.minimap_markers().circle(enemy_position, 1.0, Rgba([255, 0, 0, 255]));
+//! ```
+use std::mem::size_of;
+
+use bina_ecs::triomphe::Arc;
+use image::Rgba;
+use wgpu::BufferUsages;
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    camera::{ViewTransform, CAMERA_FLOATS},
+    gizmos::GizmoBatch,
+    hdr::HDR_FORMAT,
+    layers::RenderLayers,
+    mask::StencilBuffer,
+    meshes::MeshBuffers,
+    polygon::{Anchor, Material, Polygon, Vector},
+    renderers::{DrawPolygon, DrawResources, PolygonRenderer},
+    texture::{bind_texture, load_img, SamplerOptions, Texture, TextureInner, TextureRef},
+    texture_array::SpriteBatch,
+    transform::TransformHandle,
+    transforms::TransformBuffer,
+    Graphics, GraphicsInner,
+};
+
+/// A view of the world from above, drawn on top of the UI
+pub struct Minimap {
+    center: TransformHandle,
+    half_extents: Vector,
+    layers: RenderLayers,
+    resolution: PhysicalSize<u32>,
+    anchor: Anchor,
+    /// From the anchor to the corner of the minimap at the anchor, in UI units
+    offset: Vector,
+    /// In UI units
+    size: Vector,
+    border: Option<(f32, Rgba<u8>)>,
+    /// Created during the first flush, and again whenever the device changes
+    gpu: Option<MinimapGpu>,
+}
+
+struct MinimapGpu {
+    target: Arc<OffscreenTarget>,
+    display: Polygon,
+    border: Option<Polygon>,
+}
+
+impl Minimap {
+    /// Creates a minimap centered on the position of a `Transform`, that shows everything within
+    /// `half_extents` of it, drawn into a texture that is `resolution` pixels large
+    ///
+    /// By default, the minimap sees `RenderLayers::WORLD` and is drawn in the top right
+    /// corner of the window, one UI unit for each pixel of the texture
+    pub fn new(center: TransformHandle, half_extents: Vector, resolution: PhysicalSize<u32>) -> Self {
+        Self {
+            center,
+            half_extents,
+            layers: RenderLayers::WORLD,
+            resolution,
+            anchor: Anchor::TopRight,
+            offset: Vector::new(-16.0, 16.0),
+            size: Vector::new(resolution.width as f32, resolution.height as f32),
+            border: None,
+            gpu: None,
+        }
+    }
+
+    /// Sets the layers the minimap sees. Polygons on the UI layer are never drawn on the minimap
+    pub fn with_layers(mut self, layers: RenderLayers) -> Self {
+        self.layers = layers;
+        self
+    }
+
+    /// Places the corner or edge of the minimap that matches `anchor` at `offset` UI units from
+    /// that point of the window, where y points down, and makes it `size` UI units large
+    pub fn with_placement(mut self, anchor: Anchor, offset: Vector, size: Vector) -> Self {
+        self.anchor = anchor;
+        self.offset = offset;
+        self.size = size;
+        self.gpu = None;
+        self
+    }
+
+    /// Surrounds the minimap with a border that is `width` UI units thick
+    pub fn with_border(mut self, width: f32, color: Rgba<u8>) -> Self {
+        self.border = Some((width, color));
+        self.gpu = None;
+        self
+    }
+
+    /// Gets the position the minimap is centered on, which follows its `Transform`
+    pub fn center(&self) -> Vector {
+        self.center.position()
+    }
+
+    /// Creates the GPU side of the minimap on the current device
+    fn create_gpu(&self, graphics: &Graphics) -> MinimapGpu {
+        let target = Arc::new(OffscreenTarget::new(graphics, self.resolution, "minimap"));
+        // The anchor of the minimap is at its origin, so that resizing the window moves it along with the anchor
+        let pivot = self.anchor.position(self.size);
+        let quad = |grow: f32, material| {
+            let (left, top) = (-pivot.x - grow, -pivot.y - grow);
+            let (right, bottom) = (self.size.x - pivot.x + grow, self.size.y - pivot.y + grow);
+            Polygon::new(
+                graphics,
+                &[
+                    (Vector::new(left, top), Vector::new(0.0, 0.0)),
+                    (Vector::new(right, top), Vector::new(1.0, 0.0)),
+                    (Vector::new(right, bottom), Vector::new(1.0, 1.0)),
+                    (Vector::new(left, bottom), Vector::new(0.0, 1.0)),
+                ],
+                material,
+            )
+        };
+        let display = quad(
+            0.0,
+            Material::Texture(Texture {
+                texture: TextureRef::Shared(target.texture.clone()),
+            }),
+        )
+        .with_name("minimap");
+        let border = self.border.map(|(width, color)| {
+            let texture = load_img(
+                graphics,
+                &color.0,
+                1,
+                1,
+                SamplerOptions::PIXEL_ART,
+                Some("minimap border"),
+            );
+            quad(
+                width,
+                Material::Texture(Texture {
+                    texture: TextureRef::Shared(Arc::new(texture)),
+                }),
+            )
+            .with_name("minimap border")
+        });
+        MinimapGpu {
+            target,
+            display,
+            border,
+        }
+    }
+
+    /// Draws the minimap onto the UI, and returns the pass that draws the world into it
+    /// under the given ambient light
+    ///
+    /// The polygons of the pass are added while the instructions of the frame are sent
+    pub(crate) fn prepare(&mut self, graphics: &Graphics, light: [f32; 3]) -> OffscreenPass {
+        if !self
+            .gpu
+            .as_ref()
+            .is_some_and(|x| x.target.texture.generation == graphics.inner.generation)
+        {
+            self.gpu = Some(self.create_gpu(graphics));
+        }
+        let gpu = self.gpu.as_mut().unwrap();
+
+        let origin = self.anchor.position(graphics.ui_size()) + self.offset;
+        if let Some(border) = &mut gpu.border {
+            border.draw_overlay(graphics, origin, u32::MAX - 2);
+        }
+        gpu.display.draw_overlay(graphics, origin, u32::MAX - 1);
+
+        let view = ViewTransform::new(
+            self.center.position(),
+            self.half_extents,
+            0.0,
+            self.resolution,
+            graphics.scaling_mode,
+        );
+        graphics.inner.queue.write_buffer(
+            &gpu.target.camera_buffer,
+            0,
+            bytemuck::cast_slice(&view.to_uniform(light)),
+        );
+        OffscreenPass {
+            target: gpu.target.clone(),
+            layers: self.layers,
+            polygons: Vec::new(),
+            sprite_batches: Vec::new(),
+            markers: graphics
+                .minimap_markers
+                .take_frame(&graphics.inner, &view)
+                .batch,
+        }
+    }
+}
+
+/// A texture that polygons are drawn into through its own camera
+pub(crate) struct OffscreenTarget {
+    /// Sampled by the polygons that show this target
+    texture: Arc<TextureInner>,
+    view: wgpu::TextureView,
+    size: PhysicalSize<u32>,
+    camera_buffer: wgpu::Buffer,
+    camera_bind_group: wgpu::BindGroup,
+}
+
+impl OffscreenTarget {
+    fn new(graphics: &Graphics, size: PhysicalSize<u32>, name: &str) -> Self {
+        let device = &graphics.inner.device;
+        let size = PhysicalSize::new(size.width.max(1), size.height.max(1));
+        // Drawn in the same format as the scene, so it is tonemapped along with everything else
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(name),
+            size: wgpu::Extent3d {
+                width: size.width,
+                height: size.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: HDR_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let camera_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(name),
+            size: size_of::<[f32; CAMERA_FLOATS]>() as u64,
+            usage: BufferUsages::UNIFORM.union(BufferUsages::COPY_DST),
+            mapped_at_creation: false,
+        });
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &graphics.inner.layouts.camera,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(camera_buffer.as_entire_buffer_binding()),
+            }],
+            label: Some(name),
+        });
+        Self {
+            texture: Arc::new(bind_texture(
+                graphics,
+                texture,
+                SamplerOptions::LINEAR,
+                Some(name),
+            )),
+            view,
+            size,
+            camera_buffer,
+            camera_bind_group,
+        }
+    }
+}
+
+/// Everything drawn into an `OffscreenTarget` during a frame
+pub(crate) struct OffscreenPass {
+    target: Arc<OffscreenTarget>,
+    /// The layers that polygons must be on to be drawn in this pass
+    pub(crate) layers: RenderLayers,
+    pub(crate) polygons: Vec<DrawPolygon>,
+    pub(crate) sprite_batches: Vec<SpriteBatch>,
+    markers: Option<GizmoBatch>,
+}
+
+impl OffscreenPass {
+    /// Encodes this pass, returning the number of draw calls made
+    ///
+    /// Must be encoded before anything that samples the target
+    pub(crate) fn draw(
+        self,
+        graphics: &GraphicsInner,
+        encoder: &mut wgpu::CommandEncoder,
+        renderer: &mut PolygonRenderer,
+        stencil: &mut StencilBuffer,
+        meshes: &MeshBuffers,
+        transforms: &TransformBuffer,
+    ) -> usize {
+        // A target from an old device cannot be drawn into
+        if self.target.texture.generation != graphics.generation {
+            return 0;
+        }
+        for polygon in self.polygons {
+            renderer.push(polygon);
+        }
+        for batch in self.sprite_batches {
+            renderer.push_sprite_batch(batch);
+        }
+        if let Some(markers) = self.markers {
+            renderer.push_gizmos(markers);
+        }
+        let draw_calls = {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Offscreen Render Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &self.target.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: stencil.view(&graphics.device, &graphics.memory, self.target.size),
+                    depth_ops: None,
+                    stencil_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(0),
+                        store: false,
+                    }),
+                }),
+            });
+            renderer.draw_all(
+                &graphics.device,
+                &mut render_pass,
+                DrawResources {
+                    meshes,
+                    transforms,
+                    camera_bind_group: &self.target.camera_bind_group,
+                    ui_camera_bind_group: &self.target.camera_bind_group,
+                },
+                self.target.size,
+            )
+        };
+        renderer.clear();
+        draw_calls
+    }
+}
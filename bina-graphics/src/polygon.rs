@@ -1,24 +1,38 @@
 use std::{
-    mem::size_of,
-    ops::{Add, AddAssign, Deref, DerefMut, Sub, SubAssign},
-    sync::atomic::Ordering,
+    ops::{Add, AddAssign, Deref, DerefMut, Mul, Sub, SubAssign},
+    sync::atomic::{AtomicU64, Ordering},
 };
 
 use atomic_float::AtomicF32;
 use bina_ecs::{
-    component::{AtomicNumber, Component, NumberField, NumberFieldRef, Processable, ComponentField},
+    components::WatchedFuture,
+    entity::ErasedEntityReference,
+    universe::Universe,
+    component::{AtomicNumber, Component, NumberField, NumberFieldRef, Processable, ComponentField, StagedMutField, StagedMutFieldRef},
+    parking_lot::Mutex,
+    reflect::{Field, Reflect, ReflectNumber},
     triomphe::Arc,
 };
 use image::Rgba;
+pub use lyon::lyon_tessellation::{FillOptions, FillRule};
 use lyon::{
-    lyon_tessellation::{BuffersBuilder, FillOptions, FillTessellator, FillVertex, VertexBuffers},
+    lyon_tessellation::{BuffersBuilder, FillTessellator, FillVertex, VertexBuffers},
     math::point,
     path::traits::PathBuilder,
 };
 use nalgebra::Matrix2;
-use wgpu::{util::DeviceExt, BufferUsages};
 
-use crate::{drawing::DrawInstruction, renderers::DrawPolygon, texture::Texture, Graphics};
+use crate::{
+    drawing::DrawInstruction,
+    layers::{RenderLayers, Visible},
+    mask::{ClipRect, Mask},
+    meshes::{Mesh, MeshArena},
+    renderers::DrawPolygon,
+    texture::Texture,
+    transform::Transform,
+    transforms::{TransformSlot, TransformSlots},
+    Graphics, GraphicsInner,
+};
 
 // #[derive(Pod, Clone, Copy, Zeroable)]
 // #[repr(C)]
@@ -67,154 +81,601 @@ pub(crate) const TEXTURE_VERTEX_BUFFER_DESCRIPTOR: wgpu::VertexBufferLayout<'sta
 //         }],
 //     };
 
-const TRANSFORM_BUFFER_DESCRIPTOR: wgpu::BufferDescriptor<'static> = wgpu::BufferDescriptor {
-    label: Some("transform_buffer_descriptor"),
-    size: size_of::<f32>() as u64 * 6,
-    usage: BufferUsages::UNIFORM.union(BufferUsages::COPY_DST),
-    mapped_at_creation: false,
-};
-
 pub enum Material {
     FlatColor(Rgba<u8>),
     Texture(Texture),
 }
 
+/// An axis-aligned bounding box
+#[derive(Clone, Copy)]
+pub struct Aabb {
+    pub min: Vector,
+    pub max: Vector,
+}
+
+impl Aabb {
+    pub const fn new(min: Vector, max: Vector) -> Self {
+        Self { min, max }
+    }
+
+    /// Gets the smallest box containing every point, or `None` if there are no points
+    pub fn from_points(points: impl IntoIterator<Item = Vector>) -> Option<Self> {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+        Some(points.fold(Self::new(first, first), |aabb, point| Self {
+            min: Vector::new(aabb.min.x.min(point.x), aabb.min.y.min(point.y)),
+            max: Vector::new(aabb.max.x.max(point.x), aabb.max.y.max(point.y)),
+        }))
+    }
+
+    pub fn size(&self) -> Vector {
+        self.max - self.min
+    }
+
+    pub fn center(&self) -> Vector {
+        Vector::new(
+            (self.min.x + self.max.x) * 0.5,
+            (self.min.y + self.max.y) * 0.5,
+        )
+    }
+
+    pub fn contains(&self, point: Vector) -> bool {
+        point.x >= self.min.x
+            && point.y >= self.min.y
+            && point.x <= self.max.x
+            && point.y <= self.max.y
+    }
+
+    pub fn intersects(&self, other: &Self) -> bool {
+        self.min.x <= other.max.x
+            && self.max.x >= other.min.x
+            && self.min.y <= other.max.y
+            && self.max.y >= other.min.y
+    }
+
+    /// Gets the box containing this one after it has been transformed the same way a polygon is
+    fn transformed(&self, basis: &Matrix2<f32>, origin: Vector) -> Self {
+        let corners = [
+            self.min,
+            Vector::new(self.max.x, self.min.y),
+            self.max,
+            Vector::new(self.min.x, self.max.y),
+        ];
+        // The columns of the basis are the transformed axes, matching the shader
+        let transform = |v: Vector| {
+            Vector::new(
+                basis.m11 * v.x + basis.m21 * v.y + origin.x,
+                basis.m12 * v.x + basis.m22 * v.y + origin.y,
+            )
+        };
+        Self::from_points(corners.map(transform)).expect("There are always 4 corners")
+    }
+}
+
+/// A point on the window that a polygon on the UI layer can be placed relative to
+///
+/// Anchored polygons keep the same distance from their anchor when the window is resized,
+/// so HUD elements can be kept in a corner or along an edge at any resolution
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Anchor {
+    /// Gets the position of this anchor in UI units, given the size of the UI from `Graphics::ui_size`
+    pub fn position(self, ui_size: Vector) -> Vector {
+        let (x, y) = match self {
+            Self::TopLeft => (0.0, 0.0),
+            Self::Top => (0.5, 0.0),
+            Self::TopRight => (1.0, 0.0),
+            Self::Left => (0.0, 0.5),
+            Self::Center => (0.5, 0.5),
+            Self::Right => (1.0, 0.5),
+            Self::BottomLeft => (0.0, 1.0),
+            Self::Bottom => (0.5, 1.0),
+            Self::BottomRight => (1.0, 1.0),
+        };
+        Vector::new(ui_size.x * x, ui_size.y * y)
+    }
+}
+
 pub struct Polygon {
     pub(crate) inner: Arc<PolygonInner>,
+    /// Replaced whenever the geometry changes or the device is recreated
+    mesh: Arc<Mesh>,
+    /// Kept so that the mesh can be recreated if the device changes
+    geometry: Geometry,
+    /// The bounds of the vertices before they are transformed
+    local_bounds: Aabb,
+    pending_geometry: Arc<PendingGeometry>,
     origin: NumberField<Vector>,
     basis: Matrix2<f32>,
+    /// The bounds of the vertices after the transform from the last flush
+    world_bounds: Aabb,
+    /// Whether the transform or emission changed in the last flush, and so must be written during the next process
+    transform_dirty: bool,
     scale: NumberField<Vector>,
     rotation: NumberField<f32>,
+    emission: NumberField<f32>,
     z: NumberField<u32>,
+    layers: NumberField<RenderLayers>,
+    visible: NumberField<Visible>,
+    clip: StagedMutField<Option<ClipRect>>,
+    mask: StagedMutField<Mask>,
+    anchor: StagedMutField<Option<Anchor>>,
+    /// Where the anchor was on the UI during the last flush, which is added to the origin
+    anchor_position: Vector,
 }
 
 pub(crate) struct PolygonInner {
-    pub(crate) indices_count: u32,
-    pub(crate) vertices: wgpu::Buffer,
-    pub(crate) indices: wgpu::Buffer,
     pub(crate) material: Material,
-    pub(crate) transform_buffer: wgpu::Buffer,
-    pub(crate) transform_bind_group: wgpu::BindGroup
+    pub(crate) transform_slot: TransformSlot,
+    /// Marks the draws of the polygon in graphics debuggers
+    pub(crate) name: Option<String>,
 }
 
-impl Polygon {
-    pub fn new(graphics: &Graphics, vertices: &[(Vector, Vector)], material: Material) -> Self {
-        let mut builder = lyon::path::Path::builder_with_attributes(2);
-        let mut first = true;
-        for (v, tex_v) in vertices {
-            if first {
-                builder.begin(point(v.x, v.y), &[tex_v.x, tex_v.y]);
-                first = false;
-            } else {
-                builder.line_to(point(v.x, v.y), &[tex_v.x, tex_v.y]);
-            }
+/// Triangles where each vertex is its position followed by its texture coordinates
+type Geometry = VertexBuffers<[f32; 4], u32>;
+
+/// Geometry waiting to replace the geometry of a polygon during the next flush
+#[derive(Default)]
+struct PendingGeometry {
+    /// Incremented for every change that is queued, so that a tessellation
+    /// that finishes late cannot replace a change queued after it
+    requested: AtomicU64,
+    ready: Mutex<(u64, Option<Geometry>)>,
+}
+
+impl PendingGeometry {
+    fn request(&self) -> u64 {
+        self.requested.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    fn fulfill(&self, request: u64, geometry: Geometry) {
+        let mut ready = self.ready.lock();
+        if request > ready.0 {
+            *ready = (request, Some(geometry));
         }
-        builder.close();
-        let path = builder.build();
-
-        let mut tessellator = FillTessellator::new();
-        let mut geometry: VertexBuffers<[f32; 4], u32> = VertexBuffers::new();
-
-        {
-            // Compute the tessellation.
-            tessellator
-                .tessellate_path(
-                    &path,
-                    &FillOptions::default(),
-                    &mut BuffersBuilder::new(&mut geometry, |mut vertex: FillVertex| {
-                        let attrs = vertex.interpolated_attributes();
-                        let tx = attrs[0];
-                        let ty = attrs[1];
-
-                        [
-                            vertex.position().x,
-                            vertex.position().y,
-                            tx,
-                            ty
-                        ]
-                    }),
-                )
-                .unwrap();
+    }
+
+    fn take(&self) -> Option<Geometry> {
+        self.ready.lock().1.take()
+    }
+}
+
+fn create_mesh(graphics: &GraphicsInner, geometry: &Geometry) -> Arc<Mesh> {
+    Arc::new(MeshArena::allocate(
+        &graphics.meshes,
+        &graphics.device,
+        &graphics.queue,
+        geometry,
+    ))
+}
+
+fn bounds_of(geometry: &Geometry) -> Aabb {
+    Aabb::from_points(geometry.vertices.iter().map(|v| Vector::new(v[0], v[1])))
+        .unwrap_or(Aabb::new(Vector::new(0.0, 0.0), Vector::new(0.0, 0.0)))
+}
+
+/// Tessellates the area enclosed by the given contours, where each contour is a closed loop of
+/// vertices paired with their texture coordinates
+///
+/// Which areas count as enclosed, such as the holes inside other contours, depends on the fill rule
+fn tessellate<C: AsRef<[(Vector, Vector)]>>(contours: &[C], options: &FillOptions) -> Geometry {
+    let mut builder = lyon::path::Path::builder_with_attributes(2);
+    for contour in contours {
+        let Some(((v, tex_v), rest)) = contour.as_ref().split_first() else {
+            continue;
+        };
+        builder.begin(point(v.x, v.y), &[tex_v.x, tex_v.y]);
+        for (v, tex_v) in rest {
+            builder.line_to(point(v.x, v.y), &[tex_v.x, tex_v.y]);
         }
+        builder.close();
+    }
+    let path = builder.build();
+
+    let mut tessellator = FillTessellator::new();
+    let mut geometry: Geometry = VertexBuffers::new();
+
+    {
+        // Compute the tessellation.
+        tessellator
+            .tessellate_path(
+                &path,
+                options,
+                &mut BuffersBuilder::new(&mut geometry, |mut vertex: FillVertex| {
+                    let attrs = vertex.interpolated_attributes();
+                    let tx = attrs[0];
+                    let ty = attrs[1];
+
+                    [
+                        vertex.position().x,
+                        vertex.position().y,
+                        tx,
+                        ty
+                    ]
+                }),
+            )
+            .unwrap();
+    }
+
+    geometry
+}
+
+impl Polygon {
+    pub fn new(graphics: &Graphics, vertices: &[(Vector, Vector)], material: Material) -> Self {
+        Self::from_contours(graphics, &[vertices], &FillOptions::default(), material)
+    }
+
+    /// Creates a polygon from several closed loops of vertices, such as an outline and the holes inside it
+    ///
+    /// With the default non-zero fill rule, holes must wind in the opposite direction to the outline.
+    /// With the even-odd fill rule, any area enclosed by an even number of contours is a hole
+    pub fn from_contours<C: AsRef<[(Vector, Vector)]>>(
+        graphics: &Graphics,
+        contours: &[C],
+        options: &FillOptions,
+        material: Material,
+    ) -> Self {
+        Self::from_geometry(graphics, tessellate(contours, options), material)
+    }
 
-        let transform_buffer = graphics
-            .inner
-            .device
-            .create_buffer(&TRANSFORM_BUFFER_DESCRIPTOR);
+    /// Creates a polygon from vertices that have already been tessellated into triangles,
+    /// where each vertex is its position followed by its texture coordinates
+    pub fn from_geometry(
+        graphics: &Graphics,
+        geometry: VertexBuffers<[f32; 4], u32>,
+        material: Material,
+    ) -> Self {
+        Self::build(&graphics.inner, &graphics.transform_slots, geometry, material)
+    }
+
+    /// Creates a polygon on a background thread, so that tessellating
+    /// complex shapes does not slow down the current frame
+    ///
+    /// ```ignore
+    /// universe.queue_add_entity((Polygon::new_async(graphics, outline, material, universe),));
+    /// // Later, wherever the WatchedFuture is checked
+    /// if let Ok(polygon) = future.try_get() {
+    ///     universe.queue_add_entity((polygon,));
+    /// }
+    /// ```
+    pub fn new_async(
+        graphics: &Graphics,
+        vertices: Vec<(Vector, Vector)>,
+        material: Material,
+        universe: &Universe,
+    ) -> WatchedFuture<Self> {
+        Self::from_contours_async(graphics, vec![vertices], FillOptions::default(), material, universe)
+    }
 
+    /// The same as `from_contours`, except that the polygon is created on a background thread
+    pub fn from_contours_async(
+        graphics: &Graphics,
+        contours: Vec<Vec<(Vector, Vector)>>,
+        options: FillOptions,
+        material: Material,
+        universe: &Universe,
+    ) -> WatchedFuture<Self> {
+        let inner = graphics.inner.clone();
+        let transform_slots = graphics.transform_slots.clone();
+        let build = move || {
+            let geometry = tessellate(&contours, &options);
+            Self::build(&inner, &transform_slots, geometry, material)
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        let fut = async {
+            match bina_ecs::tokio::task::spawn_blocking(build).await {
+                Ok(polygon) => polygon,
+                // Fails the WatchedFuture
+                Err(e) => std::panic::resume_unwind(e.into_panic()),
+            }
+        };
+        // Browsers only have one thread, so the polygon is created between frames instead
+        #[cfg(target_arch = "wasm32")]
+        let fut = async { build() };
+        WatchedFuture::new(fut, universe)
+    }
+
+    fn build(
+        graphics: &GraphicsInner,
+        transform_slots: &Arc<TransformSlots>,
+        geometry: Geometry,
+        material: Material,
+    ) -> Self {
+        let local_bounds = bounds_of(&geometry);
         Self {
             inner: Arc::new(PolygonInner {
-                vertices: graphics.inner.device.create_buffer_init(
-                    &wgpu::util::BufferInitDescriptor {
-                        label: Some("Vertex Buffer"),
-                        contents: bytemuck::cast_slice(&geometry.vertices),
-                        usage: wgpu::BufferUsages::VERTEX,
-                    },
-                ),
-                indices: graphics.inner.device.create_buffer_init(
-                    &wgpu::util::BufferInitDescriptor {
-                        label: Some("Index Buffer"),
-                        contents: bytemuck::cast_slice(&geometry.indices),
-                        usage: wgpu::BufferUsages::INDEX,
-                    },
-                ),
                 material,
-                indices_count: geometry.indices.len() as u32,
-                transform_bind_group: graphics
-                    .inner
-                    .device
-                    .create_bind_group(&wgpu::BindGroupDescriptor {
-                        layout: &graphics.inner.transform_bind_group_layout,
-                        entries: &[wgpu::BindGroupEntry {
-                            binding: 0,
-                            resource: wgpu::BindingResource::Buffer(transform_buffer.as_entire_buffer_binding()),
-                        }],
-                        label: Some("transform_bind_group"),
-                    }),
-                transform_buffer,
+                transform_slot: TransformSlots::allocate(transform_slots),
+                name: None,
             }),
+            mesh: create_mesh(graphics, &geometry),
+            geometry,
+            local_bounds,
+            pending_geometry: Default::default(),
+            world_bounds: local_bounds,
+            transform_dirty: true,
             origin: NumberField::new(Vector::new(0.0, 0.0)),
             z: NumberField::new(0),
             basis: Matrix2::identity(),
             scale: NumberField::new(Vector::new(1.0, 1.0)),
             rotation: NumberField::new(1.0),
+            emission: NumberField::new(0.0),
+            layers: NumberField::new(RenderLayers::default()),
+            visible: NumberField::new(Visible::default()),
+            clip: StagedMutField::new(None),
+            mask: StagedMutField::new(Mask::None),
+            anchor: StagedMutField::new(None),
+            anchor_position: Vector::new(0.0, 0.0),
+        }
+    }
+
+    /// Names the draws of this polygon in graphics debuggers, when the `debug_labels` feature is enabled
+    ///
+    /// Polygons without a name are named after their texture
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        Arc::get_mut(&mut self.inner)
+            .expect("Polygons are not shared until they are processed")
+            .name = Some(name.into());
+        self
+    }
+
+    /// Places this polygon relative to a point on the window instead of the camera,
+    /// moving it to the UI layer
+    ///
+    /// The origin of the polygon becomes an offset from the anchor in UI units, where y points down.
+    /// For example, an origin of `(16, -16)` with `Anchor::BottomLeft` keeps a polygon 16 units
+    /// away from the bottom left corner
+    pub fn with_anchor(mut self, anchor: Anchor) -> Self {
+        *self.anchor.get_inner_mut() = Some(anchor);
+        self.layers.set_inner(RenderLayers::UI);
+        self
+    }
+
+    /// Moves this polygon immediately, discarding any changes to its transform that have not been flushed
+    ///
+    /// Meant for polygons that are not in the Universe, such as those being
+    /// reused by `Universe::spawn_from_pool`, so that their first frame is drawn in the right place
+    pub fn set_transform(&mut self, origin: Vector, rotation: f32, scale: Vector) {
+        self.origin.set_inner(origin);
+        self.rotation.set_inner(rotation);
+        self.scale.set_inner(scale);
+        self.basis = basis_of(rotation, scale);
+        self.world_bounds = self
+            .local_bounds
+            .transformed(&self.basis, origin + self.anchor_position);
+        self.transform_dirty = true;
+    }
+
+    /// Draws this polygon at the given UI position and z on the UI layer,
+    /// for polygons that are owned by `Graphics` instead of an entity
+    pub(crate) fn draw_overlay(&mut self, graphics: &Graphics, origin: Vector, z: u32) {
+        if graphics.inner.generation != self.mesh.generation {
+            self.mesh = create_mesh(&graphics.inner, &self.geometry);
+        }
+        graphics.transform_slots.set(
+            self.inner.transform_slot.index,
+            [1.0, 0.0, 0.0, 1.0, origin.x, origin.y, 0.0, 0.0],
+        );
+        graphics.queue_draw_instruction(DrawInstruction::DrawPolygon(DrawPolygon {
+            polygon: self.inner.clone(),
+            mesh: self.mesh.clone(),
+            z,
+            layers: RenderLayers::UI,
+            clip: None,
+            mask: Mask::None,
+        }));
+    }
+
+    /// Stages a new transform for a polygon that is owned by another component,
+    /// which must flush it afterwards for the transform to be applied
+    pub(crate) fn stage_transform(&mut self, origin: Vector, rotation: f32, scale: Vector) {
+        self.origin.get_ref().set(origin);
+        self.rotation.get_ref().set(rotation);
+        self.scale.get_ref().set(scale);
+    }
+
+    pub(crate) fn set_z(&mut self, z: u32) {
+        self.z.set_inner(z);
+    }
+
+    pub(crate) fn set_visible(&mut self, visible: Visible) {
+        self.visible.set_inner(visible);
+    }
+}
+
+/// One shape that was tessellated at compile time by `load_polygon!`
+pub struct StaticShape {
+    /// Each vertex is its position followed by its texture coordinates
+    pub vertices: &'static [[f32; 4]],
+    pub indices: &'static [u32],
+    /// The color of the fill or stroke in the SVG. Shapes made from a list of points are white
+    pub color: Rgba<u8>,
+}
+
+impl StaticShape {
+    /// Copies the triangles into the form that `Polygon::from_geometry` takes
+    pub fn geometry(&self) -> VertexBuffers<[f32; 4], u32> {
+        VertexBuffers {
+            vertices: self.vertices.to_vec(),
+            indices: self.indices.to_vec(),
         }
     }
 }
 
+/// An SVG image or a list of points that was tessellated at compile time by `load_polygon!`,
+/// so creating polygons from it only needs the triangles to be uploaded
+pub struct PolygonResource {
+    name: &'static str,
+    shapes: &'static [StaticShape],
+    size: (f32, f32),
+}
+
+impl PolygonResource {
+    pub const fn new(name: &'static str, shapes: &'static [StaticShape], size: (f32, f32)) -> Self {
+        Self { name, shapes, size }
+    }
+
+    /// The path of the SVG, or the name of the static for a list of points
+    pub const fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Gets every shape, in the order they should be drawn
+    pub const fn shapes(&self) -> &'static [StaticShape] {
+        self.shapes
+    }
+
+    /// Gets the width and height, from the `viewBox` of an SVG or the bounds of a list of points
+    pub const fn size(&self) -> (f32, f32) {
+        self.size
+    }
+
+    /// Creates one polygon out of every shape, drawn with the given material
+    pub fn polygon(&self, graphics: &Graphics, material: Material) -> Polygon {
+        let mut geometry: Geometry = VertexBuffers::new();
+        for shape in self.shapes {
+            let offset = geometry.vertices.len() as u32;
+            geometry.vertices.extend_from_slice(shape.vertices);
+            geometry
+                .indices
+                .extend(shape.indices.iter().map(|i| i + offset));
+        }
+        Polygon::from_geometry(graphics, geometry, material).with_name(self.name)
+    }
+
+    /// Creates a polygon for every shape in its own color, in the order they should be drawn
+    ///
+    /// Later polygons should be given a greater `z` so that they are drawn on top
+    pub fn polygons(&self, graphics: &Graphics) -> Vec<Polygon> {
+        self.shapes
+            .iter()
+            .map(|shape| {
+                Polygon::from_geometry(graphics, shape.geometry(), Material::FlatColor(shape.color))
+                    .with_name(self.name)
+            })
+            .collect()
+    }
+}
+
 impl Component for Polygon {
     type Reference<'a> = PolygonRef<'a>;
 
     fn get_ref<'a>(&'a self) -> Self::Reference<'a> {
         PolygonRef {
             inner: &self.inner,
+            mesh: &self.mesh,
+            local_bounds: &self.local_bounds,
+            pending_geometry: &self.pending_geometry,
             origin: self.origin.get_ref(),
             z: self.z.get_ref(),
             basis: &self.basis,
+            world_bounds: &self.world_bounds,
+            transform_dirty: self.transform_dirty,
             rotation: self.rotation.get_ref(),
+            emission: self.emission.get_ref(),
             scale: self.scale.get_ref(),
+            layers: self.layers.get_ref(),
+            visible: self.visible.get_ref(),
+            clip: self.clip.get_ref(),
+            mask: self.mask.get_ref(),
+            anchor: self.anchor.get_ref(),
+            anchor_position: &self.anchor_position,
         }
     }
 
     fn flush<E: bina_ecs::entity::Entity>(
             &mut self,
             _my_entity: bina_ecs::entity::EntityReference<bina_ecs::entity::Inaccessible<E>>,
-            _universe: &bina_ecs::universe::Universe,
+            universe: &bina_ecs::universe::Universe,
         ) {
+        let mut geometry_changed = false;
+        let graphics = universe.try_get_singleton::<Graphics>();
+        if let Some(graphics) = graphics {
+            if let Some(geometry) = self.pending_geometry.take() {
+                self.local_bounds = bounds_of(&geometry);
+                self.geometry = geometry;
+                geometry_changed = true;
+            }
+            // The render thread keeps the old mesh alive for as long as it is drawing it
+            if geometry_changed || graphics.inner.generation != self.mesh.generation {
+                self.mesh = create_mesh(&graphics.inner, &self.geometry);
+            }
+        }
+        let last_origin = self.origin.get_inner();
+        let last_emission = self.emission.get_inner();
         self.origin.process_modifiers();
+        self.emission.process_modifiers();
         self.z.process_modifiers();
         self.rotation.process_modifiers();
         self.scale.process_modifiers();
-        let rot = self.rotation.get_inner();
-        let scale = self.scale.get_inner();
-        self.basis = Matrix2::new(rot.cos() * scale.0.x, rot.sin() * scale.0.x, -rot.sin() * scale.0.y, rot.cos() * scale.0.y);
+        self.layers.process_modifiers();
+        self.visible.process_modifiers();
+        self.clip.process_modifiers();
+        self.mask.process_modifiers();
+        let was_anchored = self.anchor.get_inner().is_some();
+        self.anchor.process_modifiers();
+        // Anchoring a polygon after it was created moves it to the UI layer, like `with_anchor` does
+        if !was_anchored && self.anchor.get_inner().is_some() {
+            self.layers.set_inner(RenderLayers::UI);
+        }
+        // Found again every flush, so that anchored polygons follow the window as it is resized
+        let last_anchor_position = self.anchor_position;
+        self.anchor_position = match (*self.anchor.get_inner(), graphics) {
+            (Some(anchor), Some(graphics)) => anchor.position(graphics.ui_size()),
+            _ => Vector::new(0.0, 0.0),
+        };
+        let basis = basis_of(self.rotation.get_inner(), self.scale.get_inner());
+        let origin = self.origin.get_inner() + self.anchor_position;
+        self.transform_dirty = basis != self.basis
+            || origin.0 != (last_origin + last_anchor_position).0
+            || self.emission.get_inner() != last_emission;
+        if self.transform_dirty {
+            self.basis = basis;
+        }
+        if self.transform_dirty || geometry_changed {
+            self.world_bounds = self.local_bounds.transformed(&self.basis, origin);
+        }
+    }
+}
+
+/// Gets the matrix that rotates and then scales the vertices of a polygon
+pub(crate) fn basis_of(rotation: f32, scale: Vector) -> Matrix2<f32> {
+    Matrix2::new(
+        rotation.cos() * scale.x,
+        rotation.sin() * scale.x,
+        -rotation.sin() * scale.y,
+        rotation.cos() * scale.y,
+    )
+}
+
+impl Reflect for Polygon {
+    fn reflect_fields(&self, visitor: &mut dyn FnMut(&'static str, Field<'_>)) {
+        visitor("origin", Field::Number(&self.origin));
+        visitor("z", Field::Number(&self.z));
+        visitor("rotation", Field::Number(&self.rotation));
+        visitor("scale", Field::Number(&self.scale));
+        visitor("emission", Field::Number(&self.emission));
+        visitor("layers", Field::Debug(&self.layers));
+        visitor("visible", Field::Debug(&self.visible));
+        visitor("anchor", Field::Debug(self.anchor.get_inner()));
     }
 }
 
 impl Processable for Polygon {
     fn process<E: bina_ecs::entity::Entity>(
         mut component: Self::Reference<'_>,
-        _my_entity: bina_ecs::entity::EntityReference<E>,
+        my_entity: bina_ecs::entity::EntityReference<E>,
         universe: &bina_ecs::universe::Universe,
     ) {
         let graphics = unsafe { universe.try_get_singleton::<Graphics>().unwrap_unchecked() };
@@ -223,24 +684,43 @@ impl Processable for Polygon {
         component.rotation += 0.5 * universe.get_delta();
         // component.scale += Vector::new(0.5 * universe.get_delta(), 0.0);
 
-        let basis = component.basis;
-        
-        graphics.inner.queue.write_buffer(
-            &component.inner.transform_buffer,
-            0,
-            bytemuck::cast_slice(&[
-                basis.m11,
-                basis.m12,
-                basis.m21,
-                basis.m22,
-                component.origin.0.x,
-                component.origin.0.y,
-            ]),
-        );
+        // Placed relative to the transform of the entity, if it has one
+        let my_entity: &dyn ErasedEntityReference = &my_entity;
+        let transform = my_entity.get_component::<Transform>().map(Transform::get_ref);
+
+        // Written even while hidden, so that the slot is up to date once the polygon is shown
+        if component.transform_dirty || transform.is_some_and(|x| x.changed()) {
+            let origin = *component.origin + *component.anchor_position;
+            let (basis, origin) = match transform {
+                Some(transform) => transform.apply(component.basis, origin),
+                None => (*component.basis, origin),
+            };
+            graphics.transform_slots.set(
+                component.inner.transform_slot.index,
+                [
+                    basis.m11,
+                    basis.m12,
+                    basis.m21,
+                    basis.m22,
+                    origin.x,
+                    origin.y,
+                    *component.emission,
+                    0.0,
+                ],
+            );
+        }
+
+        if !component.visible.0 {
+            return;
+        }
 
         graphics.queue_draw_instruction(DrawInstruction::DrawPolygon(DrawPolygon {
             polygon: component.inner.clone(),
+            mesh: component.mesh.clone(),
             z: *component.z,
+            layers: *component.layers,
+            clip: *component.clip,
+            mask: *component.mask,
         }));
     }
 }
@@ -278,6 +758,14 @@ impl Sub for Vector {
     }
 }
 
+impl Mul<f32> for Vector {
+    type Output = Self;
+
+    fn mul(self, rhs: f32) -> Self::Output {
+        Self(self.0 * rhs)
+    }
+}
+
 impl AddAssign for Vector {
     fn add_assign(&mut self, rhs: Self) {
         self.0 += rhs.0;
@@ -334,17 +822,114 @@ impl AtomicNumber for Vector {
     }
 }
 
+impl ReflectNumber for Vector {
+    const COMPONENTS: usize = 2;
+
+    fn component(self, index: usize) -> f64 {
+        if index == 0 {
+            self.x as f64
+        } else {
+            self.y as f64
+        }
+    }
+
+    fn with_component(self, index: usize, value: f64) -> Self {
+        if index == 0 {
+            Self::new(value as f32, self.y)
+        } else {
+            Self::new(self.x, value as f32)
+        }
+    }
+}
+
 impl Vector {
-    pub fn new(x: f32, y: f32) -> Self {
+    pub const fn new(x: f32, y: f32) -> Self {
         Self(lyon::math::Vector::new(x, y))
     }
 }
 
 pub struct PolygonRef<'a> {
     inner: &'a Arc<PolygonInner>,
+    mesh: &'a Arc<Mesh>,
+    local_bounds: &'a Aabb,
+    pending_geometry: &'a Arc<PendingGeometry>,
     pub origin: NumberFieldRef<'a, Vector>,
     pub z: NumberFieldRef<'a, u32>,
     pub rotation: NumberFieldRef<'a, f32>,
     pub scale: NumberFieldRef<'a, Vector>,
+    /// How much brighter than its material this polygon is drawn, where 1 is twice as bright.
+    /// Anything brighter than white is kept until it is tonemapped, so emissive polygons can bloom.
+    /// Emission is added to any `AmbientLight`, so emissive polygons stay bright at night
+    pub emission: NumberFieldRef<'a, f32>,
+    pub layers: NumberFieldRef<'a, RenderLayers>,
+    pub visible: NumberFieldRef<'a, Visible>,
+    /// Only the parts of this polygon inside this rectangle are drawn
+    pub clip: StagedMutFieldRef<'a, Option<ClipRect>>,
+    pub mask: StagedMutFieldRef<'a, Mask>,
+    /// The point on the window that the origin is relative to, if any.
+    /// Setting an anchor on a polygon without one moves it to the UI layer
+    pub anchor: StagedMutFieldRef<'a, Option<Anchor>>,
+    anchor_position: &'a Vector,
     pub(crate) basis: &'a Matrix2<f32>,
+    world_bounds: &'a Aabb,
+    transform_dirty: bool,
+}
+
+impl<'a> PolygonRef<'a> {
+    /// The bounds of the vertices, before they are transformed
+    pub fn local_bounds(&self) -> Aabb {
+        *self.local_bounds
+    }
+
+    /// Replaces the vertices of this polygon during the next flush,
+    /// tessellating them on this thread
+    pub fn queue_set_vertices(&self, vertices: &[(Vector, Vector)]) {
+        self.queue_set_contours(&[vertices], &FillOptions::default());
+    }
+
+    /// Replaces the vertices of this polygon during the first flush after they have
+    /// been tessellated on another thread
+    ///
+    /// If vertices are queued again before then, whichever was queued last is kept
+    pub fn queue_set_vertices_async(&self, vertices: Vec<(Vector, Vector)>) {
+        self.queue_set_contours_async(vec![vertices], FillOptions::default());
+    }
+
+    /// Replaces the contours of this polygon during the next flush,
+    /// tessellating them on this thread
+    pub fn queue_set_contours<C: AsRef<[(Vector, Vector)]>>(&self, contours: &[C], options: &FillOptions) {
+        self.queue_set_geometry(tessellate(contours, options));
+    }
+
+    /// Replaces the contours of this polygon during the first flush after they have
+    /// been tessellated on another thread
+    pub fn queue_set_contours_async(&self, contours: Vec<Vec<(Vector, Vector)>>, options: FillOptions) {
+        self.queue_geometry_with(move || tessellate(&contours, &options));
+    }
+
+    /// Replaces the vertices of this polygon during the first flush after `build` has made them on another thread
+    pub(crate) fn queue_geometry_with(&self, build: impl FnOnce() -> Geometry + Send + 'static) {
+        let pending = self.pending_geometry.clone();
+        let request = pending.request();
+        #[cfg(not(target_arch = "wasm32"))]
+        bina_ecs::rayon::spawn(move || pending.fulfill(request, build()));
+        // Browsers only have one thread
+        #[cfg(target_arch = "wasm32")]
+        pending.fulfill(request, build());
+    }
+
+    /// Replaces the vertices of this polygon during the next flush with vertices that have
+    /// already been tessellated, where each vertex is its position followed by its texture coordinates
+    pub fn queue_set_geometry(&self, geometry: VertexBuffers<[f32; 4], u32>) {
+        let request = self.pending_geometry.request();
+        self.pending_geometry.fulfill(request, geometry);
+    }
+
+    /// The bounds of the polygon in world coordinates, as of the last flush
+    ///
+    /// If the entity has a `Transform`, these bounds are in the space of that transform instead.
+    /// Changes made to the transform during this process frame are not included until the next one
+    pub fn world_bounds(&self) -> Aabb {
+        *self.world_bounds
+    }
 }
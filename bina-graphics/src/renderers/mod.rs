@@ -1,59 +1,177 @@
 use bina_ecs::{rayon::slice::ParallelSliceMut, triomphe::Arc};
-use wgpu::{BindGroup, BindGroupLayout, Device, RenderPass, SurfaceConfiguration};
+use wgpu::{BindGroup, Device, RenderPass, RenderPipeline};
+use winit::dpi::PhysicalSize;
 
-use crate::polygon::{Material, PolygonInner};
+use crate::{
+    gizmos::GizmoBatch,
+    hdr::HDR_FORMAT,
+    layers::RenderLayers,
+    mask::{ClipRect, Mask},
+    meshes::{Mesh, MeshBuffers},
+    polygon::{Material, PolygonInner},
+    texture_array::SpriteBatch,
+    transforms::TransformBuffer,
+};
 
-use self::textured::TexturedPolygonRenderer;
+use self::{
+    pipelines::{BindGroupLayouts, MaterialKind, PipelineCache, PipelineKey, StencilMode, VertexLayout},
+    textured::TexturedPolygonRenderer,
+};
 
+pub(crate) mod pipelines;
 mod textured;
 
+#[derive(Clone)]
 pub(crate) struct DrawPolygon {
     pub(crate) polygon: Arc<PolygonInner>,
+    /// Held until the frame is submitted, so that its range of the arena cannot be reused before then
+    pub(crate) mesh: Arc<Mesh>,
     pub(crate) z: u32,
+    pub(crate) layers: RenderLayers,
+    pub(crate) clip: Option<ClipRect>,
+    pub(crate) mask: Mask,
 }
 
-pub(super) struct PolygonRendererCreation {
-    pub(super) poly_render: PolygonRenderer,
-    pub(super) tex_grp_layout: BindGroupLayout,
+impl DrawPolygon {
+    /// Whether this polygon is drawn in screen space through the UI camera
+    pub(crate) fn is_ui(&self) -> bool {
+        self.layers.intersects(RenderLayers::UI)
+    }
+}
+
+/// The buffers shared by every polygon drawn in a frame
+#[derive(Clone, Copy)]
+pub(crate) struct DrawResources<'a> {
+    pub(crate) meshes: &'a MeshBuffers,
+    pub(crate) transforms: &'a TransformBuffer,
+    pub(crate) camera_bind_group: &'a BindGroup,
+    /// Used instead of `camera_bind_group` for polygons on the UI layer
+    pub(crate) ui_camera_bind_group: &'a BindGroup,
 }
 
 pub(crate) struct PolygonRenderer {
     z_buffer: Vec<DrawPolygon>,
+    /// The generation of the device this renderer was created with
+    generation: u64,
+    /// Shared by every renderer
+    pipelines: PipelineCache,
     pub(crate) tex_poly: TexturedPolygonRenderer,
+    /// Drawn in order of z along with the polygons
+    sprite_batches: Vec<SpriteBatch>,
+    /// Drawn after every polygon
+    gizmos: Option<GizmoBatch>,
 }
 
+/// The pipeline that gizmos are drawn with
+const GIZMO_PIPELINE_KEY: PipelineKey = PipelineKey {
+    material: MaterialKind::Gizmo,
+    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+    stencil: StencilMode::Ignore,
+    msaa: 1,
+    vertex_layout: VertexLayout::Colored,
+    format: HDR_FORMAT,
+};
+
+/// The pipeline that sprites of texture arrays are drawn with
+const SPRITE_PIPELINE_KEY: PipelineKey = PipelineKey {
+    material: MaterialKind::TextureArray,
+    blend: Some(wgpu::BlendState::REPLACE),
+    stencil: StencilMode::Ignore,
+    msaa: 1,
+    vertex_layout: VertexLayout::SpriteInstance,
+    format: HDR_FORMAT,
+};
+
 impl PolygonRenderer {
-    pub(super) fn new(device: &Device, config: &SurfaceConfiguration, transform_bind_group_layout: &BindGroupLayout, camera_bind_group_layout: &BindGroupLayout) -> PolygonRendererCreation {
-        let (tex_poly, tex_grp_layout) = TexturedPolygonRenderer::new(device, config, transform_bind_group_layout, camera_bind_group_layout);
-        PolygonRendererCreation {
-            poly_render: Self {
-                z_buffer: Default::default(),
-                tex_poly,
-            },
-            tex_grp_layout,
+    pub(super) fn new(layouts: Arc<BindGroupLayouts>, generation: u64) -> Self {
+        Self {
+            z_buffer: Default::default(),
+            generation,
+            pipelines: PipelineCache::new(layouts),
+            tex_poly: TexturedPolygonRenderer::new(),
+            sprite_batches: Vec::new(),
+            gizmos: None,
         }
     }
     pub(super) fn push(&mut self, item: DrawPolygon) {
+        // Polygons created on a previous device cannot be drawn
+        // until they rebuild themselves
+        if item.mesh.generation != self.generation {
+            return;
+        }
+        if let Material::Texture(texture) = &item.polygon.material {
+            if texture.texture.generation != self.generation {
+                return;
+            }
+        }
         self.z_buffer.push(item);
     }
 
-    pub(super) fn draw_all<'a>(&'a mut self, render_pass: &mut RenderPass<'a>, camera_matrix_buffer_bind_group: &'a BindGroup) {
-        self.z_buffer.par_sort_unstable_by_key(|x| x.z);
+    pub(super) fn push_sprite_batch(&mut self, batch: SpriteBatch) {
+        if batch.array.generation == self.generation {
+            self.sprite_batches.push(batch);
+        }
+    }
+
+    pub(super) fn push_gizmos(&mut self, batch: GizmoBatch) {
+        if batch.generation == self.generation {
+            self.gizmos = Some(batch);
+        }
+    }
+
+    /// Draws every polygon that was pushed, returning the number of draw calls made
+    ///
+    /// Polygons on the UI layer are drawn through the UI camera, on top of everything else
+    /// except for gizmos
+    pub(super) fn draw_all<'a>(&'a mut self, device: &Device, render_pass: &mut RenderPass<'a>, resources: DrawResources<'a>, surface_size: PhysicalSize<u32>) -> usize {
+        self.z_buffer.par_sort_unstable_by_key(|x| (x.is_ui(), x.z));
 
         for draw_polygon in self.z_buffer.drain(..) {
             unsafe {
                 match &draw_polygon.polygon.material {
                     Material::FlatColor(_) => todo!(),
-                    Material::Texture(_) => self.tex_poly.push(draw_polygon),
+                    Material::Texture(_) => {
+                        self.pipelines.prepare(device, TexturedPolygonRenderer::pipeline_key(&draw_polygon));
+                        self.tex_poly.push(draw_polygon)
+                    }
                 }
             }
         }
 
-        self.tex_poly.draw_all(render_pass, camera_matrix_buffer_bind_group);
+        self.sprite_batches.sort_by_key(|x| (x.ui, x.z));
+        if !self.sprite_batches.is_empty() {
+            self.pipelines.prepare(device, SPRITE_PIPELINE_KEY);
+        }
+        if self.gizmos.is_some() {
+            self.pipelines.prepare(device, GIZMO_PIPELINE_KEY);
+        }
+
+        let mut draw_calls = self.tex_poly.draw_all(render_pass, &self.pipelines, &self.sprite_batches, resources, surface_size);
+        if let Some(gizmos) = &self.gizmos {
+            render_pass.set_pipeline(self.pipelines.get(&GIZMO_PIPELINE_KEY));
+            render_pass.set_scissor_rect(0, 0, surface_size.width, surface_size.height);
+            render_pass.set_bind_group(0, resources.camera_bind_group, &[]);
+            render_pass.set_vertex_buffer(0, gizmos.vertices.slice(..));
+            render_pass.set_index_buffer(gizmos.indices.slice(..), wgpu::IndexFormat::Uint32);
+            render_pass.draw_indexed(0..gizmos.index_count, 0, 0..1);
+            draw_calls += 1;
+        }
+        draw_calls
+    }
+
+    /// Creates every pipeline that would otherwise be created the first time something is drawn with it
+    pub(super) fn warm_up(&mut self, device: &Device) {
+        for stencil in [StencilMode::Ignore, StencilMode::Write, StencilMode::Read] {
+            self.pipelines.prepare(device, TexturedPolygonRenderer::stencil_pipeline_key(stencil));
+        }
+        self.pipelines.prepare(device, SPRITE_PIPELINE_KEY);
+        self.pipelines.prepare(device, GIZMO_PIPELINE_KEY);
     }
 
     pub(super) fn clear(&mut self) {
         self.tex_poly.clear();
+        self.sprite_batches.clear();
+        self.gizmos = None;
     }
 }
 
@@ -66,6 +184,10 @@ impl<'a> BindGroupTracker<'a> {
     fn new(index: u32) -> Self {
         Self { index, last: None }
     }
+    /// Forgets the last bind group, for after it was replaced without this tracker
+    fn reset(&mut self) {
+        self.last = None;
+    }
     fn set_bind_group(&mut self, render_pass: &mut RenderPass<'a>, bind_group: &'a BindGroup) {
         if let Some(last_grp) = self.last {
             if std::ptr::eq(last_grp, bind_group) {
@@ -76,3 +198,44 @@ impl<'a> BindGroupTracker<'a> {
         render_pass.set_bind_group(self.index, bind_group, &[]);
     }
 }
+
+/// Avoids redundant pipeline, scissor, and stencil reference changes
+struct RenderStateTracker<'a> {
+    surface_size: PhysicalSize<u32>,
+    pipeline: Option<&'a RenderPipeline>,
+    clip: Option<ClipRect>,
+    stencil_reference: u32,
+}
+
+impl<'a> RenderStateTracker<'a> {
+    fn new(surface_size: PhysicalSize<u32>) -> Self {
+        Self {
+            surface_size,
+            pipeline: None,
+            clip: None,
+            stencil_reference: 0,
+        }
+    }
+
+    fn set(&mut self, render_pass: &mut RenderPass<'a>, pipeline: &'a RenderPipeline, clip: Option<ClipRect>, mask: Mask) {
+        if !self.pipeline.is_some_and(|x| std::ptr::eq(x, pipeline)) {
+            self.pipeline = Some(pipeline);
+            render_pass.set_pipeline(pipeline);
+        }
+        if self.clip != clip {
+            self.clip = clip;
+            let rect = clip
+                .unwrap_or(ClipRect::new(0, 0, self.surface_size.width, self.surface_size.height))
+                .clamp_to(self.surface_size);
+            render_pass.set_scissor_rect(rect.x, rect.y, rect.width, rect.height);
+        }
+        let stencil_reference = match mask {
+            Mask::None => 0,
+            Mask::Write(x) | Mask::Read(x) => x as u32,
+        };
+        if self.stencil_reference != stencil_reference {
+            self.stencil_reference = stencil_reference;
+            render_pass.set_stencil_reference(stencil_reference);
+        }
+    }
+}
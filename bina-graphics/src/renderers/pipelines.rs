@@ -0,0 +1,368 @@
+//! Render pipelines shared between every renderer
+//!
+//! Creating a pipeline is slow, so each distinct combination of shader and render state
+//! is only created once per device, the first time something is drawn with it
+use std::collections::HashMap;
+
+use bina_ecs::triomphe::Arc;
+use wgpu::{BindGroupLayout, Device, PipelineLayout, RenderPipeline, ShaderModule, TextureFormat};
+
+use crate::{
+    gizmos::GIZMO_VERTEX_BUFFER_DESCRIPTOR,
+    mask::{Mask, STENCIL_FORMAT},
+    polygon::TEXTURE_VERTEX_BUFFER_DESCRIPTOR,
+    texture_array::SPRITE_INSTANCE_BUFFER_DESCRIPTOR,
+    transforms::TRANSFORM_SIZE,
+};
+
+/// The bind group layouts used by every renderer on a device
+pub(crate) struct BindGroupLayouts {
+    pub(crate) texture: BindGroupLayout,
+    /// The same as `texture`, but for the layers of a `TextureArray`
+    pub(crate) texture_array: BindGroupLayout,
+    pub(crate) transform: BindGroupLayout,
+    pub(crate) camera: BindGroupLayout,
+    /// The transform and material of a 3D mesh
+    #[cfg(feature = "3d")]
+    pub(crate) model: BindGroupLayout,
+    /// The joint matrices of a skeleton, or `None` if the device has no storage buffers, such as with WebGL
+    #[cfg(feature = "3d")]
+    pub(crate) joints: Option<BindGroupLayout>,
+}
+
+impl BindGroupLayouts {
+    pub(crate) fn new(device: &Device) -> Self {
+        let texture = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    // This should match the filterable field of the
+                    // corresponding Texture entry above.
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("texture_bind_group_layout"),
+        });
+        let texture_array = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("texture_array_bind_group_layout"),
+        });
+        let transform = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                // Every polygon's transform is in the same buffer
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: std::num::NonZeroU64::new(TRANSFORM_SIZE),
+                },
+                count: None,
+            }],
+            label: Some("transform_bind_group_layout"),
+        });
+        let camera = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("camera_matrix_bind_group_layout"),
+        });
+        #[cfg(feature = "3d")]
+        let model = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("model_bind_group_layout"),
+        });
+        #[cfg(feature = "3d")]
+        let joints = (device.limits().max_storage_buffers_per_shader_stage > 0).then(|| {
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+                label: Some("joints_bind_group_layout"),
+            })
+        });
+        Self {
+            texture,
+            texture_array,
+            transform,
+            camera,
+            #[cfg(feature = "3d")]
+            model,
+            #[cfg(feature = "3d")]
+            joints,
+        }
+    }
+}
+
+/// The shader a pipeline runs, along with the bind groups it expects
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub(crate) enum MaterialKind {
+    Texture,
+    /// A layer of a texture array for each instance of a quad
+    TextureArray,
+    /// A color for each vertex, already in world coordinates
+    Gizmo,
+}
+
+/// The layout of the vertex buffer a pipeline reads from
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub(crate) enum VertexLayout {
+    /// A position followed by a texture coordinate
+    Textured,
+    /// A position followed by a color
+    Colored,
+    /// The transform and layer of each sprite, with the corners of the quad made in the shader
+    SpriteInstance,
+}
+
+impl VertexLayout {
+    fn descriptor(self) -> wgpu::VertexBufferLayout<'static> {
+        match self {
+            Self::Textured => TEXTURE_VERTEX_BUFFER_DESCRIPTOR,
+            Self::Colored => GIZMO_VERTEX_BUFFER_DESCRIPTOR,
+            Self::SpriteInstance => SPRITE_INSTANCE_BUFFER_DESCRIPTOR,
+        }
+    }
+}
+
+/// How a pipeline uses the stencil buffer
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub(crate) enum StencilMode {
+    Ignore,
+    /// Writes into the stencil buffer without drawing any color
+    Write,
+    /// Only draws where the stencil buffer matches the stencil reference
+    Read,
+}
+
+impl From<Mask> for StencilMode {
+    fn from(mask: Mask) -> Self {
+        match mask {
+            Mask::None => Self::Ignore,
+            Mask::Write(_) => Self::Write,
+            Mask::Read(_) => Self::Read,
+        }
+    }
+}
+
+impl StencilMode {
+    fn face_state(self) -> wgpu::StencilFaceState {
+        match self {
+            Self::Ignore => wgpu::StencilFaceState::IGNORE,
+            Self::Write => wgpu::StencilFaceState {
+                compare: wgpu::CompareFunction::Always,
+                fail_op: wgpu::StencilOperation::Keep,
+                depth_fail_op: wgpu::StencilOperation::Keep,
+                pass_op: wgpu::StencilOperation::Replace,
+            },
+            Self::Read => wgpu::StencilFaceState {
+                compare: wgpu::CompareFunction::Equal,
+                fail_op: wgpu::StencilOperation::Keep,
+                depth_fail_op: wgpu::StencilOperation::Keep,
+                pass_op: wgpu::StencilOperation::Keep,
+            },
+        }
+    }
+
+    fn color_writes(self) -> wgpu::ColorWrites {
+        match self {
+            Self::Write => wgpu::ColorWrites::empty(),
+            Self::Ignore | Self::Read => wgpu::ColorWrites::ALL,
+        }
+    }
+}
+
+/// Everything that makes one render pipeline different from another
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub(crate) struct PipelineKey {
+    pub(crate) material: MaterialKind,
+    pub(crate) blend: Option<wgpu::BlendState>,
+    pub(crate) stencil: StencilMode,
+    /// The number of samples per pixel of the render target
+    pub(crate) msaa: u32,
+    pub(crate) vertex_layout: VertexLayout,
+    pub(crate) format: TextureFormat,
+}
+
+/// Every render pipeline created on a device, along with the shaders and layouts they share
+pub(crate) struct PipelineCache {
+    layouts: Arc<BindGroupLayouts>,
+    shaders: HashMap<MaterialKind, (ShaderModule, PipelineLayout)>,
+    pipelines: HashMap<PipelineKey, RenderPipeline>,
+}
+
+impl PipelineCache {
+    pub(crate) fn new(layouts: Arc<BindGroupLayouts>) -> Self {
+        Self {
+            layouts,
+            shaders: HashMap::new(),
+            pipelines: HashMap::new(),
+        }
+    }
+
+    /// Creates the pipeline for the given key if it has not been created yet
+    pub(crate) fn prepare(&mut self, device: &Device, key: PipelineKey) {
+        if self.pipelines.contains_key(&key) {
+            return;
+        }
+        let layouts = &self.layouts;
+        let (shader, layout) = self
+            .shaders
+            .entry(key.material)
+            .or_insert_with(|| create_shader(device, layouts, key.material));
+        let pipeline = create_pipeline(device, shader, layout, key);
+        self.pipelines.insert(key, pipeline);
+    }
+
+    /// Gets the pipeline for the given key, which must have been prepared
+    pub(crate) fn get(&self, key: &PipelineKey) -> &RenderPipeline {
+        self.pipelines
+            .get(key)
+            .expect("pipeline should have been prepared before drawing")
+    }
+}
+
+fn create_shader(
+    device: &Device,
+    layouts: &BindGroupLayouts,
+    material: MaterialKind,
+) -> (ShaderModule, PipelineLayout) {
+    match material {
+        MaterialKind::Texture => (
+            device.create_shader_module(wgpu::include_wgsl!("textured/shader.wgsl")),
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("textured_pipeline_layout"),
+                bind_group_layouts: &[&layouts.texture, &layouts.transform, &layouts.camera],
+                push_constant_ranges: &[],
+            }),
+        ),
+        MaterialKind::TextureArray => (
+            device.create_shader_module(wgpu::include_wgsl!("../shaders/texture_array.wgsl")),
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("texture_array_pipeline_layout"),
+                bind_group_layouts: &[&layouts.texture_array, &layouts.camera],
+                push_constant_ranges: &[],
+            }),
+        ),
+        MaterialKind::Gizmo => (
+            device.create_shader_module(wgpu::include_wgsl!("../shaders/gizmo.wgsl")),
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("gizmo_pipeline_layout"),
+                bind_group_layouts: &[&layouts.camera],
+                push_constant_ranges: &[],
+            }),
+        ),
+    }
+}
+
+fn create_pipeline(
+    device: &Device,
+    shader: &ShaderModule,
+    layout: &PipelineLayout,
+    key: PipelineKey,
+) -> RenderPipeline {
+    let stencil_face = key.stencil.face_state();
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(&format!("{:?}", key)),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[key.vertex_layout.descriptor()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: key.format,
+                blend: key.blend,
+                write_mask: key.stencil.color_writes(),
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Cw,
+            // The UI camera flips the y axis, which reverses the winding of every triangle
+            cull_mode: None,
+            // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
+            polygon_mode: wgpu::PolygonMode::Fill,
+            // Requires Features::DEPTH_CLIP_CONTROL
+            unclipped_depth: false,
+            // Requires Features::CONSERVATIVE_RASTERIZATION
+            conservative: false,
+        },
+        // Every pipeline must match the format of the stencil attachment,
+        // even if it does not use it
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: STENCIL_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Always,
+            stencil: wgpu::StencilState {
+                front: stencil_face,
+                back: stencil_face,
+                read_mask: 0xff,
+                write_mask: 0xff,
+            },
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: key.msaa,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
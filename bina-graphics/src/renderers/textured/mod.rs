@@ -1,129 +1,124 @@
 use std::hint::unreachable_unchecked;
 
-use wgpu::{BindGroupLayout, Device, RenderPass, RenderPipeline, SurfaceConfiguration, BindGroup};
+use wgpu::RenderPass;
+use winit::dpi::PhysicalSize;
 
-use crate::polygon::{Material, TEXTURE_VERTEX_BUFFER_DESCRIPTOR};
+use crate::{
+    hdr::HDR_FORMAT,
+    labels,
+    mask::Mask,
+    polygon::Material,
+    texture_array::SpriteBatch,
+};
 
-use super::{BindGroupTracker, DrawPolygon};
+use super::{
+    pipelines::{MaterialKind, PipelineCache, PipelineKey, StencilMode, VertexLayout},
+    BindGroupTracker, DrawPolygon, DrawResources, RenderStateTracker, SPRITE_PIPELINE_KEY,
+};
 
 pub(crate) struct TexturedPolygonRenderer {
     buffer: Vec<DrawPolygon>,
-    render_pipeline: RenderPipeline,
 }
 
 impl TexturedPolygonRenderer {
-    pub(crate) fn new(device: &Device, config: &SurfaceConfiguration, transform_bind_group_layout: &BindGroupLayout, camera_bind_group_layout: &BindGroupLayout) -> (Self, BindGroupLayout) {
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        // This should match the filterable field of the
-                        // corresponding Texture entry above.
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
-                label: Some("texture_bind_group_layout"),
-            });
-
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[
-                    &texture_bind_group_layout,
-                    transform_bind_group_layout,
-                    camera_bind_group_layout
-                ],
-                push_constant_ranges: &[],
-            });
-
-        let shader = device.create_shader_module(wgpu::include_wgsl!("shader.wgsl"));
+    pub(crate) fn new() -> Self {
+        Self {
+            buffer: Default::default(),
+        }
+    }
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",                       // 1.
-                buffers: &[TEXTURE_VERTEX_BUFFER_DESCRIPTOR], // 2.
-            },
-            fragment: Some(wgpu::FragmentState {
-                // 3.
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    // 4.
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList, // 1.
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Cw, // 2.
-                cull_mode: Some(wgpu::Face::Back),
-                // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-                polygon_mode: wgpu::PolygonMode::Fill,
-                // Requires Features::DEPTH_CLIP_CONTROL
-                unclipped_depth: false,
-                // Requires Features::CONSERVATIVE_RASTERIZATION
-                conservative: false,
-            },
-            depth_stencil: None, // 1.
-            multisample: wgpu::MultisampleState {
-                count: 1,                         // 2.
-                mask: !0,                         // 3.
-                alpha_to_coverage_enabled: false, // 4.
-            },
-            multiview: None,
-        });
+    /// The pipeline that the given polygon is drawn with
+    pub(super) fn pipeline_key(polygon: &DrawPolygon) -> PipelineKey {
+        Self::stencil_pipeline_key(polygon.mask.into())
+    }
 
-        (
-            Self {
-                buffer: Default::default(),
-                render_pipeline,
-            },
-            texture_bind_group_layout,
-        )
+    /// The pipeline that polygons with the given stencil mode are drawn with
+    pub(super) fn stencil_pipeline_key(stencil: StencilMode) -> PipelineKey {
+        PipelineKey {
+            material: MaterialKind::Texture,
+            blend: Some(wgpu::BlendState::REPLACE),
+            stencil,
+            msaa: 1,
+            vertex_layout: VertexLayout::Textured,
+            format: HDR_FORMAT,
+        }
     }
 
     pub(super) unsafe fn push(&mut self, polygon: DrawPolygon) {
         self.buffer.push(polygon);
     }
 
-    pub(super) fn draw_all<'a>(&'a mut self, render_pass: &mut RenderPass<'a>, camera_matrix_buffer_bind_group: &'a BindGroup) {
-        render_pass.set_pipeline(&self.render_pipeline);
+    /// Draws every polygon that was pushed, along with the given sprite batches in order of z,
+    /// returning the number of draw calls made
+    pub(super) fn draw_all<'a>(&'a mut self, render_pass: &mut RenderPass<'a>, pipelines: &'a PipelineCache, sprite_batches: &'a [SpriteBatch], resources: DrawResources<'a>, surface_size: PhysicalSize<u32>) -> usize {
         let mut bind_grp_tracker = BindGroupTracker::new(0);
+        let mut camera_tracker = BindGroupTracker::new(2);
+        let mut state_tracker = RenderStateTracker::new(surface_size);
+        // Every polygon is in the same vertex and index buffers
+        render_pass.set_vertex_buffer(0, resources.meshes.vertices.slice(..));
+        render_pass.set_index_buffer(resources.meshes.indices.slice(..), wgpu::IndexFormat::Uint32);
 
-        for DrawPolygon {
-            polygon,
-            ..
-        } in &self.buffer
-        {
+        // Masks must be written before anything can read them
+        let writers = self.buffer.iter().filter(|x| matches!(x.mask, Mask::Write(_)));
+        let others = self.buffer.iter().filter(|x| !matches!(x.mask, Mask::Write(_)));
+
+        let sprite_batches_len = sprite_batches.len();
+        let mut sprite_batches = sprite_batches.iter().peekable();
+        for draw_polygon in writers.chain(others) {
+            // Sprites are drawn before the polygons in front of them, but never before a mask is written
+            if !matches!(draw_polygon.mask, Mask::Write(_))
+                && sprite_batches.peek().is_some_and(|x| (x.ui, x.z) < (draw_polygon.is_ui(), draw_polygon.z))
+            {
+                while let Some(batch) = sprite_batches.next_if(|x| (x.ui, x.z) < (draw_polygon.is_ui(), draw_polygon.z)) {
+                    Self::draw_sprites(render_pass, pipelines, &mut state_tracker, &mut bind_grp_tracker, batch, resources);
+                }
+                // The sprites replaced the vertex buffer and the bind groups after the texture
+                render_pass.set_vertex_buffer(0, resources.meshes.vertices.slice(..));
+                camera_tracker.reset();
+            }
+            let DrawPolygon {
+                polygon,
+                mesh,
+                clip,
+                mask,
+                ..
+            } = draw_polygon;
             let Material::Texture(texture) = &polygon.material else {
                 unsafe { unreachable_unchecked() }
             };
 
+            let pipeline = pipelines.get(&Self::pipeline_key(draw_polygon));
+            state_tracker.set(render_pass, pipeline, *clip, *mask);
             bind_grp_tracker.set_bind_group(render_pass, &texture.texture.bind_group);
-            render_pass.set_bind_group(1, &polygon.transform_bind_group, &[]);
-            render_pass.set_bind_group(2, camera_matrix_buffer_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, polygon.vertices.slice(..));
-            render_pass.set_index_buffer(polygon.indices.slice(..), wgpu::IndexFormat::Uint32);
-            render_pass.draw_indexed(0..polygon.indices_count, 0, 0..1);
+            render_pass.set_bind_group(1, &resources.transforms.bind_group, &[resources.transforms.offset(polygon.transform_slot.index)]);
+            let camera = if draw_polygon.is_ui() {
+                resources.ui_camera_bind_group
+            } else {
+                resources.camera_bind_group
+            };
+            camera_tracker.set_bind_group(render_pass, camera);
+            labels::mark_draw(render_pass, polygon.name.as_deref().or(texture.texture.name.as_deref()));
+            render_pass.draw_indexed(mesh.indices.clone(), 0, 0..1);
+        }
+        for batch in sprite_batches {
+            Self::draw_sprites(render_pass, pipelines, &mut state_tracker, &mut bind_grp_tracker, batch, resources);
         }
+        self.buffer.len() + sprite_batches_len
+    }
+
+    /// Draws every sprite of a batch as an instance of one quad
+    fn draw_sprites<'a>(render_pass: &mut RenderPass<'a>, pipelines: &'a PipelineCache, state_tracker: &mut RenderStateTracker<'a>, bind_grp_tracker: &mut BindGroupTracker<'a>, batch: &'a SpriteBatch, resources: DrawResources<'a>) {
+        state_tracker.set(render_pass, pipelines.get(&SPRITE_PIPELINE_KEY), None, Mask::None);
+        bind_grp_tracker.set_bind_group(render_pass, &batch.array.bind_group);
+        let camera = if batch.ui {
+            resources.ui_camera_bind_group
+        } else {
+            resources.camera_bind_group
+        };
+        render_pass.set_bind_group(1, camera, &[]);
+        render_pass.set_vertex_buffer(0, batch.instances.slice(..));
+        labels::mark_draw(render_pass, batch.array.name.as_deref());
+        render_pass.draw(0..6, batch.range.clone());
     }
 
     pub(super) fn clear(&mut self) {
@@ -6,15 +6,19 @@ struct VertexInput {
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
+    // The ambient light plus emission
+    @location(1) light: vec3<f32>,
 }
 
 struct Transform {
     basis: mat2x2<f32>,
-    origin: vec2<f32>
+    origin: vec2<f32>,
+    emission: f32,
 }
 struct CameraMatrix {
     inverse_basis: mat2x2<f32>,
-    origin: vec2<f32>
+    origin: vec2<f32>,
+    ambient: vec4<f32>,
 }
 
 
@@ -29,6 +33,7 @@ fn vs_main(
 ) -> VertexOutput {
     var out: VertexOutput;
     out.tex_coords = model.tex_coords;
+    out.light = camera_matrix.ambient.rgb + transform.emission;
     out.clip_position = vec4<f32>(
         camera_matrix.inverse_basis * (transform.basis * model.position + transform.origin - camera_matrix.origin),
         // model.position,
@@ -43,5 +48,6 @@ var s_diffuse: sampler;
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    let color = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    return vec4<f32>(color.rgb * in.light, color.a);
 }
\ No newline at end of file
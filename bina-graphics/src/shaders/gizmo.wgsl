@@ -0,0 +1,33 @@
+struct VertexInput {
+    @location(0) position: vec2<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+}
+
+struct CameraMatrix {
+    inverse_basis: mat2x2<f32>,
+    origin: vec2<f32>
+}
+
+@group(0) @binding(0)
+var<uniform> camera_matrix: CameraMatrix;
+
+// Gizmos are already in world coordinates, so they have no transform
+@vertex
+fn vs_main(
+    model: VertexInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.color = model.color;
+    out.clip_position = vec4<f32>(camera_matrix.inverse_basis * (model.position - camera_matrix.origin), 0.0, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
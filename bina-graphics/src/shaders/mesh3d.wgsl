@@ -0,0 +1,54 @@
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) tex_coords: vec2<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) normal: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+}
+
+struct Model {
+    matrix: mat4x4<f32>,
+    normal_matrix: mat4x4<f32>,
+    color: vec4<f32>,
+    // Only x is used, the rest is padding
+    emission: vec4<f32>,
+}
+
+struct Camera {
+    view_projection: mat4x4<f32>,
+}
+
+@group(0) @binding(0)
+var t_diffuse: texture_2d<f32>;
+@group(0) @binding(1)
+var s_diffuse: sampler;
+@group(1) @binding(0)
+var<uniform> model: Model;
+@group(2) @binding(0)
+var<uniform> camera: Camera;
+
+// Meshes are lit by a single light shining down and away from the camera's default direction
+const LIGHT_DIRECTION: vec3<f32> = vec3<f32>(-0.3, -1.0, -0.5);
+const AMBIENT: f32 = 0.3;
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = camera.view_projection * model.matrix * vec4<f32>(in.position, 1.0);
+    out.normal = (model.normal_matrix * vec4<f32>(in.normal, 0.0)).xyz;
+    out.tex_coords = in.tex_coords;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_diffuse, s_diffuse, in.tex_coords) * model.color;
+    let normal = normalize(in.normal);
+    let diffuse = max(dot(normal, -normalize(LIGHT_DIRECTION)), 0.0);
+    let light = AMBIENT + (1.0 - AMBIENT) * diffuse;
+    return vec4<f32>(color.rgb * light * (1.0 + model.emission.x), color.a);
+}
@@ -0,0 +1,25 @@
+// Appended to mesh3d.wgsl for meshes that are moved by the joints of a skeleton
+
+struct SkinnedVertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) tex_coords: vec2<f32>,
+    @location(3) joints: vec4<u32>,
+    @location(4) weights: vec4<f32>,
+}
+
+@group(3) @binding(0)
+var<storage, read> joint_matrices: array<mat4x4<f32>>;
+
+@vertex
+fn vs_skinned(in: SkinnedVertexInput) -> VertexOutput {
+    let skin = joint_matrices[in.joints.x] * in.weights.x
+        + joint_matrices[in.joints.y] * in.weights.y
+        + joint_matrices[in.joints.z] * in.weights.z
+        + joint_matrices[in.joints.w] * in.weights.w;
+    var out: VertexOutput;
+    out.clip_position = camera.view_projection * model.matrix * skin * vec4<f32>(in.position, 1.0);
+    out.normal = (model.normal_matrix * skin * vec4<f32>(in.normal, 0.0)).xyz;
+    out.tex_coords = in.tex_coords;
+    return out;
+}
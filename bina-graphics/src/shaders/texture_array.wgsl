@@ -0,0 +1,63 @@
+struct InstanceInput {
+    @location(0) basis: vec4<f32>,
+    @location(1) origin: vec2<f32>,
+    @location(2) layer: u32,
+    @location(3) emission: f32,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) @interpolate(flat) layer: u32,
+    // The ambient light plus emission
+    @location(2) light: vec3<f32>,
+}
+
+struct CameraMatrix {
+    inverse_basis: mat2x2<f32>,
+    origin: vec2<f32>,
+    ambient: vec4<f32>,
+}
+
+@group(1) @binding(0)
+var<uniform> camera_matrix: CameraMatrix;
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) index: u32,
+    instance: InstanceInput,
+) -> VertexOutput {
+    // Two triangles covering a square of size 1 centered on the origin
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-0.5, -0.5),
+        vec2<f32>(0.5, -0.5),
+        vec2<f32>(0.5, 0.5),
+        vec2<f32>(-0.5, -0.5),
+        vec2<f32>(0.5, 0.5),
+        vec2<f32>(-0.5, 0.5),
+    );
+    let corner = corners[index];
+    let basis = mat2x2<f32>(instance.basis.xy, instance.basis.zw);
+
+    var out: VertexOutput;
+    // The top of the image is at the top of the quad, including on the UI where y points down
+    let up = sign(determinant(camera_matrix.inverse_basis));
+    out.tex_coords = vec2<f32>(corner.x + 0.5, 0.5 - corner.y * up);
+    out.layer = instance.layer;
+    out.light = camera_matrix.ambient.rgb + instance.emission;
+    out.clip_position = vec4<f32>(
+        camera_matrix.inverse_basis * (basis * corner + instance.origin - camera_matrix.origin),
+        0.0, 1.0);
+    return out;
+}
+
+@group(0) @binding(0)
+var t_diffuse: texture_2d_array<f32>;
+@group(0) @binding(1)
+var s_diffuse: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_diffuse, s_diffuse, in.tex_coords, in.layer);
+    return vec4<f32>(color.rgb * in.light, color.a);
+}
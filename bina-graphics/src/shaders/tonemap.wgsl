@@ -0,0 +1,89 @@
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+struct HdrSettings {
+    exposure: f32,
+    bloom_threshold: f32,
+    bloom_intensity: f32,
+    tonemapping: f32,
+}
+
+@group(0) @binding(0)
+var hdr_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var hdr_sampler: sampler;
+@group(1) @binding(0)
+var<uniform> settings: HdrSettings;
+@group(2) @binding(0)
+var bloom_texture: texture_2d<f32>;
+@group(2) @binding(1)
+var bloom_sampler: sampler;
+
+// A single triangle that covers the whole screen
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    out.tex_coords = vec2<f32>(uv.x, 1.0 - uv.y);
+    return out;
+}
+
+// Keeps only the part of each pixel brighter than the bloom threshold,
+// averaging the 4 pixels around it as the bloom texture is half the size
+@fragment
+fn fs_bright(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(hdr_texture));
+    var color = textureSample(hdr_texture, hdr_sampler, in.tex_coords + vec2<f32>(-texel.x, -texel.y)).rgb;
+    color += textureSample(hdr_texture, hdr_sampler, in.tex_coords + vec2<f32>(texel.x, -texel.y)).rgb;
+    color += textureSample(hdr_texture, hdr_sampler, in.tex_coords + vec2<f32>(-texel.x, texel.y)).rgb;
+    color += textureSample(hdr_texture, hdr_sampler, in.tex_coords + vec2<f32>(texel.x, texel.y)).rgb;
+    color *= 0.25 * settings.exposure;
+    let brightness = max(color.r, max(color.g, color.b));
+    let excess = max(brightness - settings.bloom_threshold, 0.0);
+    return vec4<f32>(color * (excess / max(brightness, 0.0001)), 1.0);
+}
+
+// Blurs the bloom texture with a 3x3 tent filter
+fn sample_bloom(tex_coords: vec2<f32>) -> vec3<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(bloom_texture));
+    var bloom = textureSample(bloom_texture, bloom_sampler, tex_coords).rgb * 4.0;
+    bloom += textureSample(bloom_texture, bloom_sampler, tex_coords + vec2<f32>(texel.x, 0.0)).rgb * 2.0;
+    bloom += textureSample(bloom_texture, bloom_sampler, tex_coords - vec2<f32>(texel.x, 0.0)).rgb * 2.0;
+    bloom += textureSample(bloom_texture, bloom_sampler, tex_coords + vec2<f32>(0.0, texel.y)).rgb * 2.0;
+    bloom += textureSample(bloom_texture, bloom_sampler, tex_coords - vec2<f32>(0.0, texel.y)).rgb * 2.0;
+    bloom += textureSample(bloom_texture, bloom_sampler, tex_coords + texel).rgb;
+    bloom += textureSample(bloom_texture, bloom_sampler, tex_coords - texel).rgb;
+    bloom += textureSample(bloom_texture, bloom_sampler, tex_coords + vec2<f32>(texel.x, -texel.y)).rgb;
+    bloom += textureSample(bloom_texture, bloom_sampler, tex_coords + vec2<f32>(-texel.x, texel.y)).rgb;
+    return bloom / 16.0;
+}
+
+// Narkowicz's fit of the ACES filmic curve
+fn aces(color: vec3<f32>) -> vec3<f32> {
+    return clamp((color * (2.51 * color + 0.03)) / (color * (2.43 * color + 0.59) + 0.14), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+fn reinhard(color: vec3<f32>) -> vec3<f32> {
+    return color / (1.0 + color);
+}
+
+@fragment
+fn fs_composite(in: VertexOutput) -> @location(0) vec4<f32> {
+    var color = textureSample(hdr_texture, hdr_sampler, in.tex_coords).rgb * settings.exposure;
+    color += sample_bloom(in.tex_coords) * settings.bloom_intensity;
+    switch u32(settings.tonemapping) {
+        case 1u: {
+            color = aces(color);
+        }
+        case 2u: {
+            color = reinhard(color);
+        }
+        default: {
+            color = clamp(color, vec3<f32>(0.0), vec3<f32>(1.0));
+        }
+    }
+    return vec4<f32>(color, 1.0);
+}
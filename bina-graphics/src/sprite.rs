@@ -0,0 +1,341 @@
+//! Sprites that play a sequence of frames, such as the frames of an animated GIF or APNG
+//!
+//! `TextureLoader` packs the frames of animated images into one texture, and remembers where
+//! each frame is so that an `AnimatedSprite` can play them
+//!
+//! ```ignore
+//! let explosion = graphics.load_texture(universe, "explosion.gif");
+//! // Once it has loaded
+//! let sprite = AnimatedSprite::new(graphics, &explosion.get().unwrap(), Vector::new(2.0, 2.0))
+//!     .with_looping(false);
+//! universe.queue_add_entity((sprite,));
+//! ```
+use std::{
+    io::Cursor,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use atomic_float::AtomicF32;
+use bina_ecs::{
+    component::{Component, ComponentField, NumberField, NumberFieldRef, Processable},
+    reflect::{Field, Reflect},
+    triomphe::Arc,
+};
+use image::{
+    codecs::{gif::GifDecoder, png::PngDecoder},
+    imageops, AnimationDecoder, Frame, ImageFormat, RgbaImage,
+};
+use lyon::lyon_tessellation::VertexBuffers;
+
+use crate::{
+    polygon::{Material, Polygon, PolygonRef, Vector, Vector2},
+    texture::TextureAsset,
+    Graphics,
+};
+
+/// Browsers play frames with a delay this short or shorter at this speed instead,
+/// and many GIFs rely on it
+const MIN_FRAME_DURATION: f32 = 0.02;
+const DEFAULT_FRAME_DURATION: f32 = 0.1;
+
+/// Where a frame is in its texture, and how long it is shown for
+#[derive(Clone, Copy, Debug)]
+pub struct SpriteFrame {
+    /// The texture coordinates of the top left corner of the frame
+    pub min: Vector2,
+    /// The texture coordinates of the bottom right corner of the frame
+    pub max: Vector2,
+    /// In seconds
+    pub duration: f32,
+}
+
+/// The frames of an animation, in the order they are played
+#[derive(Clone, Debug)]
+pub struct SpriteAnimation {
+    frames: Vec<SpriteFrame>,
+}
+
+impl SpriteAnimation {
+    /// # Panics
+    /// Panics if there are no frames
+    pub fn new(frames: Vec<SpriteFrame>) -> Self {
+        assert!(!frames.is_empty(), "An animation needs at least one frame");
+        Self { frames }
+    }
+
+    /// A single frame covering the whole texture, which is never replaced
+    pub fn still() -> Self {
+        Self::new(vec![SpriteFrame {
+            min: Vector2::new(0.0, 0.0),
+            max: Vector2::new(1.0, 1.0),
+            duration: f32::INFINITY,
+        }])
+    }
+
+    pub fn frames(&self) -> &[SpriteFrame] {
+        &self.frames
+    }
+
+    /// The sum of the durations of every frame, in seconds
+    pub fn duration(&self) -> f32 {
+        self.frames.iter().map(|x| x.duration).sum()
+    }
+
+    /// Gets the index of the frame shown at the given time, which is the last
+    /// frame after the animation ends
+    pub fn frame_at(&self, mut time: f32) -> usize {
+        for (i, frame) in self.frames.iter().enumerate() {
+            if time < frame.duration {
+                return i;
+            }
+            time -= frame.duration;
+        }
+        self.frames.len() - 1
+    }
+}
+
+/// Decodes every frame of an animated GIF or APNG, and packs them into a grid on one image
+///
+/// Returns `None` if the image is a still image, or is in a format that cannot be animated
+pub(crate) fn decode_animated(
+    bytes: &[u8],
+) -> Option<Result<(RgbaImage, SpriteAnimation), String>> {
+    let frames = match image::guess_format(bytes).ok()? {
+        ImageFormat::Gif => {
+            GifDecoder::new(Cursor::new(bytes)).and_then(|x| x.into_frames().collect_frames())
+        }
+        ImageFormat::Png => {
+            let decoder = match PngDecoder::new(Cursor::new(bytes)) {
+                Ok(decoder) => decoder,
+                Err(e) => return Some(Err(e.to_string())),
+            };
+            if !decoder.is_apng() {
+                return None;
+            }
+            decoder.apng().into_frames().collect_frames()
+        }
+        _ => return None,
+    };
+    match frames {
+        // A single frame is loaded like any other image
+        Ok(frames) if frames.len() > 1 => Some(Ok(pack(frames))),
+        Ok(_) => None,
+        Err(e) => Some(Err(e.to_string())),
+    }
+}
+
+/// Places the frames left to right, then top to bottom, in a grid that is close to square
+/// so that long animations stay within the size limit of textures
+fn pack(frames: Vec<Frame>) -> (RgbaImage, SpriteAnimation) {
+    let (width, height) = frames[0].buffer().dimensions();
+    let columns = (frames.len() as f32).sqrt().ceil() as u32;
+    let rows = (frames.len() as u32).div_ceil(columns);
+    let mut sheet = RgbaImage::new(width * columns, height * rows);
+    let (sheet_width, sheet_height) = (sheet.width() as f32, sheet.height() as f32);
+
+    let frames = frames
+        .into_iter()
+        .enumerate()
+        .map(|(i, frame)| {
+            let x = i as u32 % columns * width;
+            let y = i as u32 / columns * height;
+            let (numerator, denominator) = frame.delay().numer_denom_ms();
+            let duration = numerator as f32 / denominator.max(1) as f32 / 1000.0;
+            imageops::replace(&mut sheet, frame.buffer(), x as i64, y as i64);
+            SpriteFrame {
+                min: Vector2::new(x as f32 / sheet_width, y as f32 / sheet_height),
+                max: Vector2::new(
+                    (x + width) as f32 / sheet_width,
+                    (y + height) as f32 / sheet_height,
+                ),
+                duration: if duration <= MIN_FRAME_DURATION {
+                    DEFAULT_FRAME_DURATION
+                } else {
+                    duration
+                },
+            }
+        })
+        .collect();
+    (sheet, SpriteAnimation::new(frames))
+}
+
+/// A quad centered on the origin, showing the given frame
+fn quad(half_size: Vector, frame: &SpriteFrame) -> VertexBuffers<[f32; 4], u32> {
+    let (min, max) = (frame.min, frame.max);
+    let mut geometry = VertexBuffers::new();
+    geometry.vertices.extend([
+        [-half_size.x, -half_size.y, min.x, max.y],
+        [half_size.x, -half_size.y, max.x, max.y],
+        [half_size.x, half_size.y, max.x, min.y],
+        [-half_size.x, half_size.y, min.x, min.y],
+    ]);
+    geometry.indices.extend([0, 1, 2, 0, 2, 3]);
+    geometry
+}
+
+/// A quad that shows the frames of an animation one after the other
+///
+/// The frames are sampled from the texture the sprite was created with, so animations
+/// in pixel art should be loaded with `SamplerOptions::PIXEL_ART` to keep neighboring
+/// frames from bleeding into each other
+pub struct AnimatedSprite {
+    polygon: Polygon,
+    animation: Arc<SpriteAnimation>,
+    half_size: Vector,
+    /// How many seconds of the animation play every second, which can be 0 to pause it
+    speed: NumberField<f32>,
+    looping: bool,
+    /// Seconds since the animation started
+    time: AtomicF32,
+    /// The frame the polygon is showing
+    frame: AtomicUsize,
+}
+
+impl AnimatedSprite {
+    /// Creates a `size` large sprite that plays the animation of `texture`, if it was loaded
+    /// from an animated image. Any other texture is shown whole, as a single frame
+    pub fn new(graphics: &Graphics, texture: &TextureAsset, size: Vector) -> Self {
+        let animation = texture
+            .animation()
+            .cloned()
+            .unwrap_or_else(|| Arc::new(SpriteAnimation::still()));
+        Self::from_animation(
+            graphics,
+            Material::Texture(texture.get(graphics)),
+            animation,
+            size,
+        )
+    }
+
+    /// Creates a `size` large sprite that plays the given frames of `material`, such as
+    /// the frames of a sprite sheet
+    pub fn from_animation(
+        graphics: &Graphics,
+        material: Material,
+        animation: Arc<SpriteAnimation>,
+        size: Vector,
+    ) -> Self {
+        let half_size = Vector::new(size.x / 2.0, size.y / 2.0);
+        Self {
+            polygon: Polygon::from_geometry(
+                graphics,
+                quad(half_size, &animation.frames()[0]),
+                material,
+            ),
+            animation,
+            half_size,
+            speed: NumberField::new(1.0),
+            looping: true,
+            time: AtomicF32::new(0.0),
+            frame: AtomicUsize::new(0),
+        }
+    }
+
+    /// Sets whether the animation starts over after the last frame,
+    /// instead of stopping on it. Animations loop by default
+    pub fn with_looping(mut self, looping: bool) -> Self {
+        self.looping = looping;
+        self
+    }
+
+    pub fn with_speed(mut self, speed: f32) -> Self {
+        self.speed.set_inner(speed);
+        self
+    }
+}
+
+impl Component for AnimatedSprite {
+    type Reference<'a> = AnimatedSpriteRef<'a>;
+
+    fn get_ref<'a>(&'a self) -> Self::Reference<'a> {
+        AnimatedSpriteRef {
+            polygon: self.polygon.get_ref(),
+            speed: self.speed.get_ref(),
+            animation: &self.animation,
+            half_size: self.half_size,
+            looping: self.looping,
+            time: &self.time,
+            frame: &self.frame,
+        }
+    }
+
+    fn flush<E: bina_ecs::entity::Entity>(
+        &mut self,
+        my_entity: bina_ecs::entity::EntityReference<bina_ecs::entity::Inaccessible<E>>,
+        universe: &bina_ecs::universe::Universe,
+    ) {
+        self.speed.process_modifiers();
+        self.polygon.flush(my_entity, universe);
+    }
+}
+
+impl Reflect for AnimatedSprite {
+    fn reflect_fields(&self, visitor: &mut dyn FnMut(&'static str, Field<'_>)) {
+        visitor("speed", Field::Number(&self.speed));
+        self.polygon.reflect_fields(visitor);
+    }
+}
+
+impl Processable for AnimatedSprite {
+    fn process<E: bina_ecs::entity::Entity>(
+        component: Self::Reference<'_>,
+        my_entity: bina_ecs::entity::EntityReference<E>,
+        universe: &bina_ecs::universe::Universe,
+    ) {
+        let duration = component.animation.duration();
+        let mut time =
+            component.time.load(Ordering::Relaxed) + universe.get_delta() * *component.speed;
+        time = if component.looping && duration.is_finite() && duration > 0.0 {
+            time.rem_euclid(duration)
+        } else {
+            time.clamp(0.0, duration)
+        };
+        component.time.store(time, Ordering::Relaxed);
+
+        let frame = component.animation.frame_at(time);
+        if component.frame.swap(frame, Ordering::Relaxed) != frame {
+            component.polygon.queue_set_geometry(quad(
+                component.half_size,
+                &component.animation.frames()[frame],
+            ));
+        }
+        Polygon::process(component.polygon, my_entity, universe);
+    }
+}
+
+pub struct AnimatedSpriteRef<'a> {
+    /// The polygon that the frames are drawn on, for moving it or changing its layers
+    pub polygon: PolygonRef<'a>,
+    pub speed: NumberFieldRef<'a, f32>,
+    animation: &'a Arc<SpriteAnimation>,
+    half_size: Vector,
+    looping: bool,
+    time: &'a AtomicF32,
+    frame: &'a AtomicUsize,
+}
+
+impl<'a> AnimatedSpriteRef<'a> {
+    pub fn animation(&self) -> &SpriteAnimation {
+        self.animation
+    }
+
+    /// Gets the index of the frame being shown
+    pub fn frame(&self) -> usize {
+        self.frame.load(Ordering::Relaxed)
+    }
+
+    /// Gets how many seconds into the animation it is
+    pub fn time(&self) -> f32 {
+        self.time.load(Ordering::Relaxed)
+    }
+
+    /// Whether the animation does not loop and has reached its last frame
+    pub fn is_finished(&self) -> bool {
+        !self.looping && self.time() >= self.animation.duration()
+    }
+
+    /// Plays the animation from its first frame
+    pub fn restart(&self) {
+        self.time.store(0.0, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,168 @@
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use bina_ecs::{crossbeam::atomic::AtomicCell, parking_lot::Mutex, triomphe::Arc};
+use wgpu::{Buffer, BufferUsages, CommandEncoder, Device, QuerySet, Queue};
+
+/// How long the GPU took to execute part of a frame
+#[derive(Clone, Copy, Debug)]
+pub struct GpuSpan {
+    pub name: &'static str,
+    pub duration: Duration,
+}
+
+/// Statistics about the last frame presented by the render thread
+#[derive(Default)]
+pub(crate) struct RenderStats {
+    pub(crate) draw_calls: AtomicUsize,
+    /// Only available if the adapter supports timestamp queries
+    pub(crate) gpu_spans: Mutex<Vec<GpuSpan>>,
+}
+
+impl RenderStats {
+    pub(crate) fn draw_calls(&self) -> usize {
+        self.draw_calls.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn gpu_spans(&self) -> Vec<GpuSpan> {
+        self.gpu_spans.lock().clone()
+    }
+}
+
+/// Measures how long the GPU takes to execute each part of a frame
+///
+/// Reading the measurements back requires mapping a buffer, so only one frame
+/// is measured at a time and the frames in between are not timed
+pub(crate) struct GpuProfiler {
+    query_set: QuerySet,
+    resolve_buffer: Buffer,
+    read_buffer: Buffer,
+    /// Nanoseconds per timestamp tick
+    period: f32,
+    /// Set by the mapping callback to whether the mapping succeeded
+    mapped: Arc<AtomicCell<Option<bool>>>,
+    /// The names of the spans being measured, in order
+    spans: Vec<&'static str>,
+    in_flight: bool,
+    timing: bool,
+}
+
+/// The maximum number of timestamps written in a frame
+const MAX_TIMESTAMPS: u32 = 8;
+const TIMESTAMPS_SIZE: u64 = MAX_TIMESTAMPS as u64 * std::mem::size_of::<u64>() as u64;
+
+impl GpuProfiler {
+    /// Returns `None` if the device was not created with `Features::TIMESTAMP_QUERY`
+    pub(crate) fn new(device: &Device, queue: &Queue) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+        Some(Self {
+            query_set: device.create_query_set(&wgpu::QuerySetDescriptor {
+                label: Some("gpu_profiler_query_set"),
+                ty: wgpu::QueryType::Timestamp,
+                count: MAX_TIMESTAMPS,
+            }),
+            resolve_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("gpu_profiler_resolve_buffer"),
+                size: TIMESTAMPS_SIZE,
+                usage: BufferUsages::QUERY_RESOLVE | BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            }),
+            read_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("gpu_profiler_read_buffer"),
+                size: TIMESTAMPS_SIZE,
+                usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            period: queue.get_timestamp_period(),
+            mapped: Default::default(),
+            spans: Vec::new(),
+            in_flight: false,
+            timing: false,
+        })
+    }
+
+    /// Checks if the last measurements have been read back, returning them if so
+    ///
+    /// Must be called before `begin`
+    pub(crate) fn poll(&mut self, device: &Device) -> Option<Vec<GpuSpan>> {
+        if !self.in_flight {
+            return None;
+        }
+        device.poll(wgpu::Maintain::Poll);
+
+        match self.mapped.take()? {
+            true => {
+                let slice = self.read_buffer.slice(..);
+                let timestamps: [u64; MAX_TIMESTAMPS as usize] = {
+                    let view = slice.get_mapped_range();
+                    bytemuck::pod_read_unaligned(&view)
+                };
+                self.read_buffer.unmap();
+                self.in_flight = false;
+                Some(
+                    self.spans
+                        .iter()
+                        .zip(timestamps.windows(2))
+                        .map(|(name, window)| {
+                            let ticks = window[1].saturating_sub(window[0]);
+                            GpuSpan {
+                                name,
+                                duration: Duration::from_nanos((ticks as f64 * self.period as f64) as u64),
+                            }
+                        })
+                        .collect(),
+                )
+            }
+            false => {
+                self.in_flight = false;
+                None
+            }
+        }
+    }
+
+    /// Marks the start of a frame, if the last measurements have been read back
+    pub(crate) fn begin(&mut self, encoder: &mut CommandEncoder) {
+        self.timing = !self.in_flight;
+        if self.timing {
+            self.spans.clear();
+            encoder.write_timestamp(&self.query_set, 0);
+        }
+    }
+
+    /// Marks the end of a span that started at the end of the last span,
+    /// or at the start of the frame
+    pub(crate) fn end_span(&mut self, encoder: &mut CommandEncoder, name: &'static str) {
+        if !self.timing || self.spans.len() + 1 >= MAX_TIMESTAMPS as usize {
+            return;
+        }
+        self.spans.push(name);
+        encoder.write_timestamp(&self.query_set, self.spans.len() as u32);
+    }
+
+    /// Marks the end of a frame
+    pub(crate) fn end(&mut self, encoder: &mut CommandEncoder) {
+        if !self.timing {
+            return;
+        }
+        let count = self.spans.len() as u32 + 1;
+        encoder.resolve_query_set(&self.query_set, 0..count, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(&self.resolve_buffer, 0, &self.read_buffer, 0, TIMESTAMPS_SIZE);
+    }
+
+    /// Must be called after the encoder given to `end` is submitted
+    pub(crate) fn submitted(&mut self) {
+        if !self.timing {
+            return;
+        }
+        self.timing = false;
+        self.in_flight = true;
+        let mapped = self.mapped.clone();
+        self.read_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| mapped.store(Some(result.is_ok())));
+    }
+}
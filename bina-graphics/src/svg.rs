@@ -0,0 +1,112 @@
+//! Turning SVG images into polygons
+//!
+//! Every fill and stroke becomes a polygon of a single color, so gradients are drawn with
+//! the average color of their stops. Images with patterns or embedded images fail to load,
+//! clip paths, masks, and filters are not applied, and text is not drawn
+use std::sync::OnceLock;
+
+use bina_ecs::assets::AssetLoader;
+
+pub use bina_svg::SvgShape;
+use bina_svg::{parse_svg, ParsedSvg};
+
+use crate::{
+    polygon::{Material, Polygon},
+    Graphics,
+};
+
+/// An SVG image that has been tessellated into shapes
+///
+/// The y axis is flipped so that the image is upright, with the top left
+/// corner of the image at the origin and one SVG unit per world unit
+pub struct SvgAsset {
+    shapes: Vec<SvgShape>,
+    width: f32,
+    height: f32,
+}
+
+impl SvgAsset {
+    pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+        let ParsedSvg {
+            shapes,
+            width,
+            height,
+        } = parse_svg(bytes)?;
+        Ok(Self {
+            shapes,
+            width,
+            height,
+        })
+    }
+
+    /// Gets every fill and stroke, in the order they should be drawn
+    pub fn shapes(&self) -> &[SvgShape] {
+        &self.shapes
+    }
+
+    /// Gets the width and height of the image, which is what its `viewBox` is scaled to fit
+    pub fn size(&self) -> (f32, f32) {
+        (self.width, self.height)
+    }
+
+    /// Creates a polygon for every fill and stroke, in the order they should be drawn
+    ///
+    /// Later polygons should be given a greater `z` so that they are drawn on top
+    pub fn polygons(&self, graphics: &Graphics) -> Vec<Polygon> {
+        self.shapes
+            .iter()
+            .map(|shape| {
+                Polygon::from_geometry(
+                    graphics,
+                    shape.geometry.clone(),
+                    Material::FlatColor(shape.color),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Loads SVG images through `Assets`
+pub struct SvgLoader;
+
+impl AssetLoader for SvgLoader {
+    type Asset = SvgAsset;
+
+    fn load(&self, bytes: Vec<u8>) -> Result<Self::Asset, String> {
+        SvgAsset::parse(&bytes)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["svg"]
+    }
+}
+
+/// An SVG image compiled into the binary by `load_svg!`, which is tessellated the first time it is used
+pub struct SvgResource {
+    path: &'static str,
+    bytes: &'static [u8],
+    parsed: OnceLock<Result<SvgAsset, String>>,
+}
+
+impl SvgResource {
+    pub const fn new(path: &'static str, bytes: &'static [u8]) -> Self {
+        Self {
+            path,
+            bytes,
+            parsed: OnceLock::new(),
+        }
+    }
+
+    /// Gets the tessellated image, or the reason it could not be parsed
+    pub fn get(&self) -> Result<&SvgAsset, &str> {
+        self.parsed
+            .get_or_init(|| {
+                SvgAsset::parse(self.bytes).map_err(|e| {
+                    log::error!("Failed to load {}: {e}", self.path);
+                    e
+                })
+            })
+            .as_ref()
+            .map_err(String::as_str)
+    }
+}
@@ -0,0 +1,484 @@
+//! Terrain that can be carved away and built up while the game runs, such as by explosions and digging
+//!
+//! The terrain is a grid of densities, where anything denser than one half is solid. It is split
+//! into chunks that each have their own polygon, so an edit only rebuilds the chunks it touched.
+//! The chunks are rebuilt on other threads, and are replaced during the first flush after they are ready
+//!
+//! ```ignore
+//! let terrain = Terrain2D::new(graphics, 256, 128, 0.25, || Material::Texture(dirt.clone()))
+//!     .with_edit(TerrainEdit::Fill(Shape::rect(Vector::new(0.0, 0.0), Vector::new(64.0, 20.0))));
+//! universe.queue_add_entity((terrain,));
+//!
+//! // Later, when something explodes
+//! terrain.queue_edit(TerrainEdit::Carve(Shape::Circle { center, radius: 3.0 }));
+//! ```
+use bina_ecs::{
+    component::{
+        Component, ComponentField, NumberField, NumberFieldRef, Processable, StagedMutField,
+        StagedMutFieldRef,
+    },
+    reflect::{Field, Reflect},
+};
+use lyon::lyon_tessellation::VertexBuffers;
+
+use crate::{
+    layers::Visible,
+    polygon::{Aabb, Material, Polygon, Vector},
+    Graphics,
+};
+
+/// How many cells wide and tall each chunk is
+const CHUNK_CELLS: usize = 32;
+
+/// A shape that is carved out of terrain or filled in, in the local space of the terrain
+#[derive(Clone)]
+pub enum Shape {
+    Circle {
+        center: Vector,
+        radius: f32,
+    },
+    /// A closed loop of vertices, in either winding
+    Polygon(Vec<Vector>),
+}
+
+impl Shape {
+    /// A rectangle between two opposite corners
+    pub fn rect(min: Vector, max: Vector) -> Self {
+        Self::Polygon(vec![
+            min,
+            Vector::new(max.x, min.y),
+            max,
+            Vector::new(min.x, max.y),
+        ])
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        match self {
+            Self::Circle { center, radius } => Some(Aabb::new(
+                Vector::new(center.x - radius, center.y - radius),
+                Vector::new(center.x + radius, center.y + radius),
+            )),
+            Self::Polygon(vertices) => Aabb::from_points(vertices.iter().copied()),
+        }
+    }
+
+    /// Gets the distance from the edge of this shape, which is negative inside of it
+    fn signed_distance(&self, point: Vector) -> f32 {
+        match self {
+            Self::Circle { center, radius } => (point - *center).length() - radius,
+            Self::Polygon(vertices) => {
+                let mut inside = false;
+                let mut distance = f32::INFINITY;
+                for (i, &a) in vertices.iter().enumerate() {
+                    let b = vertices[(i + 1) % vertices.len()];
+                    if (a.y > point.y) != (b.y > point.y)
+                        && point.x < a.x + (point.y - a.y) / (b.y - a.y) * (b.x - a.x)
+                    {
+                        inside = !inside;
+                    }
+                    let edge = b - a;
+                    let t = ((point - a).dot(*edge) / edge.square_length()).clamp(0.0, 1.0);
+                    if t.is_finite() {
+                        distance = distance.min((point - (a + edge * t)).length());
+                    } else {
+                        distance = distance.min((point - a).length());
+                    }
+                }
+                if inside {
+                    -distance
+                } else {
+                    distance
+                }
+            }
+        }
+    }
+}
+
+/// A change to the shape of terrain
+#[derive(Clone)]
+pub enum TerrainEdit {
+    /// Removes the terrain inside the shape, such as for an explosion
+    Carve(Shape),
+    /// Adds terrain inside the shape, such as for building
+    Fill(Shape),
+}
+
+/// The densities of a `Terrain2D`, which are read when it is processed and edited when it is flushed
+pub struct TerrainGrid {
+    /// How many cells wide and tall the grid is, which is one less than the number of corners
+    cells: [usize; 2],
+    cell_size: f32,
+    /// The density of every corner from 0 to 1, row by row from the bottom
+    densities: Vec<f32>,
+    /// Whether each chunk has changed since it was last rebuilt, row by row from the bottom
+    dirty: Vec<bool>,
+}
+
+impl TerrainGrid {
+    fn new(cells: [usize; 2], cell_size: f32) -> Self {
+        Self {
+            cells,
+            cell_size,
+            densities: vec![0.0; (cells[0] + 1) * (cells[1] + 1)],
+            dirty: vec![false; chunk_count(cells[0]) * chunk_count(cells[1])],
+        }
+    }
+
+    /// The width and height of the terrain in its local space
+    pub fn size(&self) -> Vector {
+        Vector::new(
+            self.cells[0] as f32 * self.cell_size,
+            self.cells[1] as f32 * self.cell_size,
+        )
+    }
+
+    pub fn cell_size(&self) -> f32 {
+        self.cell_size
+    }
+
+    fn corner(&self, x: usize, y: usize) -> f32 {
+        self.densities[y * (self.cells[0] + 1) + x]
+    }
+
+    /// Gets how dense the terrain is at a point in its local space, from 0 to 1,
+    /// which is 0 outside of the terrain
+    pub fn density(&self, point: Vector) -> f32 {
+        let x = point.x / self.cell_size;
+        let y = point.y / self.cell_size;
+        if !(x >= 0.0 && y >= 0.0 && x <= self.cells[0] as f32 && y <= self.cells[1] as f32) {
+            return 0.0;
+        }
+        let (x0, y0) = (
+            (x as usize).min(self.cells[0].saturating_sub(1)),
+            (y as usize).min(self.cells[1].saturating_sub(1)),
+        );
+        let (tx, ty) = (x - x0 as f32, y - y0 as f32);
+        let bottom = lerp(self.corner(x0, y0), self.corner(x0 + 1, y0), tx);
+        let top = lerp(self.corner(x0, y0 + 1), self.corner(x0 + 1, y0 + 1), tx);
+        lerp(bottom, top, ty)
+    }
+
+    /// Checks if a point in the local space of the terrain is inside of it
+    pub fn is_solid(&self, point: Vector) -> bool {
+        self.density(point) > 0.5
+    }
+
+    /// Changes the densities under the shape of the edit, and marks the chunks it touched
+    fn apply(&mut self, edit: &TerrainEdit) {
+        let (TerrainEdit::Carve(shape) | TerrainEdit::Fill(shape)) = edit;
+        let Some(bounds) = shape.bounds() else {
+            return;
+        };
+        // One more cell on every side, as the edges of the shape are blended over a cell
+        let to_corner = |v: f32, max: usize| {
+            ((v / self.cell_size).floor() as isize).clamp(0, max as isize) as usize
+        };
+        let (min_x, max_x) = (
+            to_corner(bounds.min.x - self.cell_size, self.cells[0]),
+            to_corner(bounds.max.x + self.cell_size * 2.0, self.cells[0]),
+        );
+        let (min_y, max_y) = (
+            to_corner(bounds.min.y - self.cell_size, self.cells[1]),
+            to_corner(bounds.max.y + self.cell_size * 2.0, self.cells[1]),
+        );
+        let mut changed = false;
+        for y in min_y..=max_y {
+            for x in min_x..=max_x {
+                let point = Vector::new(x as f32 * self.cell_size, y as f32 * self.cell_size);
+                // Blending the edges keeps the outline smooth instead of following the grid
+                let coverage =
+                    (0.5 - shape.signed_distance(point) / self.cell_size).clamp(0.0, 1.0);
+                let density = &mut self.densities[y * (self.cells[0] + 1) + x];
+                let new = match edit {
+                    TerrainEdit::Carve(_) => density.min(1.0 - coverage),
+                    TerrainEdit::Fill(_) => density.max(coverage),
+                };
+                changed |= new != *density;
+                *density = new;
+            }
+        }
+        if !changed {
+            return;
+        }
+        // A corner on the border of two chunks belongs to both
+        let chunks_x = chunk_count(self.cells[0]);
+        let chunk_of = |corner: usize| corner.saturating_sub(1) / CHUNK_CELLS;
+        for y in chunk_of(min_y)..=(max_y / CHUNK_CELLS).min(chunk_count(self.cells[1]) - 1) {
+            for x in chunk_of(min_x)..=(max_x / CHUNK_CELLS).min(chunks_x - 1) {
+                self.dirty[y * chunks_x + x] = true;
+            }
+        }
+    }
+
+    /// Copies the corners of a chunk, so that it can be rebuilt on another thread
+    fn snapshot(&self, chunk: usize) -> ChunkSnapshot {
+        let chunks_x = chunk_count(self.cells[0]);
+        let start = [
+            (chunk % chunks_x) * CHUNK_CELLS,
+            (chunk / chunks_x) * CHUNK_CELLS,
+        ];
+        let cells = [
+            CHUNK_CELLS.min(self.cells[0] - start[0]),
+            CHUNK_CELLS.min(self.cells[1] - start[1]),
+        ];
+        let mut densities = Vec::with_capacity((cells[0] + 1) * (cells[1] + 1));
+        for y in start[1]..=start[1] + cells[1] {
+            for x in start[0]..=start[0] + cells[0] {
+                densities.push(self.corner(x, y));
+            }
+        }
+        ChunkSnapshot {
+            start,
+            cells,
+            cell_size: self.cell_size,
+            size: self.size(),
+            densities,
+        }
+    }
+}
+
+fn chunk_count(cells: usize) -> usize {
+    cells.div_ceil(CHUNK_CELLS).max(1)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+struct ChunkSnapshot {
+    /// The first cell of the chunk in the grid
+    start: [usize; 2],
+    cells: [usize; 2],
+    cell_size: f32,
+    /// The size of the whole terrain, which the texture is stretched over
+    size: Vector,
+    densities: Vec<f32>,
+}
+
+impl ChunkSnapshot {
+    /// Finds the solid part of every cell with marching squares, and triangulates it
+    fn tessellate(&self) -> VertexBuffers<[f32; 4], u32> {
+        let mut geometry = VertexBuffers::new();
+        let stride = self.cells[0] + 1;
+        let mut outline = Vec::with_capacity(8);
+        for y in 0..self.cells[1] {
+            for x in 0..self.cells[0] {
+                // Counterclockwise from the bottom left
+                let corners = [(x, y), (x + 1, y), (x + 1, y + 1), (x, y + 1)].map(|(cx, cy)| {
+                    let position = Vector::new(
+                        (self.start[0] + cx) as f32 * self.cell_size,
+                        (self.start[1] + cy) as f32 * self.cell_size,
+                    );
+                    (position, self.densities[cy * stride + cx])
+                });
+                // Each corner that is solid is kept, and each edge that crosses the
+                // surface is cut where the density is one half
+                outline.clear();
+                for i in 0..4 {
+                    let (a, a_density) = corners[i];
+                    let (b, b_density) = corners[(i + 1) % 4];
+                    if a_density > 0.5 {
+                        outline.push(a);
+                    }
+                    if (a_density > 0.5) != (b_density > 0.5) {
+                        let t = (0.5 - a_density) / (b_density - a_density);
+                        outline.push(a + (b - a) * t);
+                    }
+                }
+                if outline.len() < 3 {
+                    continue;
+                }
+                let first = geometry.vertices.len() as u32;
+                for v in &outline {
+                    geometry
+                        .vertices
+                        .push([v.x, v.y, v.x / self.size.x, 1.0 - v.y / self.size.y]);
+                }
+                for i in 1..outline.len() as u32 - 1 {
+                    geometry.indices.extend([first, first + i, first + i + 1]);
+                }
+            }
+        }
+        geometry
+    }
+}
+
+/// Terrain that can be carved and filled while the game runs
+///
+/// Positions given to the terrain are in its local space, where the origin is its bottom left corner.
+/// If the entity has a `Transform`, the terrain is drawn relative to it
+pub struct Terrain2D {
+    grid: StagedMutField<TerrainGrid>,
+    /// One for every chunk, in the same order as the grid
+    chunks: Vec<Polygon>,
+    origin: NumberField<Vector>,
+    z: NumberField<u32>,
+    visible: NumberField<Visible>,
+}
+
+impl Terrain2D {
+    /// Creates empty terrain that is `width` by `height` cells, where `material` is called for every chunk
+    ///
+    /// The texture of the material is stretched over the whole terrain
+    ///
+    /// # Panics
+    /// Panics if the terrain has no cells, or if `cell_size` is not positive
+    pub fn new(
+        graphics: &Graphics,
+        width: usize,
+        height: usize,
+        cell_size: f32,
+        mut material: impl FnMut() -> Material,
+    ) -> Self {
+        assert!(
+            width > 0 && height > 0,
+            "Terrain must be at least one cell wide and tall, not {width}x{height}"
+        );
+        assert!(cell_size > 0.0, "The cells of terrain must have a positive size, not {cell_size}");
+        let grid = TerrainGrid::new([width, height], cell_size);
+        let chunks = (0..grid.dirty.len())
+            .map(|i| {
+                Polygon::from_geometry(graphics, VertexBuffers::new(), material())
+                    .with_name(format!("Terrain chunk {i}"))
+            })
+            .collect();
+        Self {
+            grid: StagedMutField::new(grid),
+            chunks,
+            origin: NumberField::new(Vector::new(0.0, 0.0)),
+            z: NumberField::new(0),
+            visible: NumberField::new(Visible::default()),
+        }
+    }
+
+    /// Changes the shape of the terrain before it is added, such as to give it the ground it starts with
+    pub fn with_edit(mut self, edit: TerrainEdit) -> Self {
+        self.grid.get_inner_mut().apply(&edit);
+        self.rebuild();
+        self
+    }
+
+    pub fn with_origin(mut self, origin: Vector) -> Self {
+        self.origin.set_inner(origin);
+        self.place();
+        self
+    }
+
+    pub fn with_z(mut self, z: u32) -> Self {
+        self.z.set_inner(z);
+        self.place();
+        self
+    }
+
+    pub fn grid(&self) -> &TerrainGrid {
+        self.grid.get_inner()
+    }
+
+    /// Rebuilds every chunk that changed on another thread
+    fn rebuild(&mut self) {
+        let grid = self.grid.get_inner_mut();
+        for i in 0..grid.dirty.len() {
+            if !std::mem::take(&mut grid.dirty[i]) {
+                continue;
+            }
+            let snapshot = grid.snapshot(i);
+            self.chunks[i]
+                .get_ref()
+                .queue_geometry_with(move || snapshot.tessellate());
+        }
+    }
+
+    /// Moves every chunk onto the terrain
+    fn place(&mut self) {
+        let origin = self.origin.get_inner();
+        let z = self.z.get_inner();
+        let visible = self.visible.get_inner();
+        for polygon in &mut self.chunks {
+            polygon.stage_transform(origin, 0.0, Vector::new(1.0, 1.0));
+            polygon.set_z(z);
+            polygon.set_visible(visible);
+        }
+    }
+}
+
+impl Component for Terrain2D {
+    type Reference<'a> = Terrain2DRef<'a>;
+
+    fn get_ref<'a>(&'a self) -> Self::Reference<'a> {
+        Terrain2DRef {
+            grid: self.grid.get_ref(),
+            chunks: &self.chunks,
+            origin: self.origin.get_ref(),
+            z: self.z.get_ref(),
+            visible: self.visible.get_ref(),
+        }
+    }
+
+    fn flush<E: bina_ecs::entity::Entity>(
+        &mut self,
+        my_entity: bina_ecs::entity::EntityReference<bina_ecs::entity::Inaccessible<E>>,
+        universe: &bina_ecs::universe::Universe,
+    ) {
+        self.origin.process_modifiers();
+        self.z.process_modifiers();
+        self.visible.process_modifiers();
+        self.grid.process_modifiers();
+
+        self.rebuild();
+        self.place();
+        for polygon in &mut self.chunks {
+            polygon.flush(my_entity.clone(), universe);
+        }
+    }
+}
+
+impl Reflect for Terrain2D {
+    fn reflect_fields(&self, visitor: &mut dyn FnMut(&'static str, Field<'_>)) {
+        visitor("origin", Field::Number(&self.origin));
+        visitor("z", Field::Number(&self.z));
+        visitor("visible", Field::Debug(&self.visible));
+        visitor("chunks", Field::Debug(&self.chunks.len()));
+    }
+}
+
+impl Processable for Terrain2D {
+    fn process<E: bina_ecs::entity::Entity>(
+        component: Self::Reference<'_>,
+        my_entity: bina_ecs::entity::EntityReference<E>,
+        universe: &bina_ecs::universe::Universe,
+    ) {
+        for polygon in component.chunks {
+            Polygon::process(polygon.get_ref(), my_entity.clone(), universe);
+        }
+    }
+}
+
+pub struct Terrain2DRef<'a> {
+    grid: StagedMutFieldRef<'a, TerrainGrid>,
+    chunks: &'a [Polygon],
+    pub origin: NumberFieldRef<'a, Vector>,
+    pub z: NumberFieldRef<'a, u32>,
+    pub visible: NumberFieldRef<'a, Visible>,
+}
+
+impl<'a> Terrain2DRef<'a> {
+    /// The densities of the terrain as of the last flush, for checking what is solid
+    pub fn grid(&self) -> &TerrainGrid {
+        &self.grid
+    }
+
+    /// Checks if a point in the world is inside the terrain, as of the last flush
+    ///
+    /// If the entity has a `Transform`, the point must be in the space of that transform instead
+    pub fn is_solid(&self, point: Vector) -> bool {
+        self.grid.is_solid(point - *self.origin)
+    }
+
+    /// Changes the shape of the terrain after the current process frame ends
+    ///
+    /// The shape is in the local space of the terrain. The chunks it touches are drawn
+    /// with their new shape once they have been rebuilt, usually a frame or two later
+    pub fn queue_edit(&self, edit: TerrainEdit) {
+        self.grid.queue_modifier(move |grid| grid.apply(&edit));
+    }
+}
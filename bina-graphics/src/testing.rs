@@ -0,0 +1,189 @@
+//! Drawing the frames of a `TestUniverse` without a window, and comparing them against golden images
+//!
+//! ```ignore
+//! let mut test = TestUniverse::new();
+//! HeadlessRenderer::new(test.universe(), PhysicalSize::new(320, 240), ScalingMode::Expand)
+//!     .await
+//!     .attach(&mut test);
+//! test.universe().queue_add_entity((ship,));
+//! test.run(10);
+//! let frame = capture_frame(&mut test).unwrap();
+//! assert_golden(&frame, "tests/golden/ship.png", 0.001);
+//! ```
+use std::path::Path;
+
+use bina_ecs::{
+    pacing::FramePacer,
+    testing::TestUniverse,
+    time::Duration,
+    triomphe::Arc,
+    universe::Universe,
+};
+use image::RgbaImage;
+use winit::dpi::PhysicalSize;
+
+use crate::{capture, new_instance, Graphics, GraphicsInner, RenderChannels, RenderState, RenderTarget, ScalingMode};
+
+/// How far apart the channels of two pixels can be before they count as different,
+/// as GPUs do not all round colors the same way
+const CHANNEL_TOLERANCE: u8 = 2;
+
+/// Set to update every golden image to match what was rendered, instead of comparing against it
+const UPDATE_GOLDEN_VAR: &str = "BINA_UPDATE_GOLDEN";
+
+/// Draws the frames of a Universe into an off-screen texture, on whichever thread calls `render_frame`
+///
+/// This is what `Graphics::run_headless` runs with. Unlike `run_headless`, the Universe
+/// is not run by the renderer, so a test can run frames one at a time
+pub struct HeadlessRenderer {
+    graphics: Arc<GraphicsInner>,
+    render_state: RenderState,
+    view: wgpu::TextureView,
+    channels: RenderChannels,
+    pacer: Arc<FramePacer>,
+    size: PhysicalSize<u32>,
+}
+
+impl HeadlessRenderer {
+    /// Creates a device and queues a `Graphics` singleton into `universe` that draws with it
+    pub async fn new(universe: &Universe, size: PhysicalSize<u32>, scaling_mode: ScalingMode) -> Self {
+        let instance = new_instance();
+        let (graphics, render_state) = GraphicsInner::new_headless(&instance, size).await;
+        let graphics = Arc::new(graphics);
+        let RenderTarget::Texture(texture) = &graphics.target else {
+            unreachable!()
+        };
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let (universe_graphics, channels) = Graphics::new(graphics.clone(), scaling_mode);
+        universe.queue_set_singleton(universe_graphics);
+        Self {
+            graphics,
+            render_state,
+            view,
+            channels,
+            pacer: universe.frame_pacer().clone(),
+            size,
+        }
+    }
+
+    /// Draws the next frame that the Universe sends, waiting up to `timeout` for it,
+    /// and returns whether a frame was drawn
+    ///
+    /// Every capture queued with `Graphics::queue_capture` is given the frame
+    pub fn render_frame(&mut self, timeout: Duration) -> bool {
+        // There is no window to change
+        while self.channels.window_commands.pop().is_some() {}
+        let Some(mut instructions) = self.channels.instructions.take_filled_timeout(timeout) else {
+            return false;
+        };
+
+        self.render_state.render(
+            &self.graphics,
+            &self.view,
+            &mut instructions,
+            &self.channels.render_stats,
+            |_, _, _| Vec::new(),
+        );
+        self.channels.latency.frame_presented();
+        self.pacer.frame_presented();
+        self.channels.return_instructions(instructions);
+
+        if self.channels.captures.is_empty() {
+            return true;
+        }
+        let RenderTarget::Texture(texture) = &self.graphics.target else {
+            unreachable!()
+        };
+        match capture::read_texture(&self.graphics.device, &self.graphics.queue, texture, self.size) {
+            Some(image) => {
+                while let Some(callback) = self.channels.captures.pop() {
+                    callback(image.clone());
+                }
+            }
+            None => while self.channels.captures.pop().is_some() {},
+        }
+        true
+    }
+
+    /// Draws every frame of `test` as soon as it has run
+    ///
+    /// The Universe is never more than one frame ahead of the renderer, so
+    /// running frames without drawing them would block the Universe
+    pub fn attach(mut self, test: &mut TestUniverse) {
+        test.after_frame(move |_| {
+            self.render_frame(Duration::ZERO);
+        });
+    }
+}
+
+/// Runs one frame of `test` and gives what was drawn, or `None` if nothing was drawn
+///
+/// A `HeadlessRenderer` must be attached to `test`, and its `Graphics` singleton must have been added
+pub fn capture_frame(test: &mut TestUniverse) -> Option<RgbaImage> {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    test.singleton::<Graphics>().queue_capture(move |image| {
+        let _ = sender.send(image);
+    });
+    test.run(1);
+    receiver.try_recv().ok()
+}
+
+/// Gets the fraction of pixels that differ between two images, or `None` if they are not the same size
+pub fn image_difference(a: &RgbaImage, b: &RgbaImage) -> Option<f32> {
+    if a.dimensions() != b.dimensions() {
+        return None;
+    }
+    let different = a
+        .pixels()
+        .zip(b.pixels())
+        .filter(|(a, b)| {
+            a.0.iter()
+                .zip(b.0)
+                .any(|(a, b)| a.abs_diff(b) > CHANNEL_TOLERANCE)
+        })
+        .count();
+    Some(different as f32 / (a.width() * a.height()).max(1) as f32)
+}
+
+/// Compares `image` against the golden image at `path`, allowing up to `tolerance` of the pixels to differ
+///
+/// The golden image is written instead if it does not exist, or if the `BINA_UPDATE_GOLDEN`
+/// environment variable is set. When the images differ, what was rendered is written
+/// next to the golden image with `.actual.png` appended, to compare them by eye
+///
+/// # Panics
+/// Panics if the images differ or the golden image cannot be read or written
+pub fn assert_golden(image: &RgbaImage, path: impl AsRef<Path>, tolerance: f32) {
+    let path = path.as_ref();
+    if !path.exists() || std::env::var_os(UPDATE_GOLDEN_VAR).is_some() {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).expect("Golden image directory should be writable");
+        }
+        image.save(path).expect("Golden image should be writable");
+        return;
+    }
+    let golden = image::open(path)
+        .unwrap_or_else(|e| panic!("Failed to read golden image {}: {e}", path.display()))
+        .into_rgba8();
+    let difference = image_difference(image, &golden);
+    if difference.is_some_and(|x| x <= tolerance) {
+        return;
+    }
+    let mut actual = path.as_os_str().to_owned();
+    actual.push(".actual.png");
+    let _ = image.save(&actual);
+    match difference {
+        Some(difference) => panic!(
+            "{:.2}% of pixels differ from {}, which is more than {:.2}%",
+            difference * 100.0,
+            path.display(),
+            tolerance * 100.0
+        ),
+        None => panic!(
+            "Rendered {:?}, but {} is {:?}",
+            image.dimensions(),
+            path.display(),
+            golden.dimensions()
+        ),
+    }
+}
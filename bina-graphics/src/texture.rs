@@ -1,39 +1,139 @@
 use std::{
-    hint::unreachable_unchecked, marker::PhantomData, mem::MaybeUninit, ops::Deref, time::Duration,
+    collections::HashMap,
+    fmt::Display,
+    hint::unreachable_unchecked,
+    marker::PhantomData,
+    mem::MaybeUninit,
+    ops::Deref,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Weak,
+    },
 };
 
 use bina_ecs::{
+    assets::{AssetLoader, Handle},
     component::Component,
     crossbeam::atomic::AtomicCell,
-    tokio::{
-        self,
-        fs::File,
-        io::AsyncReadExt,
-        sync::{RwLock, RwLockReadGuard, RwLockWriteGuard},
-        time::Instant,
-    },
+    parking_lot::Mutex,
+    time::{Duration, Instant},
+    tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard},
+    triomphe::Arc,
     universe::Universe,
 };
-use image::{ImageBuffer, ImageFormat, Pixel, Rgba};
+use image::{ImageBuffer, ImageFormat, Pixel, Rgba, RgbaImage};
 use wgpu::BindGroup;
 
-use crate::Graphics;
+use crate::{
+    labels,
+    memory::{GpuMemory, MemoryAllocation},
+    sprite::{self, SpriteAnimation},
+    Graphics,
+};
 
 pub(crate) struct TextureInner {
-    // texture: wgpu::Texture,
+    /// Kept so that regions of the texture can be rewritten
+    pub(crate) texture: wgpu::Texture,
     // view: wgpu::TextureView,
     // sampler: wgpu::Sampler,
     pub(crate) bind_group: BindGroup,
+    /// The generation of the device this texture was created on
+    pub(crate) generation: u64,
+    /// What the texture was loaded from, used to label draws with it
+    pub(crate) name: Option<String>,
+    /// The last frame a polygon with this texture was queued to be drawn in,
+    /// which decides which textures the LRU cache unloads first
+    pub(crate) last_drawn: AtomicU64,
+    _memory: MemoryAllocation,
+}
+
+/// How a texture is filtered when it is drawn larger or smaller than its actual size
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Filter {
+    /// Blocky, which suits pixel art
+    Nearest,
+    Linear,
+}
+
+/// What a texture shows outside of the 0 to 1 texture coordinates
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Wrap {
+    /// Stretches the pixels on the edges of the texture
+    Clamp,
+    Repeat,
+    MirrorRepeat,
+}
+
+/// How a texture is sampled. Textures with the same options share one sampler
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct SamplerOptions {
+    pub filter: Filter,
+    pub wrap: Wrap,
+    /// Improves textures viewed at an angle. Must be between 1 and 16,
+    /// and is ignored unless `filter` is `Linear`
+    pub anisotropy: u16,
+}
+
+impl SamplerOptions {
+    pub const LINEAR: Self = Self {
+        filter: Filter::Linear,
+        wrap: Wrap::Clamp,
+        anisotropy: 1,
+    };
+    pub const PIXEL_ART: Self = Self {
+        filter: Filter::Nearest,
+        wrap: Wrap::Clamp,
+        anisotropy: 1,
+    };
+
+    pub(crate) fn create_sampler(&self, device: &wgpu::Device) -> wgpu::Sampler {
+        let filter = match self.filter {
+            Filter::Nearest => wgpu::FilterMode::Nearest,
+            Filter::Linear => wgpu::FilterMode::Linear,
+        };
+        let address_mode = match self.wrap {
+            Wrap::Clamp => wgpu::AddressMode::ClampToEdge,
+            Wrap::Repeat => wgpu::AddressMode::Repeat,
+            Wrap::MirrorRepeat => wgpu::AddressMode::MirrorRepeat,
+        };
+        device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("texture_sampler"),
+            address_mode_u: address_mode,
+            address_mode_v: address_mode,
+            address_mode_w: address_mode,
+            mag_filter: filter,
+            min_filter: filter,
+            mipmap_filter: filter,
+            // wgpu only allows anisotropic filtering on linear samplers
+            anisotropy_clamp: match self.filter {
+                Filter::Nearest => 1,
+                Filter::Linear => self.anisotropy.clamp(1, 16),
+            },
+            ..Default::default()
+        })
+    }
+}
+
+impl Default for SamplerOptions {
+    fn default() -> Self {
+        Self::LINEAR
+    }
 }
 
 pub enum CacheOption {
     DontCache,
     UncacheAfter(Duration),
     CacheForever,
+    /// Kept until every texture with this option uses more memory than the budget set with
+    /// `Graphics::set_texture_budget`, when the least recently drawn are unloaded first.
+    /// An unloaded texture is loaded again the next time it is asked for
+    Lru,
 }
 
 enum DataSource {
     Raw(&'static [u8]),
+    /// An encoded image compiled into the binary, with the path it was embedded from
+    Embedded(&'static str, &'static [u8], ImageFormat),
     File(
         &'static str,
         ImageFormat,
@@ -42,6 +142,24 @@ enum DataSource {
     ),
 }
 
+impl DataSource {
+    /// Describes where the image came from in errors
+    fn path(&self) -> &'static str {
+        match self {
+            Self::Raw(_) => "raw image",
+            Self::Embedded(path, ..) | Self::File(path, ..) => path,
+        }
+    }
+
+    /// Names the texture in GPU labels
+    fn name(&self) -> Option<&'static str> {
+        match self {
+            Self::Raw(_) => None,
+            Self::Embedded(path, ..) | Self::File(path, ..) => Some(path),
+        }
+    }
+}
+
 struct SyncPhantom<T>(PhantomData<T>);
 
 unsafe impl<T> Send for SyncPhantom<T> {}
@@ -50,11 +168,45 @@ unsafe impl<T> Sync for SyncPhantom<T> {}
 enum MaybeTexture<P: Pixel> {
     Unloaded,
     Loaded(ImageBuffer<P, Box<[u8]>>),
-    Processed(TextureInner),
+    /// Shared with every other `TextureResource` that loaded the same pixels
+    Processed(std::sync::Arc<TextureInner>),
+    /// Whether a `TextureErrorEvent` has been sent for this error yet
+    Failed(TextureError, AtomicBool),
+}
+
+/// Why a `TextureResource` could not be loaded
+#[derive(Debug, Clone)]
+pub enum TextureError {
+    /// The file is missing or could not be read
+    Unreadable(String),
+    /// The file could not be decoded in the given format
+    Corrupt(String),
+    /// The image is not the size the `TextureResource` was declared with
+    WrongSize { width: u32, height: u32 },
+}
+
+impl Display for TextureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unreadable(e) => write!(f, "unreadable: {e}"),
+            Self::Corrupt(e) => write!(f, "corrupt: {e}"),
+            Self::WrongSize { width, height } => write!(f, "wrong size: {width}x{height}"),
+        }
+    }
+}
+
+impl std::error::Error for TextureError {}
+
+/// Sent into the Universe the first time a `TextureResource` that failed to load is used
+#[derive(Debug, Clone)]
+pub struct TextureErrorEvent {
+    pub path: &'static str,
+    pub error: TextureError,
 }
 
 pub struct TextureResource<P: Pixel + Send, const W: u32, const H: u32> {
     data_source: DataSource,
+    sampler: SamplerOptions,
     texture: RwLock<MaybeTexture<P>>,
     _phantom: SyncPhantom<P>,
 }
@@ -62,15 +214,41 @@ pub struct TextureResource<P: Pixel + Send, const W: u32, const H: u32> {
 static_assertions::assert_impl_all!(TextureResource<Rgba<u8>, 0, 0>: Sync);
 
 pub struct Texture {
-    pub(crate) texture: RwLockReadGuard<'static, TextureInner>,
+    pub(crate) texture: TextureRef,
 }
 
 static_assertions::assert_impl_all!(Texture: Send, Sync);
 
-fn load_img<const W: u32, const H: u32>(graphics: &Graphics, img: &[u8]) -> TextureInner {
+/// Where the GPU texture of a `Texture` is kept
+pub(crate) enum TextureRef {
+    /// Owned by a `TextureResource`
+    Static(RwLockReadGuard<'static, TextureInner>),
+    /// Owned by a `TextureHandle`
+    Shared(Arc<TextureInner>),
+}
+
+impl Deref for TextureRef {
+    type Target = TextureInner;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Self::Static(x) => x,
+            Self::Shared(x) => x,
+        }
+    }
+}
+
+pub(crate) fn load_img(
+    graphics: &Graphics,
+    img: &[u8],
+    width: u32,
+    height: u32,
+    sampler: SamplerOptions,
+    name: Option<&str>,
+) -> TextureInner {
     let texture_size = wgpu::Extent3d {
-        width: W,
-        height: H,
+        width,
+        height,
         depth_or_array_layers: 1,
     };
 
@@ -89,7 +267,7 @@ fn load_img<const W: u32, const H: u32>(graphics: &Graphics, img: &[u8]) -> Text
             // TEXTURE_BINDING tells wgpu that we want to use this texture in shaders
             // COPY_DST means that we want to copy data to this texture
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-            label: Some("diffuse_texture"),
+            label: Some(&labels::named("diffuse_texture", name)),
             // This is the same as with the SurfaceConfig. It
             // specifies what texture formats can be used to
             // create TextureViews for this texture. The base
@@ -113,31 +291,33 @@ fn load_img<const W: u32, const H: u32>(graphics: &Graphics, img: &[u8]) -> Text
         // The layout of the texture
         wgpu::ImageDataLayout {
             offset: 0,
-            bytes_per_row: Some(4 * W),
-            rows_per_image: Some(H),
+            bytes_per_row: Some(4 * width),
+            rows_per_image: Some(height),
         },
         texture_size,
     );
 
+    bind_texture(graphics, texture, sampler, name)
+}
+
+/// Creates the bind group that the textured renderer samples the given texture through
+pub(crate) fn bind_texture(
+    graphics: &Graphics,
+    texture: wgpu::Texture,
+    sampler: SamplerOptions,
+    name: Option<&str>,
+) -> TextureInner {
     let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-    let sampler = graphics
-        .inner
-        .device
-        .create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            ..Default::default()
-        });
+    let mut samplers = graphics.inner.samplers.lock();
+    let sampler = samplers
+        .entry(sampler)
+        .or_insert_with(|| sampler.create_sampler(&graphics.inner.device));
 
     let bind_group = graphics
         .inner
         .device
         .create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &graphics.inner.texture_bind_grp_layout,
+            layout: &graphics.inner.layouts.texture,
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
@@ -145,17 +325,21 @@ fn load_img<const W: u32, const H: u32>(graphics: &Graphics, img: &[u8]) -> Text
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&sampler),
+                    resource: wgpu::BindingResource::Sampler(sampler),
                 },
             ],
-            label: Some("texture_bind_group"),
+            label: Some(&labels::named("texture_bind_group", name)),
         });
 
     TextureInner {
-        // texture,
+        _memory: GpuMemory::allocate_texture(&graphics.inner.memory, &texture),
+        texture,
         // view,
         // sampler,
         bind_group,
+        generation: graphics.inner.generation,
+        name: name.map(Into::into),
+        last_drawn: AtomicU64::new(0),
     }
 }
 
@@ -172,6 +356,21 @@ impl<const W: u32, const H: u32> TextureResource<Rgba<u8>, W, H> {
                 cache_option,
                 AtomicCell::new(MaybeUninit::uninit()),
             ),
+            sampler: SamplerOptions::LINEAR,
+            texture: RwLock::const_new(MaybeTexture::Unloaded),
+            _phantom: SyncPhantom(PhantomData),
+        }
+    }
+
+    /// Creates a texture from an encoded image, which is decoded in the background
+    /// the first time it is used
+    ///
+    /// Usually created by `load_image!`. If the image cannot be decoded or is
+    /// not `W` by `H`, this texture fails to load like a missing file would
+    pub const fn new_embedded(path: &'static str, bytes: &'static [u8], img_format: ImageFormat) -> Self {
+        Self {
+            data_source: DataSource::Embedded(path, bytes, img_format),
+            sampler: SamplerOptions::LINEAR,
             texture: RwLock::const_new(MaybeTexture::Unloaded),
             _phantom: SyncPhantom(PhantomData),
         }
@@ -180,11 +379,47 @@ impl<const W: u32, const H: u32> TextureResource<Rgba<u8>, W, H> {
     pub const unsafe fn new_raw(raw: &'static [u8]) -> Self {
         Self {
             data_source: DataSource::Raw(raw),
+            sampler: SamplerOptions::LINEAR,
             texture: RwLock::const_new(MaybeTexture::Unloaded),
             _phantom: SyncPhantom(PhantomData),
         }
     }
 
+    /// Sets how this texture is sampled, which is `SamplerOptions::LINEAR` by default
+    ///
+    /// ```ignore
+    /// static SPRITE: TextureResource<Rgba<u8>, 16, 16> =
+    ///     unsafe { TextureResource::new_file("sprite.png", ImageFormat::Png, CacheOption::CacheForever) }
+    ///         .with_sampler(SamplerOptions::PIXEL_ART);
+    /// ```
+    pub const fn with_sampler(self, sampler: SamplerOptions) -> Self {
+        // Moving every field out avoids dropping `self`, which const functions cannot do
+        let Self {
+            data_source,
+            texture,
+            _phantom,
+            ..
+        } = self;
+        Self {
+            data_source,
+            sampler,
+            texture,
+            _phantom,
+        }
+    }
+
+    /// Gets the reason this texture could not be loaded, if it failed to load
+    pub fn error(&self) -> Option<TextureError> {
+        match self.texture.try_read().ok()?.deref() {
+            MaybeTexture::Failed(error, _) => Some(error.clone()),
+            _ => None,
+        }
+    }
+
+    /// Gets the texture if it has been loaded, otherwise starts loading it in the background
+    ///
+    /// If the texture failed to load, this returns the missing texture when
+    /// `Graphics::set_missing_texture_fallback` is enabled, and `None` otherwise
     pub fn try_get(&'static self, universe: &Universe, graphics: &Graphics) -> Option<Texture> {
         // # Safety
         // The current texture must be processed
@@ -193,9 +428,11 @@ impl<const W: u32, const H: u32> TextureResource<Rgba<u8>, W, H> {
                 let MaybeTexture::Processed(inner) = x else {
                     unsafe { unreachable_unchecked() }
                 };
-                inner
+                &**inner
             });
-            Some(Texture { texture })
+            Some(Texture {
+                texture: TextureRef::Static(texture),
+            })
         };
 
         let read = self.texture.try_read().ok()?;
@@ -213,38 +450,40 @@ impl<const W: u32, const H: u32> TextureResource<Rgba<u8>, W, H> {
                     let img = unsafe {
                         ImageBuffer::<Rgba<u8>, &[u8]>::from_raw(W, H, *data).unwrap_unchecked()
                     };
-                    let inner = load_img::<W, H>(graphics, &img);
+                    let inner = self.upload(graphics, &img);
                     *write = MaybeTexture::Processed(inner);
                     let read = RwLockWriteGuard::downgrade(write);
                     return return_ref(read);
                 }
 
-                let _guard = universe.enter_tokio();
-                tokio::spawn(async {
+                let memory_pressure = graphics.memory_pressure();
+                universe.spawn(async move {
                     let mut write = self.texture.write().await;
                     let MaybeTexture::Unloaded = write.deref() else {
                         return;
                     };
-                    let DataSource::File(path, img_format, cache_option, last_access) =
-                        &self.data_source
-                    else {
-                        unsafe { unreachable_unchecked() }
-                    };
-
-                    let Ok(mut file) = File::open(path).await else {
-                        todo!("Unreadable")
+                    let img = match &self.data_source {
+                        // Decoded here instead of in `try_get`, so that large images do not stall a frame
+                        DataSource::Embedded(_, bytes, img_format) => decode::<W, H>(bytes, *img_format),
+                        DataSource::File(path, img_format, ..) => match bina_ecs::io::read(path).await {
+                            Ok(buf) => decode::<W, H>(&buf, *img_format),
+                            Err(e) => Err(TextureError::Unreadable(e.to_string())),
+                        },
+                        DataSource::Raw(_) => unsafe { unreachable_unchecked() },
                     };
-                    let mut buf = Vec::with_capacity(W as usize * H as usize);
-                    if file.read_to_end(&mut buf).await.is_err() {
-                        todo!("Unreadable")
-                    }
-                    let img = unsafe {
-                        image::load_from_memory_with_format(&buf, *img_format).unwrap_unchecked()
+                    let img = match img {
+                        Ok(img) => img,
+                        Err(e) => {
+                            log::error!("Failed to load {}: {e}", self.data_source.path());
+                            *write = MaybeTexture::Failed(e, AtomicBool::new(false));
+                            return;
+                        }
                     };
-                    let img = img.to_rgba8();
-                    let data = img.into_raw().into_boxed_slice();
-                    let img = unsafe { ImageBuffer::from_raw(W, H, data).unwrap_unchecked() };
                     *write = MaybeTexture::Loaded(img);
+                    let DataSource::File(_, _, cache_option, last_access) = &self.data_source else {
+                        // Embedded images are always in memory, so their textures are never uncached
+                        return;
+                    };
                     last_access.store(MaybeUninit::new(Instant::now()));
                     drop(write);
 
@@ -253,9 +492,20 @@ impl<const W: u32, const H: u32> TextureResource<Rgba<u8>, W, H> {
                         let mut deadline = last_instant + *duration;
                         let mut write;
                         loop {
-                            tokio::time::sleep_until(deadline).await;
+                            let timeout = deadline.saturating_duration_since(Instant::now());
+                            let sleep = async {
+                                #[cfg(not(target_arch = "wasm32"))]
+                                bina_ecs::tokio::time::sleep(timeout).await;
+                                #[cfg(target_arch = "wasm32")]
+                                crate::web::sleep(timeout).await;
+                            };
+                            // Using too much memory unloads the texture without waiting for the deadline
+                            let pressured = bina_ecs::tokio::select! {
+                                _ = sleep => false,
+                                _ = memory_pressure.notified() => true,
+                            };
                             let current_instant = unsafe { last_access.load().assume_init() };
-                            if current_instant == last_instant {
+                            if pressured || current_instant == last_instant {
                                 // If we can't write to it immediately,
                                 // then the texture is still being used
                                 if let Ok(tmp) = self.texture.try_write() {
@@ -278,28 +528,398 @@ impl<const W: u32, const H: u32> TextureResource<Rgba<u8>, W, H> {
                     drop(write);
                     return self.try_get(universe, graphics);
                 };
-                let inner = load_img::<W, H>(graphics, &img);
+                let inner = self.upload(graphics, img);
                 *write = MaybeTexture::Processed(inner);
                 let read = RwLockWriteGuard::downgrade(write);
 
                 let DataSource::File(_, _, cache_option, _) = &self.data_source else {
-                    unsafe { unreachable_unchecked() }
+                    return return_ref(read);
                 };
 
-                if let CacheOption::DontCache = cache_option {
-                    let _guard = universe.enter_tokio();
-                    tokio::spawn(async {
-                        *self.texture.write().await = MaybeTexture::Unloaded;
-                    });
+                match cache_option {
+                    CacheOption::DontCache => {
+                        universe.spawn(async {
+                            *self.texture.write().await = MaybeTexture::Unloaded;
+                        });
+                    }
+                    CacheOption::Lru => graphics.cache_texture(self),
+                    CacheOption::UncacheAfter(_) | CacheOption::CacheForever => {}
                 }
 
                 return return_ref(read);
             }
-            MaybeTexture::Processed(_) => return return_ref(read),
+            MaybeTexture::Processed(inner) => {
+                if inner.generation == graphics.inner.generation {
+                    return return_ref(read);
+                }
+                // The device this texture was created on has been replaced.
+                // It can only be recreated once every `Texture` using it is dropped
+                drop(read);
+                let mut write = self.texture.try_write().ok()?;
+                match &self.data_source {
+                    DataSource::Raw(data) => {
+                        let img = unsafe {
+                            ImageBuffer::<Rgba<u8>, &[u8]>::from_raw(W, H, *data).unwrap_unchecked()
+                        };
+                        *write = MaybeTexture::Processed(self.upload(graphics, &img));
+                        let read = RwLockWriteGuard::downgrade(write);
+                        return return_ref(read);
+                    }
+                    DataSource::File(..) | DataSource::Embedded(..) => {
+                        *write = MaybeTexture::Unloaded;
+                        drop(write);
+                        return self.try_get(universe, graphics);
+                    }
+                }
+            }
+            MaybeTexture::Failed(error, reported) => {
+                if !reported.swap(true, Ordering::Relaxed) {
+                    universe.send_event(TextureErrorEvent {
+                        path: self.data_source.path(),
+                        error: error.clone(),
+                    });
+                }
+                graphics
+                    .missing_texture_fallback()
+                    .then(|| graphics.missing_texture())
+            }
+        }
+    }
+}
+
+impl<const W: u32, const H: u32> TextureResource<Rgba<u8>, W, H> {
+    /// Creates the GPU texture for the given pixels, unless another resource already did
+    fn upload(&self, graphics: &Graphics, img: &[u8]) -> std::sync::Arc<TextureInner> {
+        graphics.inner.texture_cache.get_or_load(graphics, img, W, H, self.sampler, self.data_source.name())
+    }
+}
+
+/// A `TextureResource` loaded with `CacheOption::Lru`, regardless of its size
+pub(crate) trait CachedTexture: Sync {
+    /// Gets the last frame the texture was drawn in, or `None` if it is not loaded
+    fn last_drawn(&self) -> Option<u64>;
+    /// The size of the texture in bytes
+    fn bytes(&self) -> u64;
+    /// Unloads the texture unless something is using it, returning whether it is now unloaded
+    fn try_unload(&self) -> bool;
+}
+
+impl<const W: u32, const H: u32> CachedTexture for TextureResource<Rgba<u8>, W, H> {
+    fn last_drawn(&self) -> Option<u64> {
+        let Ok(texture) = self.texture.try_read() else {
+            // Being loaded right now
+            return Some(u64::MAX);
+        };
+        match texture.deref() {
+            MaybeTexture::Processed(inner) => Some(inner.last_drawn.load(Ordering::Relaxed)),
+            _ => None,
         }
     }
+
+    fn bytes(&self) -> u64 {
+        W as u64 * H as u64 * 4
+    }
+
+    fn try_unload(&self) -> bool {
+        // Polygons hold a read lock for as long as they use the texture
+        let Ok(mut texture) = self.texture.try_write() else {
+            return false;
+        };
+        if let MaybeTexture::Processed(_) | MaybeTexture::Loaded(_) = texture.deref() {
+            *texture = MaybeTexture::Unloaded;
+        }
+        true
+    }
+}
+
+/// What makes the textures in a `TextureCache` different from each other
+#[derive(PartialEq, Eq, Hash)]
+struct TextureKey {
+    /// The hash of the pixels, wide enough that different images do not collide
+    hash: [u8; 32],
+    width: u32,
+    height: u32,
+    /// The bind group of a texture includes its sampler
+    sampler: SamplerOptions,
+}
+
+/// The GPU textures of `TextureResource`s, by their pixels, so that resources which load
+/// the same image, such as copies of one file, share a texture and a bind group
+///
+/// Created along with the device, and only holds onto textures while a resource is using them
+#[derive(Default)]
+pub(crate) struct TextureCache {
+    /// Each key has its own slot, which is held while loading so that resources loading the
+    /// same pixels at once do not both upload them, without blocking loads of other textures
+    textures: Mutex<HashMap<TextureKey, std::sync::Arc<Mutex<Weak<TextureInner>>>>>,
 }
 
+impl TextureCache {
+    pub(crate) fn get_or_load(
+        &self,
+        graphics: &Graphics,
+        img: &[u8],
+        width: u32,
+        height: u32,
+        sampler: SamplerOptions,
+        name: Option<&str>,
+    ) -> std::sync::Arc<TextureInner> {
+        let key = TextureKey {
+            hash: blake3::hash(img).into(),
+            width,
+            height,
+            sampler,
+        };
+        let slot = {
+            let mut textures = self.textures.lock();
+            if !textures.contains_key(&key) {
+                // Slots being loaded are locked and kept
+                textures.retain(|_, slot| slot.try_lock().map_or(true, |x| x.strong_count() > 0));
+            }
+            textures.entry(key).or_default().clone()
+        };
+        let mut slot = slot.lock();
+        if let Some(texture) = slot.upgrade() {
+            return texture;
+        }
+        let texture = std::sync::Arc::new(load_img(graphics, img, width, height, sampler, name));
+        *slot = std::sync::Arc::downgrade(&texture);
+        texture
+    }
+}
+
+/// Decodes an image, checking that it is `W` by `H`
+fn decode<const W: u32, const H: u32>(
+    buf: &[u8],
+    img_format: ImageFormat,
+) -> Result<ImageBuffer<Rgba<u8>, Box<[u8]>>, TextureError> {
+    let img = image::load_from_memory_with_format(buf, img_format)
+        .map_err(|e| TextureError::Corrupt(e.to_string()))?
+        .to_rgba8();
+    let (width, height) = img.dimensions();
+    if (width, height) != (W, H) {
+        return Err(TextureError::WrongSize { width, height });
+    }
+    let data = img.into_raw().into_boxed_slice();
+    // Safety: the size of the image was checked above
+    Ok(unsafe { ImageBuffer::from_raw(W, H, data).unwrap_unchecked() })
+}
+
+/// A decoded image, which is uploaded to the GPU the first time it is used
+pub struct TextureAsset {
+    /// Kept up to date with every write, so the texture can be recreated along with the device
+    image: Mutex<RgbaImage>,
+    sampler: AtomicCell<SamplerOptions>,
+    /// Recreated along with the device
+    texture: Mutex<Option<Arc<TextureInner>>>,
+    name: Option<String>,
+    /// Where each frame is, if this was loaded from an animated image
+    animation: Option<Arc<SpriteAnimation>>,
+}
+
+impl TextureAsset {
+    pub fn new(image: RgbaImage, sampler: SamplerOptions) -> Self {
+        Self {
+            image: Mutex::new(image),
+            sampler: AtomicCell::new(sampler),
+            texture: Mutex::new(None),
+            name: None,
+            animation: None,
+        }
+    }
+
+    /// Names the texture in graphics debuggers, when the `debug_labels` feature is enabled
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn get(&self, graphics: &Graphics) -> Texture {
+        let mut texture = self.texture.lock();
+        let texture = match &*texture {
+            Some(texture) if texture.generation == graphics.inner.generation => texture.clone(),
+            _ => {
+                let image = self.image.lock();
+                texture
+                    .insert(Arc::new(load_img(
+                        graphics,
+                        &image,
+                        image.width(),
+                        image.height(),
+                        self.sampler.load(),
+                        self.name.as_deref(),
+                    )))
+                    .clone()
+            }
+        };
+        Texture {
+            texture: TextureRef::Shared(texture),
+        }
+    }
+
+    /// Changes how this texture is sampled. `Texture`s that were already
+    /// taken out of this asset keep using the old sampler
+    pub fn set_sampler(&self, sampler: SamplerOptions) {
+        self.sampler.store(sampler);
+        *self.texture.lock() = None;
+    }
+
+    /// Gets the width and height of the image
+    pub fn size(&self) -> (u32, u32) {
+        self.image.lock().dimensions()
+    }
+
+    /// Gets the frames of this texture if it was loaded from an animated GIF or APNG,
+    /// which can be played with an `AnimatedSprite`
+    pub fn animation(&self) -> Option<&Arc<SpriteAnimation>> {
+        self.animation.as_ref()
+    }
+
+    /// Replaces the pixels inside of the given rectangle with rows of `Rgba8` pixels
+    ///
+    /// The GPU texture is updated in place during the next flush, so `Texture`s
+    /// that were already taken out of this asset show the new pixels too
+    ///
+    /// ```ignore
+    /// // Reveal a tile of fog of war
+    /// fog.write_region(graphics, TextureRect::new(x, y, 1, 1), &[0, 0, 0, 0]);
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if the rectangle does not fit inside of the texture,
+    /// or if there are not exactly enough pixels to fill it
+    pub fn write_region(&self, graphics: &Graphics, rect: TextureRect, pixels: &[u8]) {
+        {
+            let mut image = self.image.lock();
+            assert!(
+                rect.x + rect.width <= image.width() && rect.y + rect.height <= image.height(),
+                "Rectangle does not fit inside of the texture"
+            );
+            assert_eq!(
+                pixels.len(),
+                (rect.width * rect.height * 4) as usize,
+                "Pixels do not fill the rectangle"
+            );
+            if rect.width == 0 || rect.height == 0 {
+                return;
+            }
+            let image_width = image.width() as usize;
+            let buf: &mut [u8] = &mut image;
+            for (row, src) in pixels.chunks_exact(rect.width as usize * 4).enumerate() {
+                let start = ((rect.y as usize + row) * image_width + rect.x as usize) * 4;
+                buf[start..start + src.len()].copy_from_slice(src);
+            }
+        }
+        // If the texture has not been created yet, it will be created with the new pixels
+        if let Some(texture) = &*self.texture.lock() {
+            graphics.queue_texture_write(TextureWrite {
+                texture: texture.clone(),
+                rect,
+                pixels: pixels.to_vec(),
+            });
+        }
+    }
+}
+
+/// A rectangle of pixels inside of a texture
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TextureRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl TextureRect {
+    pub const fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+}
+
+/// Pixels waiting to be copied into a texture during the next flush
+pub(crate) struct TextureWrite {
+    texture: Arc<TextureInner>,
+    rect: TextureRect,
+    pixels: Vec<u8>,
+}
+
+impl TextureWrite {
+    pub(crate) fn apply(&self, graphics: &Graphics) {
+        // A texture from an old device is recreated from the image, which already has these pixels
+        if self.texture.generation != graphics.inner.generation {
+            return;
+        }
+        graphics.inner.queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &self.texture.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: self.rect.x,
+                    y: self.rect.y,
+                    z: 0,
+                },
+                aspect: wgpu::TextureAspect::All,
+            },
+            &self.pixels,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * self.rect.width),
+                rows_per_image: Some(self.rect.height),
+            },
+            wgpu::Extent3d {
+                width: self.rect.width,
+                height: self.rect.height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+}
+
+/// Loads images of any format supported by `image`, guessing the format from their contents
+///
+/// The frames of animated GIFs and APNGs are packed into one texture, see `TextureAsset::animation`
+#[derive(Default)]
+pub struct TextureLoader {
+    /// How every texture loaded by this loader is sampled, until it is changed with `TextureAsset::set_sampler`
+    pub sampler: SamplerOptions,
+    /// What every texture loaded by this loader is named in graphics debuggers
+    pub name: Option<String>,
+}
+
+impl AssetLoader for TextureLoader {
+    type Asset = TextureAsset;
+
+    fn load(&self, bytes: Vec<u8>) -> Result<Self::Asset, String> {
+        let mut asset = match sprite::decode_animated(&bytes).transpose()? {
+            Some((image, animation)) => TextureAsset {
+                animation: Some(Arc::new(animation)),
+                ..TextureAsset::new(image, self.sampler)
+            },
+            None => {
+                let image = image::load_from_memory(&bytes).map_err(|e| e.to_string())?;
+                TextureAsset::new(image.to_rgba8(), self.sampler)
+            }
+        };
+        asset.name = self.name.clone();
+        Ok(asset)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["png", "jpg", "jpeg", "gif", "bmp", "webp", "tga", "tiff", "ico"]
+    }
+}
+
+/// A texture whose size is only known once it has been loaded,
+/// such as a downloaded image or user content
+///
+/// Created through `Graphics::load_texture`, `Graphics::create_texture`, or `Assets` with a `TextureLoader`
+pub type TextureHandle = Handle<TextureAsset>;
+
 impl Component for Texture {
     fn get_ref<'a>(&'a self) -> Self::Reference<'a> {
         self
@@ -0,0 +1,467 @@
+//! Sprites that sample one layer of a texture array, which are drawn together in a single
+//! draw call for every array and z
+//!
+//! Every `Polygon` is its own draw call, which adds up when there are hundreds of small sprites
+//! that each have their own texture. Packing textures of the same size into the layers of a
+//! `TextureArray` lets the sprites that use them be drawn as instances of one quad instead,
+//! without building an atlas
+//!
+//! ```ignore
+//! let icons = Arc::new(TextureArray::new(icon_images, SamplerOptions::PIXEL_ART)?);
+//! for (i, position) in positions.into_iter().enumerate() {
+//!     let sprite = ArraySprite::new(graphics, icons.clone(), i as u32, Vector::new(1.0, 1.0))
+//!         .with_origin(position);
+//!     universe.queue_add_entity((sprite,));
+//! }
+//! ```
+use std::{mem::size_of, ops::Range};
+
+use bina_ecs::{
+    component::{Component, ComponentField, NumberField, NumberFieldRef, Processable},
+    entity::ErasedEntityReference,
+    parking_lot::Mutex,
+    reflect::{Field, Reflect},
+    triomphe::Arc,
+};
+use image::RgbaImage;
+use wgpu::util::DeviceExt;
+
+use crate::{
+    labels,
+    layers::{RenderLayers, Visible},
+    memory::{GpuMemory, MemoryAllocation},
+    polygon::{basis_of, Vector},
+    texture::{SamplerOptions, TextureError},
+    transform::Transform,
+    Graphics, GraphicsInner,
+};
+
+/// The most layers that every device supports, including WebGL
+pub const MAX_LAYERS: usize = 256;
+
+pub(crate) const SPRITE_INSTANCE_BUFFER_DESCRIPTOR: wgpu::VertexBufferLayout<'static> =
+    wgpu::VertexBufferLayout {
+        array_stride: size_of::<SpriteInstance>() as wgpu::BufferAddress,
+        step_mode: wgpu::VertexStepMode::Instance,
+        attributes: &[
+            wgpu::VertexAttribute {
+                offset: 0,
+                shader_location: 0,
+                format: wgpu::VertexFormat::Float32x4,
+            },
+            wgpu::VertexAttribute {
+                offset: size_of::<f32>() as wgpu::BufferAddress * 4,
+                shader_location: 1,
+                format: wgpu::VertexFormat::Float32x2,
+            },
+            wgpu::VertexAttribute {
+                offset: size_of::<f32>() as wgpu::BufferAddress * 6,
+                shader_location: 2,
+                format: wgpu::VertexFormat::Uint32,
+            },
+            wgpu::VertexAttribute {
+                offset: size_of::<f32>() as wgpu::BufferAddress * 7,
+                shader_location: 3,
+                format: wgpu::VertexFormat::Float32,
+            },
+        ],
+    };
+
+/// Images of the same size, uploaded to the GPU as the layers of one texture the first time
+/// a sprite uses them
+pub struct TextureArray {
+    images: Vec<RgbaImage>,
+    sampler: SamplerOptions,
+    name: Option<String>,
+    /// Recreated along with the device
+    gpu: Mutex<Option<Arc<TextureArrayGpu>>>,
+}
+
+impl TextureArray {
+    /// Creates an array with one layer for each image, in the same order
+    ///
+    /// Returns `TextureError::WrongSize` with the size of the first image that is not the same
+    /// size as the first
+    ///
+    /// # Panics
+    /// Panics if there are no images, or more than `MAX_LAYERS`
+    pub fn new(images: Vec<RgbaImage>, sampler: SamplerOptions) -> Result<Self, TextureError> {
+        assert!(
+            !images.is_empty(),
+            "A texture array needs at least one layer"
+        );
+        assert!(
+            images.len() <= MAX_LAYERS,
+            "A texture array can have at most {MAX_LAYERS} layers"
+        );
+        let size = images[0].dimensions();
+        if let Some(image) = images.iter().find(|x| x.dimensions() != size) {
+            let (width, height) = image.dimensions();
+            return Err(TextureError::WrongSize { width, height });
+        }
+        Ok(Self {
+            images,
+            sampler,
+            name: None,
+            gpu: Mutex::new(None),
+        })
+    }
+
+    /// Names the texture in graphics debuggers, when the `debug_labels` feature is enabled
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn layer_count(&self) -> usize {
+        self.images.len()
+    }
+
+    /// Gets the width and height of every layer
+    pub fn size(&self) -> (u32, u32) {
+        self.images[0].dimensions()
+    }
+
+    fn get(&self, graphics: &Graphics) -> Arc<TextureArrayGpu> {
+        let mut gpu = self.gpu.lock();
+        match &*gpu {
+            Some(gpu) if gpu.generation == graphics.inner.generation => gpu.clone(),
+            _ => gpu.insert(Arc::new(self.upload(graphics))).clone(),
+        }
+    }
+
+    fn upload(&self, graphics: &Graphics) -> TextureArrayGpu {
+        let (width, height) = self.size();
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: self.images.len() as u32,
+        };
+        let texture = graphics
+            .inner
+            .device
+            .create_texture(&wgpu::TextureDescriptor {
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                label: Some(&labels::named("texture_array", self.name.as_deref())),
+                view_formats: &[],
+            });
+        for (layer, image) in self.images.iter().enumerate() {
+            graphics.inner.queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: layer as u32,
+                    },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                image,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * width),
+                    rows_per_image: Some(height),
+                },
+                wgpu::Extent3d {
+                    depth_or_array_layers: 1,
+                    ..size
+                },
+            );
+        }
+        // A single layer would be viewed as a plain 2D texture by default
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let mut samplers = graphics.inner.samplers.lock();
+        let sampler = samplers
+            .entry(self.sampler)
+            .or_insert_with(|| self.sampler.create_sampler(&graphics.inner.device));
+        let bind_group = graphics
+            .inner
+            .device
+            .create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &graphics.inner.layouts.texture_array,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(sampler),
+                    },
+                ],
+                label: Some(&labels::named(
+                    "texture_array_bind_group",
+                    self.name.as_deref(),
+                )),
+            });
+        TextureArrayGpu {
+            _memory: GpuMemory::allocate_texture(&graphics.inner.memory, &texture),
+            _texture: texture,
+            bind_group,
+            generation: graphics.inner.generation,
+            name: self.name.clone(),
+        }
+    }
+}
+
+pub(crate) struct TextureArrayGpu {
+    _texture: wgpu::Texture,
+    _memory: MemoryAllocation,
+    pub(crate) bind_group: wgpu::BindGroup,
+    pub(crate) generation: u64,
+    pub(crate) name: Option<String>,
+}
+
+/// One sprite as the shader reads it
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct SpriteInstance {
+    /// Stored the same way as the basis in the transform slots
+    basis: [f32; 4],
+    origin: [f32; 2],
+    layer: u32,
+    emission: f32,
+}
+
+/// A sprite waiting to be put into a batch during the next flush
+pub(crate) struct QueuedSprite {
+    array: Arc<TextureArrayGpu>,
+    z: u32,
+    pub(crate) layers: RenderLayers,
+    instance: SpriteInstance,
+}
+
+impl QueuedSprite {
+    pub(crate) fn is_ui(&self) -> bool {
+        self.layers.intersects(RenderLayers::UI)
+    }
+}
+
+/// Sprites of the same array and z, which are drawn as instances of one quad
+#[derive(Clone)]
+pub(crate) struct SpriteBatch {
+    pub(crate) array: Arc<TextureArrayGpu>,
+    pub(crate) z: u32,
+    pub(crate) ui: bool,
+    /// Shared by every batch made in the same flush
+    pub(crate) instances: Arc<wgpu::Buffer>,
+    pub(crate) range: Range<u32>,
+}
+
+/// Groups the given sprites by whether they are on the UI, their z, and their array,
+/// and writes all of them into one instance buffer
+pub(crate) fn batch<'a>(
+    graphics: &GraphicsInner,
+    sprites: impl IntoIterator<Item = &'a QueuedSprite>,
+) -> Vec<SpriteBatch> {
+    let mut sprites: Vec<_> = sprites.into_iter().collect();
+    if sprites.is_empty() {
+        return Vec::new();
+    }
+    let key = |x: &QueuedSprite| (x.is_ui(), x.z, Arc::as_ptr(&x.array));
+    sprites.sort_unstable_by_key(|x| key(x));
+    let instances: Vec<_> = sprites.iter().map(|x| x.instance).collect();
+    let instances = Arc::new(graphics.device.create_buffer_init(
+        &wgpu::util::BufferInitDescriptor {
+            label: Some("sprite_instance_buffer"),
+            contents: bytemuck::cast_slice(&instances),
+            usage: wgpu::BufferUsages::VERTEX,
+        },
+    ));
+
+    let mut batches: Vec<SpriteBatch> = Vec::new();
+    for (i, sprite) in sprites.into_iter().enumerate() {
+        let i = i as u32;
+        match batches.last_mut() {
+            Some(batch) if (batch.ui, batch.z, Arc::as_ptr(&batch.array)) == key(sprite) => {
+                batch.range.end = i + 1;
+            }
+            _ => batches.push(SpriteBatch {
+                array: sprite.array.clone(),
+                z: sprite.z,
+                ui: sprite.is_ui(),
+                instances: instances.clone(),
+                range: i..i + 1,
+            }),
+        }
+    }
+    batches
+}
+
+/// A quad showing one layer of a `TextureArray`, centered on its origin
+///
+/// Sprites cannot be clipped or masked, and are drawn after any polygon with the same z
+pub struct ArraySprite {
+    array: Arc<TextureArray>,
+    /// Replaced whenever the device is recreated
+    gpu: Arc<TextureArrayGpu>,
+    layer: NumberField<u32>,
+    origin: NumberField<Vector>,
+    rotation: NumberField<f32>,
+    size: NumberField<Vector>,
+    emission: NumberField<f32>,
+    z: NumberField<u32>,
+    layers: NumberField<RenderLayers>,
+    visible: NumberField<Visible>,
+}
+
+impl ArraySprite {
+    /// Creates a `size` large sprite showing the given layer of `array`
+    pub fn new(graphics: &Graphics, array: Arc<TextureArray>, layer: u32, size: Vector) -> Self {
+        Self {
+            gpu: array.get(graphics),
+            array,
+            layer: NumberField::new(layer),
+            origin: NumberField::new(Vector::new(0.0, 0.0)),
+            rotation: NumberField::new(0.0),
+            size: NumberField::new(size),
+            emission: NumberField::new(0.0),
+            z: NumberField::new(0),
+            layers: NumberField::new(RenderLayers::WORLD),
+            visible: NumberField::new(Visible::default()),
+        }
+    }
+
+    pub fn with_origin(mut self, origin: Vector) -> Self {
+        self.origin.set_inner(origin);
+        self
+    }
+
+    pub fn with_rotation(mut self, rotation: f32) -> Self {
+        self.rotation.set_inner(rotation);
+        self
+    }
+
+    pub fn with_z(mut self, z: u32) -> Self {
+        self.z.set_inner(z);
+        self
+    }
+
+    pub fn with_layers(mut self, layers: RenderLayers) -> Self {
+        self.layers.set_inner(layers);
+        self
+    }
+
+    pub fn with_emission(mut self, emission: f32) -> Self {
+        self.emission.set_inner(emission);
+        self
+    }
+}
+
+impl Component for ArraySprite {
+    type Reference<'a> = ArraySpriteRef<'a>;
+
+    fn get_ref<'a>(&'a self) -> Self::Reference<'a> {
+        ArraySpriteRef {
+            array: &self.array,
+            gpu: &self.gpu,
+            layer: self.layer.get_ref(),
+            origin: self.origin.get_ref(),
+            rotation: self.rotation.get_ref(),
+            size: self.size.get_ref(),
+            emission: self.emission.get_ref(),
+            z: self.z.get_ref(),
+            layers: self.layers.get_ref(),
+            visible: self.visible.get_ref(),
+        }
+    }
+
+    fn flush<E: bina_ecs::entity::Entity>(
+        &mut self,
+        _my_entity: bina_ecs::entity::EntityReference<bina_ecs::entity::Inaccessible<E>>,
+        universe: &bina_ecs::universe::Universe,
+    ) {
+        if let Some(graphics) = universe.try_get_singleton::<Graphics>() {
+            if graphics.inner.generation != self.gpu.generation {
+                self.gpu = self.array.get(graphics);
+            }
+        }
+        self.layer.process_modifiers();
+        self.origin.process_modifiers();
+        self.rotation.process_modifiers();
+        self.size.process_modifiers();
+        self.emission.process_modifiers();
+        self.z.process_modifiers();
+        self.layers.process_modifiers();
+        self.visible.process_modifiers();
+    }
+}
+
+impl Reflect for ArraySprite {
+    fn reflect_fields(&self, visitor: &mut dyn FnMut(&'static str, Field<'_>)) {
+        visitor("layer", Field::Number(&self.layer));
+        visitor("origin", Field::Number(&self.origin));
+        visitor("z", Field::Number(&self.z));
+        visitor("rotation", Field::Number(&self.rotation));
+        visitor("size", Field::Number(&self.size));
+        visitor("emission", Field::Number(&self.emission));
+    }
+}
+
+impl Processable for ArraySprite {
+    fn process<E: bina_ecs::entity::Entity>(
+        component: Self::Reference<'_>,
+        my_entity: bina_ecs::entity::EntityReference<E>,
+        universe: &bina_ecs::universe::Universe,
+    ) {
+        if !component.visible.0 {
+            return;
+        }
+        let graphics = unsafe { universe.try_get_singleton::<Graphics>().unwrap_unchecked() };
+        let layer = *component.layer;
+        // Layers past the end of the array would sample nothing
+        if layer as usize >= component.array.layer_count() {
+            return;
+        }
+
+        // Placed relative to the transform of the entity, if it has one
+        let my_entity: &dyn ErasedEntityReference = &my_entity;
+        let basis = basis_of(*component.rotation, *component.size);
+        let (basis, origin) = match my_entity.get_component::<Transform>() {
+            Some(transform) => transform.get_ref().apply(&basis, *component.origin),
+            None => (basis, *component.origin),
+        };
+        graphics.queue_array_sprite(QueuedSprite {
+            array: component.gpu.clone(),
+            z: *component.z,
+            layers: *component.layers,
+            instance: SpriteInstance {
+                basis: [basis.m11, basis.m12, basis.m21, basis.m22],
+                origin: [origin.x, origin.y],
+                layer,
+                emission: *component.emission,
+            },
+        });
+    }
+}
+
+pub struct ArraySpriteRef<'a> {
+    array: &'a Arc<TextureArray>,
+    gpu: &'a Arc<TextureArrayGpu>,
+    /// The layer of the array that is shown
+    pub layer: NumberFieldRef<'a, u32>,
+    pub origin: NumberFieldRef<'a, Vector>,
+    pub rotation: NumberFieldRef<'a, f32>,
+    /// The width and height of the quad
+    pub size: NumberFieldRef<'a, Vector>,
+    /// How much brighter than its texture this sprite is drawn, the same as for polygons
+    pub emission: NumberFieldRef<'a, f32>,
+    pub z: NumberFieldRef<'a, u32>,
+    pub layers: NumberFieldRef<'a, RenderLayers>,
+    pub visible: NumberFieldRef<'a, Visible>,
+}
+
+impl<'a> ArraySpriteRef<'a> {
+    pub fn array(&self) -> &Arc<TextureArray> {
+        self.array
+    }
+}
@@ -0,0 +1,385 @@
+//! Keyframed animations of the transforms of nodes, such as those imported from glTF
+//!
+//! Clips are played on a `Skeleton` by an `AnimationPlayer` in the same entity,
+//! which can crossfade smoothly from one clip to another
+use bina_ecs::{
+    component::{
+        Component, ComponentField, NumberField, NumberFieldRef, Processable, StagedMutField,
+        StagedMutFieldRef,
+    },
+    entity::ErasedEntityReference,
+    reflect::{Field, Reflect},
+    triomphe::Arc,
+};
+use nalgebra::{UnitQuaternion, Vector3};
+
+use super::{skinning::Skeleton, Transform};
+
+/// How values are found between two keyframes
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Interpolation {
+    /// Keeps the value of the previous keyframe until the next one
+    Step,
+    /// Blends smoothly between keyframes, using a spherical blend for rotations
+    Linear,
+}
+
+/// The keyframes of one part of a transform
+#[derive(Clone, Debug)]
+pub enum Keyframes {
+    Translation(Vec<Vector3<f32>>),
+    Rotation(Vec<UnitQuaternion<f32>>),
+    Scale(Vec<Vector3<f32>>),
+}
+
+/// Animates one part of the transform of one node
+#[derive(Clone, Debug)]
+pub struct Channel {
+    /// The index of the node that is animated
+    pub node: usize,
+    pub interpolation: Interpolation,
+    /// The time of each keyframe in seconds, in increasing order
+    pub times: Vec<f32>,
+    /// One value for each time
+    pub keyframes: Keyframes,
+}
+
+impl Channel {
+    /// Finds the keyframes on either side of `time`, and how far `time` is between them
+    fn locate(&self, time: f32) -> Option<(usize, usize, f32)> {
+        let last = self.times.len().checked_sub(1)?;
+        let next = self.times.partition_point(|x| *x <= time);
+        if next == 0 {
+            return Some((0, 0, 0.0));
+        }
+        if next > last {
+            return Some((last, last, 0.0));
+        }
+        let previous = next - 1;
+        let t = match self.interpolation {
+            Interpolation::Step => 0.0,
+            Interpolation::Linear => {
+                let span = self.times[next] - self.times[previous];
+                if span > 0.0 {
+                    (time - self.times[previous]) / span
+                } else {
+                    0.0
+                }
+            }
+        };
+        Some((previous, next, t))
+    }
+
+    /// Writes the value of this channel at `time` into the transform of its node
+    pub fn apply(&self, time: f32, transform: &mut Transform) {
+        let Some((a, b, t)) = self.locate(time) else {
+            return;
+        };
+        match &self.keyframes {
+            Keyframes::Translation(values) => {
+                if let (Some(a), Some(b)) = (values.get(a), values.get(b)) {
+                    transform.translation = a.lerp(b, t);
+                }
+            }
+            Keyframes::Rotation(values) => {
+                if let (Some(a), Some(b)) = (values.get(a), values.get(b)) {
+                    // Falls back to a normalized linear blend when the rotations are opposite
+                    transform.rotation = a.try_slerp(b, t, 1.0e-6).unwrap_or_else(|| a.nlerp(b, t));
+                }
+            }
+            Keyframes::Scale(values) => {
+                if let (Some(a), Some(b)) = (values.get(a), values.get(b)) {
+                    transform.scale = a.lerp(b, t);
+                }
+            }
+        }
+    }
+}
+
+/// A named animation of the transforms of several nodes
+#[derive(Clone, Debug, Default)]
+pub struct AnimationClip {
+    pub name: Option<String>,
+    /// The time of the last keyframe of every channel, in seconds
+    pub duration: f32,
+    pub channels: Vec<Channel>,
+}
+
+impl AnimationClip {
+    /// Writes the pose at `time` seconds into the local transforms of the nodes,
+    /// leaving every part of a transform that this clip does not animate as it was
+    ///
+    /// Times outside of the clip hold the first or last keyframe
+    pub fn sample(&self, time: f32, transforms: &mut [Transform]) {
+        for channel in &self.channels {
+            if let Some(transform) = transforms.get_mut(channel.node) {
+                channel.apply(time, transform);
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+struct PlayingClip {
+    clip: Arc<AnimationClip>,
+    /// In seconds from the start of the clip
+    time: f32,
+    looping: bool,
+}
+
+impl PlayingClip {
+    fn advance(&mut self, delta: f32) {
+        self.time += delta;
+        if self.looping && self.clip.duration > 0.0 {
+            self.time = self.time.rem_euclid(self.clip.duration);
+        } else {
+            self.time = self.time.clamp(0.0, self.clip.duration);
+        }
+    }
+
+    /// Samples the clip on top of the rest pose of a skeleton
+    fn pose(&self, rest_pose: &[Transform]) -> Vec<Transform> {
+        let mut pose = rest_pose.to_vec();
+        self.clip.sample(self.time, &mut pose);
+        pose
+    }
+}
+
+#[derive(Clone, Default)]
+struct Playback {
+    current: Option<PlayingClip>,
+    /// The clip that is being faded out of
+    previous: Option<PlayingClip>,
+    /// How long the crossfade has been going, in seconds
+    fade_time: f32,
+    fade_duration: f32,
+    paused: bool,
+}
+
+impl Playback {
+    fn advance(&mut self, delta: f32) {
+        if let Some(current) = &mut self.current {
+            current.advance(delta);
+        }
+        if let Some(previous) = &mut self.previous {
+            previous.advance(delta);
+            self.fade_time += delta.abs();
+            if self.fade_time >= self.fade_duration {
+                self.previous = None;
+            }
+        }
+    }
+
+    /// How much of the current clip is in the pose, from 0 to 1
+    fn weight(&self) -> f32 {
+        if self.previous.is_none() || self.fade_duration <= 0.0 {
+            1.0
+        } else {
+            (self.fade_time / self.fade_duration).clamp(0.0, 1.0)
+        }
+    }
+}
+
+/// Plays animation clips on the `Skeleton` in the same entity
+///
+/// Every process frame, the current clip is sampled into the pose of the skeleton, blended with the
+/// clip it is crossfading from, and then moved forward by the delta time
+///
+/// ```ignore
+/// universe.queue_add_entity((
+///     Skeleton::new(graphics, skeleton_data),
+///     AnimationPlayer::new().with_clip(walk, true),
+/// ));
+/// // Later, from a component that can reach the player
+/// player.queue_crossfade(run, true, 0.25);
+/// ```
+pub struct AnimationPlayer {
+    playback: StagedMutField<Playback>,
+    /// How many seconds of animation are played each second, which may be negative
+    speed: NumberField<f32>,
+}
+
+impl Default for AnimationPlayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clone for AnimationPlayer {
+    fn clone(&self) -> Self {
+        Self {
+            playback: StagedMutField::new(self.playback.get_inner().clone()),
+            speed: self.speed.clone(),
+        }
+    }
+}
+
+impl AnimationPlayer {
+    /// Creates a player that is not playing anything, which leaves the skeleton as it is
+    pub fn new() -> Self {
+        Self {
+            playback: StagedMutField::new(Playback::default()),
+            speed: NumberField::new(1.0),
+        }
+    }
+
+    /// Starts playing `clip` from the beginning
+    pub fn with_clip(mut self, clip: Arc<AnimationClip>, looping: bool) -> Self {
+        self.playback.get_inner_mut().current = Some(PlayingClip {
+            clip,
+            time: 0.0,
+            looping,
+        });
+        self
+    }
+
+    pub fn with_speed(mut self, speed: f32) -> Self {
+        self.speed.set_inner(speed);
+        self
+    }
+}
+
+impl Component for AnimationPlayer {
+    type Reference<'a> = AnimationPlayerRef<'a>;
+
+    fn get_ref<'a>(&'a self) -> Self::Reference<'a> {
+        AnimationPlayerRef {
+            playback: self.playback.get_ref(),
+            speed: self.speed.get_ref(),
+        }
+    }
+
+    fn flush<E: bina_ecs::entity::Entity>(
+        &mut self,
+        _my_entity: bina_ecs::entity::EntityReference<bina_ecs::entity::Inaccessible<E>>,
+        _universe: &bina_ecs::universe::Universe,
+    ) {
+        self.playback.process_modifiers();
+        self.speed.process_modifiers();
+    }
+}
+
+impl Reflect for AnimationPlayer {
+    fn reflect_fields(&self, visitor: &mut dyn FnMut(&'static str, Field<'_>)) {
+        let playback = self.playback.get_inner();
+        let current = playback.current.as_ref();
+        visitor(
+            "clip",
+            Field::Debug(&current.and_then(|x| x.clip.name.as_deref())),
+        );
+        visitor("time", Field::Debug(&current.map(|x| x.time)));
+        visitor("paused", Field::Debug(&playback.paused));
+        visitor("speed", Field::Number(&self.speed));
+    }
+}
+
+impl Processable for AnimationPlayer {
+    fn process<E: bina_ecs::entity::Entity>(
+        component: Self::Reference<'_>,
+        my_entity: bina_ecs::entity::EntityReference<E>,
+        universe: &bina_ecs::universe::Universe,
+    ) {
+        let playback = &*component.playback;
+        let Some(current) = &playback.current else {
+            return;
+        };
+        let my_entity: &dyn ErasedEntityReference = &my_entity;
+        if let Some(skeleton) = my_entity.get_component::<Skeleton>() {
+            let rest_pose = &skeleton.data().rest_pose;
+            let mut pose = current.pose(rest_pose);
+            if let Some(previous) = &playback.previous {
+                let weight = playback.weight();
+                for (to, from) in pose.iter_mut().zip(previous.pose(rest_pose)) {
+                    *to = from.lerp(to, weight);
+                }
+            }
+            skeleton.get_ref().queue_set_pose(pose);
+        }
+        if !playback.paused {
+            let delta = universe.get_delta() * component.speed.get();
+            component
+                .playback
+                .queue_modifier(move |playback| playback.advance(delta));
+        }
+    }
+}
+
+pub struct AnimationPlayerRef<'a> {
+    playback: StagedMutFieldRef<'a, Playback>,
+    pub speed: NumberFieldRef<'a, f32>,
+}
+
+impl<'a> AnimationPlayerRef<'a> {
+    /// Gets the clip that is playing, or the clip that finished playing last
+    pub fn clip(&self) -> Option<&Arc<AnimationClip>> {
+        self.playback.current.as_ref().map(|x| &x.clip)
+    }
+
+    /// Gets how far into the current clip the player is, in seconds
+    pub fn time(&self) -> f32 {
+        self.playback.current.as_ref().map_or(0.0, |x| x.time)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.playback.paused
+    }
+
+    /// Checks if the current clip does not loop and has reached its end, or if nothing is playing
+    pub fn is_finished(&self) -> bool {
+        match &self.playback.current {
+            Some(current) => !current.looping && current.time >= current.clip.duration,
+            None => true,
+        }
+    }
+
+    /// Switches to `clip` immediately after the current process frame ends, starting from the beginning
+    pub fn queue_play(&self, clip: Arc<AnimationClip>, looping: bool) {
+        self.playback.queue_modifier(move |playback| {
+            playback.current = Some(PlayingClip {
+                clip,
+                time: 0.0,
+                looping,
+            });
+            playback.previous = None;
+        });
+    }
+
+    /// Blends from the current pose into `clip` over `duration` seconds after the current process frame ends
+    ///
+    /// Crossfading while another crossfade is in progress fades out of the clip that was fading in
+    pub fn queue_crossfade(&self, clip: Arc<AnimationClip>, looping: bool, duration: f32) {
+        self.playback.queue_modifier(move |playback| {
+            playback.previous = playback.current.take();
+            playback.current = Some(PlayingClip {
+                clip,
+                time: 0.0,
+                looping,
+            });
+            playback.fade_time = 0.0;
+            playback.fade_duration = duration;
+        });
+    }
+
+    /// Stops playing after the current process frame ends, leaving the skeleton in its last pose
+    pub fn queue_stop(&self) {
+        self.playback.queue_modifier(|playback| {
+            playback.current = None;
+            playback.previous = None;
+        });
+    }
+
+    pub fn queue_set_paused(&self, paused: bool) {
+        self.playback
+            .queue_modifier(move |playback| playback.paused = paused);
+    }
+
+    /// Jumps to `time` seconds into the current clip after the current process frame ends
+    pub fn queue_seek(&self, time: f32) {
+        self.playback.queue_modifier(move |playback| {
+            if let Some(current) = &mut playback.current {
+                current.time = 0.0;
+                current.advance(time);
+            }
+        });
+    }
+}
@@ -0,0 +1,1014 @@
+//! Importing scenes from glTF 2.0 files
+//!
+//! Both binary `.glb` files and `.gltf` files are supported, but a `.gltf` file must embed its
+//! buffers and images as base64 data URIs, as loaders only see the bytes of a single file.
+//! Meshes keep their first set of texture coordinates and the base color of their material.
+//! Cameras, lights and morph targets are ignored
+use std::collections::{hash_map::Entry, HashMap};
+
+use bina_ecs::{assets::AssetLoader, triomphe::Arc, universe::Universe};
+use image::{Rgba, RgbaImage};
+use nalgebra::{Matrix3, Matrix4, Quaternion, Rotation3, UnitQuaternion, Vector3};
+
+use super::{
+    animation::{AnimationClip, AnimationPlayer, Channel, Interpolation, Keyframes},
+    skinning::{Joint, Skeleton, SkeletonData, VertexSkin},
+    Material3D, Mesh, MeshData, Transform, Vertex3D,
+};
+use crate::{
+    json::Json,
+    texture::{Filter, SamplerOptions, TextureAsset, Wrap},
+    Graphics,
+};
+
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const JSON_CHUNK: u32 = 0x4E4F534A;
+const BIN_CHUNK: u32 = 0x004E4942;
+/// The most values an accessor without a buffer view can have, as nothing else bounds its count
+const MAX_ZEROED_VALUES: usize = 1 << 24;
+
+/// One part of a mesh with a single material
+pub struct GltfPrimitive {
+    pub data: Arc<MeshData>,
+    /// The index of the material in `GltfAsset::materials`, or the default material if `None`
+    pub material: Option<usize>,
+}
+
+pub struct GltfMaterial {
+    pub name: Option<String>,
+    /// Converted from the linear color stored in the file
+    pub base_color: Rgba<u8>,
+    /// The index of the texture in `GltfAsset::textures`
+    pub texture: Option<usize>,
+}
+
+pub struct GltfNode {
+    pub name: Option<String>,
+    /// Relative to the parent of the node
+    pub transform: Transform,
+    /// The index of the mesh in `GltfAsset::meshes`
+    pub mesh: Option<usize>,
+    /// The index of the skin in `GltfAsset::skins` that moves the mesh
+    pub skin: Option<usize>,
+    pub children: Vec<usize>,
+}
+
+/// The joints that a skinned mesh is bound to
+pub struct GltfSkin {
+    pub name: Option<String>,
+    /// The index of the node of every joint
+    pub joints: Vec<usize>,
+    /// One for each joint
+    pub inverse_bind_matrices: Vec<Matrix4<f32>>,
+}
+
+/// A scene imported from a glTF file
+///
+/// The universe has no hierarchy of entities, so the hierarchy of nodes stays in the asset,
+/// and every mesh is spawned with the transform it has in the world
+///
+/// ```ignore
+/// let scene: Handle<GltfAsset> = assets.load(universe, "models/house.glb");
+/// if let Some(scene) = scene.get() {
+///     scene.spawn(graphics, universe, Transform::IDENTITY);
+/// }
+/// ```
+pub struct GltfAsset {
+    /// Every mesh, as a list of primitives
+    pub meshes: Vec<Vec<GltfPrimitive>>,
+    pub materials: Vec<GltfMaterial>,
+    pub textures: Vec<Arc<TextureAsset>>,
+    pub nodes: Vec<GltfNode>,
+    pub skins: Vec<GltfSkin>,
+    /// The nodes at the top of the default scene
+    pub roots: Vec<usize>,
+    pub animations: Vec<AnimationClip>,
+}
+
+impl GltfAsset {
+    /// Parses a `.glb` or `.gltf` file
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let (json, bin) = if bytes.starts_with(GLB_MAGIC) {
+            split_glb(bytes)?
+        } else {
+            let text = std::str::from_utf8(bytes).map_err(|e| e.to_string())?;
+            (Json::parse(text)?, None)
+        };
+        Document::new(json, bin)?.import()
+    }
+
+    pub fn find_node(&self, name: &str) -> Option<usize> {
+        self.nodes
+            .iter()
+            .position(|node| node.name.as_deref() == Some(name))
+    }
+
+    pub fn find_animation(&self, name: &str) -> Option<&AnimationClip> {
+        self.animations
+            .iter()
+            .find(|clip| clip.name.as_deref() == Some(name))
+    }
+
+    /// Gets the transform of every node relative to its parent, as stored in the file
+    ///
+    /// This is the pose that animations are sampled into
+    pub fn local_transforms(&self) -> Vec<Transform> {
+        self.nodes.iter().map(|node| node.transform).collect()
+    }
+
+    /// Gets the transform of every node in the world from the transforms of every node relative to
+    /// its parent, with the whole scene placed at `root`
+    ///
+    /// Nodes that are not part of the default scene keep the identity transform
+    pub fn world_transforms(&self, root: Transform, locals: &[Transform]) -> Vec<Transform> {
+        let mut world = vec![Transform::IDENTITY; self.nodes.len()];
+        let mut visited = vec![false; self.nodes.len()];
+        let mut stack: Vec<(usize, Transform)> =
+            self.roots.iter().map(|&node| (node, root)).collect();
+        while let Some((node, parent)) = stack.pop() {
+            // A valid file never visits a node twice, but this stops a cycle from looping forever
+            if std::mem::replace(&mut visited[node], true) {
+                continue;
+            }
+            let local = locals.get(node).unwrap_or(&self.nodes[node].transform);
+            world[node] = parent.mul_transform(local);
+            stack.extend(
+                self.nodes[node]
+                    .children
+                    .iter()
+                    .map(|&child| (child, world[node])),
+            );
+        }
+        world
+    }
+
+    /// Creates the material of a primitive, uploading its texture if needed
+    pub fn material(&self, graphics: &Graphics, index: Option<usize>) -> Material3D {
+        let Some(material) = index.and_then(|i| self.materials.get(i)) else {
+            return Material3D::default();
+        };
+        Material3D {
+            base_color: material.base_color,
+            texture: material
+                .texture
+                .and_then(|i| self.textures.get(i))
+                .map(|texture| texture.get(graphics)),
+            emission: 0.0,
+        }
+    }
+
+    /// Creates a skeleton with a bone for every node, so that every animation in the file can be
+    /// played on it, and a joint for every joint of the given skin
+    pub fn skeleton(&self, skin: usize) -> Option<SkeletonData> {
+        let skin = self.skins.get(skin)?;
+        let mut parents = vec![None; self.nodes.len()];
+        for (i, node) in self.nodes.iter().enumerate() {
+            for &child in &node.children {
+                parents[child] = Some(i);
+            }
+        }
+        Some(SkeletonData {
+            names: self.nodes.iter().map(|node| node.name.clone()).collect(),
+            parents,
+            rest_pose: self.local_transforms(),
+            joints: skin
+                .joints
+                .iter()
+                .enumerate()
+                .map(|(i, &bone)| Joint {
+                    bone,
+                    inverse_bind_matrix: skin
+                        .inverse_bind_matrices
+                        .get(i)
+                        .copied()
+                        .unwrap_or_else(Matrix4::identity),
+                })
+                .collect(),
+        })
+    }
+
+    /// Queues an entity for every primitive of every mesh in the default scene,
+    /// with the whole scene placed at `root`
+    ///
+    /// Every skin gets an entity with a `Skeleton` and an idle `AnimationPlayer`
+    pub fn spawn(&self, graphics: &Graphics, universe: &Universe, root: Transform) {
+        self.spawn_with_player(graphics, universe, root, AnimationPlayer::new());
+    }
+
+    /// Like `spawn`, but every skeleton is given a copy of `player`, such as one that is already
+    /// playing one of the animations of this asset
+    pub fn spawn_with_player(
+        &self,
+        graphics: &Graphics,
+        universe: &Universe,
+        root: Transform,
+        player: AnimationPlayer,
+    ) {
+        let world = self.world_transforms(root, &self.local_transforms());
+        let mut skins = HashMap::new();
+        for (node, transform) in self.nodes.iter().zip(world) {
+            let Some(primitives) = node.mesh.and_then(|i| self.meshes.get(i)) else {
+                continue;
+            };
+            // Skinned meshes are placed by their joints, so the transform of their node is ignored
+            let skin = node.skin.and_then(|i| {
+                if let Entry::Vacant(entry) = skins.entry(i) {
+                    let skeleton = Skeleton::new(graphics, Arc::new(self.skeleton(i)?));
+                    entry.insert(skeleton.skin());
+                    universe.queue_add_entity((skeleton, player.clone()));
+                }
+                skins.get(&i).cloned()
+            });
+            for primitive in primitives {
+                let mut mesh = Mesh::new(
+                    graphics,
+                    primitive.data.clone(),
+                    self.material(graphics, primitive.material),
+                );
+                mesh = match &skin {
+                    Some(skin) => mesh.with_transform(root).with_skin(skin.clone()),
+                    None => mesh.with_transform(transform),
+                };
+                universe.queue_add_entity((mesh,));
+            }
+        }
+    }
+}
+
+/// Splits a `.glb` file into its JSON and its binary buffer
+fn split_glb(bytes: &[u8]) -> Result<(Json, Option<Vec<u8>>), String> {
+    let word = |offset: usize| {
+        bytes
+            .get(offset..offset + 4)
+            .map(|x| u32::from_le_bytes([x[0], x[1], x[2], x[3]]))
+            .ok_or_else(|| "The file ends in the middle of a chunk".to_string())
+    };
+    if word(4)? != 2 {
+        return Err("Only version 2 of glTF is supported".into());
+    }
+    let length = (word(8)? as usize).min(bytes.len());
+    let mut offset = 12;
+    let mut json = None;
+    let mut bin = None;
+    while offset + 8 <= length {
+        let chunk_length = word(offset)? as usize;
+        let chunk_type = word(offset + 4)?;
+        let data = (offset + 8)
+            .checked_add(chunk_length)
+            .and_then(|end| bytes.get(offset + 8..end))
+            .ok_or("The file ends in the middle of a chunk")?;
+        match chunk_type {
+            JSON_CHUNK => {
+                let text = std::str::from_utf8(data).map_err(|e| e.to_string())?;
+                json = Some(Json::parse(text)?);
+            }
+            BIN_CHUNK if bin.is_none() => bin = Some(data.to_vec()),
+            _ => {}
+        }
+        // Chunks are padded to 4 bytes
+        offset += 8 + chunk_length.next_multiple_of(4);
+    }
+    Ok((json.ok_or("The file has no JSON chunk")?, bin))
+}
+
+/// Decodes the data in a `data:` URI
+fn decode_data_uri(uri: &str) -> Result<Vec<u8>, String> {
+    let Some(data) = uri.strip_prefix("data:") else {
+        return Err(format!(
+            "{uri} is a separate file, which can only be loaded if it is embedded or a .glb file is used"
+        ));
+    };
+    let (header, data) = data.split_once(',').ok_or("A data URI has no data")?;
+    if !header.ends_with(";base64") {
+        return Err("Only base64 data URIs are supported".into());
+    }
+    decode_base64(data)
+}
+
+fn decode_base64(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::with_capacity(text.len() / 4 * 3);
+    let mut bits = 0u32;
+    let mut bit_count = 0;
+    for c in text.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => break,
+            c if c.is_ascii_whitespace() => continue,
+            _ => return Err("Invalid base64".into()),
+        };
+        bits = bits << 6 | value as u32;
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            bytes.push((bits >> bit_count) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+/// Converts a linear color channel into sRGB
+fn srgb(x: f32) -> u8 {
+    let x = x.clamp(0.0, 1.0);
+    let x = if x <= 0.0031308 {
+        x * 12.92
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    };
+    (x * 255.0).round() as u8
+}
+
+/// The numbers in an accessor, with every element flattened into `components` numbers
+struct AccessorData {
+    values: Vec<f64>,
+    components: usize,
+}
+
+impl AccessorData {
+    /// Gets every element, which must have exactly `N` components
+    fn elements<const N: usize>(&self) -> Result<Vec<[f32; N]>, String> {
+        if self.components != N {
+            return Err(format!(
+                "Expected an accessor with {N} components, but it has {}",
+                self.components
+            ));
+        }
+        Ok(self
+            .values
+            .chunks_exact(N)
+            .map(|x| std::array::from_fn(|i| x[i] as f32))
+            .collect())
+    }
+}
+
+struct Document {
+    json: Json,
+    buffers: Vec<Vec<u8>>,
+}
+
+impl Document {
+    fn new(json: Json, mut bin: Option<Vec<u8>>) -> Result<Self, String> {
+        if let Some(version) = json.get("asset").get("version").as_str() {
+            if !version.starts_with('2') {
+                return Err(format!("Version {version} of glTF is not supported"));
+            }
+        }
+        let mut buffers = Vec::new();
+        for buffer in json.get("buffers").as_array() {
+            buffers.push(match buffer.get("uri").as_str() {
+                Some(uri) => decode_data_uri(uri)?,
+                // Only the first buffer of a .glb file can be its binary chunk
+                None => bin.take().ok_or("A buffer has no data")?,
+            });
+        }
+        Ok(Self { json, buffers })
+    }
+
+    fn array(&self, name: &str) -> &[Json] {
+        self.json.get(name).as_array()
+    }
+
+    fn item(&self, name: &str, index: usize) -> Result<&Json, String> {
+        self.array(name)
+            .get(index)
+            .ok_or_else(|| format!("There is no item {index} in {name}"))
+    }
+
+    fn buffer_view(&self, index: usize) -> Result<(&[u8], Option<usize>), String> {
+        let view = self.item("bufferViews", index)?;
+        let buffer = view
+            .get("buffer")
+            .as_usize()
+            .and_then(|i| self.buffers.get(i))
+            .ok_or("A buffer view has an invalid buffer")?;
+        let offset = view.get("byteOffset").as_usize().unwrap_or(0);
+        let length = view
+            .get("byteLength")
+            .as_usize()
+            .ok_or("A buffer view has no length")?;
+        let bytes = offset
+            .checked_add(length)
+            .and_then(|end| buffer.get(offset..end))
+            .ok_or("A buffer view is outside of its buffer")?;
+        Ok((bytes, view.get("byteStride").as_usize()))
+    }
+
+    fn accessor(&self, index: usize) -> Result<AccessorData, String> {
+        let accessor = self.item("accessors", index)?;
+        if !accessor.get("sparse").is_null() {
+            return Err("Sparse accessors are not supported".into());
+        }
+        let components = match accessor.get("type").as_str() {
+            Some("SCALAR") => 1,
+            Some("VEC2") => 2,
+            Some("VEC3") => 3,
+            Some("VEC4") | Some("MAT2") => 4,
+            Some("MAT3") => 9,
+            Some("MAT4") => 16,
+            _ => return Err("An accessor has an invalid type".into()),
+        };
+        let component_type = accessor.get("componentType").as_usize();
+        let size = match component_type {
+            Some(5120 | 5121) => 1,
+            Some(5122 | 5123) => 2,
+            Some(5125 | 5126) => 4,
+            _ => return Err("An accessor has an invalid component type".into()),
+        };
+        let count = accessor
+            .get("count")
+            .as_usize()
+            .ok_or("An accessor has no count")?;
+        let normalized = accessor.get("normalized").as_bool().unwrap_or(false);
+
+        // An accessor without a buffer view is all zeros
+        let Some(view) = accessor.get("bufferView").as_usize() else {
+            let len = count
+                .checked_mul(components)
+                .filter(|&len| len <= MAX_ZEROED_VALUES)
+                .ok_or("An accessor without a buffer view has too many values")?;
+            return Ok(AccessorData {
+                values: vec![0.0; len],
+                components,
+            });
+        };
+        let (bytes, stride) = self.buffer_view(view)?;
+        let offset = accessor.get("byteOffset").as_usize().unwrap_or(0);
+        let element_size = size * components;
+        let stride = stride.unwrap_or(element_size);
+        if stride < element_size {
+            return Err("An accessor has elements that overlap".into());
+        }
+        // Checked before allocating, so that a hostile count cannot run out of memory,
+        // and so that no offset below can overflow
+        let end = match count.checked_sub(1) {
+            Some(last) => last
+                .checked_mul(stride)
+                .and_then(|x| x.checked_add(offset))
+                .and_then(|x| x.checked_add(element_size)),
+            None => Some(offset),
+        };
+        if end.map_or(true, |end| end > bytes.len()) {
+            return Err("An accessor is outside of its buffer view".into());
+        }
+
+        let mut values = Vec::with_capacity(count * components);
+        for element in 0..count {
+            for component in 0..components {
+                let start = offset + element * stride + component * size;
+                let x = &bytes[start..start + size];
+                let value = match component_type {
+                    Some(5120) => {
+                        let x = x[0] as i8 as f64;
+                        if normalized {
+                            (x / 127.0).max(-1.0)
+                        } else {
+                            x
+                        }
+                    }
+                    Some(5121) => {
+                        let x = x[0] as f64;
+                        if normalized {
+                            x / 255.0
+                        } else {
+                            x
+                        }
+                    }
+                    Some(5122) => {
+                        let x = i16::from_le_bytes([x[0], x[1]]) as f64;
+                        if normalized {
+                            (x / 32767.0).max(-1.0)
+                        } else {
+                            x
+                        }
+                    }
+                    Some(5123) => {
+                        let x = u16::from_le_bytes([x[0], x[1]]) as f64;
+                        if normalized {
+                            x / 65535.0
+                        } else {
+                            x
+                        }
+                    }
+                    Some(5125) => u32::from_le_bytes([x[0], x[1], x[2], x[3]]) as f64,
+                    _ => f32::from_le_bytes([x[0], x[1], x[2], x[3]]) as f64,
+                };
+                values.push(value);
+            }
+        }
+        Ok(AccessorData { values, components })
+    }
+
+    fn import(&self) -> Result<GltfAsset, String> {
+        let textures = self.textures()?;
+        let materials = self.materials(textures.len())?;
+        let meshes = self
+            .array("meshes")
+            .iter()
+            .map(|mesh| {
+                let name = mesh.get("name").as_str();
+                mesh.get("primitives")
+                    .as_array()
+                    .iter()
+                    .filter_map(|primitive| self.primitive(primitive, name).transpose())
+                    .collect::<Result<Vec<_>, String>>()
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let nodes = self.nodes()?;
+
+        let scene = self
+            .json
+            .get("scene")
+            .as_usize()
+            .and_then(|i| self.array("scenes").get(i))
+            .or_else(|| self.array("scenes").first());
+        let roots = match scene {
+            Some(scene) => scene
+                .get("nodes")
+                .as_array()
+                .iter()
+                .filter_map(Json::as_usize)
+                .filter(|&i| i < nodes.len())
+                .collect(),
+            // Without a scene, every node without a parent is part of the scene
+            None => {
+                let mut has_parent = vec![false; nodes.len()];
+                for node in &nodes {
+                    for &child in &node.children {
+                        has_parent[child] = true;
+                    }
+                }
+                (0..nodes.len()).filter(|&i| !has_parent[i]).collect()
+            }
+        };
+
+        let skins = self
+            .array("skins")
+            .iter()
+            .map(|skin| self.skin(skin, nodes.len()))
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let animations = self
+            .array("animations")
+            .iter()
+            .map(|animation| self.animation(animation, nodes.len()))
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(GltfAsset {
+            meshes,
+            materials,
+            textures,
+            nodes,
+            skins,
+            roots,
+            animations,
+        })
+    }
+
+    fn textures(&self) -> Result<Vec<Arc<TextureAsset>>, String> {
+        // Images are decoded once, even if several textures sample them differently
+        let mut images: HashMap<usize, RgbaImage> = HashMap::new();
+        let mut textures = Vec::new();
+        for texture in self.array("textures") {
+            let source = texture
+                .get("source")
+                .as_usize()
+                .ok_or("A texture has no image")?;
+            if let Entry::Vacant(entry) = images.entry(source) {
+                entry.insert(self.image(source)?);
+            }
+
+            let sampler = texture
+                .get("sampler")
+                .as_usize()
+                .and_then(|i| self.array("samplers").get(i));
+            let filter = match sampler.and_then(|x| x.get("magFilter").as_usize()) {
+                Some(9728) => Filter::Nearest,
+                _ => Filter::Linear,
+            };
+            let wrap = match sampler.and_then(|x| x.get("wrapS").as_usize()) {
+                Some(33071) => Wrap::Clamp,
+                Some(33648) => Wrap::MirrorRepeat,
+                _ => Wrap::Repeat,
+            };
+            let mut asset = TextureAsset::new(
+                images[&source].clone(),
+                SamplerOptions {
+                    filter,
+                    wrap,
+                    ..SamplerOptions::LINEAR
+                },
+            );
+            let image = self.item("images", source)?;
+            if let Some(name) = texture.get("name").as_str().or(image.get("name").as_str()) {
+                asset = asset.with_name(name);
+            }
+            textures.push(Arc::new(asset));
+        }
+        Ok(textures)
+    }
+
+    fn image(&self, index: usize) -> Result<RgbaImage, String> {
+        let image = self.item("images", index)?;
+        let bytes = match (
+            image.get("bufferView").as_usize(),
+            image.get("uri").as_str(),
+        ) {
+            (Some(view), _) => self.buffer_view(view)?.0.to_vec(),
+            (None, Some(uri)) => decode_data_uri(uri)?,
+            (None, None) => return Err("An image has no data".into()),
+        };
+        let image = image::load_from_memory(&bytes).map_err(|e| e.to_string())?;
+        Ok(image.to_rgba8())
+    }
+
+    fn materials(&self, texture_count: usize) -> Result<Vec<GltfMaterial>, String> {
+        self.array("materials")
+            .iter()
+            .map(|material| {
+                let pbr = material.get("pbrMetallicRoughness");
+                let [r, g, b, a] = pbr.get("baseColorFactor").as_floats().unwrap_or([1.0; 4]);
+                let texture = pbr.get("baseColorTexture").get("index").as_usize();
+                if texture.is_some_and(|i| i >= texture_count) {
+                    return Err("A material has an invalid texture".to_string());
+                }
+                Ok(GltfMaterial {
+                    name: material.get("name").as_str().map(Into::into),
+                    base_color: Rgba([
+                        srgb(r),
+                        srgb(g),
+                        srgb(b),
+                        (a.clamp(0.0, 1.0) * 255.0).round() as u8,
+                    ]),
+                    texture,
+                })
+            })
+            .collect()
+    }
+
+    /// Reads a primitive, or `None` if it is made of points or lines
+    fn primitive(&self, primitive: &Json, name: Option<&str>) -> Result<Option<GltfPrimitive>, String> {
+        let mode = primitive.get("mode").as_usize().unwrap_or(4);
+        if mode < 4 {
+            return Ok(None);
+        }
+        let attributes = primitive.get("attributes");
+        let positions = self
+            .accessor(
+                attributes
+                    .get("POSITION")
+                    .as_usize()
+                    .ok_or("A primitive has no positions")?,
+            )?
+            .elements::<3>()?;
+        let normals = match attributes.get("NORMAL").as_usize() {
+            Some(i) => Some(self.accessor(i)?.elements::<3>()?),
+            None => None,
+        };
+        let tex_coords = match attributes.get("TEXCOORD_0").as_usize() {
+            Some(i) => Some(self.accessor(i)?.elements::<2>()?),
+            None => None,
+        };
+        let skin = match (
+            attributes.get("JOINTS_0").as_usize(),
+            attributes.get("WEIGHTS_0").as_usize(),
+        ) {
+            (Some(joints), Some(weights)) => {
+                let joints = self.accessor(joints)?.elements::<4>()?;
+                let weights = self.accessor(weights)?.elements::<4>()?;
+                if joints.len() != positions.len() || weights.len() != positions.len() {
+                    return Err(
+                        "A primitive has joints or weights for the wrong number of vertices".into(),
+                    );
+                }
+                joints
+                    .into_iter()
+                    .zip(weights)
+                    .map(|(joints, weights)| VertexSkin {
+                        joints: joints.map(|x| x as u32),
+                        weights,
+                    })
+                    .collect()
+            }
+            _ => Vec::new(),
+        };
+
+        let vertices = positions
+            .iter()
+            .enumerate()
+            .map(|(i, &position)| Vertex3D {
+                position,
+                normal: normals
+                    .as_ref()
+                    .and_then(|x| x.get(i).copied())
+                    .unwrap_or_default(),
+                tex_coords: tex_coords
+                    .as_ref()
+                    .and_then(|x| x.get(i).copied())
+                    .unwrap_or_default(),
+            })
+            .collect::<Vec<_>>();
+        let order: Vec<u32> = match primitive.get("indices").as_usize() {
+            Some(i) => {
+                let indices = self.accessor(i)?;
+                if indices.components != 1 {
+                    return Err("Indices must be scalars".into());
+                }
+                indices.values.iter().map(|&x| x as u32).collect()
+            }
+            None => (0..vertices.len() as u32).collect(),
+        };
+        if order.iter().any(|&i| i as usize >= vertices.len()) {
+            return Err("A primitive has an index outside of its vertices".into());
+        }
+
+        let mut indices = Vec::new();
+        match mode {
+            // Every other triangle of a strip is wound the other way, so it is flipped back
+            5 => {
+                for i in 0..order.len().saturating_sub(2) {
+                    if i % 2 == 0 {
+                        indices.extend([order[i], order[i + 1], order[i + 2]]);
+                    } else {
+                        indices.extend([order[i + 1], order[i], order[i + 2]]);
+                    }
+                }
+            }
+            6 => {
+                for i in 1..order.len().saturating_sub(1) {
+                    indices.extend([order[0], order[i], order[i + 1]]);
+                }
+            }
+            _ => indices.extend(order.chunks_exact(3).flatten()),
+        }
+
+        let material = primitive.get("material").as_usize();
+        if material.is_some_and(|i| i >= self.array("materials").len()) {
+            return Err("A primitive has an invalid material".into());
+        }
+        let mut data = MeshData {
+            vertices,
+            indices,
+            skin,
+            name: name.map(Into::into),
+        };
+        if normals.is_none() {
+            data.compute_normals();
+        }
+        Ok(Some(GltfPrimitive {
+            data: Arc::new(data),
+            material,
+        }))
+    }
+
+    fn nodes(&self) -> Result<Vec<GltfNode>, String> {
+        let count = self.array("nodes").len();
+        self.array("nodes")
+            .iter()
+            .map(|node| {
+                let transform = match node.get("matrix").as_floats::<16>() {
+                    Some(matrix) => decompose(&Matrix4::from_column_slice(&matrix)),
+                    None => {
+                        let mut transform = Transform::IDENTITY;
+                        if let Some(translation) = node.get("translation").as_floats::<3>() {
+                            transform.translation = translation.into();
+                        }
+                        if let Some([x, y, z, w]) = node.get("rotation").as_floats::<4>() {
+                            transform.rotation =
+                                UnitQuaternion::new_normalize(Quaternion::new(w, x, y, z));
+                        }
+                        if let Some(scale) = node.get("scale").as_floats::<3>() {
+                            transform.scale = scale.into();
+                        }
+                        transform
+                    }
+                };
+                let children = node
+                    .get("children")
+                    .as_array()
+                    .iter()
+                    .map(|child| child.as_usize().filter(|&i| i < count))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or("A node has an invalid child")?;
+                let mesh = node.get("mesh").as_usize();
+                if mesh.is_some_and(|i| i >= self.array("meshes").len()) {
+                    return Err("A node has an invalid mesh".to_string());
+                }
+                let skin = node.get("skin").as_usize();
+                if skin.is_some_and(|i| i >= self.array("skins").len()) {
+                    return Err("A node has an invalid skin".to_string());
+                }
+                Ok(GltfNode {
+                    name: node.get("name").as_str().map(Into::into),
+                    transform,
+                    mesh,
+                    skin,
+                    children,
+                })
+            })
+            .collect()
+    }
+
+    fn skin(&self, skin: &Json, node_count: usize) -> Result<GltfSkin, String> {
+        let joints = skin
+            .get("joints")
+            .as_array()
+            .iter()
+            .map(|joint| joint.as_usize().filter(|&i| i < node_count))
+            .collect::<Option<Vec<_>>>()
+            .ok_or("A skin has an invalid joint")?;
+        let inverse_bind_matrices = match skin.get("inverseBindMatrices").as_usize() {
+            Some(i) => self
+                .accessor(i)?
+                .elements::<16>()?
+                .iter()
+                .map(|x| Matrix4::from_column_slice(x))
+                .collect(),
+            // Without inverse bind matrices, every joint was bound at the origin
+            None => vec![Matrix4::identity(); joints.len()],
+        };
+        Ok(GltfSkin {
+            name: skin.get("name").as_str().map(Into::into),
+            joints,
+            inverse_bind_matrices,
+        })
+    }
+
+    fn animation(&self, animation: &Json, node_count: usize) -> Result<AnimationClip, String> {
+        let samplers = animation.get("samplers").as_array();
+        let mut clip = AnimationClip {
+            name: animation.get("name").as_str().map(Into::into),
+            ..Default::default()
+        };
+        for channel in animation.get("channels").as_array() {
+            let target = channel.get("target");
+            // Channels without a node are meant for extensions
+            let Some(node) = target.get("node").as_usize() else {
+                continue;
+            };
+            if node >= node_count {
+                return Err("An animation targets an invalid node".into());
+            }
+            let sampler = channel
+                .get("sampler")
+                .as_usize()
+                .and_then(|i| samplers.get(i))
+                .ok_or("An animation channel has an invalid sampler")?;
+            let times = self
+                .accessor(
+                    sampler
+                        .get("input")
+                        .as_usize()
+                        .ok_or("An animation sampler has no input")?,
+                )?
+                .elements::<1>()?
+                .into_iter()
+                .map(|[x]| x)
+                .collect::<Vec<_>>();
+            let output = self.accessor(
+                sampler
+                    .get("output")
+                    .as_usize()
+                    .ok_or("An animation sampler has no output")?,
+            )?;
+            let (interpolation, cubic) = match sampler.get("interpolation").as_str() {
+                Some("STEP") => (Interpolation::Step, false),
+                // Cubic splines are blended linearly between their values
+                Some("CUBICSPLINE") => (Interpolation::Linear, true),
+                _ => (Interpolation::Linear, false),
+            };
+            let keyframes = match target.get("path").as_str() {
+                Some("translation") => Keyframes::Translation(
+                    keep_values(output.elements::<3>()?, cubic)
+                        .into_iter()
+                        .map(Vector3::from)
+                        .collect(),
+                ),
+                Some("scale") => Keyframes::Scale(
+                    keep_values(output.elements::<3>()?, cubic)
+                        .into_iter()
+                        .map(Vector3::from)
+                        .collect(),
+                ),
+                Some("rotation") => Keyframes::Rotation(
+                    keep_values(output.elements::<4>()?, cubic)
+                        .into_iter()
+                        .map(|[x, y, z, w]| {
+                            UnitQuaternion::new_normalize(Quaternion::new(w, x, y, z))
+                        })
+                        .collect(),
+                ),
+                // Morph target weights are not supported
+                _ => continue,
+            };
+            clip.duration = clip.duration.max(times.last().copied().unwrap_or(0.0));
+            clip.channels.push(Channel {
+                node,
+                interpolation,
+                times,
+                keyframes,
+            });
+        }
+        Ok(clip)
+    }
+}
+
+/// Drops the tangents on either side of every value of a cubic spline
+fn keep_values<T>(values: Vec<T>, cubic: bool) -> Vec<T> {
+    if cubic {
+        values.into_iter().skip(1).step_by(3).collect()
+    } else {
+        values
+    }
+}
+
+/// Splits a matrix into a translation, rotation and scale, ignoring any shear
+fn decompose(matrix: &Matrix4<f32>) -> Transform {
+    let translation = matrix.fixed_view::<3, 1>(0, 3).into_owned();
+    let mut linear: Matrix3<f32> = matrix.fixed_view::<3, 3>(0, 0).into_owned();
+    let mut scale = Vector3::new(
+        linear.column(0).norm(),
+        linear.column(1).norm(),
+        linear.column(2).norm(),
+    );
+    // A mirrored matrix is treated as a negative scale along x
+    if linear.determinant() < 0.0 {
+        scale.x = -scale.x;
+    }
+    for i in 0..3 {
+        if scale[i] != 0.0 {
+            let column = linear.column(i) / scale[i];
+            linear.set_column(i, &column);
+        }
+    }
+    Transform {
+        translation,
+        rotation: UnitQuaternion::from_rotation_matrix(&Rotation3::from_matrix_unchecked(linear)),
+        scale,
+    }
+}
+
+/// Loads `.glb` and `.gltf` files through `Assets`
+pub struct GltfLoader;
+
+impl AssetLoader for GltfLoader {
+    type Asset = GltfAsset;
+
+    fn load(&self, bytes: Vec<u8>) -> Result<Self::Asset, String> {
+        GltfAsset::from_bytes(&bytes)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["gltf", "glb"]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A document with one 8 byte buffer, viewed whole, and the given accessor
+    fn document(accessor: &str) -> Document {
+        let json = format!(
+            r#"{{
+                "buffers": [{{ "uri": "data:application/octet-stream;base64,AAAAAAAAAAA=" }}],
+                "bufferViews": [{{ "buffer": 0, "byteLength": 8 }}],
+                "accessors": [{accessor}]
+            }}"#
+        );
+        Document::new(Json::parse(&json).unwrap(), None).unwrap()
+    }
+
+    #[test]
+    fn reads_accessors() {
+        let document =
+            document(r#"{ "bufferView": 0, "componentType": 5126, "count": 2, "type": "SCALAR" }"#);
+        assert_eq!(document.accessor(0).unwrap().values, [0.0, 0.0]);
+    }
+
+    #[test]
+    fn rejects_hostile_accessors() {
+        for accessor in [
+            // More elements than the buffer view holds
+            r#"{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "SCALAR" }"#,
+            r#"{ "bufferView": 0, "componentType": 5126, "count": 1e18, "type": "MAT4" }"#,
+            r#"{ "bufferView": 0, "componentType": 5126, "count": 2, "type": "SCALAR", "byteOffset": 18446744073709549568 }"#,
+            // Nothing to read, but too many zeros to allocate
+            r#"{ "componentType": 5126, "count": 1e18, "type": "MAT4" }"#,
+        ] {
+            assert!(document(accessor).accessor(0).is_err(), "{accessor}");
+        }
+    }
+
+    #[test]
+    fn rejects_buffer_views_outside_of_their_buffer() {
+        let json = r#"{
+            "buffers": [{ "uri": "data:application/octet-stream;base64,AAAAAAAAAAA=" }],
+            "bufferViews": [{ "buffer": 0, "byteOffset": 18446744073709549568, "byteLength": 18446744073709549568 }]
+        }"#;
+        let document = Document::new(Json::parse(json).unwrap(), None).unwrap();
+        assert!(document.buffer_view(0).is_err());
+    }
+}
@@ -0,0 +1,425 @@
+//! Drawing meshes in 3D through a perspective camera
+//!
+//! Meshes are drawn with depth testing before any polygon, so the 2D scene is always
+//! drawn on top of them, such as for a HUD. Nothing is drawn in 3D until a camera is set
+//! with `Graphics::set_camera_3d`
+use bina_ecs::{
+    component::{
+        Component, ComponentField, NumberField, NumberFieldRef, Processable, StagedMutField,
+        StagedMutFieldRef,
+    },
+    reflect::{Field, Reflect},
+    triomphe::Arc,
+};
+use image::Rgba;
+use nalgebra::{Isometry3, Matrix4, Perspective3, Point3, UnitQuaternion, Vector3};
+use wgpu::util::DeviceExt;
+
+use skinning::VertexSkin;
+
+use crate::{
+    drawing::DrawInstruction,
+    labels,
+    layers::Visible,
+    memory::{GpuMemory, MemoryAllocation, MemoryCategory},
+    texture::Texture,
+    Graphics, GraphicsInner,
+};
+
+pub mod animation;
+pub mod gltf;
+mod obj;
+pub(crate) mod renderer;
+pub mod skinning;
+
+pub use animation::{AnimationClip, AnimationPlayer};
+pub use gltf::{GltfAsset, GltfLoader};
+pub use obj::ObjLoader;
+pub use skinning::{Skeleton, Skin};
+
+/// Maps clip space depths from -1..1, as nalgebra produces, to the 0..1 that wgpu expects
+#[rustfmt::skip]
+const OPENGL_TO_WGPU: Matrix4<f32> = Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 0.5, 0.5,
+    0.0, 0.0, 0.0, 1.0,
+);
+
+/// The number of floats in the uniform of a mesh: its model matrix, the matrix its normals
+/// are transformed by, its base color, and its emission padded to a vector
+pub(crate) const MODEL_FLOATS: usize = 40;
+
+/// The position, rotation and scale of something in 3D
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Transform {
+    pub translation: Vector3<f32>,
+    pub rotation: UnitQuaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Transform {
+    pub const IDENTITY: Self = Self {
+        translation: Vector3::new(0.0, 0.0, 0.0),
+        rotation: UnitQuaternion::new_unchecked(nalgebra::Quaternion::new(1.0, 0.0, 0.0, 0.0)),
+        scale: Vector3::new(1.0, 1.0, 1.0),
+    };
+
+    pub fn from_translation(translation: Vector3<f32>) -> Self {
+        Self {
+            translation,
+            ..Self::IDENTITY
+        }
+    }
+
+    pub fn to_matrix(&self) -> Matrix4<f32> {
+        Matrix4::new_translation(&self.translation)
+            * self.rotation.to_homogeneous()
+            * Matrix4::new_nonuniform_scaling(&self.scale)
+    }
+
+    /// Blends between `self` and `other`, where 0 gives `self` and 1 gives `other`
+    pub fn lerp(&self, other: &Self, t: f32) -> Self {
+        Self {
+            translation: self.translation.lerp(&other.translation, t),
+            // Falls back to a normalized linear blend when the rotations are opposite
+            rotation: self
+                .rotation
+                .try_slerp(&other.rotation, t, 1.0e-6)
+                .unwrap_or_else(|| self.rotation.nlerp(&other.rotation, t)),
+            scale: self.scale.lerp(&other.scale, t),
+        }
+    }
+
+    /// Applies `self` after `child`, such as to get the transform of a child in the world
+    pub fn mul_transform(&self, child: &Self) -> Self {
+        Self {
+            translation: self.translation
+                + self.rotation * self.scale.component_mul(&child.translation),
+            rotation: self.rotation * child.rotation,
+            scale: self.scale.component_mul(&child.scale),
+        }
+    }
+}
+
+/// A camera that sees in perspective, where further things look smaller
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Camera3D {
+    pub position: Vector3<f32>,
+    /// With no rotation, the camera looks down the negative z axis with y up
+    pub rotation: UnitQuaternion<f32>,
+    /// The vertical field of view, in radians
+    pub fov_y: f32,
+    /// Anything closer than this is not drawn
+    pub near: f32,
+    /// Anything further than this is not drawn
+    pub far: f32,
+}
+
+impl Camera3D {
+    /// Creates a camera with a 60 degree field of view that sees from 0.1 to 1000 units away
+    pub fn new(position: Vector3<f32>) -> Self {
+        Self {
+            position,
+            rotation: UnitQuaternion::identity(),
+            fov_y: std::f32::consts::FRAC_PI_3,
+            near: 0.1,
+            far: 1000.0,
+        }
+    }
+
+    /// Turns the camera to face `target`, keeping `up` towards the top of the window
+    pub fn look_at(mut self, target: Vector3<f32>, up: Vector3<f32>) -> Self {
+        let view = Isometry3::look_at_rh(&Point3::from(self.position), &Point3::from(target), &up);
+        self.rotation = view.rotation.inverse();
+        self
+    }
+
+    /// Gets the matrix that maps world coordinates to clip space
+    pub(crate) fn view_projection(&self, aspect: f32) -> Matrix4<f32> {
+        let view = Isometry3::from_parts(self.position.into(), self.rotation).inverse();
+        let projection = Perspective3::new(aspect, self.fov_y, self.near, self.far);
+        OPENGL_TO_WGPU * projection.to_homogeneous() * view.to_homogeneous()
+    }
+}
+
+/// How the surface of a mesh looks
+pub struct Material3D {
+    /// Multiplied with the texture, or used alone if there is no texture
+    pub base_color: Rgba<u8>,
+    pub texture: Option<Texture>,
+    /// How much brighter than its lit color the mesh is, which makes it bloom when HDR is enabled
+    pub emission: f32,
+}
+
+impl Default for Material3D {
+    fn default() -> Self {
+        Self {
+            base_color: Rgba([255, 255, 255, 255]),
+            texture: None,
+            emission: 0.0,
+        }
+    }
+}
+
+#[derive(bytemuck::Pod, bytemuck::Zeroable, Clone, Copy, PartialEq, Debug, Default)]
+#[repr(C)]
+pub struct Vertex3D {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub tex_coords: [f32; 2],
+}
+
+/// Triangles in 3D, where every 3 indices are one triangle wound counter clockwise
+#[derive(Clone, Default, Debug)]
+pub struct MeshData {
+    pub vertices: Vec<Vertex3D>,
+    pub indices: Vec<u32>,
+    /// The joints that move each vertex, or empty if the mesh is not skinned
+    pub skin: Vec<VertexSkin>,
+    /// Names the buffers and draws of the mesh in graphics debuggers
+    pub name: Option<String>,
+}
+
+impl MeshData {
+    /// Replaces every normal with the average of the normals of the triangles it is part of
+    pub fn compute_normals(&mut self) {
+        let mut normals = vec![Vector3::zeros(); self.vertices.len()];
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] =
+                [0, 1, 2].map(|i| Vector3::from(self.vertices[triangle[i] as usize].position));
+            // Larger triangles contribute more, as the cross product is not normalized
+            let normal = (b - a).cross(&(c - a));
+            for &i in triangle {
+                normals[i as usize] += normal;
+            }
+        }
+        for (vertex, normal) in self.vertices.iter_mut().zip(normals) {
+            vertex.normal = normal
+                .try_normalize(f32::EPSILON)
+                .unwrap_or_default()
+                .into();
+        }
+    }
+}
+
+pub(crate) struct MeshInner {
+    pub(crate) material: Material3D,
+}
+
+/// The buffers of a mesh on the GPU, which are replaced if the device changes
+pub(crate) struct GpuMesh {
+    pub(crate) vertices: wgpu::Buffer,
+    pub(crate) indices: wgpu::Buffer,
+    pub(crate) index_count: u32,
+    /// The joints and weights of every vertex, if the mesh is skinned
+    pub(crate) skin: Option<wgpu::Buffer>,
+    uniform: wgpu::Buffer,
+    pub(crate) bind_group: wgpu::BindGroup,
+    pub(crate) generation: u64,
+    /// The name of the mesh data, which marks each draw of the mesh
+    pub(crate) name: Option<String>,
+    _memory: MemoryAllocation,
+}
+
+impl GpuMesh {
+    fn new(graphics: &GraphicsInner, data: &MeshData) -> Self {
+        let name = data.name.as_deref();
+        let uniform = graphics.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&labels::named("mesh_3d_uniform", name)),
+            size: (std::mem::size_of::<f32>() * MODEL_FLOATS) as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let vertices = graphics
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&labels::named("mesh_3d_vertex_buffer", name)),
+                contents: bytemuck::cast_slice(&data.vertices),
+                usage: wgpu::BufferUsages::VERTEX,
+            });
+        let indices = graphics
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&labels::named("mesh_3d_index_buffer", name)),
+                contents: bytemuck::cast_slice(&data.indices),
+                usage: wgpu::BufferUsages::INDEX,
+            });
+        let skin = (data.skin.len() == data.vertices.len() && !data.skin.is_empty()).then(|| {
+            graphics
+                .device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(&labels::named("mesh_3d_skin_buffer", name)),
+                    contents: bytemuck::cast_slice(&data.skin),
+                    usage: wgpu::BufferUsages::VERTEX,
+                })
+        });
+        let bytes = vertices.size() + indices.size() + skin.as_ref().map_or(0, |x| x.size());
+        Self {
+            _memory: GpuMemory::allocate(&graphics.memory, MemoryCategory::Meshes, bytes),
+            vertices,
+            indices,
+            index_count: data.indices.len() as u32,
+            skin,
+            bind_group: graphics
+                .device
+                .create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &graphics.layouts.model,
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: uniform.as_entire_binding(),
+                    }],
+                    label: Some(&labels::named("mesh_3d_bind_group", name)),
+                }),
+            uniform,
+            generation: graphics.generation,
+            name: data.name.clone(),
+        }
+    }
+}
+
+/// A mesh drawn in 3D with a material
+///
+/// The data of the mesh is copied onto the GPU, so many meshes can be created from the same data
+pub struct Mesh {
+    pub(crate) inner: Arc<MeshInner>,
+    /// Kept so that the buffers can be recreated if the device changes
+    data: Arc<MeshData>,
+    gpu: Arc<GpuMesh>,
+    skin: Option<Skin>,
+    transform: StagedMutField<Transform>,
+    visible: NumberField<Visible>,
+}
+
+impl Mesh {
+    pub fn new(graphics: &Graphics, data: Arc<MeshData>, material: Material3D) -> Self {
+        let mesh = Self {
+            inner: Arc::new(MeshInner { material }),
+            gpu: Arc::new(GpuMesh::new(&graphics.inner, &data)),
+            data,
+            skin: None,
+            transform: StagedMutField::new(Transform::IDENTITY),
+            visible: NumberField::new(Visible::default()),
+        };
+        mesh.write_uniform(&graphics.inner);
+        mesh
+    }
+
+    pub fn with_transform(mut self, transform: Transform) -> Self {
+        *self.transform.get_inner_mut() = transform;
+        self
+    }
+
+    /// Moves the mesh with the joints of a skeleton, if the mesh has joints and weights
+    ///
+    /// The transform of the mesh should be where the skeleton is placed
+    pub fn with_skin(mut self, skin: Skin) -> Self {
+        self.skin = Some(skin);
+        self
+    }
+
+    fn write_uniform(&self, graphics: &GraphicsInner) {
+        let matrix = self.transform.get_inner().to_matrix();
+        let normal_matrix = matrix
+            .try_inverse()
+            .unwrap_or_else(Matrix4::identity)
+            .transpose();
+        let material = &self.inner.material;
+        let [r, g, b, a] = crate::gizmos::linear(material.base_color);
+        let mut floats = [0.0; MODEL_FLOATS];
+        floats[..16].copy_from_slice(matrix.as_slice());
+        floats[16..32].copy_from_slice(normal_matrix.as_slice());
+        floats[32..37].copy_from_slice(&[r, g, b, a, material.emission]);
+        graphics
+            .queue
+            .write_buffer(&self.gpu.uniform, 0, bytemuck::cast_slice(&floats));
+    }
+}
+
+impl Component for Mesh {
+    type Reference<'a> = MeshRef<'a>;
+
+    fn get_ref<'a>(&'a self) -> Self::Reference<'a> {
+        MeshRef {
+            inner: &self.inner,
+            gpu: &self.gpu,
+            skin: &self.skin,
+            transform: self.transform.get_ref(),
+            visible: self.visible.get_ref(),
+        }
+    }
+
+    fn flush<E: bina_ecs::entity::Entity>(
+        &mut self,
+        _my_entity: bina_ecs::entity::EntityReference<bina_ecs::entity::Inaccessible<E>>,
+        universe: &bina_ecs::universe::Universe,
+    ) {
+        let last_transform = *self.transform.get_inner();
+        self.transform.process_modifiers();
+        self.visible.process_modifiers();
+        let Some(graphics) = universe.try_get_singleton::<Graphics>() else {
+            return;
+        };
+        let mut dirty = *self.transform.get_inner() != last_transform;
+        // The render thread keeps the old buffers alive for as long as it is drawing them
+        if graphics.inner.generation != self.gpu.generation {
+            self.gpu = Arc::new(GpuMesh::new(&graphics.inner, &self.data));
+            dirty = true;
+        }
+        if dirty {
+            self.write_uniform(&graphics.inner);
+        }
+    }
+}
+
+impl Reflect for Mesh {
+    fn reflect_fields(&self, visitor: &mut dyn FnMut(&'static str, Field<'_>)) {
+        visitor("transform", Field::Debug(self.transform.get_inner()));
+        visitor("visible", Field::Debug(&self.visible));
+    }
+}
+
+impl Processable for Mesh {
+    fn process<E: bina_ecs::entity::Entity>(
+        component: Self::Reference<'_>,
+        _my_entity: bina_ecs::entity::EntityReference<E>,
+        universe: &bina_ecs::universe::Universe,
+    ) {
+        let Some(graphics) = universe.try_get_singleton::<Graphics>() else {
+            return;
+        };
+        if !component.visible.0 {
+            return;
+        }
+        graphics.queue_draw_instruction(DrawInstruction::DrawMesh(renderer::DrawMesh {
+            mesh: component.inner.clone(),
+            gpu: component.gpu.clone(),
+            joints: component.skin.as_ref().and_then(Skin::joints),
+        }));
+    }
+}
+
+pub struct MeshRef<'a> {
+    inner: &'a Arc<MeshInner>,
+    gpu: &'a Arc<GpuMesh>,
+    skin: &'a Option<Skin>,
+    pub transform: StagedMutFieldRef<'a, Transform>,
+    pub visible: NumberFieldRef<'a, Visible>,
+}
+
+impl<'a> MeshRef<'a> {
+    pub fn material(&self) -> &Material3D {
+        &self.inner.material
+    }
+
+    /// Sets the transform of the mesh after the current process frame ends
+    pub fn queue_set_transform(&self, transform: Transform) {
+        self.transform.queue_modifier(move |x| *x = transform);
+    }
+}
@@ -0,0 +1,127 @@
+//! Reading meshes from Wavefront OBJ files
+//!
+//! Only geometry is read: positions, texture coordinates, normals and faces.
+//! Every object and group in the file is merged into one mesh, and materials are ignored
+use std::collections::HashMap;
+
+use bina_ecs::assets::AssetLoader;
+
+use super::{MeshData, Vertex3D};
+
+impl MeshData {
+    /// Parses the geometry of an OBJ file, triangulating any face with more than 3 vertices
+    ///
+    /// If the file has no normals, they are computed from the faces
+    pub fn from_obj(text: &str) -> Result<Self, String> {
+        let mut positions: Vec<[f32; 3]> = Vec::new();
+        let mut tex_coords: Vec<[f32; 2]> = Vec::new();
+        let mut normals: Vec<[f32; 3]> = Vec::new();
+        let mut data = Self::default();
+        // Faces refer to positions, texture coordinates and normals separately,
+        // so every distinct combination of them becomes one vertex
+        let mut vertices: HashMap<(usize, Option<usize>, Option<usize>), u32> = HashMap::new();
+        let mut has_normals = true;
+
+        for (number, line) in text.lines().enumerate() {
+            let error = |message: &str| format!("Line {}: {message}", number + 1);
+            let line = line.split('#').next().unwrap_or_default();
+            let mut words = line.split_whitespace();
+            let Some(keyword) = words.next() else {
+                continue;
+            };
+            let mut floats = || -> Result<Vec<f32>, String> {
+                words
+                    .by_ref()
+                    .map(|x| x.parse::<f32>().map_err(|_| error("Invalid number")))
+                    .collect()
+            };
+            match keyword {
+                "v" => match floats()?[..] {
+                    [x, y, z, ..] => positions.push([x, y, z]),
+                    _ => return Err(error("A position needs 3 numbers")),
+                },
+                // OBJ has the origin of textures at the bottom left, but wgpu has it at the top left
+                "vt" => match floats()?[..] {
+                    [u, v, ..] => tex_coords.push([u, 1.0 - v]),
+                    [u] => tex_coords.push([u, 1.0]),
+                    _ => return Err(error("A texture coordinate needs a number")),
+                },
+                "vn" => match floats()?[..] {
+                    [x, y, z, ..] => normals.push([x, y, z]),
+                    _ => return Err(error("A normal needs 3 numbers")),
+                },
+                "f" => {
+                    let mut face = Vec::new();
+                    for corner in words {
+                        let mut parts = corner.split('/');
+                        let position = resolve(parts.next(), positions.len())
+                            .ok_or_else(|| error("Invalid position index"))?
+                            .ok_or_else(|| error("A face vertex needs a position"))?;
+                        let tex_coord = resolve(parts.next(), tex_coords.len())
+                            .ok_or_else(|| error("Invalid texture coordinate index"))?;
+                        let normal = resolve(parts.next(), normals.len())
+                            .ok_or_else(|| error("Invalid normal index"))?;
+                        has_normals &= normal.is_some();
+
+                        let key = (position, tex_coord, normal);
+                        let index = *vertices.entry(key).or_insert_with(|| {
+                            data.vertices.push(Vertex3D {
+                                position: positions[position],
+                                normal: normal.map_or([0.0; 3], |i| normals[i]),
+                                tex_coords: tex_coord.map_or([0.0; 2], |i| tex_coords[i]),
+                            });
+                            data.vertices.len() as u32 - 1
+                        });
+                        face.push(index);
+                    }
+                    if face.len() < 3 {
+                        return Err(error("A face needs at least 3 vertices"));
+                    }
+                    // Faces are convex, so they can be split into a fan of triangles
+                    for i in 1..face.len() - 1 {
+                        data.indices.extend([face[0], face[i], face[i + 1]]);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if !has_normals {
+            data.compute_normals();
+        }
+        Ok(data)
+    }
+}
+
+/// Turns an index in a face into an index into the given number of elements
+///
+/// OBJ indices start at 1, and negative indices count back from the last element.
+/// Gives `Some(None)` if the index was left out, and `None` if it is invalid
+fn resolve(index: Option<&str>, len: usize) -> Option<Option<usize>> {
+    let index = match index {
+        None | Some("") => return Some(None),
+        Some(x) => x.parse::<isize>().ok()?,
+    };
+    let index = if index < 0 {
+        len.checked_sub(index.unsigned_abs())?
+    } else {
+        (index as usize).checked_sub(1)?
+    };
+    (index < len).then_some(Some(index))
+}
+
+/// Loads OBJ files through `Assets`
+pub struct ObjLoader;
+
+impl AssetLoader for ObjLoader {
+    type Asset = MeshData;
+
+    fn load(&self, bytes: Vec<u8>) -> Result<Self::Asset, String> {
+        let text = String::from_utf8(bytes).map_err(|e| e.to_string())?;
+        MeshData::from_obj(&text)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["obj"]
+    }
+}
@@ -0,0 +1,312 @@
+use bina_ecs::triomphe::Arc;
+use wgpu::{BindGroup, Device, Queue, RenderPipeline, TextureView};
+use winit::dpi::PhysicalSize;
+
+use crate::{hdr::HDR_FORMAT, labels, renderers::pipelines::BindGroupLayouts};
+
+use super::{skinning::GpuJoints, GpuMesh, MeshInner};
+
+/// The format of the depth buffer that meshes are tested against
+const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+/// A position, a normal, then a texture coordinate
+const MESH_VERTEX_BUFFER_DESCRIPTOR: wgpu::VertexBufferLayout<'static> = wgpu::VertexBufferLayout {
+    array_stride: std::mem::size_of::<super::Vertex3D>() as wgpu::BufferAddress,
+    step_mode: wgpu::VertexStepMode::Vertex,
+    attributes: &wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3, 2 => Float32x2],
+};
+
+/// The joints, then the weights of each joint
+const SKIN_VERTEX_BUFFER_DESCRIPTOR: wgpu::VertexBufferLayout<'static> = wgpu::VertexBufferLayout {
+    array_stride: std::mem::size_of::<super::skinning::VertexSkin>() as wgpu::BufferAddress,
+    step_mode: wgpu::VertexStepMode::Vertex,
+    attributes: &wgpu::vertex_attr_array![3 => Uint32x4, 4 => Float32x4],
+};
+
+pub(crate) struct DrawMesh {
+    pub(crate) mesh: Arc<MeshInner>,
+    /// Held until the frame is submitted, so that its buffers outlive the draw
+    pub(crate) gpu: Arc<GpuMesh>,
+    /// The joint matrices of the skeleton that the mesh follows
+    pub(crate) joints: Option<Arc<GpuJoints>>,
+}
+
+/// Draws every mesh in a pass of its own, before the 2D scene is drawn over it
+pub(crate) struct MeshRenderer {
+    pipeline: RenderPipeline,
+    /// `None` if the device has no storage buffers, in which case skinned meshes are not skinned
+    skinned_pipeline: Option<RenderPipeline>,
+    camera_bind_group: BindGroup,
+    /// Bound for meshes without a texture
+    white_texture: BindGroup,
+    /// Resized alongside the surface
+    depth: Option<(TextureView, PhysicalSize<u32>)>,
+    meshes: Vec<DrawMesh>,
+    /// The generation of the device this renderer was created with
+    generation: u64,
+}
+
+impl MeshRenderer {
+    pub(crate) fn new(
+        device: &Device,
+        queue: &Queue,
+        layouts: &BindGroupLayouts,
+        camera_buffer: &wgpu::Buffer,
+        generation: u64,
+    ) -> Self {
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layouts.camera,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: camera_buffer.as_entire_binding(),
+            }],
+            label: Some("camera_3d_bind_group"),
+        });
+
+        let white = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("mesh_white_texture"),
+            size: wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        queue.write_texture(
+            white.as_image_copy(),
+            &[255; 4],
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4),
+                rows_per_image: Some(1),
+            },
+            wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
+        let white_view = white.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
+        let white_texture = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layouts.texture,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&white_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+            label: Some("mesh_white_texture_bind_group"),
+        });
+
+        let shader = device.create_shader_module(wgpu::include_wgsl!("../shaders/mesh3d.wgsl"));
+        let pipeline = create_pipeline(
+            device,
+            &[&layouts.texture, &layouts.model, &layouts.camera],
+            &shader,
+            "vs_main",
+            &[MESH_VERTEX_BUFFER_DESCRIPTOR],
+        );
+        // The skinned shader is only compiled if storage buffers are supported
+        let skinned_pipeline = layouts.joints.as_ref().map(|joints| {
+            let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("mesh3d_skinned.wgsl"),
+                source: wgpu::ShaderSource::Wgsl(
+                    concat!(
+                        include_str!("../shaders/mesh3d.wgsl"),
+                        include_str!("../shaders/mesh3d_skinned.wgsl")
+                    )
+                    .into(),
+                ),
+            });
+            create_pipeline(
+                device,
+                &[&layouts.texture, &layouts.model, &layouts.camera, joints],
+                &shader,
+                "vs_skinned",
+                &[MESH_VERTEX_BUFFER_DESCRIPTOR, SKIN_VERTEX_BUFFER_DESCRIPTOR],
+            )
+        });
+
+        Self {
+            pipeline,
+            skinned_pipeline,
+            camera_bind_group,
+            white_texture,
+            depth: None,
+            meshes: Vec::new(),
+            generation,
+        }
+    }
+
+    pub(crate) fn push(&mut self, item: DrawMesh) {
+        // Meshes created on a previous device cannot be drawn until they rebuild themselves
+        if item.gpu.generation != self.generation || item.gpu.index_count == 0 {
+            return;
+        }
+        if let Some(texture) = &item.mesh.material.texture {
+            if texture.texture.generation != self.generation {
+                return;
+            }
+        }
+        if let Some(joints) = &item.joints {
+            if joints.generation != self.generation {
+                return;
+            }
+        }
+        self.meshes.push(item);
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.meshes.is_empty()
+    }
+
+    /// Recreates the depth buffer if the surface size changed
+    fn resize_depth(&mut self, device: &Device, size: PhysicalSize<u32>) {
+        if self.depth.as_ref().map(|(_, x)| *x) != Some(size) {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("depth_buffer"),
+                size: wgpu::Extent3d {
+                    width: size.width.max(1),
+                    height: size.height.max(1),
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: DEPTH_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                view_formats: &[],
+            });
+            self.depth = Some((
+                texture.create_view(&wgpu::TextureViewDescriptor::default()),
+                size,
+            ));
+        }
+    }
+
+    /// Clears `view` and draws every mesh that was pushed onto it, returning the number of draw calls made
+    pub(crate) fn draw_all(
+        &mut self,
+        device: &Device,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &TextureView,
+        surface_size: PhysicalSize<u32>,
+    ) -> usize {
+        self.resize_depth(device, surface_size);
+        let depth_view = &self.depth.as_ref().unwrap().0;
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Mesh Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: false,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        render_pass.set_bind_group(2, &self.camera_bind_group, &[]);
+        let mut skinned = None;
+        for DrawMesh { mesh, gpu, joints } in &self.meshes {
+            // Meshes without joints, or without a skeleton, are drawn in the pose they were bound in
+            let skin = match (&self.skinned_pipeline, &gpu.skin, joints) {
+                (Some(pipeline), Some(skin), Some(joints)) => Some((pipeline, skin, joints)),
+                _ => None,
+            };
+            if skinned != Some(skin.is_some()) {
+                skinned = Some(skin.is_some());
+                match skin {
+                    Some((pipeline, ..)) => render_pass.set_pipeline(pipeline),
+                    None => render_pass.set_pipeline(&self.pipeline),
+                }
+            }
+            if let Some((_, skin, joints)) = skin {
+                render_pass.set_bind_group(3, &joints.bind_group, &[]);
+                render_pass.set_vertex_buffer(1, skin.slice(..));
+            }
+            let texture = match &mesh.material.texture {
+                Some(texture) => &texture.texture.bind_group,
+                None => &self.white_texture,
+            };
+            render_pass.set_bind_group(0, texture, &[]);
+            render_pass.set_bind_group(1, &gpu.bind_group, &[]);
+            render_pass.set_vertex_buffer(0, gpu.vertices.slice(..));
+            render_pass.set_index_buffer(gpu.indices.slice(..), wgpu::IndexFormat::Uint32);
+            labels::mark_draw(&mut render_pass, gpu.name.as_deref());
+            render_pass.draw_indexed(0..gpu.index_count, 0, 0..1);
+        }
+        self.meshes.len()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.meshes.clear();
+    }
+}
+
+fn create_pipeline(
+    device: &Device,
+    bind_group_layouts: &[&wgpu::BindGroupLayout],
+    shader: &wgpu::ShaderModule,
+    entry_point: &str,
+    buffers: &[wgpu::VertexBufferLayout],
+) -> RenderPipeline {
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("mesh_3d_pipeline_layout"),
+        bind_group_layouts,
+        push_constant_ranges: &[],
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("mesh_3d_pipeline"),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point,
+            buffers,
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: HDR_FORMAT,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            polygon_mode: wgpu::PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
@@ -0,0 +1,289 @@
+//! Meshes that bend with a skeleton of bones
+//!
+//! A `Skeleton` holds the pose of every bone, and writes the matrix of every joint into a storage
+//! buffer during each flush. Meshes follow it through a `Skin`, and are moved on the GPU by up to 4
+//! joints per vertex. Devices without storage buffers, such as WebGL, draw skinned meshes in the
+//! pose they were bound in
+use bina_ecs::{
+    component::{Component, ComponentField, Processable, StagedMutField, StagedMutFieldRef},
+    parking_lot::Mutex,
+    reflect::{Field, Reflect},
+    triomphe::Arc,
+};
+use nalgebra::Matrix4;
+
+use super::Transform;
+use crate::{Graphics, GraphicsInner};
+
+/// The joints that move a vertex, and how much each of them moves it
+#[derive(bytemuck::Pod, bytemuck::Zeroable, Clone, Copy, PartialEq, Debug, Default)]
+#[repr(C)]
+pub struct VertexSkin {
+    /// Indices into `SkeletonData::joints`
+    pub joints: [u32; 4],
+    /// Should add up to 1
+    pub weights: [f32; 4],
+}
+
+/// A bone that vertices are bound to
+#[derive(Clone, Debug)]
+pub struct Joint {
+    /// The index of the bone in the skeleton
+    pub bone: usize,
+    /// Moves a vertex from the space of the mesh into the space of the bone, as it was when bound
+    pub inverse_bind_matrix: Matrix4<f32>,
+}
+
+/// The bones of a skeleton, which are shared between every `Skeleton` made from them
+#[derive(Clone, Debug, Default)]
+pub struct SkeletonData {
+    pub names: Vec<Option<String>>,
+    /// The parent of every bone, or `None` for bones at the root of the skeleton
+    pub parents: Vec<Option<usize>>,
+    /// The transform of every bone relative to its parent when it is not animated
+    pub rest_pose: Vec<Transform>,
+    pub joints: Vec<Joint>,
+}
+
+impl SkeletonData {
+    pub fn find_bone(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|x| x.as_deref() == Some(name))
+    }
+
+    /// Gets the matrix of every bone relative to the root of the skeleton,
+    /// from the transform of every bone relative to its parent
+    pub fn bone_matrices(&self, pose: &[Transform]) -> Vec<Matrix4<f32>> {
+        let count = self.parents.len();
+        let mut matrices: Vec<Option<Matrix4<f32>>> = vec![None; count];
+        let mut chain = Vec::new();
+        for bone in 0..count {
+            // Walks up to the nearest bone that is already known, then back down
+            let mut current = Some(bone);
+            while let Some(i) = current {
+                if matrices[i].is_some() || chain.len() > count {
+                    break;
+                }
+                chain.push(i);
+                current = self.parents[i].filter(|&parent| parent < count);
+            }
+            let mut parent = current
+                .and_then(|i| matrices[i])
+                .unwrap_or_else(Matrix4::identity);
+            while let Some(i) = chain.pop() {
+                let local = pose.get(i).or(self.rest_pose.get(i)).copied();
+                parent *= local.unwrap_or_default().to_matrix();
+                matrices[i] = Some(parent);
+            }
+        }
+        matrices
+            .into_iter()
+            .map(Option::unwrap_or_default)
+            .collect()
+    }
+
+    /// Gets the matrix that moves vertices with every joint, from the transform of every bone
+    /// relative to its parent
+    pub fn joint_matrices(&self, pose: &[Transform]) -> Vec<Matrix4<f32>> {
+        let bones = self.bone_matrices(pose);
+        self.joints
+            .iter()
+            .map(|joint| {
+                bones
+                    .get(joint.bone)
+                    .copied()
+                    .unwrap_or_else(Matrix4::identity)
+                    * joint.inverse_bind_matrix
+            })
+            .collect()
+    }
+}
+
+/// The joint matrices of a skeleton on the GPU, which are replaced if the device changes
+pub(crate) struct GpuJoints {
+    buffer: wgpu::Buffer,
+    pub(crate) bind_group: wgpu::BindGroup,
+    pub(crate) generation: u64,
+}
+
+impl GpuJoints {
+    /// Gives `None` if the device does not support storage buffers
+    fn new(graphics: &GraphicsInner, joint_count: usize) -> Option<Self> {
+        let layout = graphics.layouts.joints.as_ref()?;
+        let buffer = graphics.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("joint_buffer"),
+            // Empty buffers cannot be bound
+            size: (std::mem::size_of::<Matrix4<f32>>() * joint_count.max(1)) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Some(Self {
+            bind_group: graphics
+                .device
+                .create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout,
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: buffer.as_entire_binding(),
+                    }],
+                    label: Some("joint_bind_group"),
+                }),
+            buffer,
+            generation: graphics.generation,
+        })
+    }
+}
+
+/// Connects meshes to the joints of a `Skeleton`
+///
+/// Gotten from `Skeleton::skin`, and given to `Mesh::with_skin`
+#[derive(Clone)]
+pub struct Skin {
+    /// Replaced by the skeleton whenever the device changes
+    joints: Arc<Mutex<Option<Arc<GpuJoints>>>>,
+}
+
+impl Skin {
+    pub(crate) fn joints(&self) -> Option<Arc<GpuJoints>> {
+        self.joints.lock().clone()
+    }
+}
+
+/// Poses the bones of a skeleton, moving every mesh with its `Skin`
+///
+/// Usually placed in an entity with an `AnimationPlayer`, which sets the pose every frame.
+/// Joint matrices are relative to the root of the skeleton, so skinned meshes
+/// should be given the transform that the skeleton is placed at
+pub struct Skeleton {
+    data: Arc<SkeletonData>,
+    /// The transform of every bone relative to its parent
+    pose: StagedMutField<Vec<Transform>>,
+    skin: Skin,
+}
+
+impl Skeleton {
+    /// Creates a skeleton in its rest pose
+    pub fn new(graphics: &Graphics, data: Arc<SkeletonData>) -> Self {
+        let skeleton = Self {
+            pose: StagedMutField::new(data.rest_pose.clone()),
+            skin: Skin {
+                joints: Arc::new(Mutex::new(
+                    GpuJoints::new(&graphics.inner, data.joints.len()).map(Arc::new),
+                )),
+            },
+            data,
+        };
+        skeleton.write_joints(&graphics.inner);
+        skeleton
+    }
+
+    pub fn data(&self) -> &Arc<SkeletonData> {
+        &self.data
+    }
+
+    pub fn skin(&self) -> Skin {
+        self.skin.clone()
+    }
+
+    fn write_joints(&self, graphics: &GraphicsInner) {
+        let Some(joints) = self.skin.joints() else {
+            return;
+        };
+        let matrices = self.data.joint_matrices(self.pose.get_inner());
+        if matrices.is_empty() {
+            return;
+        }
+        let floats: Vec<f32> = matrices
+            .iter()
+            .flat_map(|x| x.as_slice().iter().copied())
+            .collect();
+        graphics
+            .queue
+            .write_buffer(&joints.buffer, 0, bytemuck::cast_slice(&floats));
+    }
+}
+
+impl Component for Skeleton {
+    type Reference<'a> = SkeletonRef<'a>;
+
+    fn get_ref<'a>(&'a self) -> Self::Reference<'a> {
+        SkeletonRef {
+            data: &self.data,
+            skin: &self.skin,
+            pose: self.pose.get_ref(),
+        }
+    }
+
+    fn flush<E: bina_ecs::entity::Entity>(
+        &mut self,
+        _my_entity: bina_ecs::entity::EntityReference<bina_ecs::entity::Inaccessible<E>>,
+        universe: &bina_ecs::universe::Universe,
+    ) {
+        let last_pose = self.pose.get_inner().clone();
+        self.pose.process_modifiers();
+        let Some(graphics) = universe.try_get_singleton::<Graphics>() else {
+            return;
+        };
+        let mut dirty = *self.pose.get_inner() != last_pose;
+        let stale = self
+            .skin
+            .joints()
+            .is_some_and(|joints| joints.generation != graphics.inner.generation);
+        // Meshes keep drawing with the old buffer until they see the new one
+        if stale {
+            *self.skin.joints.lock() =
+                GpuJoints::new(&graphics.inner, self.data.joints.len()).map(Arc::new);
+            dirty = true;
+        }
+        if dirty {
+            self.write_joints(&graphics.inner);
+        }
+    }
+}
+
+impl Reflect for Skeleton {
+    fn reflect_fields(&self, visitor: &mut dyn FnMut(&'static str, Field<'_>)) {
+        visitor("bones", Field::Debug(&self.data.parents.len()));
+        visitor("joints", Field::Debug(&self.data.joints.len()));
+    }
+}
+
+impl Processable for Skeleton {
+    fn process<E: bina_ecs::entity::Entity>(
+        _component: Self::Reference<'_>,
+        _my_entity: bina_ecs::entity::EntityReference<E>,
+        _universe: &bina_ecs::universe::Universe,
+    ) {
+        // The pose is set by other components, and only applied during the flush
+    }
+}
+
+pub struct SkeletonRef<'a> {
+    data: &'a Arc<SkeletonData>,
+    skin: &'a Skin,
+    /// The transform of every bone relative to its parent
+    pub pose: StagedMutFieldRef<'a, Vec<Transform>>,
+}
+
+impl<'a> SkeletonRef<'a> {
+    pub fn data(&self) -> &Arc<SkeletonData> {
+        self.data
+    }
+
+    pub fn skin(&self) -> Skin {
+        self.skin.clone()
+    }
+
+    /// Replaces the transform of every bone after the current process frame ends
+    pub fn queue_set_pose(&self, pose: Vec<Transform>) {
+        self.pose.queue_modifier(move |x| *x = pose);
+    }
+
+    /// Sets the transform of a bone relative to its parent after the current process frame ends
+    pub fn queue_set_bone(&self, bone: usize, transform: Transform) {
+        self.pose.queue_modifier(move |x| {
+            if let Some(x) = x.get_mut(bone) {
+                *x = transform;
+            }
+        });
+    }
+}
@@ -0,0 +1,236 @@
+//! A position, rotation and scale that several components can share
+//!
+//! A `Transform` placed in the same entity as a `Polygon` or a `Skeleton2D` moves it, with the
+//! polygon's own origin, rotation and scale applied on top. Anything that cannot reach the
+//! entity, such as a `Camera`, can follow the transform through a `TransformHandle` instead
+use std::sync::atomic::Ordering;
+
+use atomic_float::AtomicF32;
+use bina_ecs::{
+    component::{Component, ComponentField, NumberField, NumberFieldRef, Processable},
+    reflect::{Field, Reflect},
+    triomphe::Arc,
+};
+use nalgebra::{Matrix2, Matrix3, Vector2};
+
+use crate::polygon::Vector;
+
+/// Computes the matrix that scales, then rotates, then translates
+fn compose(position: Vector, rotation: f32, scale: Vector) -> Matrix3<f32> {
+    let (sin, cos) = rotation.sin_cos();
+    Matrix3::new(
+        cos * scale.x,
+        -sin * scale.y,
+        position.x,
+        sin * scale.x,
+        cos * scale.y,
+        position.y,
+        0.0,
+        0.0,
+        1.0,
+    )
+}
+
+/// The matrix of a `Transform` as of its last flush, which can be read from anywhere
+///
+/// Gotten from `Transform::handle`
+#[derive(Clone)]
+pub struct TransformHandle(Arc<[AtomicF32; 6]>);
+
+impl TransformHandle {
+    fn new(matrix: &Matrix3<f32>) -> Self {
+        let handle = Self(Arc::new(Default::default()));
+        handle.store(matrix);
+        handle
+    }
+
+    fn store(&self, matrix: &Matrix3<f32>) {
+        let floats = [
+            matrix.m11, matrix.m12, matrix.m21, matrix.m22, matrix.m13, matrix.m23,
+        ];
+        for (atomic, float) in self.0.iter().zip(floats) {
+            atomic.store(float, Ordering::Relaxed);
+        }
+    }
+
+    fn load(&self, i: usize) -> f32 {
+        self.0[i].load(Ordering::Relaxed)
+    }
+
+    /// Gets the matrix that moves points from the space of the transform into the world
+    pub fn matrix(&self) -> Matrix3<f32> {
+        Matrix3::new(
+            self.load(0),
+            self.load(1),
+            self.load(4),
+            self.load(2),
+            self.load(3),
+            self.load(5),
+            0.0,
+            0.0,
+            1.0,
+        )
+    }
+
+    pub fn position(&self) -> Vector {
+        Vector::new(self.load(4), self.load(5))
+    }
+
+    /// Gets the counterclockwise rotation, in radians
+    pub fn rotation(&self) -> f32 {
+        self.load(2).atan2(self.load(0))
+    }
+}
+
+/// A position, rotation and scale that other components in the same entity are placed relative to
+///
+/// ```ignore
+/// universe.queue_add_entity((
+///     Transform::new(Vector::new(0.0, 2.0)).with_rotation(0.5),
+///     Polygon::new(graphics, &vertices, material),
+/// ));
+/// ```
+pub struct Transform {
+    position: NumberField<Vector>,
+    /// Counterclockwise, in radians
+    rotation: NumberField<f32>,
+    scale: NumberField<Vector>,
+    /// Computed from the other fields during each flush
+    matrix: Matrix3<f32>,
+    /// Whether the matrix changed in the last flush
+    changed: bool,
+    handle: TransformHandle,
+}
+
+impl Transform {
+    pub fn new(position: Vector) -> Self {
+        let matrix = compose(position, 0.0, Vector::new(1.0, 1.0));
+        Self {
+            position: NumberField::new(position),
+            rotation: NumberField::new(0.0),
+            scale: NumberField::new(Vector::new(1.0, 1.0)),
+            handle: TransformHandle::new(&matrix),
+            matrix,
+            changed: true,
+        }
+    }
+
+    pub fn with_rotation(mut self, rotation: f32) -> Self {
+        self.rotation.set_inner(rotation);
+        self.update_matrix();
+        self
+    }
+
+    pub fn with_scale(mut self, scale: Vector) -> Self {
+        self.scale.set_inner(scale);
+        self.update_matrix();
+        self
+    }
+
+    /// Gets a handle that follows this transform, even after it is added to the `Universe`
+    pub fn handle(&self) -> TransformHandle {
+        self.handle.clone()
+    }
+
+    pub fn matrix(&self) -> &Matrix3<f32> {
+        &self.matrix
+    }
+
+    /// Recomputes the matrix, returning whether it changed
+    fn update_matrix(&mut self) -> bool {
+        let matrix = compose(
+            self.position.get_inner(),
+            self.rotation.get_inner(),
+            self.scale.get_inner(),
+        );
+        if matrix == self.matrix {
+            return false;
+        }
+        self.matrix = matrix;
+        self.handle.store(&matrix);
+        true
+    }
+}
+
+impl Component for Transform {
+    type Reference<'a> = TransformRef<'a>;
+
+    fn get_ref<'a>(&'a self) -> Self::Reference<'a> {
+        TransformRef {
+            position: self.position.get_ref(),
+            rotation: self.rotation.get_ref(),
+            scale: self.scale.get_ref(),
+            matrix: &self.matrix,
+            changed: self.changed,
+            handle: &self.handle,
+        }
+    }
+
+    fn flush<E: bina_ecs::entity::Entity>(
+        &mut self,
+        _my_entity: bina_ecs::entity::EntityReference<bina_ecs::entity::Inaccessible<E>>,
+        _universe: &bina_ecs::universe::Universe,
+    ) {
+        self.position.process_modifiers();
+        self.rotation.process_modifiers();
+        self.scale.process_modifiers();
+        self.changed = self.update_matrix();
+    }
+}
+
+impl Reflect for Transform {
+    fn reflect_fields(&self, visitor: &mut dyn FnMut(&'static str, Field<'_>)) {
+        visitor("position", Field::Number(&self.position));
+        visitor("rotation", Field::Number(&self.rotation));
+        visitor("scale", Field::Number(&self.scale));
+    }
+}
+
+impl Processable for Transform {
+    fn process<E: bina_ecs::entity::Entity>(
+        _component: Self::Reference<'_>,
+        _my_entity: bina_ecs::entity::EntityReference<E>,
+        _universe: &bina_ecs::universe::Universe,
+    ) {
+        // Read by the other components in the entity, and only changed during the flush
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct TransformRef<'a> {
+    pub position: NumberFieldRef<'a, Vector>,
+    pub rotation: NumberFieldRef<'a, f32>,
+    pub scale: NumberFieldRef<'a, Vector>,
+    matrix: &'a Matrix3<f32>,
+    changed: bool,
+    handle: &'a TransformHandle,
+}
+
+impl<'a> TransformRef<'a> {
+    /// Gets the matrix that moves points from the space of this transform into the world, as of the last flush
+    pub fn matrix(&self) -> &Matrix3<f32> {
+        self.matrix
+    }
+
+    /// Checks if the matrix changed during the last flush
+    pub fn changed(&self) -> bool {
+        self.changed
+    }
+
+    pub fn handle(&self) -> TransformHandle {
+        self.handle.clone()
+    }
+
+    /// Moves a point from the space of this transform into the world
+    pub fn transform_point(&self, point: Vector) -> Vector {
+        let point = self.matrix * Vector2::new(point.x, point.y).push(1.0);
+        Vector::new(point.x, point.y)
+    }
+
+    /// Places a polygon basis and origin in the world, where the basis is stored transposed
+    /// the same way as in the transform slots
+    pub(crate) fn apply(&self, basis: &Matrix2<f32>, origin: Vector) -> (Matrix2<f32>, Vector) {
+        let linear: Matrix2<f32> = self.matrix.fixed_view::<2, 2>(0, 0).into_owned();
+        (basis * linear.transpose(), self.transform_point(origin))
+    }
+}
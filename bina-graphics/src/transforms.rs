@@ -0,0 +1,202 @@
+//! The transforms of every polygon, kept in one uniform buffer
+//!
+//! Each polygon owns a slot in the buffer for as long as it exists. Polygons only
+//! rewrite their slot when their transform changes, and every slot that changed
+//! in a frame is uploaded with a single write. Draws pick their slot with a dynamic offset
+use std::{
+    mem::size_of,
+    num::NonZeroU64,
+    ops::Range,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use atomic_float::AtomicF32;
+use bina_ecs::{
+    parking_lot::{Mutex, RwLock},
+    triomphe::Arc,
+};
+use wgpu::{BindGroupLayout, BufferUsages, Device};
+
+use crate::memory::{GpuMemory, MemoryAllocation, MemoryCategory};
+
+/// The number of floats in a transform: a 2x2 basis, an origin, the emission of the polygon,
+/// and padding to the alignment of the basis
+const TRANSFORM_FLOATS: usize = 8;
+
+/// The size of a transform in the shader
+pub(crate) const TRANSFORM_SIZE: u64 = (size_of::<f32>() * TRANSFORM_FLOATS) as u64;
+
+/// How many slots the buffer starts with
+const INITIAL_CAPACITY: u32 = 256;
+
+/// The transform of every polygon, as last written by the polygons themselves
+pub(crate) struct TransformSlots {
+    transforms: RwLock<Vec<[AtomicF32; TRANSFORM_FLOATS]>>,
+    free: Mutex<Vec<u32>>,
+    /// The slots written since the last upload, from `dirty_start` up to but excluding `dirty_end`
+    dirty_start: AtomicU32,
+    dirty_end: AtomicU32,
+}
+
+impl TransformSlots {
+    pub(crate) fn new() -> Self {
+        Self {
+            transforms: RwLock::new(Vec::new()),
+            free: Mutex::new(Vec::new()),
+            dirty_start: AtomicU32::new(u32::MAX),
+            dirty_end: AtomicU32::new(0),
+        }
+    }
+
+    pub(crate) fn allocate(slots: &Arc<Self>) -> TransformSlot {
+        let index = slots.free.lock().pop().unwrap_or_else(|| {
+            let mut transforms = slots.transforms.write();
+            transforms.push(Default::default());
+            transforms.len() as u32 - 1
+        });
+        TransformSlot {
+            index,
+            slots: slots.clone(),
+        }
+    }
+
+    pub(crate) fn set(&self, index: u32, transform: [f32; TRANSFORM_FLOATS]) {
+        let transforms = self.transforms.read();
+        for (atomic, value) in transforms[index as usize].iter().zip(transform) {
+            atomic.store(value, Ordering::Relaxed);
+        }
+        self.dirty_start.fetch_min(index, Ordering::Relaxed);
+        self.dirty_end.fetch_max(index + 1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn len(&self) -> u32 {
+        self.transforms.read().len() as u32
+    }
+
+    /// Gets the slots written since the last call, if any were
+    fn take_dirty(&self) -> Option<Range<u32>> {
+        let start = self.dirty_start.swap(u32::MAX, Ordering::Relaxed);
+        let end = self.dirty_end.swap(0, Ordering::Relaxed);
+        (start < end).then_some(start..end)
+    }
+
+    /// Lays out the given slots the way they are laid out in a buffer with the given stride
+    fn to_bytes(&self, range: Range<u32>, stride: u64) -> Vec<u8> {
+        let transforms = self.transforms.read();
+        let mut bytes = vec![0; (range.len() as u64 * stride) as usize];
+        for (chunk, transform) in bytes
+            .chunks_mut(stride as usize)
+            .zip(&transforms[range.start as usize..range.end as usize])
+        {
+            let floats = transform.each_ref().map(|x| x.load(Ordering::Relaxed));
+            chunk[..TRANSFORM_SIZE as usize].copy_from_slice(bytemuck::cast_slice(&floats));
+        }
+        bytes
+    }
+}
+
+/// A slot in `TransformSlots`, which is freed when this is dropped
+pub(crate) struct TransformSlot {
+    pub(crate) index: u32,
+    slots: Arc<TransformSlots>,
+}
+
+impl Drop for TransformSlot {
+    fn drop(&mut self) {
+        self.slots.free.lock().push(self.index);
+    }
+}
+
+/// The uniform buffer that the transforms are uploaded into
+pub(crate) struct TransformBuffer {
+    buffer: wgpu::Buffer,
+    pub(crate) bind_group: wgpu::BindGroup,
+    capacity: u32,
+    /// The distance between each slot, which is the smallest multiple of the device's
+    /// uniform offset alignment that fits a transform
+    stride: u64,
+    _memory: MemoryAllocation,
+}
+
+impl TransformBuffer {
+    pub(crate) fn new(
+        device: &Device,
+        layout: &BindGroupLayout,
+        memory: &Arc<GpuMemory>,
+        capacity: u32,
+    ) -> Self {
+        let alignment = device.limits().min_uniform_buffer_offset_alignment as u64;
+        let stride = TRANSFORM_SIZE.div_ceil(alignment) * alignment;
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("transform_buffer"),
+            size: stride * capacity as u64,
+            usage: BufferUsages::UNIFORM.union(BufferUsages::COPY_DST),
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &buffer,
+                    offset: 0,
+                    size: NonZeroU64::new(TRANSFORM_SIZE),
+                }),
+            }],
+            label: Some("transform_bind_group"),
+        });
+        Self {
+            _memory: GpuMemory::allocate(memory, MemoryCategory::Buffers, buffer.size()),
+            buffer,
+            bind_group,
+            capacity,
+            stride,
+        }
+    }
+
+    pub(crate) fn with_initial_capacity(
+        device: &Device,
+        layout: &BindGroupLayout,
+        memory: &Arc<GpuMemory>,
+    ) -> Self {
+        Self::new(device, layout, memory, INITIAL_CAPACITY)
+    }
+
+    /// The dynamic offset of the given slot
+    pub(crate) fn offset(&self, index: u32) -> u32 {
+        (index as u64 * self.stride) as u32
+    }
+}
+
+/// Uploads every transform that changed since the last upload
+///
+/// The buffer is replaced with a larger one if there are more slots than it can hold.
+/// If `full` is true, or the buffer was replaced, every transform is uploaded
+pub(crate) fn upload(
+    slots: &TransformSlots,
+    buffer: &Mutex<Arc<TransformBuffer>>,
+    device: &Device,
+    queue: &wgpu::Queue,
+    layout: &BindGroupLayout,
+    memory: &Arc<GpuMemory>,
+    mut full: bool,
+) {
+    let len = slots.len();
+    let mut buffer = buffer.lock();
+    if len > buffer.capacity {
+        *buffer = Arc::new(TransformBuffer::new(
+            device,
+            layout,
+            memory,
+            len.next_power_of_two(),
+        ));
+        full = true;
+    }
+    let dirty = slots.take_dirty();
+    let range = if full { Some(0..len) } else { dirty };
+    let Some(range) = range.filter(|x| !x.is_empty()) else {
+        return;
+    };
+    let bytes = slots.to_bytes(range.clone(), buffer.stride);
+    queue.write_buffer(&buffer.buffer, range.start as u64 * buffer.stride, &bytes);
+}
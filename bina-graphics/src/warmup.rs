@@ -0,0 +1,70 @@
+//! Doing the work of the first frames that draw something ahead of time, behind a loading screen
+//!
+//! Textures are only uploaded to the GPU the first time they are drawn, and render pipelines
+//! are only created the first time something needs them, which can make those frames hitch.
+//! Warming up does both while the loading screen is still showing
+//!
+//! ```ignore
+//! let mut progress = assets.preload(universe, ["sprites/hero.png", "sounds/jump.ogg"]);
+//! progress.add_task(graphics.warm_up(&progress));
+//! ```
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use bina_ecs::{
+    assets::{LoadState, Preload},
+    triomphe::Arc,
+};
+
+use crate::{texture::TextureHandle, Graphics};
+
+/// Finishes once every texture it was given has been uploaded and every render pipeline has been created
+///
+/// Created by `Graphics::warm_up`
+pub struct Warmup {
+    done: Arc<AtomicBool>,
+}
+
+impl Preload for Warmup {
+    fn load_state(&self) -> LoadState {
+        if self.done.load(Ordering::Acquire) {
+            LoadState::Loaded
+        } else {
+            LoadState::Loading
+        }
+    }
+}
+
+/// A `Warmup` that is still waiting for its textures to load
+pub(crate) struct PendingWarmup {
+    textures: Vec<TextureHandle>,
+    done: Arc<AtomicBool>,
+}
+
+impl PendingWarmup {
+    pub(crate) fn new(textures: Vec<TextureHandle>) -> (Self, Warmup) {
+        let done = Arc::new(AtomicBool::new(false));
+        let warmup = Warmup { done: done.clone() };
+        (Self { textures, done }, warmup)
+    }
+
+    /// Uploads every texture that has finished loading, returning whether none are still loading
+    pub(crate) fn upload(&mut self, graphics: &Graphics) -> bool {
+        self.textures.retain(|handle| match handle.load_state() {
+            LoadState::Loading => true,
+            LoadState::Loaded => {
+                if let Some(asset) = handle.get() {
+                    // The asset keeps the texture once it has been uploaded
+                    asset.get(graphics);
+                }
+                false
+            }
+            LoadState::Failed => false,
+        });
+        self.textures.is_empty()
+    }
+
+    /// Gets the flag the render thread sets once it has created every render pipeline
+    pub(crate) fn into_done(self) -> Arc<AtomicBool> {
+        self.done
+    }
+}
@@ -0,0 +1,209 @@
+//! Helpers for embedding a game into a web page
+//!
+//! Browsers only give a page a single thread which must never block, so the Universe runs
+//! one frame at a time on the browser's event loop instead of on its own thread
+//!
+//! ```ignore
+//! #[wasm_bindgen(start)]
+//! pub fn main() {
+//!     bina_graphics::web::start(async {
+//!         let universe = Universe::new();
+//!         run_in_canvas(universe, LoopCount::Forever, DeltaStrategy::RealDelta(Duration::ZERO), "game", ScalingMode::Expand).await
+//!     });
+//! }
+//! ```
+use std::{
+    future::Future,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use bina_ecs::{
+    time::{Duration, Instant},
+    tokio::sync::oneshot,
+    universe::{DeltaStrategy, LoopCount, Universe},
+};
+use wasm_bindgen::{prelude::wasm_bindgen, JsCast};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::HtmlCanvasElement;
+use winit::{
+    dpi::PhysicalSize,
+    event_loop::EventLoop,
+    platform::web::{WindowBuilderExtWebSys, WindowExtWebSys},
+    window::{Window, WindowBuilder},
+};
+
+use crate::{Graphics, ScalingMode};
+
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+/// Stops rendering new frames
+///
+/// The Universe will stop at the end of its current process frame
+/// as it waits for the renderer to take its draw instructions
+#[wasm_bindgen(js_name = pause)]
+pub fn pause() {
+    PAUSED.store(true, Ordering::Relaxed);
+}
+
+/// Resumes rendering after a call to `pause`
+#[wasm_bindgen(js_name = resume)]
+pub fn resume() {
+    PAUSED.store(false, Ordering::Relaxed);
+}
+
+pub fn is_paused() -> bool {
+    PAUSED.load(Ordering::Relaxed)
+}
+
+#[derive(Debug)]
+pub enum CanvasError {
+    /// There is no document to search in
+    NoDocument,
+    /// No element has the given id
+    NotFound,
+    /// The element with the given id is not a canvas
+    NotACanvas,
+}
+
+/// Finds the canvas element with the given id
+pub fn find_canvas(canvas_id: &str) -> Result<HtmlCanvasElement, CanvasError> {
+    web_sys::window()
+        .and_then(|window| window.document())
+        .ok_or(CanvasError::NoDocument)?
+        .get_element_by_id(canvas_id)
+        .ok_or(CanvasError::NotFound)?
+        .dyn_into()
+        .map_err(|_| CanvasError::NotACanvas)
+}
+
+/// Runs the given Universe inside the canvas with the given id
+///
+/// The canvas may be freely sized through CSS. Its drawing buffer is resized to match
+/// its displayed size (multiplied by `devicePixelRatio`) every frame, so the game is never
+/// stretched or blurry. Rendering can be paused and resumed from JavaScript through the
+/// exported `pause` and `resume` functions
+///
+/// # Panics
+/// Panics if the canvas cannot be found
+pub async fn run_in_canvas(
+    universe: Universe,
+    count: LoopCount,
+    delta: DeltaStrategy,
+    canvas_id: &str,
+    scaling_mode: ScalingMode,
+) -> ! {
+    let canvas = find_canvas(canvas_id).expect("Canvas should exist");
+    Graphics::run_with_window(
+        universe,
+        count,
+        delta,
+        EventLoop::new(),
+        WindowBuilder::new().with_canvas(Some(canvas)),
+        scaling_mode,
+    )
+    .await
+}
+
+/// Starts running the given future, such as one that calls `run_in_canvas`
+///
+/// Browsers cannot block on a future, so this should be called
+/// from a function marked with `#[wasm_bindgen(start)]`
+pub fn start(fut: impl Future<Output = ()> + 'static) {
+    wasm_bindgen_futures::spawn_local(fut);
+}
+
+/// Runs a Universe one frame at a time from the browser's event loop
+pub(crate) struct UniverseLoop {
+    universe: Option<Universe>,
+    /// The number of frames left to run, if limited
+    remaining: Option<usize>,
+    delta: DeltaStrategy,
+    last_frame: Instant,
+    exit_sender: Option<oneshot::Sender<i32>>,
+}
+
+impl UniverseLoop {
+    pub(crate) fn new(
+        universe: Universe,
+        count: LoopCount,
+        delta: DeltaStrategy,
+        exit_sender: oneshot::Sender<i32>,
+    ) -> Self {
+        Self {
+            universe: Some(universe),
+            remaining: match count {
+                LoopCount::Forever => None,
+                LoopCount::Count(n) => Some(n),
+            },
+            delta,
+            last_frame: Instant::now(),
+            exit_sender: Some(exit_sender),
+        }
+    }
+
+    /// Runs a single frame, unless the Universe has exited
+    ///
+    /// The browser decides how often frames run, so the target
+    /// delta of `DeltaStrategy::RealDelta` and `DeltaStrategy::Adaptive` is ignored
+    pub(crate) fn run_frame(&mut self) {
+        let Some(universe) = &mut self.universe else {
+            return;
+        };
+        if self.remaining == Some(0) {
+            self.exit(None);
+            return;
+        }
+        let now = Instant::now();
+        let delta = match &self.delta {
+            DeltaStrategy::FakeDelta(delta) => *delta,
+            DeltaStrategy::RealDelta(_) | DeltaStrategy::Adaptive(_) => now - self.last_frame,
+        };
+        self.last_frame = now;
+        if let Some(remaining) = &mut self.remaining {
+            *remaining -= 1;
+        }
+        if let Some(result) = universe.loop_once_with_delta(delta) {
+            self.exit(Some(result));
+        }
+    }
+
+    fn exit(&mut self, result: Option<Result<(), Box<dyn std::error::Error + Send + Sync>>>) {
+        drop(self.universe.take());
+        if let Some(result) = result {
+            result.expect("Error while running Universe");
+        }
+        if let Some(exit_sender) = self.exit_sender.take() {
+            let _ = exit_sender.send(0);
+        }
+    }
+}
+
+/// Waits for the given duration without blocking the browser
+pub(crate) async fn sleep(duration: Duration) {
+    let millis = duration.as_millis().min(i32::MAX as u128) as i32;
+    let promise = js_sys::Promise::new(&mut |resolve, _| {
+        if let Some(window) = web_sys::window() {
+            let _ = window.set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, millis);
+        }
+    });
+    let _ = JsFuture::from(promise).await;
+}
+
+/// Checks if the displayed size of the canvas differs from its drawing buffer,
+/// and if so, resizes the drawing buffer and returns its new size
+///
+/// Winit sizes the canvas through its style, which would override any CSS rules,
+/// so only the drawing buffer is changed here
+pub(crate) fn poll_canvas_size(window: &Window) -> Option<PhysicalSize<u32>> {
+    let canvas = window.canvas();
+    let ratio = web_sys::window()?.device_pixel_ratio();
+    let width = (canvas.client_width() as f64 * ratio).round() as u32;
+    let height = (canvas.client_height() as f64 * ratio).round() as u32;
+
+    if width == 0 || height == 0 || (width == canvas.width() && height == canvas.height()) {
+        return None;
+    }
+    canvas.set_width(width);
+    canvas.set_height(height);
+    Some(PhysicalSize::new(width, height))
+}
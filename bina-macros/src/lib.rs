@@ -1,15 +1,84 @@
 #![feature(ptr_from_ref)]
-use std::{ops::Deref, path::Path};
+use std::{
+    io::Write,
+    ops::Deref,
+    path::{Path, PathBuf},
+};
 
-use image::io::Reader as ImageReader;
+use image::{io::Reader as ImageReader, ImageFormat, ImageOutputFormat};
+use lyon::lyon_tessellation::{
+    BuffersBuilder, FillOptions, FillTessellator, FillVertex, VertexBuffers,
+};
 use proc_macro::TokenStream;
 use proc_macro2::Span;
 use quote::{format_ident, quote, ToTokens};
 use syn::{
-    parse::Parse, parse_macro_input, Data, DeriveInput, Fields, Ident, Lit, LitByteStr, Token,
-    Type, Visibility,
+    parse::Parse, parse_macro_input, Attribute, Data, DeriveInput, Fields, Ident, Lit, LitByteStr,
+    LitStr, Token, Type, Visibility,
 };
 
+/// The options of a struct or field given to `derive_component!` with `#[bina(...)]`
+#[derive(Default)]
+struct ComponentOptions {
+    /// Copies the doc comments onto the reference
+    doc: bool,
+    /// Makes the field public on the reference, whatever the visibility of the field itself
+    pub_ref: bool,
+    /// Leaves the field out of the reference
+    skip_ref: bool,
+}
+
+impl ComponentOptions {
+    /// Reads every `#[bina(...)]` attribute, removing them from `attrs`
+    fn take(attrs: &mut Vec<Attribute>) -> syn::Result<Self> {
+        let mut options = Self::default();
+        for attr in attrs.iter().filter(|x| x.path().is_ident("bina")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("doc") {
+                    options.doc = true;
+                } else if meta.path.is_ident("pub_ref") {
+                    options.pub_ref = true;
+                } else if meta.path.is_ident("skip_ref") {
+                    options.skip_ref = true;
+                } else {
+                    return Err(meta.error("Expected `doc`, `pub_ref`, or `skip_ref`"));
+                }
+                Ok(())
+            })?;
+        }
+        attrs.retain(|x| !x.path().is_ident("bina"));
+        Ok(options)
+    }
+}
+
+/// Declares a struct that implements `Component`, along with the `*Reference` struct that it is processed through
+///
+/// Fields marked with `#[improve]` that are numbers become `NumberField`s, which are changed
+/// through modifiers while processing and applied when flushed. Every other field is borrowed.
+/// How fields appear on the reference can be changed with `#[bina(...)]`:
+///
+/// - `doc` copies the doc comments of the field onto the reference. On the struct, it copies those of the struct
+/// - `pub_ref` makes the field public on the reference, even if the field itself is not.
+///   On the struct, it makes every field public on the reference
+/// - `skip_ref` leaves the field out of the reference, for fields that are not needed while processing
+///
+/// Otherwise, fields on the reference have the same visibility as the fields themselves
+///
+/// ```ignore
+/// derive_component! {
+///     /// Moves an entity every frame
+///     #[bina(doc)]
+///     pub struct Body {
+///         /// Units per second
+///         #[improve]
+///         #[bina(doc, pub_ref)]
+///         speed: f32,
+///         pub name: String,
+///         #[bina(skip_ref)]
+///         history: Vec<f32>,
+///     }
+/// }
+/// ```
 // #[proc_macro_derive(Component, attributes(improve))]
 #[proc_macro]
 pub fn derive_component(input: TokenStream) -> TokenStream {
@@ -17,7 +86,7 @@ pub fn derive_component(input: TokenStream) -> TokenStream {
         vis,
         ident,
         data,
-        attrs,
+        mut attrs,
         generics,
     } = parse_macro_input!(input);
 
@@ -27,59 +96,75 @@ pub fn derive_component(input: TokenStream) -> TokenStream {
     let Fields::Named(data) = data.fields else {
         return quote! { compile_error!("This macro can only handle named fields") }.into();
     };
-    let fields = data.named;
+    let struct_options = match ComponentOptions::take(&mut attrs) {
+        Ok(x) => x,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    if struct_options.skip_ref {
+        return quote! { compile_error!("`skip_ref` can only be used on fields") }.into();
+    }
     let ref_ident = format_ident!("{ident}Reference");
+    let ref_docs = attrs
+        .iter()
+        .filter(|x| struct_options.doc && x.path().is_ident("doc"));
     let mut process_modifier_fields = Vec::new();
     let mut new_struct_data = Vec::new();
+    let mut ref_data = Vec::new();
+    let mut get_ref_body = Vec::new();
 
-    let ref_data: Vec<_> = fields
-        .iter()
-        .map(|field| {
-            if let Some(attr) = field.attrs.last() {
-                if attr.meta.path().to_token_stream().to_string() == "improve" {
-                    let Type::Path(path) = &field.ty else {
-                        return quote! { compile_error!("Unexpected type") }.into();
-                    };
-                    let ident = field.ident.as_ref().unwrap();
-                    let ty = &field.ty;
-
-                    match path.to_token_stream().to_string().as_str() {
-                        "u8" | "u16" | "u32" | "u64" | "u128" | "usize" | "i8" | "i16" | "i32"
-                        | "i64" | "i128" | "isize" | "f32" | "f64" => {
-                            process_modifier_fields.push(ident);
-                            new_struct_data
-                                .push(quote! { #ident: bina::ecs::component::NumberField<#ty>, });
-                            quote! { #ident: bina::ecs::component::NumberFieldRef<'a, #ty>, }
-                        }
-                        _ => {
-                            new_struct_data.push(quote! { #ident: #ty, });
-                            quote! { #ident: &'a #ty, }
-                        }
-                    }
-                } else {
-                    return quote! { compile_error!("Unexpected attribute") }.into();
-                }
-            } else {
-                let ident = &field.ident;
-                let ty = &field.ty;
-                new_struct_data.push(quote! { #ident: #ty, });
-                quote! { #ident: &'a #ty, }
-            }
-        })
-        .collect();
+    for mut field in data.named {
+        let options = match ComponentOptions::take(&mut field.attrs) {
+            Ok(x) => x,
+            Err(e) => return e.to_compile_error().into(),
+        };
+        let attr_count = field.attrs.len();
+        field.attrs.retain(|x| !x.path().is_ident("improve"));
+        let improve = field.attrs.len() != attr_count;
+        if field.attrs.iter().any(|x| !x.path().is_ident("doc")) {
+            return quote! { compile_error!("Unexpected attribute") }.into();
+        }
+        if improve && options.skip_ref {
+            return quote! { compile_error!("Fields marked with #[improve] cannot be skipped, as they are changed through the reference") }.into();
+        }
 
-    let get_ref_body = fields.iter().map(|field| {
-        let ident = field.ident.as_ref().unwrap();
-        if process_modifier_fields.contains(&ident) {
-            quote! {
-                #ident: self.#ident.get_ref(),
-            }
+        let field_ident = field.ident.as_ref().unwrap();
+        let field_vis = &field.vis;
+        let ty = &field.ty;
+        let docs = &field.attrs;
+        let is_number = match ty {
+            Type::Path(path) => matches!(
+                path.to_token_stream().to_string().as_str(),
+                "u8" | "u16" | "u32" | "u64" | "u128" | "usize" | "i8" | "i16" | "i32"
+                    | "i64" | "i128" | "isize" | "f32" | "f64"
+            ),
+            _ if improve => return quote! { compile_error!("Unexpected type") }.into(),
+            _ => false,
+        };
+
+        let (struct_ty, ref_ty, get_ref) = if improve && is_number {
+            process_modifier_fields.push(field_ident.clone());
+            (
+                quote! { bina::ecs::component::NumberField<#ty> },
+                quote! { bina::ecs::component::NumberFieldRef<'a, #ty> },
+                quote! { self.#field_ident.get_ref() },
+            )
         } else {
-            quote! {
-                #ident: &self.#ident,
-            }
+            (quote! { #ty }, quote! { &'a #ty }, quote! { &self.#field_ident })
+        };
+        new_struct_data.push(quote! { #(#docs)* #field_vis #field_ident: #struct_ty, });
+        if options.skip_ref {
+            continue;
         }
-    });
+        let ref_vis = if options.pub_ref || struct_options.pub_ref {
+            quote! { pub }
+        } else {
+            field_vis.to_token_stream()
+        };
+        let ref_field_docs = docs.iter().filter(|_| options.doc);
+        ref_data.push(quote! { #(#ref_field_docs)* #ref_vis #field_ident: #ref_ty, });
+        get_ref_body.push(quote! { #field_ident: #get_ref, });
+    }
+
     let flush_body = process_modifier_fields.iter().map(|ident| {
         quote! { bina::ecs::component::ComponentField::process_modifiers(&mut self.#ident); }
     });
@@ -90,6 +175,7 @@ pub fn derive_component(input: TokenStream) -> TokenStream {
             #(#new_struct_data)*
         }
 
+        #(#ref_docs)*
         #vis struct #ref_ident<'a> {
             #(#ref_data)*
             _phantom: std::marker::PhantomData<&'a ()>
@@ -113,6 +199,149 @@ pub fn derive_component(input: TokenStream) -> TokenStream {
     .into()
 }
 
+/// Implements `Tag` for a unit struct, along with a `Component` and `Processable` that do nothing
+///
+/// ```ignore
+/// #[derive(Tag)]
+/// struct Player;
+/// ```
+#[proc_macro_derive(Tag)]
+pub fn derive_tag(input: TokenStream) -> TokenStream {
+    let DeriveInput {
+        ident,
+        data,
+        generics,
+        ..
+    } = parse_macro_input!(input);
+
+    let Data::Struct(data) = data else {
+        return quote! { compile_error!("Tags must be unit structs"); }.into();
+    };
+    // Anything with fields could take up memory in every entity
+    let Fields::Unit = data.fields else {
+        return quote! { compile_error!("Tags must be unit structs"); }.into();
+    };
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    quote! {
+        impl #impl_generics bina::ecs::component::Component for #ident #ty_generics #where_clause {
+            type Reference<'a> = &'a Self;
+
+            fn get_ref<'a>(&'a self) -> Self::Reference<'a> {
+                self
+            }
+        }
+
+        impl #impl_generics bina::ecs::component::Processable for #ident #ty_generics #where_clause {
+            fn process<E: bina::ecs::entity::Entity>(
+                _component: Self::Reference<'_>,
+                _my_entity: bina::ecs::entity::EntityReference<E>,
+                _universe: &bina::ecs::universe::Universe,
+            ) {
+            }
+        }
+
+        impl #impl_generics bina::ecs::component::Tag for #ident #ty_generics #where_clause {}
+    }
+    .into()
+}
+
+/// Implements `Singleton` for a struct that implements `CommandSingleton`, applying the commands
+/// queued in the field marked with `#[commands]` when the singleton is flushed
+///
+/// A `queue_command` method is added that queues a command from anywhere while processing,
+/// so the state of the singleton never needs to be behind a lock
+///
+/// ```ignore
+/// derive_singleton! {
+///     pub struct Score {
+///         total: u32,
+///         #[commands]
+///         commands: CommandQueue<ScoreCommand>,
+///     }
+/// }
+///
+/// impl CommandSingleton for Score {
+///     type Command = ScoreCommand;
+///
+///     fn apply(&mut self, command: ScoreCommand, _universe: &Universe) {
+///         match command {
+///             ScoreCommand::Add(n) => self.total += n,
+///             ScoreCommand::Reset => self.total = 0,
+///         }
+///     }
+/// }
+/// ```
+#[proc_macro]
+pub fn derive_singleton(input: TokenStream) -> TokenStream {
+    let DeriveInput {
+        vis,
+        ident,
+        data,
+        attrs,
+        generics,
+    } = parse_macro_input!(input);
+
+    let Data::Struct(data) = data else {
+        return quote! { compile_error!("This macro can only handle structs"); }.into();
+    };
+    let Fields::Named(fields) = data.fields else {
+        return quote! { compile_error!("This macro can only handle named fields"); }.into();
+    };
+    let mut queue_field = None;
+    let mut struct_fields = Vec::new();
+    for mut field in fields.named {
+        let len = field.attrs.len();
+        field.attrs.retain(|x| !x.path().is_ident("commands"));
+        if field.attrs.len() != len {
+            if queue_field.is_some() {
+                return quote! { compile_error!("Only one field can be marked with #[commands]"); }
+                    .into();
+            }
+            queue_field = field.ident.clone();
+        }
+        struct_fields.push(field);
+    }
+    let Some(queue_field) = queue_field else {
+        return quote! { compile_error!("A field with a CommandQueue must be marked with #[commands]"); }
+            .into();
+    };
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    quote! {
+        #(#attrs)*
+        #vis struct #ident #generics #where_clause {
+            #(#struct_fields,)*
+        }
+
+        impl #impl_generics #ident #ty_generics #where_clause {
+            /// Queues a command to be applied when this singleton is flushed
+            #vis fn queue_command(&self, command: <Self as bina::ecs::singleton::CommandSingleton>::Command) {
+                self.#queue_field.push(command);
+            }
+        }
+
+        impl #impl_generics bina::ecs::singleton::Singleton for #ident #ty_generics #where_clause {
+            fn flush_stage(&self) -> bina::ecs::universe::FlushStage {
+                bina::ecs::singleton::CommandSingleton::flush_stage(self)
+            }
+
+            fn process(&self, universe: &bina::ecs::universe::Universe) {
+                bina::ecs::singleton::CommandSingleton::process(self, universe);
+            }
+
+            fn flush(&mut self, universe: &bina::ecs::universe::Universe) {
+                let commands: Vec<_> = self.#queue_field.drain().collect();
+                for command in commands {
+                    bina::ecs::singleton::CommandSingleton::apply(self, command, universe);
+                }
+                bina::ecs::singleton::CommandSingleton::after_commands(self, universe);
+            }
+        }
+    }
+    .into()
+}
+
 struct ImageInput {
     pub vis: Visibility,
     pub ident: Ident,
@@ -131,6 +360,14 @@ impl Parse for ImageInput {
     }
 }
 
+/// Compiles an image into the binary as a `TextureResource`, which is decoded the first time it is used
+///
+/// The path is relative to the crate's Cargo.toml. PNG and QOI images are embedded as is,
+/// and every other format is converted to QOI, which is quick to decode
+///
+/// ```ignore
+/// load_image!(pub HERO = "sprites/hero.png");
+/// ```
 #[proc_macro]
 pub fn load_image(input: TokenStream) -> TokenStream {
     let ImageInput {
@@ -139,33 +376,790 @@ pub fn load_image(input: TokenStream) -> TokenStream {
     let Lit::Str(path) = path else {
         return quote! { compile_error!("Path must be a string literal") }.into();
     };
-    let path = path.to_token_stream().to_string();
-    let path = path.trim_matches('"');
+    let path = path.value();
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+    let absolute = Path::new(&manifest_dir).join(&path);
 
-    let img = match ImageReader::open(path) {
+    let img = match ImageReader::open(&absolute).and_then(|x| x.with_guessed_format()) {
         Ok(x) => x,
         Err(e) => {
-            let msg = format!(
-                "Failed to load image at {:?}: {e:?}",
-                AsRef::<Path>::as_ref(".")
-                    .canonicalize()
-                    .unwrap()
-                    .join(path)
-            );
+            let msg = format!("Failed to load image at {absolute:?}: {e:?}");
             return quote! { compile_error!(#msg) }.into();
         }
     };
+    let format = img.format();
     let Ok(img) = img.decode() else {
         return quote! { compile_error!("Image is invalid") }.into();
     };
-    let img = img.to_rgba8();
-
-    let bytes = LitByteStr::new(img.deref(), Span::call_site());
-    let bytes = Lit::ByteStr(bytes);
     let width = img.width();
     let height = img.height();
 
+    let absolute = absolute.to_string_lossy().into_owned();
+    let (bytes, format) = match format {
+        Some(ImageFormat::Png) => (quote! { include_bytes!(#absolute) }, quote! { Png }),
+        Some(ImageFormat::Qoi) => (quote! { include_bytes!(#absolute) }, quote! { Qoi }),
+        _ => {
+            let mut qoi = std::io::Cursor::new(Vec::new());
+            if image::DynamicImage::ImageRgba8(img.to_rgba8())
+                .write_to(&mut qoi, ImageOutputFormat::Qoi)
+                .is_err()
+            {
+                return quote! { compile_error!("Failed to convert image to QOI") }.into();
+            }
+            let bytes = LitByteStr::new(qoi.get_ref(), Span::call_site());
+            (
+                quote! {
+                    {
+                        // Makes cargo rebuild when the image changes
+                        const _: &[u8] = include_bytes!(#absolute);
+                        #bytes
+                    }
+                },
+                quote! { Qoi },
+            )
+        }
+    };
+
     quote! {
-        #vis static #ident: bina::graphics::raw_img::RawImg<bina::graphics::image::Rgba<u8>> = unsafe { bina::graphics::raw_img::RawImg::new(#width, #height, #bytes) };
+        #vis static #ident: bina::graphics::texture::TextureResource<bina::graphics::image::Rgba<u8>, #width, #height> =
+            bina::graphics::texture::TextureResource::new_embedded(#path, #bytes, bina::graphics::image::ImageFormat::#format);
     }.into()
 }
+
+struct EmbedInput {
+    pub path: LitStr,
+    pub compress: bool,
+}
+
+impl Parse for EmbedInput {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let path = input.parse()?;
+        let compress = if input.parse::<Option<Token![,]>>()?.is_some() {
+            let ident: Ident = input.parse()?;
+            if ident != "compress" {
+                return Err(syn::Error::new(ident.span(), "Expected `compress`"));
+            }
+            true
+        } else {
+            false
+        };
+        Ok(Self { path, compress })
+    }
+}
+
+/// Finds every file in the given directory and its subdirectories
+fn walk_dir(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        for entry in std::fs::read_dir(current)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Compiles every file in a directory into the binary, so that it can be mounted in `Assets`
+///
+/// The path is relative to the crate's Cargo.toml. Adding `compress` stores the files
+/// compressed with deflate. Cargo only rebuilds when files that were embedded change,
+/// so adding a new file requires a clean build
+///
+/// ```ignore
+/// static ASSETS: EmbeddedAssets = embed_assets!("assets/", compress);
+/// ```
+#[proc_macro]
+pub fn embed_assets(input: TokenStream) -> TokenStream {
+    let EmbedInput { path, compress } = parse_macro_input!(input);
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+    let dir = Path::new(&manifest_dir).join(path.value());
+
+    let mut files = match walk_dir(&dir) {
+        Ok(x) => x,
+        Err(e) => {
+            let msg = format!("Failed to read directory at {dir:?}: {e:?}");
+            return quote! { compile_error!(#msg) }.into();
+        }
+    };
+    let relative = |file: &Path| {
+        file.strip_prefix(&dir)
+            .unwrap()
+            .components()
+            .map(|x| x.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/")
+    };
+    // EmbeddedAssets uses a binary search to find files
+    files.sort_by_cached_key(|file| relative(file));
+
+    let mut entries = Vec::with_capacity(files.len());
+    let mut tracked = Vec::new();
+    for file in files {
+        let file_path = relative(&file);
+        let absolute = file.to_string_lossy().into_owned();
+        if compress {
+            let bytes = match std::fs::read(&file) {
+                Ok(x) => x,
+                Err(e) => {
+                    let msg = format!("Failed to read {absolute}: {e:?}");
+                    return quote! { compile_error!(#msg) }.into();
+                }
+            };
+            let mut encoder =
+                flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::best());
+            let compressed = encoder.write_all(&bytes).and_then(|_| encoder.finish());
+            let Ok(compressed) = compressed else {
+                return quote! { compile_error!("Failed to compress embedded file") }.into();
+            };
+            let bytes = LitByteStr::new(&compressed, Span::call_site());
+            entries.push(quote! {
+                bina::ecs::pack::EmbeddedFile {
+                    path: #file_path,
+                    bytes: #bytes,
+                    compression: bina::ecs::pack::PackCompression::Deflate,
+                }
+            });
+            // Makes cargo rebuild when the file changes
+            tracked.push(absolute);
+        } else {
+            entries.push(quote! {
+                bina::ecs::pack::EmbeddedFile {
+                    path: #file_path,
+                    bytes: include_bytes!(#absolute),
+                    compression: bina::ecs::pack::PackCompression::None,
+                }
+            });
+        }
+    }
+
+    quote! {
+        {
+            #(const _: &[u8] = include_bytes!(#tracked);)*
+            bina::ecs::pack::EmbeddedAssets::new(&[#(#entries),*])
+        }
+    }
+    .into()
+}
+
+/// Checks if a file name matches a pattern where `*` matches any number of characters
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => {
+            let Some(name) = name.strip_prefix(prefix) else {
+                return false;
+            };
+            (0..=name.len()).any(|i| name.is_char_boundary(i) && wildcard_match(rest, &name[i..]))
+        }
+    }
+}
+
+/// Gap between images, so that filtering does not bleed neighbouring images into each other
+const ATLAS_PADDING: u32 = 1;
+
+/// Packs rectangles into rows, tallest first
+///
+/// Returns the position of each rectangle in the same order they were given,
+/// and the size of the atlas, which is always a power of two
+fn pack_shelves(sizes: &[(u32, u32)]) -> (Vec<(u32, u32)>, u32, u32) {
+    let area: u64 = sizes
+        .iter()
+        .map(|(w, h)| (w + ATLAS_PADDING) as u64 * (h + ATLAS_PADDING) as u64)
+        .sum();
+    let max_width = sizes.iter().map(|x| x.0).max().unwrap_or(1);
+    let width = ((area as f64).sqrt().ceil() as u32)
+        .max(max_width)
+        .next_power_of_two();
+
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by_key(|&i| std::cmp::Reverse(sizes[i].1));
+    let mut positions = vec![(0, 0); sizes.len()];
+    let (mut x, mut y, mut shelf_height) = (0, 0, 0);
+    for i in order {
+        let (w, h) = sizes[i];
+        if x + w > width {
+            x = 0;
+            y += shelf_height + ATLAS_PADDING;
+            shelf_height = 0;
+        }
+        positions[i] = (x, y);
+        x += w + ATLAS_PADDING;
+        shelf_height = shelf_height.max(h);
+    }
+    let height = (y + shelf_height).max(1).next_power_of_two();
+    (positions, width, height)
+}
+
+/// Packs every image matching the pattern into one `TextureAtlas` at compile time
+///
+/// The pattern is relative to the crate's Cargo.toml, and may only have
+/// wildcards in the file name. Regions are named after the file stem of their image
+///
+/// ```ignore
+/// load_atlas!(pub SPRITES = "sprites/*.png");
+/// ```
+#[proc_macro]
+pub fn load_atlas(input: TokenStream) -> TokenStream {
+    let ImageInput {
+        vis, ident, path, ..
+    } = parse_macro_input!(input);
+    let Lit::Str(path) = path else {
+        return quote! { compile_error!("Path must be a string literal") }.into();
+    };
+    let pattern = path.value();
+    let (dir, file_pattern) = pattern.rsplit_once('/').unwrap_or((".", &pattern));
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+    let dir = Path::new(&manifest_dir).join(dir);
+
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(x) => x,
+        Err(e) => {
+            let msg = format!("Failed to read directory at {dir:?}: {e:?}");
+            return quote! { compile_error!(#msg) }.into();
+        }
+    };
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|x| x.ok().map(|x| x.path()))
+        .filter(|x| {
+            x.is_file()
+                && x.file_name()
+                    .is_some_and(|name| wildcard_match(file_pattern, &name.to_string_lossy()))
+        })
+        .collect();
+    files.sort();
+    if files.is_empty() {
+        let msg = format!("No images match {pattern:?}");
+        return quote! { compile_error!(#msg) }.into();
+    }
+
+    let mut names = Vec::with_capacity(files.len());
+    let mut images = Vec::with_capacity(files.len());
+    for file in &files {
+        let name = file
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        if names.contains(&name) {
+            let msg = format!("More than one image is named {name:?}");
+            return quote! { compile_error!(#msg) }.into();
+        }
+        let img = match ImageReader::open(file).map(|x| x.decode()) {
+            Ok(Ok(x)) => x.to_rgba8(),
+            _ => {
+                let msg = format!("Image at {file:?} is invalid");
+                return quote! { compile_error!(#msg) }.into();
+            }
+        };
+        names.push(name);
+        images.push(img);
+    }
+
+    let sizes: Vec<_> = images.iter().map(|x| x.dimensions()).collect();
+    let (positions, width, height) = pack_shelves(&sizes);
+    let mut atlas = image::RgbaImage::new(width, height);
+    for (img, &(x, y)) in images.iter().zip(&positions) {
+        image::imageops::replace(&mut atlas, img, x as i64, y as i64);
+    }
+
+    // TextureAtlas uses a binary search to find regions
+    let mut regions: Vec<_> = names
+        .iter()
+        .zip(sizes.iter().zip(&positions))
+        .map(|(name, (&(w, h), &(x, y)))| (name, x, y, w, h))
+        .collect();
+    regions.sort_by(|a, b| a.0.cmp(b.0));
+    let regions = regions.into_iter().map(|(name, x, y, w, h)| {
+        quote! {
+            bina::graphics::atlas::AtlasRegion { name: #name, x: #x, y: #y, width: #w, height: #h }
+        }
+    });
+    // Makes cargo rebuild when an image changes
+    let tracked = files.iter().map(|x| x.to_string_lossy().into_owned());
+
+    let bytes = LitByteStr::new(atlas.deref(), Span::call_site());
+    quote! {
+        #vis static #ident: bina::graphics::atlas::TextureAtlas<#width, #height> = {
+            #(const _: &[u8] = include_bytes!(#tracked);)*
+            bina::graphics::atlas::TextureAtlas::new(
+                unsafe { bina::graphics::texture::TextureResource::new_raw(#bytes) },
+                &[#(#regions),*],
+            )
+        };
+    }
+    .into()
+}
+
+/// Compiles an SVG image into the binary as an `SvgResource`, which is tessellated the first time it is used
+///
+/// The path is relative to the crate's Cargo.toml
+///
+/// ```ignore
+/// load_svg!(pub LOGO = "images/logo.svg");
+/// ```
+#[proc_macro]
+pub fn load_svg(input: TokenStream) -> TokenStream {
+    let ImageInput {
+        vis, ident, path, ..
+    } = parse_macro_input!(input);
+    let Lit::Str(path) = path else {
+        return quote! { compile_error!("Path must be a string literal") }.into();
+    };
+    let path = path.value();
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+    let absolute = Path::new(&manifest_dir).join(&path);
+
+    if let Err(e) = std::fs::metadata(&absolute) {
+        let msg = format!("Failed to load SVG at {absolute:?}: {e:?}");
+        return quote! { compile_error!(#msg) }.into();
+    }
+    let absolute = absolute.to_string_lossy().into_owned();
+
+    quote! {
+        #vis static #ident: bina::graphics::svg::SvgResource =
+            bina::graphics::svg::SvgResource::new(#path, include_bytes!(#absolute));
+    }
+    .into()
+}
+
+enum PolygonSource {
+    Svg(LitStr),
+    Points(Vec<(f32, f32)>),
+}
+
+struct PolygonInput {
+    pub vis: Visibility,
+    pub ident: Ident,
+    pub _eq_token: Token![=],
+    pub source: PolygonSource,
+}
+
+/// Parses a number literal, which may be negative
+fn parse_number(input: syn::parse::ParseStream) -> syn::Result<f32> {
+    let negative = input.parse::<Option<Token![-]>>()?.is_some();
+    let value = match input.parse::<Lit>()? {
+        Lit::Float(x) => x.base10_parse::<f32>()?,
+        Lit::Int(x) => x.base10_parse::<f32>()?,
+        lit => return Err(syn::Error::new(lit.span(), "Expected a number")),
+    };
+    Ok(if negative { -value } else { value })
+}
+
+impl Parse for PolygonInput {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let vis = input.parse()?;
+        let ident = input.parse()?;
+        let _eq_token = input.parse()?;
+        let source = if input.peek(LitStr) {
+            PolygonSource::Svg(input.parse()?)
+        } else {
+            let content;
+            syn::bracketed!(content in input);
+            let mut points = Vec::new();
+            while !content.is_empty() {
+                let point;
+                syn::parenthesized!(point in content);
+                let x = parse_number(&point)?;
+                point.parse::<Token![,]>()?;
+                let y = parse_number(&point)?;
+                point.parse::<Option<Token![,]>>()?;
+                points.push((x, y));
+                if content.parse::<Option<Token![,]>>()?.is_none() {
+                    break;
+                }
+            }
+            PolygonSource::Points(points)
+        };
+        Ok(Self {
+            vis,
+            ident,
+            _eq_token,
+            source,
+        })
+    }
+}
+
+/// Triangles where each vertex is its position followed by its texture coordinates
+type Geometry = VertexBuffers<[f32; 4], u32>;
+
+/// Tessellates the area inside a closed loop of points, stretching the texture over its bounds,
+/// and gives the width and height of the bounds
+fn tessellate_points(points: &[(f32, f32)]) -> Result<(Geometry, f32, f32), String> {
+    if points.len() < 3 {
+        return Err("A polygon needs at least 3 points".into());
+    }
+    let (min_x, max_x, min_y, max_y) = points.iter().fold(
+        (f32::MAX, f32::MIN, f32::MAX, f32::MIN),
+        |(min_x, max_x, min_y, max_y), &(x, y)| (min_x.min(x), max_x.max(x), min_y.min(y), max_y.max(y)),
+    );
+    let width = max_x - min_x;
+    let height = max_y - min_y;
+    let lyon_points: Vec<_> = points.iter().map(|&(x, y)| lyon::math::point(x, y)).collect();
+    let mut builder = lyon::path::Path::builder();
+    builder.add_polygon(lyon::path::Polygon {
+        points: &lyon_points,
+        closed: true,
+    });
+
+    let mut geometry: Geometry = VertexBuffers::new();
+    FillTessellator::new()
+        .tessellate_path(
+            &builder.build(),
+            &FillOptions::default(),
+            &mut BuffersBuilder::new(&mut geometry, |vertex: FillVertex| {
+                let p = vertex.position();
+                // Texture coordinates point y down, so the top of the bounds is 0
+                [
+                    p.x,
+                    p.y,
+                    (p.x - min_x) / width.max(f32::EPSILON),
+                    (max_y - p.y) / height.max(f32::EPSILON),
+                ]
+            }),
+        )
+        .map_err(|e| format!("Failed to tessellate polygon: {e:?}"))?;
+    Ok((geometry, width, height))
+}
+
+/// Tessellates an SVG image or a list of points at compile time into a `PolygonResource`,
+/// so that creating polygons from it only needs to upload the triangles
+///
+/// An SVG path is relative to the crate's Cargo.toml, and is tessellated the same way as `load_svg!`,
+/// with one shape for every fill and stroke. A list of points becomes one shape, which is the area
+/// inside the loop of points with the texture stretched over its bounds
+///
+/// ```ignore
+/// load_polygon!(pub SHIP = "shapes/ship.svg");
+/// load_polygon!(pub ARROW = [(0.0, -0.5), (1.0, 0.0), (0.0, 0.5), (0.25, 0.0)]);
+///
+/// universe.queue_add_entity((ARROW.polygon(graphics, Material::FlatColor(Rgba([255, 0, 0, 255]))),));
+/// for polygon in SHIP.polygons(graphics) {
+///     universe.queue_add_entity((polygon,));
+/// }
+/// ```
+#[proc_macro]
+pub fn load_polygon(input: TokenStream) -> TokenStream {
+    let PolygonInput {
+        vis, ident, source, ..
+    } = parse_macro_input!(input);
+
+    let (name, shapes, width, height, tracked) = match source {
+        PolygonSource::Svg(path) => {
+            let path = path.value();
+            let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+            let absolute = Path::new(&manifest_dir).join(&path);
+            let bytes = match std::fs::read(&absolute) {
+                Ok(x) => x,
+                Err(e) => {
+                    let msg = format!("Failed to load SVG at {absolute:?}: {e:?}");
+                    return quote! { compile_error!(#msg) }.into();
+                }
+            };
+            let svg = match bina_svg::parse_svg(&bytes) {
+                Ok(x) => x,
+                Err(e) => {
+                    let msg = format!("{path} is invalid: {e}");
+                    return quote! { compile_error!(#msg) }.into();
+                }
+            };
+            let shapes = svg
+                .shapes
+                .into_iter()
+                .map(|shape| (shape.geometry, shape.color.0))
+                .collect();
+            let absolute = absolute.to_string_lossy().into_owned();
+            (path, shapes, svg.width, svg.height, Some(absolute))
+        }
+        PolygonSource::Points(points) => match tessellate_points(&points) {
+            Ok((geometry, width, height)) => (
+                ident.to_string(),
+                vec![(geometry, [255; 4])],
+                width,
+                height,
+                None,
+            ),
+            Err(e) => return quote! { compile_error!(#e) }.into(),
+        },
+    };
+
+    let all_finite = shapes
+        .iter()
+        .flat_map(|(geometry, _)| geometry.vertices.iter().flatten())
+        .all(|x| x.is_finite());
+    if !all_finite || !width.is_finite() || !height.is_finite() {
+        return quote! { compile_error!("Polygon has vertices that are not finite") }.into();
+    }
+    let shapes = shapes.iter().map(|(geometry, [r, g, b, a])| {
+        let vertices = geometry
+            .vertices
+            .iter()
+            .map(|[x, y, tx, ty]| quote! { [#x, #y, #tx, #ty] });
+        let indices = &geometry.indices;
+        quote! {
+            bina::graphics::polygon::StaticShape {
+                vertices: &[#(#vertices),*],
+                indices: &[#(#indices),*],
+                color: bina::graphics::image::Rgba([#r, #g, #b, #a]),
+            }
+        }
+    });
+    // Makes cargo rebuild when the SVG changes
+    let tracked = tracked.map(|x| quote! { const _: &[u8] = include_bytes!(#x); });
+
+    quote! {
+        #vis static #ident: bina::graphics::polygon::PolygonResource = {
+            #tracked
+            bina::graphics::polygon::PolygonResource::new(#name, &[#(#shapes),*], (#width, #height))
+        };
+    }
+    .into()
+}
+
+/// Reads the number of channels and the sample rate from the header of a WAV or OGG Vorbis file
+#[cfg(feature = "audio")]
+fn read_audio_header(bytes: &[u8]) -> Result<(u16, u32), String> {
+    let u16_at = |i: usize| bytes.get(i..i + 2).map(|x| u16::from_le_bytes([x[0], x[1]]));
+    let u32_at = |i: usize| {
+        bytes
+            .get(i..i + 4)
+            .map(|x| u32::from_le_bytes([x[0], x[1], x[2], x[3]]))
+    };
+
+    if bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(&b"WAVE"[..]) {
+        let mut fmt = None;
+        let mut has_data = false;
+        let mut i = 12;
+        while let Some(size) = u32_at(i + 4) {
+            match &bytes[i..i + 4] {
+                b"fmt " => fmt = Some(i + 8),
+                b"data" => has_data = true,
+                _ => {}
+            }
+            // Chunks are padded to an even length
+            i += 8 + size as usize + (size as usize & 1);
+        }
+        let Some(fmt) = fmt else {
+            return Err("WAV file has no fmt chunk".into());
+        };
+        if !has_data {
+            return Err("WAV file has no data chunk".into());
+        }
+        let (Some(format), Some(channels), Some(sample_rate), Some(bits)) =
+            (u16_at(fmt), u16_at(fmt + 2), u32_at(fmt + 4), u16_at(fmt + 14))
+        else {
+            return Err("WAV fmt chunk is too short".into());
+        };
+        match (format, bits) {
+            // PCM, or extensible, which is how PCM with more than 2 channels or 16 bits is usually stored
+            (1 | 0xFFFE, 8 | 16 | 24 | 32) | (3, 32) => {}
+            (3, _) => return Err(format!("WAV files with {bits} bit floats are not supported")),
+            (1 | 0xFFFE, _) => return Err(format!("WAV files with {bits} bit samples are not supported")),
+            _ => return Err(format!("WAV files in format {format:#x} are not supported, only PCM and floats are")),
+        }
+        if channels == 0 || sample_rate == 0 {
+            return Err("WAV file has no channels or a sample rate of 0".into());
+        }
+        return Ok((channels, sample_rate));
+    }
+
+    if bytes.starts_with(b"OggS") {
+        // The first packet starts after the page header and its table of segment lengths
+        let segments = *bytes.get(26).ok_or("OGG page header is too short")? as usize;
+        let packet = 27 + segments;
+        if bytes.get(packet..packet + 8) == Some(&b"OpusHead"[..]) {
+            return Err("OGG files with Opus are not supported, only Vorbis is".into());
+        }
+        if bytes.get(packet..packet + 7) != Some(&b"\x01vorbis"[..]) {
+            return Err("OGG file does not start with a Vorbis identification header".into());
+        }
+        let (Some(version), Some(&channels), Some(sample_rate)) =
+            (u32_at(packet + 7), bytes.get(packet + 11), u32_at(packet + 12))
+        else {
+            return Err("Vorbis identification header is too short".into());
+        };
+        if version != 0 || channels == 0 || sample_rate == 0 {
+            return Err("Vorbis identification header is invalid".into());
+        }
+        return Ok((channels as u16, sample_rate));
+    }
+
+    Err("Only WAV and OGG Vorbis files are supported".into())
+}
+
+/// Compiles a WAV or OGG Vorbis file into the binary as a `SoundResource`, which is decoded the first time it is used
+///
+/// The path is relative to the crate's Cargo.toml. The header is checked at compile time,
+/// so files in a format that cannot be played fail to compile instead
+///
+/// ```ignore
+/// load_audio!(pub JUMP = "sounds/jump.wav");
+///
+/// if let Ok(sound) = JUMP.handle() {
+///     audio.play(&sound, PlaySettings::default());
+/// }
+/// ```
+#[cfg(feature = "audio")]
+#[proc_macro]
+pub fn load_audio(input: TokenStream) -> TokenStream {
+    let ImageInput {
+        vis, ident, path, ..
+    } = parse_macro_input!(input);
+    let Lit::Str(path) = path else {
+        return quote! { compile_error!("Path must be a string literal") }.into();
+    };
+    let path = path.value();
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+    let absolute = Path::new(&manifest_dir).join(&path);
+
+    let bytes = match std::fs::read(&absolute) {
+        Ok(x) => x,
+        Err(e) => {
+            let msg = format!("Failed to load audio at {absolute:?}: {e:?}");
+            return quote! { compile_error!(#msg) }.into();
+        }
+    };
+    let (channels, sample_rate) = match read_audio_header(&bytes) {
+        Ok(x) => x,
+        Err(e) => {
+            let msg = format!("{path} is invalid: {e}");
+            return quote! { compile_error!(#msg) }.into();
+        }
+    };
+    let absolute = absolute.to_string_lossy().into_owned();
+
+    quote! {
+        #vis static #ident: bina::audio::sound::SoundResource =
+            bina::audio::sound::SoundResource::new(#path, include_bytes!(#absolute), #channels, #sample_rate);
+    }
+    .into()
+}
+
+/// Reads the family name and the number of glyphs of a TrueType or OpenType font,
+/// checking that it has every table needed to draw it
+fn read_font_tables(bytes: &[u8]) -> Result<(Option<String>, u16), String> {
+    let u16_at = |i: usize| bytes.get(i..i + 2).map(|x| u16::from_be_bytes([x[0], x[1]]));
+    let u32_at = |i: usize| {
+        bytes
+            .get(i..i + 4)
+            .map(|x| u32::from_be_bytes([x[0], x[1], x[2], x[3]]))
+    };
+
+    match bytes.get(0..4) {
+        Some(b"\x00\x01\x00\x00" | b"OTTO" | b"true") => {}
+        Some(b"wOFF" | b"wOF2") => return Err("WOFF fonts are not supported, convert it to TTF or OTF".into()),
+        Some(b"ttcf") => return Err("Font collections are not supported, extract a single font".into()),
+        _ => return Err("Only TrueType and OpenType fonts are supported".into()),
+    }
+    let count = u16_at(4).ok_or("Font header is too short")? as usize;
+    let mut tables = Vec::with_capacity(count);
+    for i in 0..count {
+        let record = 12 + i * 16;
+        let (Some(tag), Some(offset), Some(length)) =
+            (bytes.get(record..record + 4), u32_at(record + 8), u32_at(record + 12))
+        else {
+            return Err("Font table directory is truncated".into());
+        };
+        let (offset, length) = (offset as usize, length as usize);
+        if offset.checked_add(length).map_or(true, |end| end > bytes.len()) {
+            let tag = String::from_utf8_lossy(tag);
+            return Err(format!("Font table {tag} is past the end of the file"));
+        }
+        tables.push((tag, offset));
+    }
+    let table = |tag: &[u8]| tables.iter().find(|(x, _)| *x == tag).map(|(_, offset)| *offset);
+
+    for tag in ["head", "cmap", "hhea", "hmtx", "maxp"] {
+        if table(tag.as_bytes()).is_none() {
+            return Err(format!("Font has no {tag} table"));
+        }
+    }
+    let has_outlines = (table(b"glyf").is_some() && table(b"loca").is_some())
+        || table(b"CFF ").is_some()
+        || table(b"CFF2").is_some();
+    if !has_outlines {
+        return Err("Font has no glyph outlines".into());
+    }
+    let glyph_count = table(b"maxp")
+        .and_then(|x| u16_at(x + 4))
+        .ok_or("Font maxp table is too short")?;
+
+    // The family name is optional, as the name of the static is used without one
+    let family = table(b"name").and_then(|name| {
+        let count = u16_at(name + 2)? as usize;
+        let strings = name + u16_at(name + 4)? as usize;
+        (0..count).find_map(|i| {
+            let record = name + 6 + i * 12;
+            let (platform, name_id) = (u16_at(record)?, u16_at(record + 6)?);
+            let (length, offset) = (u16_at(record + 8)? as usize, u16_at(record + 10)? as usize);
+            if name_id != 1 {
+                return None;
+            }
+            let text = bytes.get(strings + offset..strings + offset + length)?;
+            match platform {
+                // Unicode and Windows names are UTF-16
+                0 | 3 => {
+                    let units: Vec<u16> = text
+                        .chunks_exact(2)
+                        .map(|x| u16::from_be_bytes([x[0], x[1]]))
+                        .collect();
+                    String::from_utf16(&units).ok()
+                }
+                // Mac names are Mac Roman, which is the same as ASCII for most names
+                1 => text.is_ascii().then(|| String::from_utf8_lossy(text).into_owned()),
+                _ => None,
+            }
+        })
+    });
+    Ok((family, glyph_count))
+}
+
+/// Compiles a TrueType or OpenType font into the binary as a `FontResource`
+///
+/// The path is relative to the crate's Cargo.toml. The tables of the font are checked
+/// at compile time, so fonts that cannot be drawn fail to compile instead
+///
+/// ```ignore
+/// load_font!(pub PIXEL = "fonts/pixel.ttf");
+///
+/// graphics.add_font(&PIXEL);
+/// ```
+#[proc_macro]
+pub fn load_font(input: TokenStream) -> TokenStream {
+    let ImageInput {
+        vis, ident, path, ..
+    } = parse_macro_input!(input);
+    let Lit::Str(path) = path else {
+        return quote! { compile_error!("Path must be a string literal") }.into();
+    };
+    let path = path.value();
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+    let absolute = Path::new(&manifest_dir).join(&path);
+
+    let bytes = match std::fs::read(&absolute) {
+        Ok(x) => x,
+        Err(e) => {
+            let msg = format!("Failed to load font at {absolute:?}: {e:?}");
+            return quote! { compile_error!(#msg) }.into();
+        }
+    };
+    let (family, glyph_count) = match read_font_tables(&bytes) {
+        Ok(x) => x,
+        Err(e) => {
+            let msg = format!("{path} is invalid: {e}");
+            return quote! { compile_error!(#msg) }.into();
+        }
+    };
+    let family = family.unwrap_or_else(|| ident.to_string());
+    let absolute = absolute.to_string_lossy().into_owned();
+
+    quote! {
+        #vis static #ident: bina::graphics::font::FontResource =
+            bina::graphics::font::FontResource::new(#path, #family, include_bytes!(#absolute), #glyph_count);
+    }
+    .into()
+}
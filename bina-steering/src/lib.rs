@@ -0,0 +1,219 @@
+//! Steering behaviors for movement AI
+//!
+//! Each behavior adds a force to a `Steering`, which is applied to a velocity at the end.
+//! Behaviors are weighted, so they can be blended together
+//!
+//! ```ignore
+//! // Inside of the process of a component with a `velocity: NumberField<Vector>`
+//! let index = universe.get_singleton::<SpatialIndex>();
+//! let mut velocity = component.velocity.get_ref();
+//! Steering::new(position, *velocity, 200.0)
+//!     .with_agent(component.agent_id, index)
+//!     .arrive(target, 64.0, 1.0)
+//!     .separation(32.0, 1.5)
+//!     .alignment(96.0, 0.5)
+//!     .cohesion(96.0, 0.5)
+//!     .apply(&mut velocity, universe.get_delta());
+//! index.insert(Agent { id: component.agent_id, position, velocity: *velocity });
+//! ```
+use std::sync::atomic::Ordering;
+
+use atomic_float::AtomicF32;
+use bina_ecs::{component::NumberFieldRef, rand::Rng, rng::BufferedRng};
+use bina_graphics::polygon::Vector;
+
+pub mod spatial;
+
+pub use spatial::{Agent, AgentId, SpatialIndex};
+
+/// Shortens a vector to at most `max` long
+fn truncate(vector: Vector, max: f32) -> Vector {
+    let length = vector.length();
+    if length > max {
+        vector * (max / length)
+    } else {
+        vector
+    }
+}
+
+/// Lengthens or shortens a vector to exactly `length` long, unless it is zero
+fn with_length(vector: Vector, length: f32) -> Vector {
+    let current = vector.length();
+    if current > f32::EPSILON {
+        vector * (length / current)
+    } else {
+        vector
+    }
+}
+
+/// Wandering around aimlessly, but smoothly
+///
+/// A point is moved randomly around a circle in front of the agent, and the agent seeks it.
+/// The point is kept between updates, so keep this in the component of the agent
+pub struct Wander {
+    /// The radius of the circle
+    pub radius: f32,
+    /// How far in front of the agent the circle is
+    pub distance: f32,
+    /// The most the point moves around the circle in each update, in radians
+    pub jitter: f32,
+    angle: AtomicF32,
+}
+
+impl Wander {
+    pub fn new(radius: f32, distance: f32, jitter: f32) -> Self {
+        Self {
+            radius,
+            distance,
+            jitter,
+            angle: AtomicF32::new(0.0),
+        }
+    }
+}
+
+/// Forces on an agent, which are summed up and then applied to its velocity
+#[must_use = "the velocity only changes when the steering is applied"]
+pub struct Steering<'a> {
+    position: Vector,
+    velocity: Vector,
+    max_speed: f32,
+    /// The most the velocity can change per second
+    max_force: f32,
+    force: Vector,
+    /// The agent itself, and the index its neighbors are found in
+    agent: Option<(AgentId, &'a SpatialIndex)>,
+}
+
+impl<'a> Steering<'a> {
+    /// Starts steering an agent that moves no faster than `max_speed`
+    pub fn new(position: Vector, velocity: Vector, max_speed: f32) -> Self {
+        Self {
+            position,
+            velocity,
+            max_speed,
+            max_force: f32::INFINITY,
+            force: Vector::new(0.0, 0.0),
+            agent: None,
+        }
+    }
+
+    /// Limits how quickly the velocity can change, per second. Unlimited by default
+    pub fn with_max_force(mut self, max_force: f32) -> Self {
+        self.max_force = max_force;
+        self
+    }
+
+    /// Sets the agent that is being steered, which is needed for the flocking behaviors
+    pub fn with_agent(mut self, id: AgentId, index: &'a SpatialIndex) -> Self {
+        self.agent = Some((id, index));
+        self
+    }
+
+    /// Adds a force that changes the velocity to `desired`
+    fn steer_towards(mut self, desired: Vector, weight: f32) -> Self {
+        self.force += (desired - self.velocity) * weight;
+        self
+    }
+
+    /// Moves straight towards `target` at full speed
+    pub fn seek(self, target: Vector, weight: f32) -> Self {
+        let desired = with_length(target - self.position, self.max_speed);
+        self.steer_towards(desired, weight)
+    }
+
+    /// Moves straight away from `threat` at full speed
+    pub fn flee(self, threat: Vector, weight: f32) -> Self {
+        let desired = with_length(self.position - threat, self.max_speed);
+        self.steer_towards(desired, weight)
+    }
+
+    /// Moves towards `target` like `seek`, but slows down within `slowing_radius` to stop on it
+    pub fn arrive(self, target: Vector, slowing_radius: f32, weight: f32) -> Self {
+        let offset = target - self.position;
+        let distance = offset.length();
+        let speed = if distance < slowing_radius {
+            self.max_speed * distance / slowing_radius
+        } else {
+            self.max_speed
+        };
+        self.steer_towards(with_length(offset, speed), weight)
+    }
+
+    pub fn wander(self, wander: &Wander, weight: f32) -> Self {
+        let jitter = BufferedRng.gen_range(-1.0..=1.0) * wander.jitter;
+        let angle = wander.angle.fetch_add(jitter, Ordering::Relaxed) + jitter;
+        let forward = if self.velocity.square_length() > f32::EPSILON {
+            with_length(self.velocity, wander.distance)
+        } else {
+            Vector::new(wander.distance, 0.0)
+        };
+        let target = self.position
+            + forward
+            + Vector::new(angle.cos() * wander.radius, angle.sin() * wander.radius);
+        self.seek(target, weight)
+    }
+
+    /// Every other agent within `radius`
+    fn neighbors(&self, radius: f32) -> impl Iterator<Item = &Agent> + '_ {
+        self.agent.into_iter().flat_map(move |(id, index)| {
+            index
+                .query(self.position, radius)
+                .filter(move |agent| agent.id != id)
+        })
+    }
+
+    /// Moves away from agents within `radius`, more strongly from closer ones
+    pub fn separation(self, radius: f32, weight: f32) -> Self {
+        let mut away = Vector::new(0.0, 0.0);
+        for agent in self.neighbors(radius) {
+            let offset = self.position - agent.position;
+            let distance = offset.length();
+            if distance > f32::EPSILON {
+                away += offset * (1.0 / (distance * distance));
+            }
+        }
+        if away.square_length() <= f32::EPSILON {
+            return self;
+        }
+        let desired = with_length(away, self.max_speed);
+        self.steer_towards(desired, weight)
+    }
+
+    /// Moves in the same direction as agents within `radius`
+    pub fn alignment(self, radius: f32, weight: f32) -> Self {
+        let mut heading = Vector::new(0.0, 0.0);
+        for agent in self.neighbors(radius) {
+            heading += agent.velocity;
+        }
+        if heading.square_length() <= f32::EPSILON {
+            return self;
+        }
+        let desired = with_length(heading, self.max_speed);
+        self.steer_towards(desired, weight)
+    }
+
+    /// Moves towards the center of agents within `radius`
+    pub fn cohesion(self, radius: f32, weight: f32) -> Self {
+        let mut center = Vector::new(0.0, 0.0);
+        let mut count = 0;
+        for agent in self.neighbors(radius) {
+            center += agent.position;
+            count += 1;
+        }
+        if count == 0 {
+            return self;
+        }
+        self.seek(center * (1.0 / count as f32), weight)
+    }
+
+    /// Gets the velocity after `delta` seconds of steering
+    pub fn velocity(&self, delta: f32) -> Vector {
+        let force = truncate(self.force, self.max_force);
+        truncate(self.velocity + force * delta, self.max_speed)
+    }
+
+    /// Sets a velocity field to the velocity after `delta` seconds of steering
+    pub fn apply(self, velocity: &mut NumberFieldRef<Vector>, delta: f32) {
+        velocity.set(self.velocity(delta));
+    }
+}
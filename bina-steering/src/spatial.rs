@@ -0,0 +1,123 @@
+//! Finding agents that are close to a point
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use bina_ecs::{crossbeam::queue::SegQueue, singleton::Singleton, universe::Universe};
+use bina_graphics::{gizmos::UniverseGizmos, image::Rgba, polygon::Vector};
+use fxhash::FxHashMap;
+
+const CELL_COLOR: Rgba<u8> = Rgba([80, 80, 80, 255]);
+const VELOCITY_COLOR: Rgba<u8> = Rgba([0, 255, 0, 255]);
+
+/// Identifies an agent in a `SpatialIndex`, so that it can skip itself when looking for neighbors
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct AgentId(u64);
+
+/// Where an agent is and how it is moving
+#[derive(Clone, Copy)]
+pub struct Agent {
+    pub id: AgentId,
+    pub position: Vector,
+    pub velocity: Vector,
+}
+
+/// A grid of agents. Add it to the `Universe` as a singleton
+///
+/// Agents insert themselves every process frame, and the grid is rebuilt when
+/// this flushes. Queries see every agent as it was inserted during the last frame
+pub struct SpatialIndex {
+    cell_size: f32,
+    next_id: AtomicU64,
+    cells: FxHashMap<(i32, i32), Vec<Agent>>,
+    pending: SegQueue<Agent>,
+    debug_draw: AtomicBool,
+}
+
+impl SpatialIndex {
+    /// Queries are fastest when `cell_size` is close to the radius that is usually searched
+    pub fn new(cell_size: f32) -> Self {
+        Self {
+            cell_size,
+            next_id: AtomicU64::new(0),
+            cells: FxHashMap::default(),
+            pending: SegQueue::new(),
+            debug_draw: AtomicBool::new(false),
+        }
+    }
+
+    pub fn create_id(&self) -> AgentId {
+        AgentId(self.next_id.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Adds an agent to the grid for the next frame. Agents that are not inserted every frame disappear
+    pub fn insert(&self, agent: Agent) {
+        self.pending.push(agent);
+    }
+
+    /// Draws every agent with gizmos, along with the cells that have agents in them
+    ///
+    /// Each agent is drawn as an arrow from its position to where its velocity
+    /// would take it in one second
+    pub fn set_debug_draw(&self, enabled: bool) {
+        self.debug_draw.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn toggle_debug_draw(&self) {
+        self.debug_draw.fetch_xor(true, Ordering::Relaxed);
+    }
+
+    fn cell(&self, position: Vector) -> (i32, i32) {
+        (
+            (position.x / self.cell_size).floor() as i32,
+            (position.y / self.cell_size).floor() as i32,
+        )
+    }
+
+    /// Gets every agent within `radius` of `center`
+    pub fn query(&self, center: Vector, radius: f32) -> impl Iterator<Item = &Agent> + '_ {
+        let min = self.cell(center - Vector::new(radius, radius));
+        let max = self.cell(center + Vector::new(radius, radius));
+        (min.0..=max.0)
+            .flat_map(move |x| (min.1..=max.1).map(move |y| (x, y)))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .filter(move |agent| (agent.position - center).square_length() <= radius * radius)
+    }
+}
+
+impl Singleton for SpatialIndex {
+    fn process(&self, universe: &Universe) {
+        if !self.debug_draw.load(Ordering::Relaxed) {
+            return;
+        }
+        let gizmos = universe.gizmos();
+        let size = Vector::new(self.cell_size, self.cell_size);
+        for (&(x, y), agents) in &self.cells {
+            if agents.is_empty() {
+                continue;
+            }
+            let center = Vector::new(
+                (x as f32 + 0.5) * self.cell_size,
+                (y as f32 + 0.5) * self.cell_size,
+            );
+            gizmos.rect(center, size, CELL_COLOR);
+            for agent in agents {
+                gizmos.arrow(
+                    agent.position,
+                    agent.position + agent.velocity,
+                    VELOCITY_COLOR,
+                );
+            }
+        }
+    }
+
+    fn flush(&mut self, _universe: &Universe) {
+        // Emptied cells are kept, so that agents moving around do not reallocate them every frame
+        for agents in self.cells.values_mut() {
+            agents.clear();
+        }
+        while let Some(agent) = self.pending.pop() {
+            let cell = self.cell(agent.position);
+            self.cells.entry(cell).or_default().push(agent);
+        }
+    }
+}
@@ -0,0 +1,256 @@
+//! Reading SVG images and tessellating their fills and strokes
+//!
+//! Shared by bina-graphics and bina-macros, so that `load_polygon!` tessellates SVGs
+//! exactly the same way as `SvgAsset` does.
+//!
+//! usvg resolves CSS, `<use>`, `<defs>`, units, and inherited styles, and flattens the
+//! image into paths, which are then tessellated by lyon. Every shape has a single color,
+//! so gradients are drawn with the average color of their stops, and patterns and embedded
+//! images are rejected. Clip paths, masks, and filters are not applied, and text is not drawn
+use image::Rgba;
+use lyon::{
+    lyon_tessellation::{
+        BuffersBuilder, FillOptions, FillRule, FillTessellator, FillVertex, VertexBuffers,
+    },
+    math::point,
+    path::Path,
+};
+use usvg::{
+    tiny_skia_path::{self, PathSegment, PathStroker},
+    Group, Node, Paint, PaintOrder, Transform, Tree,
+};
+
+/// How far curves may stray from their true shape, in SVG units
+const TOLERANCE: f32 = 0.02;
+
+/// One fill or stroke of an SVG, already tessellated
+pub struct SvgShape {
+    pub geometry: VertexBuffers<[f32; 4], u32>,
+    pub color: Rgba<u8>,
+}
+
+/// Every fill and stroke of an SVG, along with the size of the image
+pub struct ParsedSvg {
+    /// In the order they should be drawn
+    pub shapes: Vec<SvgShape>,
+    pub width: f32,
+    pub height: f32,
+}
+
+/// Tessellates every fill and stroke of an SVG
+///
+/// The y axis is flipped so that the image is upright, with the top left
+/// corner of the image at the origin and one SVG unit per world unit
+pub fn parse_svg(bytes: &[u8]) -> Result<ParsedSvg, String> {
+    let tree = Tree::from_data(bytes, &usvg::Options::default()).map_err(|e| e.to_string())?;
+    let mut svg = ParsedSvg {
+        shapes: Vec::new(),
+        width: tree.size().width(),
+        height: tree.size().height(),
+    };
+    add_group(&mut svg.shapes, tree.root(), 1.0)?;
+    Ok(svg)
+}
+
+/// Adds the shapes of every child of a group, where `opacity` is that of the group and its parents
+fn add_group(shapes: &mut Vec<SvgShape>, group: &Group, opacity: f32) -> Result<(), String> {
+    let opacity = opacity * group.opacity().get();
+    for node in group.children() {
+        match node {
+            Node::Group(group) => add_group(shapes, group, opacity)?,
+            Node::Path(path) if path.is_visible() => {
+                // SVGs point the y axis down, while the world points it up
+                let transform = path.abs_transform().post_scale(1.0, -1.0);
+                let fill = path.fill().map(|fill| {
+                    let color = paint_color(fill.paint(), fill.opacity().get() * opacity)?;
+                    let rule = match fill.rule() {
+                        usvg::FillRule::NonZero => FillRule::NonZero,
+                        usvg::FillRule::EvenOdd => FillRule::EvenOdd,
+                    };
+                    add_fill(shapes, path.data(), transform, rule, color)
+                });
+                let stroke = path.stroke().map(|stroke| {
+                    let color = paint_color(stroke.paint(), stroke.opacity().get() * opacity)?;
+                    // Strokes are turned into the outline of the area they cover, which is then filled
+                    let stroke = stroke.to_tiny_skia();
+                    let scale = PathStroker::compute_resolution_scale(&transform);
+                    let dashed = match &stroke.dash {
+                        Some(dash) => path.data().dash(dash, scale),
+                        None => Some(path.data().clone()),
+                    };
+                    match dashed.and_then(|x| x.stroke(&stroke, scale)) {
+                        Some(outline) => {
+                            add_fill(shapes, &outline, transform, FillRule::NonZero, color)
+                        }
+                        None => Ok(()),
+                    }
+                });
+                let (first, second) = match path.paint_order() {
+                    PaintOrder::FillAndStroke => (fill, stroke),
+                    PaintOrder::StrokeAndFill => (stroke, fill),
+                };
+                first.transpose()?;
+                second.transpose()?;
+            }
+            Node::Path(_) => {}
+            Node::Image(_) => return Err("Embedded images are not supported".into()),
+            // Only produced by usvg's text feature, which is not enabled
+            Node::Text(_) => {}
+        }
+    }
+    Ok(())
+}
+
+/// Gets the single color that a paint is drawn with, or `None` if it is fully transparent
+fn paint_color(paint: &Paint, opacity: f32) -> Result<Option<Rgba<u8>>, String> {
+    let (r, g, b, a) = match paint {
+        Paint::Color(color) => (
+            color.red as f32,
+            color.green as f32,
+            color.blue as f32,
+            opacity,
+        ),
+        Paint::LinearGradient(gradient) => average_stops(gradient.stops(), opacity),
+        Paint::RadialGradient(gradient) => average_stops(gradient.stops(), opacity),
+        Paint::Pattern(_) => return Err("Patterns are not supported".into()),
+    };
+    let a = (a * 255.0).round() as u8;
+    Ok((a > 0).then(|| Rgba([r.round() as u8, g.round() as u8, b.round() as u8, a])))
+}
+
+/// Averages the colors of a gradient, weighing each by its opacity
+fn average_stops(stops: &[usvg::Stop], opacity: f32) -> (f32, f32, f32, f32) {
+    let mut sum = (0.0, 0.0, 0.0, 0.0);
+    for stop in stops {
+        let a = stop.opacity().get();
+        let color = stop.color();
+        sum.0 += color.red as f32 * a;
+        sum.1 += color.green as f32 * a;
+        sum.2 += color.blue as f32 * a;
+        sum.3 += a;
+    }
+    if sum.3 == 0.0 {
+        return (0.0, 0.0, 0.0, 0.0);
+    }
+    let count = stops.len() as f32;
+    (
+        sum.0 / sum.3,
+        sum.1 / sum.3,
+        sum.2 / sum.3,
+        sum.3 / count * opacity,
+    )
+}
+
+fn add_fill(
+    shapes: &mut Vec<SvgShape>,
+    path: &tiny_skia_path::Path,
+    transform: Transform,
+    rule: FillRule,
+    color: Option<Rgba<u8>>,
+) -> Result<(), String> {
+    let Some(color) = color else {
+        return Ok(());
+    };
+    let Some(path) = path.clone().transform(transform) else {
+        return Ok(());
+    };
+    let mut geometry = VertexBuffers::new();
+    FillTessellator::new()
+        .tessellate_path(
+            &to_lyon(&path),
+            &FillOptions::tolerance(TOLERANCE).with_fill_rule(rule),
+            &mut BuffersBuilder::new(&mut geometry, |vertex: FillVertex| {
+                let p = vertex.position();
+                [p.x, p.y, 0.0, 0.0]
+            }),
+        )
+        .map_err(|e| format!("Failed to fill path: {e:?}"))?;
+    shapes.push(SvgShape { geometry, color });
+    Ok(())
+}
+
+fn to_lyon(path: &tiny_skia_path::Path) -> Path {
+    let mut builder = Path::builder();
+    let mut open = false;
+    for segment in path.segments() {
+        match segment {
+            PathSegment::MoveTo(p) => {
+                if open {
+                    builder.end(false);
+                }
+                builder.begin(point(p.x, p.y));
+                open = true;
+            }
+            PathSegment::LineTo(p) => {
+                builder.line_to(point(p.x, p.y));
+            }
+            PathSegment::QuadTo(c, p) => {
+                builder.quadratic_bezier_to(point(c.x, c.y), point(p.x, p.y));
+            }
+            PathSegment::CubicTo(c1, c2, p) => {
+                builder.cubic_bezier_to(point(c1.x, c1.y), point(c2.x, c2.y), point(p.x, p.y));
+            }
+            PathSegment::Close => {
+                builder.end(true);
+                open = false;
+            }
+        }
+    }
+    if open {
+        builder.end(false);
+    }
+    builder.build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_css_and_use() {
+        let svg = parse_svg(
+            br##"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" width="20" height="10">
+                <style>.red { fill: #ff0000 }</style>
+                <defs><rect id="square" width="10" height="10"/></defs>
+                <use xlink:href="#square" class="red"/>
+                <use xlink:href="#square" x="10" style="fill: #0000ff"/>
+            </svg>"##,
+        )
+        .unwrap();
+        assert_eq!((svg.width, svg.height), (20.0, 10.0));
+        let colors: Vec<_> = svg.shapes.iter().map(|x| x.color).collect();
+        assert_eq!(colors, [Rgba([255, 0, 0, 255]), Rgba([0, 0, 255, 255])]);
+        // The second square is to the right of the first, and the image is flipped upright
+        let xs = |shape: &SvgShape| shape.geometry.vertices.iter().map(|v| v[0]).sum::<f32>();
+        assert!(xs(&svg.shapes[1]) > xs(&svg.shapes[0]));
+        assert!(svg.shapes[0].geometry.vertices.iter().all(|v| v[1] <= 0.0));
+    }
+
+    #[test]
+    fn fills_gradients_with_their_average() {
+        let svg = parse_svg(
+            br##"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10">
+                <linearGradient id="fade">
+                    <stop offset="0" stop-color="#ff0000"/>
+                    <stop offset="1" stop-color="#0000ff"/>
+                </linearGradient>
+                <rect width="10" height="10" fill="url(#fade)" stroke="#00ff00"/>
+            </svg>"##,
+        )
+        .unwrap();
+        let colors: Vec<_> = svg.shapes.iter().map(|x| x.color).collect();
+        assert_eq!(colors, [Rgba([128, 0, 128, 255]), Rgba([0, 255, 0, 255])]);
+    }
+
+    #[test]
+    fn rejects_what_cannot_be_drawn() {
+        assert!(parse_svg(b"not an svg").is_err());
+        assert!(parse_svg(
+            br##"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10">
+                <pattern id="dots" width="2" height="2"><circle r="1"/></pattern>
+                <rect width="10" height="10" fill="url(#dots)"/>
+            </svg>"##,
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,607 @@
+//! Resolving the rectangle of every node from its style
+use bina_ecs::triomphe::Arc;
+use bina_graphics::polygon::Vector;
+use fxhash::FxHashMap;
+
+use crate::{
+    navigation::Neighbors,
+    node::{NodeState, UiNodeId},
+    style::{Align, Direction, Justify, Style},
+};
+
+/// A rectangle in UI coordinates, where the origin is the top left corner of the window and y points down
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Rect {
+    pub const fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn contains(&self, position: Vector) -> bool {
+        position.x >= self.x
+            && position.y >= self.y
+            && position.x < self.x + self.width
+            && position.y < self.y + self.height
+    }
+
+    pub fn center(&self) -> Vector {
+        Vector::new(self.x + self.width * 0.5, self.y + self.height * 0.5)
+    }
+}
+
+struct TreeNode {
+    parent: Option<UiNodeId>,
+    children: Vec<UiNodeId>,
+    style: Style,
+    neighbors: Neighbors,
+    state: Arc<NodeState>,
+}
+
+/// Every node, and the results of the last layout
+#[derive(Default)]
+pub(crate) struct UiTree {
+    nodes: FxHashMap<UiNodeId, TreeNode>,
+    roots: Vec<UiNodeId>,
+    /// Set whenever a node is added, removed or restyled
+    dirty: bool,
+    /// The size of the window during the last layout
+    size: [f32; 2],
+    /// The visible, interactive nodes, from the bottom most to the top most
+    hit_boxes: Vec<(UiNodeId, Rect)>,
+}
+
+impl UiTree {
+    /// Adds a node as the last child of `parent`, so that it is drawn above its siblings.
+    /// If the parent no longer exists, the node is never laid out
+    pub(crate) fn insert(
+        &mut self,
+        id: UiNodeId,
+        parent: Option<UiNodeId>,
+        style: Style,
+        state: Arc<NodeState>,
+    ) {
+        match parent {
+            Some(parent) => {
+                if let Some(parent) = self.nodes.get_mut(&parent) {
+                    parent.children.push(id);
+                }
+            }
+            None => self.roots.push(id),
+        }
+        self.nodes.insert(
+            id,
+            TreeNode {
+                parent,
+                children: Vec::new(),
+                style,
+                neighbors: Neighbors::NEAREST,
+                state,
+            },
+        );
+        self.dirty = true;
+    }
+
+    /// Removes a node. Its children stay hidden until they are removed as well
+    pub(crate) fn remove(&mut self, id: UiNodeId) {
+        let Some(node) = self.nodes.remove(&id) else {
+            return;
+        };
+        match node.parent {
+            Some(parent) => {
+                if let Some(parent) = self.nodes.get_mut(&parent) {
+                    parent.children.retain(|x| *x != id);
+                }
+            }
+            None => self.roots.retain(|x| *x != id),
+        }
+        for child in node.children {
+            if let Some(child) = self.nodes.get_mut(&child) {
+                child.parent = None;
+            }
+            self.hide(child);
+        }
+        self.dirty = true;
+    }
+
+    pub(crate) fn style(&self, id: UiNodeId) -> Option<Style> {
+        self.nodes.get(&id).map(|x| x.style)
+    }
+
+    pub(crate) fn set_style(&mut self, id: UiNodeId, style: Style) {
+        if let Some(node) = self.nodes.get_mut(&id) {
+            node.style = style;
+            self.dirty = true;
+        }
+    }
+
+    pub(crate) fn neighbors(&self, id: UiNodeId) -> Option<Neighbors> {
+        self.nodes.get(&id).map(|x| x.neighbors)
+    }
+
+    pub(crate) fn set_neighbors(&mut self, id: UiNodeId, neighbors: Neighbors) {
+        if let Some(node) = self.nodes.get_mut(&id) {
+            node.neighbors = neighbors;
+        }
+    }
+
+    pub(crate) fn state(&self, id: UiNodeId) -> Arc<NodeState> {
+        self.nodes[&id].state.clone()
+    }
+
+    /// Gets the top most interactive node under the given position
+    pub(crate) fn hit_test(&self, position: Vector) -> Option<UiNodeId> {
+        self.hit_boxes
+            .iter()
+            .rev()
+            .find(|(_, rect)| rect.contains(position))
+            .map(|(id, _)| *id)
+    }
+
+    /// Gets every node that can be hovered, clicked and focused, in the order they are drawn
+    pub(crate) fn interactive_nodes(&self) -> impl DoubleEndedIterator<Item = UiNodeId> + '_ {
+        self.hit_boxes.iter().map(|(id, _)| *id)
+    }
+
+    /// Gets every interactive node and where it was placed, in the order they are drawn
+    pub(crate) fn hit_boxes(&self) -> &[(UiNodeId, Rect)] {
+        &self.hit_boxes
+    }
+
+    fn hide(&self, id: UiNodeId) {
+        if let Some(node) = self.nodes.get(&id) {
+            node.state.hide();
+            for child in &node.children {
+                self.hide(*child);
+            }
+        }
+    }
+
+    /// Lays out every node against a window of the given size, if anything has changed since the last layout
+    pub(crate) fn layout(&mut self, size: Vector) {
+        let size = [size.x, size.y];
+        if !self.dirty && self.size == size {
+            return;
+        }
+        self.dirty = false;
+        self.size = size;
+        self.hit_boxes.clear();
+
+        let mut order = 0;
+        let roots = std::mem::take(&mut self.roots);
+        let window = Rect::new(0.0, 0.0, size[0], size[1]);
+        for &root in &roots {
+            self.place_anchored(root, window, &mut order);
+        }
+        self.roots = roots;
+    }
+
+    /// Gets the size a node would like to be inside of a parent whose inside is `available`
+    fn measure(&self, id: UiNodeId, available: [f32; 2]) -> [f32; 2] {
+        let style = &self.nodes[&id].style;
+        let width = style.width.resolve(available[0]);
+        let height = style.height.resolve(available[1]);
+        if let (Some(width), Some(height)) = (width, height) {
+            return [width, height];
+        }
+
+        let inner = [
+            width.unwrap_or(available[0]) - style.padding.horizontal(),
+            height.unwrap_or(available[1]) - style.padding.vertical(),
+        ];
+        let mut content = [0.0f32; 2];
+        let mut count = 0usize;
+        for child in self.visible_children(id) {
+            let child_size = self.measure(child, inner);
+            count += 1;
+            match style.flex {
+                Some(flex) => {
+                    let main = main_axis(flex.direction);
+                    content[main] += child_size[main];
+                    content[1 - main] = content[1 - main].max(child_size[1 - main]);
+                }
+                None => {
+                    content[0] = content[0].max(child_size[0]);
+                    content[1] = content[1].max(child_size[1]);
+                }
+            }
+        }
+        if let Some(flex) = style.flex {
+            content[main_axis(flex.direction)] += flex.gap * count.saturating_sub(1) as f32;
+        }
+        [
+            width.unwrap_or(content[0] + style.padding.horizontal()),
+            height.unwrap_or(content[1] + style.padding.vertical()),
+        ]
+    }
+
+    fn visible_children(&self, id: UiNodeId) -> impl Iterator<Item = UiNodeId> + '_ {
+        self.nodes[&id]
+            .children
+            .iter()
+            .copied()
+            .filter(|x| self.nodes[x].style.visible)
+    }
+
+    /// Places a node inside of `parent` using its anchor
+    fn place_anchored(&mut self, id: UiNodeId, parent: Rect, order: &mut u32) {
+        let style = self.nodes[&id].style;
+        if !style.visible {
+            self.hide(id);
+            return;
+        }
+        let [width, height] = self.measure(id, [parent.width, parent.height]);
+        let rect = Rect::new(
+            parent.x + (parent.width - width) * style.anchor.x + style.offset.x,
+            parent.y + (parent.height - height) * style.anchor.y + style.offset.y,
+            width,
+            height,
+        );
+        self.place(id, rect, order);
+    }
+
+    /// Gives a node its final rectangle, then places its children inside of it
+    fn place(&mut self, id: UiNodeId, rect: Rect, order: &mut u32) {
+        let node = &self.nodes[&id];
+        let style = node.style;
+        node.state.set(rect, *order);
+        *order += 1;
+        if style.interactive {
+            self.hit_boxes.push((id, rect));
+        }
+
+        let inner = Rect::new(
+            rect.x + style.padding.left,
+            rect.y + style.padding.top,
+            (rect.width - style.padding.horizontal()).max(0.0),
+            (rect.height - style.padding.vertical()).max(0.0),
+        );
+        let children = node.children.clone();
+        let Some(flex) = style.flex else {
+            for child in children {
+                self.place_anchored(child, inner, order);
+            }
+            return;
+        };
+
+        let main = main_axis(flex.direction);
+        let cross = 1 - main;
+        let inner_origin = [inner.x, inner.y];
+        let inner_size = [inner.width, inner.height];
+
+        let mut placed = Vec::with_capacity(children.len());
+        for child in children {
+            if self.nodes[&child].style.visible {
+                let size = self.measure(child, inner_size);
+                placed.push((child, size));
+            } else {
+                self.hide(child);
+            }
+        }
+        if placed.is_empty() {
+            return;
+        }
+
+        let gaps = flex.gap * (placed.len() - 1) as f32;
+        let used: f32 = placed.iter().map(|(_, size)| size[main]).sum();
+        let free = inner_size[main] - used - gaps;
+        let total_grow: f32 = placed.iter().map(|(x, _)| self.nodes[x].style.grow).sum();
+
+        let mut position = 0.0;
+        let mut spacing = flex.gap;
+        if free > 0.0 && total_grow > 0.0 {
+            for (child, size) in &mut placed {
+                size[main] += free * self.nodes[child].style.grow / total_grow;
+            }
+        } else if free > 0.0 {
+            match flex.justify {
+                Justify::Start => {}
+                Justify::Center => position = free * 0.5,
+                Justify::End => position = free,
+                Justify::SpaceBetween if placed.len() > 1 => {
+                    spacing += free / (placed.len() - 1) as f32
+                }
+                Justify::SpaceBetween => {}
+            }
+        }
+
+        for (child, mut size) in placed {
+            let child_style = self.nodes[&child].style;
+            let auto_cross = match flex.direction {
+                Direction::Row => child_style.height.resolve(0.0).is_none(),
+                Direction::Column => child_style.width.resolve(0.0).is_none(),
+            };
+            if flex.align == Align::Stretch && auto_cross {
+                size[cross] = inner_size[cross];
+            }
+            let cross_position = match flex.align {
+                Align::Start | Align::Stretch => 0.0,
+                Align::Center => (inner_size[cross] - size[cross]) * 0.5,
+                Align::End => inner_size[cross] - size[cross],
+            };
+            let mut origin = [0.0; 2];
+            origin[main] = inner_origin[main] + position;
+            origin[cross] = inner_origin[cross] + cross_position;
+            let rect = Rect::new(
+                origin[0] + child_style.offset.x,
+                origin[1] + child_style.offset.y,
+                size[0],
+                size[1],
+            );
+            self.place(child, rect, order);
+            position += size[main] + spacing;
+        }
+    }
+}
+
+fn main_axis(direction: Direction) -> usize {
+    match direction {
+        Direction::Row => 0,
+        Direction::Column => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        node::NodeLayout,
+        style::{Anchor, Edges, Flex, Length},
+    };
+
+    const WINDOW: Vector = Vector::new(800.0, 600.0);
+
+    #[derive(Default)]
+    struct Tree {
+        tree: UiTree,
+        next_id: u64,
+    }
+
+    impl Tree {
+        fn add(&mut self, parent: Option<UiNodeId>, style: Style) -> (UiNodeId, NodeLayout) {
+            let id = UiNodeId(self.next_id);
+            self.next_id += 1;
+            let state = Arc::new(NodeState::new());
+            self.tree.insert(id, parent, style, state.clone());
+            (id, NodeLayout(state))
+        }
+    }
+
+    fn sized(width: f32, height: f32) -> Style {
+        Style {
+            width: Length::Units(width),
+            height: Length::Units(height),
+            ..Style::DEFAULT
+        }
+    }
+
+    #[test]
+    fn anchors() {
+        let mut tree = Tree::default();
+        let (_, top_left) = tree.add(None, sized(100.0, 50.0));
+        let (_, center) = tree.add(
+            None,
+            Style {
+                anchor: Anchor::CENTER,
+                ..sized(100.0, 50.0)
+            },
+        );
+        let (_, bottom_right) = tree.add(
+            None,
+            Style {
+                anchor: Anchor::BOTTOM_RIGHT,
+                offset: Vector::new(-10.0, -20.0),
+                ..sized(100.0, 50.0)
+            },
+        );
+        tree.tree.layout(WINDOW);
+        assert_eq!(top_left.rect(), Rect::new(0.0, 0.0, 100.0, 50.0));
+        assert_eq!(center.rect(), Rect::new(350.0, 275.0, 100.0, 50.0));
+        assert_eq!(bottom_right.rect(), Rect::new(690.0, 530.0, 100.0, 50.0));
+        assert!(top_left.z() < center.z() && center.z() < bottom_right.z());
+    }
+
+    #[test]
+    fn percent_lengths_are_inside_the_padding() {
+        let mut tree = Tree::default();
+        let (panel, _) = tree.add(
+            None,
+            Style {
+                padding: Edges::all(50.0),
+                ..sized(400.0, 300.0)
+            },
+        );
+        let (_, child) = tree.add(
+            Some(panel),
+            Style {
+                width: Length::Percent(50.0),
+                height: Length::Percent(100.0),
+                ..Style::DEFAULT
+            },
+        );
+        tree.tree.layout(WINDOW);
+        assert_eq!(child.rect(), Rect::new(50.0, 50.0, 150.0, 200.0));
+    }
+
+    #[test]
+    fn auto_sizes_fit_the_children() {
+        let mut tree = Tree::default();
+        let (row, row_layout) = tree.add(
+            None,
+            Style {
+                padding: Edges::symmetric(5.0, 10.0),
+                flex: Some(Flex {
+                    gap: 4.0,
+                    ..Flex::ROW
+                }),
+                ..Style::DEFAULT
+            },
+        );
+        tree.add(Some(row), sized(20.0, 30.0));
+        tree.add(Some(row), sized(40.0, 10.0));
+        tree.tree.layout(WINDOW);
+        assert_eq!(row_layout.rect(), Rect::new(0.0, 0.0, 74.0, 50.0));
+    }
+
+    #[test]
+    fn grow_and_gap() {
+        let mut tree = Tree::default();
+        let (row, _) = tree.add(
+            None,
+            Style {
+                flex: Some(Flex {
+                    gap: 10.0,
+                    ..Flex::ROW
+                }),
+                ..sized(230.0, 40.0)
+            },
+        );
+        let (_, fixed) = tree.add(Some(row), sized(30.0, 20.0));
+        let (_, one) = tree.add(
+            Some(row),
+            Style {
+                grow: 1.0,
+                ..sized(0.0, 20.0)
+            },
+        );
+        let (_, three) = tree.add(
+            Some(row),
+            Style {
+                grow: 3.0,
+                ..sized(0.0, 20.0)
+            },
+        );
+        tree.tree.layout(WINDOW);
+        assert_eq!(fixed.rect(), Rect::new(0.0, 0.0, 30.0, 20.0));
+        assert_eq!(one.rect(), Rect::new(40.0, 0.0, 45.0, 20.0));
+        assert_eq!(three.rect(), Rect::new(95.0, 0.0, 135.0, 20.0));
+    }
+
+    #[test]
+    fn justify() {
+        for (justify, xs) in [
+            (Justify::Start, [0.0, 20.0, 40.0]),
+            (Justify::Center, [20.0, 40.0, 60.0]),
+            (Justify::End, [40.0, 60.0, 80.0]),
+            (Justify::SpaceBetween, [0.0, 40.0, 80.0]),
+        ] {
+            let mut tree = Tree::default();
+            let (row, _) = tree.add(
+                None,
+                Style {
+                    flex: Some(Flex {
+                        justify,
+                        ..Flex::ROW
+                    }),
+                    ..sized(100.0, 20.0)
+                },
+            );
+            let children: Vec<_> = (0..3)
+                .map(|_| tree.add(Some(row), sized(20.0, 20.0)).1)
+                .collect();
+            tree.tree.layout(WINDOW);
+            let placed: Vec<_> = children.iter().map(|x| x.rect().x).collect();
+            assert_eq!(placed, xs, "{justify:?}");
+        }
+    }
+
+    #[test]
+    fn align() {
+        for (align, y, height) in [
+            (Align::Start, 0.0, 20.0),
+            (Align::Center, 40.0, 20.0),
+            (Align::End, 80.0, 20.0),
+            (Align::Stretch, 0.0, 100.0),
+        ] {
+            let mut tree = Tree::default();
+            let (row, _) = tree.add(
+                None,
+                Style {
+                    flex: Some(Flex { align, ..Flex::ROW }),
+                    ..sized(100.0, 100.0)
+                },
+            );
+            let (_, auto_height) = tree.add(
+                Some(row),
+                Style {
+                    width: Length::Units(20.0),
+                    height: Length::Auto,
+                    padding: Edges::symmetric(0.0, 10.0),
+                    ..Style::DEFAULT
+                },
+            );
+            let (_, fixed_height) = tree.add(Some(row), sized(20.0, 20.0));
+            tree.tree.layout(WINDOW);
+            assert_eq!(
+                auto_height.rect(),
+                Rect::new(0.0, y, 20.0, height),
+                "{align:?}"
+            );
+            // Stretching only changes lengths that are `Auto`
+            assert_eq!(fixed_height.rect().height, 20.0, "{align:?}");
+        }
+    }
+
+    #[test]
+    fn hidden_nodes_take_no_space() {
+        let mut tree = Tree::default();
+        let (column, _) = tree.add(
+            None,
+            Style {
+                flex: Some(Flex::COLUMN),
+                ..sized(100.0, 100.0)
+            },
+        );
+        let (_, hidden) = tree.add(
+            Some(column),
+            Style {
+                visible: false,
+                ..sized(20.0, 20.0)
+            },
+        );
+        let (_, shown) = tree.add(Some(column), sized(20.0, 20.0));
+        tree.tree.layout(WINDOW);
+        assert!(!hidden.is_visible());
+        assert!(shown.is_visible());
+        assert_eq!(shown.rect().y, 0.0);
+    }
+
+    #[test]
+    fn contains_includes_the_top_left_edges_only() {
+        let rect = Rect::new(10.0, 20.0, 30.0, 40.0);
+        assert!(rect.contains(Vector::new(10.0, 20.0)));
+        assert!(rect.contains(Vector::new(39.9, 59.9)));
+        assert!(!rect.contains(Vector::new(40.0, 30.0)));
+        assert!(!rect.contains(Vector::new(20.0, 60.0)));
+        assert!(!rect.contains(Vector::new(9.9, 30.0)));
+        assert!(!rect.contains(Vector::new(20.0, 19.9)));
+        assert!(!Rect::new(0.0, 0.0, 0.0, 0.0).contains(Vector::new(0.0, 0.0)));
+    }
+
+    #[test]
+    fn hit_test_finds_the_top_most_interactive_node() {
+        let mut tree = Tree::default();
+        let interactive = |style: Style| Style {
+            interactive: true,
+            ..style
+        };
+        let (panel, _) = tree.add(None, interactive(sized(200.0, 200.0)));
+        let (button, _) = tree.add(Some(panel), interactive(sized(50.0, 50.0)));
+        // Not interactive, so the pointer passes through it to the button
+        tree.add(Some(panel), sized(100.0, 100.0));
+        tree.tree.layout(WINDOW);
+        assert_eq!(tree.tree.hit_test(Vector::new(10.0, 10.0)), Some(button));
+        assert_eq!(tree.tree.hit_test(Vector::new(150.0, 150.0)), Some(panel));
+        assert_eq!(tree.tree.hit_test(Vector::new(250.0, 10.0)), None);
+    }
+}
@@ -0,0 +1,333 @@
+//! A retained mode UI that is laid out against the window
+//!
+//! The UI is a tree of `UiNode` components. Each node is anchored inside of its parent,
+//! or placed one after another if its parent is a flex node. The tree is laid out by the
+//! `Ui` singleton whenever it changes or the window is resized, and backgrounds are drawn
+//! in screen space through the UI camera of `Graphics`.
+//!
+//! Lengths are in UI units, which are logical pixels multiplied by `Graphics::ui_scale`, so the UI
+//! keeps its size when the window moves to a monitor with a different scale factor.
+//!
+//! Keyboard focus moves with Tab, or between neighboring nodes with `UiAction`s, which come from
+//! the arrow keys or can be sent by a gamepad.
+//!
+//! Only nodes with a background are drawn by this crate. Anything else drawn inside of a
+//! node, such as text or a nine-slice border, follows it by reading its `NodeLayout`
+//!
+//! ```ignore
+//! universe.queue_set_singleton(Ui::new());
+//!
+//! // Later, from a component
+//! let ui = universe.get_singleton::<Ui>();
+//! let bar = ui
+//!     .create_root(Style {
+//!         anchor: Anchor::BOTTOM,
+//!         width: Length::Percent(100.0),
+//!         height: Length::Units(48.0),
+//!         flex: Some(Flex { gap: 8.0, ..Flex::ROW }),
+//!         ..Style::DEFAULT
+//!     })
+//!     .with_color(graphics, Rgba([20, 20, 20, 255]));
+//! let button = ui
+//!     .create_child(bar.id(), Style { grow: 1.0, interactive: true, ..Style::DEFAULT })
+//!     .with_color(graphics, Rgba([80, 80, 80, 255]));
+//! universe.queue_add_entity((bar,));
+//! universe.queue_add_entity((button,));
+//! ```
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use bina_ecs::{parking_lot::Mutex, singleton::Singleton, triomphe::Arc, universe::Universe};
+use bina_graphics::{
+    polygon::Vector,
+    winit::event::{MouseButton, VirtualKeyCode},
+    Graphics, KeyInput, PointerButton, PointerMoved,
+};
+use layout::UiTree;
+use navigation::UiAction;
+use node::{NodeLayout, NodeState, UiNode, UiNodeId};
+use style::Style;
+
+pub mod layout;
+pub mod navigation;
+pub mod node;
+pub mod style;
+pub mod widgets;
+
+/// Sent when a button is pressed and released over the same interactive node
+#[derive(Clone, Copy, Debug)]
+pub struct Clicked {
+    pub node: UiNodeId,
+    pub button: MouseButton,
+}
+
+/// Sent when the pointer moves onto or off of an interactive node
+#[derive(Clone, Copy, Debug)]
+pub struct HoverChanged {
+    pub node: UiNodeId,
+    pub hovered: bool,
+}
+
+/// Sent when a node gains or loses keyboard focus
+#[derive(Clone, Copy, Debug)]
+pub struct FocusChanged {
+    pub node: UiNodeId,
+    pub focused: bool,
+}
+
+#[derive(Default)]
+struct PointerState {
+    /// The last known position of the pointer, in UI coordinates
+    position: Option<Vector>,
+    hovered: Option<(UiNodeId, Arc<NodeState>)>,
+    pressed: Option<(UiNodeId, MouseButton, Arc<NodeState>)>,
+}
+
+/// Lays out every `UiNode` and sends pointer events to them. Add it to the `Universe` as a singleton
+///
+/// Layout needs the size of the window, so nothing is laid out unless `Graphics` has been added too
+pub struct Ui {
+    tree: Arc<Mutex<UiTree>>,
+    next_id: AtomicU64,
+    pointer: Mutex<PointerState>,
+    focus: Mutex<FocusState>,
+    /// Whether navigating past the last node in a direction moves focus to the first
+    wrap_navigation: AtomicBool,
+}
+
+#[derive(Default)]
+struct FocusState {
+    focused: Option<(UiNodeId, Arc<NodeState>)>,
+    /// Set by `Ui::queue_focus`
+    pending: Option<Option<UiNodeId>>,
+}
+
+impl FocusState {
+    fn set(&mut self, node: Option<UiNodeId>, tree: &UiTree, universe: &Universe) {
+        if self.focused.as_ref().map(|(id, _)| *id) == node {
+            return;
+        }
+        if let Some((node, state)) = self.focused.take() {
+            state.set_focused(false);
+            universe.send_event(FocusChanged {
+                node,
+                focused: false,
+            });
+        }
+        if let Some(node) = node {
+            let state = tree.state(node);
+            state.set_focused(true);
+            universe.send_event(FocusChanged {
+                node,
+                focused: true,
+            });
+            self.focused = Some((node, state));
+        }
+    }
+
+    /// Moves focus to the next interactive node, or the previous one if `backwards`, wrapping around at the ends
+    fn cycle(&mut self, backwards: bool, tree: &UiTree, universe: &Universe) {
+        let order: Vec<_> = tree.interactive_nodes().collect();
+        if order.is_empty() {
+            return;
+        }
+        let current = self
+            .focused
+            .as_ref()
+            .and_then(|(id, _)| order.iter().position(|x| x == id));
+        let next = match (current, backwards) {
+            (None, false) => 0,
+            (None, true) => order.len() - 1,
+            (Some(i), false) => (i + 1) % order.len(),
+            (Some(i), true) => (i + order.len() - 1) % order.len(),
+        };
+        self.set(Some(order[next]), tree, universe);
+    }
+}
+
+impl Ui {
+    pub fn new() -> Self {
+        Self {
+            tree: Arc::new(Mutex::new(UiTree::default())),
+            next_id: AtomicU64::new(0),
+            pointer: Mutex::new(PointerState::default()),
+            focus: Mutex::new(FocusState::default()),
+            wrap_navigation: AtomicBool::new(true),
+        }
+    }
+
+    /// Sets whether navigating past the last node in a direction moves focus around to the other side,
+    /// which is true by default
+    pub fn with_wrap_navigation(self, wrap: bool) -> Self {
+        self.set_wrap_navigation(wrap);
+        self
+    }
+
+    pub fn set_wrap_navigation(&self, wrap: bool) {
+        self.wrap_navigation.store(wrap, Ordering::Relaxed);
+    }
+
+    fn create_node(&self, parent: Option<UiNodeId>, style: Style) -> UiNode {
+        let id = UiNodeId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let state = Arc::new(NodeState::new());
+        self.tree.lock().insert(id, parent, style, state.clone());
+        UiNode::new(id, NodeLayout(state), self.tree.clone())
+    }
+
+    /// Creates a node that is placed inside of the window. Later roots are drawn above earlier ones
+    pub fn create_root(&self, style: Style) -> UiNode {
+        self.create_node(None, style)
+    }
+
+    /// Creates a node inside of another. Later children are drawn above earlier ones
+    ///
+    /// If the parent has already been removed, the node is never shown
+    pub fn create_child(&self, parent: UiNodeId, style: Style) -> UiNode {
+        self.create_node(Some(parent), style)
+    }
+
+    /// Gets the interactive node under the pointer, if any
+    pub fn hovered(&self) -> Option<UiNodeId> {
+        self.pointer.lock().hovered.as_ref().map(|(id, _)| *id)
+    }
+
+    /// Gets the position of the pointer in UI coordinates, if it has ever been over the window
+    pub fn pointer_position(&self) -> Option<Vector> {
+        self.pointer.lock().position
+    }
+
+    /// Gets the node that receives keyboard input, if any
+    pub fn focused(&self) -> Option<UiNodeId> {
+        self.focus.lock().focused.as_ref().map(|(id, _)| *id)
+    }
+
+    /// Gives keyboard focus to a node after the next layout, or takes it away from every node if `None`
+    ///
+    /// Only visible, interactive nodes can be focused
+    pub fn queue_focus(&self, node: Option<UiNodeId>) {
+        self.focus.lock().pending = Some(node);
+    }
+
+    /// Whether the pointer is over any interactive node, which is useful to keep
+    /// clicks on the UI from also being handled by the game
+    pub fn is_pointer_captured(&self) -> bool {
+        self.pointer.lock().hovered.is_some()
+    }
+}
+
+impl Default for Ui {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PointerState {
+    fn hover(&mut self, tree: &UiTree, universe: &Universe) {
+        let hit = self.position.and_then(|x| tree.hit_test(x));
+        if self.hovered.as_ref().map(|(id, _)| *id) == hit {
+            return;
+        }
+        if let Some((node, state)) = self.hovered.take() {
+            state.set_hovered(false);
+            universe.send_event(HoverChanged {
+                node,
+                hovered: false,
+            });
+        }
+        if let Some(node) = hit {
+            let state = tree.state(node);
+            state.set_hovered(true);
+            universe.send_event(HoverChanged {
+                node,
+                hovered: true,
+            });
+            self.hovered = Some((node, state));
+        }
+    }
+
+    /// Returns the node that was pressed on, if a button was pressed
+    fn button(
+        &mut self,
+        event: &PointerButton,
+        position: Vector,
+        tree: &UiTree,
+        universe: &Universe,
+    ) -> Option<Option<UiNodeId>> {
+        self.position = Some(position);
+        self.hover(tree, universe);
+        let hit = tree.hit_test(position);
+        if event.pressed {
+            if self.pressed.is_none() {
+                if let Some(node) = hit {
+                    let state = tree.state(node);
+                    state.set_pressed(true);
+                    self.pressed = Some((node, event.button, state));
+                }
+            }
+            return Some(hit);
+        }
+        let (node, button, state) = self.pressed.take()?;
+        if button != event.button {
+            self.pressed = Some((node, button, state));
+            return None;
+        }
+        state.set_pressed(false);
+        if hit == Some(node) {
+            universe.send_event(Clicked { node, button });
+        }
+        None
+    }
+}
+
+impl Singleton for Ui {
+    fn process(&self, universe: &Universe) {
+        let Some(graphics) = universe.try_get_singleton::<Graphics>() else {
+            return;
+        };
+        let mut tree = self.tree.lock();
+        tree.layout(graphics.ui_size());
+
+        let mut focus = self.focus.lock();
+        // Nodes that were removed, hidden or made non-interactive lose focus
+        let lost_focus = focus
+            .focused
+            .as_ref()
+            .is_some_and(|(node, _)| !tree.interactive_nodes().any(|x| x == *node));
+        if lost_focus {
+            focus.set(None, &tree, universe);
+        }
+        if let Some(node) = focus.pending.take() {
+            let node = node.filter(|node| tree.interactive_nodes().any(|x| x == *node));
+            focus.set(node, &tree, universe);
+        }
+
+        let mut pointer = self.pointer.lock();
+        for event in universe.read_events::<PointerButton>() {
+            let position = graphics.screen_to_ui(event.position);
+            // Pressing outside of every interactive node takes focus away
+            if let Some(node) = pointer.button(event, position, &tree, universe) {
+                focus.set(node, &tree, universe);
+            }
+        }
+        for event in universe.read_events::<KeyInput>() {
+            if event.pressed && event.key == Some(VirtualKeyCode::Tab) {
+                focus.cycle(event.modifiers.shift(), &tree, universe);
+            }
+        }
+        let wrap = self.wrap_navigation.load(Ordering::Relaxed);
+        for action in UiAction::read(universe) {
+            let from = focus.focused.as_ref().and_then(|(id, state)| {
+                let neighbors = tree.neighbors(*id)?;
+                Some((*id, NodeLayout(state.clone()).rect(), neighbors))
+            });
+            if let Some(node) = navigation::navigate(tree.hit_boxes(), from, action, wrap) {
+                focus.set(Some(node), &tree, universe);
+            }
+        }
+        // Buttons are pressed wherever the pointer last moved to, so this is where it is now
+        if let Some(event) = universe.read_events::<PointerMoved>().last() {
+            pointer.position = Some(graphics.screen_to_ui(event.position));
+        }
+        // Nodes may have moved under the pointer even if it did not move
+        pointer.hover(&tree, universe);
+    }
+}
@@ -0,0 +1,156 @@
+//! Moving keyboard focus between nodes with the arrow keys, a d-pad or any other directional input
+use bina_ecs::universe::Universe;
+use bina_graphics::{polygon::Vector, winit::event::VirtualKeyCode, KeyInput};
+
+use crate::{layout::Rect, node::UiNodeId};
+
+/// An input that moves focus, or is handled by the focused node
+///
+/// The arrow keys, Enter, Space and Escape are turned into actions automatically. Other inputs,
+/// such as a gamepad, are mapped by sending this as an event through the Universe
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum UiAction {
+    Up,
+    Down,
+    Left,
+    Right,
+    /// Activates the focused node, such as pressing a button
+    Confirm,
+    /// Backs out of the current menu. Nothing is done by the UI itself,
+    /// so whatever opened the menu should read this and close it
+    Cancel,
+}
+
+impl UiAction {
+    /// Gets the action that a pressed key is mapped to by default
+    pub fn from_key(input: &KeyInput) -> Option<Self> {
+        if !input.pressed {
+            return None;
+        }
+        Some(match input.key? {
+            VirtualKeyCode::Up => Self::Up,
+            VirtualKeyCode::Down => Self::Down,
+            VirtualKeyCode::Left => Self::Left,
+            VirtualKeyCode::Right => Self::Right,
+            VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter | VirtualKeyCode::Space => {
+                Self::Confirm
+            }
+            VirtualKeyCode::Escape => Self::Cancel,
+            _ => return None,
+        })
+    }
+
+    /// Reads every action from the last frame, whether it came from the keyboard or was sent as an event
+    pub fn read(universe: &Universe) -> impl Iterator<Item = Self> + '_ {
+        universe
+            .read_events::<KeyInput>()
+            .iter()
+            .filter_map(Self::from_key)
+            .chain(universe.read_events::<Self>().iter().copied())
+    }
+
+    /// The direction this action moves focus in, where y points down
+    fn direction(self) -> Option<Vector> {
+        match self {
+            Self::Up => Some(Vector::new(0.0, -1.0)),
+            Self::Down => Some(Vector::new(0.0, 1.0)),
+            Self::Left => Some(Vector::new(-1.0, 0.0)),
+            Self::Right => Some(Vector::new(1.0, 0.0)),
+            Self::Confirm | Self::Cancel => None,
+        }
+    }
+}
+
+/// Where focus moves from a node in one direction
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Neighbor {
+    /// The closest interactive node in that direction
+    #[default]
+    Nearest,
+    /// A specific node, as long as it can be focused
+    Node(UiNodeId),
+    /// Focus stays on this node, so that it can handle the action itself, like a slider does
+    Stay,
+}
+
+/// Overrides where focus moves from a node in each direction
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Neighbors {
+    pub up: Neighbor,
+    pub down: Neighbor,
+    pub left: Neighbor,
+    pub right: Neighbor,
+}
+
+impl Neighbors {
+    pub const NEAREST: Self = Self {
+        up: Neighbor::Nearest,
+        down: Neighbor::Nearest,
+        left: Neighbor::Nearest,
+        right: Neighbor::Nearest,
+    };
+
+    fn get(&self, action: UiAction) -> Neighbor {
+        match action {
+            UiAction::Up => self.up,
+            UiAction::Down => self.down,
+            UiAction::Left => self.left,
+            UiAction::Right => self.right,
+            UiAction::Confirm | UiAction::Cancel => Neighbor::Stay,
+        }
+    }
+}
+
+/// Finds the node that focus moves to from `from` because of `action`
+///
+/// `nodes` are the nodes that can be focused. If `wrap` is true and no node lies in the
+/// direction of the action, focus wraps around to the furthest node in the other direction
+pub(crate) fn navigate(
+    nodes: &[(UiNodeId, Rect)],
+    from: Option<(UiNodeId, Rect, Neighbors)>,
+    action: UiAction,
+    wrap: bool,
+) -> Option<UiNodeId> {
+    let direction = action.direction()?;
+    let Some((from, rect, neighbors)) = from else {
+        // Nothing is focused yet, so start from whichever end the action points away from
+        let first = nodes.first().map(|(id, _)| *id);
+        let last = nodes.last().map(|(id, _)| *id);
+        return match action {
+            UiAction::Up | UiAction::Left => last,
+            _ => first,
+        };
+    };
+    match neighbors.get(action) {
+        Neighbor::Stay => return None,
+        Neighbor::Node(node) => return nodes.iter().any(|(id, _)| *id == node).then_some(node),
+        Neighbor::Nearest => {}
+    }
+
+    let center = rect.center();
+    // Distance along the direction, and how far off to the side
+    let offsets = nodes
+        .iter()
+        .filter(|(id, _)| *id != from)
+        .map(|(id, rect)| {
+            let delta = rect.center() - center;
+            let along = delta.x * direction.x + delta.y * direction.y;
+            let across = (delta.x * direction.y - delta.y * direction.x).abs();
+            (*id, along, across)
+        });
+    // Nodes off to the side count for more, so that focus prefers staying in the same row or column
+    let ahead = offsets
+        .clone()
+        .filter(|(_, along, _)| *along > 0.0)
+        .min_by(|a, b| (a.1 + a.2 * 2.0).total_cmp(&(b.1 + b.2 * 2.0)));
+    if let Some((id, ..)) = ahead {
+        return Some(id);
+    }
+    if !wrap {
+        return None;
+    }
+    offsets
+        .filter(|(_, along, _)| *along < 0.0)
+        .min_by(|a, b| (a.1 + a.2 * 2.0).total_cmp(&(b.1 + b.2 * 2.0)))
+        .map(|(id, ..)| id)
+}
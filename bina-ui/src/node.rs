@@ -0,0 +1,261 @@
+//! The component that places an entity in the UI
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use atomic_float::AtomicF32;
+use bina_ecs::{
+    component::{Component, Processable},
+    entity::{Entity, EntityReference, Inaccessible},
+    parking_lot::Mutex,
+    triomphe::Arc,
+    universe::Universe,
+};
+use bina_graphics::{
+    image::{Rgba, RgbaImage},
+    layers::{RenderLayers, Visible},
+    polygon::{Material, Polygon, Vector},
+    texture::{SamplerOptions, Texture, TextureAsset, TextureRect},
+    Graphics,
+};
+
+use crate::{layout::Rect, layout::UiTree, navigation::Neighbors, style::Style};
+
+/// Identifies a node, even after it has been removed
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct UiNodeId(pub(crate) u64);
+
+/// The results of the last layout of a node
+pub(crate) struct NodeState {
+    rect: [AtomicF32; 4],
+    order: AtomicU32,
+    visible: AtomicBool,
+    hovered: AtomicBool,
+    pressed: AtomicBool,
+    focused: AtomicBool,
+}
+
+impl NodeState {
+    pub(crate) fn new() -> Self {
+        Self {
+            rect: std::array::from_fn(|_| AtomicF32::new(0.0)),
+            order: AtomicU32::new(0),
+            // Nodes are hidden until their first layout
+            visible: AtomicBool::new(false),
+            hovered: AtomicBool::new(false),
+            pressed: AtomicBool::new(false),
+            focused: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn set(&self, rect: Rect, order: u32) {
+        for (atomic, value) in self
+            .rect
+            .iter()
+            .zip([rect.x, rect.y, rect.width, rect.height])
+        {
+            atomic.store(value, Ordering::Relaxed);
+        }
+        self.order.store(order, Ordering::Relaxed);
+        self.visible.store(true, Ordering::Relaxed);
+    }
+
+    pub(crate) fn hide(&self) {
+        self.visible.store(false, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_hovered(&self, hovered: bool) {
+        self.hovered.store(hovered, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_pressed(&self, pressed: bool) {
+        self.pressed.store(pressed, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_focused(&self, focused: bool) {
+        self.focused.store(focused, Ordering::Relaxed);
+    }
+}
+
+/// Where a node was placed by the last layout
+///
+/// Clone this handle into any component that draws inside of a node, such as text
+/// or a nine-slice border. Those should be drawn on `RenderLayers::UI` with a z
+/// of at least `z`, so that they appear above the node but below later nodes
+#[derive(Clone)]
+pub struct NodeLayout(pub(crate) Arc<NodeState>);
+
+impl NodeLayout {
+    pub fn rect(&self) -> Rect {
+        let [x, y, width, height] = self.0.rect.each_ref().map(|x| x.load(Ordering::Relaxed));
+        Rect::new(x, y, width, height)
+    }
+
+    /// The position of the node in drawing order. Nodes are drawn above their parents and earlier siblings
+    pub fn z(&self) -> u32 {
+        self.0.order.load(Ordering::Relaxed)
+    }
+
+    /// Whether the node is shown, which is false if it or any of its parents are hidden, or it has not been laid out yet
+    pub fn is_visible(&self) -> bool {
+        self.0.visible.load(Ordering::Relaxed)
+    }
+
+    /// Whether the pointer is over this node. Only interactive nodes are hovered
+    pub fn is_hovered(&self) -> bool {
+        self.0.hovered.load(Ordering::Relaxed)
+    }
+
+    /// Whether a button was pressed on this node and has not been released yet
+    pub fn is_pressed(&self) -> bool {
+        self.0.pressed.load(Ordering::Relaxed)
+    }
+
+    /// Whether this node receives keyboard input
+    pub fn is_focused(&self) -> bool {
+        self.0.focused.load(Ordering::Relaxed)
+    }
+}
+
+/// A rectangle in the UI, created with `Ui::create_root` or `Ui::create_child`
+///
+/// The node is removed from the UI when this component is dropped.
+/// It is invisible unless it is given a background
+pub struct UiNode {
+    id: UiNodeId,
+    layout: NodeLayout,
+    tree: Arc<Mutex<UiTree>>,
+    background: Option<Polygon>,
+    /// The texture of the background if it is a single color, which is rewritten when the color changes
+    color: Option<TextureAsset>,
+    /// The background is only drawn once it has been placed by a flush
+    background_placed: bool,
+}
+
+impl UiNode {
+    pub(crate) fn new(id: UiNodeId, layout: NodeLayout, tree: Arc<Mutex<UiTree>>) -> Self {
+        Self {
+            id,
+            layout,
+            tree,
+            background: None,
+            color: None,
+            background_placed: false,
+        }
+    }
+
+    pub fn id(&self) -> UiNodeId {
+        self.id
+    }
+
+    /// Gets a handle to where this node was placed, which stays valid after this node is moved into an entity
+    pub fn layout(&self) -> NodeLayout {
+        self.layout.clone()
+    }
+
+    pub fn style(&self) -> Style {
+        self.tree
+            .lock()
+            .style(self.id)
+            .expect("Nodes are in the tree until they are dropped")
+    }
+
+    /// Changes the style of this node, which takes effect in the next layout
+    pub fn set_style(&self, style: Style) {
+        self.tree.lock().set_style(self.id, style);
+    }
+
+    pub fn neighbors(&self) -> Neighbors {
+        self.tree
+            .lock()
+            .neighbors(self.id)
+            .expect("Nodes are in the tree until they are dropped")
+    }
+
+    /// Changes where focus moves from this node when navigating with `UiAction`s
+    pub fn set_neighbors(&self, neighbors: Neighbors) {
+        self.tree.lock().set_neighbors(self.id, neighbors);
+    }
+
+    /// Stretches the given texture over the node
+    pub fn with_texture(mut self, graphics: &Graphics, texture: Texture) -> Self {
+        self.color = None;
+        let corners = [
+            Vector::new(0.0, 0.0),
+            Vector::new(1.0, 0.0),
+            Vector::new(1.0, 1.0),
+            Vector::new(0.0, 1.0),
+        ];
+        // UI coordinates and texture coordinates both point down, so they are the same on a unit square
+        let vertices = corners.map(|x| (x, x));
+        self.background = Some(Polygon::new(
+            graphics,
+            &vertices,
+            Material::Texture(texture),
+        ));
+        self
+    }
+
+    /// Fills the node with a single color
+    pub fn with_color(self, graphics: &Graphics, color: Rgba<u8>) -> Self {
+        let asset = TextureAsset::new(
+            RgbaImage::from_pixel(1, 1, color),
+            SamplerOptions::PIXEL_ART,
+        );
+        let texture = asset.get(graphics);
+        let mut node = self.with_texture(graphics, texture);
+        node.color = Some(asset);
+        node
+    }
+
+    /// Changes the color of a background created with `with_color`, which is visible after the next flush
+    ///
+    /// Does nothing if the background is not a single color
+    pub fn set_color(&self, graphics: &Graphics, color: Rgba<u8>) {
+        if let Some(asset) = &self.color {
+            asset.write_region(graphics, TextureRect::new(0, 0, 1, 1), &color.0);
+        }
+    }
+}
+
+impl Drop for UiNode {
+    fn drop(&mut self) {
+        self.tree.lock().remove(self.id);
+    }
+}
+
+impl Component for UiNode {
+    fn get_ref<'a>(&'a self) -> Self::Reference<'a> {
+        self
+    }
+
+    fn flush<E: Entity>(
+        &mut self,
+        my_entity: EntityReference<Inaccessible<E>>,
+        universe: &Universe,
+    ) {
+        let Some(background) = &mut self.background else {
+            return;
+        };
+        let rect = self.layout.rect();
+        let mut polygon = background.get_ref();
+        polygon.origin.set(Vector::new(rect.x, rect.y));
+        polygon.scale.set(Vector::new(rect.width, rect.height));
+        polygon.rotation.set(0.0);
+        polygon.z.set(self.layout.z());
+        polygon.layers.set(RenderLayers::UI);
+        polygon.visible.set(Visible(self.layout.is_visible()));
+        background.flush(my_entity, universe);
+        self.background_placed = true;
+    }
+}
+
+impl Processable for UiNode {
+    fn process<E: Entity>(
+        component: Self::Reference<'_>,
+        my_entity: EntityReference<E>,
+        universe: &Universe,
+    ) {
+        if let (Some(background), true) = (&component.background, component.background_placed) {
+            Polygon::process(background.get_ref(), my_entity, universe);
+        }
+    }
+}
@@ -0,0 +1,193 @@
+//! How a node is sized and positioned relative to its parent
+use bina_graphics::polygon::Vector;
+
+/// A length along one axis, in UI units
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Length {
+    /// Just large enough to fit the children of the node, plus its padding
+    Auto,
+    Units(f32),
+    /// A percentage of the inside of the parent, after its padding
+    Percent(f32),
+}
+
+impl Length {
+    /// Gets the length, or `None` if it is decided by the contents
+    pub(crate) fn resolve(self, parent: f32) -> Option<f32> {
+        match self {
+            Self::Auto => None,
+            Self::Units(x) => Some(x),
+            Self::Percent(x) => Some(parent * x / 100.0),
+        }
+    }
+}
+
+/// The point of the parent a node is attached to, as a fraction of its size
+///
+/// The same point of the node is placed there, so `Anchor::BOTTOM_RIGHT` puts the
+/// bottom right corner of the node on the bottom right corner of its parent
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Anchor {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Anchor {
+    pub const TOP_LEFT: Self = Self::new(0.0, 0.0);
+    pub const TOP: Self = Self::new(0.5, 0.0);
+    pub const TOP_RIGHT: Self = Self::new(1.0, 0.0);
+    pub const LEFT: Self = Self::new(0.0, 0.5);
+    pub const CENTER: Self = Self::new(0.5, 0.5);
+    pub const RIGHT: Self = Self::new(1.0, 0.5);
+    pub const BOTTOM_LEFT: Self = Self::new(0.0, 1.0);
+    pub const BOTTOM: Self = Self::new(0.5, 1.0);
+    pub const BOTTOM_RIGHT: Self = Self::new(1.0, 1.0);
+
+    pub const fn new(x: f32, y: f32) -> Self {
+        Self { x, y }
+    }
+}
+
+/// Space on each side of a rectangle
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Edges {
+    pub top: f32,
+    pub right: f32,
+    pub bottom: f32,
+    pub left: f32,
+}
+
+impl Edges {
+    pub const ZERO: Self = Self::all(0.0);
+
+    pub const fn all(x: f32) -> Self {
+        Self {
+            top: x,
+            right: x,
+            bottom: x,
+            left: x,
+        }
+    }
+
+    pub const fn symmetric(horizontal: f32, vertical: f32) -> Self {
+        Self {
+            top: vertical,
+            right: horizontal,
+            bottom: vertical,
+            left: horizontal,
+        }
+    }
+
+    pub(crate) fn horizontal(&self) -> f32 {
+        self.left + self.right
+    }
+
+    pub(crate) fn vertical(&self) -> f32 {
+        self.top + self.bottom
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Direction {
+    /// Children are placed from left to right
+    Row,
+    /// Children are placed from top to bottom
+    Column,
+}
+
+/// Where children are placed along the direction of a flex node
+/// when they do not fill it, and none of them grow
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Justify {
+    Start,
+    Center,
+    End,
+    /// The first and last child touch the edges, and the rest are evenly spaced between them
+    SpaceBetween,
+}
+
+/// Where children are placed across the direction of a flex node
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Align {
+    Start,
+    Center,
+    End,
+    /// Children whose size across the direction is `Length::Auto` fill the node
+    Stretch,
+}
+
+/// Places the children of a node one after another, instead of anchoring each of them
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Flex {
+    pub direction: Direction,
+    /// The space between each child
+    pub gap: f32,
+    pub justify: Justify,
+    pub align: Align,
+}
+
+impl Flex {
+    pub const ROW: Self = Self {
+        direction: Direction::Row,
+        gap: 0.0,
+        justify: Justify::Start,
+        align: Align::Stretch,
+    };
+    pub const COLUMN: Self = Self {
+        direction: Direction::Column,
+        ..Self::ROW
+    };
+}
+
+/// How a node is laid out
+///
+/// ```ignore
+/// let menu = ui.create_root(Style {
+///     anchor: Anchor::CENTER,
+///     width: Length::Units(240.0),
+///     padding: Edges::all(16.0),
+///     flex: Some(Flex { gap: 8.0, ..Flex::COLUMN }),
+///     ..Style::DEFAULT
+/// });
+/// ```
+#[derive(Clone, Copy)]
+pub struct Style {
+    /// Where the node is attached to its parent. Ignored if the parent is a flex node
+    pub anchor: Anchor,
+    /// Moves the node after it has been placed
+    pub offset: Vector,
+    pub width: Length,
+    pub height: Length,
+    /// Space between the edges of the node and its children
+    pub padding: Edges,
+    /// Lays out the children one after another. If `None`, each child is anchored instead
+    pub flex: Option<Flex>,
+    /// How much of the unused space of a flex parent this node takes,
+    /// relative to its siblings. Nodes with 0 keep their size
+    pub grow: f32,
+    /// Hidden nodes, and all of their children, are not drawn and take no space
+    pub visible: bool,
+    /// Only interactive nodes can be hovered and clicked.
+    /// The pointer passes through other nodes to whatever is below them
+    pub interactive: bool,
+}
+
+impl Style {
+    pub const DEFAULT: Self = Self {
+        anchor: Anchor::TOP_LEFT,
+        offset: Vector::new(0.0, 0.0),
+        width: Length::Auto,
+        height: Length::Auto,
+        padding: Edges::ZERO,
+        flex: None,
+        grow: 0.0,
+        visible: true,
+        interactive: false,
+    };
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
@@ -0,0 +1,88 @@
+//! A node that can be clicked, or activated from the keyboard
+use bina_ecs::{
+    component::{Component, Processable},
+    entity::{Entity, EntityReference, Inaccessible},
+    universe::Universe,
+};
+use bina_graphics::{winit::event::MouseButton, Graphics};
+
+use super::{StateColors, WidgetNode, WidgetState};
+use crate::{
+    navigation::UiAction,
+    node::{UiNode, UiNodeId},
+    Clicked,
+};
+
+/// Sent when a button is clicked with the left button,
+/// or `UiAction::Confirm` is sent while it has focus
+#[derive(Clone, Copy, Debug)]
+pub struct ButtonPressed {
+    pub node: UiNodeId,
+}
+
+pub struct Button {
+    widget: WidgetNode,
+}
+
+impl Button {
+    /// Turns a node into a button. The node is made interactive, and its background is replaced
+    pub fn new(node: UiNode, graphics: &Graphics, colors: StateColors) -> Self {
+        Self {
+            widget: WidgetNode::new(node, graphics, colors),
+        }
+    }
+
+    pub fn node(&self) -> &UiNode {
+        &self.widget.node
+    }
+
+    pub fn state(&self) -> WidgetState {
+        self.widget.state()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.widget.is_enabled()
+    }
+
+    /// Disabled buttons cannot be pressed
+    pub fn set_enabled(&self, enabled: bool) {
+        self.widget.set_enabled(enabled);
+    }
+}
+
+impl Component for Button {
+    fn get_ref<'a>(&'a self) -> Self::Reference<'a> {
+        self
+    }
+
+    fn flush<E: Entity>(
+        &mut self,
+        my_entity: EntityReference<Inaccessible<E>>,
+        universe: &Universe,
+    ) {
+        self.widget.flush(my_entity, universe);
+    }
+}
+
+impl Processable for Button {
+    fn process<E: Entity>(
+        component: Self::Reference<'_>,
+        my_entity: EntityReference<E>,
+        universe: &Universe,
+    ) {
+        component.widget.process(my_entity, universe);
+        if !component.is_enabled() {
+            return;
+        }
+        let node = component.widget.node.id();
+        let clicked = universe
+            .read_events::<Clicked>()
+            .iter()
+            .any(|x| x.node == node && x.button == MouseButton::Left);
+        let activated = component.widget.is_focused()
+            && UiAction::read(universe).any(|x| x == UiAction::Confirm);
+        if clicked || activated {
+            universe.send_event(ButtonPressed { node });
+        }
+    }
+}
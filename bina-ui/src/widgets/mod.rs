@@ -0,0 +1,145 @@
+//! Standard interactive widgets, built on top of `UiNode`
+//!
+//! Each widget wraps an interactive node whose background color shows whether it is hovered,
+//! pressed or disabled. A widget with keyboard focus is drawn as if it were hovered. Widgets are
+//! components, so they are added to entities instead of their node, and their events are sent
+//! through the Universe
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use bina_ecs::{
+    component::{Component, Processable},
+    crossbeam::atomic::AtomicCell,
+    entity::{Entity, EntityReference, Inaccessible},
+    universe::Universe,
+};
+use bina_graphics::{image::Rgba, Graphics};
+
+use crate::{node::UiNode, style::Style};
+
+pub mod button;
+pub mod slider;
+pub mod text_field;
+
+pub use button::{Button, ButtonPressed};
+pub use slider::{Slider, SliderChanged};
+pub use text_field::{TextChanged, TextField, TextSubmitted};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WidgetState {
+    Normal,
+    /// The pointer is over the widget, or it has keyboard focus
+    Hovered,
+    Pressed,
+    /// The widget ignores all input
+    Disabled,
+}
+
+/// The background color of a widget in each of its states
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct StateColors {
+    pub normal: Rgba<u8>,
+    pub hovered: Rgba<u8>,
+    pub pressed: Rgba<u8>,
+    pub disabled: Rgba<u8>,
+}
+
+impl StateColors {
+    pub const DEFAULT: Self = Self {
+        normal: Rgba([70, 70, 70, 255]),
+        hovered: Rgba([95, 95, 95, 255]),
+        pressed: Rgba([50, 50, 50, 255]),
+        disabled: Rgba([40, 40, 40, 255]),
+    };
+
+    pub fn get(&self, state: WidgetState) -> Rgba<u8> {
+        match state {
+            WidgetState::Normal => self.normal,
+            WidgetState::Hovered => self.hovered,
+            WidgetState::Pressed => self.pressed,
+            WidgetState::Disabled => self.disabled,
+        }
+    }
+}
+
+impl Default for StateColors {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// The node shared by every widget, which is recolored whenever its state changes
+pub(crate) struct WidgetNode {
+    pub(crate) node: UiNode,
+    colors: StateColors,
+    enabled: AtomicBool,
+    state: AtomicCell<WidgetState>,
+}
+
+impl WidgetNode {
+    pub(crate) fn new(node: UiNode, graphics: &Graphics, colors: StateColors) -> Self {
+        node.set_style(Style {
+            interactive: true,
+            ..node.style()
+        });
+        Self {
+            node: node.with_color(graphics, colors.normal),
+            colors,
+            enabled: AtomicBool::new(true),
+            state: AtomicCell::new(WidgetState::Normal),
+        }
+    }
+
+    pub(crate) fn state(&self) -> WidgetState {
+        self.state.load()
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Disabled widgets are not interactive, so they cannot be hovered, clicked or focused
+    pub(crate) fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+        self.node.set_style(Style {
+            interactive: enabled,
+            ..self.node.style()
+        });
+    }
+
+    /// Whether the widget has keyboard focus, and so should handle key presses
+    pub(crate) fn is_focused(&self) -> bool {
+        self.is_enabled() && self.node.layout().is_focused()
+    }
+
+    /// Recolors the node if its state has changed since the last frame
+    pub(crate) fn update(&self, universe: &Universe) {
+        let layout = self.node.layout();
+        let state = if !self.is_enabled() {
+            WidgetState::Disabled
+        } else if layout.is_pressed() {
+            WidgetState::Pressed
+        } else if layout.is_hovered() || layout.is_focused() {
+            WidgetState::Hovered
+        } else {
+            WidgetState::Normal
+        };
+        if self.state.swap(state) != state {
+            if let Some(graphics) = universe.try_get_singleton::<Graphics>() {
+                self.node.set_color(graphics, self.colors.get(state));
+            }
+        }
+    }
+
+    pub(crate) fn process<E: Entity>(&self, my_entity: EntityReference<E>, universe: &Universe) {
+        self.update(universe);
+        UiNode::process(&self.node, my_entity, universe);
+    }
+
+    pub(crate) fn flush<E: Entity>(
+        &mut self,
+        my_entity: EntityReference<Inaccessible<E>>,
+        universe: &Universe,
+    ) {
+        self.node.flush(my_entity, universe);
+    }
+}
@@ -0,0 +1,192 @@
+//! A node that picks a number from a range by dragging, or with the left and right actions
+use std::{ops::RangeInclusive, sync::atomic::Ordering};
+
+use atomic_float::AtomicF32;
+use bina_ecs::{
+    component::{Component, Processable},
+    entity::{Entity, EntityReference, Inaccessible},
+    universe::Universe,
+};
+use bina_graphics::{image::Rgba, winit::event::VirtualKeyCode, Graphics, KeyInput};
+
+use super::{StateColors, WidgetNode, WidgetState};
+use crate::{
+    navigation::{Neighbor, Neighbors, UiAction},
+    node::{UiNode, UiNodeId},
+    style::{Length, Style},
+    Ui,
+};
+
+/// Sent when the user changes the value of a slider. Not sent by `Slider::set_value`
+#[derive(Clone, Copy, Debug)]
+pub struct SliderChanged {
+    pub node: UiNodeId,
+    pub value: f32,
+}
+
+/// A horizontal slider, which fills from the left up to its value
+pub struct Slider {
+    widget: WidgetNode,
+    /// A child of the node whose width shows the value
+    fill: UiNode,
+    min: f32,
+    max: f32,
+    step: f32,
+    value: AtomicF32,
+}
+
+impl Slider {
+    /// Turns a node into a slider that starts at the lowest value of `range`
+    ///
+    /// The node is made interactive and its background is replaced. Any children it
+    /// already has are drawn below the fill. `UiAction::Left` and `UiAction::Right`
+    /// change the value while it has focus, instead of moving focus to its neighbors
+    pub fn new(
+        ui: &Ui,
+        node: UiNode,
+        graphics: &Graphics,
+        colors: StateColors,
+        fill_color: Rgba<u8>,
+        range: RangeInclusive<f32>,
+    ) -> Self {
+        let (min, max) = range.into_inner();
+        let fill = ui
+            .create_child(
+                node.id(),
+                Style {
+                    width: Length::Percent(0.0),
+                    height: Length::Percent(100.0),
+                    ..Style::DEFAULT
+                },
+            )
+            .with_color(graphics, fill_color);
+        node.set_neighbors(Neighbors {
+            left: Neighbor::Stay,
+            right: Neighbor::Stay,
+            ..node.neighbors()
+        });
+        Self {
+            widget: WidgetNode::new(node, graphics, colors),
+            fill,
+            min,
+            max,
+            step: (max - min) / 20.0,
+            value: AtomicF32::new(min),
+        }
+    }
+
+    /// Sets how much the left and right actions change the value
+    pub fn with_step(mut self, step: f32) -> Self {
+        self.step = step;
+        self
+    }
+
+    pub fn with_value(self, value: f32) -> Self {
+        self.set_value(value);
+        self
+    }
+
+    pub fn node(&self) -> &UiNode {
+        &self.widget.node
+    }
+
+    pub fn state(&self) -> WidgetState {
+        self.widget.state()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.widget.is_enabled()
+    }
+
+    /// Disabled sliders cannot be changed by the user
+    pub fn set_enabled(&self, enabled: bool) {
+        self.widget.set_enabled(enabled);
+    }
+
+    pub fn value(&self) -> f32 {
+        self.value.load(Ordering::Relaxed)
+    }
+
+    /// Changes the value, which is clamped to the range of the slider
+    pub fn set_value(&self, value: f32) {
+        let value = value.clamp(self.min, self.max);
+        self.value.store(value, Ordering::Relaxed);
+        let fraction = if self.max > self.min {
+            (value - self.min) / (self.max - self.min)
+        } else {
+            0.0
+        };
+        self.fill.set_style(Style {
+            width: Length::Percent(fraction * 100.0),
+            ..self.fill.style()
+        });
+    }
+}
+
+impl Component for Slider {
+    fn get_ref<'a>(&'a self) -> Self::Reference<'a> {
+        self
+    }
+
+    fn flush<E: Entity>(
+        &mut self,
+        my_entity: EntityReference<Inaccessible<E>>,
+        universe: &Universe,
+    ) {
+        self.widget.flush(my_entity.clone(), universe);
+        self.fill.flush(my_entity, universe);
+    }
+}
+
+impl Processable for Slider {
+    fn process<E: Entity>(
+        component: Self::Reference<'_>,
+        my_entity: EntityReference<E>,
+        universe: &Universe,
+    ) {
+        component.widget.process(my_entity.clone(), universe);
+        UiNode::process(&component.fill, my_entity, universe);
+        if !component.is_enabled() {
+            return;
+        }
+
+        let old = component.value();
+        let mut value = old;
+        let layout = component.widget.node.layout();
+        if layout.is_pressed() {
+            let position = universe
+                .try_get_singleton::<Ui>()
+                .and_then(Ui::pointer_position);
+            let rect = layout.rect();
+            if let (Some(position), true) = (position, rect.width > 0.0) {
+                let fraction = ((position.x - rect.x) / rect.width).clamp(0.0, 1.0);
+                value = component.min + fraction * (component.max - component.min);
+            }
+        }
+        if component.widget.is_focused() {
+            for action in UiAction::read(universe) {
+                match action {
+                    UiAction::Left => value -= component.step,
+                    UiAction::Right => value += component.step,
+                    _ => {}
+                }
+            }
+            for event in universe.read_events::<KeyInput>() {
+                match (event.pressed, event.key) {
+                    (true, Some(VirtualKeyCode::Home)) => value = component.min,
+                    (true, Some(VirtualKeyCode::End)) => value = component.max,
+                    _ => {}
+                }
+            }
+        }
+
+        let value = value.clamp(component.min, component.max);
+        if value != old {
+            component.set_value(value);
+            universe.send_event(SliderChanged {
+                node: component.widget.node.id(),
+                value,
+            });
+        }
+    }
+}
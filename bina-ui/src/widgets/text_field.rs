@@ -0,0 +1,161 @@
+//! A node that can be typed into while it has focus
+use bina_ecs::{
+    component::{Component, Processable},
+    entity::{Entity, EntityReference, Inaccessible},
+    parking_lot::Mutex,
+    universe::Universe,
+};
+use bina_graphics::{winit::event::VirtualKeyCode, CharacterTyped, Graphics, KeyInput};
+
+use super::{StateColors, WidgetNode, WidgetState};
+use crate::{
+    navigation::{Neighbor, Neighbors},
+    node::{UiNode, UiNodeId},
+};
+
+/// Sent at most once a frame when the user changes the text of a text field
+#[derive(Clone, Debug)]
+pub struct TextChanged {
+    pub node: UiNodeId,
+    pub text: String,
+}
+
+/// Sent when Enter is pressed while a text field has focus
+#[derive(Clone, Debug)]
+pub struct TextSubmitted {
+    pub node: UiNodeId,
+    pub text: String,
+}
+
+/// A single line of editable text. Click on it or tab to it to give it focus
+///
+/// Focus does not move left or right out of a text field, so that typing is not interrupted.
+/// The text itself is not drawn by this crate. Whatever draws it should read
+/// `TextField::text` and place it using the layout of `TextField::node`
+pub struct TextField {
+    widget: WidgetNode,
+    text: Mutex<String>,
+    /// The most characters the user can type
+    max_length: Option<usize>,
+}
+
+impl TextField {
+    /// Turns a node into an empty text field. The node is made interactive, and its background is replaced
+    pub fn new(node: UiNode, graphics: &Graphics, colors: StateColors) -> Self {
+        node.set_neighbors(Neighbors {
+            left: Neighbor::Stay,
+            right: Neighbor::Stay,
+            ..node.neighbors()
+        });
+        Self {
+            widget: WidgetNode::new(node, graphics, colors),
+            text: Mutex::new(String::new()),
+            max_length: None,
+        }
+    }
+
+    pub fn with_text(self, text: impl Into<String>) -> Self {
+        *self.text.lock() = text.into();
+        self
+    }
+
+    pub fn with_max_length(mut self, max_length: usize) -> Self {
+        self.max_length = Some(max_length);
+        self
+    }
+
+    pub fn node(&self) -> &UiNode {
+        &self.widget.node
+    }
+
+    pub fn state(&self) -> WidgetState {
+        self.widget.state()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.widget.is_enabled()
+    }
+
+    /// Disabled text fields cannot be typed into
+    pub fn set_enabled(&self, enabled: bool) {
+        self.widget.set_enabled(enabled);
+    }
+
+    pub fn text(&self) -> String {
+        self.text.lock().clone()
+    }
+
+    /// Replaces the text. This does not send `TextChanged`
+    pub fn set_text(&self, text: impl Into<String>) {
+        *self.text.lock() = text.into();
+    }
+}
+
+impl Component for TextField {
+    fn get_ref<'a>(&'a self) -> Self::Reference<'a> {
+        self
+    }
+
+    fn flush<E: Entity>(
+        &mut self,
+        my_entity: EntityReference<Inaccessible<E>>,
+        universe: &Universe,
+    ) {
+        self.widget.flush(my_entity, universe);
+    }
+}
+
+impl Processable for TextField {
+    fn process<E: Entity>(
+        component: Self::Reference<'_>,
+        my_entity: EntityReference<E>,
+        universe: &Universe,
+    ) {
+        component.widget.process(my_entity, universe);
+        if !component.widget.is_focused() {
+            return;
+        }
+
+        let node = component.widget.node.id();
+        let mut text = component.text.lock();
+        let mut changed = false;
+        // Backspace and Enter also arrive as control characters on some platforms,
+        // so they are only handled as keys
+        for CharacterTyped(character) in universe.read_events::<CharacterTyped>() {
+            if character.is_control() {
+                continue;
+            }
+            if component
+                .max_length
+                .is_some_and(|max| text.chars().count() >= max)
+            {
+                break;
+            }
+            text.push(*character);
+            changed = true;
+        }
+        let mut submitted = false;
+        for event in universe.read_events::<KeyInput>() {
+            if !event.pressed {
+                continue;
+            }
+            match event.key {
+                Some(VirtualKeyCode::Back) => changed |= text.pop().is_some(),
+                Some(VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter) => submitted = true,
+                _ => {}
+            }
+        }
+        if changed {
+            universe.send_event(TextChanged {
+                node,
+                text: text.clone(),
+            });
+        }
+        if submitted {
+            universe.send_event(TextSubmitted {
+                node,
+                text: text.clone(),
+            });
+        }
+    }
+}
@@ -0,0 +1,171 @@
+//! The harness shared by the stress test examples
+//!
+//! Each example runs for a fixed number of frames while `Diagnostics` writes every frame to a CSV
+//! file, then prints a summary of the frame times. Comparing the CSV files of two releases shows
+//! whether the ECS or the renderer got slower
+//!
+//! Every example takes the same arguments:
+//! - `--frames <n>`: how many frames to run, 2000 by default
+//! - `--count <n>`: how many things to stress the engine with, which depends on the example
+//! - `--csv <path>`: where to write the CSV file, `<example>.csv` by default
+//! - `--headless`: renders off-screen at 1280x720 instead of opening a window
+// Not every example uses every helper
+#![allow(dead_code)]
+use std::{
+    fs::File,
+    io::BufWriter,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use bina::{
+    ecs::rand::Rng,
+    ecs::{
+        diagnostics::Diagnostics,
+        singleton::Singleton,
+        universe::{DeltaStrategy, LoopCount, Universe},
+    },
+    graphics::{
+        image::{Rgba, RgbaImage},
+        polygon::Vector,
+        texture::{SamplerOptions, TextureAsset},
+        winit::dpi::PhysicalSize,
+        Graphics, ScalingMode,
+    },
+};
+
+/// How many texels wide and tall the palette is
+const PALETTE_SIZE: u32 = 16;
+
+pub struct BenchOptions {
+    pub frames: usize,
+    pub count: usize,
+    pub csv: String,
+    pub headless: bool,
+}
+
+impl BenchOptions {
+    /// Reads the options from the command line, using `count` if `--count` is not given
+    pub fn from_args(name: &str, count: usize) -> Self {
+        let mut options = Self {
+            frames: 2000,
+            count,
+            csv: format!("{name}.csv"),
+            headless: false,
+        };
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().unwrap_or_else(|| panic!("{arg} needs a value"));
+            match arg.as_str() {
+                "--frames" => options.frames = value().parse().expect("--frames must be a number"),
+                "--count" => options.count = value().parse().expect("--count must be a number"),
+                "--csv" => options.csv = value(),
+                "--headless" => options.headless = true,
+                _ => panic!("Unknown argument {arg}"),
+            }
+        }
+        options
+    }
+}
+
+/// A texture of many colors. Flat colors are not drawn yet, so sprites use `square`
+/// to take their color from a single texel of this instead
+pub fn palette() -> TextureAsset {
+    let image = RgbaImage::from_fn(PALETTE_SIZE, PALETTE_SIZE, |x, y| {
+        let step = 256 / PALETTE_SIZE;
+        Rgba([
+            (x * step) as u8,
+            (y * step) as u8,
+            (255 - x * step / 2) as u8,
+            255,
+        ])
+    });
+    TextureAsset::new(image, SamplerOptions::PIXEL_ART).with_name("palette")
+}
+
+/// Gets the vertices of a square centered on the origin, colored by a random texel of the `palette`
+pub fn square(half_size: f32, rng: &mut impl Rng) -> [(Vector, Vector); 4] {
+    let texel = |x: u32| (x as f32 + 0.5) / PALETTE_SIZE as f32;
+    let uv = Vector::new(
+        texel(rng.gen_range(0..PALETTE_SIZE)),
+        texel(rng.gen_range(0..PALETTE_SIZE)),
+    );
+    [
+        (Vector::new(-half_size, -half_size), uv),
+        (Vector::new(half_size, -half_size), uv),
+        (Vector::new(half_size, half_size), uv),
+        (Vector::new(-half_size, half_size), uv),
+    ]
+}
+
+/// Exits the Universe after the given number of frames, printing a summary of the frame times
+struct Bench {
+    name: &'static str,
+    frames: usize,
+    frame: AtomicUsize,
+}
+
+impl Singleton for Bench {
+    fn process(&self, universe: &Universe) {
+        let frame = self.frame.fetch_add(1, Ordering::Relaxed) + 1;
+        if frame == 1 {
+            // Presenting in step with the monitor would cap the frame rate
+            universe.get_singleton::<Graphics>().set_vsync(false);
+        }
+        if frame < self.frames {
+            return;
+        }
+        if frame == self.frames {
+            let times = universe.get_singleton::<Diagnostics>().frame_times();
+            println!(
+                "{}: {} frames, {:.1} FPS, average {:.2} ms, p50 {:.2} ms, p95 {:.2} ms, p99 {:.2} ms, worst {:.2} ms",
+                self.name,
+                self.frames,
+                times.fps,
+                times.average * 1000.0,
+                times.p50 * 1000.0,
+                times.p95 * 1000.0,
+                times.p99 * 1000.0,
+                times.worst * 1000.0,
+            );
+        }
+        universe.exit_ok();
+    }
+}
+
+/// Runs the Universe as fast as possible until enough frames have passed
+pub async fn run(name: &'static str, universe: Universe, options: BenchOptions) {
+    let csv = File::create(&options.csv)
+        .unwrap_or_else(|e| panic!("Failed to create {}: {e}", options.csv));
+    universe.queue_set_singleton(Diagnostics::new().with_csv(BufWriter::new(csv)));
+    universe.queue_set_singleton(Bench {
+        name,
+        frames: options.frames,
+        frame: AtomicUsize::new(0),
+    });
+
+    let delta = DeltaStrategy::RealDelta(Duration::ZERO);
+    if options.headless {
+        let size = PhysicalSize::new(1280, 720);
+        if let Err(e) = Graphics::run_headless(
+            universe,
+            LoopCount::Forever,
+            delta,
+            size,
+            ScalingMode::Expand,
+        )
+        .await
+        {
+            panic!("{name} failed: {e}");
+        }
+    } else {
+        Graphics::run(
+            universe,
+            LoopCount::Forever,
+            delta,
+            name,
+            ScalingMode::Expand,
+        )
+        .await;
+    }
+}
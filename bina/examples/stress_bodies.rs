@@ -0,0 +1,158 @@
+//! Simulates 10k bouncing bodies with a fixed time step, so that they move the same
+//! however fast the frames are
+//!
+//! `cargo run --release -p bina --example stress_bodies -- --frames 2000`
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use bina::{
+    ecs::{
+        component::{Component, Processable},
+        diagnostics::Diagnostics,
+        entity::{Entity, EntityReference, ErasedEntityReference},
+        rand::{rngs::SmallRng, Rng, SeedableRng},
+        singleton::Singleton,
+        tokio,
+        universe::Universe,
+    },
+    graphics::{
+        polygon::{Material, Polygon, Vector},
+        Graphics,
+    },
+    macros::derive_component,
+};
+use common::BenchOptions;
+
+mod common;
+
+/// The duration of each physics step, in seconds
+const STEP: f32 = 1.0 / 120.0;
+/// More steps than this are dropped, so that a slow frame cannot make the next one slower
+const MAX_STEPS: u32 = 8;
+const GRAVITY: f32 = -0.5;
+/// How much speed a body keeps when it bounces
+const RESTITUTION: f32 = 0.95;
+const RADIUS: f32 = 0.003;
+const HALF_EXTENT: f32 = 0.5 - RADIUS;
+
+/// Decides how many steps every body takes each frame
+struct PhysicsClock {
+    accumulator: f32,
+    /// Decided during the flush of the previous frame
+    steps: AtomicU32,
+}
+
+impl Singleton for PhysicsClock {
+    fn process(&self, universe: &Universe) {
+        if let Some(diagnostics) = universe.try_get_singleton::<Diagnostics>() {
+            diagnostics.set_counter("physics steps", self.steps.load(Ordering::Relaxed) as f64);
+        }
+    }
+
+    fn flush(&mut self, universe: &Universe) {
+        self.accumulator += universe.get_delta();
+        let steps = ((self.accumulator / STEP) as u32).min(MAX_STEPS);
+        self.accumulator = (self.accumulator - steps as f32 * STEP).min(STEP);
+        *self.steps.get_mut() = steps;
+    }
+}
+
+derive_component! {
+    struct Body {
+        #[improve]
+        vx: f32,
+        #[improve]
+        vy: f32,
+    }
+}
+
+/// Bounces a position and velocity off of the edges of the visible area along one axis
+fn bounce(position: &mut f32, velocity: &mut f32) {
+    if *position < -HALF_EXTENT {
+        *position = -2.0 * HALF_EXTENT - *position;
+        *velocity = -*velocity * RESTITUTION;
+    } else if *position > HALF_EXTENT {
+        *position = 2.0 * HALF_EXTENT - *position;
+        *velocity = -*velocity * RESTITUTION;
+    }
+}
+
+impl Processable for Body {
+    fn process<E: Entity>(
+        mut component: Self::Reference<'_>,
+        my_entity: EntityReference<E>,
+        universe: &Universe,
+    ) {
+        let my_entity: &dyn ErasedEntityReference = &my_entity;
+        let Some(polygon) = my_entity.get_component::<Polygon>() else {
+            return;
+        };
+        let steps = universe
+            .get_singleton::<PhysicsClock>()
+            .steps
+            .load(Ordering::Relaxed);
+        let mut origin = polygon.get_ref().origin;
+        let mut position = *origin;
+        let (mut vx, mut vy) = (*component.vx, *component.vy);
+        for _ in 0..steps {
+            vy += GRAVITY * STEP;
+            position.x += vx * STEP;
+            position.y += vy * STEP;
+            bounce(&mut position.x, &mut vx);
+            bounce(&mut position.y, &mut vy);
+        }
+        origin.set(position);
+        component.vx.set(vx);
+        component.vy.set(vy);
+    }
+}
+
+/// Adds every body on the first frame, once `Graphics` exists
+struct Spawner {
+    count: usize,
+    spawned: AtomicBool,
+}
+
+impl Singleton for Spawner {
+    fn process(&self, universe: &Universe) {
+        if self.spawned.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        let graphics = universe.get_singleton::<Graphics>();
+        let mut rng = SmallRng::seed_from_u64(0);
+        let palette = common::palette();
+        for _ in 0..self.count {
+            let position = Vector::new(
+                rng.gen_range(-HALF_EXTENT..HALF_EXTENT),
+                rng.gen_range(-HALF_EXTENT..HALF_EXTENT),
+            );
+            let polygon = Polygon::new(
+                graphics,
+                &common::square(RADIUS, &mut rng),
+                Material::Texture(palette.get(graphics)),
+            );
+            polygon.get_ref().origin.set(position);
+            universe.queue_add_entity((
+                polygon,
+                Body {
+                    vx: rng.gen_range(-0.3..0.3).into(),
+                    vy: rng.gen_range(-0.3..0.3).into(),
+                },
+            ));
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let options = BenchOptions::from_args("stress_bodies", 10_000);
+    let universe = Universe::new();
+    universe.queue_set_singleton(PhysicsClock {
+        accumulator: 0.0,
+        steps: AtomicU32::new(0),
+    });
+    universe.queue_set_singleton(Spawner {
+        count: options.count,
+        spawned: AtomicBool::new(false),
+    });
+    common::run("stress_bodies", universe, options).await;
+}
@@ -0,0 +1,90 @@
+//! Draws 100k small sprites that drift across the screen, each moved by its own component
+//!
+//! `cargo run --release -p bina --example stress_sprites -- --frames 2000`
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use bina::{
+    ecs::{
+        component::{Component, Processable},
+        entity::{Entity, EntityReference, ErasedEntityReference},
+        rand::{rngs::SmallRng, Rng, SeedableRng},
+        singleton::Singleton,
+        tokio,
+        universe::Universe,
+    },
+    graphics::{
+        polygon::{Material, Polygon, Vector},
+        Graphics,
+    },
+    macros::derive_component,
+};
+use common::BenchOptions;
+
+mod common;
+
+/// Half of the size of each sprite
+const HALF_SIZE: f32 = 0.002;
+
+derive_component! {
+    struct Drift {
+        velocity: Vector,
+    }
+}
+
+impl Processable for Drift {
+    fn process<E: Entity>(
+        component: Self::Reference<'_>,
+        my_entity: EntityReference<E>,
+        universe: &Universe,
+    ) {
+        let my_entity: &dyn ErasedEntityReference = &my_entity;
+        let Some(polygon) = my_entity.get_component::<Polygon>() else {
+            return;
+        };
+        let mut origin = polygon.get_ref().origin;
+        let mut next = *origin + *component.velocity * universe.get_delta();
+        // Wraps around the visible area, which is 1 unit across
+        next.x = (next.x + 0.5).rem_euclid(1.0) - 0.5;
+        next.y = (next.y + 0.5).rem_euclid(1.0) - 0.5;
+        origin.set(next);
+    }
+}
+
+/// Adds every sprite on the first frame, once `Graphics` exists
+struct Spawner {
+    count: usize,
+    spawned: AtomicBool,
+}
+
+impl Singleton for Spawner {
+    fn process(&self, universe: &Universe) {
+        if self.spawned.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        let graphics = universe.get_singleton::<Graphics>();
+        let mut rng = SmallRng::seed_from_u64(0);
+        let palette = common::palette();
+        for _ in 0..self.count {
+            let position = Vector::new(rng.gen_range(-0.5..0.5), rng.gen_range(-0.5..0.5));
+            let velocity = Vector::new(rng.gen_range(-0.1..0.1), rng.gen_range(-0.1..0.1));
+            let polygon = Polygon::new(
+                graphics,
+                &common::square(HALF_SIZE, &mut rng),
+                Material::Texture(palette.get(graphics)),
+            );
+            polygon.get_ref().origin.set(position);
+            universe.queue_add_entity((polygon, Drift { velocity }));
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let options = BenchOptions::from_args("stress_sprites", 100_000);
+    let universe = Universe::new();
+    universe.queue_set_singleton(Spawner {
+        count: options.count,
+        spawned: AtomicBool::new(false),
+    });
+    common::run("stress_sprites", universe, options).await;
+}
@@ -0,0 +1,54 @@
+//! Fills the screen with 5k labels whose text changes every frame, so none of it can be cached
+//!
+//! Text is drawn through the debug overlay, so this needs the `egui` feature
+//!
+//! `cargo run --release -p bina --features egui --example stress_text -- --frames 2000`
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use bina::{
+    ecs::{singleton::Singleton, tokio, universe::Universe},
+    graphics::{gizmos::UniverseGizmos, image::Rgba, polygon::Vector},
+};
+use common::BenchOptions;
+
+mod common;
+
+struct TextWall {
+    count: usize,
+    frame: AtomicU64,
+}
+
+impl Singleton for TextWall {
+    fn process(&self, universe: &Universe) {
+        let frame = self.frame.fetch_add(1, Ordering::Relaxed);
+        let gizmos = universe.gizmos();
+        // As close to square as possible, filling the visible area, which is 1 unit across
+        let columns = (self.count as f32).sqrt().ceil() as usize;
+        let rows = self.count.div_ceil(columns);
+        let spacing = Vector::new(1.0 / columns as f32, 1.0 / rows as f32);
+        for i in 0..self.count {
+            let (column, row) = (i % columns, i / columns);
+            let position = Vector::new(
+                (column as f32 + 0.5) * spacing.x - 0.5,
+                0.5 - (row as f32 + 0.5) * spacing.y,
+            );
+            let shade = (i * 37 % 200) as u8 + 55;
+            gizmos.text(
+                position,
+                format!("{}", frame + i as u64),
+                Rgba([255, shade, 255 - shade, 255]),
+            );
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let options = BenchOptions::from_args("stress_text", 5_000);
+    let universe = Universe::new();
+    universe.queue_set_singleton(TextWall {
+        count: options.count,
+        frame: AtomicU64::new(0),
+    });
+    common::run("stress_text", universe, options).await;
+}
@@ -0,0 +1,131 @@
+//! Native dialogs for choosing files and folders, such as for importing content into an editor
+//!
+//! Dialogs are opened on the thread that runs the event loop, as some platforms cannot open them
+//! from any other thread, and are attached to the window so that they stay in front of it.
+//! The game keeps running while a dialog is open, and the chosen path is read from a `WatchedFuture`
+//!
+//! ```ignore
+//! const IMAGES: FileFilter = FileFilter::new("Images", &["png", "jpg"]);
+//!
+//! universe.queue_add_entity((dialog::open_file(graphics, &[IMAGES], universe),));
+//! // Later, wherever the WatchedFuture is checked
+//! if let Ok(Some(path)) = future.try_get() {
+//!     import_image(&path);
+//! }
+//! ```
+use std::{future::Future, path::PathBuf};
+
+use bina_ecs::{components::WatchedFuture, tokio::sync::oneshot, universe::Universe};
+use bina_graphics::Graphics;
+use rfd::AsyncFileDialog;
+
+/// A named group of file extensions that a dialog lets the player choose between
+#[derive(Clone, Copy, Debug)]
+pub struct FileFilter {
+    pub name: &'static str,
+    /// Extensions without the leading dot, such as `"png"`
+    pub extensions: &'static [&'static str],
+}
+
+impl FileFilter {
+    pub const fn new(name: &'static str, extensions: &'static [&'static str]) -> Self {
+        Self { name, extensions }
+    }
+}
+
+/// Asks the player to choose a file to open, resolving to `None` if the dialog was cancelled
+///
+/// Any file can be chosen if `filters` is empty
+pub fn open_file(
+    graphics: &Graphics,
+    filters: &[FileFilter],
+    universe: &Universe,
+) -> WatchedFuture<Option<PathBuf>> {
+    let dialog = with_filters(filters);
+    show(graphics, universe, move |x| {
+        let fut = dialog(x).pick_file();
+        async move { fut.await.map(|x| x.path().to_path_buf()) }
+    })
+}
+
+/// The same as `open_file`, except that any number of files can be chosen
+pub fn open_files(
+    graphics: &Graphics,
+    filters: &[FileFilter],
+    universe: &Universe,
+) -> WatchedFuture<Option<Vec<PathBuf>>> {
+    let dialog = with_filters(filters);
+    show(graphics, universe, move |x| {
+        let fut = dialog(x).pick_files();
+        async move {
+            fut.await
+                .map(|x| x.into_iter().map(|x| x.path().to_path_buf()).collect())
+        }
+    })
+}
+
+/// Asks the player where to save a file, suggesting `file_name`, resolving to `None` if the dialog was cancelled
+///
+/// Nothing is written to the chosen path, and the player may have already confirmed that
+/// an existing file will be replaced
+pub fn save_file(
+    graphics: &Graphics,
+    filters: &[FileFilter],
+    file_name: impl Into<String>,
+    universe: &Universe,
+) -> WatchedFuture<Option<PathBuf>> {
+    let dialog = with_filters(filters);
+    let file_name = file_name.into();
+    show(graphics, universe, move |x| {
+        let fut = dialog(x).set_file_name(file_name).save_file();
+        async move { fut.await.map(|x| x.path().to_path_buf()) }
+    })
+}
+
+/// Asks the player to choose a folder, resolving to `None` if the dialog was cancelled
+pub fn pick_folder(graphics: &Graphics, universe: &Universe) -> WatchedFuture<Option<PathBuf>> {
+    show(graphics, universe, |x| {
+        let fut = x.pick_folder();
+        async move { fut.await.map(|x| x.path().to_path_buf()) }
+    })
+}
+
+/// Copies `filters` so that they can be added to a dialog on the event loop's thread
+fn with_filters(
+    filters: &[FileFilter],
+) -> impl FnOnce(AsyncFileDialog) -> AsyncFileDialog + Send + 'static {
+    let filters = filters.to_vec();
+    move |dialog| {
+        filters.into_iter().fold(dialog, |dialog, filter| {
+            dialog.add_filter(filter.name, filter.extensions)
+        })
+    }
+}
+
+/// Opens a dialog on the thread that runs the event loop, and waits for it on the Universe's runtime
+///
+/// Resolves to `None` without opening a dialog if there is no window, such as when running headless
+fn show<T, F>(
+    graphics: &Graphics,
+    universe: &Universe,
+    dialog: impl FnOnce(AsyncFileDialog) -> F + Send + 'static,
+) -> WatchedFuture<Option<T>>
+where
+    T: Send + Sync + 'static,
+    F: Future<Output = Option<T>> + Send + 'static,
+{
+    let (sender, receiver) = oneshot::channel();
+    graphics.run_on_window(move |window| {
+        // Dialogs are opened when they are picked, not when their future is awaited
+        let _ = sender.send(dialog(AsyncFileDialog::new().set_parent(window)));
+    });
+    WatchedFuture::new(
+        async move {
+            match receiver.await {
+                Ok(fut) => fut.await,
+                Err(_) => None,
+            }
+        },
+        universe,
+    )
+}
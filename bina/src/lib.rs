@@ -1,3 +1,15 @@
 pub use bina_ecs as ecs;
 pub use bina_graphics as graphics;
 pub use bina_macros as macros;
+#[cfg(feature = "audio")]
+pub use bina_audio as audio;
+pub use bina_ui as ui;
+pub use bina_steering as steering;
+
+// Browsers have no files to keep settings in
+#[cfg(all(feature = "serde", not(target_arch = "wasm32")))]
+pub mod settings;
+
+// Browsers and phones have no native file dialogs
+#[cfg(all(feature = "dialog", not(any(target_arch = "wasm32", target_os = "android"))))]
+pub mod dialog;
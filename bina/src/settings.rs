@@ -0,0 +1,264 @@
+//! Player settings that are kept in a RON file, such as the resolution, vsync, volume and key bindings
+//!
+//! The file is read before the window opens, so that the window can be built at the right size,
+//! and is watched for changes while the game runs. Changing a setting in game saves the file
+//! in the background and applies the setting to `Graphics` and `Audio`
+use std::{
+    collections::BTreeMap,
+    io,
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+    thread::JoinHandle,
+};
+
+#[cfg(feature = "audio")]
+use bina_audio::Audio;
+use bina_ecs::{
+    config::{self, ConfigError},
+    crossbeam::channel::{unbounded, Sender},
+    io::modified_time,
+    parking_lot::Mutex,
+    singleton::Singleton,
+    triomphe::Arc,
+    universe::Universe,
+};
+use bina_graphics::{
+    winit::{dpi::PhysicalSize, event::VirtualKeyCode, window::WindowBuilder},
+    Graphics,
+};
+use serde::{Deserialize, Serialize};
+
+/// Sent into the Universe whenever a setting changes, either in game or because the
+/// file was edited. Also sent on the first frame
+#[derive(Clone, Copy, Debug)]
+pub struct SettingsChanged;
+
+/// The contents of the file. Settings that are missing from the file keep their defaults
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct SettingsValues {
+    /// The size of the inside of the window, or `None` to let the platform decide
+    resolution: Option<PhysicalSize<u32>>,
+    vsync: bool,
+    volume: f32,
+    /// The key bound to each action, by the name of the action
+    key_bindings: BTreeMap<String, VirtualKeyCode>,
+}
+
+impl Default for SettingsValues {
+    fn default() -> Self {
+        Self {
+            resolution: None,
+            vsync: true,
+            volume: 1.0,
+            key_bindings: BTreeMap::new(),
+        }
+    }
+}
+
+/// Settings chosen by the player. Add it to the `Universe` as a singleton
+///
+/// ```ignore
+/// let settings = Settings::load("settings.ron").with_default_key_binding("jump", VirtualKeyCode::Space);
+/// let window = settings.window_builder().with_title("Game");
+/// universe.queue_set_singleton(settings);
+/// Graphics::run_with_builder(universe, count, delta, window, scaling_mode).await;
+///
+/// // Later, from a component
+/// let jump = universe.get_singleton::<Settings>().key_binding("jump");
+/// if event.pressed && event.key.is_some() && event.key == jump { ... }
+/// ```
+pub struct Settings {
+    values: Mutex<SettingsValues>,
+    /// Whether the values changed in game since they were last saved
+    unsaved: AtomicBool,
+    /// Whether a `SettingsChanged` event must be sent
+    changed: AtomicBool,
+    /// The contents of the file after it was modified by something else
+    reloaded: Arc<Mutex<Option<SettingsValues>>>,
+    /// The last resolution given to `Graphics`, so that resizing the window
+    /// by hand is not undone
+    applied_resolution: Mutex<Option<PhysicalSize<u32>>>,
+    saves: Option<Sender<String>>,
+    watcher: Option<JoinHandle<()>>,
+}
+
+impl Settings {
+    /// Reads the settings from a RON file, and starts watching it for changes
+    ///
+    /// If the file does not exist, the defaults are used and saved to it. If it cannot be
+    /// read or parsed, the error is logged and the defaults are used, but the file is left
+    /// alone until a setting is changed in game
+    pub fn load(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let modified = modified_time(&path);
+        let (values, unsaved) = match config::read(&path) {
+            Ok(values) => (values, false),
+            Err(ConfigError::Io(e)) if e.kind() == io::ErrorKind::NotFound => {
+                (SettingsValues::default(), true)
+            }
+            Err(e) => {
+                log::error!("Failed to load {}: {e}", path.display());
+                (SettingsValues::default(), false)
+            }
+        };
+
+        let reloaded = Arc::new(Mutex::new(None));
+        let (sender, receiver) = unbounded();
+        // Saves are written by the watcher, so that they are not read back as changes
+        let watcher = {
+            let reloaded = reloaded.clone();
+            config::watch(path, modified, receiver, move |values| *reloaded.lock() = Some(values))
+        };
+        Self {
+            values: Mutex::new(values),
+            unsaved: AtomicBool::new(unsaved),
+            changed: AtomicBool::new(true),
+            reloaded,
+            applied_resolution: Mutex::new(None),
+            saves: Some(sender),
+            watcher: Some(watcher),
+        }
+    }
+
+    /// Binds a key to an action, unless the file already binds the action to a key
+    pub fn with_default_key_binding(self, action: &str, key: VirtualKeyCode) -> Self {
+        let mut values = self.values.lock();
+        if !values.key_bindings.contains_key(action) {
+            values.key_bindings.insert(action.to_owned(), key);
+            // Saved so that the file lists every action that can be rebound
+            self.unsaved.store(true, Ordering::Relaxed);
+        }
+        drop(values);
+        self
+    }
+
+    /// Creates a `WindowBuilder` that opens the window at the saved resolution, if there is one
+    pub fn window_builder(&self) -> WindowBuilder {
+        let builder = WindowBuilder::new();
+        match self.resolution() {
+            Some(size) => builder.with_inner_size(size),
+            None => builder,
+        }
+    }
+
+    fn modify(&self, f: impl FnOnce(&mut SettingsValues)) {
+        let mut values = self.values.lock();
+        let old = values.clone();
+        f(&mut values);
+        if *values != old {
+            self.unsaved.store(true, Ordering::Relaxed);
+            self.changed.store(true, Ordering::Relaxed);
+        }
+    }
+
+    pub fn resolution(&self) -> Option<PhysicalSize<u32>> {
+        self.values.lock().resolution
+    }
+
+    /// Sets the size of the inside of the window, or `None` to leave the window as it is
+    pub fn set_resolution(&self, resolution: Option<PhysicalSize<u32>>) {
+        self.modify(|x| x.resolution = resolution);
+    }
+
+    pub fn vsync(&self) -> bool {
+        self.values.lock().vsync
+    }
+
+    pub fn set_vsync(&self, vsync: bool) {
+        self.modify(|x| x.vsync = vsync);
+    }
+
+    /// Gets the master volume, which is 1 by default
+    pub fn volume(&self) -> f32 {
+        self.values.lock().volume
+    }
+
+    pub fn set_volume(&self, volume: f32) {
+        self.modify(|x| x.volume = volume);
+    }
+
+    /// Gets the key bound to an action, if any
+    pub fn key_binding(&self, action: &str) -> Option<VirtualKeyCode> {
+        self.values.lock().key_bindings.get(action).copied()
+    }
+
+    /// Binds a key to an action, or unbinds the action if `None`
+    pub fn set_key_binding(&self, action: &str, key: Option<VirtualKeyCode>) {
+        self.modify(|x| match key {
+            Some(key) => {
+                x.key_bindings.insert(action.to_owned(), key);
+            }
+            None => {
+                x.key_bindings.remove(action);
+            }
+        });
+    }
+
+    /// Gets every action and the key bound to it, such as to list them in a menu
+    pub fn key_bindings(&self) -> Vec<(String, VirtualKeyCode)> {
+        self.values
+            .lock()
+            .key_bindings
+            .iter()
+            .map(|(action, key)| (action.clone(), *key))
+            .collect()
+    }
+}
+
+impl Singleton for Settings {
+    fn process(&self, universe: &Universe) {
+        let mut values = self.values.lock();
+        if let Some(reloaded) = self.reloaded.lock().take() {
+            // Changes made in game win over edits to the file that happened at the same time
+            if !self.unsaved.load(Ordering::Relaxed) && reloaded != *values {
+                *values = reloaded;
+                self.changed.store(true, Ordering::Relaxed);
+            }
+        }
+        if self.unsaved.swap(false, Ordering::Relaxed) {
+            match ron::ser::to_string_pretty(&*values, ron::ser::PrettyConfig::default()) {
+                Ok(contents) => {
+                    if let Some(saves) = &self.saves {
+                        let _ = saves.send(contents);
+                    }
+                }
+                Err(e) => log::error!("Failed to serialize settings: {e}"),
+            }
+        }
+
+        // Checked every frame, as `Graphics` and `Audio` may be added after the settings
+        if let Some(graphics) = universe.try_get_singleton::<Graphics>() {
+            if graphics.vsync() != values.vsync {
+                graphics.set_vsync(values.vsync);
+            }
+            let mut applied_resolution = self.applied_resolution.lock();
+            if *applied_resolution != values.resolution {
+                *applied_resolution = values.resolution;
+                if let Some(size) = values.resolution {
+                    graphics.set_window_size(size);
+                }
+            }
+        }
+        #[cfg(feature = "audio")]
+        if let Some(audio) = universe.try_get_singleton::<Audio>() {
+            if audio.master_volume() != values.volume {
+                audio.set_master_volume(values.volume);
+            }
+        }
+
+        if self.changed.swap(false, Ordering::Relaxed) {
+            universe.send_event(SettingsChanged);
+        }
+    }
+}
+
+impl Drop for Settings {
+    /// Waits for the last changes to be saved
+    fn drop(&mut self) {
+        self.saves.take();
+        if let Some(watcher) = self.watcher.take() {
+            let _ = watcher.join();
+        }
+    }
+}